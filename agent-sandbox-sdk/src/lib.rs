@@ -4,6 +4,6 @@
 //! The client is auto-generated from the OpenAPI specification.
 
 #![allow(clippy::all)]
-#![allow(unused_imports, dead_code)]
+#![allow(unused_imports, dead_code, irrefutable_let_patterns)]
 
 include!(concat!(env!("OUT_DIR"), "/codegen.rs"));
@@ -0,0 +1,21 @@
+fn main() {
+    // The gRPC code is generated only when the server is built in; protoc comes vendored so
+    // the build does not depend on a system install
+    #[cfg(feature = "grpc")]
+    {
+        std::env::set_var(
+            "PROTOC",
+            protoc_bin_vendored::protoc_bin_path().expect("No vendored protoc for this platform"),
+        );
+        tonic_build::configure()
+            .compile_protos(
+                &["proto/prompt_backend.proto"],
+                &[
+                    std::path::PathBuf::from("proto"),
+                    protoc_bin_vendored::include_path()
+                        .expect("No vendored protobuf includes for this platform"),
+                ],
+            )
+            .expect("Failed to compile proto/prompt_backend.proto");
+    }
+}
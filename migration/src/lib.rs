@@ -1,3 +1,6 @@
+// Iden enums are named after their table, so columns such as `Prompt::SystemPrompt` repeat it
+#![allow(clippy::enum_variant_names)]
+
 pub use sea_orm_migration::prelude::*;
 
 mod m20250102_000001_create_session_table;
@@ -19,6 +22,65 @@ mod m20251107_000005_drop_session_status_fields;
 mod m20251108_000001_drop_inbox_status_from_prompt;
 mod m20251111_000001_add_cancellation_to_session;
 mod m20251111_000002_add_process_pid_to_session;
+mod m20251112_000001_add_timings_to_prompt;
+mod m20251113_000001_add_sandbox_wait_fields_to_session;
+mod m20251114_000001_add_blob_key_to_message;
+mod m20251115_000001_add_tags_to_session;
+mod m20251116_000001_add_progress_to_prompt;
+mod m20251117_000001_add_rerun_of_to_prompt;
+mod m20251118_000001_add_run_config_to_prompt;
+mod m20251119_000001_add_run_claim_to_prompt;
+mod m20251120_000001_create_session_artifact_table;
+mod m20251121_000001_create_annotation_table;
+mod m20251122_000001_create_user_deprovision_table;
+mod m20251123_000001_create_notification_tables;
+mod m20251124_000001_create_outbox_event_table;
+mod m20251125_000001_add_path_policy_to_session;
+mod m20251126_000001_add_title_pending_to_session;
+mod m20251127_000001_create_sandbox_exec_table;
+mod m20251128_000001_add_tool_summary_to_prompt;
+mod m20251129_000001_add_priority_to_prompt;
+mod m20251130_000001_add_agent_token_to_session;
+mod m20251201_000001_create_session_upload_table;
+mod m20251202_000001_create_prompt_artifact_table;
+mod m20251203_000001_add_history_options_to_prompt;
+mod m20251204_000001_create_organization_table;
+mod m20251205_000001_add_ip_return_key_to_session;
+mod m20251206_000001_create_session_event_table;
+mod m20251207_000001_add_model_to_session_and_prompt;
+mod m20251208_000001_add_cancellation_mode_to_session;
+mod m20251209_000001_create_user_settings_table;
+mod m20251210_000001_add_fan_out_limit_to_session;
+mod m20251211_000001_add_stderr_to_prompt;
+mod m20251212_000001_create_idempotency_key_table;
+mod m20251213_000001_create_user_erasure_table;
+mod m20251214_000001_add_error_kind_to_prompt;
+mod m20251215_000001_add_egress_policy_to_session;
+mod m20251216_000001_add_pull_request_description_to_session;
+mod m20251217_000001_create_warm_sandbox_table;
+mod m20251218_000001_add_keep_sandbox_until_archive_to_session;
+mod m20251219_000001_create_prompt_attachment_table;
+mod m20251220_000001_add_unpushed_work_to_session;
+mod m20251221_000001_create_prompt_file_change_table;
+mod m20251222_000001_add_region_to_session;
+mod m20251223_000001_add_worker_to_session;
+mod m20251224_000001_add_last_activity_at;
+mod m20251225_000001_add_secret_findings_to_prompt;
+mod m20251226_000001_create_poller_control_table;
+mod m20251227_000001_add_budget_to_session;
+mod m20251228_000001_create_conversation_chunk_table;
+mod m20251229_000001_add_output_log_path_to_prompt;
+mod m20251230_000001_add_estimated_start_at_to_session;
+mod m20251231_000001_add_worker_instance;
+mod m20260101_000001_add_sandbox_capabilities_to_session;
+mod m20260102_000001_add_message_compaction_to_session;
+mod m20260103_000001_add_draft_to_prompt;
+mod m20260104_000001_add_agent_token_expiry_to_session;
+mod m20260105_000001_add_title_retry_to_session;
+mod m20260106_000001_add_workspace_disk_to_session;
+mod m20260107_000001_add_seq_to_message;
+mod m20260108_000001_create_operational_event_table;
+mod m20260109_000001_add_ingestion_filter_to_session;
 
 pub struct Migrator;
 
@@ -45,6 +107,65 @@ impl MigratorTrait for Migrator {
             Box::new(m20251108_000001_drop_inbox_status_from_prompt::Migration),
             Box::new(m20251111_000001_add_cancellation_to_session::Migration),
             Box::new(m20251111_000002_add_process_pid_to_session::Migration),
+            Box::new(m20251112_000001_add_timings_to_prompt::Migration),
+            Box::new(m20251113_000001_add_sandbox_wait_fields_to_session::Migration),
+            Box::new(m20251114_000001_add_blob_key_to_message::Migration),
+            Box::new(m20251115_000001_add_tags_to_session::Migration),
+            Box::new(m20251116_000001_add_progress_to_prompt::Migration),
+            Box::new(m20251117_000001_add_rerun_of_to_prompt::Migration),
+            Box::new(m20251118_000001_add_run_config_to_prompt::Migration),
+            Box::new(m20251119_000001_add_run_claim_to_prompt::Migration),
+            Box::new(m20251120_000001_create_session_artifact_table::Migration),
+            Box::new(m20251121_000001_create_annotation_table::Migration),
+            Box::new(m20251122_000001_create_user_deprovision_table::Migration),
+            Box::new(m20251123_000001_create_notification_tables::Migration),
+            Box::new(m20251124_000001_create_outbox_event_table::Migration),
+            Box::new(m20251125_000001_add_path_policy_to_session::Migration),
+            Box::new(m20251126_000001_add_title_pending_to_session::Migration),
+            Box::new(m20251127_000001_create_sandbox_exec_table::Migration),
+            Box::new(m20251128_000001_add_tool_summary_to_prompt::Migration),
+            Box::new(m20251129_000001_add_priority_to_prompt::Migration),
+            Box::new(m20251130_000001_add_agent_token_to_session::Migration),
+            Box::new(m20251201_000001_create_session_upload_table::Migration),
+            Box::new(m20251202_000001_create_prompt_artifact_table::Migration),
+            Box::new(m20251203_000001_add_history_options_to_prompt::Migration),
+            Box::new(m20251204_000001_create_organization_table::Migration),
+            Box::new(m20251205_000001_add_ip_return_key_to_session::Migration),
+            Box::new(m20251206_000001_create_session_event_table::Migration),
+            Box::new(m20251207_000001_add_model_to_session_and_prompt::Migration),
+            Box::new(m20251208_000001_add_cancellation_mode_to_session::Migration),
+            Box::new(m20251209_000001_create_user_settings_table::Migration),
+            Box::new(m20251210_000001_add_fan_out_limit_to_session::Migration),
+            Box::new(m20251211_000001_add_stderr_to_prompt::Migration),
+            Box::new(m20251212_000001_create_idempotency_key_table::Migration),
+            Box::new(m20251213_000001_create_user_erasure_table::Migration),
+            Box::new(m20251214_000001_add_error_kind_to_prompt::Migration),
+            Box::new(m20251215_000001_add_egress_policy_to_session::Migration),
+            Box::new(m20251216_000001_add_pull_request_description_to_session::Migration),
+            Box::new(m20251217_000001_create_warm_sandbox_table::Migration),
+            Box::new(m20251218_000001_add_keep_sandbox_until_archive_to_session::Migration),
+            Box::new(m20251219_000001_create_prompt_attachment_table::Migration),
+            Box::new(m20251220_000001_add_unpushed_work_to_session::Migration),
+            Box::new(m20251221_000001_create_prompt_file_change_table::Migration),
+            Box::new(m20251222_000001_add_region_to_session::Migration),
+            Box::new(m20251223_000001_add_worker_to_session::Migration),
+            Box::new(m20251224_000001_add_last_activity_at::Migration),
+            Box::new(m20251225_000001_add_secret_findings_to_prompt::Migration),
+            Box::new(m20251226_000001_create_poller_control_table::Migration),
+            Box::new(m20251227_000001_add_budget_to_session::Migration),
+            Box::new(m20251228_000001_create_conversation_chunk_table::Migration),
+            Box::new(m20251229_000001_add_output_log_path_to_prompt::Migration),
+            Box::new(m20251230_000001_add_estimated_start_at_to_session::Migration),
+            Box::new(m20251231_000001_add_worker_instance::Migration),
+            Box::new(m20260101_000001_add_sandbox_capabilities_to_session::Migration),
+            Box::new(m20260102_000001_add_message_compaction_to_session::Migration),
+            Box::new(m20260103_000001_add_draft_to_prompt::Migration),
+            Box::new(m20260104_000001_add_agent_token_expiry_to_session::Migration),
+            Box::new(m20260105_000001_add_title_retry_to_session::Migration),
+            Box::new(m20260106_000001_add_workspace_disk_to_session::Migration),
+            Box::new(m20260107_000001_add_seq_to_message::Migration),
+            Box::new(m20260108_000001_create_operational_event_table::Migration),
+            Box::new(m20260109_000001_add_ingestion_filter_to_session::Migration),
         ]
     }
 }
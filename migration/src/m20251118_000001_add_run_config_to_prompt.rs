@@ -0,0 +1,41 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Prompt::Table)
+                    .add_column(ColumnDef::new(Prompt::SystemPrompt).text().null())
+                    .add_column(ColumnDef::new(Prompt::CliArgs).json_binary().null())
+                    .add_column(ColumnDef::new(Prompt::TemplateVersion).text().null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Prompt::Table)
+                    .drop_column(Prompt::SystemPrompt)
+                    .drop_column(Prompt::CliArgs)
+                    .drop_column(Prompt::TemplateVersion)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Prompt {
+    Table,
+    SystemPrompt,
+    CliArgs,
+    TemplateVersion,
+}
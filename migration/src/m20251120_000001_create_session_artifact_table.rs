@@ -0,0 +1,95 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(SessionArtifact::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(SessionArtifact::Id)
+                            .uuid()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(SessionArtifact::SessionId).uuid().not_null())
+                    .col(ColumnDef::new(SessionArtifact::PromptId).uuid().null())
+                    .col(ColumnDef::new(SessionArtifact::Path).text().not_null())
+                    .col(ColumnDef::new(SessionArtifact::BlobKey).text().not_null())
+                    .col(
+                        ColumnDef::new(SessionArtifact::SizeBytes)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(SessionArtifact::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_session_artifact_session_id")
+                            .from(SessionArtifact::Table, SessionArtifact::SessionId)
+                            .to(Session::Table, Session::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_session_artifact_prompt_id")
+                            .from(SessionArtifact::Table, SessionArtifact::PromptId)
+                            .to(Prompt::Table, Prompt::Id)
+                            .on_delete(ForeignKeyAction::SetNull)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_session_artifact_session_id")
+                    .table(SessionArtifact::Table)
+                    .col(SessionArtifact::SessionId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(SessionArtifact::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum SessionArtifact {
+    Table,
+    Id,
+    SessionId,
+    PromptId,
+    Path,
+    BlobKey,
+    SizeBytes,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum Session {
+    Table,
+    Id,
+}
+
+#[derive(DeriveIden)]
+enum Prompt {
+    Table,
+    Id,
+}
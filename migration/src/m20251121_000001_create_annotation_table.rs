@@ -0,0 +1,82 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Annotation::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Annotation::Id)
+                            .uuid()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(Annotation::MessageId).uuid().not_null())
+                    .col(ColumnDef::new(Annotation::AuthorId).string().not_null())
+                    .col(ColumnDef::new(Annotation::AuthorName).string().null())
+                    .col(ColumnDef::new(Annotation::Comment).text().not_null())
+                    .col(
+                        ColumnDef::new(Annotation::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .col(
+                        ColumnDef::new(Annotation::UpdatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_annotation_message_id")
+                            .from(Annotation::Table, Annotation::MessageId)
+                            .to(Message::Table, Message::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_annotation_message_id")
+                    .table(Annotation::Table)
+                    .col(Annotation::MessageId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Annotation::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Annotation {
+    Table,
+    Id,
+    MessageId,
+    AuthorId,
+    AuthorName,
+    Comment,
+    CreatedAt,
+    UpdatedAt,
+}
+
+#[derive(DeriveIden)]
+enum Message {
+    Table,
+    Id,
+}
@@ -0,0 +1,79 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(UserDeprovision::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(UserDeprovision::Id)
+                            .uuid()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(UserDeprovision::UserId).string().not_null())
+                    .col(
+                        ColumnDef::new(UserDeprovision::RequestedBy)
+                            .string()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(UserDeprovision::SessionsCancelled)
+                            .integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(UserDeprovision::SessionsArchived)
+                            .integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(UserDeprovision::SessionsDeleted)
+                            .integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(UserDeprovision::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_user_deprovision_user_id")
+                    .table(UserDeprovision::Table)
+                    .col(UserDeprovision::UserId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(UserDeprovision::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum UserDeprovision {
+    Table,
+    Id,
+    UserId,
+    RequestedBy,
+    SessionsCancelled,
+    SessionsArchived,
+    SessionsDeleted,
+    CreatedAt,
+}
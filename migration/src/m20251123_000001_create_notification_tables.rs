@@ -0,0 +1,166 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Notification::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Notification::Id)
+                            .uuid()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(Notification::UserId).string().not_null())
+                    .col(ColumnDef::new(Notification::SessionId).uuid().not_null())
+                    .col(ColumnDef::new(Notification::PromptId).uuid().null())
+                    .col(ColumnDef::new(Notification::Kind).string_len(50).not_null())
+                    .col(ColumnDef::new(Notification::Message).text().not_null())
+                    .col(
+                        ColumnDef::new(Notification::ReadAt)
+                            .timestamp_with_time_zone()
+                            .null(),
+                    )
+                    .col(
+                        ColumnDef::new(Notification::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_notification_session_id")
+                            .from(Notification::Table, Notification::SessionId)
+                            .to(Session::Table, Session::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_notification_prompt_id")
+                            .from(Notification::Table, Notification::PromptId)
+                            .to(Prompt::Table, Prompt::Id)
+                            .on_delete(ForeignKeyAction::SetNull)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_notification_user_id_created_at")
+                    .table(Notification::Table)
+                    .col(Notification::UserId)
+                    .col(Notification::CreatedAt)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(SessionWatcher::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(SessionWatcher::Id)
+                            .uuid()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(SessionWatcher::SessionId).uuid().not_null())
+                    .col(ColumnDef::new(SessionWatcher::UserId).string().not_null())
+                    .col(
+                        ColumnDef::new(SessionWatcher::Watching)
+                            .boolean()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(SessionWatcher::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .col(
+                        ColumnDef::new(SessionWatcher::UpdatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_session_watcher_session_id")
+                            .from(SessionWatcher::Table, SessionWatcher::SessionId)
+                            .to(Session::Table, Session::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_session_watcher_session_id_user_id")
+                    .table(SessionWatcher::Table)
+                    .col(SessionWatcher::SessionId)
+                    .col(SessionWatcher::UserId)
+                    .unique()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(SessionWatcher::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(Notification::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Notification {
+    Table,
+    Id,
+    UserId,
+    SessionId,
+    PromptId,
+    Kind,
+    Message,
+    ReadAt,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum SessionWatcher {
+    Table,
+    Id,
+    SessionId,
+    UserId,
+    Watching,
+    CreatedAt,
+    UpdatedAt,
+}
+
+#[derive(DeriveIden)]
+enum Session {
+    Table,
+    Id,
+}
+
+#[derive(DeriveIden)]
+enum Prompt {
+    Table,
+    Id,
+}
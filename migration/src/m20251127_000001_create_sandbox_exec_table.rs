@@ -0,0 +1,73 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(SandboxExec::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(SandboxExec::Id)
+                            .uuid()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(SandboxExec::SessionId).uuid().not_null())
+                    .col(ColumnDef::new(SandboxExec::RequestedBy).string().not_null())
+                    .col(ColumnDef::new(SandboxExec::Command).text().not_null())
+                    .col(ColumnDef::new(SandboxExec::ExecDir).string().not_null())
+                    .col(ColumnDef::new(SandboxExec::ExitCode).big_integer().null())
+                    .col(ColumnDef::new(SandboxExec::Output).text().null())
+                    .col(ColumnDef::new(SandboxExec::Error).text().null())
+                    .col(
+                        ColumnDef::new(SandboxExec::DurationMs)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(SandboxExec::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_sandbox_exec_session_id")
+                    .table(SandboxExec::Table)
+                    .col(SandboxExec::SessionId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(SandboxExec::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum SandboxExec {
+    Table,
+    Id,
+    SessionId,
+    RequestedBy,
+    Command,
+    ExecDir,
+    ExitCode,
+    Output,
+    Error,
+    DurationMs,
+    CreatedAt,
+}
@@ -0,0 +1,91 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(SessionUpload::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(SessionUpload::Id)
+                            .uuid()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(SessionUpload::SessionId).uuid().not_null())
+                    .col(ColumnDef::new(SessionUpload::UserId).string().not_null())
+                    .col(ColumnDef::new(SessionUpload::FileName).string().not_null())
+                    .col(ColumnDef::new(SessionUpload::Path).text().not_null())
+                    .col(ColumnDef::new(SessionUpload::ContentType).string().null())
+                    .col(
+                        ColumnDef::new(SessionUpload::SizeBytes)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(SessionUpload::Sha256)
+                            .string_len(64)
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(SessionUpload::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_session_upload_session_id")
+                            .from(SessionUpload::Table, SessionUpload::SessionId)
+                            .to(Session::Table, Session::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_session_upload_session_id_path")
+                    .table(SessionUpload::Table)
+                    .col(SessionUpload::SessionId)
+                    .col(SessionUpload::Path)
+                    .unique()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(SessionUpload::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum SessionUpload {
+    Table,
+    Id,
+    SessionId,
+    UserId,
+    FileName,
+    Path,
+    ContentType,
+    SizeBytes,
+    Sha256,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum Session {
+    Table,
+    Id,
+}
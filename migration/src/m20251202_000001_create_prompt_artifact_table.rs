@@ -0,0 +1,97 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(PromptArtifact::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(PromptArtifact::Id)
+                            .uuid()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(PromptArtifact::PromptId).uuid().not_null())
+                    .col(ColumnDef::new(PromptArtifact::SessionId).uuid().not_null())
+                    .col(
+                        ColumnDef::new(PromptArtifact::Kind)
+                            .string_len(20)
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(PromptArtifact::Repo).string().not_null())
+                    .col(
+                        ColumnDef::new(PromptArtifact::CommitSha)
+                            .string_len(40)
+                            .null(),
+                    )
+                    .col(ColumnDef::new(PromptArtifact::PullRequestUrl).text().null())
+                    .col(
+                        ColumnDef::new(PromptArtifact::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_prompt_artifact_prompt_id")
+                            .from(PromptArtifact::Table, PromptArtifact::PromptId)
+                            .to(Prompt::Table, Prompt::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_prompt_artifact_prompt_id")
+                    .table(PromptArtifact::Table)
+                    .col(PromptArtifact::PromptId)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_prompt_artifact_commit_sha")
+                    .table(PromptArtifact::Table)
+                    .col(PromptArtifact::CommitSha)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(PromptArtifact::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum PromptArtifact {
+    Table,
+    Id,
+    PromptId,
+    SessionId,
+    Kind,
+    Repo,
+    CommitSha,
+    PullRequestUrl,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum Prompt {
+    Table,
+    Id,
+}
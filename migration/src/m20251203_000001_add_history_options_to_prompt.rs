@@ -0,0 +1,46 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Prompt::Table)
+                    .add_column(
+                        ColumnDef::new(Prompt::IncludeHistory)
+                            .boolean()
+                            .not_null()
+                            .default(true),
+                    )
+                    .add_column(ColumnDef::new(Prompt::HistoryDepth).integer().null())
+                    .add_column(ColumnDef::new(Prompt::RenderedPrompt).text().null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Prompt::Table)
+                    .drop_column(Prompt::IncludeHistory)
+                    .drop_column(Prompt::HistoryDepth)
+                    .drop_column(Prompt::RenderedPrompt)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Prompt {
+    Table,
+    IncludeHistory,
+    HistoryDepth,
+    RenderedPrompt,
+}
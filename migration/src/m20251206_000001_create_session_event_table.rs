@@ -0,0 +1,89 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(SessionEvent::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(SessionEvent::Id)
+                            .uuid()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(SessionEvent::SessionId).uuid().not_null())
+                    .col(
+                        ColumnDef::new(SessionEvent::EventType)
+                            .string_len(50)
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(SessionEvent::Actor).string().not_null())
+                    .col(
+                        ColumnDef::new(SessionEvent::FromStatus)
+                            .string_len(50)
+                            .null(),
+                    )
+                    .col(ColumnDef::new(SessionEvent::ToStatus).string_len(50).null())
+                    .col(ColumnDef::new(SessionEvent::Metadata).json_binary().null())
+                    .col(
+                        ColumnDef::new(SessionEvent::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_session_event_session_id")
+                            .from(SessionEvent::Table, SessionEvent::SessionId)
+                            .to(Session::Table, Session::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_session_event_session_id_created_at")
+                    .table(SessionEvent::Table)
+                    .col(SessionEvent::SessionId)
+                    .col(SessionEvent::CreatedAt)
+                    .col(SessionEvent::Id)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(SessionEvent::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum SessionEvent {
+    Table,
+    Id,
+    SessionId,
+    EventType,
+    Actor,
+    FromStatus,
+    ToStatus,
+    Metadata,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum Session {
+    Table,
+    Id,
+}
@@ -0,0 +1,72 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(UserErasure::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(UserErasure::Id)
+                            .uuid()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(UserErasure::UserId).string().not_null())
+                    .col(ColumnDef::new(UserErasure::RequestedBy).string().not_null())
+                    .col(
+                        ColumnDef::new(UserErasure::Status)
+                            .string_len(50)
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(UserErasure::Report).json_binary().not_null())
+                    .col(
+                        ColumnDef::new(UserErasure::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .col(
+                        ColumnDef::new(UserErasure::CompletedAt)
+                            .timestamp_with_time_zone()
+                            .null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_user_erasure_user_id_created_at")
+                    .table(UserErasure::Table)
+                    .col(UserErasure::UserId)
+                    .col(UserErasure::CreatedAt)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(UserErasure::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum UserErasure {
+    Table,
+    Id,
+    UserId,
+    RequestedBy,
+    Status,
+    Report,
+    CreatedAt,
+    CompletedAt,
+}
@@ -0,0 +1,84 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(WarmSandbox::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(WarmSandbox::Id)
+                            .uuid()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(WarmSandbox::Repo).string().null())
+                    .col(
+                        ColumnDef::new(WarmSandbox::SbxConfig)
+                            .json_binary()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(WarmSandbox::Status)
+                            .string_len(50)
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(WarmSandbox::AuthenticatedHost)
+                            .string()
+                            .null(),
+                    )
+                    .col(ColumnDef::new(WarmSandbox::RepoDir).string().null())
+                    .col(ColumnDef::new(WarmSandbox::ReturnKey).uuid().null())
+                    .col(
+                        ColumnDef::new(WarmSandbox::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .col(
+                        ColumnDef::new(WarmSandbox::ExpiresAt)
+                            .timestamp_with_time_zone()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_warm_sandbox_status_repo")
+                    .table(WarmSandbox::Table)
+                    .col(WarmSandbox::Status)
+                    .col(WarmSandbox::Repo)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(WarmSandbox::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum WarmSandbox {
+    Table,
+    Id,
+    Repo,
+    SbxConfig,
+    Status,
+    AuthenticatedHost,
+    RepoDir,
+    ReturnKey,
+    CreatedAt,
+    ExpiresAt,
+}
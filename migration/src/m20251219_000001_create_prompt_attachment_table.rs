@@ -0,0 +1,87 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(PromptAttachment::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(PromptAttachment::Id)
+                            .uuid()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(PromptAttachment::UserId).string().not_null())
+                    .col(
+                        ColumnDef::new(PromptAttachment::FileName)
+                            .string()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(PromptAttachment::Kind)
+                            .string_len(50)
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(PromptAttachment::ContentType)
+                            .string()
+                            .null(),
+                    )
+                    .col(
+                        ColumnDef::new(PromptAttachment::SizeBytes)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(PromptAttachment::Sha256)
+                            .string_len(64)
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(PromptAttachment::BlobKey).text().not_null())
+                    .col(
+                        ColumnDef::new(PromptAttachment::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_prompt_attachment_user_id")
+                    .table(PromptAttachment::Table)
+                    .col(PromptAttachment::UserId)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(PromptAttachment::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum PromptAttachment {
+    Table,
+    Id,
+    UserId,
+    FileName,
+    Kind,
+    ContentType,
+    SizeBytes,
+    Sha256,
+    BlobKey,
+    CreatedAt,
+}
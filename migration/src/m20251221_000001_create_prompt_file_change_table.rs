@@ -0,0 +1,104 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(PromptFileChange::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(PromptFileChange::Id)
+                            .uuid()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(PromptFileChange::PromptId).uuid().not_null())
+                    .col(
+                        ColumnDef::new(PromptFileChange::SessionId)
+                            .uuid()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(PromptFileChange::Path).text().not_null())
+                    .col(
+                        ColumnDef::new(PromptFileChange::Insertions)
+                            .integer()
+                            .null(),
+                    )
+                    .col(ColumnDef::new(PromptFileChange::Deletions).integer().null())
+                    .col(
+                        ColumnDef::new(PromptFileChange::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_prompt_file_change_prompt_id")
+                            .from(PromptFileChange::Table, PromptFileChange::PromptId)
+                            .to(Prompt::Table, Prompt::Id)
+                            .on_delete(ForeignKeyAction::Cascade)
+                            .on_update(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_prompt_file_change_prompt_id")
+                    .table(PromptFileChange::Table)
+                    .col(PromptFileChange::PromptId)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Prompt::Table)
+                    .add_column(ColumnDef::new(Prompt::ChangeSummary).json_binary().null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Prompt::Table)
+                    .drop_column(Prompt::ChangeSummary)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .drop_table(Table::drop().table(PromptFileChange::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum PromptFileChange {
+    Table,
+    Id,
+    PromptId,
+    SessionId,
+    Path,
+    Insertions,
+    Deletions,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum Prompt {
+    Table,
+    Id,
+    ChangeSummary,
+}
@@ -0,0 +1,52 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(PollerControl::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(PollerControl::Name)
+                            .string_len(64)
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(PollerControl::Paused)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .col(ColumnDef::new(PollerControl::UpdatedBy).string().null())
+                    .col(
+                        ColumnDef::new(PollerControl::UpdatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(PollerControl::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum PollerControl {
+    Table,
+    Name,
+    Paused,
+    UpdatedBy,
+    UpdatedAt,
+}
@@ -0,0 +1,74 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        // Existing messages are numbered in the order they were stored, new ones by the sequence
+        db.execute_unprepared("CREATE SEQUENCE message_seq_seq AS bigint")
+            .await?;
+        db.execute_unprepared("ALTER TABLE message ADD COLUMN seq bigint")
+            .await?;
+        db.execute_unprepared(
+            "UPDATE message SET seq = numbered.seq \
+             FROM (SELECT id, row_number() OVER (ORDER BY created_at, id) AS seq FROM message) numbered \
+             WHERE message.id = numbered.id",
+        )
+        .await?;
+        db.execute_unprepared(
+            "SELECT setval('message_seq_seq', COALESCE((SELECT max(seq) FROM message), 0) + 1, false)",
+        )
+        .await?;
+        db.execute_unprepared(
+            "ALTER TABLE message \
+             ALTER COLUMN seq SET DEFAULT nextval('message_seq_seq'), \
+             ALTER COLUMN seq SET NOT NULL",
+        )
+        .await?;
+        db.execute_unprepared("ALTER SEQUENCE message_seq_seq OWNED BY message.seq")
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_message_prompt_id_seq")
+                    .table(Message::Table)
+                    .col(Message::PromptId)
+                    .col(Message::Seq)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_message_prompt_id_seq")
+                    .table(Message::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        // Dropping the column drops the sequence it owns
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Message::Table)
+                    .drop_column(Message::Seq)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Message {
+    Table,
+    PromptId,
+    Seq,
+}
@@ -0,0 +1,98 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(OperationalEvent::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(OperationalEvent::Id)
+                            .uuid()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(OperationalEvent::Source)
+                            .string_len(64)
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(OperationalEvent::Kind)
+                            .string_len(50)
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(OperationalEvent::Message).text().not_null())
+                    .col(
+                        ColumnDef::new(OperationalEvent::Context)
+                            .json_binary()
+                            .null(),
+                    )
+                    .col(
+                        ColumnDef::new(OperationalEvent::Count)
+                            .integer()
+                            .not_null()
+                            .default(1),
+                    )
+                    .col(
+                        ColumnDef::new(OperationalEvent::FirstSeenAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .col(
+                        ColumnDef::new(OperationalEvent::LastSeenAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_operational_event_last_seen_at")
+                    .table(OperationalEvent::Table)
+                    .col(OperationalEvent::LastSeenAt)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_operational_event_source_kind")
+                    .table(OperationalEvent::Table)
+                    .col(OperationalEvent::Source)
+                    .col(OperationalEvent::Kind)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(OperationalEvent::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum OperationalEvent {
+    Table,
+    Id,
+    Source,
+    Kind,
+    Message,
+    Context,
+    Count,
+    FirstSeenAt,
+    LastSeenAt,
+}
@@ -0,0 +1,49 @@
+fn main() {
+    let spec_path =
+        std::env::var("OPENAPI_SPEC_PATH").unwrap_or_else(|_| "openapi.json".to_string());
+
+    let file = std::fs::File::open(&spec_path)
+        .unwrap_or_else(|e| panic!("Failed to open OpenAPI spec at {}: {}", spec_path, e));
+
+    let mut spec: serde_json::Value =
+        serde_json::from_reader(file).expect("Failed to parse OpenAPI spec");
+    drop_unsupported_operations(&mut spec);
+    let spec = serde_json::from_value(spec).expect("Failed to parse OpenAPI spec");
+
+    let mut generator = progenitor::Generator::default();
+
+    let tokens = generator
+        .generate_tokens(&spec)
+        .expect("Failed to generate client code");
+
+    let ast = syn::parse2(tokens).expect("Failed to parse generated code");
+
+    let content = prettyplease::unparse(&ast);
+
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    let dest_path = std::path::Path::new(&out_dir).join("codegen.rs");
+
+    std::fs::write(&dest_path, content).expect("Failed to write generated code");
+
+    println!("cargo:rerun-if-changed={}", spec_path);
+}
+
+/// Remove operations progenitor cannot generate, i.e. request bodies that are not JSON such as
+/// multipart uploads. They are left to hand-written calls.
+fn drop_unsupported_operations(spec: &mut serde_json::Value) {
+    let Some(paths) = spec.get_mut("paths").and_then(|p| p.as_object_mut()) else {
+        return;
+    };
+    for item in paths.values_mut() {
+        let Some(operations) = item.as_object_mut() else {
+            continue;
+        };
+        operations.retain(|_, operation| {
+            operation
+                .pointer("/requestBody/content")
+                .and_then(|content| content.as_object())
+                .is_none_or(|content| content.contains_key("application/json"))
+        });
+    }
+    paths.retain(|_, item| item.as_object().is_some_and(|ops| !ops.is_empty()));
+}
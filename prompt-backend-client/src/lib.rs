@@ -0,0 +1,10 @@
+//! Prompt Backend API Client
+//!
+//! This crate provides a Rust client for the prompt-backend API, with typed models for
+//! sessions, prompts, messages and the dead letter queue.
+//! The client is auto-generated from the OpenAPI specification.
+
+#![allow(clippy::all)]
+#![allow(unused_imports, dead_code)]
+
+include!(concat!(env!("OUT_DIR"), "/codegen.rs"));
@@ -8,6 +8,7 @@ use rocket_okapi::okapi::openapi3::{
 use rocket_okapi::request::{OpenApiFromRequest, RequestHeaderInput};
 
 use super::jwks::JwksCache;
+use crate::services::organizations;
 
 #[derive(Debug, Clone)]
 pub struct AuthenticatedUser {
@@ -16,8 +17,53 @@ pub struct AuthenticatedUser {
     pub email: Option<String>,
     #[allow(dead_code)]
     pub name: Option<String>,
+    /// Keycloak realm roles from the token
+    pub roles: Vec<String>,
+    /// Organization from the `ORG_CLAIM` claim; its members can read each other's sessions
+    pub org_id: Option<String>,
 }
 
+impl AuthenticatedUser {
+    /// Whether the user holds the operator role (`ADMIN_ROLE`, default "admin")
+    pub fn is_admin(&self) -> bool {
+        let admin_role = std::env::var("ADMIN_ROLE").unwrap_or_else(|_| "admin".to_string());
+        self.roles.iter().any(|role| role == &admin_role)
+    }
+
+    /// The user a bearer token belongs to: the dev user for `DEV_AUTH_TOKEN` in dev auth mode,
+    /// otherwise the subject of a Keycloak token. Shared by the Rocket guard and the gRPC server.
+    pub async fn from_bearer(jwks_cache: &JwksCache, token: &str) -> Result<Self, String> {
+        if let Some(dev) = &crate::config::get().dev_auth {
+            if token == dev.token {
+                return Ok(AuthenticatedUser {
+                    user_id: dev.user_id.clone(),
+                    email: dev.email.clone(),
+                    name: dev.name.clone(),
+                    roles: dev.roles.clone(),
+                    org_id: dev.org_id.clone(),
+                });
+            }
+        }
+
+        let claims = jwks_cache.validate_token(token).await?;
+        tracing::debug!("Token validated successfully for user: {}", claims.sub);
+        Ok(AuthenticatedUser {
+            user_id: claims.sub,
+            email: claims.email,
+            name: claims.name,
+            roles: claims.realm_access.unwrap_or_default().roles,
+            org_id: claims
+                .other
+                .get(&crate::config::get().org_claim)
+                .and_then(organizations::org_id_from_claim),
+        })
+    }
+}
+
+/// An authenticated user holding the operator role, required for admin endpoints
+#[derive(Debug, Clone)]
+pub struct AdminUser(pub AuthenticatedUser);
+
 #[rocket::async_trait]
 impl<'r> FromRequest<'r> for AuthenticatedUser {
     type Error = String;
@@ -63,15 +109,8 @@ impl<'r> FromRequest<'r> for AuthenticatedUser {
 
         let token = &auth_header[7..];
 
-        match jwks_cache.validate_token(token).await {
-            Ok(claims) => {
-                tracing::debug!("Token validated successfully for user: {}", claims.sub);
-                Outcome::Success(AuthenticatedUser {
-                    user_id: claims.sub,
-                    email: claims.email,
-                    name: claims.name,
-                })
-            }
+        match AuthenticatedUser::from_bearer(jwks_cache, token).await {
+            Ok(user) => Outcome::Success(user),
             Err(e) => {
                 tracing::error!("Token validation failed: {}", e);
                 Outcome::Error((Status::Unauthorized, e))
@@ -107,3 +146,37 @@ impl<'a> OpenApiFromRequest<'a> for AuthenticatedUser {
         Ok(Responses::default())
     }
 }
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AdminUser {
+    type Error = String;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match AuthenticatedUser::from_request(request).await {
+            Outcome::Success(user) if user.is_admin() => Outcome::Success(AdminUser(user)),
+            Outcome::Success(user) => {
+                tracing::warn!(
+                    "User {} attempted to access an admin endpoint",
+                    user.user_id
+                );
+                Outcome::Error((Status::Forbidden, "Admin role required".to_string()))
+            }
+            Outcome::Error(e) => Outcome::Error(e),
+            Outcome::Forward(status) => Outcome::Forward(status),
+        }
+    }
+}
+
+impl<'a> OpenApiFromRequest<'a> for AdminUser {
+    fn from_request_input(
+        gen: &mut OpenApiGenerator,
+        name: String,
+        required: bool,
+    ) -> rocket_okapi::Result<RequestHeaderInput> {
+        AuthenticatedUser::from_request_input(gen, name, required)
+    }
+
+    fn get_responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        AuthenticatedUser::get_responses(gen)
+    }
+}
@@ -1,10 +1,11 @@
 use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
-use reqwest;
 use serde::{Deserialize, Deserializer, Serialize};
 use serde_json::Value;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
+use crate::services::http_client;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Jwk {
     pub kty: String,
@@ -41,6 +42,12 @@ where
     }
 }
 
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RealmAccess {
+    #[serde(default)]
+    pub roles: Vec<String>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: String,
@@ -51,25 +58,55 @@ pub struct Claims {
     pub iat: u64,
     pub email: Option<String>,
     pub name: Option<String>,
+    /// Keycloak realm roles assigned to the user
+    #[serde(default)]
+    pub realm_access: Option<RealmAccess>,
+    /// Remaining claims, e.g. the organization claim named by `ORG_CLAIM`
+    #[serde(flatten)]
+    pub other: serde_json::Map<String, Value>,
 }
 
+/// Keycloak signing keys, fetched on first use and cached
+#[derive(Clone)]
 pub struct JwksCache {
-    jwks_uri: String,
-    issuer: String,
+    /// JWKS URI and issuer, None when Keycloak is not configured
+    keycloak: Option<(String, String)>,
     cache: Arc<RwLock<Option<Jwks>>>,
 }
 
 impl JwksCache {
     pub fn new(jwks_uri: String, issuer: String) -> Self {
         Self {
-            jwks_uri,
-            issuer,
+            keycloak: Some((jwks_uri, issuer)),
             cache: Arc::new(RwLock::new(None)),
         }
     }
 
+    /// Read `KEYCLOAK_JWKS_URI` and `KEYCLOAK_ISSUER`. Nothing is fetched until a token is
+    /// validated, and without both variables every JWT is rejected.
+    pub fn from_env() -> Self {
+        match (
+            std::env::var("KEYCLOAK_JWKS_URI"),
+            std::env::var("KEYCLOAK_ISSUER"),
+        ) {
+            (Ok(jwks_uri), Ok(issuer)) => Self::new(jwks_uri, issuer),
+            _ => Self {
+                keycloak: None,
+                cache: Arc::new(RwLock::new(None)),
+            },
+        }
+    }
+
+    fn keycloak(&self) -> Result<(&str, &str), String> {
+        self.keycloak
+            .as_ref()
+            .map(|(jwks_uri, issuer)| (jwks_uri.as_str(), issuer.as_str()))
+            .ok_or_else(|| "Keycloak is not configured".to_string())
+    }
+
     pub async fn fetch_jwks(&self) -> Result<Jwks, String> {
-        let response = reqwest::get(&self.jwks_uri)
+        let (jwks_uri, _) = self.keycloak()?;
+        let response = http_client::send_with_retry(http_client::client().get(jwks_uri))
             .await
             .map_err(|e| format!("Failed to fetch JWKS: {}", e))?;
 
@@ -95,6 +132,7 @@ impl JwksCache {
     }
 
     pub async fn validate_token(&self, token: &str) -> Result<Claims, String> {
+        let (_, issuer) = self.keycloak()?;
         let header = decode_header(token).map_err(|e| format!("Invalid token header: {}", e))?;
 
         let kid = header.kid.ok_or("Missing kid in token header")?;
@@ -110,7 +148,7 @@ impl JwksCache {
             .map_err(|e| format!("Invalid RSA key: {}", e))?;
 
         let mut validation = Validation::new(Algorithm::RS256);
-        validation.set_issuer(&[&self.issuer]);
+        validation.set_issuer(&[issuer]);
         validation.validate_exp = true;
         // Validate that tokens are specifically intended for prompt-backend
         validation.set_audience(&["prompt-backend"]);
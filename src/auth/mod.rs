@@ -1,5 +1,5 @@
 pub mod guard;
 pub mod jwks;
 
-pub use guard::AuthenticatedUser;
+pub use guard::{AdminUser, AuthenticatedUser};
 pub use jwks::JwksCache;
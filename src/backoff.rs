@@ -0,0 +1,34 @@
+use std::time::Duration;
+
+/// Exponential backoff with full jitter.
+///
+/// Returns a random delay in `[0, min(max, base * 2^attempt)]`, so retries from many
+/// sessions or replicas spread out instead of arriving in lockstep.
+pub fn jittered_backoff(attempt: u32, base: Duration, max: Duration) -> Duration {
+    let ceiling = base.saturating_mul(2u32.saturating_pow(attempt)).min(max);
+    ceiling.mul_f64(fastrand::f64())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_jittered_backoff_stays_within_ceiling() {
+        let base = Duration::from_secs(2);
+        let max = Duration::from_secs(60);
+
+        for attempt in 0..10 {
+            let ceiling = base.saturating_mul(2u32.pow(attempt)).min(max);
+            for _ in 0..50 {
+                assert!(jittered_backoff(attempt, base, max) <= ceiling);
+            }
+        }
+    }
+
+    #[test]
+    fn test_jittered_backoff_saturates_on_large_attempts() {
+        let max = Duration::from_secs(60);
+        assert!(jittered_backoff(u32::MAX, Duration::from_secs(2), max) <= max);
+    }
+}
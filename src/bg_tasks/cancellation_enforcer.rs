@@ -1,42 +1,101 @@
+use chrono::Utc;
 use sea_orm::{ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, Set};
 use std::time::Duration;
 use tracing::{error, info, warn};
 
-use crate::entities::session::{self, CancellationStatus, Entity as Session, UiStatus};
+use super::{worker_instance, worker_registry};
+use crate::config;
+use crate::entities::session::{
+    self, CancellationMode, CancellationStatus, Entity as Session, UiStatus,
+};
+use crate::services::operational_events;
+use crate::services::poller_control;
+use crate::services::process_supervisor;
+use crate::services::sandbox_capabilities::{self, Capability, SandboxCapabilities};
+use crate::services::session_state_machine::{Actor, SessionStateMachine, TransitionCause};
+use crate::services::soft_cancel;
+
+/// Name of the loop in the worker registry
+const WORKER: &str = "cancellation_enforcer";
 
 /// Periodic poller that checks for sessions with cancellation requested
 /// and running processes, then kills those processes
 pub async fn run_cancellation_enforcer(db: DatabaseConnection) -> anyhow::Result<()> {
-    info!("Starting cancellation enforcer - checking every 2 seconds");
+    let settings = &config::get().cancellation;
+    info!(
+        "Starting cancellation enforcer - checking every {:?} plus up to {:?} of jitter",
+        settings.interval, settings.jitter
+    );
+
+    worker_registry::register(WORKER, settings.interval + settings.jitter);
 
     loop {
-        tokio::time::sleep(Duration::from_secs(2)).await;
+        tokio::time::sleep(jittered(settings.interval, settings.jitter)).await;
+        // Report even while paused, so other instances do not take this one's sessions over
+        if let Err(e) = worker_instance::heartbeat(&db).await {
+            warn!("Failed to record that this worker instance is alive: {}", e);
+        }
+        if poller_control::paused(&db, WORKER).await {
+            continue;
+        }
+
+        match take_over_orphans(&db, settings.instance_stale_after).await {
+            Ok(count) if count > 0 => info!(
+                "Took over {} sessions whose worker instance stopped reporting",
+                count
+            ),
+            Ok(_) => {}
+            Err(e) => error!("Failed to take over orphaned sessions: {}", e),
+        }
 
         match enforce_cancellations(&db).await {
             Ok(count) => {
+                worker_registry::record_success(WORKER, count as u64);
                 if count > 0 {
                     info!("Killed {} running processes for cancelled sessions", count);
                 }
             }
             Err(e) => {
+                worker_registry::record_error(WORKER, &e);
+                operational_events::record_iteration_error(&db, WORKER, &e).await;
                 error!("Failed to enforce cancellations: {}", e);
             }
         }
     }
 }
 
-/// Find sessions with cancellation requested and a running process, then kill those processes
+/// `interval` plus a random share of `jitter`
+fn jittered(interval: Duration, jitter: Duration) -> Duration {
+    interval + Duration::from_millis(fastrand::u64(0..=jitter.as_millis() as u64))
+}
+
+/// Find sessions with cancellation requested and a running process, then kill those processes.
+/// Soft cancellations are first asked to wrap up and only killed once their grace period has
+/// passed. A process that outlives the first signal gets the next ones configured in
+/// `CANCELLATION_KILL_SIGNALS`.
 async fn enforce_cancellations(db: &DatabaseConnection) -> anyhow::Result<usize> {
-    // Query all sessions with cancellation requested and a process PID
+    // A PID only means something in the instance that spawned it, so only this instance's
+    // sessions are signalled
     let sessions_to_cancel = Session::find()
         .filter(session::Column::CancellationStatus.eq(CancellationStatus::Requested))
         .filter(session::Column::ProcessPid.is_not_null())
+        .filter(session::Column::WorkerInstanceId.eq(worker_instance::instance_id()))
         .all(db)
         .await?;
+    let signals = &config::get().cancellation.signals;
+    let first_signal = signals.first().map_or("TERM", |s| s.name.as_str());
 
     let mut count = 0;
 
     for session_model in sessions_to_cancel {
+        let session_model = if session_model.cancellation_mode == Some(CancellationMode::Soft) {
+            match wrap_up_expired(db, session_model).await {
+                Some(session_model) => session_model,
+                None => continue,
+            }
+        } else {
+            session_model
+        };
         let session_id = session_model.id;
         let pid = match session_model.process_pid {
             Some(p) => p,
@@ -54,81 +113,208 @@ async fn enforce_cancellations(db: &DatabaseConnection) -> anyhow::Result<usize>
             pid, session_id
         );
 
-        // Kill the process using the kill command
-        // First try SIGTERM (graceful shutdown)
-        let kill_result = std::process::Command::new("kill")
-            .arg("-TERM")
-            .arg(pid.to_string())
-            .output();
-
-        match kill_result {
-            Ok(output) if output.status.success() => {
-                info!(
-                    "Successfully sent SIGTERM to process {} for session {}",
-                    pid, session_id
-                );
-                count += 1;
+        // Start with the first configured signal; a process that cannot be signalled and no
+        // longer runs already ended
+        let outcome = if process_supervisor::signal(pid as u32, first_signal) {
+            info!(
+                "Successfully sent SIG{} to process {} for session {}",
+                first_signal, pid, session_id
+            );
+            tokio::spawn(process_supervisor::escalate(pid as u32, signals));
+            "after killing its process"
+        } else if !process_supervisor::signal(pid as u32, "0") {
+            info!(
+                "Process {} for session {} already terminated",
+                pid, session_id
+            );
+            "as its process was already dead"
+        } else {
+            warn!(
+                "Failed to send SIG{} to process {} for session {}",
+                first_signal, pid, session_id
+            );
+            continue;
+        };
+        count += 1;
 
-                // Update session to mark as cancelled and clear PID
-                let mut active_session: session::ActiveModel = session_model.into();
-                active_session.cancellation_status = Set(Some(CancellationStatus::Cancelled));
-                active_session.ui_status = Set(UiStatus::NeedsReview);
-                active_session.process_pid = Set(None);
-
-                if let Err(e) = active_session.update(db).await {
-                    error!(
-                        "Failed to update session {} after killing process: {}",
-                        session_id, e
-                    );
-                } else {
-                    info!(
-                        "Session {} marked as cancelled after killing process {}",
-                        session_id, pid
-                    );
-                }
+        // Mark the session cancelled and clear its PID
+        let from = session_model.ui_status.clone();
+        let mut active_session = mark_cancelled(session_model);
+        active_session.process_pid = Set(None);
+        active_session.worker_instance_id = Set(None);
+
+        match active_session.update(db).await {
+            Ok(updated) => {
+                info!("Session {} marked as cancelled {}", session_id, outcome);
+                SessionStateMachine::after_save(
+                    db,
+                    &from,
+                    &updated,
+                    TransitionCause::Cancelled,
+                    &Actor::System("cancellation_enforcer"),
+                )
+                .await;
             }
-            Ok(output) => {
-                // Check stderr for "No such process" error
-                let stderr = String::from_utf8_lossy(&output.stderr);
-                if stderr.contains("No such process") {
-                    info!(
-                        "Process {} for session {} already terminated",
-                        pid, session_id
-                    );
-
-                    // Update session anyway to clear the PID and mark as cancelled
-                    let mut active_session: session::ActiveModel = session_model.into();
-                    active_session.cancellation_status = Set(Some(CancellationStatus::Cancelled));
-                    active_session.ui_status = Set(UiStatus::NeedsReview);
-                    active_session.process_pid = Set(None);
-
-                    if let Err(e) = active_session.update(db).await {
-                        error!(
-                            "Failed to update session {} after process already dead: {}",
-                            session_id, e
-                        );
-                    } else {
-                        info!(
-                            "Session {} marked as cancelled (process was already dead)",
-                            session_id
-                        );
-                    }
-                    count += 1;
-                } else {
-                    warn!(
-                        "Failed to kill process {} for session {}: {}",
-                        pid, session_id, stderr
-                    );
+            Err(e) => error!(
+                "Failed to update session {} after cancelling process {}: {}",
+                session_id, pid, e
+            ),
+        }
+    }
+
+    Ok(count)
+}
+
+/// Clear the PIDs of sessions whose worker instance stopped reporting, since their processes
+/// went with it. Sessions waiting on their process to be cancelled are marked cancelled.
+async fn take_over_orphans(
+    db: &DatabaseConnection,
+    stale_after: Duration,
+) -> anyhow::Result<usize> {
+    let last_seen = worker_instance::last_seen(db).await?;
+    let now = Utc::now();
+    let orphaned: Vec<session::Model> = Session::find()
+        .filter(session::Column::ProcessPid.is_not_null())
+        .all(db)
+        .await?
+        .into_iter()
+        .filter(|s| {
+            worker_instance::is_orphaned(
+                s.worker_instance_id.as_deref(),
+                &last_seen,
+                now,
+                stale_after,
+            )
+        })
+        .collect();
+
+    let mut count = 0;
+    for session_model in orphaned {
+        let session_id = session_model.id;
+        warn!(
+            "Taking over session {} from worker instance {}, which stopped reporting; \
+             its process {:?} is presumed gone",
+            session_id,
+            session_model
+                .worker_instance_id
+                .as_deref()
+                .unwrap_or("unknown"),
+            session_model.process_pid
+        );
+        let cancel_requested =
+            session_model.cancellation_status == Some(CancellationStatus::Requested);
+        let from = session_model.ui_status.clone();
+        let mut active_session = if cancel_requested {
+            mark_cancelled(session_model)
+        } else {
+            session_model.into()
+        };
+        active_session.process_pid = Set(None);
+        active_session.worker_instance_id = Set(None);
+
+        match active_session.update(db).await {
+            Ok(updated) => {
+                count += 1;
+                if cancel_requested {
+                    SessionStateMachine::after_save(
+                        db,
+                        &from,
+                        &updated,
+                        TransitionCause::Cancelled,
+                        &Actor::System("cancellation_enforcer"),
+                    )
+                    .await;
                 }
             }
-            Err(e) => {
-                error!(
-                    "Failed to execute kill command for process {} (session {}): {}",
-                    pid, session_id, e
-                );
-            }
+            Err(e) => error!("Failed to take over session {}: {}", session_id, e),
         }
     }
 
+    let before = now - chrono::Duration::from_std(stale_after).unwrap_or_default();
+    worker_instance::prune(db, before).await?;
     Ok(count)
 }
+
+/// Ask a soft-cancelled run to wrap up, or let it keep going while its grace period lasts.
+///
+/// Returns the session once its process should be terminated: the grace period has passed or
+/// the agent could not be asked.
+async fn wrap_up_expired(
+    db: &DatabaseConnection,
+    session_model: session::Model,
+) -> Option<session::Model> {
+    let session_id = session_model.id;
+    let capabilities = SandboxCapabilities::from_json(session_model.sandbox_capabilities.as_ref());
+    if !sandbox_capabilities::supports(capabilities.as_ref(), Capability::FileApi) {
+        warn!(
+            "Session {} cannot be asked to wrap up, terminating it instead: {}",
+            session_id,
+            sandbox_capabilities::unsupported_message("soft cancellation", Capability::FileApi)
+        );
+        return Some(session_model);
+    }
+    let Some(signalled_at) = session_model.cancel_signalled_at else {
+        if let Err(e) = soft_cancel::signal(&session_model).await {
+            warn!(
+                "Failed to ask session {} to wrap up, terminating it instead: {}",
+                session_id, e
+            );
+            return Some(session_model);
+        }
+        info!("Asked session {} to wrap up before cancelling", session_id);
+
+        let mut active_session: session::ActiveModel = session_model.into();
+        active_session.cancel_signalled_at = Set(Some(Utc::now().into()));
+        if let Err(e) = active_session.update(db).await {
+            error!(
+                "Failed to record wrap-up request for session {}: {}",
+                session_id, e
+            );
+        }
+        return None;
+    };
+
+    let elapsed = Utc::now()
+        .signed_duration_since(signalled_at)
+        .to_std()
+        .unwrap_or_default();
+    if elapsed < config::get().soft_cancel_grace {
+        return None;
+    }
+    info!(
+        "Session {} did not wrap up within {:?}, terminating it",
+        session_id,
+        config::get().soft_cancel_grace
+    );
+    Some(session_model)
+}
+
+/// Mark a session as cancelled and move it to NeedsReview.
+///
+/// If the state machine rejects the transition the session keeps its status.
+fn mark_cancelled(session_model: session::Model) -> session::ActiveModel {
+    let mut active_session = SessionStateMachine::transition(
+        session_model.clone(),
+        UiStatus::NeedsReview,
+        TransitionCause::Cancelled,
+        &Actor::System("cancellation_enforcer"),
+    )
+    .unwrap_or_else(|_| session_model.into());
+    active_session.cancellation_status = Set(Some(CancellationStatus::Cancelled));
+    active_session
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_jittered_stays_within_jitter() {
+        let interval = Duration::from_secs(2);
+        for _ in 0..100 {
+            let wait = jittered(interval, Duration::from_millis(500));
+            assert!(wait >= interval && wait <= interval + Duration::from_millis(500));
+        }
+        assert_eq!(jittered(interval, Duration::ZERO), interval);
+    }
+}
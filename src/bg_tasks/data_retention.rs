@@ -0,0 +1,82 @@
+use chrono::Utc;
+use sea_orm::DatabaseConnection;
+use tracing::{error, info};
+
+use super::worker_registry;
+use crate::config;
+use crate::services::{message_blobs, operational_events, user_erasure};
+
+/// Name of the loop in the worker registry
+const WORKER: &str = "data_retention";
+
+/// Messages purged per batch, so one pass never holds many payloads in memory
+const PURGE_BATCH: u64 = 500;
+
+/// Periodic task that finishes pending user erasures, deletes operational events past
+/// `OPERATIONAL_EVENT_RETENTION_DAYS` and, when `MESSAGE_RETENTION_DAYS` is set, purges message
+/// content older than the retention period
+pub async fn run_data_retention(db: DatabaseConnection) -> anyhow::Result<()> {
+    let interval = config::get().data_retention_interval;
+
+    info!(
+        "Starting data retention - checking every {} seconds",
+        interval.as_secs()
+    );
+
+    worker_registry::register(WORKER, interval);
+
+    loop {
+        tokio::time::sleep(interval).await;
+
+        match enforce_retention(&db).await {
+            Ok(processed) => worker_registry::record_success(WORKER, processed),
+            Err(e) => {
+                worker_registry::record_error(WORKER, &e);
+                operational_events::record_iteration_error(&db, WORKER, &e).await;
+                error!("Data retention pass failed: {}", e);
+            }
+        }
+    }
+}
+
+/// Run one retention pass, returning how many sessions, events and messages were deleted or
+/// purged
+async fn enforce_retention(db: &DatabaseConnection) -> anyhow::Result<u64> {
+    let sessions_deleted = user_erasure::complete_pending(db).await?;
+    if sessions_deleted > 0 {
+        info!(
+            "Deleted {} sessions of pending user erasures",
+            sessions_deleted
+        );
+    }
+
+    let events_cutoff = Utc::now()
+        - chrono::Duration::from_std(config::get().operational_events.retention)
+            .unwrap_or_default();
+    let events_deleted = operational_events::prune(db, events_cutoff).await?;
+    if events_deleted > 0 {
+        info!("Deleted {} expired operational events", events_deleted);
+    }
+
+    let Some(retention) = config::get().message_retention else {
+        return Ok(sessions_deleted + events_deleted);
+    };
+    let cutoff = Utc::now() - retention;
+    let mut messages_purged = 0;
+    loop {
+        let purged = message_blobs::purge_older_than(db, cutoff, PURGE_BATCH).await?;
+        messages_purged += purged;
+        if purged < PURGE_BATCH {
+            break;
+        }
+    }
+    if messages_purged > 0 {
+        info!(
+            "Purged the content of {} messages older than {} days",
+            messages_purged,
+            retention.as_secs() / (24 * 60 * 60)
+        );
+    }
+
+    Ok(sessions_deleted + events_deleted + messages_purged)
+}
@@ -0,0 +1,94 @@
+use sea_orm::DatabaseConnection;
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+use super::worker_registry;
+use crate::entities::operational_event::OperationalEventKind;
+use crate::services::dead_letter_queue::{pending_stats, PendingDlqStats};
+use crate::services::operational_events;
+
+/// Name of the loop in the worker registry
+const WORKER: &str = "dlq_monitor";
+
+/// How often pending DLQ entries are counted
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Pending entries seen by the last successful pass, for the readiness probe
+static LAST_STATS: LazyLock<Mutex<Vec<PendingDlqStats>>> = LazyLock::new(|| Mutex::new(Vec::new()));
+
+/// Periodic task that publishes pending DLQ counts and ages as metrics
+pub async fn run_dlq_monitor(db: DatabaseConnection) -> anyhow::Result<()> {
+    info!(
+        "Starting DLQ monitor - checking every {} seconds",
+        POLL_INTERVAL.as_secs()
+    );
+
+    worker_registry::register(WORKER, POLL_INTERVAL);
+
+    loop {
+        match observe_pending(&db).await {
+            Ok(pending) => worker_registry::record_success(WORKER, pending),
+            Err(e) => {
+                worker_registry::record_error(WORKER, &e);
+                operational_events::record(
+                    &db,
+                    WORKER,
+                    OperationalEventKind::Database,
+                    &e.to_string(),
+                    None,
+                )
+                .await;
+                error!("DLQ monitor failed: {}", e);
+            }
+        }
+
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+/// Refresh the DLQ gauges, returning the number of pending entries
+async fn observe_pending(db: &DatabaseConnection) -> Result<u64, sea_orm::DbErr> {
+    let stats = pending_stats(db).await?;
+    let now = chrono::Utc::now();
+    let metrics = crate::metrics::get();
+
+    // Task types without pending entries drop out instead of keeping their last value
+    metrics.dlq_pending_entries.reset();
+    metrics.dlq_oldest_pending_age_seconds.reset();
+    for entry in &stats {
+        let age = entry.oldest_age_secs(now);
+        metrics
+            .dlq_pending_entries
+            .with_label_values(&[&entry.task_type])
+            .set(entry.pending);
+        metrics
+            .dlq_oldest_pending_age_seconds
+            .with_label_values(&[&entry.task_type])
+            .set(age as i64);
+
+        if age > crate::config::get().dlq_alert_age.as_secs() {
+            warn!(
+                "{} pending {} DLQ entries, oldest waiting {} seconds",
+                entry.pending, entry.task_type, age
+            );
+        }
+    }
+
+    let pending = stats.iter().map(|s| s.pending.max(0) as u64).sum();
+    *LAST_STATS.lock().unwrap_or_else(|e| e.into_inner()) = stats;
+    Ok(pending)
+}
+
+/// Task types whose oldest pending entry, as of the last pass, is older than `DLQ_ALERT_AGE_SECS`
+pub fn aged_task_types() -> Vec<String> {
+    let now = chrono::Utc::now();
+    let threshold = crate::config::get().dlq_alert_age.as_secs();
+    LAST_STATS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .filter(|s| s.oldest_age_secs(now) > threshold)
+        .map(|s| s.task_type.clone())
+        .collect()
+}
@@ -0,0 +1,83 @@
+use sea_orm::DatabaseConnection;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+use super::worker_registry;
+use crate::services::integrity::{detect_orphans, repair_orphans, OrphanCounts};
+use crate::services::operational_events;
+
+/// Name of the loop in the worker registry
+const WORKER: &str = "integrity_checker";
+
+/// Default interval between integrity checks
+const DEFAULT_INTERVAL_SECS: u64 = 600;
+
+/// Periodic task that finds rows referencing deleted sessions or prompts,
+/// reports them via metrics and repairs them
+pub async fn run_integrity_checker(db: DatabaseConnection) -> anyhow::Result<()> {
+    let interval_secs = std::env::var("INTEGRITY_CHECK_INTERVAL_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_INTERVAL_SECS);
+
+    info!(
+        "Starting integrity checker - checking every {} seconds",
+        interval_secs
+    );
+
+    worker_registry::register(WORKER, Duration::from_secs(interval_secs));
+
+    loop {
+        tokio::time::sleep(Duration::from_secs(interval_secs)).await;
+
+        match check_integrity(&db).await {
+            Ok(repaired) => worker_registry::record_success(WORKER, repaired),
+            Err(e) => {
+                worker_registry::record_error(WORKER, &e);
+                operational_events::record_iteration_error(&db, WORKER, &e).await;
+                error!("Integrity check failed: {}", e);
+            }
+        }
+    }
+}
+
+/// Run one detect-and-repair pass, returning how many rows were repaired
+async fn check_integrity(db: &DatabaseConnection) -> anyhow::Result<u64> {
+    let found = detect_orphans(db).await?;
+    observe(&found, |kind, count| {
+        crate::metrics::get()
+            .integrity_orphans
+            .with_label_values(&[kind])
+            .set(count as i64)
+    });
+
+    if found.total() == 0 {
+        return Ok(0);
+    }
+
+    warn!(
+        "Integrity check found orphans: {} prompts, {} messages, {} DLQ entries",
+        found.prompts, found.messages, found.dlq_entries
+    );
+
+    let repaired = repair_orphans(db).await?;
+    observe(&repaired, |kind, count| {
+        crate::metrics::get()
+            .integrity_orphans_repaired_total
+            .with_label_values(&[kind])
+            .inc_by(count)
+    });
+
+    info!(
+        "Integrity check repaired orphans: deleted {} prompts and {} messages, abandoned {} DLQ entries",
+        repaired.prompts, repaired.messages, repaired.dlq_entries
+    );
+
+    Ok(repaired.total())
+}
+
+fn observe(counts: &OrphanCounts, mut f: impl FnMut(&str, u64)) {
+    f("prompt", counts.prompts);
+    f("message", counts.messages);
+    f("dlq_entry", counts.dlq_entries);
+}
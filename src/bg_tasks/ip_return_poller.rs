@@ -1,25 +1,57 @@
-use sea_orm::{ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, Set};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, Condition, DatabaseConnection, EntityTrait, QueryFilter, Set,
+};
 use std::time::Duration;
 use tracing::{error, info, warn};
 
+use super::{prompt_run, worker_registry};
+use crate::entities::operational_event::OperationalEventKind;
 use crate::entities::session::{self, Entity as Session, UiStatus};
-use crate::services::dead_letter_queue::{exists_in_dlq, insert_dlq_entry, MAX_RETRY_COUNT};
+use crate::entities::session_event::SessionEventType;
+use crate::services::chaos::{self, Fault};
+use crate::services::dead_letter_queue::{
+    exists_in_dlq, insert_dlq_entry, IP_RETURN_TASK_TYPE, MAX_RETRY_COUNT,
+};
+use crate::services::ip_allocator::{self, ReturnOutcome};
+use crate::services::operational_events;
+use crate::services::poller_control;
+use crate::services::session_events;
+use crate::services::session_state_machine::{Actor, SessionStateMachine, TransitionCause};
+use crate::services::unpushed_work::{self, UnpushedWork};
+
+/// Name of the loop in the worker registry
+const WORKER: &str = "ip_return_poller";
+
+/// Actor of the status transitions and events recorded here
+const ACTOR: Actor = Actor::System(WORKER);
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
 
 /// Periodic poller that checks for sessions in NeedsReview or Archived status every 5 seconds
-/// and returns their IPs to the allocator
+/// and returns their IPs to the allocator. Sessions that keep their sandbox until archive are
+/// only picked up once archived, and sessions whose sandbox holds unpushed work are moved to
+/// NeedsAttention instead.
 pub async fn run_ip_return_poller(db: DatabaseConnection) -> anyhow::Result<()> {
     info!("Starting IP return poller - checking every 5 seconds");
 
+    worker_registry::register(WORKER, POLL_INTERVAL);
+
     loop {
-        tokio::time::sleep(Duration::from_secs(5)).await;
+        tokio::time::sleep(POLL_INTERVAL).await;
+        if poller_control::paused(&db, WORKER).await {
+            continue;
+        }
 
         match poll_and_return_ips(&db).await {
             Ok(count) => {
+                worker_registry::record_success(WORKER, count as u64);
                 if count > 0 {
                     info!("Processed {} sessions for IP return", count);
                 }
             }
             Err(e) => {
+                worker_registry::record_error(WORKER, &e);
+                operational_events::record_iteration_error(&db, WORKER, &e).await;
                 error!("Failed to poll and return IPs: {}", e);
             }
         }
@@ -30,25 +62,42 @@ pub async fn run_ip_return_poller(db: DatabaseConnection) -> anyhow::Result<()>
 async fn poll_and_return_ips(db: &DatabaseConnection) -> anyhow::Result<usize> {
     // Query all sessions with NeedsReview or Archived status that still have sbx_config
     let returning_sessions = Session::find()
-        .filter(session::Column::UiStatus.is_in([UiStatus::NeedsReview, UiStatus::Archived]))
+        .filter(
+            Condition::any()
+                .add(session::Column::UiStatus.eq(UiStatus::Archived))
+                .add(
+                    Condition::all()
+                        .add(session::Column::UiStatus.eq(UiStatus::NeedsReview))
+                        .add(session::Column::KeepSandboxUntilArchive.eq(false)),
+                ),
+        )
         .filter(session::Column::SbxConfig.is_not_null())
         .all(db)
         .await?;
 
     let count = returning_sessions.len();
 
-    // Get IP allocator URL from environment
-    let ip_allocator_url =
-        std::env::var("IP_ALLOCATOR_URL").unwrap_or_else(|_| "http://localhost:8000".to_string());
-    let ip_client = ip_allocator_client::Client::new(&ip_allocator_url);
-
     // Process each session
     for session in returning_sessions {
         let session_id = session.id;
         let retry_count = session.ip_return_retry_count;
 
+        // The sandbox agent can report a session finished while its run is still wrapping up;
+        // keep the sandbox until the run ends
+        match prompt_run::has_active_run(db, session_id).await {
+            Ok(false) => {}
+            Ok(true) => continue,
+            Err(e) => {
+                error!(
+                    "Failed to check for an active run of session {}: {}",
+                    session_id, e
+                );
+                continue;
+            }
+        }
+
         // Check if this session is already in the DLQ
-        match exists_in_dlq(db, "ip_return_poller", session_id).await {
+        match exists_in_dlq(db, IP_RETURN_TASK_TYPE, session_id).await {
             Ok(true) => {
                 // Already in DLQ, skip processing
                 continue;
@@ -67,18 +116,7 @@ async fn poll_and_return_ips(db: &DatabaseConnection) -> anyhow::Result<usize> {
 
         // Extract the borrowed IP and token from sbx_config
         let (item, borrow_token) = match &session.sbx_config {
-            Some(config) => {
-                let item = config
-                    .get("item")
-                    .cloned()
-                    .unwrap_or_else(|| config.clone());
-                let borrow_token = config
-                    .get("borrow_token")
-                    .and_then(|v| v.as_str())
-                    .unwrap_or("")
-                    .to_string();
-                (item, borrow_token)
-            }
+            Some(config) => ip_allocator::borrowed_item(config),
             None => {
                 warn!(
                     "Session {} in NeedsReview or Archived status but sbx_config is None, archiving anyway",
@@ -89,40 +127,145 @@ async fn poll_and_return_ips(db: &DatabaseConnection) -> anyhow::Result<usize> {
             }
         };
 
+        // Returning the IP destroys the sandbox, so first look for work that never reached the
+        // remote. Sessions the user released or that were deleted skip the check, as do retries,
+        // which were checked before their first attempt. A check that cannot run does not
+        // hold the IP.
+        if session.ip_return_key.is_none()
+            && session.deleted_at.is_none()
+            && !unpushed_work::is_released(&session)
+        {
+            match unpushed_work::check(&session).await {
+                Ok(None) => {}
+                Ok(Some(work)) => {
+                    hold_for_unpushed_work(db, session, work).await;
+                    continue;
+                }
+                Err(e) => {
+                    warn!(
+                        "Could not check session {} for unpushed work, returning its IP: {}",
+                        session_id, e
+                    );
+                    operational_events::record(
+                        db,
+                        WORKER,
+                        OperationalEventKind::Sandbox,
+                        &format!("Could not check for unpushed work: {}", e),
+                        Some(serde_json::json!({ "session_id": session_id })),
+                    )
+                    .await;
+                }
+            }
+        }
+
+        // Every attempt for this borrow sends the same key, so a return that succeeded before
+        // the session update failed is recognised when it is retried
+        let (return_key, session) = match ip_allocator::return_key(db, session).await {
+            Ok(keyed) => keyed,
+            Err(e) => {
+                error!(
+                    "Failed to store IP return key of session {}: {}",
+                    session_id, e
+                );
+                operational_events::record(
+                    db,
+                    WORKER,
+                    OperationalEventKind::Database,
+                    &format!("Failed to store IP return key: {}", e),
+                    Some(serde_json::json!({ "session_id": session_id })),
+                )
+                .await;
+                continue;
+            }
+        };
+
         info!(
-            "Returning IP for session {} (attempt {})",
+            "Returning IP for session {} (attempt {}, key {})",
             session_id,
-            retry_count + 1
+            retry_count + 1,
+            return_key
         );
 
-        // Return the IP
-        let return_input = ip_allocator_client::types::ReturnInput { item, borrow_token };
+        let return_result = if chaos::should_fail(Fault::IpReturn) {
+            Err("Injected IP return failure".to_string())
+        } else {
+            ip_allocator::return_item(item, borrow_token, return_key).await
+        };
 
-        match ip_client.handlers_ip_return_item(&return_input).await {
-            Ok(_) => {
-                info!("Successfully returned IP for session {}", session_id);
+        match return_result {
+            Ok(outcome) => {
+                match outcome {
+                    ReturnOutcome::Returned => {
+                        info!("Successfully returned IP for session {}", session_id)
+                    }
+                    ReturnOutcome::AlreadyReturned(status) => info!(
+                        "IP allocator no longer knows the borrow of session {} ({}), treating it as returned",
+                        session_id, status
+                    ),
+                }
 
-                // Set sbx_config to null, reset retry count, and update ui_status to Archived
-                let mut active_session: session::ActiveModel = session.into();
+                // Set sbx_config to null and reset retry count. Sessions in review move to
+                // NeedsReviewIpReturned; archived sessions stay archived.
+                let from = session.ui_status.clone();
+                let mut active_session = if session.ui_status == UiStatus::Archived {
+                    session.into()
+                } else {
+                    SessionStateMachine::transition(
+                        session.clone(),
+                        UiStatus::NeedsReviewIpReturned,
+                        TransitionCause::IpReturned,
+                        &ACTOR,
+                    )
+                    .unwrap_or_else(|_| session.into())
+                };
                 active_session.sbx_config = Set(None);
-                active_session.ui_status = Set(UiStatus::NeedsReviewIpReturned);
                 active_session.ip_return_retry_count = Set(0);
+                active_session.ip_return_key = Set(None);
 
-                if let Err(e) = active_session.update(db).await {
-                    error!(
-                        "Failed to update session {} after IP return: {}",
-                        session_id, e
-                    );
+                match active_session.update(db).await {
+                    Ok(updated) => {
+                        info!(
+                            "Updated session {} - set sbx_config to null after IP return",
+                            session_id
+                        );
+                        if updated.ui_status == from {
+                            session_events::record(
+                                db,
+                                session_id,
+                                SessionEventType::IpReturned,
+                                &ACTOR,
+                                None,
+                            )
+                            .await;
+                        } else {
+                            SessionStateMachine::after_save(
+                                db,
+                                &from,
+                                &updated,
+                                TransitionCause::IpReturned,
+                                &ACTOR,
+                            )
+                            .await;
+                        }
+                    }
                     // Continue processing other sessions
-                } else {
-                    info!(
-                        "Updated session {} - set sbx_config to null and ui_status to Archived",
-                        session_id
-                    );
+                    Err(e) => {
+                        error!(
+                            "Failed to update session {} after IP return: {}",
+                            session_id, e
+                        );
+                        operational_events::record(
+                            db,
+                            WORKER,
+                            OperationalEventKind::Database,
+                            &format!("Failed to update session after IP return: {}", e),
+                            Some(serde_json::json!({ "session_id": session_id })),
+                        )
+                        .await;
+                    }
                 }
             }
-            Err(e) => {
-                let error_msg = format!("{}", e);
+            Err(error_msg) => {
                 error!(
                     "Failed to return IP for session {}: {}",
                     session_id, error_msg
@@ -130,6 +273,28 @@ async fn poll_and_return_ips(db: &DatabaseConnection) -> anyhow::Result<usize> {
 
                 // Increment retry count
                 let new_retry_count = retry_count + 1;
+                operational_events::record(
+                    db,
+                    WORKER,
+                    OperationalEventKind::Allocator,
+                    &error_msg,
+                    Some(serde_json::json!({
+                        "session_id": session_id,
+                        "attempt": new_retry_count,
+                    })),
+                )
+                .await;
+                session_events::record(
+                    db,
+                    session_id,
+                    SessionEventType::IpReturnFailed,
+                    &ACTOR,
+                    Some(serde_json::json!({
+                        "attempt": new_retry_count,
+                        "error": error_msg,
+                    })),
+                )
+                .await;
 
                 // Check if we've exceeded the max retry count
                 if new_retry_count >= MAX_RETRY_COUNT {
@@ -141,7 +306,7 @@ async fn poll_and_return_ips(db: &DatabaseConnection) -> anyhow::Result<usize> {
                     // Insert into DLQ
                     match insert_dlq_entry(
                         db,
-                        "ip_return_poller",
+                        IP_RETURN_TASK_TYPE,
                         session_id,
                         session.sbx_config.clone(),
                         new_retry_count,
@@ -172,6 +337,14 @@ async fn poll_and_return_ips(db: &DatabaseConnection) -> anyhow::Result<usize> {
                                 "Failed to add session {} to dead letter queue: {}",
                                 session_id, e
                             );
+                            operational_events::record(
+                                db,
+                                WORKER,
+                                OperationalEventKind::Database,
+                                &format!("Failed to add session to the dead letter queue: {}", e),
+                                Some(serde_json::json!({ "session_id": session_id })),
+                            )
+                            .await;
                         }
                     }
                 } else {
@@ -192,3 +365,55 @@ async fn poll_and_return_ips(db: &DatabaseConnection) -> anyhow::Result<usize> {
 
     Ok(count)
 }
+
+/// Keep the sandbox of `session` and move it to NeedsAttention with the `work` found in it
+async fn hold_for_unpushed_work(
+    db: &DatabaseConnection,
+    session: session::Model,
+    work: UnpushedWork,
+) {
+    let session_id = session.id;
+    let from = session.ui_status.clone();
+    let mut active_session = match SessionStateMachine::transition(
+        session,
+        UiStatus::NeedsAttention,
+        TransitionCause::UnpushedWorkFound,
+        &ACTOR,
+    ) {
+        Ok(active_session) => active_session,
+        Err(e) => {
+            error!(
+                "Failed to hold session {} for unpushed work: {}",
+                session_id, e
+            );
+            return;
+        }
+    };
+    warn!(
+        "Session {} has unpushed work on {} ({} commits ahead, {} uncommitted changes), keeping its sandbox",
+        session_id,
+        work.branch,
+        work.commits_ahead,
+        work.dirty_files.len()
+    );
+    active_session.status_message = Set(Some(work.describe()));
+    active_session.unpushed_work = Set(Some(work.to_json()));
+    active_session.updated_at = Set(chrono::Utc::now().into());
+
+    match active_session.update(db).await {
+        Ok(updated) => {
+            SessionStateMachine::after_save(
+                db,
+                &from,
+                &updated,
+                TransitionCause::UnpushedWorkFound,
+                &ACTOR,
+            )
+            .await
+        }
+        Err(e) => error!(
+            "Failed to update session {} after finding unpushed work: {}",
+            session_id, e
+        ),
+    }
+}
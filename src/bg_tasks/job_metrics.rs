@@ -0,0 +1,86 @@
+//! Per-job metrics for the apalis workers, recorded into the registry served at `/metrics`.
+//!
+//! apalis' own `PrometheusLayer` reports through the `metrics` facade, which nothing in this
+//! process installs a recorder for, so its numbers were dropped. This layer counts each job and
+//! times it in `worker_jobs_total` and `worker_job_duration_seconds`, labelled with the job type
+//! (`OutboxJob`, as in `worker_queue_jobs`) and its outcome, `ok` or `failed`.
+
+use apalis::prelude::{Error, Job, Request};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tower::{Layer, Service};
+
+#[derive(Debug, Clone, Default)]
+pub struct JobMetricsLayer;
+
+impl<S> Layer<S> for JobMetricsLayer {
+    type Service = JobMetricsService<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        JobMetricsService { service }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct JobMetricsService<S> {
+    service: S,
+}
+
+impl<S, J> Service<Request<J>> for JobMetricsService<S>
+where
+    S: Service<Request<J>, Error = Error>,
+    S::Future: Send + 'static,
+    J: Job,
+{
+    type Response = S::Response;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<S::Response, Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<J>) -> Self::Future {
+        let start = Instant::now();
+        let job = self.service.call(request);
+        Box::pin(async move {
+            let result = job.await;
+            let outcome = if result.is_ok() { "ok" } else { "failed" };
+            let labels = [J::NAME, outcome];
+            let metrics = crate::metrics::get();
+            metrics.worker_jobs_total.with_label_values(&labels).inc();
+            metrics
+                .worker_job_duration_seconds
+                .with_label_values(&labels)
+                .observe(start.elapsed().as_secs_f64());
+            result
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_records_into_served_registry() {
+        use crate::bg_tasks::outbox_events::OutboxEventJob;
+        use apalis::prelude::service_fn;
+
+        let mut service = JobMetricsLayer.layer(service_fn(|_job: OutboxEventJob| async {
+            Err::<(), _>(std::io::Error::other("boom"))
+        }));
+        let counter = crate::metrics::get()
+            .worker_jobs_total
+            .with_label_values(&["OutboxEventJob", "failed"]);
+        let before = counter.get();
+
+        let job = OutboxEventJob {
+            event_id: uuid::Uuid::nil().to_string(),
+        };
+        assert!(service.call(Request::new(job)).await.is_err());
+        assert_eq!(counter.get(), before + 1);
+    }
+}
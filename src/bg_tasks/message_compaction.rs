@@ -0,0 +1,64 @@
+use sea_orm::{DatabaseConnection, EntityTrait};
+use tracing::{error, info};
+
+use super::worker_registry;
+use crate::config;
+use crate::entities::session::Entity as Session;
+use crate::services::message_compaction;
+use crate::services::operational_events;
+
+/// Name of the loop in the worker registry
+const WORKER: &str = "message_compaction";
+
+/// Sessions compacted per pass, largest first
+const SESSIONS_PER_PASS: u64 = 50;
+
+/// Periodic task that compacts the older tool results of sessions storing more messages than
+/// `SESSION_MESSAGE_SOFT_LIMIT` or `SESSION_MESSAGE_SOFT_LIMIT_BYTES` allow
+pub async fn run_message_compaction(db: DatabaseConnection) -> anyhow::Result<()> {
+    let quota = &config::get().message_quota;
+    if !quota.enabled() {
+        info!("Message compaction disabled");
+        return Ok(());
+    }
+    info!(
+        "Starting message compaction - limits of {:?} messages and {:?} bytes per session, checking every {} seconds",
+        quota.max_messages,
+        quota.max_bytes,
+        quota.interval.as_secs()
+    );
+
+    worker_registry::register(WORKER, quota.interval);
+
+    loop {
+        tokio::time::sleep(quota.interval).await;
+
+        match compaction_pass(&db).await {
+            Ok(compacted) => worker_registry::record_success(WORKER, compacted),
+            Err(e) => {
+                worker_registry::record_error(WORKER, &e);
+                operational_events::record_iteration_error(&db, WORKER, &e).await;
+                error!("Message compaction pass failed: {}", e);
+            }
+        }
+    }
+}
+
+/// Compact the sessions over a limit, returning how many messages were compacted
+async fn compaction_pass(db: &DatabaseConnection) -> anyhow::Result<u64> {
+    let quota = &config::get().message_quota;
+    let mut compacted = 0;
+    for usage in message_compaction::over_quota(db, quota, SESSIONS_PER_PASS).await? {
+        let Some(session) = Session::find_by_id(usage.session_id).one(db).await? else {
+            continue;
+        };
+        match message_compaction::compact_session(db, &session, quota).await {
+            Ok(count) => compacted += count,
+            Err(e) => error!(
+                "Failed to compact messages of session {}: {}",
+                session.id, e
+            ),
+        }
+    }
+    Ok(compacted)
+}
@@ -0,0 +1,250 @@
+//! Writes the messages of a CLI run to the database in batches.
+//!
+//! The stdout reader runs on a blocking thread and hands each parsed line to a bounded channel
+//! instead of inserting it itself, so a slow database no longer stalls reading the CLI's output
+//! line by line. An async task drains the channel and inserts what it holds every
+//! `MESSAGE_BATCH_INTERVAL_MS` or once `MESSAGE_BATCH_SIZE` messages are waiting, whichever
+//! comes first, which also bounds how much output a crash can lose. When the buffer is full the
+//! reader waits for room rather than dropping output, which in turn pauses the CLI once its
+//! pipe fills; such waits are counted in `message_writer_overflows_total`. Each written batch is
+//! also appended to the conversation read model, see `services::conversation_view`.
+//!
+//! Messages are read back ordered by `created_at`, and the column default is the transaction's
+//! start time, so every message is given its own increasing time instead of the default.
+
+use chrono::SubsecRound;
+use sea_orm::prelude::DateTimeWithTimeZone;
+use sea_orm::{DatabaseConnection, NotSet, Set};
+use serde_json::Value;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::task::JoinHandle;
+use tracing::{error, warn};
+
+use super::prompt_progress;
+use super::prompt_tools::{self, ToolSummary};
+use crate::entities::message;
+use crate::services::{conversation_view, message_blobs, message_stream, session_activity};
+
+/// What the writer did over a run
+#[derive(Debug, Clone, Default)]
+pub struct WriteStats {
+    /// Messages inserted
+    pub messages: u64,
+    /// Messages that could not be inserted
+    pub errors: u64,
+    /// Time spent in inserts
+    pub db_write_time: Duration,
+}
+
+/// The reader's end of the channel
+pub struct MessageSender {
+    tx: mpsc::Sender<Value>,
+}
+
+impl MessageSender {
+    /// Queue a message, waiting for room when the buffer is full. Returns false once the
+    /// writer is gone. Must be called from a blocking thread.
+    pub fn send(&self, message: Value) -> bool {
+        let metrics = crate::metrics::get();
+        let sent = match self.tx.try_send(message) {
+            Ok(()) => true,
+            Err(TrySendError::Full(message)) => {
+                metrics.message_writer_overflows_total.inc();
+                self.tx.blocking_send(message).is_ok()
+            }
+            Err(TrySendError::Closed(_)) => false,
+        };
+        if sent {
+            metrics.message_writer_buffer_depth.inc();
+        }
+        sent
+    }
+}
+
+/// Start the writer for `prompt_id`'s run in `session_id`. Drop the sender once the output ends, then await
+/// the handle for the totals; everything queued is written first.
+pub fn spawn(
+    db: DatabaseConnection,
+    session_id: uuid::Uuid,
+    prompt_id: uuid::Uuid,
+) -> (MessageSender, JoinHandle<WriteStats>) {
+    let settings = &crate::config::get().message_writer;
+    let (tx, rx) = mpsc::channel(settings.buffer_capacity.max(1));
+    let task = tokio::spawn(run(
+        db,
+        session_id,
+        prompt_id,
+        rx,
+        settings.batch_size.max(1),
+        settings.batch_interval,
+    ));
+    (MessageSender { tx }, task)
+}
+
+async fn run(
+    db: DatabaseConnection,
+    session_id: uuid::Uuid,
+    prompt_id: uuid::Uuid,
+    mut rx: mpsc::Receiver<Value>,
+    batch_size: usize,
+    interval: Duration,
+) -> WriteStats {
+    let metrics = crate::metrics::get();
+    let mut stats = WriteStats::default();
+    let mut last_progress = prompt_progress::during_cli(0);
+    let mut tool_summary = ToolSummary::default();
+    let mut last_created_at = None;
+
+    while let Some(batch) = next_batch(&mut rx, batch_size, interval).await {
+        metrics.message_writer_buffer_depth.sub(batch.len() as i64);
+
+        let mut tools_changed = false;
+        for json in &batch {
+            tools_changed |= tool_summary.record(json);
+        }
+        if tools_changed {
+            prompt_tools::store(&db, prompt_id, &tool_summary).await;
+        }
+
+        let started = Instant::now();
+        let created_at = creation_times(batch.len(), last_created_at);
+        last_created_at = created_at.last().copied();
+        let (inserted, failed) = insert(&db, session_id, prompt_id, batch, created_at).await;
+        let elapsed = started.elapsed();
+        metrics
+            .message_insert_duration_seconds
+            .observe(elapsed.as_secs_f64());
+        stats.db_write_time += elapsed;
+        stats.messages += inserted.len() as u64;
+        stats.errors += failed;
+        if !inserted.is_empty() {
+            session_activity::record(&db, session_id, prompt_id).await;
+            conversation_view::append(&db, session_id, prompt_id, &inserted).await;
+        }
+
+        let progress = prompt_progress::during_cli(stats.messages);
+        if progress != last_progress {
+            last_progress = progress;
+            prompt_progress::store(&db, prompt_id, progress).await;
+        }
+    }
+    stats
+}
+
+/// Wait for a message, then gather more until `batch_size` are held or `interval` has passed
+/// since the first. None once the channel is closed and drained.
+pub(crate) async fn next_batch<T>(
+    rx: &mut mpsc::Receiver<T>,
+    batch_size: usize,
+    interval: Duration,
+) -> Option<Vec<T>> {
+    let first = rx.recv().await?;
+    let mut batch = vec![first];
+    let deadline = tokio::time::Instant::now() + interval;
+    while batch.len() < batch_size {
+        match tokio::time::timeout_at(deadline, rx.recv()).await {
+            Ok(Some(item)) => batch.push(item),
+            Ok(None) | Err(_) => break,
+        }
+    }
+    Some(batch)
+}
+
+/// Creation times for `count` messages stored together, a microsecond apart, starting now or
+/// just after `last` when the clock has not moved past it
+pub fn creation_times(
+    count: usize,
+    last: Option<DateTimeWithTimeZone>,
+) -> Vec<DateTimeWithTimeZone> {
+    // Postgres keeps microseconds, so compare at that precision
+    let now = chrono::Utc::now().fixed_offset().trunc_subsecs(6);
+    let start = match last {
+        Some(last) => now.max(last + chrono::Duration::microseconds(1)),
+        None => now,
+    };
+    (0..count)
+        .map(|i| start + chrono::Duration::microseconds(i as i64))
+        .collect()
+}
+
+/// Insert a batch in one statement, falling back to one insert per message when that fails
+/// so a single bad row does not lose the rest. Returns the ids inserted, in order, and the
+/// failed count.
+async fn insert(
+    db: &DatabaseConnection,
+    session_id: uuid::Uuid,
+    prompt_id: uuid::Uuid,
+    batch: Vec<Value>,
+    created_at: Vec<DateTimeWithTimeZone>,
+) -> (Vec<uuid::Uuid>, u64) {
+    let mut models = Vec::with_capacity(batch.len());
+    for (json, created_at) in batch.into_iter().zip(created_at) {
+        let message_id = uuid::Uuid::new_v4();
+        let (data, blob_key) = message_blobs::prepare(message_id, json).await;
+        models.push(message::ActiveModel {
+            id: Set(message_id),
+            prompt_id: Set(prompt_id),
+            data: Set(data),
+            blob_key: Set(blob_key),
+            created_at: Set(created_at),
+            updated_at: NotSet,
+            seq: NotSet,
+        });
+    }
+
+    let count = models.len() as u64;
+    let ids: Vec<uuid::Uuid> = models.iter().filter_map(|m| m.id.clone().take()).collect();
+    match message_stream::insert(db, session_id, models.clone()).await {
+        Ok(_) => return (ids, 0),
+        Err(e) => warn!(
+            "Failed to insert {} messages for prompt {} at once, inserting them one by one: {}",
+            count, prompt_id, e
+        ),
+    }
+
+    let mut inserted = Vec::with_capacity(models.len());
+    for (id, model) in ids.into_iter().zip(models) {
+        match message_stream::insert(db, session_id, vec![model]).await {
+            Ok(()) => inserted.push(id),
+            Err(e) => error!("Failed to create message for prompt {}: {}", prompt_id, e),
+        }
+    }
+    let failed = count - inserted.len() as u64;
+    (inserted, failed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_batches_close_at_size_or_interval() {
+        let (tx, mut rx) = mpsc::channel(16);
+        for i in 0..5 {
+            tx.send(i).await.unwrap();
+        }
+        let interval = Duration::from_millis(20);
+        assert_eq!(next_batch(&mut rx, 3, interval).await, Some(vec![0, 1, 2]));
+        // Fewer than a full batch are flushed once the interval passes
+        assert_eq!(next_batch(&mut rx, 3, interval).await, Some(vec![3, 4]));
+
+        tx.send(5).await.unwrap();
+        drop(tx);
+        assert_eq!(next_batch(&mut rx, 3, interval).await, Some(vec![5]));
+        assert_eq!(next_batch(&mut rx, 3, interval).await, None);
+    }
+
+    #[test]
+    fn test_creation_times_strictly_increase() {
+        let first = creation_times(3, None);
+        assert_eq!(first.len(), 3);
+        assert!(first.windows(2).all(|w| w[0] < w[1]));
+
+        // A batch stamped ahead of the clock is still followed, not overlapped
+        let ahead = first[2] + chrono::Duration::seconds(5);
+        let next = creation_times(2, Some(ahead));
+        assert_eq!(next[0], ahead + chrono::Duration::microseconds(1));
+        assert!(next[0] < next[1]);
+    }
+}
@@ -1,22 +1,44 @@
 pub mod cancellation_enforcer;
+pub mod data_retention;
+pub mod dlq_monitor;
+pub mod integrity_checker;
 pub mod ip_return_poller;
+pub mod job_metrics;
+pub mod message_compaction;
+pub mod message_writer;
+pub mod outbox_events;
+pub mod outbox_payload;
 pub mod outbox_publisher;
+pub mod pipeline_error;
+pub mod prompt_history;
 pub mod prompt_poller;
+pub mod prompt_progress;
+pub mod prompt_run;
+pub mod prompt_timings;
+pub mod prompt_tools;
+pub mod queue_monitor;
+pub mod sandbox_prewarm;
+pub mod session_affinity;
+pub mod title_regeneration;
+pub mod worker_instance;
+pub mod worker_registry;
 
 use anyhow::Result;
-use apalis::layers::prometheus::PrometheusLayer;
 use apalis::prelude::*;
 use apalis_sql::postgres::{PgListen, PgPool, PostgresStorage};
 use std::time::Duration;
 use tracing::info;
 
+use job_metrics::JobMetricsLayer;
+
 /// Available background task names
 pub const OUTBOX_PUBLISHER: &str = "outbox-publisher";
 pub const IP_RETURN_POLLER: &str = "ip-return-poller";
+pub const OUTBOX_EVENT_DISPATCHER: &str = "outbox-event-dispatcher";
 
 /// Get all available task names
 pub fn all_tasks() -> Vec<&'static str> {
-    vec![OUTBOX_PUBLISHER, IP_RETURN_POLLER]
+    vec![OUTBOX_PUBLISHER, IP_RETURN_POLLER, OUTBOX_EVENT_DISPATCHER]
 }
 
 /// Context for running background tasks, holds optional connections to backends
@@ -118,11 +140,14 @@ impl TaskContext {
                 // Create OutboxContext with database connection
                 let database_url = std::env::var("DATABASE_URL")
                     .map_err(|_| anyhow::anyhow!("DATABASE_URL must be set"))?;
-                let db = crate::db::establish_connection(&database_url).await?;
-                let ctx = outbox_publisher::OutboxContext { db };
+                let db = crate::db::establish_connection(&database_url, OUTBOX_PUBLISHER).await?;
+                let ctx = outbox_publisher::OutboxContext {
+                    db,
+                    storage: storage.clone(),
+                };
 
                 let worker = WorkerBuilder::new(OUTBOX_PUBLISHER)
-                    .layer(PrometheusLayer)
+                    .layer(JobMetricsLayer)
                     .data(ctx)
                     .with_storage(storage)
                     .build_fn(outbox_publisher::process_outbox_job);
@@ -130,6 +155,42 @@ impl TaskContext {
                 info!("Registering worker: {}", OUTBOX_PUBLISHER);
                 Ok(monitor.register(worker))
             }
+            OUTBOX_EVENT_DISPATCHER => {
+                let pool = self
+                    .db
+                    .as_ref()
+                    .ok_or_else(|| {
+                        anyhow::anyhow!("PostgreSQL connection required for {}", task_name)
+                    })?
+                    .clone();
+
+                PostgresStorage::setup(&pool).await?;
+                let storage = PostgresStorage::new(pool.clone());
+
+                let mut listener = PgListen::new(pool.clone()).await?;
+                listener.subscribe::<outbox_events::OutboxEventJob>();
+
+                tokio::spawn(async move {
+                    if let Err(e) = listener.listen().await {
+                        tracing::error!("PgListen error for outbox events: {}", e);
+                    }
+                });
+
+                let database_url = std::env::var("DATABASE_URL")
+                    .map_err(|_| anyhow::anyhow!("DATABASE_URL must be set"))?;
+                let db =
+                    crate::db::establish_connection(&database_url, OUTBOX_EVENT_DISPATCHER).await?;
+                let ctx = outbox_events::OutboxEventContext { db };
+
+                let worker = WorkerBuilder::new(OUTBOX_EVENT_DISPATCHER)
+                    .layer(JobMetricsLayer)
+                    .data(ctx)
+                    .with_storage(storage)
+                    .build_fn(outbox_events::process_outbox_event_job);
+
+                info!("Registering worker: {}", OUTBOX_EVENT_DISPATCHER);
+                Ok(monitor.register(worker))
+            }
             IP_RETURN_POLLER => {
                 // IP return poller is spawned directly in main.rs like prompt_poller
                 info!("IP return poller is spawned separately in main.rs");
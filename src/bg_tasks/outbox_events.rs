@@ -0,0 +1,133 @@
+use apalis::prelude::*;
+use apalis_sql::postgres::{PgPool, PostgresStorage};
+use sea_orm::{DatabaseConnection, EntityTrait};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tracing::{error, info};
+
+use super::worker_registry;
+use crate::entities::outbox_event::{Entity as OutboxEvent, OutboxEventStatus};
+use crate::services::operational_events;
+use crate::services::outbox_events;
+
+/// Name of the relay loop in the worker registry
+const WORKER: &str = "outbox_event_relay";
+
+/// How often due events are claimed
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Most events claimed per iteration
+const CLAIM_BATCH: u64 = 100;
+
+/// Job that dispatches one outbox event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutboxEventJob {
+    pub event_id: String,
+}
+
+impl Job for OutboxEventJob {
+    const NAME: &'static str = "OutboxEventJob";
+}
+
+/// Context for the outbox event dispatcher containing database connection
+#[derive(Clone)]
+pub struct OutboxEventContext {
+    pub db: DatabaseConnection,
+}
+
+/// Periodic task that claims due outbox events and pushes them to the dispatcher queue
+pub async fn run_outbox_event_relay(db: DatabaseConnection, pool: PgPool) -> anyhow::Result<()> {
+    info!(
+        "Starting outbox event relay - checking every {} seconds",
+        POLL_INTERVAL.as_secs()
+    );
+
+    let mut storage = PostgresStorage::new(pool);
+    worker_registry::register(WORKER, POLL_INTERVAL);
+
+    loop {
+        match relay_due_events(&db, &mut storage).await {
+            Ok(count) => {
+                worker_registry::record_success(WORKER, count);
+                if count > 0 {
+                    info!("Relayed {} outbox events", count);
+                }
+            }
+            Err(e) => {
+                worker_registry::record_error(WORKER, &e);
+                operational_events::record_iteration_error(&db, WORKER, &e).await;
+                error!("Error relaying outbox events: {}", e);
+            }
+        }
+
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+/// Claim due events and enqueue a job for each. An event whose job could not be pushed stays
+/// dispatching and is claimed again once its lease expires.
+async fn relay_due_events(
+    db: &DatabaseConnection,
+    storage: &mut PostgresStorage<OutboxEventJob>,
+) -> anyhow::Result<u64> {
+    let event_ids = outbox_events::claim_due(db, CLAIM_BATCH).await?;
+    let count = event_ids.len() as u64;
+
+    for event_id in event_ids {
+        storage
+            .push(OutboxEventJob {
+                event_id: event_id.to_string(),
+            })
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to push outbox event job: {}", e))?;
+    }
+
+    Ok(count)
+}
+
+/// Dispatch a claimed outbox event and record the outcome on the event row.
+///
+/// Retries are scheduled on the row rather than through apalis, so the job itself only fails
+/// when the row cannot be read or written.
+pub async fn process_outbox_event_job(
+    job: OutboxEventJob,
+    ctx: Data<OutboxEventContext>,
+) -> Result<(), Error> {
+    let event_id = uuid::Uuid::parse_str(&job.event_id).map_err(|e| {
+        error!("Invalid outbox event ID format: {}", e);
+        Error::Failed(Box::new(e))
+    })?;
+
+    let Some(event) = OutboxEvent::find_by_id(event_id)
+        .one(&ctx.db)
+        .await
+        .map_err(|e| Error::Failed(Box::new(e)))?
+    else {
+        info!("Outbox event {} no longer exists, skipping", event_id);
+        return Ok(());
+    };
+
+    if event.status != OutboxEventStatus::Dispatching {
+        info!(
+            "Outbox event {} is {:?}, skipping duplicate delivery",
+            event_id, event.status
+        );
+        return Ok(());
+    }
+
+    let result = match outbox_events::dispatch(&event).await {
+        Ok(()) => {
+            info!("Dispatched outbox event {}", event_id);
+            outbox_events::mark_dispatched(&ctx.db, event).await
+        }
+        Err(e) => outbox_events::mark_failed(&ctx.db, event, &e).await,
+    };
+
+    result.map_err(|e| {
+        error!(
+            "Failed to record outcome of outbox event {}: {}",
+            event_id, e
+        );
+        Error::Failed(Box::new(e))
+    })
+}
@@ -0,0 +1,174 @@
+//! Typed payload of an `OutboxJob`.
+//!
+//! The payload records what the job was enqueued for: the prompt's session and its
+//! `updated_at` at the time as a snapshot version, the prompt's priority, and who enqueued
+//! it and when. Its correlation id stays the same across deferrals and retries, so the log
+//! lines of every delivery of one job can be found together. Workers still read the session
+//! and prompt themselves; the payload is checked against them before a run.
+//!
+//! Jobs enqueued before the payload was typed carry `{}`, `null` or only
+//! `{"affinity_deferrals": n}`. Every field defaults, so they decode as version 0 payloads and
+//! keep their deferral count. Fields added later must default the same way.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Deserializer, Serialize};
+use uuid::Uuid;
+
+use crate::entities::prompt::{self, PromptPriority};
+use crate::entities::session;
+
+/// Version of the payloads this worker enqueues; 0 is a job enqueued before payloads were typed
+pub const PAYLOAD_VERSION: u32 = 1;
+
+/// How a job got on the queue
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DispatchMetadata {
+    /// Component that enqueued the job, e.g. `prompt_poller`
+    pub enqueued_by: Option<String>,
+    pub enqueued_at: Option<DateTime<Utc>>,
+    /// Times the job was put back because another worker held its session
+    pub affinity_deferrals: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OutboxPayload {
+    pub version: u32,
+    /// Session of the prompt when the job was enqueued
+    pub session_id: Option<Uuid>,
+    /// `updated_at` of the session when the job was enqueued
+    pub session_version: Option<DateTime<Utc>>,
+    /// Id shared by every delivery of the job
+    pub correlation_id: Option<Uuid>,
+    pub priority: Option<PromptPriority>,
+    #[serde(flatten)]
+    pub dispatch: DispatchMetadata,
+}
+
+/// Why a job's payload does not fit the prompt it names
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum PayloadError {
+    #[error("Payload version {0} is newer than this worker supports ({PAYLOAD_VERSION})")]
+    UnsupportedVersion(u32),
+    #[error("Job was enqueued for session {enqueued}, but its prompt belongs to session {actual}")]
+    SessionMismatch { enqueued: Uuid, actual: Uuid },
+}
+
+impl OutboxPayload {
+    /// Payload of a job running `prompt` of `session`, enqueued now by `enqueued_by`
+    pub fn new(session: &session::Model, prompt: &prompt::Model, enqueued_by: &str) -> Self {
+        OutboxPayload {
+            version: PAYLOAD_VERSION,
+            session_id: Some(session.id),
+            session_version: Some(session.updated_at.with_timezone(&Utc)),
+            correlation_id: Some(Uuid::new_v4()),
+            priority: Some(prompt.priority),
+            dispatch: DispatchMetadata {
+                enqueued_by: Some(enqueued_by.to_string()),
+                enqueued_at: Some(Utc::now()),
+                affinity_deferrals: 0,
+            },
+        }
+    }
+
+    /// Check a payload before its job does anything
+    pub fn validate_version(&self) -> Result<(), PayloadError> {
+        if self.version > PAYLOAD_VERSION {
+            return Err(PayloadError::UnsupportedVersion(self.version));
+        }
+        Ok(())
+    }
+
+    /// Check the payload against the session the job's prompt belongs to. Version 0 payloads
+    /// name no session and always match.
+    pub fn validate_session(&self, session_id: Uuid) -> Result<(), PayloadError> {
+        match self.session_id {
+            Some(enqueued) if enqueued != session_id => Err(PayloadError::SessionMismatch {
+                enqueued,
+                actual: session_id,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// For log lines: the correlation id, or `legacy` for jobs enqueued without one
+    pub fn correlation(&self) -> String {
+        self.correlation_id
+            .map_or_else(|| "legacy".to_string(), |id| id.to_string())
+    }
+}
+
+/// Deserialize a payload that may be `null`, as some jobs enqueued before it was typed are
+pub fn nullable<'de, D: Deserializer<'de>>(deserializer: D) -> Result<OutboxPayload, D::Error> {
+    Ok(Option::<OutboxPayload>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bg_tasks::outbox_publisher::OutboxJob;
+    use serde_json::json;
+
+    #[test]
+    fn test_jobs_enqueued_before_typed_payloads_decode() {
+        let prompt_id = Uuid::new_v4().to_string();
+        for (payload, deferrals) in [
+            (json!({}), 0),
+            (json!(null), 0),
+            (json!({"affinity_deferrals": 3}), 3),
+        ] {
+            let job: OutboxJob =
+                serde_json::from_value(json!({"prompt_id": prompt_id, "payload": payload}))
+                    .unwrap();
+            assert_eq!(job.payload.version, 0);
+            assert_eq!(job.payload.dispatch.affinity_deferrals, deferrals);
+            assert_eq!(job.payload.correlation(), "legacy");
+            assert!(job.payload.validate_version().is_ok());
+            assert!(job.payload.validate_session(Uuid::new_v4()).is_ok());
+        }
+        let job: OutboxJob = serde_json::from_value(json!({"prompt_id": prompt_id})).unwrap();
+        assert_eq!(job.payload, OutboxPayload::default());
+    }
+
+    #[test]
+    fn test_payload_round_trips_and_validates() {
+        let session_id = Uuid::new_v4();
+        let payload = OutboxPayload {
+            version: PAYLOAD_VERSION,
+            session_id: Some(session_id),
+            session_version: Some(Utc::now()),
+            correlation_id: Some(Uuid::new_v4()),
+            priority: Some(PromptPriority::High),
+            dispatch: DispatchMetadata {
+                enqueued_by: Some("prompt_poller".to_string()),
+                enqueued_at: Some(Utc::now()),
+                affinity_deferrals: 1,
+            },
+        };
+        let value = serde_json::to_value(&payload).unwrap();
+        assert_eq!(value["affinity_deferrals"], 1);
+        assert_eq!(
+            serde_json::from_value::<OutboxPayload>(value).unwrap(),
+            payload
+        );
+
+        assert!(payload.validate_session(session_id).is_ok());
+        let other = Uuid::new_v4();
+        assert_eq!(
+            payload.validate_session(other),
+            Err(PayloadError::SessionMismatch {
+                enqueued: session_id,
+                actual: other
+            })
+        );
+        let newer = OutboxPayload {
+            version: PAYLOAD_VERSION + 1,
+            ..payload
+        };
+        assert_eq!(
+            newer.validate_version(),
+            Err(PayloadError::UnsupportedVersion(PAYLOAD_VERSION + 1))
+        );
+    }
+}
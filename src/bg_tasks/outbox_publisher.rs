@@ -1,26 +1,77 @@
 use apalis::prelude::*;
+use apalis_sql::postgres::PostgresStorage;
+use sea_orm::sea_query::Expr;
 use sea_orm::{
-    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, NotSet, Order, QueryFilter,
-    QueryOrder, Set,
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, PaginatorTrait, QueryFilter,
+    Set,
 };
 use serde::{Deserialize, Serialize};
-use serde_json::json;
-use tracing::{error, info};
+use std::time::Instant;
+use tracing::{error, info, warn};
 
 use sandbox_client::types::FileContentEncoding;
 use sandbox_client::types::FileWriteRequest;
 use sandbox_client::types::ShellExecRequest;
 
-use crate::entities::message;
-use crate::entities::message::Entity as Message;
+use super::message_writer;
+use super::outbox_payload::{self, OutboxPayload};
+use super::pipeline_error::{self, PipelineError};
+use super::prompt_history;
+use super::prompt_run::{self, Claim};
+use super::prompt_timings::PromptTimings;
+use super::sandbox_prewarm::{self, WarmState};
+use super::session_affinity::{self, Dispatch};
+use super::worker_instance;
+use crate::config;
 use crate::entities::prompt::Entity as Prompt;
-use crate::entities::session::{CancellationStatus, Entity as Session, UiStatus};
+use crate::entities::session::{self, CancellationStatus, Entity as Session, UiStatus};
+use crate::services::agent_tokens;
+use crate::services::branch_guard;
+use crate::services::chaos::{self, Fault};
+use crate::services::egress_policy::{self, EgressPolicy};
+use crate::services::github::GithubClient;
+use crate::services::github_host;
+use crate::services::http_client;
+use crate::services::ingestion_filter::{self, IngestionFilter};
+use crate::services::output_log::{self, OutputCopy};
+use crate::services::path_policy::{self, PathPolicy};
+use crate::services::pr_description;
+use crate::services::process_supervisor;
+use crate::services::prompt_artifacts;
+use crate::services::prompt_attachments;
+use crate::services::prompt_changes;
+use crate::services::sandbox_capabilities::{self, Capability};
+use crate::services::sandbox_gh_auth;
+use crate::services::secret_scan;
+use crate::services::session_artifacts;
+use crate::services::session_budget::{self, RunMeter, SessionBudget, Spent};
+use crate::services::session_state_machine::{Actor, SessionStateMachine, TransitionCause};
+use crate::services::shell;
+use crate::services::soft_cancel;
+use crate::services::user_settings;
+use crate::services::workspace_disk;
 
 /// Job that reads from PostgreSQL outbox and publishes to Redis
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OutboxJob {
     pub prompt_id: String,
-    pub payload: serde_json::Value,
+    /// See `OutboxPayload`; jobs enqueued before it was typed decode with defaults
+    #[serde(default, deserialize_with = "outbox_payload::nullable")]
+    pub payload: OutboxPayload,
+}
+
+impl OutboxJob {
+    /// Job running `prompt` of `session`, enqueued by `enqueued_by`
+    pub fn new(
+        session: &crate::entities::session::Model,
+        prompt: &crate::entities::prompt::Model,
+        enqueued_by: &str,
+    ) -> Self {
+        OutboxJob {
+            prompt_id: prompt.id.to_string(),
+            payload: OutboxPayload::new(session, prompt, enqueued_by),
+        }
+    }
 }
 
 impl Job for OutboxJob {
@@ -31,82 +82,86 @@ impl Job for OutboxJob {
 #[derive(Clone)]
 pub struct OutboxContext {
     pub db: DatabaseConnection,
+    /// The queue the job came from, for putting back jobs of sessions another worker holds
+    pub storage: PostgresStorage<OutboxJob>,
 }
 
-/// Fetch all previous prompts in the session and format them using toon-format
-async fn get_formatted_session_history(
-    db: &DatabaseConnection,
-    session_id: uuid::Uuid,
-    current_prompt_id: uuid::Uuid,
-) -> Result<String, Error> {
-    // Fetch all prompts for this session, excluding the current prompt, ordered by creation time
-    let prompts = Prompt::find()
-        .filter(crate::entities::prompt::Column::SessionId.eq(session_id))
-        .filter(crate::entities::prompt::Column::Id.ne(current_prompt_id))
-        .order_by(crate::entities::prompt::Column::CreatedAt, Order::Asc)
-        .all(db)
-        .await
-        .map_err(|e| {
-            error!("Failed to fetch prompts for session {}: {}", session_id, e);
-            Error::Failed(Box::new(e))
-        })?;
-
-    if prompts.is_empty() {
-        info!("No previous prompts found for session {}", session_id);
-        return Ok(String::new());
-    }
-
-    info!(
-        "Found {} previous prompts for session {}",
-        prompts.len(),
-        session_id
-    );
-
-    // Build the session history structure
-    let mut session_data = Vec::new();
-
-    for prompt in prompts {
-        // Fetch messages for this prompt
-        let messages = Message::find()
-            .filter(crate::entities::message::Column::PromptId.eq(prompt.id))
-            .order_by(crate::entities::message::Column::CreatedAt, Order::Asc)
-            .all(db)
-            .await
-            .map_err(|e| {
-                error!("Failed to fetch messages for prompt {}: {}", prompt.id, e);
-                Error::Failed(Box::new(e))
-            })?;
-
-        let mut messages_data = Vec::new();
-        for message in messages {
-            messages_data.push(message.data);
-        }
-
-        session_data.push(json!({
-            "prompt_id": prompt.id.to_string(),
-            "prompt_data": prompt.data,
-            "messages": messages_data,
-        }));
+/// Move a session out of InProgress once its run is over.
+///
+/// A session whose other prompt already finished the run has left InProgress; it keeps its
+/// status and only the remaining fields are updated.
+fn leave_in_progress(
+    session_model: crate::entities::session::Model,
+    cause: TransitionCause,
+) -> Result<crate::entities::session::ActiveModel, PipelineError> {
+    if session_model.ui_status != UiStatus::InProgress {
+        info!(
+            "Session {} already left InProgress ({:?}), keeping its status",
+            session_model.id, session_model.ui_status
+        );
+        return Ok(session_model.into());
     }
 
-    // Create the final JSON structure
-    let history_json = json!({
-        "session_id": session_id.to_string(),
-        "previous_prompts": session_data,
-    });
+    SessionStateMachine::transition(
+        session_model,
+        UiStatus::NeedsReview,
+        cause,
+        &Actor::System("outbox_publisher"),
+    )
+    .map_err(|e| PipelineError::DbWriteFailed(e.to_string()))
+}
 
-    // Use toon-format to encode the history
-    let formatted_history = toon_format::encode_default(&history_json).map_err(|e| {
-        error!("Failed to format session history with toon-format: {}", e);
-        Error::Failed(format!("Toon format error: {}", e).into())
+/// End a run that must not proceed: move the session to review with `message` as its status
+/// message. The job succeeds, since retrying would fail the same way.
+async fn stop_with_error(
+    ctx: &OutboxContext,
+    session_id: uuid::Uuid,
+    message: String,
+) -> Result<(), PipelineError> {
+    let session_model = Session::find_by_id(session_id)
+        .one(&ctx.db)
+        .await?
+        .ok_or_else(|| PipelineError::DbWriteFailed("Session not found".to_string()))?;
+
+    let from = session_model.ui_status.clone();
+    let mut active_session = leave_in_progress(session_model, TransitionCause::RunCompleted)?;
+    active_session.status_message = Set(Some(message));
+    let updated = active_session.update(&ctx.db).await.map_err(|e| {
+        error!("Failed to record error for session {}: {}", session_id, e);
+        PipelineError::from(e)
     })?;
-
-    Ok(formatted_history)
+    SessionStateMachine::after_save(
+        &ctx.db,
+        &from,
+        &updated,
+        TransitionCause::RunCompleted,
+        &Actor::System("outbox_publisher"),
+    )
+    .await;
+    Ok(())
 }
 
-/// Process an outbox job: read prompt by ID, get related session, set up sandbox, and run Claude Code
+/// Process an outbox job: claim the prompt, then run it unless another delivery of the same
+/// job already has.
+///
+/// Apalis may deliver a job more than once (e.g. after a worker crash), so the prompt's run
+/// claim decides whether this delivery does any work. A failed run releases its claim so
+/// retries can run the prompt again.
 pub async fn process_outbox_job(job: OutboxJob, ctx: Data<OutboxContext>) -> Result<(), Error> {
-    info!("Processing outbox job for prompt_id: {}", job.prompt_id);
+    info!(
+        "Processing outbox job for prompt_id: {} (correlation {}, enqueued by {})",
+        job.prompt_id,
+        job.payload.correlation(),
+        job.payload
+            .dispatch
+            .enqueued_by
+            .as_deref()
+            .unwrap_or("unknown"),
+    );
+    job.payload.validate_version().map_err(|e| {
+        error!("Rejecting outbox job for prompt {}: {}", job.prompt_id, e);
+        Error::Failed(Box::new(e))
+    })?;
 
     // Parse prompt ID from job
     let prompt_id = uuid::Uuid::parse_str(&job.prompt_id).map_err(|e| {
@@ -114,17 +169,77 @@ pub async fn process_outbox_job(job: OutboxJob, ctx: Data<OutboxContext>) -> Res
         Error::Failed(Box::new(e))
     })?;
 
+    let session_id = match session_affinity::acquire(&ctx.db, &ctx.storage, prompt_id, &job)
+        .await
+        .map_err(|e| {
+            error!("Failed to claim the session of prompt {}: {}", prompt_id, e);
+            Error::Failed(Box::new(e))
+        })? {
+        Dispatch::Run(session_id) => session_id,
+        Dispatch::Deferred => return Ok(()),
+    };
+    if let Some(session_id) = session_id {
+        job.payload.validate_session(session_id).map_err(|e| {
+            error!("Rejecting outbox job for prompt {}: {}", prompt_id, e);
+            Error::Failed(Box::new(e))
+        })?;
+    }
+
+    let run_id = match prompt_run::claim(&ctx.db, prompt_id).await.map_err(|e| {
+        error!("Failed to claim prompt {}: {}", prompt_id, e);
+        Error::Failed(Box::new(e))
+    })? {
+        Claim::Claimed(run_id) => run_id,
+        Claim::AlreadyCompleted => {
+            info!(
+                "Prompt {} already completed, skipping duplicate delivery",
+                prompt_id
+            );
+            return Ok(());
+        }
+        Claim::InProgress(run_id) => {
+            info!(
+                "Prompt {} is already running as run {}, skipping duplicate delivery",
+                prompt_id, run_id
+            );
+            return Ok(());
+        }
+        Claim::Draft => {
+            warn!(
+                "Prompt {} is a draft, skipping it until it is submitted",
+                prompt_id
+            );
+            return Ok(());
+        }
+    };
+
+    let result = run_prompt(prompt_id, &ctx).await;
+    match &result {
+        Ok(()) => prompt_run::complete(&ctx.db, prompt_id, run_id).await,
+        Err(e) => {
+            prompt_run::release(&ctx.db, prompt_id, run_id).await;
+            pipeline_error::record(&ctx.db, prompt_id, e).await;
+        }
+    }
+    if let Some(session_id) = session_id {
+        session_affinity::touch(&ctx.db, session_id).await;
+    }
+    result.map_err(Error::from)
+}
+
+/// Read prompt by ID, get related session, set up sandbox, and run Claude Code
+async fn run_prompt(prompt_id: uuid::Uuid, ctx: &OutboxContext) -> Result<(), PipelineError> {
     // Query the specific prompt
     let prompt_model = Prompt::find_by_id(prompt_id)
         .one(&ctx.db)
         .await
         .map_err(|e| {
             error!("Failed to query prompt {}: {}", prompt_id, e);
-            Error::Failed(Box::new(e))
+            PipelineError::from(e)
         })?
         .ok_or_else(|| {
             error!("Prompt {} not found", prompt_id);
-            Error::Failed("Prompt not found".into())
+            PipelineError::DbWriteFailed("Prompt not found".to_string())
         })?;
 
     // Query the related session
@@ -134,11 +249,11 @@ pub async fn process_outbox_job(job: OutboxJob, ctx: Data<OutboxContext>) -> Res
         .await
         .map_err(|e| {
             error!("Failed to query session {}: {}", session_id, e);
-            Error::Failed(Box::new(e))
+            PipelineError::from(e)
         })?
         .ok_or_else(|| {
             error!("Session {} not found", session_id);
-            Error::Failed("Session not found".into())
+            PipelineError::DbWriteFailed("Session not found".to_string())
         })?;
 
     // Check if session has cancellation requested
@@ -149,56 +264,64 @@ pub async fn process_outbox_job(job: OutboxJob, ctx: Data<OutboxContext>) -> Res
         );
 
         // Update session to mark as cancelled
-        let mut active_session: crate::entities::session::ActiveModel = _session_model.into();
+        let from = _session_model.ui_status.clone();
+        let mut active_session = leave_in_progress(_session_model, TransitionCause::Cancelled)?;
         active_session.cancellation_status = Set(Some(CancellationStatus::Cancelled));
-        active_session.ui_status = Set(UiStatus::NeedsReview);
 
-        active_session.update(&ctx.db).await.map_err(|e| {
+        let updated = active_session.update(&ctx.db).await.map_err(|e| {
             error!(
                 "Failed to update session {} to cancelled status: {}",
                 session_id, e
             );
-            Error::Failed(Box::new(e))
+            PipelineError::from(e)
         })?;
+        SessionStateMachine::after_save(
+            &ctx.db,
+            &from,
+            &updated,
+            TransitionCause::Cancelled,
+            &Actor::System("outbox_publisher"),
+        )
+        .await;
 
         info!("Session {} marked as cancelled", session_id);
+        pipeline_error::record(&ctx.db, prompt_id, &PipelineError::Cancelled).await;
         return Ok(());
     }
 
-    info!("Processing prompt {} for session {}", prompt_id, session_id);
-
-    // Extract prompt content from the data field
-    let prompt_content = match &prompt_model.data {
-        serde_json::Value::String(s) => s.clone(),
-        serde_json::Value::Object(obj) => {
-            // Try to extract from common field names: "content", "prompt", "text", "message"
-            obj.get("content")
-                .or_else(|| obj.get("prompt"))
-                .or_else(|| obj.get("text"))
-                .or_else(|| obj.get("message"))
-                .and_then(|v| v.as_str())
-                .map(|s| s.to_string())
-                .unwrap_or_else(|| {
-                    // If no common field found, serialize the entire object as a string
-                    serde_json::to_string(&prompt_model.data).unwrap_or_default()
-                })
-        }
-        _ => serde_json::to_string(&prompt_model.data).unwrap_or_default(),
+    // A session that used up its budget gets no more runs
+    let budget = SessionBudget::effective(
+        _session_model.budget.as_ref(),
+        &config::get().session_budget,
+    );
+    let spent_before = if budget.is_empty() {
+        Spent::default()
+    } else {
+        session_budget::spent(&ctx.db, session_id, prompt_id).await?
     };
+    if let Some(limit) = budget.exceeded(&spent_before) {
+        warn!("Refusing to run session {}: {}", session_id, limit);
+        stop_with_error(ctx, session_id, limit.to_string()).await?;
+        pipeline_error::record(&ctx.db, prompt_id, &PipelineError::BudgetExceeded(limit)).await;
+        return Ok(());
+    }
 
-    // Fetch and format session history using toon-format
-    let formatted_history: String =
-        get_formatted_session_history(&ctx.db, session_id, prompt_id).await?;
+    info!("Processing prompt {} for session {}", prompt_id, session_id);
 
-    // Prepend the formatted history to the current prompt if there is history
-    let prompt_content = if !formatted_history.is_empty() {
-        format!(
-            "# Previous Session History\n\n{}\n\n# Current Prompt\n\n{}",
-            formatted_history, prompt_content
-        )
-    } else {
-        prompt_content
-    };
+    let job_started = Instant::now();
+    let mut timings = PromptTimings::new(prompt_id);
+
+    // Render the prompt text, with the session history unless the prompt opted out
+    let phase_started = Instant::now();
+    let prompt_content = prompt_history::render(&ctx.db, &prompt_model)
+        .await
+        .map_err(|e| {
+            error!("Failed to render prompt {}: {}", prompt_id, e);
+            PipelineError::DbWriteFailed(e.to_string())
+        })?;
+    timings
+        .record(&ctx.db, "history", phase_started.elapsed())
+        .await;
 
     // Read borrowed IP from session's sbx_config (already allocated by prompt_poller)
     let borrowed_ip_json = _session_model.sbx_config.as_ref().ok_or_else(|| {
@@ -206,31 +329,47 @@ pub async fn process_outbox_job(job: OutboxJob, ctx: Data<OutboxContext>) -> Res
             "Session {} has no sbx_config - IP should have been borrowed during enqueue",
             session_id
         );
-        Error::Failed("Session missing sbx_config".into())
+        PipelineError::SandboxFailed("Session missing sbx_config".to_string())
     })?;
 
     // Parse the sbx_config JSON to extract mcp_json_string and api_url
     // Note: The data is nested under "item" key from prompt_poller
-    let item = borrowed_ip_json["item"]
-        .as_object()
-        .ok_or_else(|| Error::Failed("Missing item object in sbx_config".into()))?;
+    let item = borrowed_ip_json["item"].as_object().ok_or_else(|| {
+        PipelineError::SandboxFailed("Missing item object in sbx_config".to_string())
+    })?;
 
     let mcp_json_string = item["mcp_json_string"]
         .as_str()
-        .ok_or_else(|| Error::Failed("Missing mcp_json_string in sbx_config.item".into()))?
+        .ok_or_else(|| {
+            PipelineError::SandboxFailed("Missing mcp_json_string in sbx_config.item".to_string())
+        })?
         .to_string();
 
-    let api_url = item["api_url"]
-        .as_str()
-        .ok_or_else(|| Error::Failed("Missing api_url in sbx_config.item".into()))?;
+    let api_url = item["api_url"].as_str().ok_or_else(|| {
+        PipelineError::SandboxFailed("Missing api_url in sbx_config.item".to_string())
+    })?;
 
     // Create sandbox client using the api_url
-    let sbx = sandbox_client::Client::new(api_url);
+    let sbx = sandbox_client::Client::new_with_client(api_url, http_client::client());
+
+    if chaos::should_fail(Fault::SandboxTimeout) {
+        error!("Injected sandbox timeout for session {}", session_id);
+        return Err(PipelineError::Timeout(
+            "Injected sandbox timeout".to_string(),
+        ));
+    }
+
+    // Optional features of the run depend on what the sandbox image supports
+    let capabilities = sandbox_capabilities::for_run(&ctx.db, &sbx, &_session_model, api_url).await;
+    let supports = |capability| sandbox_capabilities::supports(capabilities.as_ref(), capability);
+    // Features left out for lack of a capability, reported in the status message
+    let mut unsupported: Vec<String> = Vec::new();
 
     let uuid = uuid::Uuid::new_v4();
     let prompt_file_path = format!("/home/gem/prompt_{}.md", uuid);
     let prompt_file_path_for_cli = prompt_file_path.clone();
     // upload formatted history to a file in the sandbox
+    let phase_started = Instant::now();
     sbx.write_file(&FileWriteRequest {
         content: prompt_content.to_string(),
         file: prompt_file_path.clone(),
@@ -243,55 +382,75 @@ pub async fn process_outbox_job(job: OutboxJob, ctx: Data<OutboxContext>) -> Res
     .await
     .map_err(|e| {
         error!("Failed to upload formatted history to sandbox: {}", e);
-        Error::Failed(Box::new(e))
+        PipelineError::sandbox(PipelineError::SandboxFailed, e)
     })?;
-
-    // Read GitHub token from environment variable
-    let github_token = std::env::var("GITHUB_TOKEN").map_err(|e| {
-        error!("Failed to read GITHUB_TOKEN from environment: {}", e);
-        Error::Failed(Box::new(std::io::Error::new(
-            std::io::ErrorKind::NotFound,
-            "GITHUB_TOKEN environment variable not set",
-        )))
+    // The rendered prompt points at the paths its attachments are copied to
+    prompt_attachments::ship(&ctx.db, &sbx, &prompt_model.data)
+        .await
+        .map_err(|e| {
+            error!("Failed to copy attachments of prompt {}: {}", prompt_id, e);
+            PipelineError::SandboxFailed(e.to_string())
+        })?;
+    timings
+        .record(&ctx.db, "upload_prompt", phase_started.elapsed())
+        .await;
+
+    // Resolve which GitHub host the repo lives on (github.com or a GitHub Enterprise host)
+    let repo_location = github_host::resolve_repo(
+        _session_model
+            .repo
+            .as_deref()
+            .ok_or_else(|| PipelineError::CloneFailed("Session missing repo".to_string()))?,
+    )
+    .map_err(|e| {
+        error!("Failed to resolve repo for session {}: {}", session_id, e);
+        PipelineError::CloneFailed(e.to_string())
     })?;
+    let hostname = repo_location.host.hostname.clone();
 
-    // Authenticate with GitHub using the fetched token
+    // Resolve the GitHub token for this host and session owner
+    let github_token = github_host::resolve_token(&repo_location.host, &_session_model.user_id)
+        .await
+        .map_err(|e| {
+            error!(
+                "Failed to resolve GitHub token for host {}: {}",
+                hostname, e
+            );
+            PipelineError::AuthFailed(e.to_string())
+        })?;
+
+    // A pre-warmed sandbox may already be logged in to the host or hold a clone of the repo
+    let warm = WarmState::from_sbx_config(borrowed_ip_json);
+
+    // Log gh in through a token file, configuring git to use it as the credential helper
+    let phase_started = Instant::now();
+    if !warm.is_authenticated(&hostname) {
+        sandbox_gh_auth::login(&sbx, &hostname, &github_token)
+            .await
+            .map_err(|e| {
+                error!("Failed to authenticate with GitHub: {}", e);
+                PipelineError::AuthFailed(e)
+            })?;
+    }
+    timings
+        .record(&ctx.db, "gh_auth", phase_started.elapsed())
+        .await;
 
-    // Pass the token to gh auth login via stdin
-    let auth_command = format!("echo '{}' | gh auth login --with-token", github_token);
-    sbx.exec_command_v1_shell_exec_post(&ShellExecRequest {
-        command: auth_command,
-        async_mode: false,
-        id: None,
-        timeout: Some(30.0_f64),
-        exec_dir: Some(String::from("/home/gem")),
-    })
-    .await
-    .map_err(|e| {
-        error!("Failed to authenticate with GitHub: {}", e);
-        Error::Failed(Box::new(e))
-    })?;
-    // Pass the token to gh auth login via stdin
-    sbx.exec_command_v1_shell_exec_post(&ShellExecRequest {
-        command: "gh auth setup-git".to_string(),
-        async_mode: false,
-        id: None,
-        timeout: Some(30.0_f64),
-        exec_dir: Some(String::from("/home/gem")),
-    })
-    .await
-    .map_err(|e| {
-        error!("Failed to authenticate with GitHub: {}", e);
-        Error::Failed(Box::new(e))
-    })?;
     // clone the repo using session_id as directory name
+    let phase_started = Instant::now();
     let repo_dir = format!("repo_{}", session_id);
+    let clone_command = match _session_model
+        .repo
+        .as_deref()
+        .and_then(|repo| warm.clone_of(repo))
+    {
+        Some(warm_dir) => {
+            sandbox_prewarm::adopt_clone_script(warm_dir, &repo_dir, &repo_location.clone_url())
+        }
+        None => format!("git clone {} {}", repo_location.clone_url(), repo_dir),
+    };
     sbx.exec_command_v1_shell_exec_post(&ShellExecRequest {
-        command: format!(
-            "git clone https://github.com/{}.git {}",
-            _session_model.repo.clone().unwrap(),
-            repo_dir
-        ),
+        command: clone_command,
         async_mode: false,
         id: None,
         timeout: Some(30.0_f64),
@@ -300,15 +459,20 @@ pub async fn process_outbox_job(job: OutboxJob, ctx: Data<OutboxContext>) -> Res
     .await
     .map_err(|e| {
         error!("Failed to execute command: {}", e);
-        Error::Failed(Box::new(e))
+        PipelineError::sandbox(PipelineError::CloneFailed, e)
     })?;
 
+    timings
+        .record(&ctx.db, "clone", phase_started.elapsed())
+        .await;
+
     // checkout the target branch
+    let phase_started = Instant::now();
     let repo_path = format!("/home/gem/{}", repo_dir);
     sbx.exec_command_v1_shell_exec_post(&ShellExecRequest {
         command: format!(
             "git checkout {}",
-            _session_model.target_branch.clone().unwrap()
+            shell::quote(&_session_model.target_branch.clone().unwrap())
         ),
         async_mode: false,
         id: None,
@@ -318,7 +482,7 @@ pub async fn process_outbox_job(job: OutboxJob, ctx: Data<OutboxContext>) -> Res
     .await
     .map_err(|e| {
         error!("Failed to execute command: {}", e);
-        Error::Failed(Box::new(e))
+        PipelineError::sandbox(PipelineError::CheckoutFailed, e)
     })?;
 
     let branch = _session_model
@@ -327,7 +491,10 @@ pub async fn process_outbox_job(job: OutboxJob, ctx: Data<OutboxContext>) -> Res
         .unwrap_or_else(|| format!("claude/{}", _session_model.id));
     // if branch exists, checkout the branch, else switch -c the branch
     sbx.exec_command_v1_shell_exec_post(&ShellExecRequest {
-        command: format!("git checkout {} || git switch -c {}", branch, branch),
+        command: format!(
+            "git checkout {branch} || git switch -c {branch}",
+            branch = shell::quote(&branch)
+        ),
         async_mode: false,
         id: None,
         timeout: Some(30.0_f64),
@@ -336,9 +503,180 @@ pub async fn process_outbox_job(job: OutboxJob, ctx: Data<OutboxContext>) -> Res
     .await
     .map_err(|e| {
         error!("Failed to execute command: {}", e);
-        Error::Failed(Box::new(e))
+        PipelineError::sandbox(PipelineError::CheckoutFailed, e)
+    })?;
+
+    // Re-runs start from the latest pushed state of the branch so manual fixes are included.
+    // A branch that was never pushed has nothing to fetch and is left as checked out.
+    if prompt_model.rerun_of.is_some() {
+        sbx.exec_command_v1_shell_exec_post(&ShellExecRequest {
+            command: format!(
+                "git fetch origin {} && git reset --hard {}",
+                shell::quote(&branch),
+                shell::quote(&format!("origin/{}", branch))
+            ),
+            async_mode: false,
+            id: None,
+            timeout: Some(60.0_f64),
+            exec_dir: Some(repo_path.clone()),
+        })
+        .await
+        .map_err(|e| {
+            error!("Failed to re-sync branch {}: {}", branch, e);
+            PipelineError::sandbox(PipelineError::CheckoutFailed, e)
+        })?;
+    }
+
+    timings
+        .record(&ctx.db, "checkout", phase_started.elapsed())
+        .await;
+
+    // A clone too big for the sandbox would break the run later in ways that do not say why
+    if let Some(limit) = workspace_disk::check(
+        &ctx.db,
+        &sbx,
+        session_id,
+        &repo_path,
+        &config::get().workspace_disk,
+    )
+    .await
+    {
+        warn!("Refusing to run session {}: {}", session_id, limit);
+        stop_with_error(ctx, session_id, limit.to_string()).await?;
+        pipeline_error::record(&ctx.db, prompt_id, &PipelineError::DiskLimitExceeded(limit)).await;
+        return Ok(());
+    }
+
+    // Keep pushes off the target branch and protected branches
+    let target_branch = _session_model.target_branch.clone().unwrap_or_default();
+    let first_run = Prompt::find()
+        .filter(crate::entities::prompt::Column::SessionId.eq(session_id))
+        .filter(crate::entities::prompt::Column::CompletedAt.is_not_null())
+        .count(&ctx.db)
+        .await?
+        == 0;
+    let github = GithubClient::new(&repo_location.host, github_token.clone());
+    if let Err(e) = branch_guard::check(
+        &github,
+        &repo_location.repo,
+        &branch,
+        &target_branch,
+        _session_model.branch.is_none(),
+        first_run,
+    )
+    .await
+    {
+        error!("Refusing to run session {}: {}", session_id, e);
+        stop_with_error(ctx, session_id, e.to_string()).await?;
+        pipeline_error::record(&ctx.db, prompt_id, &PipelineError::BranchGuard(e)).await;
+        return Ok(());
+    }
+    let policy = PathPolicy::from_json(_session_model.path_policy.as_ref());
+    sbx.write_file(&FileWriteRequest {
+        content: branch_guard::pre_push_hook(
+            &branch,
+            &target_branch,
+            policy.as_ref(),
+            config::get().secret_scan,
+        ),
+        file: format!("{}/.git/hooks/pre-push", repo_path),
+        append: false,
+        sudo: false,
+        encoding: FileContentEncoding::Utf8,
+        leading_newline: false,
+        trailing_newline: false,
+    })
+    .await
+    .map_err(|e| {
+        error!("Failed to install pre-push hook: {}", e);
+        PipelineError::sandbox(PipelineError::SandboxFailed, e)
+    })?;
+    sbx.exec_command_v1_shell_exec_post(&ShellExecRequest {
+        command: "chmod +x .git/hooks/pre-push".to_string(),
+        async_mode: false,
+        id: None,
+        timeout: Some(10.0_f64),
+        exec_dir: Some(repo_path.clone()),
+    })
+    .await
+    .map_err(|e| {
+        error!("Failed to install pre-push hook: {}", e);
+        PipelineError::sandbox(PipelineError::SandboxFailed, e)
     })?;
 
+    // Let the agent in the sandbox report status and messages for this run
+    if let Some(callback_url) = &config::get().agent_callback_url {
+        let token = agent_tokens::issue(&ctx.db, session_id).await?;
+        sbx.write_file(&FileWriteRequest {
+            content: agent_tokens::env_file(callback_url, session_id, prompt_id, &token),
+            file: agent_tokens::ENV_FILE.to_string(),
+            append: false,
+            sudo: false,
+            encoding: FileContentEncoding::Utf8,
+            leading_newline: false,
+            trailing_newline: false,
+        })
+        .await
+        .map_err(|e| {
+            error!("Failed to write agent env file: {}", e);
+            PipelineError::sandbox(PipelineError::SandboxFailed, e)
+        })?;
+    }
+
+    // Restrict the sandbox's network before Claude gets to run anything in it. A sandbox
+    // can come from a session with a policy, so sessions without one clear its rules.
+    let egress = EgressPolicy::from_json(_session_model.egress_policy.as_ref());
+    let effective_egress = if supports(Capability::Firewall) {
+        egress_policy::apply(&sbx, egress.as_ref(), &repo_location.host)
+            .await
+            .map_err(|e| {
+                error!(
+                    "Failed to apply egress policy for session {}: {}",
+                    session_id, e
+                );
+                PipelineError::SandboxFailed(e)
+            })?
+    } else if egress.is_some() {
+        // Running without the restrictions the session asked for is not an option
+        let message =
+            sandbox_capabilities::unsupported_message("egress policies", Capability::Firewall);
+        error!("Cannot run session {}: {}", session_id, message);
+        stop_with_error(ctx, session_id, message.clone()).await?;
+        pipeline_error::record(
+            &ctx.db,
+            prompt_id,
+            &PipelineError::CapabilityUnsupported(message),
+        )
+        .await;
+        return Ok(());
+    } else {
+        None
+    };
+    Session::update_many()
+        .col_expr(
+            session::Column::EffectiveEgressPolicy,
+            Expr::value(
+                effective_egress
+                    .as_ref()
+                    .and_then(|p| serde_json::to_value(p).ok()),
+            ),
+        )
+        .filter(session::Column::Id.eq(session_id))
+        .exec(&ctx.db)
+        .await?;
+
+    // Commits after this one that reach the remote branch are attributed to the prompt, and
+    // the diff from it is the prompt's change summary
+    let base_sha = if supports(Capability::Git) {
+        prompt_artifacts::head_sha(&sbx, &repo_path).await
+    } else {
+        unsupported.push(sandbox_capabilities::unsupported_message(
+            "linking commits and summarizing changes",
+            Capability::Git,
+        ));
+        None
+    };
+
     // Run Claude Code CLI directly in the job (not fire-and-forget)
     let session_id = _session_model.id;
     info!("Running Claude Code CLI for session {}", session_id);
@@ -362,7 +700,7 @@ pub async fn process_outbox_job(job: OutboxJob, ctx: Data<OutboxContext>) -> Res
             "Failed to create base temp directory {}: {}",
             temp_base_dir, e
         );
-        return Err(Error::Failed(Box::new(e)));
+        return Err(PipelineError::CliSpawnFailed(e.to_string()));
     }
 
     let temp_dir = match tempfile::Builder::new()
@@ -375,7 +713,7 @@ pub async fn process_outbox_job(job: OutboxJob, ctx: Data<OutboxContext>) -> Res
                 "Failed to create temp directory for session {} in {}: {}",
                 session_id, temp_base_dir, e
             );
-            return Err(Error::Failed(Box::new(e)));
+            return Err(PipelineError::CliSpawnFailed(e.to_string()));
         }
     };
 
@@ -386,15 +724,11 @@ pub async fn process_outbox_job(job: OutboxJob, ctx: Data<OutboxContext>) -> Res
             "Failed to write MCP config for session {}: {}",
             session_id, e
         );
-        return Err(Error::Failed(Box::new(e)));
+        return Err(PipelineError::CliSpawnFailed(e.to_string()));
     }
 
-    // Load system prompt template from embedded markdown file
-    const SYSTEM_PROMPT_TEMPLATE: &str =
-        include_str!("../../prompts/outbox_handler_system_prompt.md");
-
     // Construct system prompt with context about the task by replacing placeholders
-    let system_prompt = SYSTEM_PROMPT_TEMPLATE
+    let mut system_prompt = prompt_run::SYSTEM_PROMPT_TEMPLATE
         .replace("{REPO_PATH}", &repo_path)
         .replace(
             "{REPO}",
@@ -411,50 +745,121 @@ pub async fn process_outbox_job(job: OutboxJob, ctx: Data<OutboxContext>) -> Res
                 .clone()
                 .unwrap_or_else(|| "main".to_string()),
         );
+    if let Some(policy) = &policy {
+        system_prompt.push_str(&policy.prompt_section());
+    }
+    if let (Some(egress), Some(effective)) = (&egress, &effective_egress) {
+        system_prompt.push_str(&egress.prompt_section(effective));
+    }
+    if supports(Capability::FileApi) {
+        system_prompt.push_str(&soft_cancel::prompt_section(session_id));
+    } else {
+        unsupported.push(sandbox_capabilities::unsupported_message(
+            "soft cancellation, collecting artifacts or copying the CLI output",
+            Capability::FileApi,
+        ));
+    }
+
+    let model = config::get().pricing.model_or_default(_session_model.model);
+    let settings = user_settings::find(&ctx.db, &_session_model.user_id)
+        .await
+        .unwrap_or_else(|e| {
+            warn!(
+                "Failed to load settings of user {}, using defaults: {}",
+                _session_model.user_id, e
+            );
+            None
+        });
+    let (allowed_tools, disallowed_tools) =
+        user_settings::tool_names(&user_settings::allowed_tools(settings.as_ref()));
+
+    let mut cli_args: Vec<String> = vec![
+        "--dangerously-skip-permissions".to_string(),
+        "--model".to_string(),
+        model.id(),
+        "--print".to_string(),
+        "--output-format=stream-json".to_string(),
+        "--session-id".to_string(),
+        session_id.to_string(),
+        "--allowedTools".to_string(),
+    ];
+    cli_args.extend(allowed_tools);
+    cli_args.push("--disallowedTools".to_string());
+    cli_args.extend(disallowed_tools);
+    cli_args.extend([
+        "--append-system-prompt".to_string(),
+        system_prompt.clone(),
+        "-p".to_string(),
+        format!("`cat {}`", prompt_file_path_for_cli),
+        "--verbose".to_string(),
+        "--strict-mcp-config".to_string(),
+        "--mcp-config".to_string(),
+        mcp_config_path.to_string_lossy().into_owned(),
+    ]);
+    prompt_run::record(
+        &ctx.db,
+        prompt_id,
+        &prompt_content,
+        &system_prompt,
+        &cli_args,
+        model,
+    )
+    .await;
 
     // Create clones for spawn_blocking
     let prompt_id_clone = prompt_id;
     let db_clone = ctx.db.clone();
     let session_id_clone = session_id;
     let db_for_pid = ctx.db.clone();
+    let db_for_disk = ctx.db.clone();
+    let api_url_for_disk = api_url.to_string();
+    let repo_path_for_disk = repo_path.clone();
+    let fake_cli_run = chaos::fake_cli_run();
+    let mut ingestion = IngestionFilter::new(ingestion_filter::effective(
+        _session_model.ingestion_filter.as_ref(),
+        &config::get().ingestion_filter,
+    ));
+    let db_for_filter = ctx.db.clone();
+    let (message_sender, message_writer) =
+        message_writer::spawn(ctx.db.clone(), session_id, prompt_id);
+    let run_meter = std::sync::Arc::new(RunMeter::default());
+    // Copy the output into the sandbox too, to recover what the database misses
+    let output_log_path = output_log::log_path(prompt_id);
+    let mut output_copy = if config::get().output_log && supports(Capability::FileApi) {
+        output_log::record_path(&ctx.db, prompt_id, &output_log_path).await;
+        Some(OutputCopy::spawn(api_url, output_log_path.clone()))
+    } else {
+        None
+    };
+
+    // Wait for a CLI slot; the wait counts towards the cli phase
+    let phase_started = Instant::now();
+    let supervisor = process_supervisor::get();
+    let cli_slot = supervisor.acquire().await;
 
     // Spawn the Claude CLI process with piped stdout/stderr for streaming
     let cli_result = tokio::task::spawn_blocking(move || {
+        let _cli_slot = cli_slot;
         use std::io::{BufRead, BufReader};
-        use std::process::{Command, Stdio};
-
-        let child = Command::new("claude")
-            .args([
-                "--dangerously-skip-permissions",
-                "--print",
-                "--output-format=stream-json",
-                "--session-id",
-                &session_id_clone.to_string(),
-                "--allowedTools",
-                "WebSearch",
-                "mcp__*",
-                "ListMcpResourcesTool",
-                "ReadMcpResourceTool",
-                "--disallowedTools",
-                "Bash",
-                "Edit",
-                "Write",
-                "NotebookEdit",
-                "Read",
-                "Glob",
-                "Grep",
-                "KillShell",
-                "BashOutput",
-                "TodoWrite",
-                "--append-system-prompt",
-                &system_prompt,
-                "-p",
-                &format!("`cat {}`", prompt_file_path_for_cli),
-                "--verbose",
-                "--strict-mcp-config",
-                "--mcp-config",
-                mcp_config_path.to_str().unwrap(),
-            ])
+        use std::process::Stdio;
+
+        let mut command = match fake_cli_run {
+            Some(duration) => {
+                warn!(
+                    "Running fake CLI for {:?} instead of Claude for session {}",
+                    duration, session_id_clone
+                );
+                let mut command = supervisor.command("sleep");
+                command.arg(duration.as_secs().to_string());
+                command
+            }
+            None => {
+                let mut command = supervisor.command("claude");
+                command.args(&cli_args);
+                command
+            }
+        };
+        let child = command
             .current_dir(temp_dir.path())
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
@@ -471,10 +876,38 @@ pub async fn process_outbox_job(job: OutboxJob, ctx: Data<OutboxContext>) -> Res
         // Store the process PID in the database
         let pid = child.id();
         info!("Claude CLI process spawned with PID {} for session {}", pid, session_id_clone);
+        let cgroup = supervisor.confine(pid);
 
         // Update session with PID using tokio runtime handle
         let handle = tokio::runtime::Handle::current();
+
+        // Stop the CLI once the session's budget is used up
+        let budget_enforcer = (!budget.is_empty()).then(|| {
+            handle.spawn(session_budget::enforce(
+                budget,
+                spent_before,
+                run_meter.clone(),
+                Instant::now(),
+                pid,
+                &config::get().session_budget,
+            ))
+        });
+        // Likewise once the clone outgrows the sandbox's disk
+        let disk_enforcer = handle.spawn(workspace_disk::enforce(
+            db_for_disk,
+            api_url_for_disk,
+            session_id_clone,
+            repo_path_for_disk,
+            pid,
+            &config::get().workspace_disk,
+            config::get().session_budget.kill_grace,
+        ));
         let update_result = handle.block_on(async {
+            // The instance must be known before it owns a PID, or another instance could take
+            // the session over before this one's enforcer first reports
+            if let Err(e) = worker_instance::heartbeat(&db_for_pid).await {
+                warn!("Failed to record worker instance before storing PID {}: {}", pid, e);
+            }
             let session = Session::find_by_id(session_id_clone)
                 .one(&db_for_pid)
                 .await
@@ -489,6 +922,8 @@ pub async fn process_outbox_job(job: OutboxJob, ctx: Data<OutboxContext>) -> Res
 
             let mut active_session: crate::entities::session::ActiveModel = session.into();
             active_session.process_pid = Set(Some(pid as i32));
+            active_session.worker_instance_id =
+                Set(Some(worker_instance::instance_id().to_string()));
 
             active_session.update(&db_for_pid).await.map_err(|e| {
                 error!("Failed to update session {} with PID: {}", session_id_clone, e);
@@ -509,13 +944,13 @@ pub async fn process_outbox_job(job: OutboxJob, ctx: Data<OutboxContext>) -> Res
 
         // Spawn a thread to handle stderr
         let session_id_for_stderr = session_id_clone;
-        std::thread::spawn(move || {
+        let stderr_thread = std::thread::spawn(move || {
             let stderr_reader = BufReader::new(stderr);
-            let mut stderr_lines = Vec::new();
+            let mut stderr_tail = prompt_run::StderrTail::default();
             for line in stderr_reader.lines() {
                 match line {
                     Ok(line) => {
-                        stderr_lines.push(line);
+                        stderr_tail.push(line);
                     }
                     Err(e) => {
                         error!("Error reading stderr for session {}: {}", session_id_for_stderr, e);
@@ -523,21 +958,34 @@ pub async fn process_outbox_job(job: OutboxJob, ctx: Data<OutboxContext>) -> Res
                     }
                 }
             }
-            if !stderr_lines.is_empty() {
+            if stderr_tail.total_lines() > 0 {
+                let (first, last) = stderr_tail.first_and_last();
                 error!("Claude Code stderr for session {} ({} lines total). First/last lines: [{} ... {}]",
                     session_id_for_stderr,
-                    stderr_lines.len(),
-                    stderr_lines.first().unwrap_or(&String::new()),
-                    stderr_lines.last().unwrap_or(&String::new())
+                    stderr_tail.total_lines(),
+                    first,
+                    last
                 );
             }
+            stderr_tail
         });
 
-        // Read stdout line by line and send to channel
+        // Read stdout line by line and hand each message the ingestion filter keeps to the
+        // writer and the output copy
         let stdout_reader = BufReader::new(stdout);
         let mut line_count = 0;
-        let mut message_count = 0;
-        let mut error_count = 0;
+        let mut parse_errors = 0;
+        let mut push_rejection = None;
+        let mut queued = 0u64;
+        let mut queue = |message: serde_json::Value| {
+            if let Some(copy) = output_copy.as_mut() {
+                copy.send(message.to_string());
+            }
+            queued += 1;
+            if !message_sender.send(message) {
+                error!("Message writer for session {} stopped, dropping output", session_id_clone);
+            }
+        };
 
         for line in stdout_reader.lines() {
             match line {
@@ -549,37 +997,19 @@ pub async fn process_outbox_job(job: OutboxJob, ctx: Data<OutboxContext>) -> Res
                         continue;
                     }
 
-                    // Parse JSON and insert into database
+                    if push_rejection.is_none() {
+                        push_rejection = branch_guard::push_rejection(&line);
+                    }
+
                     match serde_json::from_str::<serde_json::Value>(&line) {
                         Ok(json) => {
-                            let message_id = uuid::Uuid::new_v4();
-                            let new_message = message::ActiveModel {
-                                id: Set(message_id),
-                                prompt_id: Set(prompt_id_clone),
-                                data: Set(json),
-                                created_at: NotSet,
-                                updated_at: NotSet,
-                            };
-
-                            // Use tokio runtime handle to insert from blocking context
-                            let handle = tokio::runtime::Handle::current();
-                            let db_clone2 = db_clone.clone();
-                            match handle.block_on(async move {
-                                new_message.insert(&db_clone2).await
-                            }) {
-                                Ok(_) => {
-                                    message_count += 1;
-                                }
-                                Err(e) => {
-                                    error_count += 1;
-                                    error!("Failed to create message for session {}: {}", session_id_clone, e);
-                                }
-                            }
+                            run_meter.record(&json);
+                            ingestion.apply(json).into_iter().for_each(&mut queue);
                         }
                         Err(e) => {
-                            error_count += 1;
+                            parse_errors += 1;
                             // Only log first few parse errors to avoid spam
-                            if error_count <= 3 {
+                            if parse_errors <= 3 {
                                 error!("Failed to parse JSON at line {} for session {}: {}", line_count, session_id_clone, e);
                             }
                         }
@@ -592,52 +1022,265 @@ pub async fn process_outbox_job(job: OutboxJob, ctx: Data<OutboxContext>) -> Res
             }
         }
 
-        info!("Processed {} lines of output for session {} ({} messages created, {} errors)", line_count, session_id_clone, message_count, error_count);
+        ingestion.finish().into_iter().for_each(&mut queue);
+        if ingestion.filtered() > 0 {
+            info!("Ingestion filter kept {} messages of session {} out of the message table", ingestion.filtered(), session_id_clone);
+            handle.block_on(ingestion_filter::record(&db_for_filter, session_id_clone, ingestion.filtered()));
+        }
+
+        // Let the writer flush what is still buffered
+        drop(message_sender);
+        let write_stats = handle.block_on(message_writer).unwrap_or_else(|e| {
+            error!("Message writer for session {} failed: {}", session_id_clone, e);
+            message_writer::WriteStats::default()
+        });
+        let message_count = write_stats.messages;
+        let db_write_time = write_stats.db_write_time;
+        // Output the database missed can be backfilled from the copy
+        let output_gap = match output_copy.map(|copy| handle.block_on(copy.finish())) {
+            Some(copy_stats) if queued > message_count => {
+                warn!("Stored {} of {} messages for session {}, the output log holds {} lines ({} missed)", message_count, queued, session_id_clone, copy_stats.lines, copy_stats.missed);
+                true
+            }
+            _ => false,
+        };
+
+        info!("Processed {} lines of output for session {} ({} messages created, {} errors)", line_count, session_id_clone, message_count, parse_errors + write_stats.errors);
 
-        // Wait for process to complete and get exit status
+        // Wait for process to complete and get exit status. VmHWM is only readable while the
+        // process exists; the cgroup's peak also covers its children.
+        let peak_rss = process_supervisor::peak_rss_bytes(pid);
         let status = child.wait()?;
+        let budget_exceeded = budget_enforcer.and_then(|enforcer| {
+            if enforcer.is_finished() {
+                handle.block_on(enforcer).ok()
+            } else {
+                enforcer.abort();
+                None
+            }
+        });
+        let disk_exceeded = if disk_enforcer.is_finished() {
+            handle.block_on(disk_enforcer).ok()
+        } else {
+            disk_enforcer.abort();
+            None
+        };
+        if let Some(peak) = cgroup.as_ref().and_then(|c| c.peak_memory_bytes()).or(peak_rss) {
+            process_supervisor::observe_peak_memory(peak);
+        }
         info!("Claude Code CLI exit status for session {}: {:?}", session_id_clone, status);
 
-        Ok(status)
+        // Stderr closes with the process, so the reader finishes right after it
+        let stderr_tail = stderr_thread.join().unwrap_or_default();
+        let stderr = if !status.success() || message_count == 0 {
+            stderr_tail.into_text()
+        } else {
+            None
+        };
+        handle.block_on(prompt_run::record_exit(
+            &db_clone,
+            prompt_id_clone,
+            status.code(),
+            stderr,
+        ));
+
+        Ok((status, db_write_time, push_rejection, budget_exceeded, disk_exceeded, output_gap))
     })
     .await
     .map_err(|e| {
         error!("Failed to join spawn_blocking task: {}", e);
-        Error::Failed(Box::new(e))
+        PipelineError::CliSpawnFailed(e.to_string())
     })?;
 
     // Log the CLI result
-    match cli_result {
-        Ok(status) => {
+    let (exit_status, push_rejection, budget_exceeded, disk_exceeded, output_gap) = match cli_result
+    {
+        Ok((status, db_write_time, push_rejection, budget_exceeded, disk_exceeded, output_gap)) => {
             info!("Claude CLI completed with status: {:?}", status);
+            timings
+                .record(&ctx.db, "cli", phase_started.elapsed())
+                .await;
+            timings
+                .record(&ctx.db, "message_db_write", db_write_time)
+                .await;
+            (
+                status,
+                push_rejection,
+                budget_exceeded,
+                disk_exceeded,
+                output_gap,
+            )
         }
         Err(e) => {
             error!("Claude CLI process failed: {}", e);
-            return Err(Error::Failed(Box::new(e)));
+            return Err(PipelineError::CliSpawnFailed(e.to_string()));
+        }
+    };
+
+    // Recover what the database missed while the sandbox still holds the copy
+    if output_gap {
+        if let Err(e) =
+            output_log::backfill(&ctx.db, &sbx, session_id, prompt_id, &output_log_path).await
+        {
+            warn!(
+                "Failed to backfill messages of prompt {} from its output log: {}",
+                prompt_id, e
+            );
         }
     }
 
+    // Save files left outside the repo before the sandbox is returned
+    if supports(Capability::FileApi) {
+        let phase_started = Instant::now();
+        session_artifacts::collect(&ctx.db, &sbx, session_id, prompt_id).await;
+        timings
+            .record(&ctx.db, "artifacts", phase_started.elapsed())
+            .await;
+    }
+
+    // Link the prompt to the commits it pushed and the branch's pull requests
+    if supports(Capability::Git) {
+        prompt_artifacts::record(
+            &ctx.db,
+            &sbx,
+            &github,
+            &repo_location.repo,
+            &repo_path,
+            &branch,
+            session_id,
+            prompt_id,
+            base_sha.as_deref(),
+        )
+        .await;
+        prompt_changes::record(
+            &ctx.db,
+            &sbx,
+            &repo_path,
+            session_id,
+            prompt_id,
+            base_sha.as_deref(),
+        )
+        .await;
+    }
+    pr_description::describe_if_new(&ctx.db, session_id).await;
+
+    // The hook kept denied changes off the remote; a run that made them still fails
+    let policy_violation = match &policy {
+        Some(policy) => path_policy::check(&sbx, policy, &repo_path, &target_branch).await,
+        None => None,
+    };
+    // Likewise the hook refused commits with likely secrets; find where they are
+    let secret_findings = if config::get().secret_scan {
+        secret_scan::scan(&sbx, &repo_path, &target_branch).await
+    } else {
+        Vec::new()
+    };
+    if !secret_findings.is_empty() {
+        secret_scan::store(&ctx.db, prompt_id, &secret_findings).await;
+    }
+
     // Update session ui_status to NeedsReview (poller will handle IP return)
     info!("Updating session {} ui_status to NeedsReview", session_id);
 
     let session_result = Session::find_by_id(session_id).one(&ctx.db).await;
     match session_result {
         Ok(Some(session_model)) => {
-            let mut active_session: crate::entities::session::ActiveModel = session_model.into();
-            active_session.ui_status = Set(UiStatus::NeedsReview);
+            let from = session_model.ui_status.clone();
+            // A soft-cancelled run that wrapped up within its grace period completes the
+            // cancellation itself
+            let cancel_requested =
+                session_model.cancellation_status == Some(CancellationStatus::Requested);
+            let mut active_session =
+                leave_in_progress(session_model, TransitionCause::RunCompleted)?;
             active_session.process_pid = Set(None); // Clear PID now that process is complete
-
-            if let Err(e) = active_session.update(&ctx.db).await {
-                error!(
-                    "Failed to update session {} ui_status to NeedsReview: {}",
-                    session_id, e
+            active_session.worker_instance_id = Set(None);
+            if cancel_requested {
+                active_session.cancellation_status = Set(Some(CancellationStatus::Cancelled));
+            }
+            if let Some(violation) = &policy_violation {
+                warn!(
+                    "Session {} broke its path policy: {}",
+                    session_id, violation
                 );
-                return Err(Error::Failed(Box::new(e)));
-            } else {
+                active_session.status_message = Set(Some(violation.clone()));
+            } else if !secret_findings.is_empty() {
+                warn!(
+                    "Session {} committed {} likely secrets",
+                    session_id,
+                    secret_findings.len()
+                );
+                active_session.status_message =
+                    Set(Some(secret_scan::findings_message(&secret_findings)));
+            } else if let Some(limit) = &budget_exceeded {
+                warn!("Stopped session {}: {}", session_id, limit);
+                active_session.status_message = Set(Some(limit.to_string()));
+            } else if let Some(limit) = &disk_exceeded {
+                warn!("Stopped session {}: {}", session_id, limit);
+                active_session.status_message = Set(Some(limit.to_string()));
+            } else if let Some(rejection) = push_rejection {
+                warn!("Push rejected for session {}: {}", session_id, rejection);
+                active_session.status_message = Set(Some(format!(
+                    "Push was rejected by the server: {}",
+                    rejection
+                )));
+            } else if !unsupported.is_empty() {
                 info!(
-                    "Updated session {} ui_status to NeedsReview - poller will handle IP return",
-                    session_id
+                    "Session {} ran without some features: {}",
+                    session_id,
+                    unsupported.join("; ")
                 );
+                active_session.status_message = Set(Some(unsupported.join("; ")));
+            }
+
+            match active_session.update(&ctx.db).await {
+                Ok(updated) => {
+                    info!(
+                        "Updated session {} ui_status to NeedsReview - poller will handle IP return",
+                        session_id
+                    );
+                    SessionStateMachine::after_save(
+                        &ctx.db,
+                        &from,
+                        &updated,
+                        TransitionCause::RunCompleted,
+                        &Actor::System("outbox_publisher"),
+                    )
+                    .await;
+                    // The run is over either way; a failed CLI is recorded without retrying,
+                    // since its pushes may already have landed. Cancelled runs end killed.
+                    if let Some(violation) = policy_violation {
+                        let error = PipelineError::PathPolicyViolation(violation);
+                        warn!("Run of prompt {} failed: {}", prompt_id, error);
+                        pipeline_error::record(&ctx.db, prompt_id, &error).await;
+                    } else if !secret_findings.is_empty() {
+                        let error = PipelineError::SecretsDetected(secret_findings.len());
+                        warn!("Run of prompt {} failed: {}", prompt_id, error);
+                        pipeline_error::record(&ctx.db, prompt_id, &error).await;
+                    } else if let Some(limit) = budget_exceeded {
+                        let error = PipelineError::BudgetExceeded(limit);
+                        warn!("Run of prompt {} failed: {}", prompt_id, error);
+                        pipeline_error::record(&ctx.db, prompt_id, &error).await;
+                    } else if let Some(limit) = disk_exceeded {
+                        let error = PipelineError::DiskLimitExceeded(limit);
+                        warn!("Run of prompt {} failed: {}", prompt_id, error);
+                        pipeline_error::record(&ctx.db, prompt_id, &error).await;
+                    } else if !exit_status.success() {
+                        let error = if cancel_requested {
+                            PipelineError::Cancelled
+                        } else {
+                            PipelineError::CliNonZeroExit(exit_status.code())
+                        };
+                        warn!("Run of prompt {} failed: {}", prompt_id, error);
+                        pipeline_error::record(&ctx.db, prompt_id, &error).await;
+                    }
+                }
+                Err(e) => {
+                    error!(
+                        "Failed to update session {} ui_status to NeedsReview: {}",
+                        session_id, e
+                    );
+                    return Err(PipelineError::from(e));
+                }
             }
         }
         Ok(None) => {
@@ -645,18 +1288,24 @@ pub async fn process_outbox_job(job: OutboxJob, ctx: Data<OutboxContext>) -> Res
                 "Session {} not found when trying to update status",
                 session_id
             );
-            return Err(Error::Failed("Session not found".into()));
+            return Err(PipelineError::DbWriteFailed(
+                "Session not found".to_string(),
+            ));
         }
         Err(e) => {
             error!(
                 "Failed to query session {} for status update: {}",
                 session_id, e
             );
-            return Err(Error::Failed(Box::new(e)));
+            return Err(PipelineError::from(e));
         }
     }
 
-    info!("Completed outbox job for prompt_id: {}", job.prompt_id);
+    timings
+        .record(&ctx.db, "total", job_started.elapsed())
+        .await;
+
+    info!("Completed outbox job for prompt_id: {}", prompt_id);
 
     Ok(())
 }
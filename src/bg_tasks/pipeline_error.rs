@@ -0,0 +1,150 @@
+//! Failures of the pipeline that runs a prompt in its sandbox, by the stage they happened at.
+//!
+//! An outbox job fails with a `PipelineError`. The kind is stored on the prompt, counted in
+//! `pipeline_failures_total` and explained to the user in the failure notification; the detail
+//! only goes to the logs and the job error, since it can contain sandbox output.
+
+use sea_orm::sea_query::Expr;
+use sea_orm::{ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter};
+use tracing::warn;
+
+use crate::entities::prompt::{self, Entity as Prompt, PipelineErrorKind};
+use crate::services::branch_guard::BranchGuardError;
+use crate::services::notifications;
+use crate::services::session_budget::BudgetLimit;
+use crate::services::workspace_disk::DiskLimit;
+
+#[derive(Debug, thiserror::Error)]
+pub enum PipelineError {
+    #[error("GitHub authentication failed: {0}")]
+    AuthFailed(String),
+    #[error("Clone failed: {0}")]
+    CloneFailed(String),
+    #[error("Checkout failed: {0}")]
+    CheckoutFailed(String),
+    #[error("Sandbox request failed: {0}")]
+    SandboxFailed(String),
+    #[error("Failed to spawn Claude CLI: {0}")]
+    CliSpawnFailed(String),
+    #[error("Claude CLI exited with {}", exit_description(*.0))]
+    CliNonZeroExit(Option<i32>),
+    #[error("Database error: {0}")]
+    DbWriteFailed(String),
+    #[error("Run cancelled")]
+    Cancelled,
+    #[error("Timed out: {0}")]
+    Timeout(String),
+    #[error("Secret scan found {0} likely secrets")]
+    SecretsDetected(usize),
+    #[error("{0}")]
+    PathPolicyViolation(String),
+    #[error("{0}")]
+    BudgetExceeded(BudgetLimit),
+    #[error("{0}")]
+    DiskLimitExceeded(DiskLimit),
+    #[error("{0}")]
+    CapabilityUnsupported(String),
+    #[error("{0}")]
+    BranchGuard(BranchGuardError),
+}
+
+fn exit_description(code: Option<i32>) -> String {
+    match code {
+        Some(code) => format!("code {}", code),
+        None => "a signal".to_string(),
+    }
+}
+
+impl PipelineError {
+    pub fn kind(&self) -> PipelineErrorKind {
+        match self {
+            PipelineError::AuthFailed(_) => PipelineErrorKind::AuthFailed,
+            PipelineError::CloneFailed(_) => PipelineErrorKind::CloneFailed,
+            PipelineError::CheckoutFailed(_) => PipelineErrorKind::CheckoutFailed,
+            PipelineError::SandboxFailed(_) => PipelineErrorKind::SandboxFailed,
+            PipelineError::CliSpawnFailed(_) => PipelineErrorKind::CliSpawnFailed,
+            PipelineError::CliNonZeroExit(_) => PipelineErrorKind::CliNonZeroExit,
+            PipelineError::DbWriteFailed(_) => PipelineErrorKind::DbWriteFailed,
+            PipelineError::Cancelled => PipelineErrorKind::Cancelled,
+            PipelineError::Timeout(_) => PipelineErrorKind::Timeout,
+            PipelineError::SecretsDetected(_) => PipelineErrorKind::SecretsDetected,
+            PipelineError::PathPolicyViolation(_) => PipelineErrorKind::PathPolicyViolation,
+            PipelineError::BudgetExceeded(_) => PipelineErrorKind::BudgetExceeded,
+            PipelineError::DiskLimitExceeded(_) => PipelineErrorKind::DiskLimitExceeded,
+            PipelineError::CapabilityUnsupported(_) => PipelineErrorKind::CapabilityUnsupported,
+            PipelineError::BranchGuard(_) => PipelineErrorKind::BranchRejected,
+        }
+    }
+
+    /// Classify a failed sandbox request made at the stage `stage` builds errors for; requests
+    /// that timed out are `Timeout` whatever the stage
+    pub fn sandbox<E: std::fmt::Debug>(
+        stage: fn(String) -> PipelineError,
+        e: sandbox_client::Error<E>,
+    ) -> Self {
+        match &e {
+            sandbox_client::Error::CommunicationError(inner) if inner.is_timeout() => {
+                PipelineError::Timeout(e.to_string())
+            }
+            _ => stage(e.to_string()),
+        }
+    }
+}
+
+impl From<DbErr> for PipelineError {
+    fn from(e: DbErr) -> Self {
+        PipelineError::DbWriteFailed(e.to_string())
+    }
+}
+
+impl From<PipelineError> for apalis::prelude::Error {
+    fn from(e: PipelineError) -> Self {
+        apalis::prelude::Error::Failed(Box::new(e))
+    }
+}
+
+/// Record that the run of `prompt_id` failed with `error`: store its kind on the prompt, count
+/// it, and tell the user unless they cancelled the run themselves
+pub async fn record(db: &DatabaseConnection, prompt_id: uuid::Uuid, error: &PipelineError) {
+    let kind = error.kind();
+    crate::metrics::get()
+        .pipeline_failures_total
+        .with_label_values(&[kind.as_str()])
+        .inc();
+
+    let stored = Prompt::update_many()
+        .col_expr(prompt::Column::ErrorKind, Expr::value(kind))
+        .filter(prompt::Column::Id.eq(prompt_id))
+        .exec(db)
+        .await;
+    if let Err(e) = stored {
+        warn!("Failed to store error kind of prompt {}: {}", prompt_id, e);
+    }
+
+    if kind != PipelineErrorKind::Cancelled {
+        notifications::prompt_failed(db, prompt_id, kind.user_message()).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kind_and_message() {
+        let error = PipelineError::CliNonZeroExit(Some(2));
+        assert_eq!(error.kind(), PipelineErrorKind::CliNonZeroExit);
+        assert_eq!(error.to_string(), "Claude CLI exited with code 2");
+        assert_eq!(
+            PipelineError::CliNonZeroExit(None).to_string(),
+            "Claude CLI exited with a signal"
+        );
+
+        let error: PipelineError = DbErr::Custom("connection reset".to_string()).into();
+        assert_eq!(error.kind(), PipelineErrorKind::DbWriteFailed);
+
+        let error = PipelineError::PathPolicyViolation("Changed deploy/app.yaml".to_string());
+        assert_eq!(error.kind(), PipelineErrorKind::PathPolicyViolation);
+        assert_eq!(error.to_string(), "Changed deploy/app.yaml");
+    }
+}
@@ -0,0 +1,192 @@
+//! The prompt text a run sends to the CLI: the prompt itself, preceded by the session's
+//! earlier prompts and their messages unless the prompt opted out of history.
+
+use sea_orm::{ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter, QueryOrder};
+use serde_json::{json, Value};
+use tracing::{info, warn};
+
+use crate::entities::message::{self, Entity as Message};
+use crate::entities::prompt::{self, Entity as Prompt, Model as PromptModel};
+use crate::services::conversation;
+use crate::services::message_blobs;
+
+/// Longest JSON excerpt of an unencodable prompt included in the log
+const MAX_LOGGED_JSON_CHARS: usize = 2000;
+
+#[derive(Debug, thiserror::Error)]
+pub enum HistoryError {
+    #[error(transparent)]
+    Database(#[from] DbErr),
+    #[error("Failed to load message {0} for history: {1}")]
+    Message(uuid::Uuid, String),
+}
+
+/// The text of a prompt's `data`: its conversation rendered for the CLI, falling back to the
+/// serialized JSON for data that is not one in any shape
+pub fn prompt_text(data: &Value) -> String {
+    match conversation::from_stored(data) {
+        Some(conversation) => conversation.render(),
+        None => serde_json::to_string(data).unwrap_or_default(),
+    }
+}
+
+/// The full text a run of `prompt` sends to the CLI, loading history as it is now
+pub async fn render(db: &DatabaseConnection, prompt: &PromptModel) -> Result<String, HistoryError> {
+    let history = if prompt.include_history {
+        formatted_session_history(db, prompt).await?
+    } else {
+        String::new()
+    };
+    Ok(with_history(&history, prompt_text(&prompt.data)))
+}
+
+fn with_history(history: &str, prompt_text: String) -> String {
+    if history.is_empty() {
+        return prompt_text;
+    }
+    format!(
+        "# Previous Session History\n\n{}\n\n# Current Prompt\n\n{}",
+        history, prompt_text
+    )
+}
+
+/// The session's prompts created before `current`, or the `history_depth` most recent of
+/// them, with their messages, encoded by `encode`; empty when there are none
+async fn formatted_session_history(
+    db: &DatabaseConnection,
+    current: &PromptModel,
+) -> Result<String, HistoryError> {
+    let session_id = current.session_id;
+    let mut prompts = Prompt::find()
+        .filter(prompt::Column::SessionId.eq(session_id))
+        .filter(prompt::Column::Id.ne(current.id))
+        .filter(prompt::Column::Draft.eq(false))
+        .filter(prompt::Column::CreatedAt.lte(current.created_at))
+        .order_by_asc(prompt::Column::CreatedAt)
+        .all(db)
+        .await?;
+    if let Some(depth) = current.history_depth {
+        let skip = prompts.len().saturating_sub(depth.max(0) as usize);
+        prompts.drain(..skip);
+    }
+
+    if prompts.is_empty() {
+        info!("No previous prompts found for session {}", session_id);
+        return Ok(String::new());
+    }
+
+    info!(
+        "Including {} previous prompts of session {}",
+        prompts.len(),
+        session_id
+    );
+
+    let mut session_data = Vec::new();
+    for prompt in prompts {
+        let messages = Message::find()
+            .filter(message::Column::PromptId.eq(prompt.id))
+            .order_by_asc(message::Column::CreatedAt)
+            .all(db)
+            .await?;
+
+        let mut messages_data = Vec::new();
+        for message in messages {
+            let data = message_blobs::resolve(&message)
+                .await
+                .map_err(|e| HistoryError::Message(message.id, e))?;
+            messages_data.push(data);
+        }
+
+        session_data.push(json!({
+            "prompt_id": prompt.id.to_string(),
+            "prompt_data": prompt.data,
+            "messages": messages_data,
+        }));
+    }
+
+    let history_json = json!({
+        "session_id": session_id.to_string(),
+        "previous_prompts": session_data,
+    });
+    Ok(encode(session_id, &history_json))
+}
+
+/// Encode session history for the CLI with toon-format.
+///
+/// If toon-format rejects the history, it is sent as a fenced JSON block instead, so one odd
+/// message shape does not fail the run. The previous prompts that cannot be encoded on their
+/// own are logged with a JSON excerpt, and `history_encoding_fallbacks_total` is incremented.
+pub fn encode(session_id: uuid::Uuid, history: &Value) -> String {
+    encode_with(session_id, history, |value| {
+        toon_format::encode_default(value).map_err(|e| e.to_string())
+    })
+}
+
+fn encode_with(
+    session_id: uuid::Uuid,
+    history: &Value,
+    encoder: impl Fn(&Value) -> Result<String, String>,
+) -> String {
+    let error = match encoder(history) {
+        Ok(encoded) => return encoded,
+        Err(e) => e,
+    };
+
+    crate::metrics::get().history_encoding_fallbacks_total.inc();
+    warn!(
+        "Failed to encode history of session {} with toon-format, sending JSON instead: {}",
+        session_id, error
+    );
+    let prompts = history["previous_prompts"].as_array().into_iter().flatten();
+    for prompt in prompts.filter(|prompt| encoder(prompt).is_err()) {
+        let json = prompt.to_string();
+        warn!(
+            "History of prompt {} in session {} cannot be encoded with toon-format: {}",
+            prompt["prompt_id"].as_str().unwrap_or("unknown"),
+            session_id,
+            json.chars().take(MAX_LOGGED_JSON_CHARS).collect::<String>()
+        );
+    }
+
+    format!(
+        "```json\n{}\n```",
+        serde_json::to_string_pretty(history).unwrap_or_else(|_| history.to_string())
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_falls_back_to_json() {
+        let history = json!({
+            "session_id": "s",
+            "previous_prompts": [{"prompt_id": "p1", "messages": ["odd"]}],
+        });
+        let session_id = uuid::Uuid::nil();
+
+        assert_eq!(
+            encode_with(session_id, &history, |_| Ok("toon".to_string())),
+            "toon"
+        );
+
+        let fallback = encode_with(session_id, &history, |_| Err("unsupported".to_string()));
+        assert!(fallback.starts_with("```json\n{"));
+        assert!(fallback.ends_with("}\n```"));
+        assert!(fallback.contains("\"prompt_id\": \"p1\""));
+    }
+
+    #[test]
+    fn test_prompt_text_and_history_heading() {
+        assert_eq!(prompt_text(&json!("fix it")), "fix it");
+        assert_eq!(prompt_text(&json!({"prompt": "fix it"})), "fix it");
+        assert_eq!(prompt_text(&json!({"other": 1})), "{\"other\":1}");
+
+        assert_eq!(with_history("", "fix it".to_string()), "fix it");
+        assert_eq!(
+            with_history("h", "fix it".to_string()),
+            "# Previous Session History\n\nh\n\n# Current Prompt\n\nfix it"
+        );
+    }
+}
@@ -1,12 +1,116 @@
 use apalis::prelude::Storage;
 use apalis_sql::postgres::{PgPool, PostgresStorage};
-use sea_orm::{ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, Set};
+use chrono::Utc;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, Condition, DatabaseConnection, EntityTrait, QueryFilter,
+    QueryOrder, Set,
+};
+use std::collections::HashMap;
 use std::time::Duration;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
 use super::outbox_publisher::OutboxJob;
+use super::sandbox_prewarm;
+use super::worker_registry;
+use crate::backoff::jittered_backoff;
+use crate::entities::operational_event::OperationalEventKind;
 use crate::entities::prompt::{self, Entity as Prompt};
 use crate::entities::session::{self, CancellationStatus, Entity as Session, UiStatus};
+use crate::services::cost_estimate;
+use crate::services::fan_out;
+use crate::services::ip_allocator;
+use crate::services::operational_events;
+use crate::services::poller_control;
+use crate::services::sandbox_queue::{dispatch_order, queued_statuses};
+use crate::services::session_state_machine::{Actor, SessionStateMachine, TransitionCause};
+use crate::services::session_titles;
+
+/// Base delay before retrying a failed IP borrow for a session
+const BORROW_BACKOFF_BASE: Duration = Duration::from_secs(2);
+
+/// Upper bound on the delay between IP borrow attempts for a session
+const BORROW_BACKOFF_MAX: Duration = Duration::from_secs(120);
+
+/// Name of the loop in the worker registry
+const WORKER: &str = "prompt_poller";
+
+/// Actor of the status transitions made here
+const ACTOR: Actor = Actor::System(WORKER);
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long a session held back by its user's budget waits before it is estimated again
+const BUDGET_RECHECK_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Why the session's pending prompts must not be dispatched, when their estimated cost exceeds
+/// what is left of the monthly budget of its user or organization
+async fn over_budget(
+    db: &DatabaseConnection,
+    session_model: &session::Model,
+) -> anyhow::Result<Option<String>> {
+    let Some(remaining) = cost_estimate::remaining_budget_usd(db, session_model).await? else {
+        return Ok(None);
+    };
+    let Some(estimate) = cost_estimate::estimate_session(db, session_model).await? else {
+        return Ok(None);
+    };
+
+    Ok((estimate.cost_usd > remaining).then(|| {
+        format!(
+            "Estimated cost ${:.2} exceeds the remaining monthly budget of ${:.2}",
+            estimate.cost_usd, remaining
+        )
+    }))
+}
+
+/// Keep the progress of running fan-outs in their tracking session's status message and move
+/// each to review once all of its child sessions are done
+async fn update_fan_outs(db: &DatabaseConnection) -> anyhow::Result<()> {
+    let tracking_sessions = Session::find()
+        .filter(session::Column::UiStatus.eq(UiStatus::InProgress))
+        .filter(session::Column::FanOutLimit.is_not_null())
+        .all(db)
+        .await?;
+
+    for tracking in tracking_sessions {
+        let summary = fan_out::summarize(&fan_out::children(db, tracking.id).await?);
+        let message = summary.describe();
+
+        if summary.unfinished > 0 {
+            if tracking.status_message.as_deref() != Some(message.as_str()) {
+                let mut active_session: session::ActiveModel = tracking.into();
+                active_session.status_message = Set(Some(message));
+                active_session.update(db).await?;
+            }
+            continue;
+        }
+
+        info!("Fan-out {} finished: {}", tracking.id, message);
+        let from = tracking.ui_status.clone();
+        let cancel_requested = tracking.cancellation_status == Some(CancellationStatus::Requested);
+        let mut active_session = SessionStateMachine::transition(
+            tracking,
+            UiStatus::NeedsReview,
+            TransitionCause::FanOutFinished,
+            &ACTOR,
+        )?;
+        active_session.status_message = Set(Some(message));
+        if cancel_requested {
+            active_session.cancellation_status = Set(Some(CancellationStatus::Cancelled));
+        }
+        let updated = active_session.update(db).await?;
+        SessionStateMachine::after_save(
+            db,
+            &from,
+            &updated,
+            TransitionCause::FanOutFinished,
+            &ACTOR,
+        )
+        .await;
+    }
+
+    Ok(())
+}
 
 /// Periodic poller that checks for pending prompts every second
 /// and pushes them to the outbox queue for processing
@@ -14,99 +118,241 @@ pub async fn run_prompt_poller(db: DatabaseConnection, pool: PgPool) -> anyhow::
     info!("Starting prompt poller - checking every 1 second");
 
     let mut storage = PostgresStorage::new(pool);
+    worker_registry::register(WORKER, POLL_INTERVAL);
 
     loop {
-        tokio::time::sleep(Duration::from_secs(1)).await;
+        tokio::time::sleep(POLL_INTERVAL).await;
+        if poller_control::paused(&db, WORKER).await {
+            continue;
+        }
 
         match poll_and_enqueue_prompts(&db, &mut storage).await {
             Ok(count) => {
+                worker_registry::record_success(WORKER, count as u64);
                 if count > 0 {
                     info!("Enqueued {} pending prompts for processing", count);
                 }
             }
             Err(e) => {
+                worker_registry::record_error(WORKER, &e);
+                operational_events::record_iteration_error(&db, WORKER, &e).await;
                 error!("Failed to poll and enqueue prompts: {}", e);
             }
         }
     }
 }
 
-/// Query for prompts that belong to sessions with Pending UI status and push them to the outbox queue
+/// Query for prompts that belong to sessions waiting for a sandbox and push them to the outbox queue
 async fn poll_and_enqueue_prompts(
     db: &DatabaseConnection,
     storage: &mut PostgresStorage<OutboxJob>,
 ) -> anyhow::Result<usize> {
-    // Query all sessions with Pending UI status and no cancellation requested
+    update_fan_outs(db).await?;
+
+    // Query all sessions waiting for a sandbox whose borrow backoff has elapsed and that have
+    // no cancellation requested
     let pending_sessions = Session::find()
-        .filter(session::Column::UiStatus.eq(UiStatus::Pending))
+        .filter(session::Column::UiStatus.is_in(queued_statuses()))
         .filter(
             session::Column::CancellationStatus
                 .is_null()
                 .or(session::Column::CancellationStatus.ne(CancellationStatus::Requested)),
         )
+        .filter(
+            Condition::any()
+                .add(session::Column::NextBorrowAttemptAt.is_null())
+                .add(session::Column::NextBorrowAttemptAt.lte(Utc::now())),
+        )
+        .order_by_asc(session::Column::CreatedAt)
         .all(db)
         .await?;
 
-    let mut count = 0;
+    // Start sessions with the most urgent prompts first, oldest first within a priority. Drafts
+    // wait until they are submitted, so sessions with nothing else stay queued.
+    let session_ids: Vec<_> = pending_sessions.iter().map(|s| s.id).collect();
+    let mut prompts_by_session: HashMap<uuid::Uuid, Vec<prompt::Model>> = HashMap::new();
+    for prompt in Prompt::find()
+        .filter(prompt::Column::SessionId.is_in(session_ids))
+        .filter(prompt::Column::Draft.eq(false))
+        .order_by_asc(prompt::Column::CreatedAt)
+        .all(db)
+        .await?
+    {
+        prompts_by_session
+            .entry(prompt.session_id)
+            .or_default()
+            .push(prompt);
+    }
+    let mut queue: Vec<_> = pending_sessions
+        .into_iter()
+        .filter_map(|s| prompts_by_session.remove(&s.id).map(|prompts| (s, prompts)))
+        .collect();
+    queue.sort_by_key(|(_, prompts)| dispatch_order(prompts));
 
-    // Get IP allocator URL from environment
-    let ip_allocator_url =
-        std::env::var("IP_ALLOCATOR_URL").unwrap_or_else(|_| "http://localhost:8000".to_string());
-    let ip_client = ip_allocator_client::Client::new(&ip_allocator_url);
+    let mut count = 0;
+    let mut fan_out_slots = fan_out::Slots::default();
 
     // Process each pending session
-    for session_model in pending_sessions {
-        // Find prompts for this session
-        let prompts = Prompt::find()
-            .filter(prompt::Column::SessionId.eq(session_model.id))
-            .all(db)
-            .await?;
-
-        if prompts.is_empty() {
+    for (session_model, prompts) in queue {
+        let Some(first_prompt) = prompts.first() else {
+            continue;
+        };
+
+        // Children of a fan-out wait while the fan-out already runs as many as it may
+        if let Some(message) = fan_out_slots.hold_reason(db, &session_model).await? {
+            if session_model.status_message.as_deref() != Some(message.as_str()) {
+                let mut active_session: session::ActiveModel = session_model.into();
+                active_session.status_message = Set(Some(message));
+                active_session.update(db).await?;
+            }
+            continue;
+        }
+
+        // Sessions created without a prompt get their real title and branch before they first
+        // run. Naming calls the Anthropic API, so it runs in the background and the session is
+        // dispatched on a later poll, once it is done.
+        if session_model.title_pending {
+            session_titles::spawn_refresh(db, session_model, first_prompt);
             continue;
         }
 
-        // Borrow an IP for this session
-        info!(
-            "Borrowing IP for session {} with {} prompts",
-            session_model.id,
-            prompts.len()
-        );
+        if let Some(message) = over_budget(db, &session_model).await? {
+            warn!("Holding session {}: {}", session_model.id, message);
+            let next_attempt_at = Utc::now()
+                + chrono::Duration::from_std(BUDGET_RECHECK_INTERVAL)
+                    .unwrap_or(chrono::Duration::zero());
+            let mut active_session: session::ActiveModel = session_model.into();
+            active_session.status_message = Set(Some(message));
+            active_session.next_borrow_attempt_at = Set(Some(next_attempt_at.into()));
+            active_session.update(db).await?;
+            continue;
+        }
 
-        let borrowed_ip = ip_client.handlers_ip_borrow(None).await.map_err(|e| {
-            anyhow::anyhow!(
-                "Failed to borrow IP for session {}: {}",
+        // A session that kept its sandbox through review runs its follow-ups in it. Otherwise
+        // take a pre-warmed sandbox when there is one, or borrow an IP for this session.
+        // A return already under way (it has a key) may have released the kept sandbox.
+        // Warm sandboxes come from any region, so sessions preferring one borrow their own.
+        let kept = session_model.sbx_config.is_some() && session_model.ip_return_key.is_none();
+        let held_sbx_config = match &session_model.sbx_config {
+            Some(kept_config) if kept => Some(kept_config.clone()),
+            _ if session_model.region.is_some() => None,
+            _ => sandbox_prewarm::claim(db, session_model.repo.as_deref()).await?,
+        };
+        if held_sbx_config.is_none() {
+            info!(
+                "Borrowing IP for session {} with {} prompts",
                 session_model.id,
-                e
-            )
-        })?;
+                prompts.len()
+            );
+        }
+
+        let mut region_message = None;
+        let sbx_config_data = match held_sbx_config {
+            Some(sbx_config) => sbx_config,
+            None => match ip_allocator::borrow(session_model.region.as_deref()).await {
+                Ok(borrowed) => {
+                    info!(
+                        "Successfully borrowed IP for session {} in region {:?}: {:?}",
+                        session_model.id, borrowed.region, borrowed.output.item
+                    );
+                    if let (true, Some(preferred)) = (borrowed.fell_back, &session_model.region) {
+                        let message =
+                            ip_allocator::fallback_message(preferred, borrowed.region.as_deref());
+                        warn!("Session {}: {}", session_model.id, message);
+                        region_message = Some(message);
+                    }
+                    serde_json::json!({
+                        "item": borrowed.output.item,
+                        "borrow_token": borrowed.output.borrow_token,
+                    })
+                }
+                Err(e) => {
+                    // Allocator is out of capacity or unavailable: leave the session waiting and
+                    // retry it later with backoff instead of failing the whole poll
+                    let attempts = session_model.sandbox_borrow_attempts + 1;
+                    let delay =
+                        jittered_backoff(attempts as u32, BORROW_BACKOFF_BASE, BORROW_BACKOFF_MAX);
+                    warn!(
+                        "Failed to borrow IP for session {} (attempt {}), retrying in {:?}: {}",
+                        session_model.id, attempts, delay, e
+                    );
+                    operational_events::record(
+                        db,
+                        WORKER,
+                        OperationalEventKind::Allocator,
+                        &format!("Failed to borrow IP: {}", e),
+                        Some(serde_json::json!({
+                            "session_id": session_model.id,
+                            "attempt": attempts,
+                        })),
+                    )
+                    .await;
 
-        info!(
-            "Successfully borrowed IP for session {}: {:?}",
-            session_model.id, borrowed_ip.item
-        );
+                    let next_attempt_at = Utc::now()
+                        + chrono::Duration::from_std(delay).unwrap_or(chrono::Duration::zero());
+                    let from = session_model.ui_status.clone();
+                    let mut active_session = SessionStateMachine::transition(
+                        session_model,
+                        UiStatus::WaitingForSandbox,
+                        TransitionCause::SandboxUnavailable,
+                        &ACTOR,
+                    )?;
+                    active_session.status_message = Set(Some(format!(
+                        "Waiting for a sandbox to become available (attempt {})",
+                        attempts
+                    )));
+                    active_session.sandbox_borrow_attempts = Set(attempts);
+                    active_session.next_borrow_attempt_at = Set(Some(next_attempt_at.into()));
+                    let updated = active_session.update(db).await?;
+                    SessionStateMachine::after_save(
+                        db,
+                        &from,
+                        &updated,
+                        TransitionCause::SandboxUnavailable,
+                        &ACTOR,
+                    )
+                    .await;
+                    continue;
+                }
+            },
+        };
 
         // Save session_id before moving session_model
         let session_id = session_model.id;
+        let from = session_model.ui_status.clone();
 
         // Update session's sbx_config with the borrowed IP data (including borrow_token)
-        let mut active_session: session::ActiveModel = session_model.into();
-        let sbx_config_data = serde_json::json!({
-            "item": borrowed_ip.item,
-            "borrow_token": borrowed_ip.borrow_token,
-        });
+        let mut active_session = SessionStateMachine::transition(
+            session_model,
+            UiStatus::InProgress,
+            TransitionCause::SandboxBorrowed,
+            &ACTOR,
+        )?;
+        if !kept {
+            active_session.sandbox_region =
+                Set(ip_allocator::item_region(&sbx_config_data["item"]));
+        }
         active_session.sbx_config = Set(Some(sbx_config_data));
-        active_session.ui_status = Set(UiStatus::InProgress);
-        active_session.update(db).await?;
+        active_session.ip_return_key = Set(None);
+        active_session.status_message = Set(region_message);
+        active_session.sandbox_borrow_attempts = Set(0);
+        active_session.next_borrow_attempt_at = Set(None);
+        let updated = active_session.update(db).await?;
+        SessionStateMachine::after_save(
+            db,
+            &from,
+            &updated,
+            TransitionCause::SandboxBorrowed,
+            &ACTOR,
+        )
+        .await;
 
+        fan_out_slots.claim(&updated);
         info!("Updated session {} sbx_config with borrowed IP", session_id);
 
         // Enqueue each prompt for this session
         for prompt in prompts {
-            let job = OutboxJob {
-                prompt_id: prompt.id.to_string(),
-                payload: serde_json::json!({}),
-            };
+            let job = OutboxJob::new(&updated, &prompt, WORKER);
 
             storage
                 .push(job)
@@ -0,0 +1,64 @@
+use sea_orm::{ActiveModelTrait, DatabaseConnection, Set};
+use tracing::error;
+
+use crate::entities::prompt;
+
+/// Progress once the sandbox is set up and the CLI is about to start
+const BOOTSTRAP_DONE: i16 = 20;
+
+/// Progress once the CLI has exited; the rest is finalizing the session
+const CLI_DONE: i16 = 90;
+
+/// Message count at which a run is considered roughly two thirds done.
+/// Runs vary widely, so CLI progress approaches `CLI_DONE` asymptotically instead of
+/// ever reaching it from message counts alone.
+const TYPICAL_MESSAGES: f64 = 40.0;
+
+/// Progress after a recorded timing phase finishes, None for phases that don't move it.
+///
+/// Bootstrapping phases cover 0-20%, the CLI run 20-90% and finalizing 90-100%.
+pub fn after_phase(phase: &str) -> Option<i16> {
+    match phase {
+        "history" => Some(4),
+        "upload_prompt" => Some(8),
+        "gh_auth" => Some(12),
+        "clone" => Some(17),
+        "checkout" => Some(BOOTSTRAP_DONE),
+        "cli" => Some(CLI_DONE),
+        "total" => Some(100),
+        _ => None,
+    }
+}
+
+/// Progress while the CLI is running, after it has produced `messages` messages
+pub fn during_cli(messages: u64) -> i16 {
+    let span = f64::from(CLI_DONE - BOOTSTRAP_DONE);
+    let fraction = 1.0 - (-(messages as f64) / TYPICAL_MESSAGES).exp();
+    (BOOTSTRAP_DONE + (span * fraction).floor() as i16).min(CLI_DONE - 1)
+}
+
+/// Persist a prompt's progress. Failures are logged but never fail the job.
+pub async fn store(db: &DatabaseConnection, prompt_id: uuid::Uuid, progress: i16) {
+    let active_prompt = prompt::ActiveModel {
+        id: Set(prompt_id),
+        progress: Set(progress),
+        ..Default::default()
+    };
+
+    if let Err(e) = active_prompt.update(db).await {
+        error!("Failed to persist progress for prompt {}: {}", prompt_id, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cli_progress_stays_within_its_band() {
+        assert_eq!(during_cli(0), BOOTSTRAP_DONE);
+        assert!(during_cli(10) > BOOTSTRAP_DONE);
+        assert!(during_cli(40) > during_cli(10));
+        assert_eq!(during_cli(100_000), CLI_DONE - 1);
+    }
+}
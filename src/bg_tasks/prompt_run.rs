@@ -0,0 +1,355 @@
+use chrono::Utc;
+use sea_orm::sea_query::Expr;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter,
+    QuerySelect, Set, TransactionTrait,
+};
+use std::collections::VecDeque;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+use crate::entities::message::{self, Entity as Message};
+use crate::entities::prompt::{self, Entity as Prompt};
+use crate::entities::session::ClaudeModel;
+use crate::services::{conversation_view, hashing, message_blobs, session_activity};
+
+/// A claimed run older than this is assumed to belong to a crashed worker and may be resumed,
+/// unless overridden with `OUTBOX_RUN_STALE_SECS`
+const DEFAULT_RUN_STALE_AFTER: Duration = Duration::from_secs(2 * 60 * 60);
+
+pub fn run_stale_after() -> Duration {
+    std::env::var("OUTBOX_RUN_STALE_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_RUN_STALE_AFTER)
+}
+
+/// Outcome of an outbox job trying to claim its prompt
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Claim {
+    /// The job owns a fresh run and should execute the prompt
+    Claimed(uuid::Uuid),
+    /// A previous delivery already ran the prompt to completion
+    AlreadyCompleted,
+    /// Another delivery is running the prompt right now
+    InProgress(uuid::Uuid),
+    /// The prompt is a draft, so it must not run until it is submitted
+    Draft,
+}
+
+/// Claim `prompt_id` for a new run, so a redelivered job never runs a prompt twice.
+///
+/// The prompt row is locked for the duration of the check. A prompt whose run was released
+/// after a failure, or whose run went stale because its worker died, is claimed again and any
+/// messages from the earlier attempt are discarded so the new run replaces them.
+pub async fn claim(db: &DatabaseConnection, prompt_id: uuid::Uuid) -> Result<Claim, DbErr> {
+    let txn = db.begin().await?;
+
+    let prompt = Prompt::find_by_id(prompt_id)
+        .lock_exclusive()
+        .one(&txn)
+        .await?
+        .ok_or_else(|| DbErr::RecordNotFound(format!("Prompt {} not found", prompt_id)))?;
+
+    if prompt.completed_at.is_some() {
+        txn.commit().await?;
+        return Ok(Claim::AlreadyCompleted);
+    }
+    if prompt.draft {
+        txn.commit().await?;
+        return Ok(Claim::Draft);
+    }
+
+    if let (Some(run_id), Some(started_at)) = (prompt.run_id, prompt.started_at) {
+        let age = (Utc::now() - started_at.with_timezone(&Utc))
+            .to_std()
+            .unwrap_or_default();
+        if age < run_stale_after() {
+            txn.commit().await?;
+            return Ok(Claim::InProgress(run_id));
+        }
+        warn!(
+            "Run {} of prompt {} started {:?} ago without finishing, resuming",
+            run_id, prompt_id, age
+        );
+    }
+
+    if prompt.run_id.is_none() {
+        let waited = (Utc::now() - prompt.created_at.with_timezone(&Utc))
+            .to_std()
+            .unwrap_or_default();
+        crate::metrics::get()
+            .prompt_queue_latency_seconds
+            .with_label_values(&[prompt.priority.as_str()])
+            .observe(waited.as_secs_f64());
+    }
+
+    let run_id = uuid::Uuid::new_v4();
+    let active_prompt = prompt::ActiveModel {
+        id: Set(prompt_id),
+        run_id: Set(Some(run_id)),
+        started_at: Set(Some(Utc::now().into())),
+        completed_at: Set(None),
+        progress: Set(0),
+        tool_summary: Set(None),
+        exit_code: Set(None),
+        stderr: Set(None),
+        error_kind: Set(None),
+        change_summary: Set(None),
+        secret_findings: Set(None),
+        output_log_path: Set(None),
+        ..Default::default()
+    };
+    active_prompt.update(&txn).await?;
+    txn.commit().await?;
+
+    discard_messages(db, prompt_id).await?;
+    session_activity::record(db, prompt.session_id, prompt_id).await;
+    info!("Claimed prompt {} with run {}", prompt_id, run_id);
+
+    Ok(Claim::Claimed(run_id))
+}
+
+/// Delete messages left by an earlier, unfinished attempt at a prompt
+async fn discard_messages(db: &DatabaseConnection, prompt_id: uuid::Uuid) -> Result<(), DbErr> {
+    let stale = Message::find()
+        .filter(message::Column::PromptId.eq(prompt_id))
+        .all(db)
+        .await?;
+    if stale.is_empty() {
+        return Ok(());
+    }
+
+    warn!(
+        "Discarding {} messages from an earlier attempt at prompt {}",
+        stale.len(),
+        prompt_id
+    );
+    for model in &stale {
+        message_blobs::remove(model).await;
+    }
+    Message::delete_many()
+        .filter(message::Column::PromptId.eq(prompt_id))
+        .exec(db)
+        .await?;
+    conversation_view::refresh(db, [prompt_id]).await;
+    Ok(())
+}
+
+/// Mark the run as finished so later deliveries of the same prompt are no-ops
+pub async fn complete(db: &DatabaseConnection, prompt_id: uuid::Uuid, run_id: uuid::Uuid) {
+    let result = Prompt::update_many()
+        .col_expr(prompt::Column::CompletedAt, Expr::value(Utc::now()))
+        .filter(prompt::Column::Id.eq(prompt_id))
+        .filter(prompt::Column::RunId.eq(run_id))
+        .exec(db)
+        .await;
+
+    if let Err(e) = result {
+        error!(
+            "Failed to mark run {} of prompt {} complete: {}",
+            run_id, prompt_id, e
+        );
+    }
+}
+
+/// Whether a prompt of `session_id` is being run right now: claimed, not completed, and not so
+/// old that its worker is presumed dead
+pub async fn has_active_run(
+    db: &DatabaseConnection,
+    session_id: uuid::Uuid,
+) -> Result<bool, DbErr> {
+    let stale_before = Utc::now()
+        - chrono::Duration::from_std(run_stale_after()).unwrap_or(chrono::Duration::zero());
+    Ok(Prompt::find()
+        .filter(prompt::Column::SessionId.eq(session_id))
+        .filter(prompt::Column::RunId.is_not_null())
+        .filter(prompt::Column::CompletedAt.is_null())
+        .filter(prompt::Column::StartedAt.gt(stale_before))
+        .one(db)
+        .await?
+        .is_some())
+}
+
+/// Give up the claim after a failed run so a retry can claim the prompt again
+pub async fn release(db: &DatabaseConnection, prompt_id: uuid::Uuid, run_id: uuid::Uuid) {
+    let result = Prompt::update_many()
+        .col_expr(
+            prompt::Column::RunId,
+            Expr::value(Option::<uuid::Uuid>::None),
+        )
+        .filter(prompt::Column::Id.eq(prompt_id))
+        .filter(prompt::Column::RunId.eq(run_id))
+        .exec(db)
+        .await;
+
+    if let Err(e) = result {
+        error!(
+            "Failed to release run {} of prompt {}: {}",
+            run_id, prompt_id, e
+        );
+    }
+}
+
+/// System prompt template, with `{REPO_PATH}`, `{REPO}`, `{BRANCH}` and `{TARGET_BRANCH}`
+/// placeholders
+pub const SYSTEM_PROMPT_TEMPLATE: &str =
+    include_str!("../../prompts/outbox_handler_system_prompt.md");
+
+/// Version of the system prompt template: the first 12 hex digits of its SHA-256, so it
+/// changes whenever the template does
+pub fn template_version() -> String {
+    hashing::sha256_hex(SYSTEM_PROMPT_TEMPLATE)[..12].to_string()
+}
+
+/// Persist exactly what a prompt was run with: the prompt text sent, the rendered system
+/// prompt, the CLI argument vector, the template version and the model.
+///
+/// Persistence failures are logged but never fail the job.
+pub async fn record(
+    db: &DatabaseConnection,
+    prompt_id: uuid::Uuid,
+    rendered_prompt: &str,
+    system_prompt: &str,
+    cli_args: &[String],
+    model: ClaudeModel,
+) {
+    let active_prompt = prompt::ActiveModel {
+        id: Set(prompt_id),
+        rendered_prompt: Set(Some(rendered_prompt.to_string())),
+        system_prompt: Set(Some(system_prompt.to_string())),
+        cli_args: Set(Some(serde_json::json!(cli_args))),
+        template_version: Set(Some(template_version())),
+        model: Set(Some(model)),
+        ..Default::default()
+    };
+
+    if let Err(e) = active_prompt.update(db).await {
+        error!(
+            "Failed to persist run configuration for prompt {}: {}",
+            prompt_id, e
+        );
+    }
+}
+
+/// Stderr kept per run, from the end; the last lines usually say why the CLI gave up
+const STDERR_TAIL_BYTES: usize = 16 * 1024;
+
+/// The last `STDERR_TAIL_BYTES` of a run's stderr, collected line by line
+#[derive(Debug, Default)]
+pub struct StderrTail {
+    lines: VecDeque<String>,
+    bytes: usize,
+    total_lines: usize,
+}
+
+impl StderrTail {
+    pub fn push(&mut self, mut line: String) {
+        if line.len() > STDERR_TAIL_BYTES {
+            let mut start = line.len() - STDERR_TAIL_BYTES;
+            while !line.is_char_boundary(start) {
+                start += 1;
+            }
+            line.drain(..start);
+        }
+        self.total_lines += 1;
+        self.bytes += line.len() + 1;
+        self.lines.push_back(line);
+        while self.bytes > STDERR_TAIL_BYTES + 1 {
+            let Some(dropped) = self.lines.pop_front() else {
+                break;
+            };
+            self.bytes -= dropped.len() + 1;
+        }
+    }
+
+    pub fn total_lines(&self) -> usize {
+        self.total_lines
+    }
+
+    pub fn first_and_last(&self) -> (&str, &str) {
+        (
+            self.lines.front().map_or("", String::as_str),
+            self.lines.back().map_or("", String::as_str),
+        )
+    }
+
+    /// The kept lines, prefixed with how many earlier ones were dropped; None when stderr was
+    /// empty
+    pub fn into_text(self) -> Option<String> {
+        if self.total_lines == 0 {
+            return None;
+        }
+        let omitted = self.total_lines - self.lines.len();
+        let mut text = String::with_capacity(self.bytes + 40);
+        if omitted > 0 {
+            text.push_str(&format!("[{} earlier lines omitted]\n", omitted));
+        }
+        for line in self.lines {
+            text.push_str(&line);
+            text.push('\n');
+        }
+        Some(text)
+    }
+}
+
+/// Persist how the last run's CLI process exited, with its stderr when the run failed or
+/// produced no messages.
+///
+/// Persistence failures are logged but never fail the job.
+pub async fn record_exit(
+    db: &DatabaseConnection,
+    prompt_id: uuid::Uuid,
+    exit_code: Option<i32>,
+    stderr: Option<String>,
+) {
+    let active_prompt = prompt::ActiveModel {
+        id: Set(prompt_id),
+        exit_code: Set(exit_code),
+        stderr: Set(stderr),
+        ..Default::default()
+    };
+
+    if let Err(e) = active_prompt.update(db).await {
+        error!("Failed to persist exit of prompt {}: {}", prompt_id, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_template_version_is_stable_hex() {
+        let version = template_version();
+        assert_eq!(version.len(), 12);
+        assert!(version.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(version, template_version());
+    }
+
+    #[test]
+    fn test_stderr_tail_keeps_the_last_lines() {
+        assert_eq!(StderrTail::default().into_text(), None);
+
+        let mut tail = StderrTail::default();
+        tail.push("first".to_string());
+        tail.push("second".to_string());
+        assert_eq!(tail.first_and_last(), ("first", "second"));
+        assert_eq!(tail.into_text().as_deref(), Some("first\nsecond\n"));
+
+        let mut tail = StderrTail::default();
+        for i in 0..10_000 {
+            tail.push(format!("line {}", i));
+        }
+        let text = tail.into_text().unwrap();
+        assert!(text.len() <= STDERR_TAIL_BYTES + 40);
+        assert!(text.starts_with('['));
+        assert!(text.ends_with("line 9999\n"));
+
+        let mut tail = StderrTail::default();
+        tail.push("é".repeat(STDERR_TAIL_BYTES));
+        let text = tail.into_text().unwrap();
+        assert!(text.len() <= STDERR_TAIL_BYTES + 1);
+    }
+}
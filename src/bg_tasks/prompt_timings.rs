@@ -0,0 +1,55 @@
+use sea_orm::{ActiveModelTrait, DatabaseConnection, Set};
+use serde_json::{Map, Value};
+use std::time::Duration;
+use tracing::error;
+
+use super::prompt_progress;
+use crate::entities::prompt;
+
+/// Per-phase timings for a single prompt run.
+///
+/// Each recorded phase is stored as milliseconds under its phase name in the prompt's
+/// `timings` column and observed in the `prompt_phase_duration_seconds` histogram. Phases
+/// that mark a pipeline milestone also advance the prompt's `progress`.
+pub struct PromptTimings {
+    prompt_id: uuid::Uuid,
+    phases: Map<String, Value>,
+}
+
+impl PromptTimings {
+    pub fn new(prompt_id: uuid::Uuid) -> Self {
+        Self {
+            prompt_id,
+            phases: Map::new(),
+        }
+    }
+
+    /// Record how long a phase took and persist the timings collected so far.
+    ///
+    /// Persistence failures are logged but never fail the job.
+    pub async fn record(&mut self, db: &DatabaseConnection, phase: &str, duration: Duration) {
+        crate::metrics::get()
+            .prompt_phase_duration_seconds
+            .with_label_values(&[phase])
+            .observe(duration.as_secs_f64());
+
+        self.phases
+            .insert(phase.to_string(), Value::from(duration.as_millis() as u64));
+
+        let mut active_prompt = prompt::ActiveModel {
+            id: Set(self.prompt_id),
+            timings: Set(Some(Value::Object(self.phases.clone()))),
+            ..Default::default()
+        };
+        if let Some(progress) = prompt_progress::after_phase(phase) {
+            active_prompt.progress = Set(progress);
+        }
+
+        if let Err(e) = active_prompt.update(db).await {
+            error!(
+                "Failed to persist timings for prompt {}: {}",
+                self.prompt_id, e
+            );
+        }
+    }
+}
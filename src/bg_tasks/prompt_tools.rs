@@ -0,0 +1,148 @@
+use rocket_okapi::okapi::schemars::{self, JsonSchema};
+use sea_orm::{ActiveModelTrait, DatabaseConnection, Set};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use tracing::error;
+
+use crate::entities::prompt;
+
+/// Tool calls made during a prompt's run, as stored in the prompt's `tool_summary` column
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct ToolSummary {
+    /// Tool calls of any kind
+    pub total: u64,
+    /// Calls per category: `file_read`, `file_write`, `shell`, `web_search`, `web_fetch` or
+    /// `other`
+    pub by_category: BTreeMap<String, u64>,
+    /// Calls per tool name as reported by the CLI, e.g. `mcp__sandbox__read_file`
+    pub by_tool: BTreeMap<String, u64>,
+}
+
+impl ToolSummary {
+    /// Count the `tool_use` blocks of a CLI stream-json message. Returns whether any were
+    /// found, i.e. whether the summary changed.
+    pub fn record(&mut self, message: &Value) -> bool {
+        let names = tool_uses(message);
+        for name in &names {
+            self.total += 1;
+            *self
+                .by_category
+                .entry(category(name).to_string())
+                .or_default() += 1;
+            *self.by_tool.entry(name.to_string()).or_default() += 1;
+        }
+        !names.is_empty()
+    }
+}
+
+/// Names of the tools an assistant message calls
+fn tool_uses(message: &Value) -> Vec<&str> {
+    if message.get("type").and_then(Value::as_str) != Some("assistant") {
+        return Vec::new();
+    }
+    message
+        .pointer("/message/content")
+        .and_then(Value::as_array)
+        .map(|blocks| {
+            blocks
+                .iter()
+                .filter(|b| b.get("type").and_then(Value::as_str) == Some("tool_use"))
+                .filter_map(|b| b.get("name").and_then(Value::as_str))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Coarse category of a tool, from its name with any `mcp__<server>__` prefix removed
+pub fn category(name: &str) -> &'static str {
+    let tool = name
+        .strip_prefix("mcp__")
+        .and_then(|rest| rest.split_once("__"))
+        .map(|(_, tool)| tool)
+        .unwrap_or(name)
+        .to_lowercase();
+    let has = |words: &[&str]| words.iter().any(|w| tool.contains(w));
+
+    if (has(&["search"]) && has(&["web", "google", "bing"])) || tool == "websearch" {
+        "web_search"
+    } else if has(&["fetch", "browser", "http", "url"]) {
+        "web_fetch"
+    } else if has(&["bash", "shell", "exec", "command", "terminal"]) {
+        "shell"
+    } else if has(&[
+        "write", "edit", "replace", "create", "delete", "move", "upload",
+    ]) {
+        "file_write"
+    } else if has(&[
+        "read", "list", "view", "glob", "grep", "find", "search", "download",
+    ]) {
+        "file_read"
+    } else {
+        "other"
+    }
+}
+
+/// Persist a prompt's tool summary. Failures are logged but never fail the job.
+pub async fn store(db: &DatabaseConnection, prompt_id: uuid::Uuid, summary: &ToolSummary) {
+    let value = match serde_json::to_value(summary) {
+        Ok(value) => value,
+        Err(e) => {
+            error!(
+                "Failed to encode tool summary for prompt {}: {}",
+                prompt_id, e
+            );
+            return;
+        }
+    };
+    let active_prompt = prompt::ActiveModel {
+        id: Set(prompt_id),
+        tool_summary: Set(Some(value)),
+        ..Default::default()
+    };
+
+    if let Err(e) = active_prompt.update(db).await {
+        error!(
+            "Failed to persist tool summary for prompt {}: {}",
+            prompt_id, e
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_category() {
+        assert_eq!(category("mcp__sandbox__read_file"), "file_read");
+        assert_eq!(category("mcp__sandbox__file_list"), "file_read");
+        assert_eq!(category("mcp__sandbox__write_file"), "file_write");
+        assert_eq!(category("mcp__sandbox__str_replace_editor"), "file_write");
+        assert_eq!(category("mcp__sandbox__execute_bash"), "shell");
+        assert_eq!(category("WebSearch"), "web_search");
+        assert_eq!(category("mcp__browser__navigate"), "other");
+        assert_eq!(category("mcp__sandbox__browser_screenshot"), "web_fetch");
+    }
+
+    #[test]
+    fn test_record_counts_tool_use_blocks() {
+        let mut summary = ToolSummary::default();
+        let message = serde_json::json!({
+            "type": "assistant",
+            "message": {"content": [
+                {"type": "text", "text": "Reading files"},
+                {"type": "tool_use", "name": "mcp__sandbox__read_file", "input": {}},
+                {"type": "tool_use", "name": "mcp__sandbox__read_file", "input": {}},
+                {"type": "tool_use", "name": "mcp__sandbox__execute_bash", "input": {}}
+            ]}
+        });
+        assert!(summary.record(&message));
+        assert!(!summary.record(&serde_json::json!({"type": "user"})));
+
+        assert_eq!(summary.total, 3);
+        assert_eq!(summary.by_category["file_read"], 2);
+        assert_eq!(summary.by_category["shell"], 1);
+        assert_eq!(summary.by_tool["mcp__sandbox__read_file"], 2);
+    }
+}
@@ -0,0 +1,50 @@
+use sea_orm::DatabaseConnection;
+use std::time::Duration;
+use tracing::{error, info};
+
+use super::worker_registry;
+use crate::entities::operational_event::OperationalEventKind;
+use crate::services::{operational_events, queue_stats, sandbox_queue};
+
+/// Name of the loop in the worker registry
+const WORKER: &str = "queue_monitor";
+
+/// How often the queue is measured; short, since autoscalers act on these gauges
+const POLL_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Periodic task that publishes prompt queue depth and worker job counts as metrics, and
+/// refreshes the estimated start time of queued sessions
+pub async fn run_queue_monitor(db: DatabaseConnection) -> anyhow::Result<()> {
+    info!(
+        "Starting queue monitor - checking every {} seconds",
+        POLL_INTERVAL.as_secs()
+    );
+
+    worker_registry::register(WORKER, POLL_INTERVAL);
+
+    loop {
+        match queue_stats::collect(&db).await {
+            Ok(stats) => {
+                queue_stats::observe(&stats);
+                worker_registry::record_success(WORKER, stats.pending_prompts().max(0) as u64);
+            }
+            Err(e) => {
+                worker_registry::record_error(WORKER, &e);
+                operational_events::record(
+                    &db,
+                    WORKER,
+                    OperationalEventKind::Database,
+                    &e.to_string(),
+                    None,
+                )
+                .await;
+                error!("Queue monitor failed: {}", e);
+            }
+        }
+        if let Err(e) = sandbox_queue::refresh_estimates(&db).await {
+            error!("Failed to refresh estimated start times: {}", e);
+        }
+
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
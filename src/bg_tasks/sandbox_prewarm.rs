@@ -0,0 +1,493 @@
+//! Sandboxes borrowed ahead of demand, so a session's first prompt does not wait for the borrow,
+//! `gh auth` and, for hot repos, the clone.
+//!
+//! The prewarmer keeps `SANDBOX_PREWARM_COUNT` generic sandboxes plus the per-repo counts of
+//! `SANDBOX_PREWARM_REPOS` in `warm_sandbox`. A sandbox is logged in to its repo's GitHub host
+//! when that host's token is not per user, and a repo sandbox holds a clone of the repo. The
+//! prompt poller claims a ready sandbox for its repo, then a generic one, before borrowing on
+//! demand; the claim is recorded under `warm` in the session's `sbx_config` so the outbox
+//! publisher skips the steps already done. Sandboxes nobody claimed within
+//! `SANDBOX_PREWARM_TTL_SECS` are returned to the allocator, keeping clones recent.
+
+use chrono::Utc;
+use sandbox_client::types::ShellExecRequest;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, IntoActiveModel, QueryFilter,
+    QueryOrder, Set,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+use super::worker_registry;
+use crate::config;
+use crate::entities::operational_event::OperationalEventKind;
+use crate::entities::warm_sandbox::{self, Entity as WarmSandbox, WarmSandboxStatus};
+use crate::services::github_host;
+use crate::services::http_client;
+use crate::services::ip_allocator;
+use crate::services::operational_events;
+use crate::services::sandbox_gh_auth;
+
+/// Name of the loop in the worker registry
+const WORKER: &str = "sandbox_prewarm";
+
+const POLL_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Pool label of sandboxes not tied to a repo
+const GENERIC_POOL: &str = "generic";
+
+/// A sandbox still warming after this long was abandoned by a restart and is returned
+const WARMING_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Directory under /home/gem a repo sandbox's clone is kept in until a session claims it
+const WARM_REPO_DIR: &str = "warm_repo";
+
+/// What a claimed sandbox already has done, stored under `warm` in the session's `sbx_config`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WarmState {
+    /// GitHub host `gh` is logged in to
+    pub authenticated_host: Option<String>,
+    /// Repo cloned into `repo_dir`
+    pub repo: Option<String>,
+    pub repo_dir: Option<String>,
+}
+
+impl WarmState {
+    /// The warm state recorded in a session's `sbx_config`, default when it was borrowed on demand
+    pub fn from_sbx_config(sbx_config: &serde_json::Value) -> WarmState {
+        sbx_config
+            .get("warm")
+            .and_then(|warm| serde_json::from_value(warm.clone()).ok())
+            .unwrap_or_default()
+    }
+
+    /// Whether `gh` in the sandbox is already logged in to `hostname`
+    pub fn is_authenticated(&self, hostname: &str) -> bool {
+        self.authenticated_host.as_deref() == Some(hostname)
+    }
+
+    /// The clone of `repo` in the sandbox, if it has one
+    pub fn clone_of(&self, repo: &str) -> Option<&str> {
+        match (&self.repo, &self.repo_dir) {
+            (Some(cloned), Some(dir)) if cloned == repo => Some(dir),
+            _ => None,
+        }
+    }
+}
+
+/// Shell that moves the warm clone into `repo_dir` and brings it up to date, falling back to a
+/// fresh clone when the warm one is gone, e.g. on a later prompt of the session
+pub fn adopt_clone_script(warm_dir: &str, repo_dir: &str, clone_url: &str) -> String {
+    format!(
+        "if [ -d {warm} ] && [ ! -d {dir} ]; then \
+         mv {warm} {dir} && git -C {dir} fetch --prune origin && git -C {dir} reset --hard '@{{u}}'; \
+         else git clone {url} {dir}; fi",
+        warm = warm_dir,
+        dir = repo_dir,
+        url = clone_url,
+    )
+}
+
+/// Claim a ready sandbox for a session on `repo`, preferring one with a clone of the repo.
+/// Returns the `sbx_config` to store on the session.
+pub async fn claim(
+    db: &DatabaseConnection,
+    repo: Option<&str>,
+) -> Result<Option<serde_json::Value>, sea_orm::DbErr> {
+    if !config::get().prewarm.enabled() {
+        return Ok(None);
+    }
+
+    let now = Utc::now();
+    let mut candidates = Vec::new();
+    if let Some(repo) = repo {
+        candidates.push(ready(repo_filter(Some(repo)), now).all(db).await?);
+    }
+    candidates.push(ready(repo_filter(None), now).all(db).await?);
+
+    for warm in candidates.into_iter().flatten() {
+        // Only one claimer gets to delete the row; a sandbox that expired meanwhile is left to
+        // be returned
+        let deleted = WarmSandbox::delete_many()
+            .filter(warm_sandbox::Column::Id.eq(warm.id))
+            .filter(warm_sandbox::Column::Status.eq(WarmSandboxStatus::Ready))
+            .filter(warm_sandbox::Column::ExpiresAt.gt(Utc::now()))
+            .exec(db)
+            .await?;
+        if deleted.rows_affected == 1 {
+            crate::metrics::get()
+                .warm_sandbox_claims_total
+                .with_label_values(&["hit"])
+                .inc();
+            info!(
+                "Claimed warm sandbox {} ({})",
+                warm.id,
+                warm.repo.as_deref().unwrap_or(GENERIC_POOL)
+            );
+            return Ok(Some(session_sbx_config(warm)));
+        }
+    }
+
+    crate::metrics::get()
+        .warm_sandbox_claims_total
+        .with_label_values(&["miss"])
+        .inc();
+    Ok(None)
+}
+
+fn repo_filter(repo: Option<&str>) -> sea_orm::Condition {
+    let column = warm_sandbox::Column::Repo;
+    sea_orm::Condition::all().add(match repo {
+        Some(repo) => column.eq(repo),
+        None => column.is_null(),
+    })
+}
+
+fn ready(pool: sea_orm::Condition, now: chrono::DateTime<Utc>) -> sea_orm::Select<WarmSandbox> {
+    WarmSandbox::find()
+        .filter(pool)
+        .filter(warm_sandbox::Column::Status.eq(WarmSandboxStatus::Ready))
+        .filter(warm_sandbox::Column::ExpiresAt.gt(now))
+        .order_by_asc(warm_sandbox::Column::CreatedAt)
+}
+
+/// The borrow as stored on sessions, with what the prewarmer already did under `warm`
+fn session_sbx_config(warm: warm_sandbox::Model) -> serde_json::Value {
+    let mut sbx_config = warm.sbx_config;
+    let state = WarmState {
+        authenticated_host: warm.authenticated_host,
+        repo: warm.repo_dir.as_ref().and(warm.repo),
+        repo_dir: warm.repo_dir,
+    };
+    if let Some(obj) = sbx_config.as_object_mut() {
+        obj.insert(
+            "warm".to_string(),
+            serde_json::to_value(state).unwrap_or_default(),
+        );
+    }
+    sbx_config
+}
+
+/// Periodic task that keeps the warm pools topped up and returns expired sandboxes
+pub async fn run_sandbox_prewarmer(db: DatabaseConnection) -> anyhow::Result<()> {
+    let prewarm = &config::get().prewarm;
+    if !prewarm.enabled() {
+        info!("Sandbox pre-warming disabled");
+        return Ok(());
+    }
+    info!(
+        "Starting sandbox prewarmer - {} generic, {:?} per repo, checking every {} seconds",
+        prewarm.generic,
+        prewarm.repos,
+        POLL_INTERVAL.as_secs()
+    );
+
+    worker_registry::register(WORKER, POLL_INTERVAL);
+
+    loop {
+        match prewarm_pass(&db).await {
+            Ok(count) => worker_registry::record_success(WORKER, count),
+            Err(e) => {
+                worker_registry::record_error(WORKER, &e);
+                operational_events::record_iteration_error(&db, WORKER, &e).await;
+                error!("Sandbox prewarmer failed: {}", e);
+            }
+        }
+
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+/// One pass: return stale sandboxes, warm missing ones and refresh the gauges. Returns the
+/// number of sandboxes returned or warmed.
+async fn prewarm_pass(db: &DatabaseConnection) -> anyhow::Result<u64> {
+    let recycled = recycle(db).await?;
+    let warmed = top_up(db).await?;
+    observe(db).await?;
+    Ok(recycled + warmed)
+}
+
+/// Pools and how many sandboxes each should hold; None is the generic pool
+fn targets() -> Vec<(Option<String>, usize)> {
+    let prewarm = &config::get().prewarm;
+    std::iter::once((None, prewarm.generic))
+        .chain(
+            prewarm
+                .repos
+                .iter()
+                .map(|(repo, count)| (Some(repo.clone()), *count)),
+        )
+        .collect()
+}
+
+/// Return sandboxes that expired unclaimed, were abandoned while warming or belong to a pool
+/// that is no longer configured
+async fn recycle(db: &DatabaseConnection) -> anyhow::Result<u64> {
+    let now = Utc::now();
+    let warming_cutoff =
+        now - chrono::Duration::from_std(WARMING_TIMEOUT).unwrap_or(chrono::Duration::zero());
+    let pools: Vec<Option<String>> = targets()
+        .into_iter()
+        .filter(|(_, count)| *count > 0)
+        .map(|(repo, _)| repo)
+        .collect();
+
+    let mut count = 0;
+    for warm in WarmSandbox::find().all(db).await? {
+        let stale = match warm.status {
+            WarmSandboxStatus::Ready => warm.expires_at <= now,
+            WarmSandboxStatus::Warming => warm.created_at <= warming_cutoff,
+        };
+        if stale || !pools.contains(&warm.repo) {
+            release(db, warm).await?;
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+/// Remove a warm sandbox and return its IP. The row is deleted first so it cannot be claimed
+/// meanwhile, and put back expired with its return key when the allocator could not be reached.
+async fn release(db: &DatabaseConnection, warm: warm_sandbox::Model) -> anyhow::Result<()> {
+    let deleted = WarmSandbox::delete_by_id(warm.id).exec(db).await?;
+    if deleted.rows_affected == 0 {
+        return Ok(());
+    }
+
+    let key = warm.return_key.unwrap_or_else(uuid::Uuid::new_v4);
+    let item = warm.sbx_config.get("item").cloned().unwrap_or_default();
+    let borrow_token = warm
+        .sbx_config
+        .get("borrow_token")
+        .and_then(|token| token.as_str())
+        .unwrap_or_default()
+        .to_string();
+
+    match ip_allocator::return_item(item, borrow_token, key).await {
+        Ok(_) => {
+            info!("Returned warm sandbox {}", warm.id);
+            Ok(())
+        }
+        Err(e) => {
+            warn!(
+                "Failed to return warm sandbox {}, retrying next pass: {}",
+                warm.id, e
+            );
+            let mut retry = warm.into_active_model();
+            retry.status = Set(WarmSandboxStatus::Ready);
+            retry.return_key = Set(Some(key));
+            retry.expires_at = Set(Utc::now().into());
+            retry.insert(db).await?;
+            Ok(())
+        }
+    }
+}
+
+/// Borrow and warm the sandboxes each pool is short of. Stops at the first failed borrow so
+/// pre-warming does not compete with sessions for a busy allocator.
+async fn top_up(db: &DatabaseConnection) -> anyhow::Result<u64> {
+    let ip_client = ip_allocator_client::Client::new_with_client(
+        &ip_allocator::allocator_url(),
+        http_client::client(),
+    );
+
+    let mut count = 0;
+    for (repo, target) in targets() {
+        let held = WarmSandbox::find()
+            .filter(repo_filter(repo.as_deref()))
+            .all(db)
+            .await?
+            .len();
+
+        for _ in held..target {
+            let borrowed = match ip_client.handlers_ip_borrow(None).await {
+                Ok(borrowed) => borrowed,
+                Err(e) => {
+                    warn!("Pre-warming paused, failed to borrow a sandbox: {}", e);
+                    operational_events::record(
+                        db,
+                        WORKER,
+                        OperationalEventKind::Allocator,
+                        &format!("Failed to borrow a sandbox: {}", e),
+                        Some(serde_json::json!({ "repo": repo })),
+                    )
+                    .await;
+                    return Ok(count);
+                }
+            };
+            let sbx_config = serde_json::json!({
+                "item": borrowed.item,
+                "borrow_token": borrowed.borrow_token,
+            });
+            warm_one(db, repo.as_deref(), sbx_config).await?;
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+/// Record a freshly borrowed sandbox, authenticate it and clone `repo` into it. A sandbox that
+/// fails to warm is returned right away.
+async fn warm_one(
+    db: &DatabaseConnection,
+    repo: Option<&str>,
+    sbx_config: serde_json::Value,
+) -> anyhow::Result<()> {
+    let ttl =
+        chrono::Duration::from_std(config::get().prewarm.ttl).unwrap_or(chrono::Duration::zero());
+    let warm = warm_sandbox::ActiveModel {
+        id: Set(uuid::Uuid::new_v4()),
+        repo: Set(repo.map(str::to_string)),
+        sbx_config: Set(sbx_config.clone()),
+        status: Set(WarmSandboxStatus::Warming),
+        authenticated_host: Set(None),
+        repo_dir: Set(None),
+        return_key: Set(None),
+        created_at: Set(Utc::now().into()),
+        expires_at: Set((Utc::now() + ttl).into()),
+    }
+    .insert(db)
+    .await?;
+
+    match prepare(&sbx_config, repo).await {
+        Ok((authenticated_host, repo_dir)) => {
+            let mut ready = warm.into_active_model();
+            ready.status = Set(WarmSandboxStatus::Ready);
+            ready.authenticated_host = Set(authenticated_host);
+            ready.repo_dir = Set(repo_dir);
+            let ready = ready.update(db).await?;
+            info!(
+                "Warmed sandbox {} ({})",
+                ready.id,
+                ready.repo.as_deref().unwrap_or(GENERIC_POOL)
+            );
+            Ok(())
+        }
+        Err(e) => {
+            warn!("Failed to warm sandbox {}, returning it: {}", warm.id, e);
+            release(db, warm).await
+        }
+    }
+}
+
+/// Log `gh` in to the repo's host when its token is shared rather than per user, and clone the
+/// repo. Returns the authenticated host and the clone's directory.
+async fn prepare(
+    sbx_config: &serde_json::Value,
+    repo: Option<&str>,
+) -> Result<(Option<String>, Option<String>), String> {
+    let api_url = sbx_config["item"]["api_url"]
+        .as_str()
+        .ok_or("Missing api_url in borrowed item")?;
+    let sbx = sandbox_client::Client::new_with_client(api_url, http_client::client());
+
+    let location = repo
+        .map(github_host::resolve_repo)
+        .transpose()
+        .map_err(|e| e.to_string())?;
+    let host = match &location {
+        Some(location) => location.host.clone(),
+        None => github_host::find_host(None)?,
+    };
+
+    // Hosts with an IdP alias log in with the session owner's token once a session claims it
+    let mut authenticated_host = None;
+    if host.idp_alias.is_none() {
+        let token = github_host::resolve_token(&host, "").await?;
+        sandbox_gh_auth::login(&sbx, &host.hostname, &token).await?;
+        authenticated_host = Some(host.hostname.clone());
+    }
+
+    let mut repo_dir = None;
+    if let Some(location) = &location {
+        exec(
+            &sbx,
+            format!(
+                "rm -rf {dir} && git clone {url} {dir}",
+                dir = WARM_REPO_DIR,
+                url = location.clone_url()
+            ),
+        )
+        .await?;
+        repo_dir = Some(WARM_REPO_DIR.to_string());
+    }
+
+    Ok((authenticated_host, repo_dir))
+}
+
+async fn exec(sbx: &sandbox_client::Client, command: String) -> Result<(), String> {
+    let result = sbx
+        .exec_command_v1_shell_exec_post(&ShellExecRequest {
+            command,
+            async_mode: false,
+            id: None,
+            timeout: Some(300.0_f64),
+            exec_dir: Some(String::from("/home/gem")),
+        })
+        .await
+        .map_err(|e| e.to_string())?
+        .into_inner()
+        .data;
+
+    match result {
+        Some(result) if result.exit_code != Some(0) => Err(format!(
+            "exit code {:?}: {}",
+            result.exit_code,
+            result.output.unwrap_or_default().trim()
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Refresh the warm sandbox gauges
+async fn observe(db: &DatabaseConnection) -> Result<(), sea_orm::DbErr> {
+    let mut counts: BTreeMap<(String, &'static str), i64> = BTreeMap::new();
+    for warm in WarmSandbox::find().all(db).await? {
+        let status = match warm.status {
+            WarmSandboxStatus::Warming => "warming",
+            WarmSandboxStatus::Ready => "ready",
+        };
+        let pool = warm.repo.unwrap_or_else(|| GENERIC_POOL.to_string());
+        *counts.entry((pool, status)).or_default() += 1;
+    }
+
+    let gauge = &crate::metrics::get().warm_sandboxes;
+    gauge.reset();
+    for ((pool, status), count) in counts {
+        gauge.with_label_values(&[&pool, status]).set(count);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_warm_state_round_trip() {
+        let warm = warm_sandbox::Model {
+            id: uuid::Uuid::new_v4(),
+            repo: Some("acme/api".to_string()),
+            sbx_config: serde_json::json!({"item": {"api_url": "http://sbx"}, "borrow_token": "t"}),
+            status: WarmSandboxStatus::Ready,
+            authenticated_host: Some("github.com".to_string()),
+            repo_dir: Some(WARM_REPO_DIR.to_string()),
+            return_key: None,
+            created_at: Utc::now().into(),
+            expires_at: Utc::now().into(),
+        };
+        let sbx_config = session_sbx_config(warm);
+        assert_eq!(sbx_config["borrow_token"], "t");
+
+        let state = WarmState::from_sbx_config(&sbx_config);
+        assert!(state.is_authenticated("github.com"));
+        assert!(!state.is_authenticated("ghe.corp.example"));
+        assert_eq!(state.clone_of("acme/api"), Some(WARM_REPO_DIR));
+        assert_eq!(state.clone_of("acme/web"), None);
+
+        let on_demand = WarmState::from_sbx_config(&serde_json::json!({"item": {}}));
+        assert_eq!(on_demand, WarmState::default());
+    }
+}
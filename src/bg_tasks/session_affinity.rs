@@ -0,0 +1,195 @@
+//! Keeps the prompts of a session on the worker that ran its previous one.
+//!
+//! Every outbox publisher replica takes jobs from the same apalis queue, so successive prompts
+//! of a session could land on different hosts and lose the temp directories the last run left
+//! behind and the local process its PID refers to. A worker claims a session by writing its
+//! `WORKER_ID` to `session.worker_id` when it starts a run, and the claim lasts
+//! `SESSION_AFFINITY_TTL_SECS` after its last run. Another worker picking up a job of a claimed
+//! session puts it back on the queue a few seconds later instead of running it. After
+//! `MAX_DEFERRALS` deferrals it takes the session over, so a dead or busy owner cannot stall it.
+
+use apalis::prelude::Storage;
+use apalis_sql::postgres::PostgresStorage;
+use chrono::Utc;
+use sea_orm::sea_query::Expr;
+use sea_orm::{ColumnTrait, Condition, DatabaseConnection, DbErr, EntityTrait, QueryFilter};
+use std::time::Duration;
+use tracing::{info, warn};
+
+use super::outbox_publisher::OutboxJob;
+use crate::entities::prompt::Entity as Prompt;
+use crate::entities::session::{self, Entity as Session};
+
+/// How long a deferred job waits before it is offered to the workers again
+const DEFER_DELAY: Duration = Duration::from_secs(5);
+
+/// Deferrals after which a job's worker takes the session over from its owner
+const MAX_DEFERRALS: u64 = 12;
+
+/// Whether a job runs on this worker
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Dispatch {
+    /// Run it here; holds the claimed session, None when the prompt does not exist
+    Run(Option<uuid::Uuid>),
+    /// Another worker holds the session, and the job was put back on the queue
+    Deferred,
+}
+
+/// Outcome of a worker trying to claim a session
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Affinity {
+    Claimed,
+    /// Another worker ran the session recently
+    HeldBy(String),
+}
+
+pub fn worker_id() -> &'static str {
+    &crate::config::get().worker_id
+}
+
+fn deferrals(job: &OutboxJob) -> u64 {
+    job.payload.dispatch.affinity_deferrals
+}
+
+/// `job` with its deferral count incremented, keeping its correlation id
+fn deferred(job: &OutboxJob) -> OutboxJob {
+    let mut job = job.clone();
+    job.payload.dispatch.affinity_deferrals += 1;
+    job
+}
+
+/// Claim `session_id` for `worker`: unclaimed sessions, sessions it already holds and claims
+/// older than `ttl` are taken, or any claim when `take_over` is set
+pub async fn claim(
+    db: &DatabaseConnection,
+    session_id: uuid::Uuid,
+    worker: &str,
+    ttl: Duration,
+    take_over: bool,
+) -> Result<Affinity, DbErr> {
+    let now = Utc::now();
+    let mut update = Session::update_many()
+        .col_expr(session::Column::WorkerId, Expr::value(worker))
+        .col_expr(session::Column::WorkerClaimedAt, Expr::value(now))
+        .filter(session::Column::Id.eq(session_id));
+    if !take_over {
+        let lapsed_before = now - chrono::Duration::from_std(ttl).unwrap_or_default();
+        update = update.filter(
+            Condition::any()
+                .add(session::Column::WorkerId.is_null())
+                .add(session::Column::WorkerId.eq(worker))
+                .add(session::Column::WorkerClaimedAt.is_null())
+                .add(session::Column::WorkerClaimedAt.lt(lapsed_before)),
+        );
+    }
+    if update.exec(db).await?.rows_affected > 0 {
+        return Ok(Affinity::Claimed);
+    }
+
+    let holder = Session::find_by_id(session_id)
+        .one(db)
+        .await?
+        .and_then(|s| s.worker_id);
+    Ok(holder.map_or(Affinity::Claimed, Affinity::HeldBy))
+}
+
+/// Restart the claim's TTL once a run ends, so it counts from the last run rather than the
+/// first
+pub async fn touch(db: &DatabaseConnection, session_id: uuid::Uuid) {
+    let result = Session::update_many()
+        .col_expr(session::Column::WorkerClaimedAt, Expr::value(Utc::now()))
+        .filter(session::Column::Id.eq(session_id))
+        .filter(session::Column::WorkerId.eq(worker_id()))
+        .exec(db)
+        .await;
+    if let Err(e) = result {
+        warn!(
+            "Failed to refresh the worker claim of session {}: {}",
+            session_id, e
+        );
+    }
+}
+
+/// Claim the session of `prompt_id` for this worker before running `job`, or put the job back
+/// on the queue when another worker holds the session
+pub async fn acquire(
+    db: &DatabaseConnection,
+    storage: &PostgresStorage<OutboxJob>,
+    prompt_id: uuid::Uuid,
+    job: &OutboxJob,
+) -> Result<Dispatch, DbErr> {
+    // A missing prompt fails the run the usual way
+    let Some(prompt) = Prompt::find_by_id(prompt_id).one(db).await? else {
+        return Ok(Dispatch::Run(None));
+    };
+    let session_id = prompt.session_id;
+
+    let ttl = crate::config::get().session_affinity_ttl;
+    let take_over = ttl.is_zero() || deferrals(job) >= MAX_DEFERRALS;
+    let holder = match claim(db, session_id, worker_id(), ttl, take_over).await? {
+        Affinity::Claimed => return Ok(Dispatch::Run(Some(session_id))),
+        Affinity::HeldBy(holder) => holder,
+    };
+
+    let run_at = Utc::now() + chrono::Duration::from_std(DEFER_DELAY).unwrap_or_default();
+    match storage
+        .clone()
+        .schedule(deferred(job), run_at.timestamp())
+        .await
+    {
+        Ok(_) => {
+            info!(
+                "Session {} runs on worker {}, deferring prompt {} (deferral {}, correlation {})",
+                session_id,
+                holder,
+                prompt_id,
+                deferrals(job) + 1,
+                job.payload.correlation()
+            );
+            Ok(Dispatch::Deferred)
+        }
+        Err(e) => {
+            // Running on the wrong worker beats not running at all
+            warn!(
+                "Failed to defer prompt {} to worker {}, running it here: {}",
+                prompt_id, holder, e
+            );
+            claim(db, session_id, worker_id(), ttl, true).await?;
+            Ok(Dispatch::Run(Some(session_id)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bg_tasks::outbox_payload::OutboxPayload;
+
+    #[test]
+    fn test_deferral_count_round_trips_through_payload() {
+        let job = OutboxJob {
+            prompt_id: uuid::Uuid::new_v4().to_string(),
+            payload: OutboxPayload {
+                correlation_id: Some(uuid::Uuid::new_v4()),
+                ..Default::default()
+            },
+        };
+        assert_eq!(deferrals(&job), 0);
+
+        let again = deferred(&deferred(&job));
+        assert_eq!(again.prompt_id, job.prompt_id);
+        assert_eq!(deferrals(&again), 2);
+        assert_eq!(again.payload.correlation_id, job.payload.correlation_id);
+        assert_eq!(
+            serde_json::to_value(&again.payload).unwrap()["affinity_deferrals"],
+            2
+        );
+
+        let legacy: OutboxJob = serde_json::from_value(serde_json::json!({
+            "prompt_id": job.prompt_id,
+            "payload": null,
+        }))
+        .unwrap();
+        assert_eq!(deferrals(&deferred(&legacy)), 1);
+    }
+}
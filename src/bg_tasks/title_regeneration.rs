@@ -0,0 +1,51 @@
+use sea_orm::DatabaseConnection;
+use tracing::{error, info};
+
+use super::worker_registry;
+use crate::config;
+use crate::entities::operational_event::OperationalEventKind;
+use crate::services::operational_events;
+use crate::services::session_titles;
+
+/// Name of the loop in the worker registry
+const WORKER: &str = "title_regeneration";
+
+/// Sessions tried per pass, longest due first
+const SESSIONS_PER_PASS: u64 = 20;
+
+/// Periodic task that tries again to generate the titles and branches of sessions whose
+/// generation failed, see `services::session_titles`
+pub async fn run_title_regeneration(db: DatabaseConnection) -> anyhow::Result<()> {
+    let titles = &config::get().title_generation;
+    if titles.max_attempts == 0 {
+        info!("Title regeneration disabled");
+        return Ok(());
+    }
+    info!(
+        "Starting title regeneration - up to {} tries per session, checking every {} seconds",
+        titles.max_attempts,
+        titles.interval.as_secs()
+    );
+
+    worker_registry::register(WORKER, titles.interval);
+
+    loop {
+        tokio::time::sleep(titles.interval).await;
+
+        match session_titles::regenerate_due(&db, SESSIONS_PER_PASS).await {
+            Ok(named) => worker_registry::record_success(WORKER, named),
+            Err(e) => {
+                worker_registry::record_error(WORKER, &e);
+                operational_events::record(
+                    &db,
+                    WORKER,
+                    OperationalEventKind::Database,
+                    &e.to_string(),
+                    None,
+                )
+                .await;
+                error!("Title regeneration pass failed: {}", e);
+            }
+        }
+    }
+}
@@ -0,0 +1,111 @@
+//! Which backend process a session's CLI PID belongs to.
+//!
+//! A PID only names a process on the host that spawned it, and replicas share the session
+//! table, so the outbox publisher records this process's `instance_id()` in
+//! `session.worker_instance_id` next to `process_pid`, and the cancellation enforcer only
+//! signals PIDs of its own instance. Each instance reports to the `worker_instance` table on
+//! every enforcer iteration. One that has not reported for `WORKER_INSTANCE_STALE_SECS` is
+//! presumed dead: its processes died with it, so another instance takes its sessions over by
+//! clearing their PIDs, finishing any cancellation that was waiting on them.
+
+use chrono::{DateTime, Utc};
+use sea_orm::sea_query::OnConflict;
+use sea_orm::{ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter, Set};
+use std::collections::HashMap;
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use super::session_affinity;
+use crate::entities::worker_instance::{self, Entity as WorkerInstance};
+
+static INSTANCE_ID: LazyLock<String> = LazyLock::new(|| {
+    format!(
+        "{}/{}",
+        session_affinity::worker_id(),
+        &uuid::Uuid::new_v4().simple().to_string()[..8]
+    )
+});
+
+/// This process: its `WORKER_ID` plus a suffix that changes on every start, so a restarted
+/// replica never owns the PIDs of its previous run
+pub fn instance_id() -> &'static str {
+    &INSTANCE_ID
+}
+
+/// Record that this instance is alive
+pub async fn heartbeat(db: &DatabaseConnection) -> Result<(), DbErr> {
+    let now = Utc::now();
+    let model = worker_instance::ActiveModel {
+        id: Set(instance_id().to_string()),
+        worker_id: Set(session_affinity::worker_id().to_string()),
+        started_at: Set(now.into()),
+        last_seen_at: Set(now.into()),
+    };
+    WorkerInstance::insert(model)
+        .on_conflict(
+            OnConflict::column(worker_instance::Column::Id)
+                .update_column(worker_instance::Column::LastSeenAt)
+                .to_owned(),
+        )
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+/// When each known instance last reported
+pub async fn last_seen(db: &DatabaseConnection) -> Result<HashMap<String, DateTime<Utc>>, DbErr> {
+    Ok(WorkerInstance::find()
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|i| (i.id, i.last_seen_at.with_timezone(&Utc)))
+        .collect())
+}
+
+/// Forget instances that stopped reporting before `before`
+pub async fn prune(db: &DatabaseConnection, before: DateTime<Utc>) -> Result<u64, DbErr> {
+    Ok(WorkerInstance::delete_many()
+        .filter(worker_instance::Column::LastSeenAt.lt(before))
+        .exec(db)
+        .await?
+        .rows_affected)
+}
+
+/// Whether a PID recorded by `instance` has no live owner: the instance is unknown, was not
+/// recorded at all (PIDs from before instances were tracked), or last reported more than
+/// `stale_after` ago
+pub fn is_orphaned(
+    instance: Option<&str>,
+    last_seen: &HashMap<String, DateTime<Utc>>,
+    now: DateTime<Utc>,
+    stale_after: Duration,
+) -> bool {
+    let Some(seen) = instance.and_then(|id| last_seen.get(id)) else {
+        return true;
+    };
+    now.signed_duration_since(*seen)
+        .to_std()
+        .unwrap_or_default()
+        > stale_after
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_orphaned() {
+        let now = Utc::now();
+        let stale_after = Duration::from_secs(60);
+        let last_seen = HashMap::from([
+            ("live/1".to_string(), now - chrono::Duration::seconds(5)),
+            ("dead/2".to_string(), now - chrono::Duration::seconds(90)),
+        ]);
+
+        assert!(!is_orphaned(Some("live/1"), &last_seen, now, stale_after));
+        assert!(is_orphaned(Some("dead/2"), &last_seen, now, stale_after));
+        assert!(is_orphaned(Some("gone/3"), &last_seen, now, stale_after));
+        assert!(is_orphaned(None, &last_seen, now, stale_after));
+        assert!(instance_id().starts_with(session_affinity::worker_id()));
+    }
+}
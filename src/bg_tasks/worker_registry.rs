@@ -0,0 +1,153 @@
+//! In-process status of the background loops (prompt poller, IP return poller, cancellation
+//! enforcer, integrity checker), reported after every iteration.
+//!
+//! Pausable pollers also report whether their last check found them paused, see
+//! `services::poller_control`.
+
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+
+/// A loop is stale once this many of its intervals pass without a successful iteration
+const STALE_AFTER_INTERVALS: u32 = 5;
+
+/// Extra allowance on top of the intervals, so fast loops survive a slow database round trip
+const STALE_GRACE: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone)]
+pub struct WorkerStatus {
+    pub name: &'static str,
+    /// How often the loop runs
+    pub interval: Duration,
+    pub started_at: DateTime<Utc>,
+    pub last_success_at: Option<DateTime<Utc>>,
+    /// Items handled across all iterations
+    pub items_processed: u64,
+    pub iterations: u64,
+    pub last_error: Option<String>,
+    pub last_error_at: Option<DateTime<Utc>>,
+    /// Whether the loop skipped its last iteration because it is paused
+    pub paused: bool,
+}
+
+impl WorkerStatus {
+    /// Whether the loop has gone too long without a successful iteration. Paused loops are
+    /// idle on purpose and never stale.
+    pub fn is_stale(&self, now: DateTime<Utc>) -> bool {
+        if self.paused {
+            return false;
+        }
+        let since = self.last_success_at.unwrap_or(self.started_at);
+        let allowed = self.interval * STALE_AFTER_INTERVALS + STALE_GRACE;
+        (now - since).to_std().unwrap_or_default() > allowed
+    }
+}
+
+static REGISTRY: LazyLock<Mutex<BTreeMap<&'static str, WorkerStatus>>> =
+    LazyLock::new(|| Mutex::new(BTreeMap::new()));
+
+fn update(name: &'static str, f: impl FnOnce(&mut WorkerStatus)) {
+    let mut registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(status) = registry.get_mut(name) {
+        f(status);
+    }
+}
+
+/// Start tracking a loop that runs every `interval`
+pub fn register(name: &'static str, interval: Duration) {
+    let mut registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+    registry.insert(
+        name,
+        WorkerStatus {
+            name,
+            interval,
+            started_at: Utc::now(),
+            last_success_at: None,
+            items_processed: 0,
+            iterations: 0,
+            last_error: None,
+            last_error_at: None,
+            paused: false,
+        },
+    );
+}
+
+/// Record a successful iteration that handled `items` items
+pub fn record_success(name: &'static str, items: u64) {
+    update(name, |status| {
+        status.last_success_at = Some(Utc::now());
+        status.items_processed += items;
+        status.iterations += 1;
+    });
+}
+
+/// Record a failed iteration
+pub fn record_error(name: &'static str, error: &dyn std::fmt::Display) {
+    update(name, |status| {
+        status.last_error = Some(error.to_string());
+        status.last_error_at = Some(Utc::now());
+        status.iterations += 1;
+    });
+}
+
+/// Record whether the loop found itself paused at the start of an iteration
+pub fn record_paused(name: &'static str, paused: bool) {
+    update(name, |status| {
+        // Resuming restarts the staleness clock instead of counting the pause against it
+        if status.paused && !paused {
+            status.last_success_at = Some(Utc::now());
+        }
+        status.paused = paused;
+    });
+}
+
+/// Status of every registered loop, by name
+pub fn snapshot() -> Vec<WorkerStatus> {
+    let registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+    registry.values().cloned().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_worker_goes_stale_without_success() {
+        let started_at = Utc::now() - chrono::Duration::seconds(60);
+        let mut status = WorkerStatus {
+            name: "prompt_poller",
+            interval: Duration::from_secs(1),
+            started_at,
+            last_success_at: None,
+            items_processed: 0,
+            iterations: 0,
+            last_error: None,
+            last_error_at: None,
+            paused: false,
+        };
+        assert!(status.is_stale(Utc::now()));
+
+        status.paused = true;
+        assert!(!status.is_stale(Utc::now()));
+
+        status.paused = false;
+        status.last_success_at = Some(Utc::now());
+        assert!(!status.is_stale(Utc::now()));
+    }
+
+    #[test]
+    fn test_registry_records_iterations() {
+        register("test_worker", Duration::from_secs(1));
+        record_success("test_worker", 3);
+        record_error("test_worker", &"boom");
+
+        let status = snapshot()
+            .into_iter()
+            .find(|s| s.name == "test_worker")
+            .unwrap();
+        assert_eq!(status.items_processed, 3);
+        assert_eq!(status.iterations, 2);
+        assert_eq!(status.last_error.as_deref(), Some("boom"));
+    }
+}
@@ -0,0 +1,185 @@
+//! `prompt-backend dlq` subcommands, for on-call triage from a shell in the pod.
+//!
+//! They go straight to the database through the same service functions as the
+//! `/dead-letter-queue` endpoints, so they keep working while the API is down. Output is a
+//! table for people, or with `--json` the same DTOs the API returns.
+
+use clap::Subcommand;
+use sea_orm::{ActiveEnum, DatabaseConnection, DbErr};
+use uuid::Uuid;
+
+use crate::entities::dead_letter_queue::{DlqStatus, Model as DlqModel};
+use crate::handlers::dead_letter_queue::DlqDto;
+use crate::services::dead_letter_queue;
+
+/// Widest `last_error` shown in the list table
+const ERROR_COLUMN_WIDTH: usize = 60;
+
+#[derive(Subcommand, PartialEq)]
+pub enum DlqCommand {
+    /// List entries, newest first
+    List {
+        /// Only entries with this status: pending, resolved or abandoned
+        #[arg(long, value_parser = parse_status)]
+        status: Option<DlqStatus>,
+    },
+    /// Show one entry with its stored payload
+    Show { id: Uuid },
+    /// Give the failed task a fresh set of attempts and resolve the entry
+    Retry {
+        id: Uuid,
+        #[arg(long)]
+        notes: Option<String>,
+    },
+    /// Mark the entry resolved without retrying it
+    Resolve {
+        id: Uuid,
+        #[arg(long)]
+        notes: Option<String>,
+    },
+}
+
+fn parse_status(value: &str) -> Result<DlqStatus, String> {
+    DlqStatus::try_from_value(&value.to_lowercase())
+        .map_err(|_| format!("unknown status {:?}", value))
+}
+
+fn print_json(value: &impl serde::Serialize) -> anyhow::Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+fn truncate(text: &str, width: usize) -> String {
+    let line = text.lines().next().unwrap_or_default();
+    if line.chars().count() <= width {
+        line.to_string()
+    } else {
+        let cut: String = line.chars().take(width - 1).collect();
+        format!("{}…", cut)
+    }
+}
+
+fn format_table(entries: &[DlqModel]) -> String {
+    let mut table = format!(
+        "{:<36}  {:<9}  {:<16}  {:<36}  {:>7}  {:<19}  {}\n",
+        "ID", "STATUS", "TASK TYPE", "ENTITY", "RETRIES", "CREATED", "LAST ERROR"
+    );
+    for entry in entries {
+        table.push_str(&format!(
+            "{:<36}  {:<9}  {:<16}  {:<36}  {:>7}  {:<19}  {}\n",
+            entry.id,
+            entry.status.to_value(),
+            entry.task_type,
+            entry.entity_id,
+            entry.retry_count,
+            entry.created_at.format("%Y-%m-%d %H:%M:%S"),
+            truncate(&entry.last_error, ERROR_COLUMN_WIDTH),
+        ));
+    }
+    table
+}
+
+fn format_entry(entry: &DlqModel) -> String {
+    let payload = entry
+        .entity_data
+        .as_ref()
+        .and_then(|data| serde_json::to_string_pretty(data).ok())
+        .unwrap_or_else(|| "(none)".to_string());
+    format!(
+        "ID:               {}\n\
+         Status:           {}\n\
+         Task type:        {}\n\
+         Entity:           {}\n\
+         Retries:          {}\n\
+         First failed at:  {}\n\
+         Last error at:    {}\n\
+         Created at:       {}\n\
+         Updated at:       {}\n\
+         Resolution notes: {}\n\
+         Last error:\n{}\n\
+         Payload:\n{}\n",
+        entry.id,
+        entry.status.to_value(),
+        entry.task_type,
+        entry.entity_id,
+        entry.retry_count,
+        entry.first_failed_at,
+        entry.last_error_at,
+        entry.created_at,
+        entry.updated_at,
+        entry.resolution_notes.as_deref().unwrap_or("-"),
+        entry.last_error,
+        payload,
+    )
+}
+
+async fn find(db: &DatabaseConnection, id: Uuid) -> anyhow::Result<DlqModel> {
+    dead_letter_queue::find_entry(db, id)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("DLQ entry not found: {}", id))
+}
+
+pub async fn run_dlq(
+    db: &DatabaseConnection,
+    command: DlqCommand,
+    json: bool,
+) -> anyhow::Result<()> {
+    match command {
+        DlqCommand::List { status } => {
+            let entries = dead_letter_queue::list_entries(db, status).await?;
+            if json {
+                let dtos: Vec<DlqDto> = entries.into_iter().map(DlqDto::from).collect();
+                print_json(&dtos)
+            } else {
+                print!("{}", format_table(&entries));
+                Ok(())
+            }
+        }
+        DlqCommand::Show { id } => {
+            let entry = find(db, id).await?;
+            if json {
+                print_json(&DlqDto::from(entry))
+            } else {
+                print!("{}", format_entry(&entry));
+                Ok(())
+            }
+        }
+        DlqCommand::Retry { id, notes } => {
+            let entry = dead_letter_queue::retry_dlq_entry(db, id, notes).await?;
+            if json {
+                print_json(&DlqDto::from(entry))
+            } else {
+                println!("DLQ entry {} retried", id);
+                Ok(())
+            }
+        }
+        DlqCommand::Resolve { id, notes } => {
+            let entry = dead_letter_queue::resolve_dlq_entry(db, id, notes)
+                .await
+                .map_err(|e| match e {
+                    DbErr::RecordNotFound(_) => anyhow::anyhow!("DLQ entry not found: {}", id),
+                    e => e.into(),
+                })?;
+            if json {
+                print_json(&DlqDto::from(entry))
+            } else {
+                println!("DLQ entry {} marked as resolved", id);
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_status_and_truncate() {
+        assert_eq!(parse_status("Pending"), Ok(DlqStatus::Pending));
+        assert!(parse_status("stuck").is_err());
+
+        assert_eq!(truncate("short\nsecond line", 10), "short");
+        assert_eq!(truncate("connection refused", 10), "connectio…");
+    }
+}
@@ -0,0 +1,1025 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use sea_orm::Iterable;
+
+use crate::entities::session::ClaudeModel;
+use crate::services::ingestion_filter::{IngestionAction, IngestionRules};
+
+/// Service configuration, read once from the environment
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub request_limits: RequestLimits,
+    /// What to do when a new session targets a repo and branch another active session is
+    /// working on, from `REPO_LOCK_MODE` (`off`, `warn` or `block`)
+    pub repo_lock: RepoLockMode,
+    /// How prompt data in a shape older than the conversation schema is handled, from
+    /// `PROMPT_LEGACY_DATA` (`normalize` or `reject`)
+    pub legacy_prompt_data: LegacyPromptData,
+    /// Sandbox directories collected into object storage when a prompt finishes, from the
+    /// comma-separated `SANDBOX_ARTIFACT_PATHS`
+    pub artifact_paths: Vec<String>,
+    /// Files larger than this are skipped during collection, from `SANDBOX_ARTIFACT_MAX_BYTES`
+    pub artifact_max_bytes: i64,
+    /// Domains sandboxes may always reach when their session has an egress policy, besides the
+    /// repo's GitHub host, from the comma-separated `SANDBOX_EGRESS_ALWAYS_ALLOW`
+    pub egress_always_allowed: Vec<String>,
+    pub pricing: Pricing,
+    pub models: ModelPolicy,
+    /// Monthly spend allowed per user in USD, from `USER_MONTHLY_BUDGET_USD`; None for no limit
+    pub user_monthly_budget_usd: Option<f64>,
+    /// Static-token authentication for local development, None unless `DEV_AUTH_MODE` is on
+    pub dev_auth: Option<DevAuth>,
+    pub http: HttpClientConfig,
+    /// Readiness reports degraded once a pending DLQ entry is older than this, from
+    /// `DLQ_ALERT_AGE_SECS` (default 3600)
+    pub dlq_alert_age: Duration,
+    pub db_pool: DbPoolConfig,
+    /// Base URL sandboxes use to reach this server, from `AGENT_CALLBACK_URL`. Runs are only
+    /// issued an agent token for the `/internal/sessions/<id>/...` endpoints when it is set.
+    pub agent_callback_url: Option<String>,
+    /// How long an agent token is accepted after its run starts, from `AGENT_TOKEN_TTL_SECS`
+    /// (default 21600); it is revoked earlier when the run ends
+    pub agent_token_ttl: Duration,
+    pub uploads: UploadConfig,
+    /// JWT claim naming the user's organization, from `ORG_CLAIM` (default `organization`).
+    /// Users whose token lacks it keep strictly per-user access.
+    pub org_claim: String,
+    /// How long a soft-cancelled run may keep going to commit its work before its process is
+    /// terminated, from `SOFT_CANCEL_GRACE_SECS` (default 120)
+    pub soft_cancel_grace: Duration,
+    pub fan_out: FanOutConfig,
+    /// How long an `Idempotency-Key` replays its first response, from
+    /// `IDEMPOTENCY_KEY_TTL_SECS` (default 86400)
+    pub idempotency_key_ttl: Duration,
+    /// A prompt identical to one submitted to the same session this recently is flagged as a
+    /// likely duplicate, from `DUPLICATE_PROMPT_WINDOW_SECS` (default 60; 0 turns it off)
+    pub duplicate_prompt_window: Duration,
+    /// Message content older than this is purged, from `MESSAGE_RETENTION_DAYS`; None keeps
+    /// it forever
+    pub message_retention: Option<Duration>,
+    /// How often the data retention task runs, from `DATA_RETENTION_INTERVAL_SECS` (default
+    /// 300)
+    pub data_retention_interval: Duration,
+    /// Bearer token accepted by `GET /internal/queue-stats` besides an admin login, from
+    /// `QUEUE_STATS_TOKEN`, so autoscalers can poll it without a user account
+    pub queue_stats_token: Option<String>,
+    /// Secret `POST /webhook/keycloak` expects in `X-Webhook-Secret`, from
+    /// `KEYCLOAK_WEBHOOK_SECRET`; the webhook is off when unset
+    pub keycloak_webhook_secret: Option<String>,
+    pub pull_requests: PullRequestConfig,
+    pub prewarm: PrewarmConfig,
+    /// Sandbox regions sessions may ask for, from the comma-separated `SANDBOX_REGIONS`, e.g.
+    /// `us-east,eu-west`; any well-formed region name is passed on when empty
+    pub sandbox_regions: Vec<String>,
+    /// Identity this process claims sessions under, from `WORKER_ID`, defaulting to the host
+    /// name so a restarted replica keeps its sessions
+    pub worker_id: String,
+    /// How long after its last run a session stays with the worker that ran it, from
+    /// `SESSION_AFFINITY_TTL_SECS` (default 1800; 0 turns affinity off)
+    pub session_affinity_ttl: Duration,
+    pub message_writer: MessageWriterConfig,
+    /// Whether pushes are scanned for likely secrets, see `services::secret_scan`, from
+    /// `SECRET_SCAN_ENABLED` (default true)
+    pub secret_scan: bool,
+    pub session_budget: SessionBudgetConfig,
+    /// Whether each run's CLI output is also copied to a file in its sandbox, see
+    /// `services::output_log`, from `OUTPUT_LOG_ENABLED` (default true)
+    pub output_log: bool,
+    pub cancellation: CancellationConfig,
+    pub message_quota: MessageQuotaConfig,
+    pub status_display: StatusDisplayConfig,
+    pub title_generation: TitleGenerationConfig,
+    pub workspace_disk: WorkspaceDiskConfig,
+    pub message_stream: MessageStreamConfig,
+    pub grpc: GrpcConfig,
+    pub operational_events: OperationalEventsConfig,
+    /// Actions on CLI messages by event kind before they are stored, see
+    /// `services::ingestion_filter`, from `MESSAGE_INGESTION_FILTER`, e.g.
+    /// `stream_event=drop,system:status=aggregate` (default: none)
+    pub ingestion_filter: IngestionRules,
+}
+
+/// Soft limits on a session's stored messages, see `services::message_compaction`
+#[derive(Debug, Clone)]
+pub struct MessageQuotaConfig {
+    /// Messages a session may store before its older tool results are compacted, from
+    /// `SESSION_MESSAGE_SOFT_LIMIT`; None (unset or 0) for no limit
+    pub max_messages: Option<u64>,
+    /// Bytes of message payloads a session may store in the database before its older tool
+    /// results are compacted, from `SESSION_MESSAGE_SOFT_LIMIT_BYTES`; None (unset or 0) for
+    /// no limit
+    pub max_bytes: Option<u64>,
+    /// A session's most recent messages, which are never compacted, from
+    /// `MESSAGE_COMPACTION_KEEP_RECENT` (default 1000)
+    pub keep_recent: u64,
+    /// Characters kept of each compacted tool result, from `MESSAGE_COMPACTION_PREVIEW_CHARS`
+    /// (default 2000)
+    pub preview_chars: usize,
+    /// How often sessions over a limit are looked for, from `MESSAGE_COMPACTION_INTERVAL_SECS`
+    /// (default 600)
+    pub interval: Duration,
+}
+
+impl MessageQuotaConfig {
+    pub fn enabled(&self) -> bool {
+        self.max_messages.is_some() || self.max_bytes.is_some()
+    }
+}
+
+/// Retries of session title and branch generation, see `services::session_titles`
+#[derive(Debug, Clone)]
+pub struct TitleGenerationConfig {
+    /// Retries of a rate limited or overloaded Anthropic API call made by
+    /// `bg_tasks::title_regeneration`, from `ANTHROPIC_MAX_RETRIES` (default 3)
+    pub api_max_retries: u32,
+    /// Longest an Anthropic API call may take, retries included, from `ANTHROPIC_TIMEOUT_SECS`
+    /// (default 60)
+    pub api_timeout: Duration,
+    /// Times a session whose generation failed is given another try later, from
+    /// `TITLE_REGENERATION_MAX_ATTEMPTS` (default 5); 0 keeps the placeholders at once
+    pub max_attempts: u32,
+    /// How often sessions due another try are looked for, from
+    /// `TITLE_REGENERATION_INTERVAL_SECS` (default 60)
+    pub interval: Duration,
+}
+
+/// Overrides of the status labels and colors served by `GET /meta/statuses`, keyed
+/// `<Enum>.<Value>`, see `services::status_meta`
+#[derive(Debug, Clone)]
+pub struct StatusDisplayConfig {
+    /// From `STATUS_LABELS`, e.g. `UiStatus.NeedsReview=Ready for review`
+    pub labels: HashMap<String, String>,
+    /// From `STATUS_COLORS`, e.g. `UiStatus.NeedsReview=#f59e0b`
+    pub colors: HashMap<String, String>,
+}
+
+/// Parse `kind=action` ingestion filter rules, skipping unknown actions
+fn parse_ingestion_filter(value: &str) -> IngestionRules {
+    parse_overrides(value)
+        .into_iter()
+        .filter_map(|(kind, action)| match action.parse::<IngestionAction>() {
+            Ok(action) => Some((kind, action)),
+            Err(e) => {
+                tracing::warn!("Ignoring MESSAGE_INGESTION_FILTER rule for {}: {}", kind, e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Parse `key=value` pairs, skipping entries without a key or value
+fn parse_overrides(value: &str) -> HashMap<String, String> {
+    parse_list(value)
+        .iter()
+        .filter_map(|entry| {
+            let (key, value) = entry.split_once('=')?;
+            let (key, value) = (key.trim(), value.trim());
+            (!key.is_empty() && !value.is_empty()).then(|| (key.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+/// How cancelled sessions' CLI processes are stopped, see `bg_tasks::cancellation_enforcer`
+#[derive(Debug, Clone)]
+pub struct CancellationConfig {
+    /// How often cancelled sessions are looked for, from `CANCELLATION_ENFORCER_INTERVAL_SECS`
+    /// (default 2)
+    pub interval: Duration,
+    /// Up to this much is added at random to each wait so replicas do not query at once, from
+    /// `CANCELLATION_ENFORCER_JITTER_MS` (default 500)
+    pub jitter: Duration,
+    /// Signals sent to the CLI in turn while it keeps running, from `CANCELLATION_KILL_SIGNALS`:
+    /// `SIGNAL[:delay_secs]` entries, the delay counted from the signal before; the first is
+    /// sent at once (default `TERM,KILL:10`)
+    pub signals: Vec<KillSignal>,
+    /// An instance that has not reported for this long is presumed dead and its sessions'
+    /// PIDs are taken over, see `bg_tasks::worker_instance`, from `WORKER_INSTANCE_STALE_SECS`
+    /// (default 60)
+    pub instance_stale_after: Duration,
+}
+
+/// One step of stopping a process
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KillSignal {
+    /// Name as `kill` takes it, e.g. `TERM`
+    pub name: String,
+    /// Wait after the previous signal
+    pub after: Duration,
+}
+
+const DEFAULT_KILL_SIGNALS: &str = "TERM,KILL:10";
+
+/// Parse `SIGNAL[:delay_secs]` entries, dropping invalid ones; the default when none is valid
+fn parse_kill_signals(value: &str) -> Vec<KillSignal> {
+    let signals: Vec<KillSignal> = parse_list(value)
+        .iter()
+        .filter_map(|entry| {
+            let (name, after) = match entry.split_once(':') {
+                Some((name, secs)) => (name, Duration::from_secs(secs.trim().parse().ok()?)),
+                None => (entry.as_str(), Duration::ZERO),
+            };
+            let name = name.trim().to_ascii_uppercase();
+            let name = name.strip_prefix("SIG").unwrap_or(&name);
+            (!name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric())).then(|| {
+                KillSignal {
+                    name: name.to_string(),
+                    after,
+                }
+            })
+        })
+        .collect();
+    if signals.is_empty() && value != DEFAULT_KILL_SIGNALS {
+        return parse_kill_signals(DEFAULT_KILL_SIGNALS);
+    }
+    signals
+}
+
+/// Platform limits on a session's runs, see `services::session_budget`
+#[derive(Debug, Clone)]
+pub struct SessionBudgetConfig {
+    /// Run time a session may use across its prompts unless it sets its own, from
+    /// `SESSION_BUDGET_MAX_WALL_CLOCK_SECS`; None (unset or 0) for no limit
+    pub max_wall_clock_secs: Option<u64>,
+    /// Messages a session may produce unless it sets its own, from
+    /// `SESSION_BUDGET_MAX_MESSAGES`; None (unset or 0) for no limit
+    pub max_messages: Option<u64>,
+    /// Tokens a session may use unless it sets its own, from `SESSION_BUDGET_MAX_TOKENS`; None
+    /// (unset or 0) for no limit
+    pub max_tokens: Option<u64>,
+    /// How often a running job checks its session's budget, from
+    /// `SESSION_BUDGET_CHECK_INTERVAL_SECS` (default 10)
+    pub check_interval: Duration,
+    /// How long the CLI has to exit after SIGTERM before it is killed, from
+    /// `SESSION_BUDGET_KILL_GRACE_SECS` (default 30)
+    pub kill_grace: Duration,
+}
+
+/// Server-sent event streams of messages, see `services::message_stream`
+#[derive(Debug, Clone)]
+pub struct MessageStreamConfig {
+    /// How often a keep-alive comment is sent while there is nothing new, from
+    /// `SSE_KEEP_ALIVE_SECS` (default 15)
+    pub keep_alive: Duration,
+    /// How long a stream stays open before it ends for the client to reconnect, from
+    /// `SSE_MAX_CONNECTION_SECS` (default 3600)
+    pub max_connection: Duration,
+    /// How often a stream looks for new messages, from `SSE_POLL_INTERVAL_MS` (default 1000)
+    pub poll_interval: Duration,
+}
+
+/// gRPC server next to the REST API, see `grpc`
+#[derive(Debug, Clone)]
+pub struct GrpcConfig {
+    /// Serve gRPC, from `GRPC_ENABLED` (default false); `--grpc` turns it on as well. Only
+    /// binaries built with the `grpc` feature can.
+    pub enabled: bool,
+    /// From `GRPC_PORT` (default 50051)
+    #[cfg_attr(not(feature = "grpc"), allow(dead_code))]
+    pub port: u16,
+}
+
+/// Stored errors of background loops and workers, see `services::operational_events`
+#[derive(Debug, Clone)]
+pub struct OperationalEventsConfig {
+    /// A repeat of an error seen this recently is counted on its row instead of adding one,
+    /// from `OPERATIONAL_EVENT_MERGE_WINDOW_SECS` (default 3600)
+    pub merge_window: Duration,
+    /// Rows not seen for this long are deleted by the data retention loop, from
+    /// `OPERATIONAL_EVENT_RETENTION_DAYS` (default 30)
+    pub retention: Duration,
+}
+
+/// Disk usage of a run's clone in its sandbox, see `services::workspace_disk`
+#[derive(Debug, Clone)]
+pub struct WorkspaceDiskConfig {
+    /// Gigabytes the clone may use before the run is stopped, from `WORKSPACE_DISK_LIMIT_GB`;
+    /// None (unset or 0) for no limit
+    pub max_gb: Option<u64>,
+    /// How often a running job measures the clone, from `WORKSPACE_DISK_CHECK_INTERVAL_SECS`
+    /// (default 60)
+    pub check_interval: Duration,
+}
+
+/// Batching of CLI output into the message table, see `bg_tasks::message_writer`
+#[derive(Debug, Clone)]
+pub struct MessageWriterConfig {
+    /// Messages written in one insert, from `MESSAGE_BATCH_SIZE` (default 50)
+    pub batch_size: usize,
+    /// Longest a message waits for its batch to fill, from `MESSAGE_BATCH_INTERVAL_MS`
+    /// (default 200)
+    pub batch_interval: Duration,
+    /// Messages a run may have read but not yet written before its reader waits, from
+    /// `MESSAGE_BUFFER_CAPACITY` (default 1000)
+    pub buffer_capacity: usize,
+}
+
+/// Sandboxes borrowed ahead of demand, see `bg_tasks::sandbox_prewarm`
+#[derive(Debug, Clone)]
+pub struct PrewarmConfig {
+    /// Sandboxes kept borrowed and authenticated for any repo, from `SANDBOX_PREWARM_COUNT`
+    /// (default 0)
+    pub generic: usize,
+    /// Sandboxes kept with a clone of a hot repo, from `SANDBOX_PREWARM_REPOS`, e.g.
+    /// `acme/api=3,acme/web=1`
+    pub repos: Vec<(String, usize)>,
+    /// How long an unclaimed sandbox is kept before it is returned to the allocator, from
+    /// `SANDBOX_PREWARM_TTL_SECS` (default 1800)
+    pub ttl: Duration,
+}
+
+impl PrewarmConfig {
+    pub fn enabled(&self) -> bool {
+        self.generic > 0 || !self.repos.is_empty()
+    }
+}
+
+fn parse_prewarm_repos(value: &str) -> Vec<(String, usize)> {
+    parse_list(value)
+        .iter()
+        .filter_map(|entry| {
+            let (repo, count) = entry.split_once('=')?;
+            let count: usize = count.trim().parse().ok()?;
+            (count > 0 && !repo.trim().is_empty()).then(|| (repo.trim().to_string(), count))
+        })
+        .collect()
+}
+
+/// Descriptions generated for session pull requests, see `services::pr_description`
+#[derive(Debug, Clone)]
+pub struct PullRequestConfig {
+    /// Whether a session's pull request gets a generated description once it is opened, from
+    /// `PR_DESCRIPTION_ENABLED` (default true); `POST /sessions/<id>/pull-request/description`
+    /// works either way
+    pub describe: bool,
+    /// File with the description template, from `PR_DESCRIPTION_TEMPLATE_FILE`; read on each
+    /// use so it can change without a restart. None uses the built-in template.
+    pub template_file: Option<String>,
+    /// Link to a session in the UI with `{session_id}` in place of its id, from
+    /// `SESSION_URL_TEMPLATE`
+    pub session_url_template: Option<String>,
+}
+
+/// Limits on `POST /sessions/fan-out`, see `services::fan_out`
+#[derive(Debug, Clone)]
+pub struct FanOutConfig {
+    /// Most repos one fan-out may target, from `FAN_OUT_MAX_REPOS` (default 50)
+    pub max_repos: usize,
+    /// Child sessions of a fan-out that may hold a sandbox at once when the request does not
+    /// say, from `FAN_OUT_CONCURRENCY` (default 5)
+    pub default_concurrency: u32,
+}
+
+/// Settings for files users upload into a session's sandbox, see `services::session_uploads`
+#[derive(Debug, Clone)]
+pub struct UploadConfig {
+    /// Largest file accepted, from `UPLOAD_MAX_BYTES` (default 100 MiB)
+    pub max_bytes: u64,
+    /// Sandbox directory files are written to, under a directory per session, from `UPLOAD_DIR`
+    pub dir: String,
+    /// Sandbox directory prompt attachments are copied to before a run, under a directory per
+    /// attachment, from `ATTACHMENT_DIR` (default /home/gem/attachments)
+    pub attachment_dir: String,
+    /// Command run in the sandbox with the uploaded file's path appended, from
+    /// `UPLOAD_SCAN_COMMAND`; a nonzero exit deletes the file and rejects the upload
+    pub scan_command: Option<String>,
+}
+
+/// Settings for every database connection pool, see `db::establish_connection`
+#[derive(Debug, Clone)]
+pub struct DbPoolConfig {
+    /// Connections per pool, from `DB_MAX_CONNECTIONS` (default 10)
+    pub max_connections: u32,
+    /// Connections kept open when idle, from `DB_MIN_CONNECTIONS` (default 0)
+    pub min_connections: u32,
+    /// From `DB_CONNECT_TIMEOUT_SECS` (default 10)
+    pub connect_timeout: Duration,
+    /// How long a query waits for a free connection, from `DB_ACQUIRE_TIMEOUT_SECS` (default 30)
+    pub acquire_timeout: Duration,
+    /// Server-side `statement_timeout`, from `DB_STATEMENT_TIMEOUT_MS`; 0 (the default) for none
+    pub statement_timeout_ms: u64,
+    /// Queries slower than this are logged, from `DB_SLOW_QUERY_MS` (default 500); 0 disables
+    pub slow_query_ms: u64,
+}
+
+/// Settings for every outbound HTTP client, see `services::http_client`
+#[derive(Debug, Clone)]
+pub struct HttpClientConfig {
+    /// PEM file of extra root certificates to trust, from `HTTP_CA_BUNDLE`
+    pub ca_bundle: Option<String>,
+    /// From `HTTP_CONNECT_TIMEOUT_SECS` (default 15)
+    pub connect_timeout: Duration,
+    /// Whole-request timeout, from `HTTP_TIMEOUT_SECS` (default 60)
+    pub timeout: Duration,
+    /// Retries after a connection failure, timeout or 502/503/504, from `HTTP_MAX_RETRIES`
+    /// (default 2)
+    pub max_retries: u32,
+}
+
+/// A fixed bearer token accepted in place of a Keycloak JWT, mapping to one dev user
+#[derive(Debug, Clone)]
+pub struct DevAuth {
+    /// Token expected after `Bearer `, from `DEV_AUTH_TOKEN` (required)
+    pub token: String,
+    /// From `DEV_AUTH_USER_ID` (default: dev-user)
+    pub user_id: String,
+    /// From `DEV_AUTH_EMAIL`
+    pub email: Option<String>,
+    /// From `DEV_AUTH_NAME`
+    pub name: Option<String>,
+    /// From the comma-separated `DEV_AUTH_ROLES` (default: none)
+    pub roles: Vec<String>,
+    /// From `DEV_AUTH_ORG_ID`
+    pub org_id: Option<String>,
+}
+
+impl DevAuth {
+    /// Enabled with `DEV_AUTH_MODE=true` and a `DEV_AUTH_TOKEN`. Refuses to enable when
+    /// `APP_ENV` is `production`.
+    fn from_env() -> Option<Self> {
+        if !env_or("DEV_AUTH_MODE", false) {
+            return None;
+        }
+        if is_production(std::env::var("APP_ENV").ok().as_deref()) {
+            tracing::error!("DEV_AUTH_MODE is ignored because APP_ENV is production");
+            return None;
+        }
+        let Some(token) = std::env::var("DEV_AUTH_TOKEN")
+            .ok()
+            .filter(|token| !token.trim().is_empty())
+        else {
+            tracing::error!("DEV_AUTH_MODE is ignored because DEV_AUTH_TOKEN is not set");
+            return None;
+        };
+
+        Some(DevAuth {
+            token,
+            user_id: std::env::var("DEV_AUTH_USER_ID").unwrap_or_else(|_| "dev-user".to_string()),
+            email: std::env::var("DEV_AUTH_EMAIL").ok(),
+            name: std::env::var("DEV_AUTH_NAME").ok(),
+            roles: parse_list(&std::env::var("DEV_AUTH_ROLES").unwrap_or_default()),
+            org_id: std::env::var("DEV_AUTH_ORG_ID")
+                .ok()
+                .filter(|org| !org.trim().is_empty()),
+        })
+    }
+}
+
+pub fn is_production(app_env: Option<&str>) -> bool {
+    app_env.is_some_and(|env| env.eq_ignore_ascii_case("production") || env == "prod")
+}
+
+/// USD price per million tokens for one model
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModelPrice {
+    pub input_per_mtok: f64,
+    pub output_per_mtok: f64,
+}
+
+impl FromStr for ModelPrice {
+    type Err = String;
+
+    /// Parse `input:output`, e.g. `3:15`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (input, output) = s
+            .split_once(':')
+            .ok_or_else(|| format!("Expected input:output price, got {}", s))?;
+        let parse = |v: &str| {
+            v.trim()
+                .parse::<f64>()
+                .map_err(|_| format!("Invalid price: {}", v))
+        };
+        Ok(ModelPrice {
+            input_per_mtok: parse(input)?,
+            output_per_mtok: parse(output)?,
+        })
+    }
+}
+
+/// Model pricing used for cost estimates
+#[derive(Debug, Clone)]
+pub struct Pricing {
+    /// Model sessions run on unless they or their user choose another, from `CLAUDE_MODEL`
+    pub model: ClaudeModel,
+    /// Prices by model. Built-in list prices can be overridden with `MODEL_PRICING`, e.g.
+    /// `claude-sonnet-4-5=3:15,claude-opus-4-1=15:75`.
+    pub prices: HashMap<ClaudeModel, ModelPrice>,
+}
+
+impl Pricing {
+    /// `model`, or the default model when None
+    pub fn model_or_default(&self, model: Option<ClaudeModel>) -> ClaudeModel {
+        model.unwrap_or(self.model)
+    }
+
+    /// Price of `model`
+    pub fn price_of(&self, model: ClaudeModel) -> ModelPrice {
+        self.prices
+            .get(&model)
+            .copied()
+            .unwrap_or_else(|| list_price(model))
+    }
+}
+
+/// Built-in list price of a model
+fn list_price(model: ClaudeModel) -> ModelPrice {
+    let (input_per_mtok, output_per_mtok) = match model {
+        ClaudeModel::ClaudeSonnet45 => (3.0, 15.0),
+        ClaudeModel::ClaudeOpus41 => (15.0, 75.0),
+        ClaudeModel::ClaudeHaiku45 => (1.0, 5.0),
+    };
+    ModelPrice {
+        input_per_mtok,
+        output_per_mtok,
+    }
+}
+
+/// Parse `model=input:output` pairs on top of the built-in prices, skipping invalid entries
+/// and unknown models
+fn parse_prices(value: &str) -> HashMap<ClaudeModel, ModelPrice> {
+    let mut prices: HashMap<ClaudeModel, ModelPrice> = ClaudeModel::iter()
+        .map(|model| (model, list_price(model)))
+        .collect();
+    for entry in parse_list(value) {
+        if let Some((model, price)) = entry.split_once('=') {
+            if let (Ok(model), Ok(price)) = (model.parse(), price.parse()) {
+                prices.insert(model, price);
+            }
+        }
+    }
+    prices
+}
+
+/// Models sessions may request
+#[derive(Debug, Clone)]
+pub struct ModelPolicy {
+    /// Requestable models, from the comma-separated `ALLOWED_MODELS`. Defaults to every
+    /// model.
+    pub allowed: Vec<ClaudeModel>,
+    /// Plans (realm roles) a model is limited to, from `MODEL_PLANS`, e.g.
+    /// `claude-opus-4-1=pro|enterprise`. Models not listed are open to everyone.
+    pub plans: HashMap<ClaudeModel, Vec<String>>,
+}
+
+impl ModelPolicy {
+    /// Whether a user holding `roles` may request `model`. Admins may request any allowed
+    /// model.
+    pub fn check(
+        &self,
+        model: ClaudeModel,
+        roles: &[String],
+        is_admin: bool,
+    ) -> Result<(), String> {
+        if !self.allowed.contains(&model) {
+            return Err(format!(
+                "Model {} is not available; choose one of: {}",
+                model.id(),
+                self.allowed
+                    .iter()
+                    .map(ClaudeModel::id)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+        match self.plans.get(&model) {
+            Some(plans) if !is_admin && !plans.iter().any(|plan| roles.contains(plan)) => {
+                Err(format!(
+                    "Model {} requires one of the plans: {}",
+                    model.id(),
+                    plans.join(", ")
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Parse `model=plan|plan` pairs, skipping unknown models and entries without plans
+fn parse_model_plans(value: &str) -> HashMap<ClaudeModel, Vec<String>> {
+    parse_list(value)
+        .iter()
+        .filter_map(|entry| {
+            let (model, plans) = entry.split_once('=')?;
+            let plans: Vec<String> = plans
+                .split('|')
+                .map(str::trim)
+                .filter(|plan| !plan.is_empty())
+                .map(str::to_string)
+                .collect();
+            (!plans.is_empty()).then_some((model.parse().ok()?, plans))
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RepoLockMode {
+    /// Concurrent sessions on the same repo and branch are allowed silently
+    Off,
+    /// The session is created and the response names the conflicting session
+    Warn,
+    /// Creation fails with 409 naming the conflicting session
+    Block,
+}
+
+impl FromStr for RepoLockMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "off" => Ok(RepoLockMode::Off),
+            "warn" => Ok(RepoLockMode::Warn),
+            "block" => Ok(RepoLockMode::Block),
+            other => Err(format!("Unknown repo lock mode: {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LegacyPromptData {
+    /// Plain strings, `{"content": ...}` objects and role/content arrays are converted to a
+    /// conversation
+    Normalize,
+    /// Only conversations are accepted; other shapes fail with 400
+    Reject,
+}
+
+impl FromStr for LegacyPromptData {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "normalize" => Ok(LegacyPromptData::Normalize),
+            "reject" => Ok(LegacyPromptData::Reject),
+            other => Err(format!("Unknown legacy prompt data mode: {}", other)),
+        }
+    }
+}
+
+/// Limits on request bodies and the JSON documents inside them
+#[derive(Debug, Clone)]
+pub struct RequestLimits {
+    /// Largest JSON request body Rocket will read, from `JSON_BODY_LIMIT_BYTES`
+    pub json_body_bytes: u64,
+    /// Largest serialized prompt `data`, from `PROMPT_DATA_LIMIT_BYTES`
+    pub prompt_data_bytes: usize,
+    /// Largest serialized message `data`, from `MESSAGE_DATA_LIMIT_BYTES`
+    pub message_data_bytes: usize,
+    /// Deepest nesting allowed in a `data` document, from `JSON_MAX_DEPTH`
+    pub max_json_depth: usize,
+}
+
+static CONFIG: LazyLock<Config> = LazyLock::new(Config::from_env);
+
+/// Get the process-wide configuration
+pub fn get() -> &'static Config {
+    &CONFIG
+}
+
+/// Parse `name` from the environment, falling back to `default` when unset or invalid
+fn env_or<T: FromStr>(name: &str, default: T) -> T {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+/// A limit from the environment, None when unset, invalid or 0
+fn env_limit(name: &str) -> Option<u64> {
+    Some(env_or(name, 0)).filter(|limit| *limit > 0)
+}
+
+/// Split a comma-separated list, dropping empty entries
+fn parse_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+impl Config {
+    fn from_env() -> Self {
+        let allowed_models: Vec<ClaudeModel> =
+            parse_list(&std::env::var("ALLOWED_MODELS").unwrap_or_default())
+                .iter()
+                .filter_map(|model| model.parse().ok())
+                .collect();
+
+        Self {
+            request_limits: RequestLimits {
+                json_body_bytes: env_or("JSON_BODY_LIMIT_BYTES", 1024 * 1024),
+                prompt_data_bytes: env_or("PROMPT_DATA_LIMIT_BYTES", 256 * 1024),
+                message_data_bytes: env_or("MESSAGE_DATA_LIMIT_BYTES", 1024 * 1024),
+                max_json_depth: env_or("JSON_MAX_DEPTH", 64),
+            },
+            repo_lock: env_or("REPO_LOCK_MODE", RepoLockMode::Off),
+            legacy_prompt_data: env_or("PROMPT_LEGACY_DATA", LegacyPromptData::Normalize),
+            artifact_paths: parse_list(
+                &std::env::var("SANDBOX_ARTIFACT_PATHS")
+                    .unwrap_or_else(|_| "/home/gem/artifacts".to_string()),
+            ),
+            artifact_max_bytes: env_or("SANDBOX_ARTIFACT_MAX_BYTES", 50 * 1024 * 1024),
+            egress_always_allowed: parse_list(
+                &std::env::var("SANDBOX_EGRESS_ALWAYS_ALLOW").unwrap_or_default(),
+            ),
+            pricing: Pricing {
+                model: env_or("CLAUDE_MODEL", ClaudeModel::ClaudeSonnet45),
+                prices: parse_prices(&std::env::var("MODEL_PRICING").unwrap_or_default()),
+            },
+            models: ModelPolicy {
+                allowed: if allowed_models.is_empty() {
+                    ClaudeModel::iter().collect()
+                } else {
+                    allowed_models
+                },
+                plans: parse_model_plans(&std::env::var("MODEL_PLANS").unwrap_or_default()),
+            },
+            user_monthly_budget_usd: std::env::var("USER_MONTHLY_BUDGET_USD")
+                .ok()
+                .and_then(|v| v.parse().ok()),
+            dev_auth: DevAuth::from_env(),
+            http: HttpClientConfig {
+                ca_bundle: std::env::var("HTTP_CA_BUNDLE").ok(),
+                connect_timeout: Duration::from_secs(env_or("HTTP_CONNECT_TIMEOUT_SECS", 15)),
+                timeout: Duration::from_secs(env_or("HTTP_TIMEOUT_SECS", 60)),
+                max_retries: env_or("HTTP_MAX_RETRIES", 2),
+            },
+            dlq_alert_age: Duration::from_secs(env_or("DLQ_ALERT_AGE_SECS", 3600)),
+            db_pool: DbPoolConfig {
+                max_connections: env_or("DB_MAX_CONNECTIONS", 10),
+                min_connections: env_or("DB_MIN_CONNECTIONS", 0),
+                connect_timeout: Duration::from_secs(env_or("DB_CONNECT_TIMEOUT_SECS", 10)),
+                acquire_timeout: Duration::from_secs(env_or("DB_ACQUIRE_TIMEOUT_SECS", 30)),
+                statement_timeout_ms: env_or("DB_STATEMENT_TIMEOUT_MS", 0),
+                slow_query_ms: env_or("DB_SLOW_QUERY_MS", 500),
+            },
+            agent_callback_url: std::env::var("AGENT_CALLBACK_URL")
+                .ok()
+                .map(|url| url.trim_end_matches('/').to_string())
+                .filter(|url| !url.is_empty()),
+            agent_token_ttl: Duration::from_secs(env_or("AGENT_TOKEN_TTL_SECS", 21600)),
+            uploads: UploadConfig {
+                max_bytes: env_or("UPLOAD_MAX_BYTES", 100 * 1024 * 1024),
+                dir: std::env::var("UPLOAD_DIR")
+                    .map(|dir| dir.trim_end_matches('/').to_string())
+                    .unwrap_or_else(|_| "/home/gem/uploads".to_string()),
+                attachment_dir: std::env::var("ATTACHMENT_DIR")
+                    .map(|dir| dir.trim_end_matches('/').to_string())
+                    .unwrap_or_else(|_| "/home/gem/attachments".to_string()),
+                scan_command: std::env::var("UPLOAD_SCAN_COMMAND")
+                    .ok()
+                    .filter(|command| !command.trim().is_empty()),
+            },
+            org_claim: std::env::var("ORG_CLAIM").unwrap_or_else(|_| "organization".to_string()),
+            soft_cancel_grace: Duration::from_secs(env_or("SOFT_CANCEL_GRACE_SECS", 120)),
+            fan_out: FanOutConfig {
+                max_repos: env_or("FAN_OUT_MAX_REPOS", 50),
+                default_concurrency: env_or("FAN_OUT_CONCURRENCY", 5),
+            },
+            idempotency_key_ttl: Duration::from_secs(env_or("IDEMPOTENCY_KEY_TTL_SECS", 86400)),
+            duplicate_prompt_window: Duration::from_secs(env_or(
+                "DUPLICATE_PROMPT_WINDOW_SECS",
+                60,
+            )),
+            message_retention: std::env::var("MESSAGE_RETENTION_DAYS")
+                .ok()
+                .and_then(|v| v.parse::<u64>().ok())
+                .filter(|days| *days > 0)
+                .map(|days| Duration::from_secs(days * 24 * 60 * 60)),
+            data_retention_interval: Duration::from_secs(
+                env_or("DATA_RETENTION_INTERVAL_SECS", 300).max(1),
+            ),
+            queue_stats_token: std::env::var("QUEUE_STATS_TOKEN")
+                .ok()
+                .filter(|token| !token.is_empty()),
+            keycloak_webhook_secret: std::env::var("KEYCLOAK_WEBHOOK_SECRET")
+                .ok()
+                .filter(|secret| !secret.is_empty()),
+            pull_requests: PullRequestConfig {
+                describe: env_or("PR_DESCRIPTION_ENABLED", true),
+                template_file: std::env::var("PR_DESCRIPTION_TEMPLATE_FILE")
+                    .ok()
+                    .filter(|path| !path.is_empty()),
+                session_url_template: std::env::var("SESSION_URL_TEMPLATE")
+                    .ok()
+                    .filter(|url| !url.is_empty()),
+            },
+            prewarm: PrewarmConfig {
+                generic: env_or("SANDBOX_PREWARM_COUNT", 0),
+                repos: parse_prewarm_repos(
+                    &std::env::var("SANDBOX_PREWARM_REPOS").unwrap_or_default(),
+                ),
+                ttl: Duration::from_secs(env_or("SANDBOX_PREWARM_TTL_SECS", 1800)),
+            },
+            sandbox_regions: parse_list(&std::env::var("SANDBOX_REGIONS").unwrap_or_default()),
+            worker_id: default_worker_id(),
+            session_affinity_ttl: Duration::from_secs(env_or("SESSION_AFFINITY_TTL_SECS", 1800)),
+            message_writer: MessageWriterConfig {
+                batch_size: env_or("MESSAGE_BATCH_SIZE", 50),
+                batch_interval: Duration::from_millis(env_or("MESSAGE_BATCH_INTERVAL_MS", 200)),
+                buffer_capacity: env_or("MESSAGE_BUFFER_CAPACITY", 1000),
+            },
+            secret_scan: env_or("SECRET_SCAN_ENABLED", true),
+            session_budget: SessionBudgetConfig {
+                max_wall_clock_secs: env_limit("SESSION_BUDGET_MAX_WALL_CLOCK_SECS"),
+                max_messages: env_limit("SESSION_BUDGET_MAX_MESSAGES"),
+                max_tokens: env_limit("SESSION_BUDGET_MAX_TOKENS"),
+                check_interval: Duration::from_secs(
+                    env_or("SESSION_BUDGET_CHECK_INTERVAL_SECS", 10).max(1),
+                ),
+                kill_grace: Duration::from_secs(env_or("SESSION_BUDGET_KILL_GRACE_SECS", 30)),
+            },
+            output_log: env_or("OUTPUT_LOG_ENABLED", true),
+            workspace_disk: WorkspaceDiskConfig {
+                max_gb: env_limit("WORKSPACE_DISK_LIMIT_GB"),
+                check_interval: Duration::from_secs(
+                    env_or("WORKSPACE_DISK_CHECK_INTERVAL_SECS", 60).max(1),
+                ),
+            },
+            cancellation: CancellationConfig {
+                interval: Duration::from_secs(env_or("CANCELLATION_ENFORCER_INTERVAL_SECS", 2))
+                    .max(Duration::from_secs(1)),
+                jitter: Duration::from_millis(env_or("CANCELLATION_ENFORCER_JITTER_MS", 500)),
+                signals: parse_kill_signals(
+                    &std::env::var("CANCELLATION_KILL_SIGNALS")
+                        .unwrap_or_else(|_| DEFAULT_KILL_SIGNALS.to_string()),
+                ),
+                instance_stale_after: Duration::from_secs(env_or("WORKER_INSTANCE_STALE_SECS", 60)),
+            },
+            message_quota: MessageQuotaConfig {
+                max_messages: env_limit("SESSION_MESSAGE_SOFT_LIMIT"),
+                max_bytes: env_limit("SESSION_MESSAGE_SOFT_LIMIT_BYTES"),
+                keep_recent: env_or("MESSAGE_COMPACTION_KEEP_RECENT", 1000),
+                preview_chars: env_or("MESSAGE_COMPACTION_PREVIEW_CHARS", 2000),
+                interval: Duration::from_secs(env_or("MESSAGE_COMPACTION_INTERVAL_SECS", 600))
+                    .max(Duration::from_secs(1)),
+            },
+            title_generation: TitleGenerationConfig {
+                api_max_retries: env_or("ANTHROPIC_MAX_RETRIES", 3),
+                api_timeout: Duration::from_secs(env_or("ANTHROPIC_TIMEOUT_SECS", 60).max(1)),
+                max_attempts: env_or("TITLE_REGENERATION_MAX_ATTEMPTS", 5),
+                interval: Duration::from_secs(env_or("TITLE_REGENERATION_INTERVAL_SECS", 60))
+                    .max(Duration::from_secs(1)),
+            },
+            message_stream: MessageStreamConfig {
+                keep_alive: Duration::from_secs(env_or("SSE_KEEP_ALIVE_SECS", 15).max(1)),
+                max_connection: Duration::from_secs(env_or("SSE_MAX_CONNECTION_SECS", 3600).max(1)),
+                poll_interval: Duration::from_millis(env_or("SSE_POLL_INTERVAL_MS", 1000).max(100)),
+            },
+            grpc: GrpcConfig {
+                enabled: env_or("GRPC_ENABLED", false),
+                port: env_or("GRPC_PORT", 50051),
+            },
+            ingestion_filter: parse_ingestion_filter(
+                &std::env::var("MESSAGE_INGESTION_FILTER").unwrap_or_default(),
+            ),
+            operational_events: OperationalEventsConfig {
+                merge_window: Duration::from_secs(env_or(
+                    "OPERATIONAL_EVENT_MERGE_WINDOW_SECS",
+                    3600,
+                )),
+                retention: Duration::from_secs(
+                    env_or("OPERATIONAL_EVENT_RETENTION_DAYS", 30u64).max(1) * 24 * 60 * 60,
+                ),
+            },
+            status_display: StatusDisplayConfig {
+                labels: parse_overrides(&std::env::var("STATUS_LABELS").unwrap_or_default()),
+                colors: parse_overrides(&std::env::var("STATUS_COLORS").unwrap_or_default()),
+            },
+        }
+    }
+}
+
+/// `WORKER_ID`, else the host name, else a name unique to this process
+fn default_worker_id() -> String {
+    std::env::var("WORKER_ID")
+        .or_else(|_| std::env::var("HOSTNAME"))
+        .or_else(|_| std::fs::read_to_string("/etc/hostname"))
+        .map(|id| id.trim().to_string())
+        .ok()
+        .filter(|id| !id.is_empty())
+        .unwrap_or_else(|| format!("worker-{}", uuid::Uuid::new_v4().simple()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_repo_lock_mode() {
+        assert_eq!("block".parse(), Ok(RepoLockMode::Block));
+        assert_eq!("Warn".parse(), Ok(RepoLockMode::Warn));
+        assert!("sometimes".parse::<RepoLockMode>().is_err());
+    }
+
+    #[test]
+    fn test_parse_list() {
+        assert_eq!(
+            parse_list("/home/gem/artifacts, /tmp/reports,,"),
+            vec!["/home/gem/artifacts", "/tmp/reports"]
+        );
+        assert!(parse_list("").is_empty());
+    }
+
+    #[test]
+    fn test_parse_prewarm_repos() {
+        assert_eq!(
+            parse_prewarm_repos("acme/api=3, acme/web = 1,acme/docs=0,broken,=2"),
+            vec![("acme/api".to_string(), 3), ("acme/web".to_string(), 1)]
+        );
+    }
+
+    #[test]
+    fn test_parse_ingestion_filter() {
+        assert_eq!(
+            parse_ingestion_filter(
+                "stream_event=drop, system:status = Aggregate,ping=squash,=drop"
+            ),
+            IngestionRules::from([
+                ("stream_event".to_string(), IngestionAction::Drop),
+                ("system:status".to_string(), IngestionAction::Aggregate),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_kill_signals() {
+        assert_eq!(
+            parse_kill_signals("sigint, TERM:5,KILL:20,BAD:x,:3"),
+            vec![
+                KillSignal {
+                    name: "INT".to_string(),
+                    after: Duration::ZERO
+                },
+                KillSignal {
+                    name: "TERM".to_string(),
+                    after: Duration::from_secs(5)
+                },
+                KillSignal {
+                    name: "KILL".to_string(),
+                    after: Duration::from_secs(20)
+                },
+            ]
+        );
+        assert_eq!(
+            parse_kill_signals(""),
+            parse_kill_signals(DEFAULT_KILL_SIGNALS)
+        );
+        assert_eq!(parse_kill_signals("")[1].after, Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_is_production() {
+        assert!(is_production(Some("production")));
+        assert!(is_production(Some("PRODUCTION")));
+        assert!(!is_production(Some("development")));
+        assert!(!is_production(None));
+    }
+
+    #[test]
+    fn test_parse_prices_overrides_defaults() {
+        let prices = parse_prices("claude-sonnet-4-5=4:20, custom=0.5:2.5, claude-haiku-4-5=abc");
+        assert_eq!(
+            prices[&ClaudeModel::ClaudeSonnet45],
+            ModelPrice {
+                input_per_mtok: 4.0,
+                output_per_mtok: 20.0
+            }
+        );
+        assert_eq!(prices.len(), ClaudeModel::iter().count());
+        assert_eq!(prices[&ClaudeModel::ClaudeHaiku45].output_per_mtok, 5.0);
+
+        let pricing = Pricing {
+            model: ClaudeModel::ClaudeSonnet45,
+            prices,
+        };
+        assert_eq!(
+            pricing.price_of(ClaudeModel::ClaudeOpus41).output_per_mtok,
+            75.0
+        );
+        assert_eq!(pricing.model_or_default(None), ClaudeModel::ClaudeSonnet45);
+    }
+
+    #[test]
+    fn test_model_policy_check() {
+        let policy = ModelPolicy {
+            allowed: vec![ClaudeModel::ClaudeSonnet45, ClaudeModel::ClaudeOpus41],
+            plans: parse_model_plans("claude-opus-4-1=pro|enterprise, claude-sonnet-4-5=, gpt=pro"),
+        };
+        assert_eq!(policy.plans.len(), 1);
+
+        let free = vec!["user".to_string()];
+        let pro = vec!["user".to_string(), "pro".to_string()];
+        let (sonnet, opus) = (ClaudeModel::ClaudeSonnet45, ClaudeModel::ClaudeOpus41);
+        assert!(policy.check(sonnet, &free, false).is_ok());
+        assert!(policy.check(opus, &free, false).is_err());
+        assert!(policy.check(opus, &pro, false).is_ok());
+        assert!(policy.check(opus, &free, true).is_ok());
+        assert!(policy
+            .check(ClaudeModel::ClaudeHaiku45, &pro, true)
+            .is_err());
+    }
+}
@@ -1,5 +1,198 @@
-use sea_orm::{Database, DatabaseConnection, DbErr};
+use sea_orm::{ConnectOptions, Database, DatabaseConnection, DbErr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
+use tracing::{info, warn};
 
-pub async fn establish_connection(database_url: &str) -> Result<DatabaseConnection, DbErr> {
-    Database::connect(database_url).await
+use crate::config::{self, DbPoolConfig};
+
+/// Longest statement text included in a slow query log line
+const MAX_LOGGED_STATEMENT_CHARS: usize = 2000;
+
+/// Every pool opened in this process, by name, so `/metrics` can report their utilization
+static POOLS: LazyLock<Mutex<Vec<(&'static str, DatabaseConnection)>>> =
+    LazyLock::new(|| Mutex::new(Vec::new()));
+
+/// Open a connection pool sized and timed out per `DB_*` settings.
+///
+/// `pool_name` labels the pool's metrics and slow query logs, e.g. `server` or
+/// `prompt_poller`. Queries slower than `DB_SLOW_QUERY_MS` are logged at warn level inside the
+/// caller's span, which for requests names the handler that issued them.
+pub async fn establish_connection(
+    database_url: &str,
+    pool_name: &'static str,
+) -> Result<DatabaseConnection, DbErr> {
+    let pool = &config::get().db_pool;
+    let mut db = Database::connect(connect_options(database_url, pool)).await?;
+
+    let slow_query_ms = pool.slow_query_ms;
+    if slow_query_ms > 0 {
+        db.set_metric_callback(move |info| {
+            let elapsed_ms = info.elapsed.as_millis() as u64;
+            if elapsed_ms < slow_query_ms {
+                return;
+            }
+            crate::metrics::get()
+                .db_slow_queries_total
+                .with_label_values(&[pool_name])
+                .inc();
+            let statement = info.statement.to_string();
+            warn!(
+                pool = pool_name,
+                elapsed_ms,
+                failed = info.failed,
+                "Slow query: {}",
+                statement
+                    .chars()
+                    .take(MAX_LOGGED_STATEMENT_CHARS)
+                    .collect::<String>()
+            );
+        });
+    }
+
+    POOLS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push((pool_name, db.clone()));
+    Ok(db)
+}
+
+fn connect_options(database_url: &str, pool: &DbPoolConfig) -> ConnectOptions {
+    let mut options = ConnectOptions::new(with_statement_timeout(
+        database_url,
+        pool.statement_timeout_ms,
+    ));
+    options
+        .max_connections(pool.max_connections)
+        .min_connections(pool.min_connections)
+        .connect_timeout(pool.connect_timeout)
+        .acquire_timeout(pool.acquire_timeout);
+    options
+}
+
+/// Add a server-side `statement_timeout` to the connection URL; 0 leaves it unset
+fn with_statement_timeout(database_url: &str, statement_timeout_ms: u64) -> String {
+    if statement_timeout_ms == 0 {
+        return database_url.to_string();
+    }
+    let separator = if database_url.contains('?') { '&' } else { '?' };
+    format!(
+        "{}{}options[statement_timeout]={}",
+        database_url, separator, statement_timeout_ms
+    )
+}
+
+/// Refresh the pool utilization gauges; called before metrics are gathered
+pub fn record_pool_metrics() {
+    let metrics = crate::metrics::get();
+    let max_connections = config::get().db_pool.max_connections;
+    let pools = POOLS.lock().unwrap_or_else(|e| e.into_inner());
+    for (name, db) in pools.iter() {
+        let pool = db.get_postgres_connection_pool();
+        let size = pool.size() as i64;
+        let idle = pool.num_idle() as i64;
+        metrics
+            .db_pool_connections
+            .with_label_values(&[name, "in_use"])
+            .set(size - idle);
+        metrics
+            .db_pool_connections
+            .with_label_values(&[name, "idle"])
+            .set(idle);
+        metrics
+            .db_pool_max_connections
+            .with_label_values(&[name])
+            .set(max_connections as i64);
+    }
+}
+
+/// How often the read replica is pinged
+const REPLICA_HEALTH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Connection for read-only endpoints (lists and single reads).
+///
+/// Queries go to the replica from `DATABASE_READ_URL` while it answers health checks, and to
+/// the primary when no replica is configured or the last check failed. Replica reads may lag
+/// the primary slightly, so handlers that write or must see their own writes keep using the
+/// primary connection.
+#[derive(Clone)]
+pub struct ReadDb {
+    primary: DatabaseConnection,
+    replica: Option<DatabaseConnection>,
+    healthy: Arc<AtomicBool>,
+}
+
+impl ReadDb {
+    /// Connect to the replica at `read_url`, if any. A replica that cannot be reached at
+    /// startup is logged and left out, so reads use the primary.
+    pub async fn connect(primary: DatabaseConnection, read_url: Option<&str>) -> Self {
+        let replica = match read_url {
+            Some(url) => match establish_connection(url, "read_replica").await {
+                Ok(replica) => Some(replica),
+                Err(e) => {
+                    warn!(
+                        "Failed to connect to read replica, reading from primary: {}",
+                        e
+                    );
+                    None
+                }
+            },
+            None => None,
+        };
+        let healthy = Arc::new(AtomicBool::new(replica.is_some()));
+        Self {
+            primary,
+            replica,
+            healthy,
+        }
+    }
+
+    /// Connection for the next read-only query
+    pub fn conn(&self) -> &DatabaseConnection {
+        match &self.replica {
+            Some(replica) if self.healthy.load(Ordering::Relaxed) => replica,
+            _ => &self.primary,
+        }
+    }
+
+    /// Ping the replica every `REPLICA_HEALTH_INTERVAL`, switching reads to the primary while it
+    /// fails. Returns at once when no replica is configured.
+    pub async fn run_health_check(self) {
+        let Some(replica) = &self.replica else {
+            return;
+        };
+        loop {
+            tokio::time::sleep(REPLICA_HEALTH_INTERVAL).await;
+            let healthy = replica.ping().await;
+            let was_healthy = self.healthy.swap(healthy.is_ok(), Ordering::Relaxed);
+            match healthy {
+                Err(e) if was_healthy => {
+                    warn!("Read replica is unhealthy, reading from primary: {}", e)
+                }
+                Ok(()) if !was_healthy => info!("Read replica recovered, reading from replica"),
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_with_statement_timeout() {
+        assert_eq!(
+            with_statement_timeout("postgres://u:p@localhost/db", 5000),
+            "postgres://u:p@localhost/db?options[statement_timeout]=5000"
+        );
+        assert_eq!(
+            with_statement_timeout("postgres://u:p@localhost/db?sslmode=require", 5000),
+            "postgres://u:p@localhost/db?sslmode=require&options[statement_timeout]=5000"
+        );
+        assert_eq!(
+            with_statement_timeout("postgres://u:p@localhost/db", 0),
+            "postgres://u:p@localhost/db"
+        );
+    }
 }
@@ -0,0 +1,39 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// A run of consecutive messages of one prompt, denormalized for rendering a session's
+/// conversation in one query, see `services::conversation_view`
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "conversation_chunk")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: Uuid,
+    pub session_id: Uuid,
+    pub prompt_id: Uuid,
+    /// Position of the chunk among its prompt's chunks, from 0
+    pub chunk_index: i32,
+    /// JSON array of `ChunkMessage`s in the order they were written
+    #[sea_orm(column_type = "JsonBinary")]
+    pub messages: Json,
+    pub message_count: i32,
+    pub created_at: DateTimeWithTimeZone,
+    pub updated_at: DateTimeWithTimeZone,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::prompt::Entity",
+        from = "Column::PromptId",
+        to = "super::prompt::Column::Id"
+    )]
+    Prompt,
+}
+
+impl Related<super::prompt::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Prompt.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
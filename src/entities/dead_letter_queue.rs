@@ -28,7 +28,16 @@ pub enum Relation {}
 impl ActiveModelBehavior for ActiveModel {}
 
 #[derive(
-    Debug, Clone, PartialEq, Eq, Serialize, Deserialize, EnumIter, DeriveActiveEnum, JsonSchema,
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+    EnumIter,
+    DeriveActiveEnum,
+    JsonSchema,
+    rocket::FromFormField,
 )]
 #[sea_orm(rs_type = "String", db_type = "String(Some(50))")]
 pub enum DlqStatus {
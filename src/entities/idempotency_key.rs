@@ -0,0 +1,28 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// An `Idempotency-Key` a user sent, with the response to replay for it.
+///
+/// Keys are unique per user and forgotten once they expire.
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "idempotency_key")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: Uuid,
+    pub user_id: String,
+    pub key: String,
+    /// Method and route the key was used with, e.g. `POST /prompts`
+    pub endpoint: String,
+    /// SHA-256 of the request body, so a key reused for a different request is refused
+    pub request_hash: String,
+    /// Response body of the first request, None while it is still being handled
+    #[sea_orm(column_type = "JsonBinary", nullable)]
+    pub response: Option<Json>,
+    pub created_at: DateTimeWithTimeZone,
+    pub expires_at: DateTimeWithTimeZone,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -10,8 +10,14 @@ pub struct Model {
     pub prompt_id: Uuid,
     #[sea_orm(column_type = "JsonBinary")]
     pub data: Json,
+    /// Object storage key holding the payload when it was offloaded; `data` is then a stub
+    #[sea_orm(column_type = "Text", nullable)]
+    pub blob_key: Option<String>,
     pub created_at: DateTimeWithTimeZone,
     pub updated_at: DateTimeWithTimeZone,
+    /// Position in the order messages were stored, assigned by the database; the event id of
+    /// the message in `GET /sessions/<id>/messages/stream`
+    pub seq: i64,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -1,4 +1,25 @@
+pub mod annotation;
+pub mod conversation_chunk;
 pub mod dead_letter_queue;
+pub mod idempotency_key;
 pub mod message;
+pub mod notification;
+pub mod operational_event;
+pub mod organization;
+pub mod outbox_event;
+pub mod poller_control;
 pub mod prompt;
+pub mod prompt_artifact;
+pub mod prompt_attachment;
+pub mod prompt_file_change;
+pub mod sandbox_exec;
 pub mod session;
+pub mod session_artifact;
+pub mod session_event;
+pub mod session_upload;
+pub mod session_watcher;
+pub mod user_deprovision;
+pub mod user_erasure;
+pub mod user_settings;
+pub mod warm_sandbox;
+pub mod worker_instance;
@@ -0,0 +1,58 @@
+use rocket_okapi::okapi::schemars::{self, JsonSchema};
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// An in-app notification about a session, addressed to one user
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "notification")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: Uuid,
+    /// Recipient
+    pub user_id: String,
+    pub session_id: Uuid,
+    /// Prompt the notification is about, if any
+    pub prompt_id: Option<Uuid>,
+    pub kind: NotificationKind,
+    #[sea_orm(column_type = "Text")]
+    pub message: String,
+    pub read_at: Option<DateTimeWithTimeZone>,
+    pub created_at: DateTimeWithTimeZone,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::session::Entity",
+        from = "Column::SessionId",
+        to = "super::session::Column::Id"
+    )]
+    Session,
+}
+
+impl Related<super::session::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Session.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+#[derive(
+    Debug, Clone, PartialEq, Eq, Serialize, Deserialize, EnumIter, DeriveActiveEnum, JsonSchema,
+)]
+#[sea_orm(rs_type = "String", db_type = "String(Some(50))")]
+pub enum NotificationKind {
+    /// A run finished and the session is waiting for review
+    #[sea_orm(string_value = "needs_review")]
+    NeedsReview,
+    /// A cancellation request was carried out
+    #[sea_orm(string_value = "cancelled")]
+    Cancelled,
+    /// A prompt's run failed
+    #[sea_orm(string_value = "prompt_failed")]
+    PromptFailed,
+    /// The sandbox was kept because it holds work that was never pushed
+    #[sea_orm(string_value = "needs_attention")]
+    NeedsAttention,
+}
@@ -0,0 +1,58 @@
+use rocket_okapi::okapi::schemars::{self, JsonSchema};
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// A non-fatal error of a background loop or worker. Repeats of the same error are counted on
+/// one row instead of adding rows, see `services::operational_events`.
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "operational_event")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: Uuid,
+    /// Loop or worker that hit the error, by its name in the worker registry
+    pub source: String,
+    pub kind: OperationalEventKind,
+    pub message: String,
+    /// Details of the latest occurrence, e.g. the session it was about
+    #[sea_orm(column_type = "JsonBinary", nullable)]
+    pub context: Option<Json>,
+    /// Occurrences merged into this row
+    pub count: i32,
+    pub first_seen_at: DateTimeWithTimeZone,
+    pub last_seen_at: DateTimeWithTimeZone,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+/// What failed
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+    EnumIter,
+    DeriveActiveEnum,
+    JsonSchema,
+    rocket::FromFormField,
+)]
+#[sea_orm(rs_type = "String", db_type = "String(Some(50))")]
+pub enum OperationalEventKind {
+    /// A call to the IP allocator
+    #[sea_orm(string_value = "allocator")]
+    Allocator,
+    /// A database query
+    #[sea_orm(string_value = "database")]
+    Database,
+    /// A command or request to a sandbox
+    #[sea_orm(string_value = "sandbox")]
+    Sandbox,
+    /// Anything else, such as a whole loop iteration failing
+    #[sea_orm(string_value = "other")]
+    Other,
+}
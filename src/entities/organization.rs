@@ -0,0 +1,24 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Quotas and settings of an organization, keyed by the id in users' organization claim.
+///
+/// Organizations without a row have no org-level limits; their sessions are still shared.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "organization")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: String,
+    pub name: Option<String>,
+    /// Sessions of the organization that may be queued or running at once; None for no limit
+    pub max_active_sessions: Option<i32>,
+    /// Monthly spend allowed across the organization's sessions in USD; None for no limit
+    pub monthly_budget_usd: Option<f64>,
+    pub created_at: DateTimeWithTimeZone,
+    pub updated_at: DateTimeWithTimeZone,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
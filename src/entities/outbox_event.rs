@@ -0,0 +1,58 @@
+use rocket_okapi::okapi::schemars::{self, JsonSchema};
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// An external side effect recorded alongside the state change that caused it, dispatched
+/// at least once by the outbox event worker
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "outbox_event")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: Uuid,
+    pub kind: OutboxEventKind,
+    #[sea_orm(column_type = "JsonBinary")]
+    pub payload: Json,
+    pub status: OutboxEventStatus,
+    /// Failed dispatch attempts so far
+    pub attempts: i32,
+    #[sea_orm(column_type = "Text", nullable)]
+    pub last_error: Option<String>,
+    /// Earliest time the event may be dispatched (again)
+    pub next_attempt_at: DateTimeWithTimeZone,
+    pub created_at: DateTimeWithTimeZone,
+    pub updated_at: DateTimeWithTimeZone,
+    pub dispatched_at: Option<DateTimeWithTimeZone>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+#[derive(
+    Debug, Clone, PartialEq, Eq, Serialize, Deserialize, EnumIter, DeriveActiveEnum, JsonSchema,
+)]
+#[sea_orm(rs_type = "String", db_type = "String(Some(50))")]
+pub enum OutboxEventKind {
+    /// Redeploy the Railway deployment after the IP allocator returned an item
+    #[sea_orm(string_value = "railway_redeploy")]
+    RailwayRedeploy,
+}
+
+#[derive(
+    Debug, Clone, PartialEq, Eq, Serialize, Deserialize, EnumIter, DeriveActiveEnum, JsonSchema,
+)]
+#[sea_orm(rs_type = "String", db_type = "String(Some(50))")]
+pub enum OutboxEventStatus {
+    /// Waiting for `next_attempt_at`
+    #[sea_orm(string_value = "pending")]
+    Pending,
+    /// Handed to the worker
+    #[sea_orm(string_value = "dispatching")]
+    Dispatching,
+    #[sea_orm(string_value = "dispatched")]
+    Dispatched,
+    /// Out of attempts and moved to the DLQ
+    #[sea_orm(string_value = "failed")]
+    Failed,
+}
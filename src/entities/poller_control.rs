@@ -0,0 +1,22 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Whether a background poller is paused, shared by every instance running it.
+///
+/// Pollers without a row run normally.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "poller_control")]
+pub struct Model {
+    /// Poller name as in the worker registry, e.g. `prompt_poller`
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub name: String,
+    pub paused: bool,
+    /// Admin who last paused or resumed the poller
+    pub updated_by: Option<String>,
+    pub updated_at: DateTimeWithTimeZone,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -1,3 +1,4 @@
+use rocket_okapi::okapi::schemars::{self, JsonSchema};
 use sea_orm::entity::prelude::*;
 use serde::{Deserialize, Serialize};
 
@@ -12,6 +13,60 @@ pub struct Model {
     pub data: Json,
     pub created_at: DateTimeWithTimeZone,
     pub updated_at: DateTimeWithTimeZone,
+    #[sea_orm(column_type = "JsonBinary", nullable)]
+    pub timings: Option<Json>,
+    /// Coarse completion percentage of the current run, 0-100
+    pub progress: i16,
+    /// The prompt this one re-runs, when created through `POST /prompts/<id>/rerun`
+    pub rerun_of: Option<Uuid>,
+    /// Rendered system prompt of the last run
+    #[sea_orm(column_type = "Text", nullable)]
+    pub system_prompt: Option<String>,
+    /// Claude CLI argument vector of the last run
+    #[sea_orm(column_type = "JsonBinary", nullable)]
+    pub cli_args: Option<Json>,
+    /// Version of the system prompt template used by the last run
+    pub template_version: Option<String>,
+    /// Run currently or last holding the prompt, set when an outbox job claims it
+    pub run_id: Option<Uuid>,
+    /// When the current run claimed the prompt
+    pub started_at: Option<DateTimeWithTimeZone>,
+    /// When a run finished successfully; completed prompts are never run again
+    pub completed_at: Option<DateTimeWithTimeZone>,
+    /// Tool calls made by the current or last run, see `ToolSummary`
+    #[sea_orm(column_type = "JsonBinary", nullable)]
+    pub tool_summary: Option<Json>,
+    /// Dispatch priority; the poller starts sessions with higher priority prompts first
+    pub priority: PromptPriority,
+    /// Whether earlier prompts of the session are prepended to this one
+    pub include_history: bool,
+    /// Most recent earlier prompts included, all when None
+    pub history_depth: Option<i32>,
+    /// Prompt text of the last run, history included
+    #[sea_orm(column_type = "Text", nullable)]
+    pub rendered_prompt: Option<String>,
+    /// Claude model the last run used
+    pub model: Option<super::session::ClaudeModel>,
+    /// Exit code of the last run's CLI process, None while running or when killed by a signal
+    pub exit_code: Option<i32>,
+    /// Tail of the last run's stderr, kept only when the run failed or produced no messages
+    #[sea_orm(column_type = "Text", nullable)]
+    pub stderr: Option<String>,
+    /// Stage the last run failed at, None while running or after a clean run
+    pub error_kind: Option<PipelineErrorKind>,
+    /// Files and lines the last run changed, see `ChangeSummary`
+    #[sea_orm(column_type = "JsonBinary", nullable)]
+    pub change_summary: Option<Json>,
+    /// When the current or last run started or last produced a message
+    pub last_activity_at: Option<DateTimeWithTimeZone>,
+    /// Likely secrets the last run committed, see `SecretFinding`
+    #[sea_orm(column_type = "JsonBinary", nullable)]
+    pub secret_findings: Option<Json>,
+    /// Sandbox file the last run's CLI output was copied to, see `services::output_log`
+    #[sea_orm(column_type = "Text", nullable)]
+    pub output_log_path: Option<String>,
+    /// Still being composed: never dispatched until `POST /prompts/<id>/submit`
+    pub draft: bool,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -39,3 +94,180 @@ impl Related<super::message::Entity> for Entity {
 }
 
 impl ActiveModelBehavior for ActiveModel {}
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+    EnumIter,
+    DeriveActiveEnum,
+    JsonSchema,
+)]
+#[sea_orm(rs_type = "String", db_type = "String(Some(20))")]
+pub enum PromptPriority {
+    #[sea_orm(string_value = "high")]
+    High,
+    #[default]
+    #[sea_orm(string_value = "normal")]
+    Normal,
+    #[sea_orm(string_value = "low")]
+    Low,
+}
+
+impl PromptPriority {
+    pub const ALL: [PromptPriority; 3] = [
+        PromptPriority::High,
+        PromptPriority::Normal,
+        PromptPriority::Low,
+    ];
+
+    /// Dispatch order, lowest first
+    pub fn rank(self) -> u8 {
+        match self {
+            PromptPriority::High => 0,
+            PromptPriority::Normal => 1,
+            PromptPriority::Low => 2,
+        }
+    }
+
+    /// Metric label
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PromptPriority::High => "high",
+            PromptPriority::Normal => "normal",
+            PromptPriority::Low => "low",
+        }
+    }
+}
+
+/// Stage of the sandbox pipeline a run failed at, see `bg_tasks::pipeline_error`
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+    EnumIter,
+    DeriveActiveEnum,
+    JsonSchema,
+)]
+#[sea_orm(rs_type = "String", db_type = "String(Some(30))")]
+pub enum PipelineErrorKind {
+    /// Logging in to GitHub in the sandbox failed
+    #[sea_orm(string_value = "auth_failed")]
+    AuthFailed,
+    /// The repository could not be cloned
+    #[sea_orm(string_value = "clone_failed")]
+    CloneFailed,
+    /// The target or session branch could not be checked out
+    #[sea_orm(string_value = "checkout_failed")]
+    CheckoutFailed,
+    /// The sandbox could not be reached or written to
+    #[sea_orm(string_value = "sandbox_failed")]
+    SandboxFailed,
+    /// The Claude CLI could not be started
+    #[sea_orm(string_value = "cli_spawn_failed")]
+    CliSpawnFailed,
+    /// The Claude CLI exited with an error
+    #[sea_orm(string_value = "cli_non_zero_exit")]
+    CliNonZeroExit,
+    /// Reading or writing the run's records failed
+    #[sea_orm(string_value = "db_write_failed")]
+    DbWriteFailed,
+    /// The session was cancelled before or during the run
+    #[sea_orm(string_value = "cancelled")]
+    Cancelled,
+    /// The sandbox did not answer in time
+    #[sea_orm(string_value = "timeout")]
+    Timeout,
+    /// The run committed what look like secrets, so its pushes were refused
+    #[sea_orm(string_value = "secrets_detected")]
+    SecretsDetected,
+    /// The run changed paths the session's path policy denies, so its pushes were refused
+    #[sea_orm(string_value = "path_policy_violation")]
+    PathPolicyViolation,
+    /// The session used up its run time, message or token budget, so the run was stopped or
+    /// never started
+    #[sea_orm(string_value = "budget_exceeded")]
+    BudgetExceeded,
+    /// The clone went over the workspace disk limit, so the run was stopped or never started
+    #[sea_orm(string_value = "disk_limit_exceeded")]
+    DiskLimitExceeded,
+    /// The session needs something its sandbox cannot do, such as an egress policy without a
+    /// firewall, so the run never started
+    #[sea_orm(string_value = "capability_unsupported")]
+    CapabilityUnsupported,
+    /// The session branch is the target branch, protected, or a generated name that already
+    /// exists, so the run never started
+    #[sea_orm(string_value = "branch_rejected")]
+    BranchRejected,
+}
+
+impl PipelineErrorKind {
+    /// Metric label
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PipelineErrorKind::AuthFailed => "auth_failed",
+            PipelineErrorKind::CloneFailed => "clone_failed",
+            PipelineErrorKind::CheckoutFailed => "checkout_failed",
+            PipelineErrorKind::SandboxFailed => "sandbox_failed",
+            PipelineErrorKind::CliSpawnFailed => "cli_spawn_failed",
+            PipelineErrorKind::CliNonZeroExit => "cli_non_zero_exit",
+            PipelineErrorKind::DbWriteFailed => "db_write_failed",
+            PipelineErrorKind::Cancelled => "cancelled",
+            PipelineErrorKind::Timeout => "timeout",
+            PipelineErrorKind::SecretsDetected => "secrets_detected",
+            PipelineErrorKind::PathPolicyViolation => "path_policy_violation",
+            PipelineErrorKind::BudgetExceeded => "budget_exceeded",
+            PipelineErrorKind::DiskLimitExceeded => "disk_limit_exceeded",
+            PipelineErrorKind::CapabilityUnsupported => "capability_unsupported",
+            PipelineErrorKind::BranchRejected => "branch_rejected",
+        }
+    }
+
+    /// Explanation shown to users, without internal details
+    pub fn user_message(self) -> &'static str {
+        match self {
+            PipelineErrorKind::AuthFailed => {
+                "Could not sign in to GitHub; check that your GitHub account is connected"
+            }
+            PipelineErrorKind::CloneFailed => {
+                "Could not clone the repository; check that it exists and you have access"
+            }
+            PipelineErrorKind::CheckoutFailed => "Could not check out the branch",
+            PipelineErrorKind::SandboxFailed => "The sandbox could not be prepared for the run",
+            PipelineErrorKind::CliSpawnFailed => "Claude could not be started",
+            PipelineErrorKind::CliNonZeroExit => {
+                "Claude exited with an error; its output may be incomplete"
+            }
+            PipelineErrorKind::DbWriteFailed => "The run's results could not be saved",
+            PipelineErrorKind::Cancelled => "The run was cancelled",
+            PipelineErrorKind::Timeout => "The sandbox did not respond in time",
+            PipelineErrorKind::SecretsDetected => {
+                "The changes look like they contain secrets, so they were not pushed; remove them and run again"
+            }
+            PipelineErrorKind::PathPolicyViolation => {
+                "The changes touch paths the session's path policy does not allow, so they were not pushed; see the session's status message"
+            }
+            PipelineErrorKind::BudgetExceeded => {
+                "The session used up its budget, so the run was stopped; start a new session to continue"
+            }
+            PipelineErrorKind::DiskLimitExceeded => {
+                "The repository used more disk than the sandbox allows; keep dependencies and build output out of it, or have them cleaned up during the task"
+            }
+            PipelineErrorKind::CapabilityUnsupported => {
+                "The sandbox cannot provide what the session requires, such as its egress policy, so the run was not started"
+            }
+            PipelineErrorKind::BranchRejected => {
+                "The session's branch cannot be pushed to, so the run was not started; see the session's status message"
+            }
+        }
+    }
+}
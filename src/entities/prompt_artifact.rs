@@ -0,0 +1,61 @@
+use rocket_okapi::okapi::schemars::{self, JsonSchema};
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// A commit or pull request a prompt's run produced, for tracing changes back to prompts
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "prompt_artifact")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: Uuid,
+    pub prompt_id: Uuid,
+    pub session_id: Uuid,
+    pub kind: PromptArtifactKind,
+    /// `owner/name` of the repository
+    pub repo: String,
+    /// Full SHA of a commit pushed by the run; None for pull requests
+    pub commit_sha: Option<String>,
+    /// The pull request opened by the run, or for commits the pull request of their branch
+    /// once one exists
+    #[sea_orm(column_type = "Text", nullable)]
+    pub pull_request_url: Option<String>,
+    pub created_at: DateTimeWithTimeZone,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::prompt::Entity",
+        from = "Column::PromptId",
+        to = "super::prompt::Column::Id"
+    )]
+    Prompt,
+}
+
+impl Related<super::prompt::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Prompt.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+    EnumIter,
+    DeriveActiveEnum,
+    JsonSchema,
+)]
+#[sea_orm(rs_type = "String", db_type = "String(Some(20))")]
+pub enum PromptArtifactKind {
+    #[sea_orm(string_value = "commit")]
+    Commit,
+    #[sea_orm(string_value = "pull_request")]
+    PullRequest,
+}
@@ -0,0 +1,54 @@
+use rocket_okapi::okapi::schemars::{self, JsonSchema};
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// What an attachment holds, from its content type and name
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+    EnumIter,
+    DeriveActiveEnum,
+    JsonSchema,
+)]
+#[sea_orm(rs_type = "String", db_type = "String(Some(50))")]
+pub enum AttachmentKind {
+    /// A screenshot or other picture the agent can view
+    #[sea_orm(string_value = "image")]
+    Image,
+    /// Log or other plain text output
+    #[sea_orm(string_value = "log")]
+    Log,
+    #[sea_orm(string_value = "file")]
+    File,
+}
+
+/// A file a user attached to prompts, kept in object storage and copied into the sandbox of
+/// each run whose prompt references it
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "prompt_attachment")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: Uuid,
+    /// User who uploaded the file; only their prompts may reference it
+    pub user_id: String,
+    pub file_name: String,
+    pub kind: AttachmentKind,
+    pub content_type: Option<String>,
+    pub size_bytes: i64,
+    /// Hex SHA-256 of the contents
+    pub sha256: String,
+    /// Object key of the contents in the blob store
+    #[sea_orm(column_type = "Text")]
+    pub blob_key: String,
+    pub created_at: DateTimeWithTimeZone,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
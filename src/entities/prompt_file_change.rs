@@ -0,0 +1,39 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// A file a prompt's run changed, from `git diff --numstat` between the commit the run
+/// started at and the one it ended at
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "prompt_file_change")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: Uuid,
+    pub prompt_id: Uuid,
+    pub session_id: Uuid,
+    /// Path in the repository; renames read `old => new`
+    #[sea_orm(column_type = "Text")]
+    pub path: String,
+    /// Lines added, None for binary files
+    pub insertions: Option<i32>,
+    /// Lines removed, None for binary files
+    pub deletions: Option<i32>,
+    pub created_at: DateTimeWithTimeZone,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::prompt::Entity",
+        from = "Column::PromptId",
+        to = "super::prompt::Column::Id"
+    )]
+    Prompt,
+}
+
+impl Related<super::prompt::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Prompt.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
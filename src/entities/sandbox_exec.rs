@@ -0,0 +1,31 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Audit record of a command an admin ran in a session's sandbox
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "sandbox_exec")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: Uuid,
+    pub session_id: Uuid,
+    /// Admin user id
+    pub requested_by: String,
+    #[sea_orm(column_type = "Text")]
+    pub command: String,
+    pub exec_dir: String,
+    /// None when the command did not complete
+    pub exit_code: Option<i64>,
+    /// Captured output, truncated
+    #[sea_orm(column_type = "Text", nullable)]
+    pub output: Option<String>,
+    /// Why the command could not be run or did not complete
+    #[sea_orm(column_type = "Text", nullable)]
+    pub error: Option<String>,
+    pub duration_ms: i64,
+    pub created_at: DateTimeWithTimeZone,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
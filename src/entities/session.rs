@@ -1,5 +1,6 @@
 use rocket_okapi::okapi::schemars::{self, JsonSchema};
 use sea_orm::entity::prelude::*;
+use sea_orm::Iterable;
 use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
@@ -36,6 +37,97 @@ pub struct Model {
     pub cancelled_by: Option<String>,
     #[sea_orm(nullable)]
     pub process_pid: Option<i32>,
+    #[sea_orm(column_type = "Text", nullable)]
+    pub status_message: Option<String>,
+    #[sea_orm(default_value = 0)]
+    pub sandbox_borrow_attempts: i32,
+    #[sea_orm(nullable)]
+    pub next_borrow_attempt_at: Option<DateTimeWithTimeZone>,
+    /// JSON array of user-defined tag strings
+    #[sea_orm(column_type = "JsonBinary")]
+    pub tags: Json,
+    /// Include/exclude globs limiting which paths Claude may change, see `PathPolicy`
+    #[sea_orm(column_type = "JsonBinary", nullable)]
+    pub path_policy: Option<Json>,
+    /// Title and branch are still placeholders, generated from the first prompt before dispatch
+    #[sea_orm(default_value = false)]
+    pub title_pending: bool,
+    /// SHA-256 of the token the sandbox agent of the current run uses to report status
+    pub agent_token_hash: Option<String>,
+    /// When the agent token stops being accepted, even while the run goes on
+    pub agent_token_expires_at: Option<DateTimeWithTimeZone>,
+    /// Organization of the creating user; members of it can read the session
+    pub org_id: Option<String>,
+    /// Idempotency key sent with every attempt to return the session's sandbox IP, kept until
+    /// the return is recorded so retries are recognised by the allocator
+    pub ip_return_key: Option<Uuid>,
+    /// Claude model requested at creation, the configured default when None
+    pub model: Option<ClaudeModel>,
+    /// How a requested cancellation stops the run, hard when None
+    pub cancellation_mode: Option<CancellationMode>,
+    /// When a soft cancellation asked the agent to wrap up; the process is killed once the
+    /// grace period has passed
+    pub cancel_signalled_at: Option<DateTimeWithTimeZone>,
+    /// Set on the tracking session of a fan-out: how many of its child sessions may hold a
+    /// sandbox at once. The tracking session itself never runs.
+    pub fan_out_limit: Option<i32>,
+    /// Network destinations the sandbox may reach during runs, see `EgressPolicy`
+    #[sea_orm(column_type = "JsonBinary", nullable)]
+    pub egress_policy: Option<Json>,
+    /// Firewall rules the last run applied, see `EffectiveEgressPolicy`
+    #[sea_orm(column_type = "JsonBinary", nullable)]
+    pub effective_egress_policy: Option<Json>,
+    /// Body generated for the session's pull request, see `pr_description`
+    #[sea_orm(column_type = "Text", nullable)]
+    pub pull_request_description: Option<String>,
+    /// Keep the sandbox through review so follow-up prompts reuse it; the IP is only returned
+    /// once the session is archived
+    pub keep_sandbox_until_archive: bool,
+    /// What the last check before returning the IP found in the sandbox, see `UnpushedWork`
+    #[sea_orm(column_type = "JsonBinary", nullable)]
+    pub unpushed_work: Option<Json>,
+    /// Sandbox region the user asked for, any region when None
+    pub region: Option<String>,
+    /// Region of the sandbox the session holds or last held, when the allocator reports one
+    pub sandbox_region: Option<String>,
+    /// Worker instance the session's prompts run on, see `bg_tasks::session_affinity`
+    pub worker_id: Option<String>,
+    /// When that worker last claimed the session; the claim lapses after the affinity TTL
+    pub worker_claimed_at: Option<DateTimeWithTimeZone>,
+    /// When the session last showed signs of life: a run started or a message came in
+    pub last_activity_at: Option<DateTimeWithTimeZone>,
+    /// Limits on the session's runs set at creation, see `SessionBudget`; the platform
+    /// defaults apply to limits it leaves out
+    #[sea_orm(column_type = "JsonBinary", nullable)]
+    pub budget: Option<Json>,
+    /// When the queued session is expected to start running, see `sandbox_queue`; None once
+    /// it left the queue
+    pub estimated_start_at: Option<DateTimeWithTimeZone>,
+    /// Process that spawned `process_pid`, see `worker_instance`; a PID is only signalled by
+    /// the instance that owns it
+    pub worker_instance_id: Option<String>,
+    /// What the sandbox the session holds supports, see `SandboxCapabilities`; rediscovered
+    /// when the session gets another sandbox
+    #[sea_orm(column_type = "JsonBinary", nullable)]
+    pub sandbox_capabilities: Option<Json>,
+    /// Latest compaction of the session's messages, see `message_compaction`; None until the
+    /// session first went over a soft limit
+    #[sea_orm(column_type = "JsonBinary", nullable)]
+    pub message_compaction: Option<Json>,
+    /// Failed attempts at generating the title and branch
+    pub title_attempts: i32,
+    /// When title generation is tried again after failing, None when it is not
+    pub title_retry_at: Option<DateTimeWithTimeZone>,
+    /// Disk used by the session's clone at the last check, see `workspace_disk`
+    pub workspace_disk_bytes: Option<i64>,
+    /// When `workspace_disk_bytes` was measured
+    pub workspace_disk_checked_at: Option<DateTimeWithTimeZone>,
+    /// Ingestion filter rules set at creation, see `ingestion_filter`; they override the
+    /// deployment's rules for the same event kinds
+    #[sea_orm(column_type = "JsonBinary", nullable)]
+    pub ingestion_filter: Option<Json>,
+    /// CLI messages of the session's runs dropped or aggregated by the ingestion filter
+    pub messages_filtered: i64,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -59,6 +151,8 @@ impl ActiveModelBehavior for ActiveModel {}
 pub enum UiStatus {
     #[sea_orm(string_value = "pending")]
     Pending,
+    #[sea_orm(string_value = "waiting_for_sandbox")]
+    WaitingForSandbox,
     #[sea_orm(string_value = "in_progress")]
     InProgress,
     #[sea_orm(string_value = "needs_review")]
@@ -67,6 +161,10 @@ pub enum UiStatus {
     NeedsReviewIpReturned,
     #[sea_orm(string_value = "archived")]
     Archived,
+    /// The sandbox holds commits or changes that were never pushed; the IP is kept until the
+    /// user pushes them or releases the sandbox
+    #[sea_orm(string_value = "needs_attention")]
+    NeedsAttention,
 }
 
 #[derive(
@@ -79,3 +177,79 @@ pub enum CancellationStatus {
     #[sea_orm(string_value = "cancelled")]
     Cancelled,
 }
+
+/// Claude models sessions can run on; the values are the model ids passed to the CLI
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    Serialize,
+    Deserialize,
+    EnumIter,
+    DeriveActiveEnum,
+    JsonSchema,
+)]
+#[sea_orm(rs_type = "String", db_type = "String(None)")]
+pub enum ClaudeModel {
+    #[sea_orm(string_value = "claude-sonnet-4-5")]
+    #[serde(rename = "claude-sonnet-4-5")]
+    ClaudeSonnet45,
+    #[sea_orm(string_value = "claude-opus-4-1")]
+    #[serde(rename = "claude-opus-4-1")]
+    ClaudeOpus41,
+    #[sea_orm(string_value = "claude-haiku-4-5")]
+    #[serde(rename = "claude-haiku-4-5")]
+    ClaudeHaiku45,
+}
+
+impl ClaudeModel {
+    /// Model id, as passed to the CLI's `--model`
+    pub fn id(&self) -> String {
+        self.to_value()
+    }
+}
+
+impl std::str::FromStr for ClaudeModel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::iter()
+            .find(|model| model.to_value() == s.trim())
+            .ok_or_else(|| format!("Unknown model: {}", s))
+    }
+}
+
+#[derive(
+    Debug, Clone, PartialEq, Eq, Serialize, Deserialize, EnumIter, DeriveActiveEnum, JsonSchema,
+)]
+#[sea_orm(rs_type = "String", db_type = "String(Some(50))")]
+pub enum CancellationMode {
+    /// The CLI process is terminated straight away
+    #[sea_orm(string_value = "hard")]
+    Hard,
+    /// The agent is asked to commit its work and summarize before the process is terminated
+    #[sea_orm(string_value = "soft")]
+    Soft,
+}
+
+/// Parsed from the `mode` query parameter, case-insensitively. Hard when the parameter is
+/// missing; any other value is rejected rather than falling back to a hard cancel.
+#[rocket::async_trait]
+impl<'v> rocket::form::FromFormField<'v> for CancellationMode {
+    fn from_value(field: rocket::form::ValueField<'v>) -> rocket::form::Result<'v, Self> {
+        Self::iter()
+            .find(|mode| mode.to_value().eq_ignore_ascii_case(field.value))
+            .ok_or_else(|| {
+                rocket::form::Error::validation("expected one of: hard, soft")
+                    .with_name(field.name)
+                    .into()
+            })
+    }
+
+    fn default() -> Option<Self> {
+        Some(CancellationMode::Hard)
+    }
+}
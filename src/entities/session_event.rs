@@ -0,0 +1,74 @@
+use rocket_okapi::okapi::schemars::{self, JsonSchema};
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::session::UiStatus;
+
+/// One entry in a session's lifecycle history, appended and never changed
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "session_event")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: Uuid,
+    pub session_id: Uuid,
+    pub event_type: SessionEventType,
+    /// Who caused the event, `user:<id>` or `system:<task>`
+    pub actor: String,
+    /// Status before a status change
+    pub from_status: Option<UiStatus>,
+    /// Status after a status change
+    pub to_status: Option<UiStatus>,
+    /// Event-specific details, e.g. the transition cause or the prompt id
+    #[sea_orm(column_type = "JsonBinary", nullable)]
+    pub metadata: Option<Json>,
+    pub created_at: DateTimeWithTimeZone,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::session::Entity",
+        from = "Column::SessionId",
+        to = "super::session::Column::Id"
+    )]
+    Session,
+}
+
+impl Related<super::session::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Session.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+#[derive(
+    Debug, Clone, PartialEq, Eq, Serialize, Deserialize, EnumIter, DeriveActiveEnum, JsonSchema,
+)]
+#[sea_orm(rs_type = "String", db_type = "String(Some(50))")]
+pub enum SessionEventType {
+    #[sea_orm(string_value = "created")]
+    Created,
+    /// `ui_status` changed; the cause is in the metadata
+    #[sea_orm(string_value = "status_changed")]
+    StatusChanged,
+    #[sea_orm(string_value = "prompt_added")]
+    PromptAdded,
+    #[sea_orm(string_value = "cancellation_requested")]
+    CancellationRequested,
+    /// The sandbox IP went back to the allocator without a status change, e.g. when archived
+    #[sea_orm(string_value = "ip_returned")]
+    IpReturned,
+    /// An attempt to return the sandbox IP failed and will be retried
+    #[sea_orm(string_value = "ip_return_failed")]
+    IpReturnFailed,
+    #[sea_orm(string_value = "deleted")]
+    Deleted,
+    /// A prompt's run changed files; counts and the prompt id are in the metadata
+    #[sea_orm(string_value = "files_changed")]
+    FilesChanged,
+    /// An admin released the sandbox with `POST /sessions/<id>/force-release`; the reason,
+    /// whether the allocator was called and the DLQ entries resolved are in the metadata
+    #[sea_orm(string_value = "ip_force_released")]
+    IpForceReleased,
+}
@@ -0,0 +1,40 @@
+use rocket_okapi::okapi::schemars::{self, JsonSchema};
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Record of a user's request to erase their data, kept after the data itself is gone
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "user_erasure")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: Uuid,
+    /// The user whose data is erased
+    pub user_id: String,
+    /// User id of whoever asked for the erasure
+    pub requested_by: String,
+    pub status: ErasureStatus,
+    /// What was erased so far, see `services::user_erasure::ErasureReport`
+    #[sea_orm(column_type = "JsonBinary")]
+    pub report: Json,
+    pub created_at: DateTimeWithTimeZone,
+    /// When the last session was deleted
+    pub completed_at: Option<DateTimeWithTimeZone>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+#[derive(
+    Debug, Clone, PartialEq, Eq, Serialize, Deserialize, EnumIter, DeriveActiveEnum, JsonSchema,
+)]
+#[sea_orm(rs_type = "String", db_type = "String(Some(50))")]
+pub enum ErasureStatus {
+    /// Some sessions still hold a sandbox or a running process; they are deleted once released
+    #[sea_orm(string_value = "pending")]
+    Pending,
+    /// Everything has been deleted
+    #[sea_orm(string_value = "completed")]
+    Completed,
+}
@@ -0,0 +1,24 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::session::ClaudeModel;
+
+/// A user's defaults for new sessions and runs. Users without a row use the server defaults.
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "user_settings")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub user_id: String,
+    /// Model sessions run on when created without one; `CLAUDE_MODEL` when None
+    pub default_model: Option<ClaudeModel>,
+    /// JSON array of `AgentTool`s the CLI may use; every optional tool when None
+    #[sea_orm(column_type = "JsonBinary", nullable)]
+    pub allowed_tools: Option<Json>,
+    pub created_at: DateTimeWithTimeZone,
+    pub updated_at: DateTimeWithTimeZone,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,41 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// A sandbox borrowed ahead of demand, waiting for a session to claim it
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "warm_sandbox")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: Uuid,
+    /// Repo cloned into the sandbox, None for the generic pool
+    pub repo: Option<String>,
+    /// The allocator's borrow, in the shape stored on sessions: `{"item", "borrow_token"}`
+    #[sea_orm(column_type = "JsonBinary")]
+    pub sbx_config: Json,
+    pub status: WarmSandboxStatus,
+    /// GitHub host `gh` is logged in to in the sandbox
+    pub authenticated_host: Option<String>,
+    /// Directory under /home/gem holding the clone of `repo`
+    pub repo_dir: Option<String>,
+    /// Idempotency key of returning the IP once the sandbox expires
+    pub return_key: Option<Uuid>,
+    pub created_at: DateTimeWithTimeZone,
+    /// When an unclaimed sandbox is returned to the allocator
+    pub expires_at: DateTimeWithTimeZone,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, EnumIter, DeriveActiveEnum)]
+#[sea_orm(rs_type = "String", db_type = "String(Some(50))")]
+pub enum WarmSandboxStatus {
+    /// Borrowed, still being authenticated or cloned into
+    #[sea_orm(string_value = "warming")]
+    Warming,
+    /// Ready to be claimed
+    #[sea_orm(string_value = "ready")]
+    Ready,
+}
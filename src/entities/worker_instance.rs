@@ -0,0 +1,22 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// A running backend process, kept alive by its cancellation enforcer.
+///
+/// Sessions record the instance that spawned their CLI process in `worker_instance_id`. An
+/// instance that stopped reporting is presumed dead and its sessions are taken over.
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "worker_instance")]
+pub struct Model {
+    /// `WORKER_ID` plus a per-process suffix, so a restarted replica is a new instance
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: String,
+    pub worker_id: String,
+    pub started_at: DateTimeWithTimeZone,
+    pub last_seen_at: DateTimeWithTimeZone,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -14,6 +14,9 @@ pub struct Error {
     pub err: String,
     /// The description of the error
     pub msg: Option<String>,
+    /// Structured context for the error, e.g. the session a conflict is with
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<serde_json::Value>,
     // HTTP Status Code returned
     #[serde(skip)]
     pub http_status_code: u16,
@@ -35,6 +38,17 @@ impl OpenApiResponderInner for Error {
                 ..Default::default()
             }),
         );
+        responses.insert(
+            "403".to_string(),
+            RefOr::Object(OpenApiReponse {
+                description: "\
+                # [403 Forbidden](https://developer.mozilla.org/en-US/docs/Web/HTTP/Status/403)\n\
+                This response is given when you are not allowed to change the requested resource. \
+                "
+                .to_string(),
+                ..Default::default()
+            }),
+        );
         responses.insert(
             "404".to_string(),
             RefOr::Object(OpenApiReponse {
@@ -46,6 +60,26 @@ impl OpenApiResponderInner for Error {
                 ..Default::default()
             }),
         );
+        responses.insert(
+            "409".to_string(),
+            RefOr::Object(OpenApiReponse {
+                description: "\
+                # [409 Conflict](https://developer.mozilla.org/en-US/docs/Web/HTTP/Status/409)\n\
+                This response is given when the request conflicts with the current state of another resource. \
+                ".to_string(),
+                ..Default::default()
+            }),
+        );
+        responses.insert(
+            "413".to_string(),
+            RefOr::Object(OpenApiReponse {
+                description: "\
+                # [413 Payload Too Large](https://developer.mozilla.org/en-US/docs/Web/HTTP/Status/413)\n\
+                This response is given when the request body or a JSON document in it is larger than allowed. \
+                ".to_string(),
+                ..Default::default()
+            }),
+        );
         responses.insert(
             "422".to_string(),
             RefOr::Object(OpenApiReponse {
@@ -105,11 +139,13 @@ impl From<rocket::serde::json::Error<'_>> for Error {
             Io(io_error) => Error {
                 err: "IO Error".to_owned(),
                 msg: Some(io_error.to_string()),
+                details: None,
                 http_status_code: 422,
             },
             Parse(_raw_data, parse_error) => Error {
                 err: "Parse Error".to_owned(),
                 msg: Some(parse_error.to_string()),
+                details: None,
                 http_status_code: 422,
             },
         }
@@ -121,16 +157,134 @@ impl From<redis::RedisError> for Error {
         Error {
             err: "Redis Error".to_owned(),
             msg: Some(err.to_string()),
+            details: None,
             http_status_code: 500,
         }
     }
 }
 
+impl From<crate::services::github::GithubError> for Error {
+    fn from(err: crate::services::github::GithubError) -> Self {
+        use crate::services::github::GithubError::*;
+        let http_status_code = match &err {
+            Token(_) => 400,
+            NotFound(_) => 404,
+            RateLimited { .. } => 429,
+            Request(_) | Status { .. } => 502,
+        };
+        Error {
+            err: "GitHub Error".to_owned(),
+            msg: Some(err.to_string()),
+            details: None,
+            http_status_code,
+        }
+    }
+}
+
+impl From<crate::services::json_guard::JsonLimitError> for Error {
+    fn from(err: crate::services::json_guard::JsonLimitError) -> Self {
+        use crate::services::json_guard::JsonLimitError::*;
+        match &err {
+            TooLarge { .. } => Error::payload_too_large(err.to_string()),
+            TooDeep { .. } => Error {
+                err: "Unprocessable Entity".to_owned(),
+                msg: Some(err.to_string()),
+                details: None,
+                http_status_code: 422,
+            },
+        }
+    }
+}
+
+impl From<crate::services::idempotency::IdempotencyError> for Error {
+    fn from(err: crate::services::idempotency::IdempotencyError) -> Self {
+        use crate::services::idempotency::IdempotencyError::*;
+        match &err {
+            InvalidKey => Error::bad_request(err.to_string()),
+            InProgress => {
+                Error::conflict(err.to_string(), serde_json::json!({ "retryable": true }))
+            }
+            Mismatch => Error {
+                err: "Unprocessable Entity".to_owned(),
+                msg: Some(err.to_string()),
+                details: None,
+                http_status_code: 422,
+            },
+            Database(e) => Error::database_error(e.to_string()),
+        }
+    }
+}
+
+impl From<crate::services::prompt_attachments::AttachmentError> for Error {
+    fn from(err: crate::services::prompt_attachments::AttachmentError) -> Self {
+        use crate::services::prompt_attachments::AttachmentError::*;
+        match &err {
+            InvalidFileName(_) | NotFound(_) => Error::bad_request(err.to_string()),
+            NotConfigured | Storage(_) | Read(_) => Error::internal_server_error(err.to_string()),
+            Database(e) => Error::database_error(e.to_string()),
+        }
+    }
+}
+
+impl From<crate::services::unpushed_work::UnpushedWorkError> for Error {
+    fn from(err: crate::services::unpushed_work::UnpushedWorkError) -> Self {
+        use crate::services::unpushed_work::UnpushedWorkError::*;
+        match &err {
+            NoSandbox(_) => Error::conflict(err.to_string(), serde_json::json!({})),
+            Sandbox(_) | Command { .. } => Error {
+                err: "Sandbox Error".to_owned(),
+                msg: Some(err.to_string()),
+                details: None,
+                http_status_code: 502,
+            },
+        }
+    }
+}
+
+/// JSON body for request bodies Rocket refused to read because of its `json` limit
+#[catch(413)]
+pub fn payload_too_large_catcher() -> Error {
+    Error::payload_too_large(format!(
+        "Request body is larger than the {} byte limit",
+        crate::config::get().request_limits.json_body_bytes
+    ))
+}
+
+/// JSON body for request bodies Rocket could not parse
+#[catch(422)]
+pub fn unprocessable_entity_catcher() -> Error {
+    Error {
+        err: "Unprocessable Entity".to_owned(),
+        msg: Some("Request body could not be parsed".to_string()),
+        details: None,
+        http_status_code: 422,
+    }
+}
+
 impl Error {
+    pub fn conflict(msg: String, details: serde_json::Value) -> Self {
+        Error {
+            err: "Conflict".to_owned(),
+            msg: Some(msg),
+            details: Some(details),
+            http_status_code: 409,
+        }
+    }
+
+    pub fn payload_too_large(msg: String) -> Self {
+        Error {
+            err: "Payload Too Large".to_owned(),
+            msg: Some(msg),
+            details: None,
+            http_status_code: 413,
+        }
+    }
+
     pub fn database_error(msg: String) -> Self {
         Error {
             err: "Database Error".to_owned(),
             msg: Some(msg),
+            details: None,
             http_status_code: 500,
         }
     }
@@ -139,22 +293,43 @@ impl Error {
         Error {
             err: "Bad Request".to_owned(),
             msg: Some(msg),
+            details: None,
             http_status_code: 400,
         }
     }
 
+    pub fn forbidden(msg: String) -> Self {
+        Error {
+            err: "Forbidden".to_owned(),
+            msg: Some(msg),
+            details: None,
+            http_status_code: 403,
+        }
+    }
+
     pub fn not_found(msg: String) -> Self {
         Error {
             err: "Not Found".to_owned(),
             msg: Some(msg),
+            details: None,
             http_status_code: 404,
         }
     }
 
+    pub fn bad_gateway(msg: String) -> Self {
+        Error {
+            err: "Bad Gateway".to_owned(),
+            msg: Some(msg),
+            details: None,
+            http_status_code: 502,
+        }
+    }
+
     pub fn internal_server_error(msg: String) -> Self {
         Error {
             err: "Internal Server Error".to_owned(),
             msg: Some(msg),
+            details: None,
             http_status_code: 500,
         }
     }
@@ -0,0 +1,465 @@
+//! gRPC server for sessions, prompts and messages, built with the `grpc` feature.
+//!
+//! Each call authenticates its `authorization` metadata like the REST guard does and then runs
+//! the Rocket handler of the matching route, so both APIs share validation, access rules and
+//! side effects; only the conversion between the handlers' DTOs and the messages in
+//! `proto/prompt_backend.proto` lives here. The server listens on `GRPC_PORT` next to Rocket
+//! when `GRPC_ENABLED` is set or the binary runs with `--grpc`.
+
+// tonic's handlers return `Status` errors, whatever their size
+#![allow(clippy::result_large_err)]
+
+use rocket::futures::{Stream, StreamExt};
+use rocket::serde::json::Json;
+use rocket::State;
+use sea_orm::DatabaseConnection;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use tonic::metadata::MetadataMap;
+use tonic::{Request, Response, Status};
+use uuid::Uuid;
+
+use crate::auth::{AuthenticatedUser, JwksCache};
+use crate::db::ReadDb;
+use crate::error::Error;
+use crate::handlers::messages::{self, MessageDto};
+use crate::handlers::prompts::{self, CreatePromptInput, PromptDto};
+use crate::handlers::sessions::{self, CreateSessionInput, SessionDto, UpdateSessionInput};
+use crate::services::idempotency::{self, IdempotencyKey};
+
+pub mod proto {
+    tonic::include_proto!("prompt_backend.v1");
+}
+
+use proto::prompt_backend_server::{PromptBackend, PromptBackendServer};
+
+/// The gRPC service, holding the same state Rocket manages
+#[derive(Clone)]
+pub struct GrpcService {
+    db: DatabaseConnection,
+    read_db: ReadDb,
+    /// Reads that must see a write just made, which the replica may not have yet
+    primary_reads: ReadDb,
+    jwks_cache: JwksCache,
+}
+
+impl GrpcService {
+    pub async fn new(db: DatabaseConnection, read_db: ReadDb, jwks_cache: JwksCache) -> Self {
+        GrpcService {
+            primary_reads: ReadDb::connect(db.clone(), None).await,
+            db,
+            read_db,
+            jwks_cache,
+        }
+    }
+
+    async fn user(&self, metadata: &MetadataMap) -> Result<AuthenticatedUser, Status> {
+        let token = metadata
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| Status::unauthenticated("Missing bearer token"))?;
+        AuthenticatedUser::from_bearer(&self.jwks_cache, token)
+            .await
+            .map_err(Status::unauthenticated)
+    }
+
+    fn db(&self) -> &State<DatabaseConnection> {
+        State::from(&self.db)
+    }
+
+    fn read_db(&self) -> &State<ReadDb> {
+        State::from(&self.read_db)
+    }
+
+    async fn session(&self, user: AuthenticatedUser, id: String) -> Result<SessionDto, Status> {
+        let output = sessions::read(user, State::from(&self.primary_reads), id, None).await?;
+        Ok(output.into_inner().session.into_inner())
+    }
+
+    async fn prompt(&self, user: AuthenticatedUser, id: String) -> Result<PromptDto, Status> {
+        let output = prompts::read(user, State::from(&self.primary_reads), id).await?;
+        Ok(output.into_inner().prompt)
+    }
+}
+
+/// Serve `service` on `addr` until the process exits
+pub async fn serve(service: GrpcService, addr: SocketAddr) -> anyhow::Result<()> {
+    tracing::info!("Starting gRPC server on {}", addr);
+    tonic::transport::Server::builder()
+        .add_service(PromptBackendServer::new(service))
+        .serve(addr)
+        .await?;
+    Ok(())
+}
+
+impl From<Error> for Status {
+    fn from(error: Error) -> Self {
+        let message = error.msg.unwrap_or(error.err);
+        match error.http_status_code {
+            400 | 413 | 422 => Status::invalid_argument(message),
+            401 => Status::unauthenticated(message),
+            403 => Status::permission_denied(message),
+            404 => Status::not_found(message),
+            409 => Status::failed_precondition(message),
+            429 => Status::resource_exhausted(message),
+            502..=504 => Status::unavailable(message),
+            _ => Status::internal(message),
+        }
+    }
+}
+
+/// Name a REST enum value serializes to, e.g. `InProgress`
+fn enum_name<T: Serialize>(value: &T) -> String {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(name)) => name,
+        _ => String::new(),
+    }
+}
+
+fn parse_enum<T: DeserializeOwned>(field: &str, name: String) -> Result<T, Status> {
+    serde_json::from_value(serde_json::Value::String(name.clone()))
+        .map_err(|_| Status::invalid_argument(format!("Unknown {} {:?}", field, name)))
+}
+
+/// JSON as a protobuf `Value`, for `data` fields
+pub fn to_proto_value(value: serde_json::Value) -> prost_types::Value {
+    use prost_types::value::Kind;
+    let kind = match value {
+        serde_json::Value::Null => Kind::NullValue(0),
+        serde_json::Value::Bool(b) => Kind::BoolValue(b),
+        serde_json::Value::Number(n) => Kind::NumberValue(n.as_f64().unwrap_or_default()),
+        serde_json::Value::String(s) => Kind::StringValue(s),
+        serde_json::Value::Array(items) => Kind::ListValue(prost_types::ListValue {
+            values: items.into_iter().map(to_proto_value).collect(),
+        }),
+        serde_json::Value::Object(map) => Kind::StructValue(prost_types::Struct {
+            fields: map
+                .into_iter()
+                .map(|(key, value)| (key, to_proto_value(value)))
+                .collect(),
+        }),
+    };
+    prost_types::Value { kind: Some(kind) }
+}
+
+pub fn from_proto_value(value: prost_types::Value) -> serde_json::Value {
+    use prost_types::value::Kind;
+    match value.kind {
+        None | Some(Kind::NullValue(_)) => serde_json::Value::Null,
+        Some(Kind::BoolValue(b)) => b.into(),
+        // Whole numbers stay integers, as JSON clients sent them
+        Some(Kind::NumberValue(n)) if n.fract() == 0.0 && n.abs() < 2f64.powi(53) => {
+            (n as i64).into()
+        }
+        Some(Kind::NumberValue(n)) => n.into(),
+        Some(Kind::StringValue(s)) => s.into(),
+        Some(Kind::ListValue(list)) => list.values.into_iter().map(from_proto_value).collect(),
+        Some(Kind::StructValue(object)) => serde_json::Value::Object(
+            object
+                .fields
+                .into_iter()
+                .map(|(key, value)| (key, from_proto_value(value)))
+                .collect(),
+        ),
+    }
+}
+
+impl From<SessionDto> for proto::Session {
+    fn from(dto: SessionDto) -> Self {
+        proto::Session {
+            id: dto.id,
+            repo: dto.repo,
+            target_branch: dto.target_branch,
+            branch: dto.branch,
+            title: dto.title,
+            ui_status: enum_name(&dto.ui_status),
+            parent: dto.parent,
+            tags: dto.tags,
+            model: enum_name(&dto.model),
+            status_message: dto.status_message,
+            created_at: dto.created_at,
+            updated_at: dto.updated_at,
+        }
+    }
+}
+
+impl From<PromptDto> for proto::Prompt {
+    fn from(dto: PromptDto) -> Self {
+        proto::Prompt {
+            id: dto.id,
+            session_id: dto.session_id,
+            data: Some(to_proto_value(dto.data)),
+            priority: enum_name(&dto.priority),
+            progress: dto.progress.into(),
+            error_kind: dto.error_kind.as_ref().map(enum_name),
+            draft: dto.draft,
+            created_at: dto.created_at,
+            updated_at: dto.updated_at,
+            completed_at: dto.completed_at,
+        }
+    }
+}
+
+impl From<MessageDto> for proto::Message {
+    fn from(dto: MessageDto) -> Self {
+        proto::Message {
+            id: dto.id,
+            prompt_id: dto.prompt_id,
+            seq: dto.seq,
+            data: Some(to_proto_value(dto.data)),
+            created_at: dto.created_at,
+        }
+    }
+}
+
+type MessageStream = Pin<Box<dyn Stream<Item = Result<proto::Message, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl PromptBackend for GrpcService {
+    async fn create_session(
+        &self,
+        request: Request<proto::CreateSessionRequest>,
+    ) -> Result<Response<proto::Session>, Status> {
+        let user = self.user(request.metadata()).await?;
+        let request = request.into_inner();
+        let input = CreateSessionInput {
+            parent: request.parent,
+            repo: request.repo,
+            target_branch: request.target_branch,
+            path_policy: None,
+            egress_policy: None,
+            model: request
+                .model
+                .map(|model| parse_enum("model", model))
+                .transpose()?,
+            keep_sandbox_until_archive: None,
+            region: None,
+            budget: None,
+            ingestion_filter: None,
+        };
+        let created = sessions::create(user.clone(), self.db(), Json(input)).await?;
+        let session = self.session(user, created.into_inner().id).await?;
+        Ok(Response::new(session.into()))
+    }
+
+    async fn get_session(
+        &self,
+        request: Request<proto::GetSessionRequest>,
+    ) -> Result<Response<proto::Session>, Status> {
+        let user = self.user(request.metadata()).await?;
+        let output = sessions::read(user, self.read_db(), request.into_inner().id, None).await?;
+        Ok(Response::new(
+            output.into_inner().session.into_inner().into(),
+        ))
+    }
+
+    async fn list_sessions(
+        &self,
+        request: Request<proto::ListSessionsRequest>,
+    ) -> Result<Response<proto::ListSessionsResponse>, Status> {
+        let user = self.user(request.metadata()).await?;
+        let output = sessions::list(user, self.read_db(), request.into_inner().tag, None).await?;
+        Ok(Response::new(proto::ListSessionsResponse {
+            sessions: output
+                .into_inner()
+                .sessions
+                .into_iter()
+                .map(|session| session.into_inner().into())
+                .collect(),
+        }))
+    }
+
+    async fn update_session(
+        &self,
+        request: Request<proto::UpdateSessionRequest>,
+    ) -> Result<Response<proto::Session>, Status> {
+        let user = self.user(request.metadata()).await?;
+        let request = request.into_inner();
+        let input = UpdateSessionInput {
+            id: request.id.clone(),
+            sbx_config: None,
+            parent: None,
+            branch: None,
+            repo: request.repo,
+            target_branch: request.target_branch,
+            title: request.title,
+            ui_status: None,
+            path_policy: None,
+            egress_policy: None,
+            keep_sandbox_until_archive: None,
+        };
+        sessions::update(user.clone(), self.db(), request.id.clone(), Json(input)).await?;
+        let session = self.session(user, request.id).await?;
+        Ok(Response::new(session.into()))
+    }
+
+    async fn delete_session(
+        &self,
+        request: Request<proto::DeleteSessionRequest>,
+    ) -> Result<Response<proto::DeleteResponse>, Status> {
+        let user = self.user(request.metadata()).await?;
+        let output = sessions::delete(user, self.db(), request.into_inner().id).await?;
+        Ok(Response::new(proto::DeleteResponse {
+            deleted: output.into_inner().success,
+        }))
+    }
+
+    async fn create_prompt(
+        &self,
+        request: Request<proto::CreatePromptRequest>,
+    ) -> Result<Response<proto::Prompt>, Status> {
+        let user = self.user(request.metadata()).await?;
+        let key = request
+            .metadata()
+            .get(idempotency::HEADER.to_ascii_lowercase().as_str())
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let request = request.into_inner();
+        let input = CreatePromptInput {
+            session_id: request.session_id,
+            data: request
+                .data
+                .map(from_proto_value)
+                .unwrap_or(serde_json::Value::Null),
+            priority: request
+                .priority
+                .map(|priority| parse_enum("priority", priority))
+                .transpose()?,
+            include_history: None,
+            history_depth: None,
+            draft: request.draft,
+        };
+        let created = prompts::create(
+            user.clone(),
+            self.db(),
+            IdempotencyKey::new(key),
+            Json(input),
+        )
+        .await?;
+        let prompt = self.prompt(user, created.into_inner().id).await?;
+        Ok(Response::new(prompt.into()))
+    }
+
+    async fn get_prompt(
+        &self,
+        request: Request<proto::GetPromptRequest>,
+    ) -> Result<Response<proto::Prompt>, Status> {
+        let user = self.user(request.metadata()).await?;
+        let output = prompts::read(user, self.read_db(), request.into_inner().id).await?;
+        Ok(Response::new(output.into_inner().prompt.into()))
+    }
+
+    async fn list_prompts(
+        &self,
+        request: Request<proto::ListPromptsRequest>,
+    ) -> Result<Response<proto::ListPromptsResponse>, Status> {
+        let user = self.user(request.metadata()).await?;
+        let output = prompts::list(user, self.read_db(), request.into_inner().session_id).await?;
+        Ok(Response::new(proto::ListPromptsResponse {
+            prompts: output
+                .into_inner()
+                .prompts
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+        }))
+    }
+
+    async fn delete_prompt(
+        &self,
+        request: Request<proto::DeletePromptRequest>,
+    ) -> Result<Response<proto::DeleteResponse>, Status> {
+        let user = self.user(request.metadata()).await?;
+        let output = prompts::delete(user, self.db(), request.into_inner().id).await?;
+        Ok(Response::new(proto::DeleteResponse {
+            deleted: output.into_inner().success,
+        }))
+    }
+
+    async fn get_message(
+        &self,
+        request: Request<proto::GetMessageRequest>,
+    ) -> Result<Response<proto::Message>, Status> {
+        let user = self.user(request.metadata()).await?;
+        let output = messages::read(user, self.read_db(), request.into_inner().id, None).await?;
+        Ok(Response::new(
+            output.into_inner().message.into_inner().into(),
+        ))
+    }
+
+    async fn list_messages(
+        &self,
+        request: Request<proto::ListMessagesRequest>,
+    ) -> Result<Response<proto::ListMessagesResponse>, Status> {
+        let user = self.user(request.metadata()).await?;
+        let output =
+            messages::list(user, self.read_db(), request.into_inner().prompt_id, None).await?;
+        Ok(Response::new(proto::ListMessagesResponse {
+            messages: output
+                .into_inner()
+                .messages
+                .into_iter()
+                .map(|message| message.into_inner().into())
+                .collect(),
+        }))
+    }
+
+    async fn delete_message(
+        &self,
+        request: Request<proto::DeleteMessageRequest>,
+    ) -> Result<Response<proto::DeleteResponse>, Status> {
+        let user = self.user(request.metadata()).await?;
+        let output = messages::delete(user, self.db(), request.into_inner().id).await?;
+        Ok(Response::new(proto::DeleteResponse {
+            deleted: output.into_inner().success,
+        }))
+    }
+
+    type StreamSessionMessagesStream = MessageStream;
+
+    async fn stream_session_messages(
+        &self,
+        request: Request<proto::StreamSessionMessagesRequest>,
+    ) -> Result<Response<MessageStream>, Status> {
+        let user = self.user(request.metadata()).await?;
+        let request = request.into_inner();
+        // The same visibility check as the SSE route
+        self.session(user, request.session_id.clone()).await?;
+        let session_id = Uuid::parse_str(&request.session_id)
+            .map_err(|_| Status::invalid_argument("Invalid UUID format"))?;
+
+        let messages = messages::follow(
+            self.read_db.conn().clone(),
+            session_id,
+            request.after_seq.unwrap_or(0),
+            std::future::pending(),
+        )
+        .map(|dto| Ok(dto.into()));
+        Ok(Response::new(Box::pin(messages)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_json_round_trips_through_protobuf_values() {
+        let data = json!({
+            "messages": [{"role": "user", "content": [{"type": "text", "text": "hi"}]}],
+            "count": 3,
+            "ratio": 0.5,
+            "done": false,
+            "missing": null,
+        });
+        assert_eq!(from_proto_value(to_proto_value(data.clone())), data);
+        assert_eq!(
+            Status::from(Error::not_found("Session not found".to_string())).code(),
+            tonic::Code::NotFound
+        );
+    }
+}
@@ -0,0 +1,888 @@
+use chrono::Utc;
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::State;
+use rocket_okapi::okapi::schemars::JsonSchema;
+use rocket_okapi::openapi;
+use sea_orm::{ActiveModelTrait, DatabaseConnection, EntityTrait, QueryOrder, Set};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::auth::AdminUser;
+use crate::bg_tasks::worker_registry::{self, WorkerStatus};
+use crate::entities::operational_event::{self, OperationalEventKind};
+use crate::entities::organization::{self, Entity as Organization};
+use crate::entities::poller_control::Model as PollerControlModel;
+use crate::entities::prompt::Entity as Prompt;
+use crate::entities::sandbox_exec::Model as SandboxExecModel;
+use crate::entities::session::Entity as Session;
+use crate::entities::session::UiStatus;
+use crate::error::{Error, OResult};
+use crate::services::admin_overview::{self, RECENT_WINDOW};
+use crate::services::deprovision::{self, DeprovisionSummary};
+use crate::services::entity_lookup::{self, EntityKind};
+use crate::services::force_release::{self, ForceReleaseError, ForceReleaseSummary};
+use crate::services::integrity::{detect_orphans, repair_orphans, OrphanCounts};
+use crate::services::message_blobs;
+use crate::services::operational_events::{self, EventFilter};
+use crate::services::output_log::{self, RecoveryCounts, RecoveryError};
+use crate::services::poller_control;
+use crate::services::sandbox_exec::{self, SandboxExecError};
+use crate::services::session_state_machine::Actor;
+
+/// Messages offloaded per call when no limit is given
+const DEFAULT_OFFLOAD_BATCH: u64 = 100;
+
+/// Longest command accepted by the sandbox exec endpoint, in characters
+const MAX_EXEC_COMMAND_LENGTH: usize = 10_000;
+
+/// Operational events listed when no limit is given
+const DEFAULT_EVENTS_LIMIT: u64 = 100;
+
+/// Most operational events one request returns
+const MAX_EVENTS_LIMIT: u64 = 500;
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct IntegrityReportOutput {
+    /// Prompts whose session no longer exists
+    pub orphaned_prompts: u64,
+    /// Messages whose prompt no longer exists
+    pub orphaned_messages: u64,
+    /// Pending DLQ entries whose session or prompt no longer exists
+    pub orphaned_dlq_entries: u64,
+}
+
+impl From<OrphanCounts> for IntegrityReportOutput {
+    fn from(counts: OrphanCounts) -> Self {
+        IntegrityReportOutput {
+            orphaned_prompts: counts.prompts,
+            orphaned_messages: counts.messages,
+            orphaned_dlq_entries: counts.dlq_entries,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct OperationalEventDto {
+    pub id: String,
+    /// Loop or worker that hit the error, e.g. `ip_return_poller`
+    pub source: String,
+    pub kind: OperationalEventKind,
+    pub message: String,
+    /// Details of the latest occurrence, e.g. the session it was about
+    pub context: Option<serde_json::Value>,
+    /// Occurrences between first_seen_at and last_seen_at
+    pub count: i32,
+    pub first_seen_at: String,
+    pub last_seen_at: String,
+}
+
+impl From<operational_event::Model> for OperationalEventDto {
+    fn from(model: operational_event::Model) -> Self {
+        OperationalEventDto {
+            id: model.id.to_string(),
+            source: model.source,
+            kind: model.kind,
+            message: model.message,
+            context: model.context,
+            count: model.count,
+            first_seen_at: model.first_seen_at.to_rfc3339(),
+            last_seen_at: model.last_seen_at.to_rfc3339(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct ListOperationalEventsOutput {
+    pub events: Vec<OperationalEventDto>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct OffloadMessagesOutput {
+    /// Messages moved to object storage in this batch
+    pub offloaded: u64,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct RecoverOutputOutput {
+    /// Messages in the prompt's output log
+    pub logged: u64,
+    /// Messages the prompt already had
+    pub stored: u64,
+    /// Messages missing from the database that were stored from the log
+    pub backfilled: u64,
+}
+
+impl From<RecoveryCounts> for RecoverOutputOutput {
+    fn from(counts: RecoveryCounts) -> Self {
+        RecoverOutputOutput {
+            logged: counts.logged,
+            stored: counts.stored,
+            backfilled: counts.backfilled,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct DeprovisionUserOutput {
+    pub user_id: String,
+    /// Running or queued sessions whose cancellation was requested
+    pub sessions_cancelled: i32,
+    /// Reviewed sessions archived so their sandbox IPs are returned
+    pub sessions_archived: i32,
+    /// Sessions soft-deleted
+    pub sessions_deleted: i32,
+}
+
+impl DeprovisionUserOutput {
+    pub fn new(user_id: String, summary: DeprovisionSummary) -> Self {
+        DeprovisionUserOutput {
+            user_id,
+            sessions_cancelled: summary.sessions_cancelled,
+            sessions_archived: summary.sessions_archived,
+            sessions_deleted: summary.sessions_deleted,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct ForceReleaseInput {
+    /// Archive the session without handing its IP back to the allocator, for items the
+    /// allocator no longer knows or that were returned by hand
+    #[serde(default)]
+    pub skip_allocator: bool,
+    /// Why the sandbox is released, kept in the session's event history and the DLQ notes
+    pub reason: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct ForceReleaseOutput {
+    pub session_id: String,
+    pub ui_status: UiStatus,
+    /// Whether the IP was handed back to the allocator
+    pub allocator_called: bool,
+    /// IDs of the pending IP return DLQ entries marked resolved
+    pub dlq_entries_resolved: Vec<String>,
+}
+
+impl ForceReleaseOutput {
+    pub fn new(session_id: uuid::Uuid, ui_status: UiStatus, summary: ForceReleaseSummary) -> Self {
+        ForceReleaseOutput {
+            session_id: session_id.to_string(),
+            ui_status,
+            allocator_called: summary.allocator_called,
+            dlq_entries_resolved: summary
+                .dlq_entries_resolved
+                .iter()
+                .map(uuid::Uuid::to_string)
+                .collect(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct OrganizationDto {
+    /// Organization id as it appears in users' organization claim
+    pub id: String,
+    pub name: Option<String>,
+    pub max_active_sessions: Option<i32>,
+    pub monthly_budget_usd: Option<f64>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+impl From<organization::Model> for OrganizationDto {
+    fn from(model: organization::Model) -> Self {
+        OrganizationDto {
+            id: model.id,
+            name: model.name,
+            max_active_sessions: model.max_active_sessions,
+            monthly_budget_usd: model.monthly_budget_usd,
+            created_at: model.created_at.to_string(),
+            updated_at: model.updated_at.to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct ListOrganizationsOutput {
+    pub organizations: Vec<OrganizationDto>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct UpdateOrganizationInput {
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Sessions of the organization that may be queued or running at once; omit for no limit
+    #[serde(default)]
+    pub max_active_sessions: Option<u32>,
+    /// Monthly spend allowed across the organization's sessions in USD; omit for no limit
+    #[serde(default)]
+    pub monthly_budget_usd: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct SandboxExecInput {
+    pub command: String,
+    /// Working directory, defaults to the session's repo clone
+    pub exec_dir: Option<String>,
+    /// Seconds before the command is abandoned, default 15 and at most 45
+    pub timeout_secs: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct SandboxExecOutput {
+    /// ID of the audit record
+    pub id: String,
+    pub session_id: String,
+    pub command: String,
+    pub exec_dir: String,
+    /// Null when the command did not complete
+    pub exit_code: Option<i64>,
+    /// Captured output, truncated to 64 KiB
+    pub output: Option<String>,
+    /// Why the command could not be run or did not complete
+    pub error: Option<String>,
+    pub duration_ms: i64,
+}
+
+impl From<SandboxExecModel> for SandboxExecOutput {
+    fn from(model: SandboxExecModel) -> Self {
+        SandboxExecOutput {
+            id: model.id.to_string(),
+            session_id: model.session_id.to_string(),
+            command: model.command,
+            exec_dir: model.exec_dir,
+            exit_code: model.exit_code,
+            output: model.output,
+            error: model.error,
+            duration_ms: model.duration_ms,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct WorkerStatusDto {
+    pub name: String,
+    pub interval_seconds: u64,
+    pub started_at: String,
+    pub last_success_at: Option<String>,
+    pub items_processed: u64,
+    pub iterations: u64,
+    pub last_error: Option<String>,
+    pub last_error_at: Option<String>,
+    /// True when the loop has gone several intervals without a successful iteration
+    pub stale: bool,
+    /// True while the loop is paused with `POST /admin/pollers/<name>/pause`
+    pub paused: bool,
+}
+
+impl From<WorkerStatus> for WorkerStatusDto {
+    fn from(status: WorkerStatus) -> Self {
+        WorkerStatusDto {
+            name: status.name.to_string(),
+            interval_seconds: status.interval.as_secs(),
+            stale: status.is_stale(chrono::Utc::now()),
+            paused: status.paused,
+            started_at: status.started_at.to_rfc3339(),
+            last_success_at: status.last_success_at.map(|t| t.to_rfc3339()),
+            items_processed: status.items_processed,
+            iterations: status.iterations,
+            last_error: status.last_error,
+            last_error_at: status.last_error_at.map(|t| t.to_rfc3339()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct ListWorkersOutput {
+    pub workers: Vec<WorkerStatusDto>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct PollerStateOutput {
+    pub name: String,
+    pub paused: bool,
+    /// Admin who last paused or resumed the poller
+    pub updated_by: Option<String>,
+    pub updated_at: String,
+}
+
+impl From<PollerControlModel> for PollerStateOutput {
+    fn from(model: PollerControlModel) -> Self {
+        PollerStateOutput {
+            name: model.name,
+            paused: model.paused,
+            updated_by: model.updated_by,
+            updated_at: model.updated_at.to_rfc3339(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct SessionStatusCount {
+    pub ui_status: UiStatus,
+    pub sessions: i64,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct RecentRunsDto {
+    /// Length of the window, counted back from `collected_at`
+    pub window_seconds: i64,
+    /// Runs started in the window that completed
+    pub completed: i64,
+    /// Runs started in the window that failed; cancellations are not failures
+    pub failed: i64,
+    /// `failed / (completed + failed)`, null when no run finished
+    pub failure_rate: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct AdminOverviewOutput {
+    /// Sessions queued for a sandbox or running
+    pub active_sessions: i64,
+    /// Sessions per status, every status included; deleted sessions are not counted
+    pub sessions_by_status: Vec<SessionStatusCount>,
+    /// Sandbox IPs borrowed from the allocator and not yet returned
+    pub borrowed_ips: i64,
+    /// Of `borrowed_ips`, sandboxes held by the warm pool
+    pub warm_sandboxes: i64,
+    pub dlq_pending: i64,
+    pub recent_runs: RecentRunsDto,
+    /// Background loops of this instance without a recent successful iteration
+    pub stale_workers: Vec<String>,
+    pub collected_at: String,
+}
+
+/// Operational overview
+///
+/// Active sessions, sessions per status, borrowed IPs, pending DLQ entries, stale background
+/// loops and the failure rate of runs started in the last hour, in one call
+#[openapi(tag = "Admin")]
+#[get("/admin/overview")]
+pub async fn overview(
+    db: &State<DatabaseConnection>,
+    _admin: AdminUser,
+) -> OResult<AdminOverviewOutput> {
+    let overview = admin_overview::collect(db.inner())
+        .await
+        .map_err(|e| Error::database_error(format!("Failed to collect the overview: {}", e)))?;
+
+    Ok(Json(AdminOverviewOutput {
+        active_sessions: overview.active_sessions(),
+        borrowed_ips: overview.borrowed_ips(),
+        warm_sandboxes: overview.warm_sandboxes,
+        dlq_pending: overview.dlq_pending,
+        recent_runs: RecentRunsDto {
+            window_seconds: RECENT_WINDOW.num_seconds(),
+            completed: overview.runs_completed,
+            failed: overview.runs_failed,
+            failure_rate: overview.failure_rate(),
+        },
+        stale_workers: overview
+            .stale_workers()
+            .into_iter()
+            .map(|w| w.name.to_string())
+            .collect(),
+        collected_at: overview.collected_at.to_rfc3339(),
+        sessions_by_status: overview
+            .by_status
+            .into_iter()
+            .map(|c| SessionStatusCount {
+                ui_status: c.ui_status,
+                sessions: c.sessions,
+            })
+            .collect(),
+    }))
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct EntityMatchDto {
+    pub entity_type: EntityKind,
+    /// Id of the row; for a DLQ entry about the looked-up entity, the entry's own id
+    pub id: String,
+    /// User owning the session the row belongs to
+    pub owner: Option<String>,
+    pub session_id: Option<String>,
+    /// Key state fields of the row, e.g. `ui_status` of a session
+    pub state: serde_json::Value,
+    /// API paths by name, e.g. `self` and `events`
+    pub links: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct EntityLookupOutput {
+    pub id: String,
+    pub matches: Vec<EntityMatchDto>,
+}
+
+/// Look up an id
+///
+/// Finds the session, prompt, message or DLQ entry with the id, deleted sessions included, and
+/// the DLQ entries whose entity it is. 404 when nothing has it.
+#[openapi(tag = "Admin")]
+#[get("/admin/lookup/<id>")]
+pub async fn lookup_entity(
+    db: &State<DatabaseConnection>,
+    _admin: AdminUser,
+    id: String,
+) -> OResult<EntityLookupOutput> {
+    let uuid = uuid::Uuid::parse_str(id.trim())
+        .map_err(|_| Error::bad_request(format!("Invalid UUID: {}", id)))?;
+
+    let matches = entity_lookup::lookup(db.inner(), uuid)
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+    if matches.is_empty() {
+        return Err(Error::not_found(format!(
+            "No session, prompt, message or DLQ entry has id {}",
+            uuid
+        )));
+    }
+
+    Ok(Json(EntityLookupOutput {
+        id: uuid.to_string(),
+        matches: matches
+            .into_iter()
+            .map(|m| EntityMatchDto {
+                entity_type: m.kind,
+                id: m.id.to_string(),
+                owner: m.owner,
+                session_id: m.session_id.map(|id| id.to_string()),
+                state: m.state,
+                links: m.links,
+            })
+            .collect(),
+    }))
+}
+
+/// Background loop status
+///
+/// Last successful iteration, items processed, last error and paused state of each background loop running in this process
+#[openapi(tag = "Admin")]
+#[get("/internal/workers")]
+pub async fn list_workers(
+    db: &State<DatabaseConnection>,
+    _admin: AdminUser,
+) -> OResult<ListWorkersOutput> {
+    // The shared flag is current even before the loop's next iteration has seen it
+    let controls = poller_control::all(db.inner())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+    Ok(Json(ListWorkersOutput {
+        workers: worker_registry::snapshot()
+            .into_iter()
+            .map(|status| {
+                let mut dto = WorkerStatusDto::from(status);
+                if let Some(control) = controls.iter().find(|c| c.name == dto.name) {
+                    dto.paused = control.paused;
+                }
+                dto
+            })
+            .collect(),
+    }))
+}
+
+async fn set_poller_paused(
+    db: &DatabaseConnection,
+    admin: &AdminUser,
+    name: &str,
+    paused: bool,
+) -> OResult<PollerStateOutput> {
+    if !poller_control::is_pausable(name) {
+        return Err(Error::not_found(format!(
+            "Unknown poller {}; pausable pollers are {}",
+            name,
+            poller_control::PAUSABLE.join(", ")
+        )));
+    }
+    let control = poller_control::set_paused(db, name, paused, &admin.0.user_id)
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+    Ok(Json(control.into()))
+}
+
+/// Pause a background poller
+///
+/// Stops `prompt_poller`, `ip_return_poller` or `cancellation_enforcer` from starting new iterations on every instance, without stopping the process; work already under way finishes
+#[openapi(tag = "Admin")]
+#[post("/admin/pollers/<name>/pause")]
+pub async fn pause_poller(
+    db: &State<DatabaseConnection>,
+    admin: AdminUser,
+    name: String,
+) -> OResult<PollerStateOutput> {
+    set_poller_paused(db.inner(), &admin, &name, true).await
+}
+
+/// Resume a paused background poller
+#[openapi(tag = "Admin")]
+#[post("/admin/pollers/<name>/resume")]
+pub async fn resume_poller(
+    db: &State<DatabaseConnection>,
+    admin: AdminUser,
+    name: String,
+) -> OResult<PollerStateOutput> {
+    set_poller_paused(db.inner(), &admin, &name, false).await
+}
+
+/// Report orphaned rows
+///
+/// Counts prompts, messages and DLQ entries that reference deleted entities without changing them
+#[openapi(tag = "Admin")]
+#[get("/admin/integrity")]
+pub async fn integrity_report(
+    db: &State<DatabaseConnection>,
+    _admin: AdminUser,
+) -> OResult<IntegrityReportOutput> {
+    let counts = detect_orphans(db.inner())
+        .await
+        .map_err(|e| Error::database_error(format!("Failed to detect orphans: {}", e)))?;
+
+    Ok(Json(counts.into()))
+}
+
+/// Repair orphaned rows
+///
+/// Deletes orphaned prompts and messages and abandons orphaned DLQ entries, returning how many were repaired
+#[openapi(tag = "Admin")]
+#[post("/admin/integrity/repair")]
+pub async fn integrity_repair(
+    db: &State<DatabaseConnection>,
+    admin: AdminUser,
+) -> OResult<IntegrityReportOutput> {
+    let counts = repair_orphans(db.inner())
+        .await
+        .map_err(|e| Error::database_error(format!("Failed to repair orphans: {}", e)))?;
+
+    tracing::info!(
+        "Admin {} repaired orphans: {} prompts, {} messages, {} DLQ entries",
+        admin.0.user_id,
+        counts.prompts,
+        counts.messages,
+        counts.dlq_entries
+    );
+
+    Ok(Json(counts.into()))
+}
+
+/// List errors of background loops and workers
+///
+/// Most recently seen first, optionally only those of one `source` (e.g. `ip_return_poller`),
+/// one `kind`, or seen at or after `since` (RFC 3339). Repeats of an error are counted on one
+/// entry. At most `limit` entries, default 100 and at most 500.
+#[openapi(tag = "Admin")]
+#[get("/admin/operational-events?<source>&<kind>&<since>&<limit>")]
+pub async fn list_operational_events(
+    db: &State<DatabaseConnection>,
+    _admin: AdminUser,
+    source: Option<String>,
+    kind: Option<OperationalEventKind>,
+    since: Option<String>,
+    limit: Option<u64>,
+) -> OResult<ListOperationalEventsOutput> {
+    let since = match since {
+        Some(since) => Some(
+            chrono::DateTime::parse_from_rfc3339(&since)
+                .map_err(|_| Error::bad_request("since must be an RFC 3339 time".to_string()))?
+                .with_timezone(&Utc),
+        ),
+        None => None,
+    };
+    let filter = EventFilter {
+        source,
+        kind,
+        since,
+    };
+
+    let events = operational_events::list(
+        db.inner(),
+        &filter,
+        limit.unwrap_or(DEFAULT_EVENTS_LIMIT).min(MAX_EVENTS_LIMIT),
+    )
+    .await
+    .map_err(|e| Error::database_error(e.to_string()))?;
+
+    Ok(Json(ListOperationalEventsOutput {
+        events: events.into_iter().map(OperationalEventDto::from).collect(),
+    }))
+}
+
+/// Offload existing large messages
+///
+/// Moves up to `limit` (default 100) inline message payloads above the size threshold to
+/// object storage. Call repeatedly until `offloaded` is 0 to migrate existing rows.
+#[openapi(tag = "Admin")]
+#[post("/admin/messages/offload?<limit>")]
+pub async fn offload_messages(
+    db: &State<DatabaseConnection>,
+    _admin: AdminUser,
+    limit: Option<u64>,
+) -> OResult<OffloadMessagesOutput> {
+    let offloaded =
+        message_blobs::offload_existing(db.inner(), limit.unwrap_or(DEFAULT_OFFLOAD_BATCH))
+            .await
+            .map_err(Error::internal_server_error)?;
+
+    Ok(Json(OffloadMessagesOutput { offloaded }))
+}
+
+/// Recover a prompt's output
+///
+/// Stores the messages of the prompt's last run that are in the copy of its CLI output in the
+/// sandbox but missing from the database, e.g. because the database was unavailable during the
+/// run. Refused with 409 once the session's sandbox was returned.
+#[openapi(tag = "Admin")]
+#[post("/admin/prompts/<id>/recover-output")]
+pub async fn recover_output(
+    db: &State<DatabaseConnection>,
+    admin: AdminUser,
+    id: String,
+) -> OResult<RecoverOutputOutput> {
+    let uuid = uuid::Uuid::parse_str(&id)
+        .map_err(|_| Error::bad_request("Invalid UUID format".to_string()))?;
+    let prompt = Prompt::find_by_id(uuid)
+        .one(db.inner())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?
+        .ok_or_else(|| Error::not_found("Prompt not found".to_string()))?;
+
+    let counts = output_log::recover(db.inner(), &prompt)
+        .await
+        .map_err(|e| match e {
+            RecoveryError::NoLog(_) | RecoveryError::NoSandbox(_) => {
+                Error::conflict(e.to_string(), serde_json::json!({ "prompt_id": id }))
+            }
+            RecoveryError::Database(e) => Error::database_error(e.to_string()),
+            RecoveryError::Read(..) | RecoveryError::Storage(_) => {
+                Error::internal_server_error(e.to_string())
+            }
+        })?;
+
+    tracing::info!(
+        "Admin {} recovered {} messages of prompt {} from its output log",
+        admin.0.user_id,
+        counts.backfilled,
+        uuid
+    );
+    Ok(Json(counts.into()))
+}
+
+/// Deprovision a user
+///
+/// Requests cancellation of the user's running and queued sessions, archives reviewed ones so
+/// their sandbox IPs are returned, soft-deletes every session and records an audit entry
+#[openapi(tag = "Admin")]
+#[post("/admin/users/<user_id>/deprovision")]
+pub async fn deprovision_user(
+    db: &State<DatabaseConnection>,
+    admin: AdminUser,
+    user_id: String,
+) -> OResult<DeprovisionUserOutput> {
+    let summary =
+        deprovision::deprovision_user(db.inner(), &user_id, &Actor::User(admin.0.user_id))
+            .await
+            .map_err(|e| Error::database_error(format!("Failed to deprovision user: {}", e)))?;
+
+    Ok(Json(DeprovisionUserOutput::new(user_id, summary)))
+}
+
+/// List organizations with quotas or settings
+#[openapi(tag = "Admin")]
+#[get("/admin/organizations")]
+pub async fn list_organizations(
+    db: &State<DatabaseConnection>,
+    _admin: AdminUser,
+) -> OResult<ListOrganizationsOutput> {
+    let organizations = Organization::find()
+        .order_by_asc(organization::Column::Id)
+        .all(db.inner())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+
+    Ok(Json(ListOrganizationsOutput {
+        organizations: organizations
+            .into_iter()
+            .map(OrganizationDto::from)
+            .collect(),
+    }))
+}
+
+/// Set an organization's quotas and settings
+///
+/// Replaces every setting of the organization, creating its row on first use. `org_id` is the
+/// value of the organization claim in its members' tokens.
+#[openapi(tag = "Admin")]
+#[put("/admin/organizations/<org_id>", data = "<input>")]
+pub async fn update_organization(
+    db: &State<DatabaseConnection>,
+    _admin: AdminUser,
+    org_id: String,
+    input: Json<UpdateOrganizationInput>,
+) -> OResult<OrganizationDto> {
+    let org_id = org_id.trim().to_string();
+    if org_id.is_empty() {
+        return Err(Error::bad_request("Organization id is empty".to_string()));
+    }
+    let max_active_sessions = input
+        .max_active_sessions
+        .map(i32::try_from)
+        .transpose()
+        .map_err(|_| Error::bad_request("max_active_sessions is too large".to_string()))?;
+    if input
+        .monthly_budget_usd
+        .is_some_and(|budget| !budget.is_finite() || budget < 0.0)
+    {
+        return Err(Error::bad_request(
+            "monthly_budget_usd must be a non-negative number".to_string(),
+        ));
+    }
+
+    let existing = Organization::find_by_id(org_id.clone())
+        .one(db.inner())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+    let now = Utc::now();
+    let mut organization = match &existing {
+        Some(existing) => organization::ActiveModel::from(existing.clone()),
+        None => organization::ActiveModel {
+            id: Set(org_id),
+            created_at: Set(now.into()),
+            ..Default::default()
+        },
+    };
+    organization.name = Set(input.name.clone());
+    organization.max_active_sessions = Set(max_active_sessions);
+    organization.monthly_budget_usd = Set(input.monthly_budget_usd);
+    organization.updated_at = Set(now.into());
+
+    let organization = match existing {
+        Some(_) => organization.update(db.inner()).await,
+        None => organization.insert(db.inner()).await,
+    }
+    .map_err(|e| Error::database_error(e.to_string()))?;
+
+    Ok(Json(organization.into()))
+}
+
+fn exec_timeout(timeout_secs: Option<u64>) -> Result<Duration, String> {
+    let timeout = timeout_secs
+        .map(Duration::from_secs)
+        .unwrap_or(sandbox_exec::DEFAULT_TIMEOUT);
+    if timeout.is_zero() || timeout > sandbox_exec::MAX_TIMEOUT {
+        return Err(format!(
+            "timeout_secs must be between 1 and {}",
+            sandbox_exec::MAX_TIMEOUT.as_secs()
+        ));
+    }
+    Ok(timeout)
+}
+
+/// Run a command in a session's sandbox
+///
+/// Proxies one shell command to the sandbox the session holds, with a strict timeout, and
+/// records it in the audit log. Refused with 409 once the session's sandbox was returned.
+#[openapi(tag = "Admin")]
+#[post("/sessions/<id>/exec", data = "<input>")]
+pub async fn exec_in_sandbox(
+    db: &State<DatabaseConnection>,
+    admin: AdminUser,
+    id: String,
+    input: Json<SandboxExecInput>,
+) -> OResult<SandboxExecOutput> {
+    let uuid = uuid::Uuid::parse_str(&id)
+        .map_err(|_| Error::bad_request("Invalid UUID format".to_string()))?;
+    let command = input.command.trim();
+    if command.is_empty() || command.chars().count() > MAX_EXEC_COMMAND_LENGTH {
+        return Err(Error::bad_request(format!(
+            "command must be between 1 and {} characters",
+            MAX_EXEC_COMMAND_LENGTH
+        )));
+    }
+    let timeout = exec_timeout(input.timeout_secs).map_err(Error::bad_request)?;
+
+    let session = Session::find_by_id(uuid)
+        .one(db.inner())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?
+        .ok_or_else(|| Error::not_found("Session not found".to_string()))?;
+
+    let record = sandbox_exec::run(
+        db.inner(),
+        &session,
+        &admin.0.user_id,
+        command,
+        input.exec_dir.clone(),
+        timeout,
+    )
+    .await
+    .map_err(|e| match e {
+        SandboxExecError::NoSandbox(_) => Error::conflict(
+            e.to_string(),
+            serde_json::json!({ "ui_status": session.ui_status }),
+        ),
+        SandboxExecError::Database(e) => Error::database_error(e.to_string()),
+    })?;
+
+    Ok(Json(record.into()))
+}
+
+/// Force release a session's sandbox
+///
+/// Returns the session's IP to the allocator unless `skip_allocator` is set, then archives the
+/// session, clears its sandbox config, resolves its pending IP return DLQ entries and records
+/// the release in its event history, all in one transaction. Refused with 409 while the session
+/// may still be running and with 502 when the allocator call fails.
+#[openapi(tag = "Admin")]
+#[post("/sessions/<id>/force-release", data = "<input>")]
+pub async fn force_release_session(
+    db: &State<DatabaseConnection>,
+    admin: AdminUser,
+    id: String,
+    input: Json<ForceReleaseInput>,
+) -> OResult<ForceReleaseOutput> {
+    let uuid = uuid::Uuid::parse_str(&id)
+        .map_err(|_| Error::bad_request("Invalid UUID format".to_string()))?;
+    let reason = input
+        .reason
+        .as_deref()
+        .map(str::trim)
+        .filter(|reason| !reason.is_empty())
+        .map(str::to_string);
+
+    let (session, summary) = force_release::force_release(
+        db.inner(),
+        uuid,
+        &Actor::User(admin.0.user_id),
+        input.skip_allocator,
+        reason,
+    )
+    .await
+    .map_err(|e| match e {
+        ForceReleaseError::NotFound => Error::not_found(e.to_string()),
+        ForceReleaseError::Active(ref ui_status) => {
+            Error::conflict(e.to_string(), serde_json::json!({ "ui_status": ui_status }))
+        }
+        ForceReleaseError::Allocator(_) => Error::bad_gateway(e.to_string()),
+        ForceReleaseError::Database(e) => Error::database_error(e.to_string()),
+    })?;
+
+    Ok(Json(ForceReleaseOutput::new(
+        session.id,
+        session.ui_status,
+        summary,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exec_timeout_bounds() {
+        assert_eq!(exec_timeout(None).unwrap(), sandbox_exec::DEFAULT_TIMEOUT);
+        assert_eq!(exec_timeout(Some(5)).unwrap(), Duration::from_secs(5));
+        assert!(exec_timeout(Some(0)).is_err());
+        assert!(exec_timeout(Some(sandbox_exec::MAX_TIMEOUT.as_secs() + 1)).is_err());
+    }
+}
@@ -0,0 +1,282 @@
+use chrono::Utc;
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::State;
+use rocket_okapi::okapi::schemars::JsonSchema;
+use rocket_okapi::openapi;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, NotSet, QueryFilter,
+    QueryOrder, QuerySelect, Set,
+};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use crate::auth::AuthenticatedUser;
+use crate::db::ReadDb;
+use crate::entities::annotation::{self, Entity as Annotation, Model as AnnotationModel};
+use crate::entities::message::{Entity as Message, Model as MessageModel};
+use crate::entities::prompt::Entity as Prompt;
+use crate::entities::session::Entity as Session;
+use crate::error::{Error, OResult};
+use crate::services::organizations;
+
+/// Longest annotation comment accepted, in characters
+const MAX_COMMENT_LENGTH: usize = 10_000;
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct AnnotationInput {
+    pub comment: String,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct AnnotationDto {
+    pub id: String,
+    pub message_id: String,
+    pub author_id: String,
+    pub author_name: Option<String>,
+    pub comment: String,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+impl From<AnnotationModel> for AnnotationDto {
+    fn from(model: AnnotationModel) -> Self {
+        AnnotationDto {
+            id: model.id.to_string(),
+            message_id: model.message_id.to_string(),
+            author_id: model.author_id,
+            author_name: model.author_name,
+            comment: model.comment,
+            created_at: model.created_at.to_string(),
+            updated_at: model.updated_at.to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct AnnotationOutput {
+    pub annotation: AnnotationDto,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct ListAnnotationsOutput {
+    pub annotations: Vec<AnnotationDto>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct DeleteAnnotationOutput {
+    pub success: bool,
+    pub message: String,
+}
+
+/// Number of annotations on each of `message_ids`; messages without any are absent
+pub async fn counts(
+    db: &DatabaseConnection,
+    message_ids: Vec<Uuid>,
+) -> Result<HashMap<Uuid, i64>, Error> {
+    if message_ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let rows: Vec<(Uuid, i64)> = Annotation::find()
+        .select_only()
+        .column(annotation::Column::MessageId)
+        .column_as(annotation::Column::Id.count(), "count")
+        .filter(annotation::Column::MessageId.is_in(message_ids))
+        .group_by(annotation::Column::MessageId)
+        .into_tuple()
+        .all(db)
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+
+    Ok(rows.into_iter().collect())
+}
+
+fn validate_comment(comment: &str) -> Result<String, String> {
+    let comment = comment.trim();
+    if comment.is_empty() {
+        return Err("Comment must not be empty".to_string());
+    }
+    if comment.chars().count() > MAX_COMMENT_LENGTH {
+        return Err(format!(
+            "Comment must be at most {} characters",
+            MAX_COMMENT_LENGTH
+        ));
+    }
+    Ok(comment.to_string())
+}
+
+/// Find a message whose session the user can access
+async fn find_message(
+    db: &DatabaseConnection,
+    user: &AuthenticatedUser,
+    message_id: &str,
+) -> Result<MessageModel, Error> {
+    let uuid = Uuid::parse_str(message_id)
+        .map_err(|_| Error::bad_request("Invalid message_id UUID format".to_string()))?;
+
+    let message = Message::find_by_id(uuid)
+        .one(db)
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?
+        .ok_or_else(|| Error::not_found("Message not found".to_string()))?;
+
+    // Verify message's prompt's session belongs to user
+    let prompt = Prompt::find_by_id(message.prompt_id)
+        .one(db)
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?
+        .ok_or_else(|| Error::not_found("Prompt not found".to_string()))?;
+
+    let _session = Session::find_by_id(prompt.session_id)
+        .filter(organizations::visible_to(user))
+        .one(db)
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?
+        .ok_or_else(|| Error::not_found("Session not found".to_string()))?;
+
+    Ok(message)
+}
+
+/// Find an annotation on an accessible message that the user wrote
+async fn find_own_annotation(
+    db: &DatabaseConnection,
+    user: &AuthenticatedUser,
+    message_id: &str,
+    id: &str,
+) -> Result<AnnotationModel, Error> {
+    let message = find_message(db, user, message_id).await?;
+    let uuid =
+        Uuid::parse_str(id).map_err(|_| Error::bad_request("Invalid UUID format".to_string()))?;
+
+    let annotation = Annotation::find_by_id(uuid)
+        .filter(annotation::Column::MessageId.eq(message.id))
+        .one(db)
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?
+        .ok_or_else(|| Error::not_found("Annotation not found".to_string()))?;
+
+    if annotation.author_id != user.user_id {
+        return Err(Error::forbidden(
+            "Only the author can change an annotation".to_string(),
+        ));
+    }
+    Ok(annotation)
+}
+
+/// Annotate a message with a review comment
+#[openapi]
+#[post("/messages/<message_id>/annotations", data = "<input>")]
+pub async fn create(
+    user: AuthenticatedUser,
+    db: &State<DatabaseConnection>,
+    message_id: String,
+    input: Json<AnnotationInput>,
+) -> OResult<AnnotationOutput> {
+    let comment = validate_comment(&input.comment).map_err(Error::bad_request)?;
+    let message = find_message(db.inner(), &user, &message_id).await?;
+
+    let new_annotation = annotation::ActiveModel {
+        id: Set(Uuid::new_v4()),
+        message_id: Set(message.id),
+        author_id: Set(user.user_id.clone()),
+        author_name: Set(user.name.clone().or_else(|| user.email.clone())),
+        comment: Set(comment),
+        created_at: NotSet,
+        updated_at: NotSet,
+    };
+
+    let annotation = new_annotation
+        .insert(db.inner())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+
+    Ok(Json(AnnotationOutput {
+        annotation: annotation.into(),
+    }))
+}
+
+/// List annotations on a message, oldest first
+#[openapi]
+#[get("/messages/<message_id>/annotations")]
+pub async fn list(
+    user: AuthenticatedUser,
+    db: &State<ReadDb>,
+    message_id: String,
+) -> OResult<ListAnnotationsOutput> {
+    let message = find_message(db.conn(), &user, &message_id).await?;
+
+    let annotations = Annotation::find()
+        .filter(annotation::Column::MessageId.eq(message.id))
+        .order_by_asc(annotation::Column::CreatedAt)
+        .all(db.conn())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+
+    Ok(Json(ListAnnotationsOutput {
+        annotations: annotations.into_iter().map(AnnotationDto::from).collect(),
+    }))
+}
+
+/// Edit the comment of an annotation you wrote
+#[openapi]
+#[put("/messages/<message_id>/annotations/<id>", data = "<input>")]
+pub async fn update(
+    user: AuthenticatedUser,
+    db: &State<DatabaseConnection>,
+    message_id: String,
+    id: String,
+    input: Json<AnnotationInput>,
+) -> OResult<AnnotationOutput> {
+    let comment = validate_comment(&input.comment).map_err(Error::bad_request)?;
+    let annotation = find_own_annotation(db.inner(), &user, &message_id, &id).await?;
+
+    let mut active_annotation: annotation::ActiveModel = annotation.into();
+    active_annotation.comment = Set(comment);
+    active_annotation.updated_at = Set(Utc::now().into());
+
+    let annotation = active_annotation
+        .update(db.inner())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+
+    Ok(Json(AnnotationOutput {
+        annotation: annotation.into(),
+    }))
+}
+
+/// Delete an annotation you wrote
+#[openapi]
+#[delete("/messages/<message_id>/annotations/<id>")]
+pub async fn delete(
+    user: AuthenticatedUser,
+    db: &State<DatabaseConnection>,
+    message_id: String,
+    id: String,
+) -> OResult<DeleteAnnotationOutput> {
+    let annotation = find_own_annotation(db.inner(), &user, &message_id, &id).await?;
+    let active_annotation: annotation::ActiveModel = annotation.into();
+
+    match active_annotation.delete(db.inner()).await {
+        Ok(_) => Ok(Json(DeleteAnnotationOutput {
+            success: true,
+            message: "Annotation deleted successfully".to_string(),
+        })),
+        Err(e) => Err(Error::database_error(e.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_comment() {
+        assert_eq!(
+            validate_comment("  this change is wrong \n").unwrap(),
+            "this change is wrong"
+        );
+        assert!(validate_comment("   ").is_err());
+        assert!(validate_comment(&"x".repeat(MAX_COMMENT_LENGTH + 1)).is_err());
+    }
+}
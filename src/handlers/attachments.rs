@@ -0,0 +1,106 @@
+use rocket::form::Form;
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::State;
+use rocket_okapi::okapi::schemars::JsonSchema;
+use rocket_okapi::openapi;
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+use uuid::Uuid;
+
+use crate::auth::AuthenticatedUser;
+use crate::db::ReadDb;
+use crate::entities::prompt_attachment::{self, AttachmentKind, Entity as PromptAttachment};
+use crate::error::{Error, OResult};
+use crate::handlers::uploads::UploadForm;
+use crate::services::prompt_attachments;
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct AttachmentDto {
+    /// Referenced from prompt data as `{"type": "file", "attachment_id": "<id>"}`
+    pub id: String,
+    pub file_name: String,
+    pub kind: AttachmentKind,
+    pub content_type: Option<String>,
+    pub size_bytes: i64,
+    /// Hex SHA-256 of the contents
+    pub sha256: String,
+    /// Where runs of prompts referencing the attachment find it in the sandbox
+    pub sandbox_path: String,
+    pub created_at: String,
+}
+
+impl From<prompt_attachment::Model> for AttachmentDto {
+    fn from(model: prompt_attachment::Model) -> Self {
+        AttachmentDto {
+            sandbox_path: prompt_attachments::sandbox_path(&model),
+            id: model.id.to_string(),
+            file_name: model.file_name,
+            kind: model.kind,
+            content_type: model.content_type,
+            size_bytes: model.size_bytes,
+            sha256: model.sha256,
+            created_at: model.created_at.to_string(),
+        }
+    }
+}
+
+/// Upload a file to attach to prompts
+///
+/// Accepts `multipart/form-data` with a `file` part of at most `UPLOAD_MAX_BYTES`, e.g. a
+/// screenshot or a log. The contents are kept in object storage; reference the returned id
+/// from a prompt's content as a `file` part and the file is copied into the sandbox before
+/// the prompt runs. Any of your prompts may reference it.
+#[openapi]
+#[post("/attachments", data = "<form>")]
+pub async fn create(
+    user: AuthenticatedUser,
+    db: &State<DatabaseConnection>,
+    form: Form<UploadForm<'_>>,
+) -> OResult<AttachmentDto> {
+    let form = form.into_inner();
+    if !form.file.is_complete() {
+        return Err(Error::payload_too_large(format!(
+            "Attachment is larger than the {} byte limit",
+            crate::config::get().uploads.max_bytes
+        )));
+    }
+    let file = form.file.value;
+    let file_name = form
+        .name
+        .or_else(|| {
+            file.raw_name()
+                .map(|name| name.dangerous_unsafe_unsanitized_raw().to_string())
+        })
+        .ok_or_else(|| Error::bad_request("The file part has no file name".to_string()))?;
+    let content_type = file.content_type().map(|ct| ct.to_string());
+    let reader = file
+        .open()
+        .await
+        .map_err(|e| Error::internal_server_error(format!("Failed to read upload: {}", e)))?;
+
+    let record =
+        prompt_attachments::store(db.inner(), &user.user_id, &file_name, content_type, reader)
+            .await?;
+    Ok(Json(record.into()))
+}
+
+/// Get an attachment's details
+#[openapi]
+#[get("/attachments/<id>")]
+pub async fn read(
+    user: AuthenticatedUser,
+    db: &State<ReadDb>,
+    id: String,
+) -> OResult<AttachmentDto> {
+    let uuid =
+        Uuid::parse_str(&id).map_err(|_| Error::bad_request("Invalid UUID format".to_string()))?;
+
+    let attachment = PromptAttachment::find_by_id(uuid)
+        .filter(prompt_attachment::Column::UserId.eq(&user.user_id))
+        .one(db.conn())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?
+        .ok_or_else(|| Error::not_found("Attachment not found".to_string()))?;
+
+    Ok(Json(attachment.into()))
+}
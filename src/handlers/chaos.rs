@@ -0,0 +1,87 @@
+//! Fault injection routes, only compiled with the `testing` feature.
+//!
+//! These are mounted outside the OpenAPI spec so the published API is the same in every build.
+
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::Route;
+use std::time::Duration;
+
+use crate::auth::AdminUser;
+use crate::services::chaos::{self, Fault};
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum FaultKind {
+    IpReturn,
+    SandboxTimeout,
+}
+
+impl From<FaultKind> for Fault {
+    fn from(kind: FaultKind) -> Self {
+        match kind {
+            FaultKind::IpReturn => Fault::IpReturn,
+            FaultKind::SandboxTimeout => Fault::SandboxTimeout,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct InjectFaultInput {
+    pub fault: FaultKind,
+    /// How many consecutive attempts should fail
+    pub times: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct FakeCliInput {
+    /// How long the fake CLI process should run
+    pub seconds: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ChaosStatusOutput {
+    pub ip_return_failures: u32,
+    pub sandbox_timeouts: u32,
+    pub fake_cli_seconds: Option<u64>,
+}
+
+fn status() -> Json<ChaosStatusOutput> {
+    let injector = chaos::injector();
+    Json(ChaosStatusOutput {
+        ip_return_failures: injector.pending(Fault::IpReturn),
+        sandbox_timeouts: injector.pending(Fault::SandboxTimeout),
+        fake_cli_seconds: injector.pending_fake_cli().map(|d| d.as_secs()),
+    })
+}
+
+/// Show the faults that are still armed
+#[get("/admin/chaos")]
+pub fn get_status(_admin: AdminUser) -> Json<ChaosStatusOutput> {
+    status()
+}
+
+/// Make the next `times` attempts of a background operation fail
+#[post("/admin/chaos/faults", data = "<input>")]
+pub fn inject_fault(_admin: AdminUser, input: Json<InjectFaultInput>) -> Json<ChaosStatusOutput> {
+    chaos::injector().arm(input.fault.into(), input.times);
+    status()
+}
+
+/// Replace the next Claude CLI run with a process that sleeps, for cancellation tests
+#[post("/admin/chaos/fake-cli", data = "<input>")]
+pub fn fake_cli(_admin: AdminUser, input: Json<FakeCliInput>) -> Json<ChaosStatusOutput> {
+    chaos::injector().arm_fake_cli(Duration::from_secs(input.seconds));
+    status()
+}
+
+/// Disarm every fault
+#[delete("/admin/chaos")]
+pub fn reset(_admin: AdminUser) -> Json<ChaosStatusOutput> {
+    chaos::injector().reset();
+    status()
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![get_status, inject_fault, fake_cli, reset]
+}
@@ -3,15 +3,16 @@ use rocket::serde::{Deserialize, Serialize};
 use rocket::State;
 use rocket_okapi::okapi::schemars::JsonSchema;
 use rocket_okapi::openapi;
-use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder};
+use sea_orm::DatabaseConnection;
 use uuid::Uuid;
 
 use crate::auth::AuthenticatedUser;
-use crate::entities::dead_letter_queue::{
-    self, DlqStatus, Entity as DeadLetterQueue, Model as DlqModel,
-};
+use crate::entities::dead_letter_queue::{DlqStatus, Model as DlqModel};
 use crate::error::{Error, OResult};
-use crate::services::dead_letter_queue::{abandon_dlq_entry, resolve_dlq_entry};
+use crate::services::dead_letter_queue::{
+    abandon_dlq_entry, find_entry, list_entries, pending_stats, resolve_dlq_entry, retry_dlq_entry,
+    RetryError,
+};
 
 #[derive(Serialize, Deserialize, JsonSchema, Clone)]
 pub struct DlqDto {
@@ -53,6 +54,25 @@ pub struct ListDlqOutput {
     pub entries: Vec<DlqDto>,
 }
 
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct DlqTaskTypeStats {
+    pub task_type: String,
+    pub pending: i64,
+    /// Seconds the oldest pending entry has been in the DLQ
+    pub oldest_pending_age_seconds: u64,
+    /// Whether the oldest pending entry is past the alert threshold
+    pub over_threshold: bool,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct DlqStatsOutput {
+    /// Task types with pending entries
+    pub task_types: Vec<DlqTaskTypeStats>,
+    pub total_pending: i64,
+    /// Age past which pending entries make readiness report degraded (`DLQ_ALERT_AGE_SECS`)
+    pub alert_age_seconds: u64,
+}
+
 #[derive(Serialize, Deserialize, JsonSchema, Clone)]
 pub struct ResolveDlqInput {
     pub resolution_notes: Option<String>,
@@ -64,6 +84,17 @@ pub struct ResolveDlqOutput {
     pub message: String,
 }
 
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct RetryDlqInput {
+    pub resolution_notes: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct RetryDlqOutput {
+    pub success: bool,
+    pub message: String,
+}
+
 #[derive(Serialize, Deserialize, JsonSchema, Clone)]
 pub struct AbandonDlqInput {
     pub resolution_notes: Option<String>,
@@ -83,29 +114,9 @@ pub struct AbandonDlqOutput {
 pub async fn list_dlq_entries(
     db: &State<DatabaseConnection>,
     _user: AuthenticatedUser,
-    status: Option<String>,
+    status: Option<DlqStatus>,
 ) -> OResult<ListDlqOutput> {
-    let mut query = DeadLetterQueue::find();
-
-    // Filter by status if provided
-    if let Some(status_str) = status {
-        let dlq_status = match status_str.as_str() {
-            "pending" => DlqStatus::Pending,
-            "resolved" => DlqStatus::Resolved,
-            "abandoned" => DlqStatus::Abandoned,
-            _ => {
-                return Err(Error::bad_request(format!(
-                    "Invalid status: {}. Valid values: pending, resolved, abandoned",
-                    status_str
-                )));
-            }
-        };
-        query = query.filter(dead_letter_queue::Column::Status.eq(dlq_status));
-    }
-
-    let entries = query
-        .order_by_desc(dead_letter_queue::Column::CreatedAt)
-        .all(db.inner())
+    let entries = list_entries(db.inner(), status)
         .await
         .map_err(|e| Error::internal_server_error(format!("Failed to list DLQ entries: {}", e)))?;
 
@@ -116,6 +127,41 @@ pub async fn list_dlq_entries(
     }))
 }
 
+/// Dead letter queue statistics
+///
+/// Pending entry counts and the age of the oldest pending entry by task type
+#[openapi(tag = "Dead Letter Queue")]
+#[get("/dead-letter-queue/stats")]
+pub async fn dlq_stats(
+    db: &State<DatabaseConnection>,
+    _user: AuthenticatedUser,
+) -> OResult<DlqStatsOutput> {
+    let stats = pending_stats(db.inner()).await.map_err(|e| {
+        Error::internal_server_error(format!("Failed to get DLQ statistics: {}", e))
+    })?;
+
+    let now = chrono::Utc::now();
+    let alert_age_seconds = crate::config::get().dlq_alert_age.as_secs();
+    let task_types: Vec<DlqTaskTypeStats> = stats
+        .into_iter()
+        .map(|s| {
+            let age = s.oldest_age_secs(now);
+            DlqTaskTypeStats {
+                over_threshold: age > alert_age_seconds,
+                oldest_pending_age_seconds: age,
+                pending: s.pending,
+                task_type: s.task_type,
+            }
+        })
+        .collect();
+
+    Ok(Json(DlqStatsOutput {
+        total_pending: task_types.iter().map(|t| t.pending).sum(),
+        task_types,
+        alert_age_seconds,
+    }))
+}
+
 /// Get a specific dead letter queue entry
 ///
 /// Returns details of a single DLQ entry by ID
@@ -129,8 +175,7 @@ pub async fn get_dlq_entry(
     let uuid =
         Uuid::parse_str(&id).map_err(|_| Error::bad_request(format!("Invalid UUID: {}", id)))?;
 
-    let entry = DeadLetterQueue::find_by_id(uuid)
-        .one(db.inner())
+    let entry = find_entry(db.inner(), uuid)
         .await
         .map_err(|e| Error::internal_server_error(format!("Failed to get DLQ entry: {}", e)))?
         .ok_or_else(|| Error::not_found(format!("DLQ entry not found: {}", id)))?;
@@ -162,6 +207,37 @@ pub async fn resolve_dlq(
     }))
 }
 
+/// Retry the failed task of a DLQ entry
+///
+/// Gives the outbox event or IP return behind a pending entry a fresh set of attempts and marks
+/// the entry resolved
+#[openapi(tag = "Dead Letter Queue")]
+#[post("/dead-letter-queue/<id>/retry", data = "<input>")]
+pub async fn retry_dlq(
+    db: &State<DatabaseConnection>,
+    _user: AuthenticatedUser,
+    id: String,
+    input: Json<RetryDlqInput>,
+) -> OResult<RetryDlqOutput> {
+    let uuid =
+        Uuid::parse_str(&id).map_err(|_| Error::bad_request(format!("Invalid UUID: {}", id)))?;
+
+    retry_dlq_entry(db.inner(), uuid, input.resolution_notes.clone())
+        .await
+        .map_err(|e| match e {
+            RetryError::NotFound => Error::not_found(format!("DLQ entry not found: {}", id)),
+            RetryError::Database(e) => {
+                Error::internal_server_error(format!("Failed to retry DLQ entry: {}", e))
+            }
+            e => Error::conflict(e.to_string(), serde_json::json!({ "id": id })),
+        })?;
+
+    Ok(Json(RetryDlqOutput {
+        success: true,
+        message: format!("DLQ entry {} retried", id),
+    }))
+}
+
 /// Mark a DLQ entry as abandoned
 ///
 /// Marks a dead letter queue entry as abandoned with optional resolution notes
@@ -0,0 +1,112 @@
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket_okapi::okapi::schemars::JsonSchema;
+use rocket_okapi::openapi;
+
+use crate::auth::AuthenticatedUser;
+use crate::error::{Error, OResult};
+use crate::services::github::{Branch, GithubClient, Repository};
+use crate::services::github_host::{self, RepoRef};
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct RepositoryDto {
+    pub full_name: String,
+    pub private: bool,
+    pub default_branch: String,
+    pub html_url: String,
+}
+
+impl From<Repository> for RepositoryDto {
+    fn from(repo: Repository) -> Self {
+        RepositoryDto {
+            full_name: repo.full_name,
+            private: repo.private,
+            default_branch: repo.default_branch,
+            html_url: repo.html_url,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct BranchDto {
+    pub name: String,
+    pub sha: String,
+    pub protected: bool,
+}
+
+impl From<Branch> for BranchDto {
+    fn from(branch: Branch) -> Self {
+        BranchDto {
+            name: branch.name,
+            sha: branch.commit.sha,
+            protected: branch.protected,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct ListRepositoriesOutput {
+    pub repositories: Vec<RepositoryDto>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct ListBranchesOutput {
+    pub branches: Vec<BranchDto>,
+}
+
+async fn client_for(user: &AuthenticatedUser, host: Option<&str>) -> Result<GithubClient, Error> {
+    let host = github_host::find_host(host).map_err(Error::bad_request)?;
+    Ok(GithubClient::for_user(&host, &user.user_id).await?)
+}
+
+/// Search repositories the user can access
+///
+/// Lists the user's repositories on `host` (default host when omitted), filtered by a
+/// case-insensitive substring match on `owner/name` when `query` is given
+#[openapi(tag = "GitHub")]
+#[get("/github/repositories?<query>&<host>")]
+pub async fn search_repositories(
+    user: AuthenticatedUser,
+    query: Option<String>,
+    host: Option<String>,
+) -> OResult<ListRepositoriesOutput> {
+    let client = client_for(&user, host.as_deref()).await?;
+    let query = query.map(|q| q.to_lowercase());
+
+    let repositories = client
+        .list_repos()
+        .await?
+        .into_iter()
+        .filter(|repo| match &query {
+            Some(q) => repo.full_name.to_lowercase().contains(q),
+            None => true,
+        })
+        .map(Into::into)
+        .collect();
+
+    Ok(Json(ListRepositoriesOutput { repositories }))
+}
+
+/// List branches of a repository
+#[openapi(tag = "GitHub")]
+#[get("/github/repositories/<owner>/<name>/branches?<host>")]
+pub async fn list_branches(
+    user: AuthenticatedUser,
+    owner: String,
+    name: String,
+    host: Option<String>,
+) -> OResult<ListBranchesOutput> {
+    let host = github_host::find_host(host.as_deref()).map_err(Error::bad_request)?;
+    let repo = RepoRef::parse(&format!("{}/{}", owner, name), &host.hostname)
+        .map_err(|e| Error::bad_request(e.to_string()))?;
+    let client = GithubClient::for_user(&host, &user.user_id).await?;
+
+    let branches = client
+        .list_branches(&repo)
+        .await?
+        .into_iter()
+        .map(Into::into)
+        .collect();
+
+    Ok(Json(ListBranchesOutput { branches }))
+}
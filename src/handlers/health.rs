@@ -1,8 +1,12 @@
+use rocket::http::Status;
+use rocket::response::status;
 use rocket::serde::json::Json;
 use rocket_okapi::openapi;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
+use crate::bg_tasks::{dlq_monitor, worker_registry};
+
 #[derive(Serialize, Deserialize, JsonSchema)]
 pub struct HealthResponse {
     pub status: String,
@@ -15,3 +19,43 @@ pub fn health() -> Json<HealthResponse> {
         status: "ok".to_string(),
     })
 }
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct ReadinessResponse {
+    pub status: String,
+    /// Background loops in this process that have stopped completing iterations
+    pub stale_workers: Vec<String>,
+    /// DLQ task types with a pending entry older than `DLQ_ALERT_AGE_SECS`
+    pub aged_dlq_task_types: Vec<String>,
+}
+
+/// Readiness probe, failing with 503 while any background loop in this process is stale.
+/// Pending DLQ entries older than the alert threshold report `degraded` without failing.
+#[openapi(tag = "Health")]
+#[get("/ready")]
+pub fn ready() -> status::Custom<Json<ReadinessResponse>> {
+    let now = chrono::Utc::now();
+    let stale_workers: Vec<String> = worker_registry::snapshot()
+        .into_iter()
+        .filter(|w| w.is_stale(now))
+        .map(|w| w.name.to_string())
+        .collect();
+    let aged_dlq_task_types = dlq_monitor::aged_task_types();
+
+    let (code, status) = if !stale_workers.is_empty() {
+        (Status::ServiceUnavailable, "stale")
+    } else if !aged_dlq_task_types.is_empty() {
+        (Status::Ok, "degraded")
+    } else {
+        (Status::Ok, "ready")
+    };
+
+    status::Custom(
+        code,
+        Json(ReadinessResponse {
+            status: status.to_string(),
+            stale_workers,
+            aged_dlq_task_types,
+        }),
+    )
+}
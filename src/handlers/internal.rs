@@ -0,0 +1,377 @@
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::State;
+use rocket_okapi::gen::OpenApiGenerator;
+use rocket_okapi::okapi::openapi3::Responses;
+use rocket_okapi::okapi::schemars::JsonSchema;
+use rocket_okapi::openapi;
+use rocket_okapi::request::{OpenApiFromRequest, RequestHeaderInput};
+use sea_orm::{ActiveModelTrait, DatabaseConnection, EntityTrait, NotSet, Set};
+use uuid::Uuid;
+
+use crate::auth::AdminUser;
+use crate::bg_tasks::message_writer;
+use crate::config;
+use crate::entities::message;
+use crate::entities::prompt::{Entity as Prompt, PromptPriority};
+use crate::entities::session::{self, Entity as Session, UiStatus};
+use crate::error::{Error, OResult};
+use crate::handlers::webhooks::secrets_match;
+use crate::services::queue_stats;
+use crate::services::session_state_machine::{Actor, SessionStateMachine, TransitionCause};
+use crate::services::{
+    agent_tokens, conversation_view, json_guard, message_blobs, message_stream, session_activity,
+};
+
+/// Longest status message the agent may set, in characters
+const MAX_STATUS_MESSAGE_CHARS: usize = 500;
+
+/// Most messages the agent may write in one batch
+const MAX_MESSAGE_BATCH: usize = 100;
+
+/// Bearer token sent by the sandbox agent, if any
+pub struct AgentToken(Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AgentToken {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(AgentToken(
+            request
+                .headers()
+                .get_one("Authorization")
+                .and_then(|h| h.strip_prefix("Bearer "))
+                .map(str::to_string),
+        ))
+    }
+}
+
+impl<'a> OpenApiFromRequest<'a> for AgentToken {
+    fn from_request_input(
+        _gen: &mut OpenApiGenerator,
+        _name: String,
+        _required: bool,
+    ) -> rocket_okapi::Result<RequestHeaderInput> {
+        Ok(RequestHeaderInput::None)
+    }
+}
+
+/// The in-progress session `id` whose current agent token is `token`
+async fn agent_session(
+    db: &DatabaseConnection,
+    token: AgentToken,
+    id: &str,
+) -> Result<session::Model, Error> {
+    let forbidden = || Error::forbidden("Invalid agent token".to_string());
+    let token = token.0.ok_or_else(forbidden)?;
+    let session_id = Uuid::parse_str(id).map_err(|_| forbidden())?;
+
+    let session = Session::find_by_id(session_id)
+        .one(db)
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?
+        .filter(|session| agent_tokens::verify(session, &token))
+        .ok_or_else(forbidden)?;
+
+    if session.ui_status != UiStatus::InProgress {
+        return Err(Error::conflict(
+            "Session is no longer in progress".to_string(),
+            serde_json::json!({ "ui_status": session.ui_status }),
+        ));
+    }
+    Ok(session)
+}
+
+/// Statuses the sandbox agent may move its session to
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AgentStatus {
+    /// The work is finished and ready for review
+    NeedsReview,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct AgentStatusInput {
+    /// New status; omit to only set the status message
+    #[serde(default)]
+    pub ui_status: Option<AgentStatus>,
+    /// Shown to the user with the session, e.g. a link to the opened pull request
+    #[serde(default)]
+    pub status_message: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct AgentStatusOutput {
+    pub ui_status: UiStatus,
+    pub status_message: Option<String>,
+}
+
+/// Report session status from the sandbox agent
+///
+/// Authenticated with the per-run token written to the sandbox when the run started, not a
+/// user token. Only accepted while the session is in progress.
+#[openapi(tag = "Internal")]
+#[patch("/internal/sessions/<id>/status", data = "<input>")]
+pub async fn update_session_status(
+    token: AgentToken,
+    db: &State<DatabaseConnection>,
+    id: String,
+    input: Json<AgentStatusInput>,
+) -> OResult<AgentStatusOutput> {
+    let session = agent_session(db.inner(), token, &id).await?;
+
+    if input.ui_status.is_none() && input.status_message.is_none() {
+        return Err(Error::bad_request(
+            "Set ui_status, status_message or both".to_string(),
+        ));
+    }
+    if let Some(message) = &input.status_message {
+        if message.chars().count() > MAX_STATUS_MESSAGE_CHARS {
+            return Err(Error::bad_request(format!(
+                "status_message must be at most {} characters",
+                MAX_STATUS_MESSAGE_CHARS
+            )));
+        }
+    }
+
+    let from = session.ui_status.clone();
+    let mut active_session = match input.ui_status {
+        Some(AgentStatus::NeedsReview) => SessionStateMachine::transition(
+            session,
+            UiStatus::NeedsReview,
+            TransitionCause::AgentReported,
+            &Actor::System("sandbox_agent"),
+        )
+        .map_err(|e| Error::bad_request(e.to_string()))?,
+        None => session.into(),
+    };
+    if let Some(message) = &input.status_message {
+        active_session.status_message = Set(Some(message.clone()));
+    }
+
+    let updated = active_session
+        .update(db.inner())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+    SessionStateMachine::after_save(
+        db.inner(),
+        &from,
+        &updated,
+        TransitionCause::AgentReported,
+        &Actor::System("sandbox_agent"),
+    )
+    .await;
+
+    Ok(Json(AgentStatusOutput {
+        ui_status: updated.ui_status,
+        status_message: updated.status_message,
+    }))
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct AgentMessagesInput {
+    /// Prompt of the session the messages belong to
+    pub prompt_id: String,
+    /// Message payloads, stored in order
+    pub messages: Vec<serde_json::Value>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct AgentMessagesOutput {
+    /// Ids of the created messages, in input order
+    pub ids: Vec<String>,
+}
+
+/// Write a batch of messages from the sandbox agent
+///
+/// Authenticated like `PATCH /internal/sessions/<id>/status`. `prompt_id` must be the prompt the
+/// session is running; any other prompt is refused with 403. The batch is stored in one insert,
+/// so either every message is created or none is.
+#[openapi(tag = "Internal")]
+#[post("/internal/sessions/<id>/messages", data = "<input>")]
+pub async fn create_session_messages(
+    token: AgentToken,
+    db: &State<DatabaseConnection>,
+    id: String,
+    input: Json<AgentMessagesInput>,
+) -> OResult<AgentMessagesOutput> {
+    let session = agent_session(db.inner(), token, &id).await?;
+
+    if input.messages.is_empty() || input.messages.len() > MAX_MESSAGE_BATCH {
+        return Err(Error::bad_request(format!(
+            "messages must hold between 1 and {} entries",
+            MAX_MESSAGE_BATCH
+        )));
+    }
+    let limits = &config::get().request_limits;
+    for data in &input.messages {
+        json_guard::validate(
+            "messages",
+            data,
+            limits.message_data_bytes,
+            limits.max_json_depth,
+        )?;
+    }
+
+    let prompt_id = Uuid::parse_str(&input.prompt_id)
+        .map_err(|_| Error::bad_request("Invalid prompt_id UUID format".to_string()))?;
+    let prompt = Prompt::find_by_id(prompt_id)
+        .one(db.inner())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?
+        .filter(|prompt| prompt.session_id == session.id)
+        .ok_or_else(|| Error::not_found("Prompt not found".to_string()))?;
+    // The token belongs to the session's current run, which may only add to its own prompt
+    if prompt.run_id.is_none() || prompt.completed_at.is_some() {
+        return Err(Error::forbidden(
+            "Messages can only be added to the prompt that is running".to_string(),
+        ));
+    }
+
+    let created_at = message_writer::creation_times(input.messages.len(), None);
+    let mut models = Vec::with_capacity(input.messages.len());
+    for (data, created_at) in input.messages.iter().zip(created_at) {
+        let message_id = Uuid::new_v4();
+        let (data, blob_key) = message_blobs::prepare(message_id, data.clone()).await;
+        models.push(message::ActiveModel {
+            id: Set(message_id),
+            prompt_id: Set(prompt_id),
+            data: Set(data),
+            blob_key: Set(blob_key),
+            created_at: Set(created_at),
+            updated_at: NotSet,
+            seq: NotSet,
+        });
+    }
+    let ids: Vec<Uuid> = models.iter().filter_map(|m| m.id.clone().take()).collect();
+    message_stream::insert(db.inner(), session.id, models)
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+
+    session_activity::record(db.inner(), session.id, prompt_id).await;
+    conversation_view::append(db.inner(), session.id, prompt_id, &ids).await;
+
+    Ok(Json(AgentMessagesOutput {
+        ids: ids.iter().map(Uuid::to_string).collect(),
+    }))
+}
+
+/// Caller of `GET /internal/queue-stats`: an admin, or anything presenting `QUEUE_STATS_TOKEN`
+pub struct QueueStatsCaller;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for QueueStatsCaller {
+    type Error = String;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        if let Some(expected) = &config::get().queue_stats_token {
+            let given = request
+                .headers()
+                .get_one("Authorization")
+                .and_then(|h| h.strip_prefix("Bearer "));
+            if given.is_some_and(|given| secrets_match(expected, given)) {
+                return Outcome::Success(QueueStatsCaller);
+            }
+        }
+        match AdminUser::from_request(request).await {
+            Outcome::Success(_) => Outcome::Success(QueueStatsCaller),
+            Outcome::Error((status, e)) => Outcome::Error((status, e)),
+            Outcome::Forward(_) => Outcome::Error((
+                Status::Unauthorized,
+                "Admin login or queue stats token required".to_string(),
+            )),
+        }
+    }
+}
+
+impl<'a> OpenApiFromRequest<'a> for QueueStatsCaller {
+    fn from_request_input(
+        gen: &mut OpenApiGenerator,
+        name: String,
+        required: bool,
+    ) -> rocket_okapi::Result<RequestHeaderInput> {
+        AdminUser::from_request_input(gen, name, required)
+    }
+
+    fn get_responses(gen: &mut OpenApiGenerator) -> rocket_okapi::Result<Responses> {
+        AdminUser::get_responses(gen)
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct PriorityDepthOutput {
+    pub priority: PromptPriority,
+    pub pending: i64,
+    pub oldest_pending_age_seconds: u64,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct JobCountOutput {
+    /// apalis job type, e.g. `OutboxJob`
+    pub job_type: String,
+    /// `Pending` or `Running`
+    pub status: String,
+    pub count: i64,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct QueueStatsOutput {
+    /// Prompts not yet claimed by a run, in sessions that are neither deleted nor cancelled
+    pub pending_prompts: i64,
+    /// Seconds the oldest pending prompt has been waiting, 0 when none is
+    pub oldest_pending_age_seconds: u64,
+    /// Runs holding a prompt that have not completed or gone stale
+    pub in_flight_runs: i64,
+    /// Pending prompts per priority, highest first; every priority is listed
+    pub by_priority: Vec<PriorityDepthOutput>,
+    /// Jobs waiting for or held by a worker
+    pub jobs: Vec<JobCountOutput>,
+}
+
+/// Queue depth for autoscaling workers
+///
+/// Authenticated with an admin login or the `QUEUE_STATS_TOKEN` bearer token. The same numbers
+/// are exported on `/metrics` as the `prompt_queue_*`, `prompt_runs_in_flight` and
+/// `worker_queue_jobs` gauges.
+#[openapi(tag = "Internal")]
+#[get("/internal/queue-stats")]
+pub async fn read_queue_stats(
+    _caller: QueueStatsCaller,
+    db: &State<DatabaseConnection>,
+) -> OResult<QueueStatsOutput> {
+    let stats = queue_stats::collect(db.inner())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+    queue_stats::observe(&stats);
+
+    let by_priority = PromptPriority::ALL
+        .into_iter()
+        .map(|priority| {
+            let depth = stats.by_priority.iter().find(|d| d.priority == priority);
+            PriorityDepthOutput {
+                priority,
+                pending: depth.map_or(0, |d| d.pending),
+                oldest_pending_age_seconds: depth
+                    .map_or(0, |d| d.oldest_age_secs(stats.collected_at)),
+            }
+        })
+        .collect();
+
+    Ok(Json(QueueStatsOutput {
+        pending_prompts: stats.pending_prompts(),
+        oldest_pending_age_seconds: stats.oldest_pending_age_secs(),
+        in_flight_runs: stats.in_flight_runs,
+        by_priority,
+        jobs: stats
+            .jobs
+            .iter()
+            .map(|job| JobCountOutput {
+                job_type: job.job_type.clone(),
+                status: job.status.clone(),
+                count: job.count,
+            })
+            .collect(),
+    }))
+}
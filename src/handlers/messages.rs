@@ -1,19 +1,30 @@
+use rocket::futures::stream::{BoxStream, StreamExt};
+use rocket::response::stream::{stream, Event, EventStream};
 use rocket::serde::json::Json;
 use rocket::serde::{Deserialize, Serialize};
-use rocket::State;
+use rocket::{Shutdown, State};
 use rocket_okapi::okapi::schemars::JsonSchema;
 use rocket_okapi::openapi;
 use sea_orm::{
     ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, NotSet, QueryFilter,
     QueryOrder, Set,
 };
+use tracing::warn;
 use uuid::Uuid;
 
 use crate::auth::AuthenticatedUser;
+use crate::config;
+use crate::db::ReadDb;
 use crate::entities::message::{self, Entity as Message, Model as MessageModel};
 use crate::entities::prompt::Entity as Prompt;
 use crate::entities::session::{self, Entity as Session};
 use crate::error::{Error, OResult};
+use crate::handlers::annotations;
+use crate::services::fieldsets::{Fieldset, Sparse};
+use crate::services::{
+    conversation_view, json_guard, message_blobs, message_stream, organizations, session_activity,
+};
+use message_stream::LastEventId;
 
 #[derive(Serialize, Deserialize, JsonSchema, Clone)]
 pub struct CreateMessageInput {
@@ -32,17 +43,36 @@ pub struct CreateMessageOutput {
 pub struct MessageDto {
     pub id: String,
     pub prompt_id: String,
+    /// Position in the order messages were stored; the event id in message streams
+    pub seq: i64,
     pub data: serde_json::Value,
+    /// Number of review annotations on the message
+    pub annotation_count: i64,
     pub created_at: String,
     pub updated_at: String,
 }
 
-impl From<MessageModel> for MessageDto {
-    fn from(model: MessageModel) -> Self {
+impl MessageDto {
+    /// Build the DTO, fetching the payload from object storage if it was offloaded
+    async fn resolve(model: MessageModel, annotation_count: i64) -> Result<Self, Error> {
+        let data = message_blobs::resolve(&model)
+            .await
+            .map_err(Error::internal_server_error)?;
+
+        Ok(MessageDto {
+            data,
+            ..MessageDto::without_data(model, annotation_count)
+        })
+    }
+
+    /// Build the DTO with null `data`, for responses whose fieldset leaves it out
+    fn without_data(model: MessageModel, annotation_count: i64) -> Self {
         MessageDto {
             id: model.id.to_string(),
             prompt_id: model.prompt_id.to_string(),
-            data: model.data.clone(),
+            seq: model.seq,
+            data: serde_json::Value::Null,
+            annotation_count,
             created_at: model.created_at.to_string(),
             updated_at: model.updated_at.to_string(),
         }
@@ -51,12 +81,12 @@ impl From<MessageModel> for MessageDto {
 
 #[derive(Serialize, Deserialize, JsonSchema, Clone)]
 pub struct ReadMessageOutput {
-    pub message: MessageDto,
+    pub message: Sparse<MessageDto>,
 }
 
 #[derive(Serialize, Deserialize, JsonSchema, Clone)]
 pub struct ListMessagesOutput {
-    pub messages: Vec<MessageDto>,
+    pub messages: Vec<Sparse<MessageDto>>,
 }
 
 #[derive(Serialize, Deserialize, JsonSchema, Clone)]
@@ -84,6 +114,14 @@ pub async fn create(
     db: &State<DatabaseConnection>,
     input: Json<CreateMessageInput>,
 ) -> OResult<CreateMessageOutput> {
+    let limits = &config::get().request_limits;
+    json_guard::validate(
+        "data",
+        &input.data,
+        limits.message_data_bytes,
+        limits.max_json_depth,
+    )?;
+
     let prompt_id = Uuid::parse_str(&input.prompt_id)
         .map_err(|_| Error::bad_request("Invalid prompt_id UUID format".to_string()))?;
 
@@ -103,97 +141,234 @@ pub async fn create(
         .ok_or_else(|| Error::not_found("Session not found".to_string()))?;
 
     let id = Uuid::new_v4();
+    let (data, blob_key) = message_blobs::prepare(id, input.data.clone()).await;
 
     let new_message = message::ActiveModel {
         id: Set(id),
         prompt_id: Set(prompt_id),
-        data: Set(input.data.clone()),
+        data: Set(data),
+        blob_key: Set(blob_key),
         created_at: NotSet,
         updated_at: NotSet,
+        seq: NotSet,
     };
 
-    match new_message.insert(db.inner()).await {
-        Ok(_) => Ok(Json(CreateMessageOutput {
-            success: true,
-            message: "Message created successfully".to_string(),
-            id: id.to_string(),
-        })),
+    match message_stream::insert(db.inner(), prompt.session_id, vec![new_message]).await {
+        Ok(_) => {
+            session_activity::record(db.inner(), prompt.session_id, prompt_id).await;
+            conversation_view::append(db.inner(), prompt.session_id, prompt_id, &[id]).await;
+            Ok(Json(CreateMessageOutput {
+                success: true,
+                message: "Message created successfully".to_string(),
+                id: id.to_string(),
+            }))
+        }
         Err(e) => Err(Error::database_error(e.to_string())),
     }
 }
 
 /// Read (retrieve) a message by ID
+///
+/// `fields` selects the fields returned, e.g. `id,created_at`; all of them when omitted.
 #[openapi]
-#[get("/messages/<id>")]
+#[get("/messages/<id>?<fields>")]
 pub async fn read(
     user: AuthenticatedUser,
-    db: &State<DatabaseConnection>,
+    db: &State<ReadDb>,
     id: String,
+    fields: Option<String>,
 ) -> OResult<ReadMessageOutput> {
     let uuid =
         Uuid::parse_str(&id).map_err(|_| Error::bad_request("Invalid UUID format".to_string()))?;
+    let fields = Fieldset::parse::<MessageDto>(fields.as_deref()).map_err(Error::bad_request)?;
 
     let message = Message::find_by_id(uuid)
-        .one(db.inner())
+        .one(db.conn())
         .await
         .map_err(|e| Error::database_error(e.to_string()))?
         .ok_or_else(|| Error::not_found("Message not found".to_string()))?;
 
     // Verify message's prompt's session belongs to user
     let prompt = Prompt::find_by_id(message.prompt_id)
-        .one(db.inner())
+        .one(db.conn())
         .await
         .map_err(|e| Error::database_error(e.to_string()))?
         .ok_or_else(|| Error::not_found("Prompt not found".to_string()))?;
 
     let _session = Session::find_by_id(prompt.session_id)
-        .filter(session::Column::UserId.eq(&user.user_id))
-        .one(db.inner())
+        .filter(organizations::visible_to(&user))
+        .one(db.conn())
         .await
         .map_err(|e| Error::database_error(e.to_string()))?
         .ok_or_else(|| Error::not_found("Session not found".to_string()))?;
 
+    let annotation_count = annotations::counts(db.conn(), vec![message.id])
+        .await?
+        .get(&message.id)
+        .copied()
+        .unwrap_or(0);
+
     Ok(Json(ReadMessageOutput {
-        message: message.into(),
+        message: Sparse::new(
+            MessageDto::resolve(message, annotation_count).await?,
+            fields.as_ref(),
+        ),
     }))
 }
 
 /// List all messages for a prompt
+///
+/// `fields` selects the fields of each message, e.g. `id,annotation_count`, and leaves out
+/// the rest. Leaving out `data` also skips fetching offloaded payloads.
 #[openapi]
-#[get("/prompts/<prompt_id>/messages")]
+#[get("/prompts/<prompt_id>/messages?<fields>")]
 pub async fn list(
     user: AuthenticatedUser,
-    db: &State<DatabaseConnection>,
+    db: &State<ReadDb>,
     prompt_id: String,
+    fields: Option<String>,
 ) -> OResult<ListMessagesOutput> {
     let prompt_uuid = Uuid::parse_str(&prompt_id)
         .map_err(|_| Error::bad_request("Invalid prompt_id UUID format".to_string()))?;
+    let fields = Fieldset::parse::<MessageDto>(fields.as_deref()).map_err(Error::bad_request)?;
 
     // Verify prompt exists
     let prompt = Prompt::find_by_id(prompt_uuid)
-        .one(db.inner())
+        .one(db.conn())
         .await
         .map_err(|e| Error::database_error(e.to_string()))?
         .ok_or_else(|| Error::not_found("Prompt not found".to_string()))?;
 
     // Verify prompt's session belongs to user
     let _session = Session::find_by_id(prompt.session_id)
-        .filter(session::Column::UserId.eq(&user.user_id))
-        .one(db.inner())
+        .filter(organizations::visible_to(&user))
+        .one(db.conn())
         .await
         .map_err(|e| Error::database_error(e.to_string()))?
         .ok_or_else(|| Error::not_found("Session not found".to_string()))?;
 
-    match Message::find()
+    let messages = Message::find()
         .filter(message::Column::PromptId.eq(prompt_uuid))
         .order_by_asc(message::Column::CreatedAt)
-        .all(db.inner())
+        .all(db.conn())
         .await
-    {
-        Ok(messages) => Ok(Json(ListMessagesOutput {
-            messages: messages.into_iter().map(|m| m.into()).collect(),
-        })),
-        Err(e) => Err(Error::database_error(e.to_string())),
+        .map_err(|e| Error::database_error(e.to_string()))?;
+
+    let annotation_counts =
+        annotations::counts(db.conn(), messages.iter().map(|m| m.id).collect()).await?;
+
+    let skip_data = fields.as_ref().is_some_and(|f| !f.contains("data"));
+    let mut dtos = Vec::with_capacity(messages.len());
+    for message in messages {
+        let annotation_count = annotation_counts.get(&message.id).copied().unwrap_or(0);
+        let dto = if skip_data {
+            MessageDto::without_data(message, annotation_count)
+        } else {
+            MessageDto::resolve(message, annotation_count).await?
+        };
+        dtos.push(Sparse::new(dto, fields.as_ref()));
+    }
+
+    Ok(Json(ListMessagesOutput { messages: dtos }))
+}
+
+pub type MessageEvents = EventStream<BoxStream<'static, Event>>;
+
+/// Stream a session's messages as server-sent events
+///
+/// Each message is a `message` event whose id is its `seq`, oldest first. A client resuming a
+/// stream sends the last id it got as `Last-Event-ID`, which browsers do on their own when they
+/// reconnect, or as `after`, and gets exactly the messages after it; without either the stream
+/// starts at the session's first message. A comment is sent every `SSE_KEEP_ALIVE_SECS`
+/// while there is nothing new, and the stream ends after `SSE_MAX_CONNECTION_SECS`, for the
+/// client to reconnect.
+#[openapi]
+#[get("/sessions/<id>/messages/stream?<after>")]
+pub async fn stream_session_messages(
+    user: AuthenticatedUser,
+    db: &State<ReadDb>,
+    id: String,
+    after: Option<i64>,
+    resume: LastEventId,
+    shutdown: Shutdown,
+) -> Result<MessageEvents, Error> {
+    let uuid =
+        Uuid::parse_str(&id).map_err(|_| Error::bad_request("Invalid UUID format".to_string()))?;
+
+    Session::find_by_id(uuid)
+        .filter(organizations::visible_to(&user))
+        .one(db.conn())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?
+        .ok_or_else(|| Error::not_found("Session not found".to_string()))?;
+
+    let settings = &config::get().message_stream;
+    let events = follow(
+        db.conn().clone(),
+        uuid,
+        resume.0.or(after).unwrap_or(0),
+        shutdown,
+    )
+    .map(|dto| {
+        let seq = dto.seq;
+        Event::json(&dto).event("message").id(seq.to_string())
+    });
+
+    Ok(EventStream::from(events.boxed()).heartbeat(settings.keep_alive))
+}
+
+/// A session's messages stored after `after`, oldest first, then new ones as they are stored
+/// until `SSE_MAX_CONNECTION_SECS` passed or `stop` completes. Shared by the SSE route and the
+/// gRPC message stream.
+pub fn follow(
+    db: DatabaseConnection,
+    session_id: Uuid,
+    mut after: i64,
+    stop: impl std::future::Future<Output = ()> + Send + 'static,
+) -> impl rocket::futures::Stream<Item = MessageDto> + Send + 'static {
+    let settings = &config::get().message_stream;
+    let deadline = tokio::time::Instant::now() + settings.max_connection;
+    let uuid = session_id;
+
+    stream! {
+        let mut stop = std::pin::pin!(stop);
+        'poll: loop {
+            let messages = match message_stream::after(&db, uuid, after).await {
+                Ok(messages) => messages,
+                Err(e) => {
+                    warn!("Failed to read messages of session {} to stream: {}", uuid, e);
+                    break;
+                }
+            };
+            let full_batch = messages.len() as u64 == message_stream::BATCH_SIZE;
+            let annotation_counts =
+                annotations::counts(&db, messages.iter().map(|m| m.id).collect())
+                    .await
+                    .unwrap_or_default();
+            for message in messages {
+                let seq = message.seq;
+                let annotation_count = annotation_counts.get(&message.id).copied().unwrap_or(0);
+                // Ending here lets the client resume at this message instead of missing it
+                let Ok(dto) = MessageDto::resolve(message, annotation_count).await else {
+                    warn!("Failed to resolve message {} of session {} to stream", seq, uuid);
+                    break 'poll;
+                };
+                yield dto;
+                after = seq;
+            }
+
+            if tokio::time::Instant::now() >= deadline {
+                break;
+            }
+            if full_batch {
+                continue;
+            }
+            let wake = deadline.min(tokio::time::Instant::now() + settings.poll_interval);
+            tokio::select! {
+                _ = tokio::time::sleep_until(wake) => {}
+                _ = &mut stop => break,
+            }
+        }
     }
 }
 
@@ -206,6 +381,14 @@ pub async fn update(
     id: String,
     input: Json<UpdateMessageInput>,
 ) -> OResult<UpdateMessageOutput> {
+    let limits = &config::get().request_limits;
+    json_guard::validate(
+        "data",
+        &input.data,
+        limits.message_data_bytes,
+        limits.max_json_depth,
+    )?;
+
     let uuid =
         Uuid::parse_str(&id).map_err(|_| Error::bad_request("Invalid UUID format".to_string()))?;
 
@@ -229,14 +412,24 @@ pub async fn update(
         .map_err(|e| Error::database_error(e.to_string()))?
         .ok_or_else(|| Error::not_found("Session not found".to_string()))?;
 
+    let (data, blob_key) = message_blobs::prepare(message.id, input.data.clone()).await;
+    if blob_key.is_none() {
+        // The replacement is stored inline, so any previously offloaded payload is stale
+        message_blobs::remove(&message).await;
+    }
+
     let mut active_message: message::ActiveModel = message.into();
-    active_message.data = Set(input.data.clone());
+    active_message.data = Set(data);
+    active_message.blob_key = Set(blob_key);
 
     match active_message.update(db.inner()).await {
-        Ok(_) => Ok(Json(UpdateMessageOutput {
-            success: true,
-            message: "Message updated successfully".to_string(),
-        })),
+        Ok(_) => {
+            conversation_view::refresh(db.inner(), [prompt.id]).await;
+            Ok(Json(UpdateMessageOutput {
+                success: true,
+                message: "Message updated successfully".to_string(),
+            }))
+        }
         Err(e) => Err(Error::database_error(e.to_string())),
     }
 }
@@ -272,13 +465,17 @@ pub async fn delete(
         .map_err(|e| Error::database_error(e.to_string()))?
         .ok_or_else(|| Error::not_found("Session not found".to_string()))?;
 
+    message_blobs::remove(&message).await;
     let active_message: message::ActiveModel = message.into();
 
     match active_message.delete(db.inner()).await {
-        Ok(_) => Ok(Json(DeleteMessageOutput {
-            success: true,
-            message: "Message deleted successfully".to_string(),
-        })),
+        Ok(_) => {
+            conversation_view::refresh(db.inner(), [prompt.id]).await;
+            Ok(Json(DeleteMessageOutput {
+                success: true,
+                message: "Message deleted successfully".to_string(),
+            }))
+        }
         Err(e) => Err(Error::database_error(e.to_string())),
     }
 }
@@ -0,0 +1,73 @@
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket_okapi::okapi::schemars::JsonSchema;
+use rocket_okapi::openapi;
+
+use crate::config;
+use crate::entities::dead_letter_queue::DlqStatus;
+use crate::entities::session::{CancellationStatus, UiStatus};
+use crate::error::OResult;
+use crate::handlers::prompts::DispatchStatus;
+use crate::services::status_meta::{self, StatusEnum};
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct StatusValueDto {
+    /// The value as the API sends it, e.g. `NeedsReview`
+    pub name: String,
+    pub label: String,
+    /// CSS color, e.g. `#f59e0b`
+    pub color: String,
+    /// Whether the status ends the entity's lifecycle; only a user can move it on from there
+    pub terminal: bool,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct StatusEnumDto {
+    /// Name of the enum in the API schema, e.g. `UiStatus`
+    pub name: String,
+    /// Every value, in declaration order
+    pub values: Vec<StatusValueDto>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct ListStatusesOutput {
+    pub enums: Vec<StatusEnumDto>,
+}
+
+impl From<StatusEnum> for StatusEnumDto {
+    fn from(status: StatusEnum) -> Self {
+        StatusEnumDto {
+            name: status.name.to_string(),
+            values: status
+                .values
+                .into_iter()
+                .map(|v| StatusValueDto {
+                    name: v.name,
+                    label: v.label,
+                    color: v.color,
+                    terminal: v.terminal,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// List the status enums
+///
+/// Every value of `UiStatus`, `DispatchStatus`, `CancellationStatus` and `DlqStatus` with its
+/// display label, color and whether it is terminal, so clients need no mapping of their own.
+/// Labels and colors can be overridden with `STATUS_LABELS` and `STATUS_COLORS`.
+#[openapi(tag = "Meta")]
+#[get("/meta/statuses")]
+pub async fn list_statuses() -> OResult<ListStatusesOutput> {
+    let display = &config::get().status_display;
+    let (labels, colors) = (&display.labels, &display.colors);
+    Ok(Json(ListStatusesOutput {
+        enums: vec![
+            status_meta::describe::<UiStatus>(labels, colors).into(),
+            status_meta::describe::<DispatchStatus>(labels, colors).into(),
+            status_meta::describe::<CancellationStatus>(labels, colors).into(),
+            status_meta::describe::<DlqStatus>(labels, colors).into(),
+        ],
+    }))
+}
@@ -9,6 +9,8 @@ pub type PrometheusRegistry = prometheus::Registry;
 /// Metrics endpoint for Prometheus scraping
 #[get("/metrics")]
 pub fn metrics(registry: &State<PrometheusRegistry>) -> Result<RawText<String>, Status> {
+    crate::db::record_pool_metrics();
+
     let encoder = TextEncoder::new();
     let metric_families = registry.gather();
 
@@ -1,7 +1,22 @@
+pub mod admin;
+pub mod annotations;
+pub mod attachments;
+#[cfg(feature = "testing")]
+pub mod chaos;
 pub mod dead_letter_queue;
+pub mod github;
 pub mod health;
+pub mod internal;
 pub mod messages;
+pub mod meta;
 pub mod metrics;
+pub mod models;
+pub mod notifications;
+pub mod openapi;
 pub mod prompts;
 pub mod sessions;
+pub mod settings;
+pub mod tags;
+pub mod uploads;
+pub mod users;
 pub mod webhooks;
@@ -0,0 +1,65 @@
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::State;
+use rocket_okapi::okapi::schemars::JsonSchema;
+use rocket_okapi::openapi;
+
+use crate::auth::AuthenticatedUser;
+use crate::config;
+use crate::db::ReadDb;
+use crate::entities::session::ClaudeModel;
+use crate::error::{Error, OResult};
+use crate::services::user_settings;
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ModelDto {
+    pub name: ClaudeModel,
+    /// Sessions the user creates without a model run on this one
+    pub default: bool,
+    pub input_usd_per_mtok: f64,
+    pub output_usd_per_mtok: f64,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct ListModelsOutput {
+    pub models: Vec<ModelDto>,
+}
+
+/// List the models the user may request
+///
+/// Returns the allowlisted models the user's plan permits, with the prices cost estimates use
+#[openapi]
+#[get("/models")]
+pub async fn list(user: AuthenticatedUser, db: &State<ReadDb>) -> OResult<ListModelsOutput> {
+    let config = config::get();
+    let is_admin = user.is_admin();
+    let available = |model: ClaudeModel| config.models.check(model, &user.roles, is_admin).is_ok();
+
+    let settings = user_settings::find(db.conn(), &user.user_id)
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+    let default_model = settings
+        .and_then(|s| s.default_model)
+        .filter(|model| available(*model))
+        .unwrap_or(config.pricing.model);
+
+    Ok(Json(ListModelsOutput {
+        models: config
+            .models
+            .allowed
+            .iter()
+            .copied()
+            .filter(|model| available(*model))
+            .map(|model| {
+                let price = config.pricing.price_of(model);
+                ModelDto {
+                    name: model,
+                    default: model == default_model,
+                    input_usd_per_mtok: price.input_per_mtok,
+                    output_usd_per_mtok: price.output_per_mtok,
+                }
+            })
+            .collect(),
+    }))
+}
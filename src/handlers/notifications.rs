@@ -0,0 +1,249 @@
+use chrono::Utc;
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::State;
+use rocket_okapi::okapi::schemars::JsonSchema;
+use rocket_okapi::openapi;
+use sea_orm::sea_query::Expr;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, PaginatorTrait, QueryFilter,
+    QueryOrder, QuerySelect, Set,
+};
+use uuid::Uuid;
+
+use crate::auth::AuthenticatedUser;
+use crate::db::ReadDb;
+use crate::entities::notification::{
+    self, Entity as Notification, Model as NotificationModel, NotificationKind,
+};
+use crate::entities::session::Entity as Session;
+use crate::entities::session_watcher::{self, Entity as SessionWatcher};
+use crate::error::{Error, OResult};
+use crate::services::organizations;
+
+/// Notifications returned when no limit is given
+const DEFAULT_LIMIT: u64 = 50;
+
+/// Most notifications returned by one list call
+const MAX_LIMIT: u64 = 200;
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct NotificationDto {
+    pub id: String,
+    pub session_id: String,
+    pub prompt_id: Option<String>,
+    pub kind: NotificationKind,
+    pub message: String,
+    pub read_at: Option<String>,
+    pub created_at: String,
+}
+
+impl From<NotificationModel> for NotificationDto {
+    fn from(model: NotificationModel) -> Self {
+        NotificationDto {
+            id: model.id.to_string(),
+            session_id: model.session_id.to_string(),
+            prompt_id: model.prompt_id.map(|id| id.to_string()),
+            kind: model.kind,
+            message: model.message,
+            read_at: model.read_at.map(|d| d.to_string()),
+            created_at: model.created_at.to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct ListNotificationsOutput {
+    pub notifications: Vec<NotificationDto>,
+    /// Unread notifications in total, not just in this page
+    pub unread_count: u64,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct NotificationOutput {
+    pub notification: NotificationDto,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct MarkAllReadOutput {
+    /// Notifications that were unread
+    pub updated: u64,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct SessionWatchOutput {
+    pub session_id: String,
+    pub watching: bool,
+}
+
+/// List your notifications, newest first
+#[openapi]
+#[get("/notifications?<unread>&<limit>")]
+pub async fn list(
+    user: AuthenticatedUser,
+    db: &State<ReadDb>,
+    unread: Option<bool>,
+    limit: Option<u64>,
+) -> OResult<ListNotificationsOutput> {
+    let mut query = Notification::find().filter(notification::Column::UserId.eq(&user.user_id));
+    if unread.unwrap_or(false) {
+        query = query.filter(notification::Column::ReadAt.is_null());
+    }
+
+    let notifications = query
+        .order_by_desc(notification::Column::CreatedAt)
+        .limit(limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT))
+        .all(db.conn())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+
+    let unread_count = Notification::find()
+        .filter(notification::Column::UserId.eq(&user.user_id))
+        .filter(notification::Column::ReadAt.is_null())
+        .count(db.conn())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+
+    Ok(Json(ListNotificationsOutput {
+        notifications: notifications
+            .into_iter()
+            .map(NotificationDto::from)
+            .collect(),
+        unread_count,
+    }))
+}
+
+/// Mark one notification as read
+#[openapi]
+#[post("/notifications/<id>/read")]
+pub async fn mark_read(
+    user: AuthenticatedUser,
+    db: &State<DatabaseConnection>,
+    id: String,
+) -> OResult<NotificationOutput> {
+    let uuid =
+        Uuid::parse_str(&id).map_err(|_| Error::bad_request("Invalid UUID format".to_string()))?;
+
+    let existing = Notification::find_by_id(uuid)
+        .filter(notification::Column::UserId.eq(&user.user_id))
+        .one(db.inner())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?
+        .ok_or_else(|| Error::not_found("Notification not found".to_string()))?;
+
+    if existing.read_at.is_some() {
+        return Ok(Json(NotificationOutput {
+            notification: existing.into(),
+        }));
+    }
+
+    let mut active_notification: notification::ActiveModel = existing.into();
+    active_notification.read_at = Set(Some(Utc::now().into()));
+    let notification = active_notification
+        .update(db.inner())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+
+    Ok(Json(NotificationOutput {
+        notification: notification.into(),
+    }))
+}
+
+/// Mark all your notifications as read
+#[openapi]
+#[post("/notifications/read")]
+pub async fn mark_all_read(
+    user: AuthenticatedUser,
+    db: &State<DatabaseConnection>,
+) -> OResult<MarkAllReadOutput> {
+    let result = Notification::update_many()
+        .col_expr(
+            notification::Column::ReadAt,
+            Expr::value(chrono::DateTime::<chrono::FixedOffset>::from(Utc::now())),
+        )
+        .filter(notification::Column::UserId.eq(&user.user_id))
+        .filter(notification::Column::ReadAt.is_null())
+        .exec(db.inner())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+
+    Ok(Json(MarkAllReadOutput {
+        updated: result.rows_affected,
+    }))
+}
+
+/// Record whether the user watches session `id`
+async fn set_watching(
+    db: &DatabaseConnection,
+    user: &AuthenticatedUser,
+    id: &str,
+    watching: bool,
+) -> Result<SessionWatchOutput, Error> {
+    let uuid =
+        Uuid::parse_str(id).map_err(|_| Error::bad_request("Invalid UUID format".to_string()))?;
+
+    let session = Session::find_by_id(uuid)
+        .filter(organizations::visible_to(user))
+        .one(db)
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?
+        .ok_or_else(|| Error::not_found("Session not found".to_string()))?;
+
+    let existing = SessionWatcher::find()
+        .filter(session_watcher::Column::SessionId.eq(session.id))
+        .filter(session_watcher::Column::UserId.eq(&user.user_id))
+        .one(db)
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+
+    let result = match existing {
+        Some(watcher) => {
+            let mut active_watcher: session_watcher::ActiveModel = watcher.into();
+            active_watcher.watching = Set(watching);
+            active_watcher.updated_at = Set(Utc::now().into());
+            active_watcher.update(db).await
+        }
+        None => {
+            session_watcher::ActiveModel {
+                id: Set(Uuid::new_v4()),
+                session_id: Set(session.id),
+                user_id: Set(user.user_id.clone()),
+                watching: Set(watching),
+                created_at: Set(Utc::now().into()),
+                updated_at: Set(Utc::now().into()),
+            }
+            .insert(db)
+            .await
+        }
+    };
+    result.map_err(|e| Error::database_error(e.to_string()))?;
+
+    Ok(SessionWatchOutput {
+        session_id: session.id.to_string(),
+        watching,
+    })
+}
+
+/// Subscribe to a session's notifications
+///
+/// Owners are subscribed to their sessions by default; this undoes an earlier unsubscribe.
+#[openapi]
+#[post("/sessions/<id>/watch")]
+pub async fn watch(
+    user: AuthenticatedUser,
+    db: &State<DatabaseConnection>,
+    id: String,
+) -> OResult<SessionWatchOutput> {
+    set_watching(db.inner(), &user, &id, true).await.map(Json)
+}
+
+/// Unsubscribe from a session's notifications
+#[openapi]
+#[delete("/sessions/<id>/watch")]
+pub async fn unwatch(
+    user: AuthenticatedUser,
+    db: &State<DatabaseConnection>,
+    id: String,
+) -> OResult<SessionWatchOutput> {
+    set_watching(db.inner(), &user, &id, false).await.map(Json)
+}
@@ -0,0 +1,187 @@
+//! `GET /openapi.json`, rendered once at startup.
+//!
+//! Swagger UI, RapiDoc and client generators fetch the spec constantly, so it is serialized and
+//! hashed once and served with an `ETag` and `Cache-Control`; a request whose `If-None-Match`
+//! matches gets an empty 304. Clients built against a spec can pass its version as `?version=`
+//! to find out whether this server is still compatible with it.
+
+use std::io::Cursor;
+use std::sync::Arc;
+
+use rocket::http::{ContentType, Header, Status};
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::{self, Responder, Response};
+use rocket::State;
+use rocket_okapi::okapi::openapi3::OpenApi;
+
+use crate::error::Error;
+use crate::services::hashing;
+
+/// Version of the API, also the `info.version` of the spec
+pub const API_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Caches may reuse the spec for this long before revalidating it with the ETag
+const CACHE_CONTROL: &str = "public, max-age=300";
+
+/// The serialized spec and its entity tag
+pub struct OpenApiDocument {
+    body: Arc<[u8]>,
+    etag: String,
+}
+
+impl OpenApiDocument {
+    pub fn new(spec: &OpenApi) -> Self {
+        let body = serde_json::to_vec(spec).expect("OpenAPI spec serializes to JSON");
+        let digest = &hashing::sha256_hex(&body)[..32];
+        OpenApiDocument {
+            body: body.into(),
+            etag: format!("\"{}\"", digest),
+        }
+    }
+
+    /// Whether an `If-None-Match` value names this document
+    fn matches(&self, if_none_match: &str) -> bool {
+        if_none_match
+            .split(',')
+            .map(|tag| tag.trim().trim_start_matches("W/"))
+            .any(|tag| tag == "*" || tag == self.etag)
+    }
+}
+
+/// Value of the `If-None-Match` header, if sent
+pub struct IfNoneMatch(Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IfNoneMatch {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(IfNoneMatch(
+            request
+                .headers()
+                .get_one("If-None-Match")
+                .map(str::to_string),
+        ))
+    }
+}
+
+/// The spec, 304 when the client's copy is current, or why it cannot be served
+pub enum OpenApiResponse {
+    Document { body: Arc<[u8]>, etag: String },
+    NotModified { etag: String },
+    Error(Error),
+}
+
+impl<'r> Responder<'r, 'static> for OpenApiResponse {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'static> {
+        let mut response = Response::build();
+        response
+            .header(Header::new("Cache-Control", CACHE_CONTROL))
+            .header(Header::new("X-API-Version", API_VERSION));
+        match self {
+            OpenApiResponse::Document { body, etag } => response
+                .header(Header::new("ETag", etag))
+                .header(ContentType::JSON)
+                .sized_body(body.len(), Cursor::new(body)),
+            OpenApiResponse::NotModified { etag } => response
+                .header(Header::new("ETag", etag))
+                .status(Status::NotModified),
+            OpenApiResponse::Error(error) => return error.respond_to(request),
+        };
+        response.ok()
+    }
+}
+
+/// Whether a server at version `server` can serve a client built against version `client`:
+/// the same major version (minor version while the major is 0) and no older than the client
+fn is_compatible(server: &str, client: &str) -> Result<bool, String> {
+    fn parse(version: &str) -> Option<(u64, u64, u64)> {
+        let mut parts = version.trim().trim_start_matches('v').splitn(3, '.');
+        let major = parts.next()?.parse().ok()?;
+        let minor = parts.next().unwrap_or("0").parse().ok()?;
+        // Pre-release and build suffixes do not affect compatibility
+        let patch = parts
+            .next()
+            .unwrap_or("0")
+            .split(['-', '+'])
+            .next()?
+            .parse()
+            .ok()?;
+        Some((major, minor, patch))
+    }
+
+    let server = parse(server).ok_or_else(|| format!("Invalid server version: {}", server))?;
+    let client = parse(client).ok_or_else(|| format!("Invalid version: {}", client))?;
+    let same_line = match client.0 {
+        0 => server.0 == 0 && server.1 == client.1,
+        major => server.0 == major,
+    };
+    Ok(same_line && server >= client)
+}
+
+/// OpenAPI document for this API
+///
+/// `version` is the API version a client was built against; a server that is not compatible
+/// with it answers 409 with its own version instead of the spec.
+#[get("/openapi.json?<version>")]
+pub fn openapi_json(
+    document: &State<OpenApiDocument>,
+    if_none_match: IfNoneMatch,
+    version: Option<String>,
+) -> OpenApiResponse {
+    if let Some(version) = version {
+        match is_compatible(API_VERSION, &version) {
+            Ok(true) => {}
+            Ok(false) => {
+                return OpenApiResponse::Error(Error::conflict(
+                    format!(
+                        "API version {} is not compatible with clients built for {}",
+                        API_VERSION, version
+                    ),
+                    serde_json::json!({
+                        "server_version": API_VERSION,
+                        "requested_version": version,
+                    }),
+                ))
+            }
+            Err(e) => return OpenApiResponse::Error(Error::bad_request(e)),
+        }
+    }
+
+    let etag = document.etag.clone();
+    match if_none_match.0 {
+        Some(tags) if document.matches(&tags) => OpenApiResponse::NotModified { etag },
+        _ => OpenApiResponse::Document {
+            body: document.body.clone(),
+            etag,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_compatible() {
+        assert_eq!(is_compatible("1.4.2", "1.2.0"), Ok(true));
+        assert_eq!(is_compatible("1.4.2", "1.4.2"), Ok(true));
+        assert_eq!(is_compatible("1.4.2", "1.5.0"), Ok(false));
+        assert_eq!(is_compatible("2.0.0", "1.9.0"), Ok(false));
+        assert_eq!(is_compatible("0.1.3", "0.1.0-beta.1"), Ok(true));
+        assert_eq!(is_compatible("0.2.0", "0.1.0"), Ok(false));
+        assert_eq!(is_compatible("0.1.0", "v0.1"), Ok(true));
+        assert!(is_compatible("0.1.0", "latest").is_err());
+    }
+
+    #[test]
+    fn test_etag_matching() {
+        let document = OpenApiDocument::new(&OpenApi::default());
+        let etag = document.etag.clone();
+
+        assert!(document.matches(&etag));
+        assert!(document.matches(&format!("\"stale\", W/{}", etag)));
+        assert!(document.matches("*"));
+        assert!(!document.matches("\"stale\""));
+    }
+}
@@ -1,23 +1,58 @@
+use chrono::Utc;
 use rocket::serde::json::Json;
 use rocket::serde::{Deserialize, Serialize};
 use rocket::State;
 use rocket_okapi::okapi::schemars::JsonSchema;
 use rocket_okapi::openapi;
 use sea_orm::{
-    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, NotSet, QueryFilter,
-    QueryOrder, Set,
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, DbErr, EntityTrait,
+    EnumIter, NotSet, QueryFilter, QueryOrder, QuerySelect, QueryTrait, Set, TransactionTrait,
 };
+use tracing::warn;
 use uuid::Uuid;
 
 use crate::auth::AuthenticatedUser;
-use crate::entities::prompt::{self, Entity as Prompt, Model as PromptModel};
-use crate::entities::session::{self, Entity as Session, UiStatus};
+use crate::bg_tasks::prompt_history;
+use crate::bg_tasks::prompt_tools::ToolSummary;
+use crate::config;
+use crate::db::ReadDb;
+use crate::entities::prompt::{
+    self, Entity as Prompt, Model as PromptModel, PipelineErrorKind, PromptPriority,
+};
+use crate::entities::prompt_artifact::{self, PromptArtifactKind};
+use crate::entities::prompt_file_change;
+use crate::entities::session::{self, ClaudeModel, Entity as Session, UiStatus};
+use crate::entities::session_event::SessionEventType;
 use crate::error::{Error, OResult};
+use crate::services::idempotency::{self, Begin, IdempotencyKey};
+use crate::services::prompt_changes::ChangeSummary;
+use crate::services::sandbox_queue::queued_statuses;
+use crate::services::secret_scan::SecretFinding;
+use crate::services::session_state_machine::{Actor, SessionStateMachine, TransitionCause};
+use crate::services::status_meta::StatusMeta;
+use crate::services::{
+    conversation, json_guard, organizations, prompt_attachments, session_events,
+};
 
 #[derive(Serialize, Deserialize, JsonSchema, Clone)]
 pub struct CreatePromptInput {
     pub session_id: String,
+    /// A conversation, `{"messages": [{"role": "user", "content": [{"type": "text", "text": ...}]}]}`;
+    /// older shapes are converted unless `PROMPT_LEGACY_DATA=reject`
     pub data: serde_json::Value,
+    /// Dispatch priority (default Normal); sessions with higher priority prompts start first
+    #[serde(default)]
+    pub priority: Option<PromptPriority>,
+    /// Prepend earlier prompts of the session and their messages (default true)
+    #[serde(default)]
+    pub include_history: Option<bool>,
+    /// Only include this many of the most recent earlier prompts (default all)
+    #[serde(default)]
+    pub history_depth: Option<u32>,
+    /// Save the prompt as a draft, which is not run until `POST /prompts/<id>/submit`
+    /// (default false)
+    #[serde(default)]
+    pub draft: Option<bool>,
 }
 
 #[derive(Serialize, Deserialize, JsonSchema, Clone)]
@@ -25,6 +60,10 @@ pub struct CreatePromptOutput {
     pub success: bool,
     pub message: String,
     pub id: String,
+    /// Identical prompt submitted to the same session moments before this one, likely a
+    /// double submission; both will run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duplicate_of: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, JsonSchema, Clone)]
@@ -34,6 +73,41 @@ pub struct PromptDto {
     pub data: serde_json::Value,
     pub created_at: String,
     pub updated_at: String,
+    /// Milliseconds spent in each processing phase, keyed by phase name
+    pub timings: Option<serde_json::Value>,
+    /// Estimated completion percentage of the current run, 0-100
+    pub progress: i16,
+    /// ID of the prompt this one re-runs
+    pub rerun_of: Option<String>,
+    /// When the current run started
+    pub started_at: Option<String>,
+    /// When the prompt finished running
+    pub completed_at: Option<String>,
+    /// Tool calls made by the current or last run, null before the first tool call
+    pub tool_summary: Option<ToolSummary>,
+    pub priority: PromptPriority,
+    /// Whether earlier prompts of the session are prepended to this one
+    pub include_history: bool,
+    /// Most recent earlier prompts included, null for all
+    pub history_depth: Option<i32>,
+    /// Claude model the last run used, null before the first run
+    pub model: Option<ClaudeModel>,
+    /// Stage the last run failed at, null unless it failed
+    pub error_kind: Option<PipelineErrorKind>,
+    /// Explanation of `error_kind` suitable for showing to users
+    pub error_message: Option<String>,
+    /// Files and lines the last run changed, null before a run finished
+    pub changes: Option<ChangeSummary>,
+    /// When the current or last run started or last produced output
+    pub last_activity_at: Option<String>,
+    /// Likely secrets the last run committed, with the secrets masked; its pushes were refused
+    /// until they are removed. Null unless the secret scan found some.
+    pub secret_findings: Option<Vec<SecretFinding>>,
+    /// Sandbox file the last run's CLI output was copied to, used to recover messages the
+    /// database missed; null before a run started
+    pub output_log_path: Option<String>,
+    /// Whether the prompt is a draft waiting to be submitted
+    pub draft: bool,
 }
 
 impl From<PromptModel> for PromptDto {
@@ -44,6 +118,25 @@ impl From<PromptModel> for PromptDto {
             data: model.data.clone(),
             created_at: model.created_at.to_string(),
             updated_at: model.updated_at.to_string(),
+            timings: model.timings,
+            progress: model.progress,
+            rerun_of: model.rerun_of.map(|id| id.to_string()),
+            started_at: model.started_at.map(|t| t.to_string()),
+            completed_at: model.completed_at.map(|t| t.to_string()),
+            tool_summary: model
+                .tool_summary
+                .and_then(|s| serde_json::from_value(s).ok()),
+            priority: model.priority,
+            include_history: model.include_history,
+            history_depth: model.history_depth,
+            model: model.model,
+            error_kind: model.error_kind,
+            error_message: model.error_kind.map(|kind| kind.user_message().to_string()),
+            changes: ChangeSummary::from_json(model.change_summary.as_ref()),
+            last_activity_at: model.last_activity_at.map(|t| t.to_string()),
+            secret_findings: SecretFinding::list_from_json(model.secret_findings.as_ref()),
+            output_log_path: model.output_log_path,
+            draft: model.draft,
         }
     }
 }
@@ -53,6 +146,94 @@ pub struct ReadPromptOutput {
     pub prompt: PromptDto,
 }
 
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct PromptRunOutput {
+    pub prompt_id: String,
+    /// Fully rendered system prompt passed to the Claude CLI
+    pub system_prompt: Option<String>,
+    /// Claude CLI argument vector
+    pub cli_args: Option<serde_json::Value>,
+    /// Version of the system prompt template the prompt was rendered from
+    pub template_version: Option<String>,
+    /// Claude model the run used
+    pub model: Option<ClaudeModel>,
+    /// Exit code of the CLI process, null while running or when it was killed by a signal
+    pub exit_code: Option<i32>,
+    /// Last 16 KiB of the CLI's stderr, kept when the run failed or produced no messages.
+    /// Only shown to the session owner.
+    pub stderr: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct RenderedPromptOutput {
+    pub prompt_id: String,
+    /// Prompt text passed to the Claude CLI, session history included
+    pub rendered_prompt: String,
+    /// True when this is the text the last run sent; false for a preview built from the
+    /// current history of a prompt that has not run yet
+    pub sent: bool,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct PromptArtifactDto {
+    pub id: String,
+    pub prompt_id: String,
+    pub session_id: String,
+    pub kind: PromptArtifactKind,
+    pub repo: String,
+    /// Full SHA of a pushed commit; null for pull requests
+    pub commit_sha: Option<String>,
+    /// The pull request opened by the run, or the pull request a commit's branch belongs to
+    pub pull_request_url: Option<String>,
+    pub created_at: String,
+}
+
+impl From<prompt_artifact::Model> for PromptArtifactDto {
+    fn from(model: prompt_artifact::Model) -> Self {
+        PromptArtifactDto {
+            id: model.id.to_string(),
+            prompt_id: model.prompt_id.to_string(),
+            session_id: model.session_id.to_string(),
+            kind: model.kind,
+            repo: model.repo,
+            commit_sha: model.commit_sha,
+            pull_request_url: model.pull_request_url,
+            created_at: model.created_at.to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct ListPromptArtifactsOutput {
+    pub artifacts: Vec<PromptArtifactDto>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct PromptFileChangeDto {
+    pub path: String,
+    /// Lines added; null for binary files
+    pub insertions: Option<i32>,
+    /// Lines removed; null for binary files
+    pub deletions: Option<i32>,
+}
+
+impl From<prompt_file_change::Model> for PromptFileChangeDto {
+    fn from(model: prompt_file_change::Model) -> Self {
+        PromptFileChangeDto {
+            path: model.path,
+            insertions: model.insertions,
+            deletions: model.deletions,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct ListPromptChangesOutput {
+    /// Totals over `files`, null before a run finished
+    pub summary: Option<ChangeSummary>,
+    pub files: Vec<PromptFileChangeDto>,
+}
+
 #[derive(Serialize, Deserialize, JsonSchema, Clone)]
 pub struct ListPromptsOutput {
     pub prompts: Vec<PromptDto>,
@@ -60,6 +241,8 @@ pub struct ListPromptsOutput {
 
 #[derive(Serialize, Deserialize, JsonSchema, Clone)]
 pub struct UpdatePromptInput {
+    /// A conversation, `{"messages": [{"role": "user", "content": [{"type": "text", "text": ...}]}]}`;
+    /// older shapes are converted unless `PROMPT_LEGACY_DATA=reject`
     pub data: serde_json::Value,
 }
 
@@ -69,6 +252,76 @@ pub struct UpdatePromptOutput {
     pub message: String,
 }
 
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct RequeuePromptInput {
+    /// New prompt data; omit to run the prompt again unchanged
+    #[serde(default)]
+    pub data: Option<serde_json::Value>,
+}
+
+/// How far a prompt has got on its way to a sandbox
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, PartialEq, Eq, EnumIter)]
+pub enum DispatchStatus {
+    /// Being composed; not queued until it is submitted
+    Draft,
+    /// Waiting in the queue with its session; the prompt can still be edited
+    Pending,
+    /// Handed to a worker, or left unfinished by a failed run
+    Dispatched,
+    /// Claimed by a run that has not finished
+    Running,
+    /// Run to completion
+    Completed,
+}
+
+impl StatusMeta for DispatchStatus {
+    const NAME: &'static str = "DispatchStatus";
+
+    fn label(&self) -> &'static str {
+        match self {
+            DispatchStatus::Draft => "Draft",
+            DispatchStatus::Pending => "Queued",
+            DispatchStatus::Dispatched => "Dispatched",
+            DispatchStatus::Running => "Running",
+            DispatchStatus::Completed => "Completed",
+        }
+    }
+
+    fn color(&self) -> &'static str {
+        match self {
+            DispatchStatus::Draft => "#cbd5e1",
+            DispatchStatus::Pending => "#94a3b8",
+            DispatchStatus::Dispatched => "#a78bfa",
+            DispatchStatus::Running => "#3b82f6",
+            DispatchStatus::Completed => "#22c55e",
+        }
+    }
+
+    fn terminal(&self) -> bool {
+        match self {
+            DispatchStatus::Completed => true,
+            DispatchStatus::Draft
+            | DispatchStatus::Pending
+            | DispatchStatus::Dispatched
+            | DispatchStatus::Running => false,
+        }
+    }
+}
+
+fn dispatch_status(prompt: &PromptModel, session_status: &UiStatus) -> DispatchStatus {
+    if prompt.draft {
+        DispatchStatus::Draft
+    } else if prompt.completed_at.is_some() {
+        DispatchStatus::Completed
+    } else if prompt.run_id.is_some() {
+        DispatchStatus::Running
+    } else if queued_statuses().contains(session_status) {
+        DispatchStatus::Pending
+    } else {
+        DispatchStatus::Dispatched
+    }
+}
+
 #[derive(Serialize, Deserialize, JsonSchema, Clone)]
 pub struct DeletePromptOutput {
     pub success: bool,
@@ -76,34 +329,110 @@ pub struct DeletePromptOutput {
 }
 
 /// Create a new prompt
+///
+/// Send an `Idempotency-Key` header to make retries safe: repeating the request with the same
+/// key returns the first response instead of creating another prompt. Independently of keys, a
+/// prompt identical to one submitted to the same session moments ago is still created but
+/// flagged with `duplicate_of`.
 #[openapi]
 #[post("/prompts", data = "<input>")]
 pub async fn create(
     user: AuthenticatedUser,
     db: &State<DatabaseConnection>,
+    idempotency_key: IdempotencyKey,
     input: Json<CreatePromptInput>,
 ) -> OResult<CreatePromptOutput> {
+    let reservation = match idempotency::begin(
+        db.inner(),
+        &idempotency_key,
+        &user.user_id,
+        "POST /prompts",
+        &input.0,
+    )
+    .await?
+    {
+        Begin::Replay(output) => return Ok(Json(output)),
+        Begin::Reserved(reservation) => Some(reservation),
+        Begin::Unkeyed => None,
+    };
+
+    let result = create_prompt(&user, db.inner(), &input).await;
+    if let Some(reservation) = reservation {
+        match &result {
+            Ok(output) => reservation.finish(db.inner(), &output.0).await,
+            Err(_) => reservation.release(db.inner()).await,
+        }
+    }
+    result
+}
+
+async fn create_prompt(
+    user: &AuthenticatedUser,
+    db: &DatabaseConnection,
+    input: &CreatePromptInput,
+) -> OResult<CreatePromptOutput> {
+    let limits = &config::get().request_limits;
+    json_guard::validate(
+        "data",
+        &input.data,
+        limits.prompt_data_bytes,
+        limits.max_json_depth,
+    )?;
+    let data = conversation::normalize(&input.data)
+        .map_err(|e| Error::bad_request(format!("Invalid data: {}", e)))?;
+    let data = prompt_attachments::resolve(db, &user.user_id, data).await?;
+
     let session_id = Uuid::parse_str(&input.session_id)
         .map_err(|_| Error::bad_request("Invalid session_id UUID format".to_string()))?;
+    let history_depth = input
+        .history_depth
+        .map(i32::try_from)
+        .transpose()
+        .map_err(|_| Error::bad_request("history_depth is too large".to_string()))?;
 
     // Verify session exists and belongs to user
     let session = Session::find_by_id(session_id)
         .filter(session::Column::UserId.eq(&user.user_id))
-        .one(db.inner())
+        .one(db)
         .await
         .map_err(|e| Error::database_error(e.to_string()))?
         .ok_or_else(|| Error::not_found("Session not found".to_string()))?;
+    if session.fan_out_limit.is_some() {
+        return Err(Error::bad_request(
+            "Fan-out tracking sessions do not run prompts; add them to its child sessions"
+                .to_string(),
+        ));
+    }
 
-    // If session is in NeedsReview or NeedsReviewIpReturned state, transition to Pending when adding new prompt
-    if session.ui_status == UiStatus::NeedsReview
-        || session.ui_status == UiStatus::NeedsReviewIpReturned
-    {
-        let mut active_session: session::ActiveModel = session.into();
-        active_session.ui_status = Set(UiStatus::Pending);
-        active_session
-            .update(db.inner())
+    let actor = Actor::User(user.user_id.clone());
+    let draft = input.draft.unwrap_or(false);
+
+    // Drafts leave the session as it is until they are submitted
+    if !draft {
+        if let Some((from, updated)) = queue_for_new_prompt(db, session, &actor).await? {
+            SessionStateMachine::after_save(
+                db,
+                &from,
+                &updated,
+                TransitionCause::PromptAdded,
+                &actor,
+            )
+            .await;
+        }
+    }
+
+    let duplicate_of = if draft {
+        None
+    } else {
+        recent_duplicate(db, session_id, &data)
             .await
-            .map_err(|e| Error::database_error(e.to_string()))?;
+            .map_err(|e| Error::database_error(e.to_string()))?
+    };
+    if let Some(duplicate_of) = duplicate_of {
+        warn!(
+            "Prompt submitted to session {} duplicates prompt {} submitted moments ago",
+            session_id, duplicate_of
+        );
     }
 
     let id = Uuid::new_v4();
@@ -111,19 +440,229 @@ pub async fn create(
     let new_prompt = prompt::ActiveModel {
         id: Set(id),
         session_id: Set(session_id),
-        data: Set(input.data.clone()),
+        data: Set(data),
         created_at: NotSet,
         updated_at: NotSet,
+        timings: Set(None),
+        progress: Set(0),
+        rerun_of: Set(None),
+        system_prompt: Set(None),
+        cli_args: Set(None),
+        template_version: Set(None),
+        run_id: Set(None),
+        started_at: Set(None),
+        completed_at: Set(None),
+        tool_summary: Set(None),
+        priority: Set(input.priority.unwrap_or_default()),
+        include_history: Set(input.include_history.unwrap_or(true)),
+        history_depth: Set(history_depth),
+        rendered_prompt: Set(None),
+        model: Set(None),
+        exit_code: Set(None),
+        stderr: Set(None),
+        error_kind: Set(None),
+        change_summary: Set(None),
+        last_activity_at: Set(None),
+        secret_findings: Set(None),
+        output_log_path: Set(None),
+        draft: Set(draft),
     };
 
-    match new_prompt.insert(db.inner()).await {
-        Ok(_) => Ok(Json(CreatePromptOutput {
+    new_prompt
+        .insert(db)
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+    if draft {
+        return Ok(Json(CreatePromptOutput {
             success: true,
-            message: "Prompt created successfully".to_string(),
+            message: "Draft prompt saved".to_string(),
             id: id.to_string(),
-        })),
-        Err(e) => Err(Error::database_error(e.to_string())),
+            duplicate_of: None,
+        }));
+    }
+    session_events::record(
+        db,
+        session_id,
+        SessionEventType::PromptAdded,
+        &actor,
+        Some(serde_json::json!({ "prompt_id": id.to_string() })),
+    )
+    .await;
+
+    Ok(Json(CreatePromptOutput {
+        success: true,
+        message: "Prompt created successfully".to_string(),
+        id: id.to_string(),
+        duplicate_of: duplicate_of.map(|id| id.to_string()),
+    }))
+}
+
+/// Move a session waiting for review back to Pending for a new prompt, returning its previous
+/// status and the saved session for `SessionStateMachine::after_save`. A session held for
+/// unpushed work reuses its sandbox, e.g. to have the agent push it.
+async fn queue_for_new_prompt<C: ConnectionTrait>(
+    conn: &C,
+    session: session::Model,
+    actor: &Actor,
+) -> Result<Option<(UiStatus, session::Model)>, Error> {
+    if session.ui_status != UiStatus::NeedsReview
+        && session.ui_status != UiStatus::NeedsReviewIpReturned
+        && session.ui_status != UiStatus::NeedsAttention
+    {
+        return Ok(None);
     }
+    let from = session.ui_status.clone();
+    let mut active_session = SessionStateMachine::transition(
+        session,
+        UiStatus::Pending,
+        TransitionCause::PromptAdded,
+        actor,
+    )
+    .map_err(|e| Error::bad_request(e.to_string()))?;
+    // The next return of the IP checks for unpushed work afresh
+    active_session.unpushed_work = Set(None);
+    let updated = active_session
+        .update(conn)
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+    Ok(Some((from, updated)))
+}
+
+/// The latest prompt of the session with the same data, if one was submitted within
+/// `DUPLICATE_PROMPT_WINDOW_SECS`
+async fn recent_duplicate(
+    db: &DatabaseConnection,
+    session_id: Uuid,
+    data: &serde_json::Value,
+) -> Result<Option<Uuid>, DbErr> {
+    let window = config::get().duplicate_prompt_window;
+    if window.is_zero() {
+        return Ok(None);
+    }
+    let since = Utc::now() - window;
+    Ok(Prompt::find()
+        .filter(prompt::Column::SessionId.eq(session_id))
+        .filter(prompt::Column::CreatedAt.gte(since))
+        .filter(prompt::Column::Data.eq(data.clone()))
+        .order_by_desc(prompt::Column::CreatedAt)
+        .one(db)
+        .await?
+        .map(|p| p.id))
+}
+
+/// Re-run a prompt
+///
+/// Creates a copy of the prompt on the same session and queues it. The sandbox resets to the
+/// latest pushed state of the session branch first, so manual fixes are picked up.
+#[openapi]
+#[post("/prompts/<id>/rerun")]
+pub async fn rerun(
+    user: AuthenticatedUser,
+    db: &State<DatabaseConnection>,
+    id: String,
+) -> OResult<CreatePromptOutput> {
+    let uuid =
+        Uuid::parse_str(&id).map_err(|_| Error::bad_request("Invalid UUID format".to_string()))?;
+
+    let original = Prompt::find_by_id(uuid)
+        .one(db.inner())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?
+        .ok_or_else(|| Error::not_found("Prompt not found".to_string()))?;
+
+    // Verify prompt's session belongs to user
+    let session = Session::find_by_id(original.session_id)
+        .filter(session::Column::UserId.eq(&user.user_id))
+        .one(db.inner())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?
+        .ok_or_else(|| Error::not_found("Session not found".to_string()))?;
+
+    // Only completed sessions can re-run; queued or running ones already have work in flight
+    if session.ui_status != UiStatus::NeedsReview
+        && session.ui_status != UiStatus::NeedsReviewIpReturned
+    {
+        return Err(Error::bad_request(
+            "Prompts can only be re-run once the session needs review".to_string(),
+        ));
+    }
+
+    let actor = Actor::User(user.user_id.clone());
+    let from = session.ui_status.clone();
+    let updated = SessionStateMachine::transition(
+        session,
+        UiStatus::Pending,
+        TransitionCause::PromptAdded,
+        &actor,
+    )
+    .map_err(|e| Error::bad_request(e.to_string()))?
+    .update(db.inner())
+    .await
+    .map_err(|e| Error::database_error(e.to_string()))?;
+    SessionStateMachine::after_save(
+        db.inner(),
+        &from,
+        &updated,
+        TransitionCause::PromptAdded,
+        &actor,
+    )
+    .await;
+
+    let id = Uuid::new_v4();
+
+    let new_prompt = prompt::ActiveModel {
+        id: Set(id),
+        session_id: Set(original.session_id),
+        data: Set(original.data),
+        created_at: NotSet,
+        updated_at: NotSet,
+        timings: Set(None),
+        progress: Set(0),
+        rerun_of: Set(Some(original.id)),
+        system_prompt: Set(None),
+        cli_args: Set(None),
+        template_version: Set(None),
+        run_id: Set(None),
+        started_at: Set(None),
+        completed_at: Set(None),
+        tool_summary: Set(None),
+        priority: Set(original.priority),
+        include_history: Set(original.include_history),
+        history_depth: Set(original.history_depth),
+        rendered_prompt: Set(None),
+        model: Set(None),
+        exit_code: Set(None),
+        stderr: Set(None),
+        error_kind: Set(None),
+        change_summary: Set(None),
+        last_activity_at: Set(None),
+        secret_findings: Set(None),
+        output_log_path: Set(None),
+        draft: Set(false),
+    };
+
+    new_prompt
+        .insert(db.inner())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+    session_events::record(
+        db.inner(),
+        original.session_id,
+        SessionEventType::PromptAdded,
+        &actor,
+        Some(serde_json::json!({
+            "prompt_id": id.to_string(),
+            "rerun_of": original.id.to_string(),
+        })),
+    )
+    .await;
+
+    Ok(Json(CreatePromptOutput {
+        success: true,
+        message: "Prompt re-run queued successfully".to_string(),
+        id: id.to_string(),
+        duplicate_of: None,
+    }))
 }
 
 /// Read (retrieve) a prompt by ID
@@ -131,22 +670,22 @@ pub async fn create(
 #[get("/prompts/<id>")]
 pub async fn read(
     user: AuthenticatedUser,
-    db: &State<DatabaseConnection>,
+    db: &State<ReadDb>,
     id: String,
 ) -> OResult<ReadPromptOutput> {
     let uuid =
         Uuid::parse_str(&id).map_err(|_| Error::bad_request("Invalid UUID format".to_string()))?;
 
     let prompt = Prompt::find_by_id(uuid)
-        .one(db.inner())
+        .one(db.conn())
         .await
         .map_err(|e| Error::database_error(e.to_string()))?
         .ok_or_else(|| Error::not_found("Prompt not found".to_string()))?;
 
     // Verify prompt's session belongs to user
     let _session = Session::find_by_id(prompt.session_id)
-        .filter(session::Column::UserId.eq(&user.user_id))
-        .one(db.inner())
+        .filter(organizations::visible_to(&user))
+        .one(db.conn())
         .await
         .map_err(|e| Error::database_error(e.to_string()))?
         .ok_or_else(|| Error::not_found("Session not found".to_string()))?;
@@ -156,12 +695,216 @@ pub async fn read(
     }))
 }
 
+/// Read how a prompt was run
+///
+/// Returns the rendered system prompt, CLI arguments and template version of the prompt's
+/// last run, with its exit code and, for the session owner, the stderr of a failed run. All
+/// fields are null until the prompt has started running.
+#[openapi]
+#[get("/prompts/<id>/run")]
+pub async fn read_run(
+    user: AuthenticatedUser,
+    db: &State<ReadDb>,
+    id: String,
+) -> OResult<PromptRunOutput> {
+    let uuid =
+        Uuid::parse_str(&id).map_err(|_| Error::bad_request("Invalid UUID format".to_string()))?;
+
+    let prompt = Prompt::find_by_id(uuid)
+        .one(db.conn())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?
+        .ok_or_else(|| Error::not_found("Prompt not found".to_string()))?;
+
+    // Verify prompt's session belongs to user
+    let session = Session::find_by_id(prompt.session_id)
+        .filter(organizations::visible_to(&user))
+        .one(db.conn())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?
+        .ok_or_else(|| Error::not_found("Session not found".to_string()))?;
+
+    // Stderr can echo environment and file contents, so org members do not get it
+    let stderr = prompt.stderr.filter(|_| session.user_id == user.user_id);
+
+    Ok(Json(PromptRunOutput {
+        prompt_id: prompt.id.to_string(),
+        system_prompt: prompt.system_prompt,
+        cli_args: prompt.cli_args,
+        template_version: prompt.template_version,
+        model: prompt.model,
+        exit_code: prompt.exit_code,
+        stderr,
+    }))
+}
+
+/// Read the final prompt text sent to the CLI
+///
+/// Returns the text of the prompt's last run, or a preview of what a run would send now when
+/// it has not run yet. The preview's history can still change if prompts are added before the
+/// run starts.
+#[openapi]
+#[get("/prompts/<id>/rendered")]
+pub async fn read_rendered(
+    user: AuthenticatedUser,
+    db: &State<ReadDb>,
+    id: String,
+) -> OResult<RenderedPromptOutput> {
+    let uuid =
+        Uuid::parse_str(&id).map_err(|_| Error::bad_request("Invalid UUID format".to_string()))?;
+
+    let prompt = Prompt::find_by_id(uuid)
+        .one(db.conn())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?
+        .ok_or_else(|| Error::not_found("Prompt not found".to_string()))?;
+
+    // Verify prompt's session belongs to user
+    let _session = Session::find_by_id(prompt.session_id)
+        .filter(organizations::visible_to(&user))
+        .one(db.conn())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?
+        .ok_or_else(|| Error::not_found("Session not found".to_string()))?;
+
+    let (rendered_prompt, sent) = match prompt.rendered_prompt.clone() {
+        Some(rendered) => (rendered, true),
+        None => (
+            prompt_history::render(db.conn(), &prompt)
+                .await
+                .map_err(|e| Error::internal_server_error(e.to_string()))?,
+            false,
+        ),
+    };
+
+    Ok(Json(RenderedPromptOutput {
+        prompt_id: prompt.id.to_string(),
+        rendered_prompt,
+        sent,
+    }))
+}
+
+/// List the commits and pull requests a prompt's runs produced
+#[openapi]
+#[get("/prompts/<id>/artifacts")]
+pub async fn list_artifacts(
+    user: AuthenticatedUser,
+    db: &State<ReadDb>,
+    id: String,
+) -> OResult<ListPromptArtifactsOutput> {
+    let uuid =
+        Uuid::parse_str(&id).map_err(|_| Error::bad_request("Invalid UUID format".to_string()))?;
+
+    let prompt = Prompt::find_by_id(uuid)
+        .one(db.conn())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?
+        .ok_or_else(|| Error::not_found("Prompt not found".to_string()))?;
+
+    // Verify prompt's session belongs to user
+    let _session = Session::find_by_id(prompt.session_id)
+        .filter(organizations::visible_to(&user))
+        .one(db.conn())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?
+        .ok_or_else(|| Error::not_found("Session not found".to_string()))?;
+
+    let artifacts = prompt_artifact::Entity::find()
+        .filter(prompt_artifact::Column::PromptId.eq(uuid))
+        .order_by_asc(prompt_artifact::Column::CreatedAt)
+        .all(db.conn())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+
+    Ok(Json(ListPromptArtifactsOutput {
+        artifacts: artifacts.into_iter().map(PromptArtifactDto::from).collect(),
+    }))
+}
+
+/// List the files a prompt's last run changed, with lines added and removed
+#[openapi]
+#[get("/prompts/<id>/changes")]
+pub async fn list_changes(
+    user: AuthenticatedUser,
+    db: &State<ReadDb>,
+    id: String,
+) -> OResult<ListPromptChangesOutput> {
+    let uuid =
+        Uuid::parse_str(&id).map_err(|_| Error::bad_request("Invalid UUID format".to_string()))?;
+
+    let prompt = Prompt::find_by_id(uuid)
+        .one(db.conn())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?
+        .ok_or_else(|| Error::not_found("Prompt not found".to_string()))?;
+
+    // Verify prompt's session belongs to user
+    let _session = Session::find_by_id(prompt.session_id)
+        .filter(organizations::visible_to(&user))
+        .one(db.conn())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?
+        .ok_or_else(|| Error::not_found("Session not found".to_string()))?;
+
+    let files = prompt_file_change::Entity::find()
+        .filter(prompt_file_change::Column::PromptId.eq(uuid))
+        .order_by_asc(prompt_file_change::Column::Path)
+        .all(db.conn())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+
+    Ok(Json(ListPromptChangesOutput {
+        summary: ChangeSummary::from_json(prompt.change_summary.as_ref()),
+        files: files.into_iter().map(PromptFileChangeDto::from).collect(),
+    }))
+}
+
+/// Find the prompts that produced a commit
+///
+/// `commit` is a full SHA or a prefix of at least 7 characters. Admins search every session;
+/// other users their own and their organization's.
+#[openapi]
+#[get("/artifacts?<commit>")]
+pub async fn find_artifacts(
+    user: AuthenticatedUser,
+    db: &State<ReadDb>,
+    commit: String,
+) -> OResult<ListPromptArtifactsOutput> {
+    let commit = commit.trim().to_lowercase();
+    if !(7..=40).contains(&commit.len()) || !commit.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(Error::bad_request(
+            "commit must be 7 to 40 hexadecimal characters".to_string(),
+        ));
+    }
+
+    let mut query = prompt_artifact::Entity::find()
+        .filter(prompt_artifact::Column::CommitSha.starts_with(&commit));
+    if !user.is_admin() {
+        let visible_sessions = Session::find()
+            .select_only()
+            .column(session::Column::Id)
+            .filter(organizations::visible_to(&user))
+            .into_query();
+        query = query.filter(prompt_artifact::Column::SessionId.in_subquery(visible_sessions));
+    }
+
+    let artifacts = query
+        .order_by_asc(prompt_artifact::Column::CreatedAt)
+        .all(db.conn())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+
+    Ok(Json(ListPromptArtifactsOutput {
+        artifacts: artifacts.into_iter().map(PromptArtifactDto::from).collect(),
+    }))
+}
+
 /// List all prompts for a session
 #[openapi]
 #[get("/sessions/<session_id>/prompts")]
 pub async fn list(
     user: AuthenticatedUser,
-    db: &State<DatabaseConnection>,
+    db: &State<ReadDb>,
     session_id: String,
 ) -> OResult<ListPromptsOutput> {
     let session_uuid = Uuid::parse_str(&session_id)
@@ -169,8 +912,8 @@ pub async fn list(
 
     // Verify session belongs to user
     let _session = Session::find_by_id(session_uuid)
-        .filter(session::Column::UserId.eq(&user.user_id))
-        .one(db.inner())
+        .filter(organizations::visible_to(&user))
+        .one(db.conn())
         .await
         .map_err(|e| Error::database_error(e.to_string()))?
         .ok_or_else(|| Error::not_found("Session not found".to_string()))?;
@@ -178,7 +921,7 @@ pub async fn list(
     match Prompt::find()
         .filter(prompt::Column::SessionId.eq(session_uuid))
         .order_by_asc(prompt::Column::CreatedAt)
-        .all(db.inner())
+        .all(db.conn())
         .await
     {
         Ok(prompts) => Ok(Json(ListPromptsOutput {
@@ -189,6 +932,10 @@ pub async fn list(
 }
 
 /// Update an existing prompt (PUT - full replacement)
+///
+/// Only drafts and prompts still waiting in the queue can be edited; once a prompt has been
+/// dispatched this returns 409 with its `status`. Use `POST /prompts/<id>/requeue` to change and
+/// re-run a processed prompt.
 #[openapi]
 #[put("/prompts/<id>", data = "<input>")]
 pub async fn update(
@@ -197,33 +944,264 @@ pub async fn update(
     id: String,
     input: Json<UpdatePromptInput>,
 ) -> OResult<UpdatePromptOutput> {
+    let limits = &config::get().request_limits;
+    json_guard::validate(
+        "data",
+        &input.data,
+        limits.prompt_data_bytes,
+        limits.max_json_depth,
+    )?;
+    let data = conversation::normalize(&input.data)
+        .map_err(|e| Error::bad_request(format!("Invalid data: {}", e)))?;
+    let data = prompt_attachments::resolve(db.inner(), &user.user_id, data).await?;
+
     let uuid =
         Uuid::parse_str(&id).map_err(|_| Error::bad_request("Invalid UUID format".to_string()))?;
 
-    let prompt = Prompt::find_by_id(uuid)
-        .one(db.inner())
+    let txn = db
+        .begin()
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+    let (prompt, session) = lock_prompt(&txn, &user, uuid).await?;
+
+    let status = dispatch_status(&prompt, &session.ui_status);
+    if status != DispatchStatus::Pending && status != DispatchStatus::Draft {
+        return Err(Error::conflict(
+            "Prompt can no longer be edited because it has been dispatched; requeue it instead"
+                .to_string(),
+            serde_json::json!({ "status": status }),
+        ));
+    }
+
+    let mut active_prompt: prompt::ActiveModel = prompt.into();
+    active_prompt.data = Set(data);
+
+    active_prompt
+        .update(&txn)
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+    txn.commit()
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+
+    Ok(Json(UpdatePromptOutput {
+        success: true,
+        message: "Prompt updated successfully".to_string(),
+    }))
+}
+
+/// Load a prompt of the user's own session for a change, locking the session and the prompt
+/// so the prompt poller and outbox jobs cannot dispatch it meanwhile
+async fn lock_prompt(
+    txn: &sea_orm::DatabaseTransaction,
+    user: &AuthenticatedUser,
+    prompt_id: Uuid,
+) -> Result<(PromptModel, session::Model), Error> {
+    let session_id = Prompt::find_by_id(prompt_id)
+        .one(txn)
         .await
         .map_err(|e| Error::database_error(e.to_string()))?
-        .ok_or_else(|| Error::not_found("Prompt not found".to_string()))?;
+        .ok_or_else(|| Error::not_found("Prompt not found".to_string()))?
+        .session_id;
 
     // Verify prompt's session belongs to user
-    let _session = Session::find_by_id(prompt.session_id)
+    let session = Session::find_by_id(session_id)
         .filter(session::Column::UserId.eq(&user.user_id))
-        .one(db.inner())
+        .lock_exclusive()
+        .one(txn)
         .await
         .map_err(|e| Error::database_error(e.to_string()))?
         .ok_or_else(|| Error::not_found("Session not found".to_string()))?;
 
+    let prompt = Prompt::find_by_id(prompt_id)
+        .lock_exclusive()
+        .one(txn)
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?
+        .ok_or_else(|| Error::not_found("Prompt not found".to_string()))?;
+
+    Ok((prompt, session))
+}
+
+/// Re-process a prompt
+///
+/// Puts a dispatched prompt back in the queue, with its `data` replaced when given, and queues
+/// its session. Unlike a re-run no copy is made: the prompt's earlier messages are discarded
+/// when it runs again. Only possible once the session needs review; a prompt that is still
+/// pending returns 409 and can be edited with `PUT /prompts/<id>` instead.
+#[openapi]
+#[post("/prompts/<id>/requeue", data = "<input>")]
+pub async fn requeue(
+    user: AuthenticatedUser,
+    db: &State<DatabaseConnection>,
+    id: String,
+    input: Json<RequeuePromptInput>,
+) -> OResult<UpdatePromptOutput> {
+    let data = match &input.data {
+        Some(data) => {
+            let limits = &config::get().request_limits;
+            json_guard::validate(
+                "data",
+                data,
+                limits.prompt_data_bytes,
+                limits.max_json_depth,
+            )?;
+            let data = conversation::normalize(data)
+                .map_err(|e| Error::bad_request(format!("Invalid data: {}", e)))?;
+            Some(prompt_attachments::resolve(db.inner(), &user.user_id, data).await?)
+        }
+        None => None,
+    };
+
+    let uuid =
+        Uuid::parse_str(&id).map_err(|_| Error::bad_request("Invalid UUID format".to_string()))?;
+
+    let txn = db
+        .begin()
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+    let (prompt, session) = lock_prompt(&txn, &user, uuid).await?;
+
+    let status = dispatch_status(&prompt, &session.ui_status);
+    if status == DispatchStatus::Pending || status == DispatchStatus::Draft {
+        return Err(Error::conflict(
+            "Prompt has not been dispatched yet; edit it instead".to_string(),
+            serde_json::json!({ "status": status }),
+        ));
+    }
+    if session.ui_status != UiStatus::NeedsReview
+        && session.ui_status != UiStatus::NeedsReviewIpReturned
+    {
+        return Err(Error::conflict(
+            "Prompts can only be requeued once the session needs review".to_string(),
+            serde_json::json!({ "status": status, "ui_status": session.ui_status }),
+        ));
+    }
+
     let mut active_prompt: prompt::ActiveModel = prompt.into();
-    active_prompt.data = Set(input.data.clone());
+    if let Some(data) = data {
+        active_prompt.data = Set(data);
+    }
+    active_prompt.run_id = Set(None);
+    active_prompt.started_at = Set(None);
+    active_prompt.completed_at = Set(None);
+    active_prompt.progress = Set(0);
+    active_prompt.timings = Set(None);
+    active_prompt.tool_summary = Set(None);
+    active_prompt.exit_code = Set(None);
+    active_prompt.stderr = Set(None);
+    active_prompt.error_kind = Set(None);
+    active_prompt.change_summary = Set(None);
+    active_prompt.secret_findings = Set(None);
+    active_prompt.output_log_path = Set(None);
+    active_prompt
+        .update(&txn)
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
 
-    match active_prompt.update(db.inner()).await {
-        Ok(_) => Ok(Json(UpdatePromptOutput {
-            success: true,
-            message: "Prompt updated successfully".to_string(),
-        })),
-        Err(e) => Err(Error::database_error(e.to_string())),
+    let actor = Actor::User(user.user_id.clone());
+    let from = session.ui_status.clone();
+    let updated = SessionStateMachine::transition(
+        session,
+        UiStatus::Pending,
+        TransitionCause::PromptRequeued,
+        &actor,
+    )
+    .map_err(|e| Error::bad_request(e.to_string()))?
+    .update(&txn)
+    .await
+    .map_err(|e| Error::database_error(e.to_string()))?;
+    txn.commit()
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+    SessionStateMachine::after_save(
+        db.inner(),
+        &from,
+        &updated,
+        TransitionCause::PromptRequeued,
+        &actor,
+    )
+    .await;
+
+    Ok(Json(UpdatePromptOutput {
+        success: true,
+        message: "Prompt requeued successfully".to_string(),
+    }))
+}
+
+/// Submit a draft prompt
+///
+/// Queues the draft as if it had just been created: a session waiting for review is queued
+/// again, and the prompt's `created_at` becomes the time of submission so that it follows the
+/// prompts that ran while it was being composed. A prompt that is not a draft returns 409 with
+/// its `status`.
+#[openapi]
+#[post("/prompts/<id>/submit")]
+pub async fn submit(
+    user: AuthenticatedUser,
+    db: &State<DatabaseConnection>,
+    id: String,
+) -> OResult<UpdatePromptOutput> {
+    let uuid =
+        Uuid::parse_str(&id).map_err(|_| Error::bad_request("Invalid UUID format".to_string()))?;
+
+    let txn = db
+        .begin()
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+    let (prompt, session) = lock_prompt(&txn, &user, uuid).await?;
+
+    let status = dispatch_status(&prompt, &session.ui_status);
+    if status != DispatchStatus::Draft {
+        return Err(Error::conflict(
+            "Prompt is not a draft".to_string(),
+            serde_json::json!({ "status": status }),
+        ));
     }
+    if session.fan_out_limit.is_some() {
+        return Err(Error::bad_request(
+            "Fan-out tracking sessions do not run prompts; add them to its child sessions"
+                .to_string(),
+        ));
+    }
+
+    let session_id = session.id;
+    let mut active_prompt: prompt::ActiveModel = prompt.into();
+    active_prompt.draft = Set(false);
+    active_prompt.created_at = Set(Utc::now().into());
+    active_prompt
+        .update(&txn)
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+
+    let actor = Actor::User(user.user_id.clone());
+    let queued = queue_for_new_prompt(&txn, session, &actor).await?;
+    txn.commit()
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+    if let Some((from, updated)) = queued {
+        SessionStateMachine::after_save(
+            db.inner(),
+            &from,
+            &updated,
+            TransitionCause::PromptAdded,
+            &actor,
+        )
+        .await;
+    }
+    session_events::record(
+        db.inner(),
+        session_id,
+        SessionEventType::PromptAdded,
+        &actor,
+        Some(serde_json::json!({ "prompt_id": uuid.to_string() })),
+    )
+    .await;
+
+    Ok(Json(UpdatePromptOutput {
+        success: true,
+        message: "Prompt submitted successfully".to_string(),
+    }))
 }
 
 /// Delete a prompt by ID
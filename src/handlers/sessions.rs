@@ -4,25 +4,82 @@ use rocket::State;
 use rocket_okapi::okapi::schemars::JsonSchema;
 use rocket_okapi::openapi;
 use sea_orm::{
-    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, NotSet, QueryFilter,
-    QueryOrder, Set,
+    ActiveModelTrait, ColumnTrait, Condition, DatabaseConnection, EntityTrait, NotSet, QueryFilter,
+    QueryOrder, QuerySelect, Set, TransactionTrait,
 };
 use uuid::Uuid;
 
 use crate::auth::AuthenticatedUser;
-use crate::entities::prompt;
+use crate::config::{self, RepoLockMode};
+use crate::db::ReadDb;
+use crate::entities::prompt::PromptPriority;
 use crate::entities::session::{
-    self, CancellationStatus, Entity as Session, Model as SessionModel, UiStatus,
+    self, CancellationMode, CancellationStatus, ClaudeModel, Entity as Session,
+    Model as SessionModel, UiStatus,
 };
+use crate::entities::session_event::{self, SessionEventType};
+use crate::entities::{prompt, session_artifact};
 use crate::error::{Error, OResult};
-use crate::services::anthropic;
+use crate::services::fieldsets::{Fieldset, Sparse};
+use crate::services::idempotency::{self, Begin, IdempotencyKey};
+use crate::services::session_preflight::{self, PreflightCheck};
+use crate::services::session_state_machine::{Actor, SessionStateMachine, TransitionCause};
+use crate::services::{
+    conversation, conversation_view, cost_estimate, egress_policy, fan_out, ingestion_filter,
+    json_guard, message_blobs, message_compaction, organizations, path_policy, pr_description,
+    prompt_attachments, repo_lock, sandbox_capabilities, sandbox_queue, session_activity,
+    session_budget, session_events, session_tags, session_titles, unpushed_work, user_settings,
+};
 use chrono::Utc;
+use egress_policy::{EffectiveEgressPolicy, EgressPolicy};
+use ingestion_filter::IngestionRules;
+use message_compaction::MessageCompaction;
+use path_policy::PathPolicy;
+use pr_description::PrDescriptionError;
+use sandbox_capabilities::SandboxCapabilities;
+use session_budget::SessionBudget;
+use tracing::warn;
+use unpushed_work::UnpushedWork;
+
+/// Events returned per page when no limit is given
+const DEFAULT_EVENTS_LIMIT: u64 = 50;
+
+/// Most events returned per page
+const MAX_EVENTS_LIMIT: u64 = 200;
 
 #[derive(Serialize, Deserialize, JsonSchema, Clone)]
 pub struct CreateSessionInput {
     pub parent: Option<String>,
+    /// `owner/name`, or an https or ssh clone URL on a configured GitHub host; stored as
+    /// `owner/name` on the default host and `https://<host>/owner/name` elsewhere
     pub repo: String,
     pub target_branch: String,
+    /// Paths Claude may and may not change in this session
+    #[serde(default)]
+    pub path_policy: Option<PathPolicy>,
+    /// Hosts the sandbox may connect to while Claude runs; unrestricted when omitted
+    #[serde(default)]
+    pub egress_policy: Option<EgressPolicy>,
+    /// Claude model to run on, one of `GET /models`; the user's default model when omitted
+    #[serde(default)]
+    pub model: Option<ClaudeModel>,
+    /// Keep the sandbox through review for follow-up prompts and only return it once the
+    /// session is archived (default false)
+    #[serde(default)]
+    pub keep_sandbox_until_archive: Option<bool>,
+    /// Sandbox region to run in, e.g. `eu-west`; falls back to any region with a warning in
+    /// the status message when it has no capacity
+    #[serde(default)]
+    pub region: Option<String>,
+    /// Limits on the session's run time, messages and tokens across its prompts; the platform
+    /// defaults apply to limits left out
+    #[serde(default)]
+    pub budget: Option<SessionBudget>,
+    /// Actions on CLI messages by event kind (`type` or `type:subtype`) before they are
+    /// stored: `drop`, `aggregate` or `keep`; they override the deployment's rules for the
+    /// same kinds
+    #[serde(default)]
+    pub ingestion_filter: Option<IngestionRules>,
 }
 
 #[derive(Serialize, Deserialize, JsonSchema, Clone)]
@@ -30,14 +87,48 @@ pub struct CreateSessionOutput {
     pub success: bool,
     pub message: String,
     pub id: String,
+    /// Active session on the same repo and target branch, when the repo lock only warns
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conflicting_session_id: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, JsonSchema, Clone)]
 pub struct CreateSessionWithPromptInput {
     pub repo: String,
     pub target_branch: String,
+    /// Data of the initial prompt: a conversation, or an older shape converted unless
+    /// `PROMPT_LEGACY_DATA=reject`
     pub messages: serde_json::Value,
     pub parent_id: Option<String>,
+    /// Paths Claude may and may not change in this session
+    #[serde(default)]
+    pub path_policy: Option<PathPolicy>,
+    /// Hosts the sandbox may connect to while Claude runs; unrestricted when omitted
+    #[serde(default)]
+    pub egress_policy: Option<EgressPolicy>,
+    /// Dispatch priority of the initial prompt (default Normal)
+    #[serde(default)]
+    pub priority: Option<PromptPriority>,
+    /// Claude model to run on, one of `GET /models`; the user's default model when omitted
+    #[serde(default)]
+    pub model: Option<ClaudeModel>,
+    /// Keep the sandbox through review for follow-up prompts and only return it once the
+    /// session is archived (default false)
+    #[serde(default)]
+    pub keep_sandbox_until_archive: Option<bool>,
+    /// Sandbox region to run in, e.g. `eu-west`; falls back to any region with a warning in
+    /// the status message when it has no capacity
+    #[serde(default)]
+    pub region: Option<String>,
+    /// Limits on the session's run time, messages and tokens across its prompts; the platform
+    /// defaults apply to limits left out
+    #[serde(default)]
+    pub budget: Option<SessionBudget>,
+    /// Actions on CLI messages by event kind (`type` or `type:subtype`) before they are
+    /// stored: `drop`, `aggregate` or `keep`; they override the deployment's rules for the
+    /// same kinds
+    #[serde(default)]
+    pub ingestion_filter: Option<IngestionRules>,
 }
 
 #[derive(Serialize, Deserialize, JsonSchema, Clone)]
@@ -47,6 +138,115 @@ pub struct CreateSessionWithPromptOutput {
     pub message: String,
     pub session_id: String,
     pub prompt_id: String,
+    /// Active session on the same repo and target branch, when the repo lock only warns
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conflicting_session_id: Option<String>,
+    /// When the session is expected to start running, from the sessions queued ahead of it,
+    /// the allocator's capacity and recent bootstrap times; kept up to date on the session
+    #[serde(default)]
+    pub estimated_start_at: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct FanOutInput {
+    /// Repos to run the prompt against, one child session each
+    pub repos: Vec<String>,
+    pub target_branch: String,
+    /// Data of the child sessions' prompts, as for `POST /sessions/with-prompt`
+    pub messages: serde_json::Value,
+    /// Title of the tracking session (default "Fan-out to N repos")
+    #[serde(default)]
+    pub title: Option<String>,
+    /// Most child sessions holding a sandbox at once (default `FAN_OUT_CONCURRENCY`)
+    #[serde(default)]
+    pub max_concurrent: Option<u32>,
+    /// Paths Claude may and may not change in every child session
+    #[serde(default)]
+    pub path_policy: Option<PathPolicy>,
+    /// Hosts the child sessions' sandboxes may connect to while Claude runs
+    #[serde(default)]
+    pub egress_policy: Option<EgressPolicy>,
+    /// Dispatch priority of the child sessions' prompts (default Normal)
+    #[serde(default)]
+    pub priority: Option<PromptPriority>,
+    /// Claude model to run on, one of `GET /models`; the user's default model when omitted
+    #[serde(default)]
+    pub model: Option<ClaudeModel>,
+    /// Limits on each child session's run time, messages and tokens
+    #[serde(default)]
+    pub budget: Option<SessionBudget>,
+    /// Actions on each child session's CLI messages by event kind before they are stored
+    #[serde(default)]
+    pub ingestion_filter: Option<IngestionRules>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct FanOutChildDto {
+    pub repo: String,
+    pub session_id: String,
+    pub prompt_id: String,
+    /// Active session on the same repo and target branch, when the repo lock only warns
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conflicting_session_id: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct FanOutOutput {
+    pub success: bool,
+    pub message: String,
+    /// Tracking session; see `GET /sessions/<id>/fan-out` for progress
+    pub session_id: String,
+    pub children: Vec<FanOutChildDto>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct FanOutStatusCountDto {
+    pub status: UiStatus,
+    pub count: u64,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct FanOutChildStatusDto {
+    pub session_id: String,
+    pub repo: Option<String>,
+    pub title: Option<String>,
+    pub ui_status: UiStatus,
+    pub status_message: Option<String>,
+    pub cancellation_status: Option<CancellationStatus>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct FanOutProgressOutput {
+    pub session_id: String,
+    /// InProgress until every child session is done, then NeedsReview
+    pub ui_status: UiStatus,
+    pub max_concurrent: i32,
+    pub total: u64,
+    /// Children running or still to run
+    pub unfinished: u64,
+    /// Children whose cancellation was requested or carried out
+    pub cancelled: u64,
+    /// Children per status, including statuses no child is in
+    pub status_counts: Vec<FanOutStatusCountDto>,
+    pub children: Vec<FanOutChildStatusDto>,
+}
+
+impl From<SessionModel> for FanOutChildStatusDto {
+    fn from(model: SessionModel) -> Self {
+        FanOutChildStatusDto {
+            session_id: model.id.to_string(),
+            repo: model.repo,
+            title: model.title,
+            ui_status: model.ui_status,
+            status_message: model.status_message,
+            cancellation_status: model.cancellation_status,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, JsonSchema, Clone)]
@@ -64,8 +264,57 @@ pub struct SessionDto {
     pub updated_at: String,
     pub deleted_at: Option<String>,
     pub cancellation_status: Option<CancellationStatus>,
+    pub cancellation_mode: Option<CancellationMode>,
     pub cancelled_at: Option<String>,
     pub cancelled_by: Option<String>,
+    pub status_message: Option<String>,
+    pub tags: Vec<String>,
+    pub path_policy: Option<PathPolicy>,
+    /// Organization whose members can read the session
+    pub org_id: Option<String>,
+    /// Claude model the session runs on
+    pub model: ClaudeModel,
+    /// Set on fan-out tracking sessions: how many child sessions may hold a sandbox at once
+    pub fan_out_limit: Option<i32>,
+    /// Hosts the sandbox may connect to while Claude runs
+    pub egress_policy: Option<EgressPolicy>,
+    /// Rules installed in the sandbox for the latest run, including the hosts the run needs
+    pub effective_egress_policy: Option<EffectiveEgressPolicy>,
+    /// Body generated for the session's pull request, once it has one
+    pub pull_request_description: Option<String>,
+    /// Whether the sandbox is kept through review until the session is archived
+    pub keep_sandbox_until_archive: bool,
+    /// Work the sandbox was held for before returning its IP, see `NeedsAttention`
+    pub unpushed_work: Option<UnpushedWork>,
+    /// Sandbox region asked for at creation
+    pub region: Option<String>,
+    /// Region of the sandbox the session holds or last held, when known
+    pub sandbox_region: Option<String>,
+    /// What the session's sandbox supports, discovered when a run starts
+    pub sandbox_capabilities: Option<SandboxCapabilities>,
+    /// Disk used by the session's clone when a run last measured it
+    pub workspace_disk_bytes: Option<i64>,
+    pub workspace_disk_checked_at: Option<String>,
+    /// Latest compaction of the session's messages, set once it went over a soft limit
+    pub message_compaction: Option<MessageCompaction>,
+    /// Worker instance the session's prompts run on
+    pub worker_id: Option<String>,
+    pub worker_claimed_at: Option<String>,
+    /// When a run last started or produced output
+    pub last_activity_at: Option<String>,
+    /// Seconds since `last_activity_at`, for warning about runs that have gone quiet
+    pub seconds_since_activity: Option<i64>,
+    /// Limits set at creation; the platform defaults apply to limits left out
+    pub budget: Option<SessionBudget>,
+    /// Ingestion filter rules set at creation; the deployment's rules apply to other kinds
+    pub ingestion_filter: Option<IngestionRules>,
+    /// Whether some CLI messages of the session's runs are dropped or aggregated before they
+    /// are stored, by its own rules or the deployment's
+    pub ingestion_filter_active: bool,
+    /// CLI messages of the session's runs dropped or aggregated so far
+    pub messages_filtered: i64,
+    /// When the queued session is expected to start running, null once it left the queue
+    pub estimated_start_at: Option<String>,
 }
 
 impl From<SessionModel> for SessionDto {
@@ -83,20 +332,83 @@ impl From<SessionModel> for SessionDto {
             updated_at: model.updated_at.to_string(),
             deleted_at: model.deleted_at.map(|d| d.to_string()),
             cancellation_status: model.cancellation_status,
+            cancellation_mode: model.cancellation_mode,
             cancelled_at: model.cancelled_at.map(|d| d.to_string()),
             cancelled_by: model.cancelled_by,
+            status_message: model.status_message,
+            tags: session_tags::from_json(&model.tags),
+            path_policy: PathPolicy::from_json(model.path_policy.as_ref()),
+            org_id: model.org_id,
+            model: config::get().pricing.model_or_default(model.model),
+            fan_out_limit: model.fan_out_limit,
+            egress_policy: EgressPolicy::from_json(model.egress_policy.as_ref()),
+            effective_egress_policy: model
+                .effective_egress_policy
+                .and_then(|p| serde_json::from_value(p).ok()),
+            pull_request_description: model.pull_request_description,
+            keep_sandbox_until_archive: model.keep_sandbox_until_archive,
+            unpushed_work: UnpushedWork::from_json(model.unpushed_work.as_ref()),
+            region: model.region,
+            sandbox_region: model.sandbox_region,
+            sandbox_capabilities: SandboxCapabilities::from_json(
+                model.sandbox_capabilities.as_ref(),
+            ),
+            workspace_disk_bytes: model.workspace_disk_bytes,
+            workspace_disk_checked_at: model.workspace_disk_checked_at.map(|d| d.to_string()),
+            message_compaction: MessageCompaction::from_json(model.message_compaction.as_ref()),
+            worker_id: model.worker_id,
+            worker_claimed_at: model.worker_claimed_at.map(|d| d.to_string()),
+            seconds_since_activity: session_activity::seconds_since(
+                model.last_activity_at.as_ref(),
+                Utc::now(),
+            ),
+            last_activity_at: model.last_activity_at.map(|d| d.to_string()),
+            budget: SessionBudget::from_json(model.budget.as_ref()),
+            ingestion_filter: ingestion_filter::from_json(model.ingestion_filter.as_ref()),
+            ingestion_filter_active: !ingestion_filter::effective(
+                model.ingestion_filter.as_ref(),
+                &config::get().ingestion_filter,
+            )
+            .is_empty(),
+            messages_filtered: model.messages_filtered,
+            estimated_start_at: model.estimated_start_at.map(|t| t.to_string()),
         }
     }
 }
 
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ConversationMessageDto {
+    pub id: String,
+    pub data: serde_json::Value,
+    pub created_at: String,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ConversationPromptDto {
+    pub prompt_id: String,
+    pub data: serde_json::Value,
+    pub created_at: String,
+    pub messages: Vec<ConversationMessageDto>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ConversationOutput {
+    pub session_id: String,
+    /// Prompts oldest first, each with its messages in the order they were written
+    pub prompts: Vec<ConversationPromptDto>,
+}
+
 #[derive(Serialize, Deserialize, JsonSchema, Clone)]
 pub struct ReadSessionOutput {
-    pub session: SessionDto,
+    pub session: Sparse<SessionDto>,
 }
 
 #[derive(Serialize, Deserialize, JsonSchema, Clone)]
 pub struct ListSessionsOutput {
-    pub sessions: Vec<SessionDto>,
+    pub sessions: Vec<Sparse<SessionDto>>,
 }
 
 #[derive(Serialize, Deserialize, JsonSchema, Clone)]
@@ -109,12 +421,23 @@ pub struct UpdateSessionInput {
     pub target_branch: Option<String>,
     pub title: Option<String>,
     pub ui_status: Option<UiStatus>,
+    /// Replaces the session's path policy; an empty policy removes it
+    pub path_policy: Option<PathPolicy>,
+    /// Replaces the session's egress policy from the next run on
+    pub egress_policy: Option<EgressPolicy>,
+    /// Whether the sandbox is kept through review; turning it off returns a sandbox held by a
+    /// session in review
+    pub keep_sandbox_until_archive: Option<bool>,
 }
 
 #[derive(Serialize, Deserialize, JsonSchema, Clone)]
 pub struct UpdateSessionOutput {
     pub success: bool,
     pub message: String,
+    /// Active session on the repo and target branch the session moved to, when the repo lock
+    /// only warns
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conflicting_session_id: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, JsonSchema, Clone)]
@@ -129,55 +452,273 @@ pub struct CancelSessionOutput {
     pub message: String,
 }
 
-/// Create a new session
-#[openapi]
-#[post("/sessions", data = "<input>")]
-pub async fn create(
-    user: AuthenticatedUser,
-    db: &State<DatabaseConnection>,
-    input: Json<CreateSessionInput>,
-) -> OResult<CreateSessionOutput> {
-    let id = Uuid::new_v4();
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct UnpushedWorkOutput {
+    pub success: bool,
+    pub message: String,
+    /// What is still unpushed; None once the work was pushed
+    pub unpushed_work: Option<UnpushedWork>,
+}
 
-    let parent = match &input.parent {
-        Some(p) => Some(
-            Uuid::parse_str(p)
-                .map_err(|_| Error::bad_request("Invalid parent UUID format".to_string()))?,
-        ),
-        None => None,
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct AddSessionTagsInput {
+    pub tags: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct SessionTagsOutput {
+    pub success: bool,
+    pub tags: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionQueueOutput {
+    pub ui_status: UiStatus,
+    pub status_message: Option<String>,
+    /// 1-based position among sessions waiting for a sandbox, null once the session has one
+    pub position: Option<u64>,
+    pub in_flight: u64,
+    pub average_run_seconds: u64,
+    pub estimated_wait_seconds: Option<u64>,
+    /// When the session is expected to start running, bootstrap included; null once it has
+    /// a sandbox
+    pub estimated_start_at: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionEstimateOutput {
+    /// Model the estimate is priced for
+    pub model: ClaudeModel,
+    pub pending_prompt_ids: Vec<String>,
+    pub estimated_input_tokens: u64,
+    pub estimated_output_tokens: u64,
+    pub estimated_cost_usd: f64,
+    /// Finished runs on the same repo the estimate draws on; 0 means defaults were used
+    pub sampled_runs: u64,
+    /// What is left of the monthly budget, null when budgets are not enforced
+    pub remaining_budget_usd: Option<f64>,
+    /// False when the estimate exceeds the remaining budget and the session will not be
+    /// dispatched
+    pub within_budget: bool,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionArtifactDto {
+    pub id: String,
+    pub prompt_id: Option<String>,
+    /// Where the file was found in the sandbox
+    pub path: String,
+    /// Object storage key of the file contents
+    pub blob_key: String,
+    pub size_bytes: i64,
+    pub created_at: String,
+}
+
+impl From<session_artifact::Model> for SessionArtifactDto {
+    fn from(model: session_artifact::Model) -> Self {
+        SessionArtifactDto {
+            id: model.id.to_string(),
+            prompt_id: model.prompt_id.map(|p| p.to_string()),
+            path: model.path,
+            blob_key: model.blob_key,
+            size_bytes: model.size_bytes,
+            created_at: model.created_at.to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct ListSessionArtifactsOutput {
+    pub artifacts: Vec<SessionArtifactDto>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionEventDto {
+    pub id: String,
+    pub event_type: SessionEventType,
+    /// Who caused the event, `user:<id>` or `system:<task>`
+    pub actor: String,
+    pub from_status: Option<UiStatus>,
+    pub to_status: Option<UiStatus>,
+    pub metadata: Option<serde_json::Value>,
+    pub created_at: String,
+}
+
+impl From<session_event::Model> for SessionEventDto {
+    fn from(model: session_event::Model) -> Self {
+        SessionEventDto {
+            id: model.id.to_string(),
+            event_type: model.event_type,
+            actor: model.actor,
+            from_status: model.from_status,
+            to_status: model.to_status,
+            metadata: model.metadata,
+            created_at: model.created_at.to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ListSessionEventsOutput {
+    pub events: Vec<SessionEventDto>,
+    /// Pass as `after` to fetch the next page; null on the last page
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct PreflightCheckDto {
+    pub name: String,
+    pub passed: bool,
+    pub message: String,
+}
+
+impl From<PreflightCheck> for PreflightCheckDto {
+    fn from(check: PreflightCheck) -> Self {
+        PreflightCheckDto {
+            name: check.name.to_string(),
+            passed: check.passed,
+            message: check.message,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionPreflightOutput {
+    /// True when every check other than sandbox capacity passed
+    pub ready: bool,
+    pub checks: Vec<PreflightCheckDto>,
+    pub queued_ahead: u64,
+    pub estimated_wait_seconds: u64,
+}
+
+/// Validate a requested path policy and convert it for storage; empty policies are stored as
+/// null
+fn path_policy_json(policy: Option<&PathPolicy>) -> Result<Option<serde_json::Value>, String> {
+    match policy {
+        Some(policy) if !policy.is_empty() => {
+            policy.validate()?;
+            serde_json::to_value(policy)
+                .map(Some)
+                .map_err(|e| e.to_string())
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Validate a requested budget and convert it for storage; empty budgets are stored as null
+fn budget_json(budget: Option<&SessionBudget>) -> Result<Option<serde_json::Value>, String> {
+    match budget {
+        Some(budget) if !budget.is_empty() => {
+            budget.validate()?;
+            serde_json::to_value(budget)
+                .map(Some)
+                .map_err(|e| e.to_string())
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Validate requested ingestion filter rules and convert them for storage; empty rules are
+/// stored as null
+fn ingestion_filter_json(
+    rules: Option<&IngestionRules>,
+) -> Result<Option<serde_json::Value>, String> {
+    match rules {
+        Some(rules) if !rules.is_empty() => {
+            ingestion_filter::validate(rules)?;
+            serde_json::to_value(rules)
+                .map(Some)
+                .map_err(|e| e.to_string())
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Validate a requested egress policy and convert it for storage
+fn egress_policy_json(policy: Option<&EgressPolicy>) -> Result<Option<serde_json::Value>, String> {
+    match policy {
+        Some(policy) => {
+            policy.validate()?;
+            serde_json::to_value(policy)
+                .map(Some)
+                .map_err(|e| e.to_string())
+        }
+        None => Ok(None),
+    }
+}
+
+/// A requested sandbox region, checked against the configured `allowed` regions when there
+/// are any
+fn region_value(region: Option<&str>, allowed: &[String]) -> Result<Option<String>, String> {
+    let Some(region) = region.map(str::trim).filter(|r| !r.is_empty()) else {
+        return Ok(None);
     };
+    let well_formed = region.len() <= 50
+        && region
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+    if !well_formed {
+        return Err(format!("Invalid region: {}", region));
+    }
+    if !allowed.is_empty() && !allowed.iter().any(|a| a == region) {
+        return Err(format!(
+            "Unknown region {}; available regions: {}",
+            region,
+            allowed.join(", ")
+        ));
+    }
+    Ok(Some(region.to_string()))
+}
 
-    let prompt = "todo".to_string();
+/// Model a new session runs on: the requested one, checked against the allowlist and the
+/// user's plans, else the user's default while they may still use it. None selects the
+/// server default.
+async fn session_model(
+    db: &DatabaseConnection,
+    user: &AuthenticatedUser,
+    requested: Option<ClaudeModel>,
+) -> Result<Option<ClaudeModel>, Error> {
+    let policy = &config::get().models;
+    if let Some(model) = requested {
+        policy
+            .check(model, &user.roles, user.is_admin())
+            .map_err(Error::bad_request)?;
+        return Ok(Some(model));
+    }
 
-    // Generate title using Anthropic Haiku
-    let title = anthropic::generate_session_title(&input.repo, &input.target_branch, &prompt)
+    let settings = user_settings::find(db, &user.user_id)
         .await
-        .unwrap_or_else(|e| {
-            tracing::warn!("Failed to generate session title: {}", e);
-            "Untitled Session".to_string()
-        });
-
-    // Generate branch name
-    let generated_branch = anthropic::generate_branch_name(
-        &input.repo,
-        &input.target_branch,
-        &prompt,
-        &id.to_string(),
-    )
-    .await
-    .unwrap_or_else(|e| {
-        tracing::warn!("Failed to generate branch name: {}", e);
-        format!("claude/session-{}", &id.to_string()[..24])
-    });
+        .map_err(|e| Error::database_error(e.to_string()))?;
+    Ok(settings
+        .and_then(|s| s.default_model)
+        .filter(|model| policy.check(*model, &user.roles, user.is_admin()).is_ok()))
+}
 
-    let new_session = session::ActiveModel {
+/// A new pending session on `repo`. Title and branch are placeholders until the prompt poller
+/// generates them from the first prompt, so creation does not wait on the Anthropic API.
+pub fn new_session(
+    id: Uuid,
+    user: &AuthenticatedUser,
+    parent: Option<Uuid>,
+    repo: &str,
+    target_branch: &str,
+    path_policy: Option<serde_json::Value>,
+    model: Option<ClaudeModel>,
+) -> session::ActiveModel {
+    session::ActiveModel {
         id: Set(id),
         sbx_config: Set(None),
         parent: Set(parent),
-        branch: Set(Some(generated_branch)),
-        repo: Set(Some(input.repo.clone())),
-        target_branch: Set(Some(input.target_branch.clone())),
-        title: Set(Some(title)),
+        branch: Set(Some(session_titles::placeholder_branch(id))),
+        repo: Set(Some(repo.to_string())),
+        target_branch: Set(Some(target_branch.to_string())),
+        title: Set(Some(session_titles::PLACEHOLDER_TITLE.to_string())),
         ui_status: Set(UiStatus::Pending),
         user_id: Set(user.user_id.clone()),
         ip_return_retry_count: Set(0),
@@ -188,29 +729,150 @@ pub async fn create(
         cancelled_at: Set(None),
         cancelled_by: Set(None),
         process_pid: Set(None),
-    };
+        status_message: Set(None),
+        sandbox_borrow_attempts: Set(0),
+        next_borrow_attempt_at: Set(None),
+        tags: Set(serde_json::json!([])),
+        path_policy: Set(path_policy),
+        title_pending: Set(true),
+        agent_token_hash: Set(None),
+        agent_token_expires_at: Set(None),
+        org_id: Set(user.org_id.clone()),
+        ip_return_key: Set(None),
+        model: Set(model),
+        cancellation_mode: Set(None),
+        cancel_signalled_at: Set(None),
+        fan_out_limit: Set(None),
+        egress_policy: Set(None),
+        effective_egress_policy: Set(None),
+        pull_request_description: Set(None),
+        keep_sandbox_until_archive: Set(false),
+        unpushed_work: Set(None),
+        region: Set(None),
+        sandbox_region: Set(None),
+        worker_id: Set(None),
+        worker_claimed_at: Set(None),
+        last_activity_at: Set(None),
+        budget: Set(None),
+        estimated_start_at: Set(None),
+        worker_instance_id: Set(None),
+        sandbox_capabilities: Set(None),
+        message_compaction: Set(None),
+        title_attempts: Set(0),
+        title_retry_at: Set(None),
+        workspace_disk_bytes: Set(None),
+        workspace_disk_checked_at: Set(None),
+        ingestion_filter: Set(None),
+        messages_filtered: Set(0),
+    }
+}
 
-    match new_session.insert(db.inner()).await {
-        Ok(_) => Ok(Json(CreateSessionOutput {
-            success: true,
-            message: "Session created successfully".to_string(),
-            id: id.to_string(),
-        })),
-        Err(e) => Err(Error::database_error(e.to_string())),
+/// The first prompt of a new session
+pub fn new_prompt(
+    id: Uuid,
+    session_id: Uuid,
+    data: serde_json::Value,
+    priority: PromptPriority,
+) -> prompt::ActiveModel {
+    prompt::ActiveModel {
+        id: Set(id),
+        session_id: Set(session_id),
+        data: Set(data),
+        created_at: NotSet,
+        updated_at: NotSet,
+        timings: Set(None),
+        progress: Set(0),
+        rerun_of: Set(None),
+        system_prompt: Set(None),
+        cli_args: Set(None),
+        template_version: Set(None),
+        run_id: Set(None),
+        started_at: Set(None),
+        completed_at: Set(None),
+        tool_summary: Set(None),
+        priority: Set(priority),
+        include_history: Set(true),
+        history_depth: Set(None),
+        rendered_prompt: Set(None),
+        model: Set(None),
+        exit_code: Set(None),
+        stderr: Set(None),
+        error_kind: Set(None),
+        change_summary: Set(None),
+        last_activity_at: Set(None),
+        secret_findings: Set(None),
+        output_log_path: Set(None),
+        draft: Set(false),
     }
 }
 
-/// Create a new session with an initial prompt
+/// Preflight checks that also reject a real session creation, or an update moving session
+/// `moving` to another repo or target branch. A moved session already counts against the
+/// quota and does not conflict with itself.
+///
+/// Returns the repo in the canonical form sessions store, and the conflicting session's id
+/// when the repo lock is in warn mode and another active session targets the same repo and
+/// branch.
+async fn validate_new_session(
+    db: &DatabaseConnection,
+    user: &AuthenticatedUser,
+    repo: &str,
+    target_branch: &str,
+    moving: Option<Uuid>,
+) -> Result<(String, Option<String>), Error> {
+    session_preflight::validate_branch(target_branch).map_err(Error::bad_request)?;
+    let repo = session_preflight::validate_repo(repo)
+        .map_err(Error::bad_request)?
+        .canonical();
+    if moving.is_none() {
+        session_preflight::check_quota(db, &user.user_id, user.org_id.as_deref())
+            .await
+            .map_err(Error::bad_request)?;
+    }
+
+    let conflicting_session_id = check_repo_lock(db, &repo, target_branch, moving).await?;
+    Ok((repo, conflicting_session_id))
+}
+
+/// Check the repo lock for a new session on `repo` / `target_branch`, or session `except`
+/// moving to them
+///
+/// Fails with a conflict when the lock blocks, and returns the conflicting session's id when it
+/// only warns.
+async fn check_repo_lock(
+    db: &DatabaseConnection,
+    repo: &str,
+    target_branch: &str,
+    except: Option<Uuid>,
+) -> Result<Option<String>, Error> {
+    let mode = config::get().repo_lock;
+    let conflict = repo_lock::check(db, mode, repo, target_branch, except)
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+    match conflict {
+        Some(other) if mode == RepoLockMode::Block => Err(Error::conflict(
+            repo_lock::conflict_message(&other, repo, target_branch),
+            serde_json::json!({
+                "conflicting_session_id": other.id.to_string(),
+                "conflicting_session_status": other.ui_status,
+            }),
+        )),
+        Some(other) => Ok(Some(other.id.to_string())),
+        None => Ok(None),
+    }
+}
+
+/// Create a new session
 #[openapi]
-#[post("/sessions/with-prompt", data = "<input>")]
-pub async fn create_with_prompt(
+#[post("/sessions", data = "<input>")]
+pub async fn create(
     user: AuthenticatedUser,
     db: &State<DatabaseConnection>,
-    input: Json<CreateSessionWithPromptInput>,
-) -> OResult<CreateSessionWithPromptOutput> {
-    let session_id = Uuid::new_v4();
+    input: Json<CreateSessionInput>,
+) -> OResult<CreateSessionOutput> {
+    let id = Uuid::new_v4();
 
-    let parent = match &input.parent_id {
+    let parent = match &input.parent {
         Some(p) => Some(
             Uuid::parse_str(p)
                 .map_err(|_| Error::bad_request("Invalid parent UUID format".to_string()))?,
@@ -218,104 +880,532 @@ pub async fn create_with_prompt(
         None => None,
     };
 
-    // Extract prompt content for title/branch generation
-    // Try to get "content" field from messages, or use the entire JSON as string
-    let prompt_content = input.messages.to_string();
+    let path_policy = path_policy_json(input.path_policy.as_ref()).map_err(Error::bad_request)?;
+    let egress_policy =
+        egress_policy_json(input.egress_policy.as_ref()).map_err(Error::bad_request)?;
+    let region = region_value(input.region.as_deref(), &config::get().sandbox_regions)
+        .map_err(Error::bad_request)?;
+    let budget = budget_json(input.budget.as_ref()).map_err(Error::bad_request)?;
+    let ingestion_filter =
+        ingestion_filter_json(input.ingestion_filter.as_ref()).map_err(Error::bad_request)?;
+    let model = session_model(db.inner(), &user, input.model).await?;
+    let (repo, conflicting_session_id) =
+        validate_new_session(db.inner(), &user, &input.repo, &input.target_branch, None).await?;
 
-    tracing::info!(
-        session_id = session_id.to_string(),
-        "prompt_content" = prompt_content,
-    );
-
-    // Generate title using Anthropic Haiku
-    let title =
-        anthropic::generate_session_title(&input.repo, &input.target_branch, &prompt_content)
-            .await
-            .unwrap_or_else(|e| {
-                tracing::warn!("Failed to generate session title: {}", e);
-                "Untitled Session".to_string()
-            });
-
-    // Generate branch name
-    let generated_branch = anthropic::generate_branch_name(
-        &input.repo,
+    let mut new_session = new_session(
+        id,
+        &user,
+        parent,
+        &repo,
         &input.target_branch,
-        &prompt_content,
-        &session_id.to_string(),
-    )
-    .await
-    .unwrap_or_else(|e| {
-        tracing::warn!("Failed to generate branch name: {}", e);
-        format!("claude/session-{}", &session_id.to_string()[..24])
-    });
-
-    let new_session = session::ActiveModel {
-        id: Set(session_id),
-        sbx_config: Set(None),
-        parent: Set(parent),
-        branch: Set(Some(generated_branch)),
-        repo: Set(Some(input.repo.clone())),
-        target_branch: Set(Some(input.target_branch.clone())),
-        title: Set(Some(title)),
-        ui_status: Set(UiStatus::Pending),
-        user_id: Set(user.user_id.clone()),
-        ip_return_retry_count: Set(0),
-        created_at: NotSet,
-        updated_at: NotSet,
-        deleted_at: Set(None),
-        cancellation_status: Set(None),
-        cancelled_at: Set(None),
-        cancelled_by: Set(None),
-        process_pid: Set(None),
-    };
+        path_policy,
+        model,
+    );
+    new_session.egress_policy = Set(egress_policy);
+    new_session.keep_sandbox_until_archive = Set(input.keep_sandbox_until_archive.unwrap_or(false));
+    new_session.region = Set(region);
+    new_session.budget = Set(budget);
+    new_session.ingestion_filter = Set(ingestion_filter);
 
-    // Insert the session
     new_session
         .insert(db.inner())
         .await
         .map_err(|e| Error::database_error(e.to_string()))?;
+    session_events::record(
+        db.inner(),
+        id,
+        SessionEventType::Created,
+        &Actor::User(user.user_id.clone()),
+        Some(serde_json::json!({
+            "repo": repo,
+            "target_branch": input.target_branch,
+            "model": model,
+        })),
+    )
+    .await;
 
-    // Create the initial prompt
-    let prompt_id = Uuid::new_v4();
-    let new_prompt = prompt::ActiveModel {
-        id: Set(prompt_id),
-        session_id: Set(session_id),
-        data: Set(input.messages.clone()),
-        created_at: NotSet,
-        updated_at: NotSet,
-    };
-
-    new_prompt
-        .insert(db.inner())
-        .await
-        .map_err(|e| Error::database_error(e.to_string()))?;
-
-    Ok(Json(CreateSessionWithPromptOutput {
+    Ok(Json(CreateSessionOutput {
         success: true,
-        message: "Session and prompt created successfully".to_string(),
-        session_id: session_id.to_string(),
-        prompt_id: prompt_id.to_string(),
+        message: "Session created successfully".to_string(),
+        id: id.to_string(),
+        conflicting_session_id,
     }))
 }
 
-/// Read (retrieve) a session by ID
+/// Create a new session with an initial prompt
+///
+/// Send an `Idempotency-Key` header to make retries safe: repeating the request with the same
+/// key returns the first response instead of creating another session.
 #[openapi]
-#[get("/sessions/<id>")]
-pub async fn read(
+#[post("/sessions/with-prompt", data = "<input>")]
+pub async fn create_with_prompt(
     user: AuthenticatedUser,
     db: &State<DatabaseConnection>,
-    id: String,
+    idempotency_key: IdempotencyKey,
+    input: Json<CreateSessionWithPromptInput>,
+) -> OResult<CreateSessionWithPromptOutput> {
+    let reservation = match idempotency::begin(
+        db.inner(),
+        &idempotency_key,
+        &user.user_id,
+        "POST /sessions/with-prompt",
+        &input.0,
+    )
+    .await?
+    {
+        Begin::Replay(output) => return Ok(Json(output)),
+        Begin::Reserved(reservation) => Some(reservation),
+        Begin::Unkeyed => None,
+    };
+
+    let result = create_session_with_prompt(&user, db.inner(), &input).await;
+    if let Some(reservation) = reservation {
+        match &result {
+            Ok(output) => reservation.finish(db.inner(), &output.0).await,
+            Err(_) => reservation.release(db.inner()).await,
+        }
+    }
+    result
+}
+
+async fn create_session_with_prompt(
+    user: &AuthenticatedUser,
+    db: &DatabaseConnection,
+    input: &CreateSessionWithPromptInput,
+) -> OResult<CreateSessionWithPromptOutput> {
+    let limits = &config::get().request_limits;
+    json_guard::validate(
+        "messages",
+        &input.messages,
+        limits.prompt_data_bytes,
+        limits.max_json_depth,
+    )?;
+    let messages = conversation::normalize(&input.messages)
+        .map_err(|e| Error::bad_request(format!("Invalid messages: {}", e)))?;
+    let messages = prompt_attachments::resolve(db, &user.user_id, messages).await?;
+
+    let session_id = Uuid::new_v4();
+
+    let parent = match &input.parent_id {
+        Some(p) => Some(
+            Uuid::parse_str(p)
+                .map_err(|_| Error::bad_request("Invalid parent UUID format".to_string()))?,
+        ),
+        None => None,
+    };
+
+    let path_policy = path_policy_json(input.path_policy.as_ref()).map_err(Error::bad_request)?;
+    let egress_policy =
+        egress_policy_json(input.egress_policy.as_ref()).map_err(Error::bad_request)?;
+    let region = region_value(input.region.as_deref(), &config::get().sandbox_regions)
+        .map_err(Error::bad_request)?;
+    let budget = budget_json(input.budget.as_ref()).map_err(Error::bad_request)?;
+    let ingestion_filter =
+        ingestion_filter_json(input.ingestion_filter.as_ref()).map_err(Error::bad_request)?;
+    let model = session_model(db, user, input.model).await?;
+    let (repo, conflicting_session_id) =
+        validate_new_session(db, user, &input.repo, &input.target_branch, None).await?;
+
+    let mut new_session = new_session(
+        session_id,
+        user,
+        parent,
+        &repo,
+        &input.target_branch,
+        path_policy,
+        model,
+    );
+    new_session.egress_policy = Set(egress_policy);
+    new_session.keep_sandbox_until_archive = Set(input.keep_sandbox_until_archive.unwrap_or(false));
+    new_session.region = Set(region);
+    new_session.budget = Set(budget);
+    new_session.ingestion_filter = Set(ingestion_filter);
+
+    // Insert the session
+    let session_model = new_session
+        .insert(db)
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+
+    // Create the initial prompt
+    let prompt_id = Uuid::new_v4();
+    let new_prompt = new_prompt(
+        prompt_id,
+        session_id,
+        messages,
+        input.priority.unwrap_or_default(),
+    );
+
+    new_prompt
+        .insert(db)
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+
+    let estimated_start_at = estimate_start(db, session_model).await;
+
+    let actor = Actor::User(user.user_id.clone());
+    session_events::record(
+        db,
+        session_id,
+        SessionEventType::Created,
+        &actor,
+        Some(serde_json::json!({
+            "repo": repo,
+            "target_branch": input.target_branch,
+            "model": model,
+        })),
+    )
+    .await;
+    session_events::record(
+        db,
+        session_id,
+        SessionEventType::PromptAdded,
+        &actor,
+        Some(serde_json::json!({ "prompt_id": prompt_id.to_string() })),
+    )
+    .await;
+
+    Ok(Json(CreateSessionWithPromptOutput {
+        success: true,
+        message: "Session and prompt created successfully".to_string(),
+        session_id: session_id.to_string(),
+        prompt_id: prompt_id.to_string(),
+        conflicting_session_id,
+        estimated_start_at: estimated_start_at.map(|t| t.to_string()),
+    }))
+}
+
+/// Estimate when a new session starts running and store it on the session. None when the
+/// estimate fails, which never fails the creation.
+async fn estimate_start(
+    db: &DatabaseConnection,
+    session_model: SessionModel,
+) -> Option<chrono::DateTime<chrono::FixedOffset>> {
+    let session_id = session_model.id;
+    let estimate = match sandbox_queue::estimate_for_session(db, &session_model).await {
+        Ok(estimate) => estimate,
+        Err(e) => {
+            warn!(
+                "Failed to estimate the start of session {}: {}",
+                session_id, e
+            );
+            return None;
+        }
+    };
+    let start_at = sandbox_queue::start_at(Utc::now(), estimate.estimated_start_seconds?);
+    let mut active_session: session::ActiveModel = session_model.into();
+    active_session.estimated_start_at = Set(Some(start_at));
+    if let Err(e) = active_session.update(db).await {
+        warn!(
+            "Failed to store the estimated start of session {}: {}",
+            session_id, e
+        );
+    }
+    Some(start_at)
+}
+
+/// Run one prompt against many repos
+///
+/// Creates a tracking session and a child session per repo, each with its own copy of the
+/// prompt. The whole batch counts against the active session limits up front, and at most
+/// `max_concurrent` children hold a sandbox at once; the rest wait their turn. Cancelling the
+/// tracking session cancels every child that has not finished.
+#[openapi]
+#[post("/sessions/fan-out", data = "<input>")]
+pub async fn create_fan_out(
+    user: AuthenticatedUser,
+    db: &State<DatabaseConnection>,
+    input: Json<FanOutInput>,
+) -> OResult<FanOutOutput> {
+    let limits = &config::get().request_limits;
+    json_guard::validate(
+        "messages",
+        &input.messages,
+        limits.prompt_data_bytes,
+        limits.max_json_depth,
+    )?;
+    let messages = conversation::normalize(&input.messages)
+        .map_err(|e| Error::bad_request(format!("Invalid messages: {}", e)))?;
+    let messages = prompt_attachments::resolve(db.inner(), &user.user_id, messages).await?;
+
+    let fan_out_config = &config::get().fan_out;
+    if input.repos.is_empty() {
+        return Err(Error::bad_request(
+            "At least one repo is required".to_string(),
+        ));
+    }
+    if input.repos.len() > fan_out_config.max_repos {
+        return Err(Error::bad_request(format!(
+            "A fan-out may target at most {} repos, got {}",
+            fan_out_config.max_repos,
+            input.repos.len()
+        )));
+    }
+    session_preflight::validate_branch(&input.target_branch).map_err(Error::bad_request)?;
+    let mut repos: Vec<String> = Vec::with_capacity(input.repos.len());
+    for repo in &input.repos {
+        let canonical = session_preflight::validate_repo(repo)
+            .map_err(|e| Error::bad_request(format!("{}: {}", repo.trim(), e)))?
+            .canonical();
+        if repos.contains(&canonical) {
+            return Err(Error::bad_request(format!("Duplicate repo: {}", canonical)));
+        }
+        repos.push(canonical);
+    }
+    let max_concurrent = match input.max_concurrent {
+        Some(0) => {
+            return Err(Error::bad_request(
+                "max_concurrent must be at least 1".to_string(),
+            ))
+        }
+        Some(n) => n,
+        None => fan_out_config.default_concurrency.max(1),
+    }
+    .min(repos.len() as u32);
+
+    let path_policy = path_policy_json(input.path_policy.as_ref()).map_err(Error::bad_request)?;
+    let egress_policy =
+        egress_policy_json(input.egress_policy.as_ref()).map_err(Error::bad_request)?;
+    let budget = budget_json(input.budget.as_ref()).map_err(Error::bad_request)?;
+    let ingestion_filter =
+        ingestion_filter_json(input.ingestion_filter.as_ref()).map_err(Error::bad_request)?;
+    let model = session_model(db.inner(), &user, input.model).await?;
+    session_preflight::check_quota_for(
+        db.inner(),
+        &user.user_id,
+        user.org_id.as_deref(),
+        repos.len() as u64,
+    )
+    .await
+    .map_err(Error::bad_request)?;
+    let mut conflicts = Vec::with_capacity(repos.len());
+    for repo in &repos {
+        conflicts.push(check_repo_lock(db.inner(), repo, &input.target_branch, None).await?);
+    }
+
+    let tracking_id = Uuid::new_v4();
+    let mut tracking = new_session(
+        tracking_id,
+        &user,
+        None,
+        "",
+        &input.target_branch,
+        path_policy.clone(),
+        model,
+    );
+    tracking.repo = Set(None);
+    tracking.branch = Set(None);
+    tracking.title =
+        Set(Some(input.title.clone().unwrap_or_else(|| {
+            format!("Fan-out to {} repos", repos.len())
+        })));
+    tracking.title_pending = Set(false);
+    tracking.ui_status = Set(UiStatus::InProgress);
+    tracking.status_message = Set(Some(format!("0 of {} sessions finished", repos.len())));
+    tracking.fan_out_limit = Set(Some(max_concurrent as i32));
+    tracking.egress_policy = Set(egress_policy.clone());
+
+    let children: Vec<FanOutChildDto> = repos
+        .iter()
+        .zip(conflicts)
+        .map(|(repo, conflicting_session_id)| FanOutChildDto {
+            repo: repo.clone(),
+            session_id: Uuid::new_v4().to_string(),
+            prompt_id: Uuid::new_v4().to_string(),
+            conflicting_session_id,
+        })
+        .collect();
+
+    // All or nothing, so a failure part way does not leave a partial fan-out running
+    let txn = db
+        .begin()
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+    tracking
+        .insert(&txn)
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+    for child in &children {
+        let session_id = Uuid::parse_str(&child.session_id).expect("generated UUID");
+        let prompt_id = Uuid::parse_str(&child.prompt_id).expect("generated UUID");
+        let mut child_session = new_session(
+            session_id,
+            &user,
+            Some(tracking_id),
+            &child.repo,
+            &input.target_branch,
+            path_policy.clone(),
+            model,
+        );
+        child_session.egress_policy = Set(egress_policy.clone());
+        child_session.budget = Set(budget.clone());
+        child_session.ingestion_filter = Set(ingestion_filter.clone());
+        child_session
+            .insert(&txn)
+            .await
+            .map_err(|e| Error::database_error(e.to_string()))?;
+        new_prompt(
+            prompt_id,
+            session_id,
+            messages.clone(),
+            input.priority.unwrap_or_default(),
+        )
+        .insert(&txn)
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+    }
+    txn.commit()
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+
+    let actor = Actor::User(user.user_id.clone());
+    session_events::record(
+        db.inner(),
+        tracking_id,
+        SessionEventType::Created,
+        &actor,
+        Some(serde_json::json!({
+            "repos": repos,
+            "target_branch": input.target_branch,
+            "model": model,
+            "fan_out_limit": max_concurrent,
+        })),
+    )
+    .await;
+    for child in &children {
+        let session_id = Uuid::parse_str(&child.session_id).expect("generated UUID");
+        session_events::record(
+            db.inner(),
+            session_id,
+            SessionEventType::Created,
+            &actor,
+            Some(serde_json::json!({
+                "repo": child.repo,
+                "target_branch": input.target_branch,
+                "model": model,
+                "fan_out": tracking_id.to_string(),
+            })),
+        )
+        .await;
+        session_events::record(
+            db.inner(),
+            session_id,
+            SessionEventType::PromptAdded,
+            &actor,
+            Some(serde_json::json!({ "prompt_id": child.prompt_id })),
+        )
+        .await;
+    }
+
+    Ok(Json(FanOutOutput {
+        success: true,
+        message: format!(
+            "Fan-out created with {} sessions, running at most {} at once",
+            children.len(),
+            max_concurrent
+        ),
+        session_id: tracking_id.to_string(),
+        children,
+    }))
+}
+
+/// Progress of a fan-out
+///
+/// Summarizes the statuses of the child sessions of the fan-out tracked by session `id`
+#[openapi]
+#[get("/sessions/<id>/fan-out")]
+pub async fn fan_out_progress(
+    user: AuthenticatedUser,
+    db: &State<ReadDb>,
+    id: String,
+) -> OResult<FanOutProgressOutput> {
+    let uuid =
+        Uuid::parse_str(&id).map_err(|_| Error::bad_request("Invalid UUID format".to_string()))?;
+
+    let tracking = Session::find_by_id(uuid)
+        .filter(organizations::visible_to(&user))
+        .one(db.conn())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?
+        .ok_or_else(|| Error::not_found("Session not found".to_string()))?;
+    let Some(max_concurrent) = tracking.fan_out_limit else {
+        return Err(Error::not_found(
+            "Session is not a fan-out tracking session".to_string(),
+        ));
+    };
+
+    let children = fan_out::children(db.conn(), uuid)
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+    let summary = fan_out::summarize(&children);
+
+    Ok(Json(FanOutProgressOutput {
+        session_id: tracking.id.to_string(),
+        ui_status: tracking.ui_status,
+        max_concurrent,
+        total: summary.total,
+        unfinished: summary.unfinished,
+        cancelled: summary.cancelled,
+        status_counts: summary
+            .by_status
+            .into_iter()
+            .map(|(status, count)| FanOutStatusCountDto { status, count })
+            .collect(),
+        children: children.into_iter().map(Into::into).collect(),
+    }))
+}
+
+/// Preflight a session creation
+///
+/// Runs the checks a session creation depends on (repo access, target branch, quota and
+/// sandbox capacity) and estimates the wait, without creating rows or borrowing a sandbox
+#[openapi]
+#[post("/sessions/preflight", data = "<input>")]
+pub async fn preflight(
+    user: AuthenticatedUser,
+    db: &State<DatabaseConnection>,
+    input: Json<CreateSessionInput>,
+) -> OResult<SessionPreflightOutput> {
+    let report = session_preflight::run_preflight(
+        db.inner(),
+        &user.user_id,
+        user.org_id.as_deref(),
+        &input.repo,
+        &input.target_branch,
+    )
+    .await
+    .map_err(|e| Error::database_error(e.to_string()))?;
+
+    Ok(Json(SessionPreflightOutput {
+        ready: report.ready(),
+        queued_ahead: report.queued_ahead,
+        estimated_wait_seconds: report.estimated_wait_seconds,
+        checks: report.checks.into_iter().map(Into::into).collect(),
+    }))
+}
+
+/// Read (retrieve) a session by ID
+///
+/// `fields` selects the fields returned, e.g. `id,title,uiStatus`; all of them when omitted.
+#[openapi]
+#[get("/sessions/<id>?<fields>")]
+pub async fn read(
+    user: AuthenticatedUser,
+    db: &State<ReadDb>,
+    id: String,
+    fields: Option<String>,
 ) -> OResult<ReadSessionOutput> {
     let uuid =
         Uuid::parse_str(&id).map_err(|_| Error::bad_request("Invalid UUID format".to_string()))?;
+    let fields = Fieldset::parse::<SessionDto>(fields.as_deref()).map_err(Error::bad_request)?;
 
     match Session::find_by_id(uuid)
-        .filter(session::Column::UserId.eq(&user.user_id))
-        .one(db.inner())
+        .filter(organizations::visible_to(&user))
+        .one(db.conn())
         .await
     {
         Ok(Some(session)) => Ok(Json(ReadSessionOutput {
-            session: session.into(),
+            session: Sparse::new(session.into(), fields.as_ref()),
         })),
         Ok(None) => Err(Error::not_found("Session not found".to_string())),
         Err(e) => Err(Error::database_error(e.to_string())),
@@ -323,20 +1413,31 @@ pub async fn read(
 }
 
 /// List all sessions
+///
+/// Only sessions carrying `tag` are returned when it is given. `fields` selects the fields of
+/// each session, e.g. `id,title,uiStatus`, and leaves out the rest.
 #[openapi]
-#[get("/sessions")]
+#[get("/sessions?<tag>&<fields>")]
 pub async fn list(
     user: AuthenticatedUser,
-    db: &State<DatabaseConnection>,
+    db: &State<ReadDb>,
+    tag: Option<String>,
+    fields: Option<String>,
 ) -> OResult<ListSessionsOutput> {
-    match Session::find()
-        .filter(session::Column::UserId.eq(&user.user_id))
-        .order_by_asc(session::Column::Id)
-        .all(db.inner())
-        .await
-    {
+    let fields = Fieldset::parse::<SessionDto>(fields.as_deref()).map_err(Error::bad_request)?;
+    let mut query = Session::find()
+        .filter(organizations::visible_to(&user))
+        .filter(session::Column::DeletedAt.is_null());
+    if let Some(tag) = &tag {
+        query = query.filter(session_tags::has_tag(tag.trim()));
+    }
+
+    match query.order_by_asc(session::Column::Id).all(db.conn()).await {
         Ok(sessions) => Ok(Json(ListSessionsOutput {
-            sessions: sessions.into_iter().map(|s| s.into()).collect(),
+            sessions: sessions
+                .into_iter()
+                .map(|s| Sparse::new(s.into(), fields.as_ref()))
+                .collect(),
         })),
         Err(e) => Err(Error::database_error(e.to_string())),
     }
@@ -369,8 +1470,58 @@ pub async fn update(
         .await
         .map_err(|e| Error::database_error(e.to_string()))?
         .ok_or_else(|| Error::not_found("Session not found".to_string()))?;
+    let existing_repo = existing_session.repo.clone().unwrap_or_default();
+    let existing_target = existing_session.target_branch.clone().unwrap_or_default();
 
-    let mut active_session: session::ActiveModel = existing_session.into();
+    // Status changes go through the state machine; users may only archive and unarchive
+    let actor = Actor::User(user.user_id.clone());
+    let from = existing_session.ui_status.clone();
+    let mut transition_cause = None;
+    let mut active_session = match &input.ui_status {
+        Some(ui_status) if *ui_status != existing_session.ui_status => {
+            let cause = if *ui_status == UiStatus::Archived {
+                TransitionCause::Archived
+            } else {
+                TransitionCause::Unarchived
+            };
+            transition_cause = Some(cause);
+            SessionStateMachine::transition(existing_session, ui_status.clone(), cause, &actor)
+                .map_err(|e| Error::bad_request(e.to_string()))?
+        }
+        _ => existing_session.into(),
+    };
+
+    for branch in [&input.branch, &input.target_branch].into_iter().flatten() {
+        session_preflight::validate_branch(branch).map_err(Error::bad_request)?;
+    }
+
+    // Moving the session to another repo or target branch is checked like creating it there
+    let repo = match &input.repo {
+        Some(repo) => Some(
+            session_preflight::validate_repo(repo)
+                .map_err(Error::bad_request)?
+                .canonical(),
+        ),
+        None => None,
+    };
+    let moved = repo.as_ref().is_some_and(|repo| *repo != existing_repo)
+        || input
+            .target_branch
+            .as_ref()
+            .is_some_and(|target| *target != existing_target);
+    let conflicting_session_id = if moved {
+        validate_new_session(
+            db.inner(),
+            &user,
+            repo.as_deref().unwrap_or(&existing_repo),
+            input.target_branch.as_deref().unwrap_or(&existing_target),
+            Some(uuid),
+        )
+        .await?
+        .1
+    } else {
+        None
+    };
 
     // Only update fields that are provided (Some)
     if input.sbx_config.is_some() {
@@ -382,8 +1533,8 @@ pub async fn update(
     if input.branch.is_some() {
         active_session.branch = Set(input.branch.clone());
     }
-    if input.repo.is_some() {
-        active_session.repo = Set(input.repo.clone());
+    if let Some(repo) = repo {
+        active_session.repo = Set(Some(repo));
     }
     if input.target_branch.is_some() {
         active_session.target_branch = Set(input.target_branch.clone());
@@ -391,20 +1542,38 @@ pub async fn update(
     if input.title.is_some() {
         active_session.title = Set(input.title.clone());
     }
-    if let Some(ui_status) = &input.ui_status {
-        active_session.ui_status = Set(ui_status.clone());
+    // A title or branch the user chose is kept when the first prompt arrives
+    if input.title.is_some() || input.branch.is_some() {
+        active_session.title_pending = Set(false);
+    }
+    if input.path_policy.is_some() {
+        active_session.path_policy =
+            Set(path_policy_json(input.path_policy.as_ref()).map_err(Error::bad_request)?);
+    }
+    if input.egress_policy.is_some() {
+        active_session.egress_policy =
+            Set(egress_policy_json(input.egress_policy.as_ref()).map_err(Error::bad_request)?);
+    }
+    if let Some(keep) = input.keep_sandbox_until_archive {
+        active_session.keep_sandbox_until_archive = Set(keep);
     }
 
     // Explicitly update the updated_at timestamp
     active_session.updated_at = Set(Utc::now().into());
 
-    match active_session.update(db.inner()).await {
-        Ok(_) => Ok(Json(UpdateSessionOutput {
-            success: true,
-            message: "Session updated successfully".to_string(),
-        })),
-        Err(e) => Err(Error::database_error(e.to_string())),
+    let updated = active_session
+        .update(db.inner())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+    if let Some(cause) = transition_cause {
+        SessionStateMachine::after_save(db.inner(), &from, &updated, cause, &actor).await;
     }
+
+    Ok(Json(UpdateSessionOutput {
+        success: true,
+        message: "Session updated successfully".to_string(),
+        conflicting_session_id,
+    }))
 }
 
 /// Delete a session by ID
@@ -437,13 +1606,170 @@ pub async fn delete(
     }
 }
 
+/// Add tags to a session
+///
+/// Tags are trimmed and deduplicated; existing tags are kept
+#[openapi]
+#[post("/sessions/<id>/tags", data = "<input>")]
+pub async fn add_tags(
+    user: AuthenticatedUser,
+    db: &State<DatabaseConnection>,
+    id: String,
+    input: Json<AddSessionTagsInput>,
+) -> OResult<SessionTagsOutput> {
+    let uuid =
+        Uuid::parse_str(&id).map_err(|_| Error::bad_request("Invalid UUID format".to_string()))?;
+
+    let new_tags = session_tags::normalize(&input.tags).map_err(Error::bad_request)?;
+
+    // Verify session exists and belongs to user
+    let existing_session = Session::find_by_id(uuid)
+        .filter(session::Column::UserId.eq(&user.user_id))
+        .one(db.inner())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?
+        .ok_or_else(|| Error::not_found("Session not found".to_string()))?;
+
+    let tags = session_tags::add(session_tags::from_json(&existing_session.tags), new_tags)
+        .map_err(Error::bad_request)?;
+
+    save_tags(db.inner(), uuid, tags).await
+}
+
+/// Remove a tag from a session
+#[openapi]
+#[delete("/sessions/<id>/tags/<tag>")]
+pub async fn remove_tag(
+    user: AuthenticatedUser,
+    db: &State<DatabaseConnection>,
+    id: String,
+    tag: String,
+) -> OResult<SessionTagsOutput> {
+    let uuid =
+        Uuid::parse_str(&id).map_err(|_| Error::bad_request("Invalid UUID format".to_string()))?;
+
+    // Verify session exists and belongs to user
+    let existing_session = Session::find_by_id(uuid)
+        .filter(session::Column::UserId.eq(&user.user_id))
+        .one(db.inner())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?
+        .ok_or_else(|| Error::not_found("Session not found".to_string()))?;
+
+    let tags = session_tags::from_json(&existing_session.tags)
+        .into_iter()
+        .filter(|t| t != tag.trim())
+        .collect();
+
+    save_tags(db.inner(), uuid, tags).await
+}
+
+async fn save_tags(
+    db: &DatabaseConnection,
+    id: Uuid,
+    tags: Vec<String>,
+) -> OResult<SessionTagsOutput> {
+    let active_session = session::ActiveModel {
+        id: Set(id),
+        tags: Set(serde_json::json!(tags)),
+        updated_at: Set(Utc::now().into()),
+        ..Default::default()
+    };
+
+    match active_session.update(db).await {
+        Ok(_) => Ok(Json(SessionTagsOutput {
+            success: true,
+            tags,
+        })),
+        Err(e) => Err(Error::database_error(e.to_string())),
+    }
+}
+
+/// Mark `session` as cancellation requested for the enforcer to carry out
+async fn request_cancellation(
+    db: &DatabaseConnection,
+    session: SessionModel,
+    mode: &CancellationMode,
+    user: &AuthenticatedUser,
+) -> Result<(), Error> {
+    let session_id = session.id;
+    let mut active_session: session::ActiveModel = session.into();
+    active_session.cancellation_status = Set(Some(CancellationStatus::Requested));
+    active_session.cancelled_at = Set(Some(Utc::now().into()));
+    active_session.cancelled_by = Set(Some(user.user_id.clone()));
+    active_session.cancellation_mode = Set(Some(mode.clone()));
+
+    active_session
+        .update(db)
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+    session_events::record(
+        db,
+        session_id,
+        SessionEventType::CancellationRequested,
+        &Actor::User(user.user_id.clone()),
+        Some(serde_json::json!({ "mode": mode })),
+    )
+    .await;
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct PullRequestDescriptionOutput {
+    pub pull_request_url: String,
+    /// Body written to the pull request
+    pub description: String,
+}
+
+/// Regenerate the description of a session's pull request
+///
+/// Summarizes the session's prompts, diff and tool usage into the pull request template and
+/// replaces the body of the pull request opened from the session branch.
+#[openapi]
+#[post("/sessions/<id>/pull-request/description")]
+pub async fn describe_pull_request(
+    user: AuthenticatedUser,
+    db: &State<DatabaseConnection>,
+    id: String,
+) -> OResult<PullRequestDescriptionOutput> {
+    let uuid =
+        Uuid::parse_str(&id).map_err(|_| Error::bad_request("Invalid UUID format".to_string()))?;
+
+    let session = Session::find_by_id(uuid)
+        .filter(session::Column::UserId.eq(&user.user_id))
+        .one(db.inner())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?
+        .ok_or_else(|| Error::not_found("Session not found".to_string()))?;
+
+    let described = pr_description::describe(db.inner(), &session)
+        .await
+        .map_err(|e| match e {
+            PrDescriptionError::NoRepo(_) => Error::bad_request(e.to_string()),
+            PrDescriptionError::NoPullRequest(_) => Error::not_found(e.to_string()),
+            PrDescriptionError::Github(e) => e.into(),
+            PrDescriptionError::Database(e) => Error::database_error(e.to_string()),
+        })?;
+
+    Ok(Json(PullRequestDescriptionOutput {
+        pull_request_url: described.pull_request_url,
+        description: described.description,
+    }))
+}
+
 /// Cancel a session by ID
+///
+/// `mode=hard` (the default) terminates a running CLI process straight away. `mode=soft` first
+/// asks the agent to commit its work in progress and summarize, and only terminates the process
+/// if it is still running after `SOFT_CANCEL_GRACE_SECS`. A hard cancel escalates an earlier soft
+/// one. Cancelling a fan-out tracking session cancels its unfinished child sessions.
 #[openapi]
-#[post("/sessions/<id>/cancel")]
+#[post("/sessions/<id>/cancel?<mode>")]
 pub async fn cancel(
     user: AuthenticatedUser,
     db: &State<DatabaseConnection>,
     id: String,
+    mode: CancellationMode,
 ) -> OResult<CancelSessionOutput> {
     let uuid =
         Uuid::parse_str(&id).map_err(|_| Error::bad_request("Invalid UUID format".to_string()))?;
@@ -464,19 +1790,409 @@ pub async fn cancel(
         }));
     }
 
-    // Update session to mark as cancellation requested
-    let mut active_session: session::ActiveModel = existing_session.into();
-    active_session.cancellation_status = Set(Some(CancellationStatus::Requested));
-    active_session.cancelled_at = Set(Some(Utc::now().into()));
-    active_session.cancelled_by = Set(Some(user.user_id.clone()));
+    let children = match existing_session.fan_out_limit {
+        Some(_) => fan_out::children(db.inner(), uuid)
+            .await
+            .map_err(|e| Error::database_error(e.to_string()))?
+            .into_iter()
+            .filter(fan_out::is_unfinished)
+            .collect(),
+        None => Vec::new(),
+    };
 
-    match active_session.update(db.inner()).await {
-        Ok(_) => Ok(Json(CancelSessionOutput {
+    request_cancellation(db.inner(), existing_session, &mode, &user).await?;
+    // Cancelling a fan-out cancels its children; the tracking session is marked cancelled
+    // once they are all done
+    if !children.is_empty() {
+        let count = children.len();
+        for child in children {
+            request_cancellation(db.inner(), child, &mode, &user).await?;
+        }
+        return Ok(Json(CancelSessionOutput {
             success: true,
-            message: "Session cancellation requested successfully".to_string(),
-        })),
-        Err(e) => Err(Error::database_error(e.to_string())),
+            message: format!(
+                "Cancellation requested for the fan-out and its {} unfinished sessions",
+                count
+            ),
+        }));
     }
+
+    Ok(Json(CancelSessionOutput {
+        success: true,
+        message: "Session cancellation requested successfully".to_string(),
+    }))
+}
+
+/// The user's session held for unpushed work, and what it was held for
+async fn held_session(
+    db: &DatabaseConnection,
+    user: &AuthenticatedUser,
+    id: &str,
+) -> Result<(SessionModel, UnpushedWork), Error> {
+    let uuid =
+        Uuid::parse_str(id).map_err(|_| Error::bad_request("Invalid UUID format".to_string()))?;
+
+    let session = Session::find_by_id(uuid)
+        .filter(session::Column::UserId.eq(&user.user_id))
+        .one(db)
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?
+        .ok_or_else(|| Error::not_found("Session not found".to_string()))?;
+
+    let work = match UnpushedWork::from_json(session.unpushed_work.as_ref()) {
+        Some(work) if session.ui_status == UiStatus::NeedsAttention => work,
+        _ => {
+            return Err(Error::conflict(
+                "Session is not held for unpushed work".to_string(),
+                serde_json::json!({ "uiStatus": session.ui_status }),
+            ))
+        }
+    };
+    Ok((session, work))
+}
+
+/// Push the work a session's sandbox is held for to the session branch
+///
+/// Uncommitted changes are committed first. Once nothing is left unpushed the session goes
+/// back to the status it had and its IP is returned; otherwise 409 with what is left.
+#[openapi]
+#[post("/sessions/<id>/push")]
+pub async fn push_unpushed_work(
+    user: AuthenticatedUser,
+    db: &State<DatabaseConnection>,
+    id: String,
+) -> OResult<UnpushedWorkOutput> {
+    let (session, work) = held_session(db.inner(), &user, &id).await?;
+
+    unpushed_work::push(&session).await?;
+    let remaining = unpushed_work::check(&session).await?;
+    let actor = Actor::User(user.user_id.clone());
+
+    if let Some(remaining) = remaining {
+        let message = remaining.describe();
+        let mut active_session: session::ActiveModel = session.into();
+        active_session.status_message = Set(Some(message.clone()));
+        active_session.unpushed_work = Set(Some(remaining.to_json()));
+        active_session.updated_at = Set(Utc::now().into());
+        active_session
+            .update(db.inner())
+            .await
+            .map_err(|e| Error::database_error(e.to_string()))?;
+        return Err(Error::conflict(message, remaining.to_json()));
+    }
+
+    let from = session.ui_status.clone();
+    let mut active_session = SessionStateMachine::transition(
+        session,
+        work.held_from,
+        TransitionCause::WorkPushed,
+        &actor,
+    )
+    .map_err(|e| Error::bad_request(e.to_string()))?;
+    active_session.status_message = Set(Some(format!("Pushed unpushed work to {}", work.branch)));
+    active_session.unpushed_work = Set(None);
+    active_session.updated_at = Set(Utc::now().into());
+    let updated = active_session
+        .update(db.inner())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+    SessionStateMachine::after_save(
+        db.inner(),
+        &from,
+        &updated,
+        TransitionCause::WorkPushed,
+        &actor,
+    )
+    .await;
+
+    Ok(Json(UnpushedWorkOutput {
+        success: true,
+        message: format!("Work pushed to {}", work.branch),
+        unpushed_work: None,
+    }))
+}
+
+/// Release a session's sandbox held for unpushed work, discarding the work
+///
+/// The session goes back to the status it had and its IP is returned without another check.
+#[openapi]
+#[post("/sessions/<id>/release")]
+pub async fn release_unpushed_work(
+    user: AuthenticatedUser,
+    db: &State<DatabaseConnection>,
+    id: String,
+) -> OResult<UnpushedWorkOutput> {
+    let (session, mut work) = held_session(db.inner(), &user, &id).await?;
+    let actor = Actor::User(user.user_id.clone());
+
+    let from = session.ui_status.clone();
+    let mut active_session = SessionStateMachine::transition(
+        session,
+        work.held_from.clone(),
+        TransitionCause::ForceReleased,
+        &actor,
+    )
+    .map_err(|e| Error::bad_request(e.to_string()))?;
+    work.released_at = Some(Utc::now().to_rfc3339());
+    work.released_by = Some(actor.to_string());
+    active_session.status_message = Set(Some(
+        "Sandbox released; unpushed work will be discarded".to_string(),
+    ));
+    active_session.unpushed_work = Set(Some(work.to_json()));
+    active_session.updated_at = Set(Utc::now().into());
+    let updated = active_session
+        .update(db.inner())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+    SessionStateMachine::after_save(
+        db.inner(),
+        &from,
+        &updated,
+        TransitionCause::ForceReleased,
+        &actor,
+    )
+    .await;
+
+    Ok(Json(UnpushedWorkOutput {
+        success: true,
+        message: "Sandbox released".to_string(),
+        unpushed_work: Some(work),
+    }))
+}
+
+/// Get a session's position in the sandbox queue and its estimated wait
+#[openapi]
+#[get("/sessions/<id>/queue")]
+pub async fn queue(
+    user: AuthenticatedUser,
+    db: &State<DatabaseConnection>,
+    id: String,
+) -> OResult<SessionQueueOutput> {
+    let uuid =
+        Uuid::parse_str(&id).map_err(|_| Error::bad_request("Invalid UUID format".to_string()))?;
+
+    let existing_session = Session::find_by_id(uuid)
+        .filter(organizations::visible_to(&user))
+        .one(db.inner())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?
+        .ok_or_else(|| Error::not_found("Session not found".to_string()))?;
+
+    let estimate = sandbox_queue::estimate_for_session(db.inner(), &existing_session)
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+
+    Ok(Json(SessionQueueOutput {
+        ui_status: existing_session.ui_status,
+        status_message: existing_session.status_message,
+        position: estimate.position,
+        in_flight: estimate.in_flight,
+        average_run_seconds: estimate.average_run_seconds,
+        estimated_wait_seconds: estimate.estimated_wait_seconds,
+        estimated_start_at: estimate
+            .estimated_start_seconds
+            .map(|secs| sandbox_queue::start_at(Utc::now(), secs).to_string()),
+    }))
+}
+
+/// Estimate token usage and cost of the session's pending prompts before they run
+#[openapi]
+#[post("/sessions/<id>/estimate")]
+pub async fn estimate(
+    user: AuthenticatedUser,
+    db: &State<DatabaseConnection>,
+    id: String,
+) -> OResult<SessionEstimateOutput> {
+    let uuid =
+        Uuid::parse_str(&id).map_err(|_| Error::bad_request("Invalid UUID format".to_string()))?;
+
+    let existing_session = Session::find_by_id(uuid)
+        .filter(session::Column::UserId.eq(&user.user_id))
+        .one(db.inner())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?
+        .ok_or_else(|| Error::not_found("Session not found".to_string()))?;
+
+    let estimate = cost_estimate::estimate_session(db.inner(), &existing_session)
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?
+        .ok_or_else(|| Error::not_found("Session has no pending prompts".to_string()))?;
+    let remaining_budget_usd = cost_estimate::remaining_budget_usd(db.inner(), &existing_session)
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+
+    Ok(Json(SessionEstimateOutput {
+        model: estimate.model,
+        pending_prompt_ids: estimate
+            .pending_prompt_ids
+            .iter()
+            .map(|p| p.to_string())
+            .collect(),
+        estimated_input_tokens: estimate.usage.input_tokens,
+        estimated_output_tokens: estimate.usage.output_tokens,
+        estimated_cost_usd: estimate.cost_usd,
+        sampled_runs: estimate.sampled_runs,
+        within_budget: remaining_budget_usd.is_none_or(|r| estimate.cost_usd <= r),
+        remaining_budget_usd,
+    }))
+}
+
+/// List files collected from the sandbox outside the repo
+#[openapi]
+#[get("/sessions/<id>/artifacts")]
+pub async fn list_artifacts(
+    user: AuthenticatedUser,
+    db: &State<DatabaseConnection>,
+    id: String,
+) -> OResult<ListSessionArtifactsOutput> {
+    let uuid =
+        Uuid::parse_str(&id).map_err(|_| Error::bad_request("Invalid UUID format".to_string()))?;
+
+    Session::find_by_id(uuid)
+        .filter(organizations::visible_to(&user))
+        .one(db.inner())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?
+        .ok_or_else(|| Error::not_found("Session not found".to_string()))?;
+
+    let artifacts = session_artifact::Entity::find()
+        .filter(session_artifact::Column::SessionId.eq(uuid))
+        .order_by_asc(session_artifact::Column::CreatedAt)
+        .order_by_asc(session_artifact::Column::Path)
+        .all(db.inner())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+
+    Ok(Json(ListSessionArtifactsOutput {
+        artifacts: artifacts
+            .into_iter()
+            .map(SessionArtifactDto::from)
+            .collect(),
+    }))
+}
+
+/// List a session's lifecycle events, oldest first
+///
+/// Returns up to `limit` (default 50, at most 200) events. Pass the returned `nextCursor` as
+/// `after` to get the next page.
+#[openapi]
+#[get("/sessions/<id>/events?<limit>&<after>")]
+pub async fn list_events(
+    user: AuthenticatedUser,
+    db: &State<ReadDb>,
+    id: String,
+    limit: Option<u64>,
+    after: Option<String>,
+) -> OResult<ListSessionEventsOutput> {
+    let uuid =
+        Uuid::parse_str(&id).map_err(|_| Error::bad_request("Invalid UUID format".to_string()))?;
+    let limit = limit
+        .unwrap_or(DEFAULT_EVENTS_LIMIT)
+        .clamp(1, MAX_EVENTS_LIMIT);
+
+    Session::find_by_id(uuid)
+        .filter(organizations::visible_to(&user))
+        .one(db.conn())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?
+        .ok_or_else(|| Error::not_found("Session not found".to_string()))?;
+
+    let mut query = session_event::Entity::find().filter(session_event::Column::SessionId.eq(uuid));
+    if let Some(after) = &after {
+        let cursor = Uuid::parse_str(after)
+            .map_err(|_| Error::bad_request("Invalid after cursor".to_string()))?;
+        let cursor = session_event::Entity::find_by_id(cursor)
+            .filter(session_event::Column::SessionId.eq(uuid))
+            .one(db.conn())
+            .await
+            .map_err(|e| Error::database_error(e.to_string()))?
+            .ok_or_else(|| Error::bad_request("Invalid after cursor".to_string()))?;
+        query = query.filter(
+            Condition::any()
+                .add(session_event::Column::CreatedAt.gt(cursor.created_at))
+                .add(
+                    Condition::all()
+                        .add(session_event::Column::CreatedAt.eq(cursor.created_at))
+                        .add(session_event::Column::Id.gt(cursor.id)),
+                ),
+        );
+    }
+
+    // One extra row tells whether there is a next page
+    let mut events = query
+        .order_by_asc(session_event::Column::CreatedAt)
+        .order_by_asc(session_event::Column::Id)
+        .limit(limit + 1)
+        .all(db.conn())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+    let next_cursor = if events.len() as u64 > limit {
+        events.truncate(limit as usize);
+        events.last().map(|event| event.id.to_string())
+    } else {
+        None
+    };
+
+    Ok(Json(ListSessionEventsOutput {
+        events: events.into_iter().map(SessionEventDto::from).collect(),
+        next_cursor,
+    }))
+}
+
+/// Read a session's whole conversation: its prompts with their messages
+///
+/// Served from the conversation read model in one query, instead of listing each prompt's
+/// messages. Offloaded payloads are fetched from object storage.
+#[openapi]
+#[get("/sessions/<id>/conversation")]
+pub async fn read_conversation(
+    user: AuthenticatedUser,
+    db: &State<ReadDb>,
+    id: String,
+) -> OResult<ConversationOutput> {
+    let uuid =
+        Uuid::parse_str(&id).map_err(|_| Error::bad_request("Invalid UUID format".to_string()))?;
+
+    Session::find_by_id(uuid)
+        .filter(organizations::visible_to(&user))
+        .one(db.conn())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?
+        .ok_or_else(|| Error::not_found("Session not found".to_string()))?;
+
+    let prompts = conversation_view::load(db.conn(), uuid)
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+
+    let mut dtos = Vec::with_capacity(prompts.len());
+    for prompt in prompts {
+        let mut messages = Vec::with_capacity(prompt.messages.len());
+        for message in prompt.messages {
+            let data = message_blobs::resolve_stored(
+                message.id,
+                &message.data,
+                message.blob_key.as_deref(),
+            )
+            .await
+            .map_err(Error::internal_server_error)?;
+            messages.push(ConversationMessageDto {
+                id: message.id.to_string(),
+                data,
+                created_at: message.created_at,
+            });
+        }
+        dtos.push(ConversationPromptDto {
+            prompt_id: prompt.prompt_id.to_string(),
+            data: prompt.data,
+            created_at: prompt.created_at,
+            messages,
+        });
+    }
+
+    Ok(Json(ConversationOutput {
+        session_id: uuid.to_string(),
+        prompts: dtos,
+    }))
 }
 
 #[cfg(test)]
@@ -490,6 +2206,8 @@ mod tests {
             message: "Test message".to_string(),
             session_id: "550e8400-e29b-41d4-a716-446655440000".to_string(),
             prompt_id: "660e8400-e29b-41d4-a716-446655440001".to_string(),
+            conflicting_session_id: None,
+            estimated_start_at: None,
         };
 
         let json = serde_json::to_string(&output).expect("Failed to serialize");
@@ -516,4 +2234,22 @@ mod tests {
             "Found snake_case prompt_id, expected camelCase"
         );
     }
+
+    #[test]
+    fn test_region_value() {
+        assert_eq!(region_value(None, &[]), Ok(None));
+        assert_eq!(region_value(Some("  "), &[]), Ok(None));
+        assert_eq!(
+            region_value(Some(" eu-west "), &[]),
+            Ok(Some("eu-west".to_string()))
+        );
+        assert!(region_value(Some("EU West"), &[]).is_err());
+
+        let allowed = vec!["us-east".to_string(), "eu-west".to_string()];
+        assert_eq!(
+            region_value(Some("eu-west"), &allowed),
+            Ok(Some("eu-west".to_string()))
+        );
+        assert!(region_value(Some("ap-south"), &allowed).is_err());
+    }
 }
@@ -0,0 +1,110 @@
+use chrono::Utc;
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::State;
+use rocket_okapi::okapi::schemars::JsonSchema;
+use rocket_okapi::openapi;
+use sea_orm::{ActiveModelTrait, DatabaseConnection, Set};
+
+use crate::auth::AuthenticatedUser;
+use crate::config;
+use crate::entities::session::ClaudeModel;
+use crate::entities::user_settings;
+use crate::error::{Error, OResult};
+use crate::services::user_settings::{self as settings_service, AgentTool};
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct UserSettingsDto {
+    /// Model sessions run on when created without one, null for the server default
+    pub default_model: Option<ClaudeModel>,
+    /// Optional tools the user's runs may use; the MCP tools of the sandbox are always allowed
+    pub allowed_tools: Vec<AgentTool>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateUserSettingsInput {
+    /// Null for the server default
+    #[serde(default)]
+    pub default_model: Option<ClaudeModel>,
+    /// Null allows every optional tool
+    #[serde(default)]
+    pub allowed_tools: Option<Vec<AgentTool>>,
+}
+
+impl From<Option<user_settings::Model>> for UserSettingsDto {
+    fn from(model: Option<user_settings::Model>) -> Self {
+        UserSettingsDto {
+            allowed_tools: settings_service::allowed_tools(model.as_ref()),
+            default_model: model.and_then(|m| m.default_model),
+        }
+    }
+}
+
+/// Read the user's settings
+///
+/// Returns the model new sessions default to and the tools runs may use, with server defaults
+/// filled in for anything the user has not set
+#[openapi]
+#[get("/settings")]
+pub async fn read(
+    user: AuthenticatedUser,
+    db: &State<DatabaseConnection>,
+) -> OResult<UserSettingsDto> {
+    let settings = settings_service::find(db.inner(), &user.user_id)
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+
+    Ok(Json(settings.into()))
+}
+
+/// Replace the user's settings
+///
+/// The default model must be one the user may request (see `GET /models`). Runs that have
+/// already started keep the tools they were started with.
+#[openapi]
+#[put("/settings", data = "<input>")]
+pub async fn update(
+    user: AuthenticatedUser,
+    db: &State<DatabaseConnection>,
+    input: Json<UpdateUserSettingsInput>,
+) -> OResult<UserSettingsDto> {
+    if let Some(model) = input.default_model {
+        config::get()
+            .models
+            .check(model, &user.roles, user.is_admin())
+            .map_err(Error::bad_request)?;
+    }
+    let allowed_tools = input.allowed_tools.as_ref().map(|tools| {
+        let tools: Vec<AgentTool> = AgentTool::ALL
+            .into_iter()
+            .filter(|tool| tools.contains(tool))
+            .collect();
+        serde_json::json!(tools)
+    });
+
+    let existing = settings_service::find(db.inner(), &user.user_id)
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+    let now = Utc::now();
+    let mut settings = match &existing {
+        Some(existing) => user_settings::ActiveModel::from(existing.clone()),
+        None => user_settings::ActiveModel {
+            user_id: Set(user.user_id.clone()),
+            created_at: Set(now.into()),
+            ..Default::default()
+        },
+    };
+    settings.default_model = Set(input.default_model);
+    settings.allowed_tools = Set(allowed_tools);
+    settings.updated_at = Set(now.into());
+
+    let settings = match existing {
+        Some(_) => settings.update(db.inner()).await,
+        None => settings.insert(db.inner()).await,
+    }
+    .map_err(|e| Error::database_error(e.to_string()))?;
+
+    Ok(Json(Some(settings).into()))
+}
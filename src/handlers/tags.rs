@@ -0,0 +1,43 @@
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::State;
+use rocket_okapi::okapi::schemars::JsonSchema;
+use rocket_okapi::openapi;
+
+use crate::auth::AuthenticatedUser;
+use crate::db::ReadDb;
+use crate::error::{Error, OResult};
+use crate::services::session_tags;
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct TagCountDto {
+    pub tag: String,
+    /// Number of the user's sessions carrying this tag
+    pub count: i64,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct ListTagsOutput {
+    pub tags: Vec<TagCountDto>,
+}
+
+/// List the user's tags
+///
+/// Returns every tag used on the user's sessions with usage counts, most used first
+#[openapi]
+#[get("/tags")]
+pub async fn list(user: AuthenticatedUser, db: &State<ReadDb>) -> OResult<ListTagsOutput> {
+    let counts = session_tags::counts_for_user(db.conn(), &user.user_id)
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+
+    Ok(Json(ListTagsOutput {
+        tags: counts
+            .into_iter()
+            .map(|c| TagCountDto {
+                tag: c.tag,
+                count: c.count,
+            })
+            .collect(),
+    }))
+}
@@ -0,0 +1,191 @@
+use rocket::data::Capped;
+use rocket::form::Form;
+use rocket::fs::TempFile;
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::State;
+use rocket_okapi::okapi::schemars::gen::SchemaGenerator;
+use rocket_okapi::okapi::schemars::schema::{InstanceType, Schema, SchemaObject};
+use rocket_okapi::okapi::schemars::JsonSchema;
+use rocket_okapi::openapi;
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder};
+use uuid::Uuid;
+
+use crate::auth::AuthenticatedUser;
+use crate::db::ReadDb;
+use crate::entities::session::{self, Entity as Session};
+use crate::entities::session_upload;
+use crate::error::{Error, OResult};
+use crate::services::organizations;
+use crate::services::session_uploads::{self, UploadError};
+
+/// Multipart body of an upload
+#[derive(FromForm)]
+pub struct UploadForm<'r> {
+    /// File contents; its file name is used unless `name` is given
+    pub file: Capped<TempFile<'r>>,
+    /// Name to store the file under
+    pub name: Option<String>,
+}
+
+impl JsonSchema for UploadForm<'_> {
+    fn schema_name() -> String {
+        "UploadForm".to_string()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        let mut file = SchemaObject {
+            instance_type: Some(InstanceType::String.into()),
+            format: Some("binary".to_string()),
+            ..Default::default()
+        };
+        file.metadata().description =
+            Some("File contents; its file name is used unless `name` is given".to_string());
+        let mut schema = SchemaObject {
+            instance_type: Some(InstanceType::Object.into()),
+            ..Default::default()
+        };
+        let object = schema.object();
+        object.properties.insert("file".to_string(), file.into());
+        object
+            .properties
+            .insert("name".to_string(), gen.subschema_for::<Option<String>>());
+        object.required.insert("file".to_string());
+        schema.into()
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct SessionUploadDto {
+    pub id: String,
+    pub file_name: String,
+    /// Where the file was written in the sandbox
+    pub path: String,
+    pub content_type: Option<String>,
+    pub size_bytes: i64,
+    /// Hex SHA-256 of the contents
+    pub sha256: String,
+    pub user_id: String,
+    pub created_at: String,
+}
+
+impl From<session_upload::Model> for SessionUploadDto {
+    fn from(model: session_upload::Model) -> Self {
+        SessionUploadDto {
+            id: model.id.to_string(),
+            file_name: model.file_name,
+            path: model.path,
+            content_type: model.content_type,
+            size_bytes: model.size_bytes,
+            sha256: model.sha256,
+            user_id: model.user_id,
+            created_at: model.created_at.to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct ListSessionUploadsOutput {
+    pub uploads: Vec<SessionUploadDto>,
+}
+
+/// Upload a file into the session's sandbox
+///
+/// Accepts `multipart/form-data` with a `file` part of at most `UPLOAD_MAX_BYTES`. The file is
+/// written under the session's upload directory, outside the repo, and replaces an earlier
+/// upload of the same name. Only accepted while the session holds a sandbox.
+#[openapi]
+#[post("/sessions/<id>/uploads", data = "<form>")]
+pub async fn create(
+    user: AuthenticatedUser,
+    db: &State<DatabaseConnection>,
+    id: String,
+    form: Form<UploadForm<'_>>,
+) -> OResult<SessionUploadDto> {
+    let uuid =
+        Uuid::parse_str(&id).map_err(|_| Error::bad_request("Invalid UUID format".to_string()))?;
+
+    let session = Session::find_by_id(uuid)
+        .filter(session::Column::UserId.eq(&user.user_id))
+        .one(db.inner())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?
+        .ok_or_else(|| Error::not_found("Session not found".to_string()))?;
+
+    let form = form.into_inner();
+    if !form.file.is_complete() {
+        return Err(Error::payload_too_large(format!(
+            "Upload is larger than the {} byte limit",
+            crate::config::get().uploads.max_bytes
+        )));
+    }
+    let file = form.file.value;
+    let file_name = form
+        .name
+        .or_else(|| {
+            file.raw_name()
+                .map(|name| name.dangerous_unsafe_unsanitized_raw().to_string())
+        })
+        .ok_or_else(|| Error::bad_request("The file part has no file name".to_string()))?;
+    let content_type = file.content_type().map(|ct| ct.to_string());
+    let reader = file
+        .open()
+        .await
+        .map_err(|e| Error::internal_server_error(format!("Failed to read upload: {}", e)))?;
+
+    let record = session_uploads::upload(
+        db.inner(),
+        &session,
+        &user.user_id,
+        &file_name,
+        content_type,
+        reader,
+    )
+    .await
+    .map_err(|e| match e {
+        UploadError::NoSandbox(_) => Error::conflict(
+            e.to_string(),
+            serde_json::json!({ "ui_status": session.ui_status }),
+        ),
+        UploadError::InvalidFileName(_) | UploadError::Rejected(_) => {
+            Error::bad_request(e.to_string())
+        }
+        UploadError::Sandbox(_) | UploadError::Read(_) => {
+            Error::internal_server_error(e.to_string())
+        }
+        UploadError::Database(e) => Error::database_error(e.to_string()),
+    })?;
+
+    Ok(Json(record.into()))
+}
+
+/// List files uploaded into the session's sandbox
+#[openapi]
+#[get("/sessions/<id>/uploads")]
+pub async fn list(
+    user: AuthenticatedUser,
+    db: &State<ReadDb>,
+    id: String,
+) -> OResult<ListSessionUploadsOutput> {
+    let uuid =
+        Uuid::parse_str(&id).map_err(|_| Error::bad_request("Invalid UUID format".to_string()))?;
+
+    Session::find_by_id(uuid)
+        .filter(organizations::visible_to(&user))
+        .one(db.conn())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?
+        .ok_or_else(|| Error::not_found("Session not found".to_string()))?;
+
+    let uploads = session_upload::Entity::find()
+        .filter(session_upload::Column::SessionId.eq(uuid))
+        .order_by_asc(session_upload::Column::CreatedAt)
+        .order_by_asc(session_upload::Column::FileName)
+        .all(db.conn())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?;
+
+    Ok(Json(ListSessionUploadsOutput {
+        uploads: uploads.into_iter().map(SessionUploadDto::from).collect(),
+    }))
+}
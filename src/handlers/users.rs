@@ -0,0 +1,74 @@
+use rocket::serde::json::Json;
+use rocket::serde::{Deserialize, Serialize};
+use rocket::State;
+use rocket_okapi::okapi::schemars::JsonSchema;
+use rocket_okapi::openapi;
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QueryOrder};
+
+use crate::auth::AuthenticatedUser;
+use crate::entities::user_erasure::{self, Entity as UserErasure, ErasureStatus};
+use crate::error::{Error, OResult};
+use crate::services::session_state_machine::Actor;
+use crate::services::user_erasure::{self as erasure, ErasureReport};
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct UserErasureDto {
+    pub id: String,
+    pub status: ErasureStatus,
+    pub report: ErasureReport,
+    pub created_at: String,
+    /// When the last session was deleted, null while sessions are still being released
+    pub completed_at: Option<String>,
+}
+
+impl From<user_erasure::Model> for UserErasureDto {
+    fn from(model: user_erasure::Model) -> Self {
+        UserErasureDto {
+            id: model.id.to_string(),
+            status: model.status,
+            report: serde_json::from_value(model.report).unwrap_or_default(),
+            created_at: model.created_at.to_string(),
+            completed_at: model.completed_at.map(|t| t.to_string()),
+        }
+    }
+}
+
+/// Erase all of the user's data
+///
+/// Cancels running and queued sessions, returns their sandboxes and permanently deletes every
+/// session with its prompts, messages, artifacts and events, along with the user's settings,
+/// notifications, annotations and uploads. DLQ entries about the sessions lose their stored
+/// payload. Sessions still holding a sandbox or a running process are deleted as soon as they
+/// are released; the report then stays `Pending` until they are (see
+/// `GET /users/me/data/erasure`). This cannot be undone.
+#[openapi]
+#[delete("/users/me/data")]
+pub async fn erase_data(
+    user: AuthenticatedUser,
+    db: &State<DatabaseConnection>,
+) -> OResult<UserErasureDto> {
+    let actor = Actor::User(user.user_id.clone());
+    let erasure = erasure::erase_user(db.inner(), &user.user_id, &actor)
+        .await
+        .map_err(|e| Error::database_error(format!("Failed to erase user data: {}", e)))?;
+
+    Ok(Json(erasure.into()))
+}
+
+/// Read the user's latest data erasure
+#[openapi]
+#[get("/users/me/data/erasure")]
+pub async fn read_erasure(
+    user: AuthenticatedUser,
+    db: &State<DatabaseConnection>,
+) -> OResult<UserErasureDto> {
+    let erasure = UserErasure::find()
+        .filter(user_erasure::Column::UserId.eq(&user.user_id))
+        .order_by_desc(user_erasure::Column::CreatedAt)
+        .one(db.inner())
+        .await
+        .map_err(|e| Error::database_error(e.to_string()))?
+        .ok_or_else(|| Error::not_found("No data erasure was requested".to_string()))?;
+
+    Ok(Json(erasure.into()))
+}
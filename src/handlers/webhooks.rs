@@ -1,9 +1,20 @@
+use rocket::request::{FromRequest, Outcome, Request};
 use rocket::serde::json::Json;
 use rocket::serde::{Deserialize, Serialize};
+use rocket::State;
+use rocket_okapi::gen::OpenApiGenerator;
 use rocket_okapi::okapi::schemars::JsonSchema;
 use rocket_okapi::openapi;
+use rocket_okapi::request::{OpenApiFromRequest, RequestHeaderInput};
+use sea_orm::DatabaseConnection;
 
+use crate::config;
+use crate::entities::outbox_event::OutboxEventKind;
 use crate::error::{Error, OResult};
+use crate::handlers::admin::DeprovisionUserOutput;
+use crate::services::deprovision;
+use crate::services::outbox_events;
+use crate::services::session_state_machine::Actor;
 
 #[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
 pub struct ReturnItemInput {
@@ -16,104 +27,171 @@ pub struct ReturnItemOutput {
     pub message: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct RailwayGraphQLRequest {
-    query: String,
-    variables: RailwayVariables,
-    #[serde(rename = "operationName")]
-    operation_name: String,
+/// Webhook endpoint for IP allocator to trigger Railway redeployment
+/// This endpoint receives item return notifications from the IP allocator
+/// and records a Railway redeploy in the outbox; the outbox event worker performs it with
+/// retries and moves it to the dead letter queue if it keeps failing
+#[openapi]
+#[post("/webhook/return", data = "<input>")]
+pub async fn return_item(
+    db: &State<DatabaseConnection>,
+    input: Json<ReturnItemInput>,
+) -> OResult<ReturnItemOutput> {
+    tracing::info!("Received return item webhook: {:?}", input.item);
+
+    let event = outbox_events::record(
+        db.inner(),
+        OutboxEventKind::RailwayRedeploy,
+        serde_json::json!({ "item": input.item }),
+    )
+    .await
+    .map_err(|e| Error::database_error(format!("Failed to record redeploy: {}", e)))?;
+
+    tracing::info!("Queued Railway redeployment as outbox event {}", event.id);
+
+    Ok(Json(ReturnItemOutput {
+        success: true,
+        message: "Railway redeployment queued".to_string(),
+    }))
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct RailwayVariables {
-    id: String,
+/// Value of the `X-Webhook-Secret` header, if sent
+pub struct WebhookSecret(Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for WebhookSecret {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(WebhookSecret(
+            request
+                .headers()
+                .get_one("X-Webhook-Secret")
+                .map(str::to_string),
+        ))
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct RailwayGraphQLResponse {
-    data: Option<serde_json::Value>,
-    errors: Option<Vec<serde_json::Value>>,
+impl<'a> OpenApiFromRequest<'a> for WebhookSecret {
+    fn from_request_input(
+        _gen: &mut OpenApiGenerator,
+        _name: String,
+        _required: bool,
+    ) -> rocket_okapi::Result<RequestHeaderInput> {
+        Ok(RequestHeaderInput::None)
+    }
 }
 
-/// Webhook endpoint for IP allocator to trigger Railway redeployment
-/// This endpoint receives item return notifications from the IP allocator
-/// and triggers a Railway deployment redeploy to refresh the deployment state
-#[openapi]
-#[post("/webhook/return", data = "<input>")]
-pub async fn return_item(input: Json<ReturnItemInput>) -> OResult<ReturnItemOutput> {
-    tracing::info!("Received return item webhook: {:?}", input.item);
+/// Compare secrets without exiting early on the first differing byte
+pub fn secrets_match(expected: &str, given: &str) -> bool {
+    expected.len() == given.len()
+        && expected
+            .bytes()
+            .zip(given.bytes())
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
 
-    // Get Railway API configuration from environment
-    let railway_api_key = std::env::var("RAILWAY_API_KEY")
-        .map_err(|_| Error::internal_server_error("RAILWAY_API_KEY not configured".to_string()))?;
-
-    let deployment_id = std::env::var("RAILWAY_DEPLOYMENT_ID").map_err(|_| {
-        Error::internal_server_error("RAILWAY_DEPLOYMENT_ID not configured".to_string())
-    })?;
-
-    // Prepare the GraphQL mutation
-    let graphql_request = RailwayGraphQLRequest {
-        query: "mutation deploymentRedeploy($id: String!) {\n  deploymentRedeploy(id: $id) {\n    id\n  }\n}".to_string(),
-        variables: RailwayVariables {
-            id: deployment_id.clone(),
-        },
-        operation_name: "deploymentRedeploy".to_string(),
-    };
+/// A Keycloak admin event, as forwarded by an event listener webhook
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct KeycloakAdminEvent {
+    pub operation_type: String,
+    pub resource_type: String,
+    /// e.g. `users/<user_id>`
+    pub resource_path: String,
+}
 
-    tracing::info!(
-        "Triggering Railway redeployment for deployment: {}",
-        deployment_id
-    );
-
-    // Make blocking HTTP request to Railway GraphQL API
-    let client = reqwest::Client::new();
-    let response = client
-        .post("https://backboard.railway.app/graphql/v2")
-        .header("Authorization", format!("Bearer {}", railway_api_key))
-        .header("Content-Type", "application/json")
-        .json(&graphql_request)
-        .send()
-        .await
-        .map_err(|e| {
-            Error::internal_server_error(format!("Failed to send Railway request: {}", e))
-        })?;
-
-    let status = response.status();
-    let response_text = response.text().await.map_err(|e| {
-        Error::internal_server_error(format!("Failed to read Railway response: {}", e))
-    })?;
-
-    tracing::info!(
-        "Railway API response status: {}, body: {}",
-        status,
-        response_text
-    );
-
-    if !status.is_success() {
-        return Err(Error::internal_server_error(format!(
-            "Railway API request failed with status {}: {}",
-            status, response_text
-        )));
+impl KeycloakAdminEvent {
+    /// The user removed by this event, if it is a user deletion
+    fn deleted_user_id(&self) -> Option<&str> {
+        if self.operation_type != "DELETE" || self.resource_type != "USER" {
+            return None;
+        }
+        self.resource_path
+            .strip_prefix("users/")
+            .filter(|id| !id.is_empty() && !id.contains('/'))
     }
+}
 
-    // Parse the response to check for GraphQL errors
-    let graphql_response: RailwayGraphQLResponse =
-        serde_json::from_str(&response_text).map_err(|e| {
-            Error::internal_server_error(format!("Failed to parse Railway response: {}", e))
-        })?;
-
-    if let Some(errors) = graphql_response.errors {
-        let error_messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
-        return Err(Error::internal_server_error(format!(
-            "Railway GraphQL errors: {}",
-            error_messages.join(", ")
-        )));
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct KeycloakEventOutput {
+    /// Set when the event deleted a user and the user was deprovisioned
+    pub deprovisioned: Option<DeprovisionUserOutput>,
+}
+
+/// Keycloak admin event listener
+///
+/// Deprovisions users deleted in Keycloak. Requires `KEYCLOAK_WEBHOOK_SECRET` to be set and
+/// sent in the `X-Webhook-Secret` header; events other than user deletions are ignored.
+#[openapi]
+#[post("/webhook/keycloak", data = "<event>")]
+pub async fn keycloak_event(
+    db: &State<DatabaseConnection>,
+    secret: WebhookSecret,
+    event: Json<KeycloakAdminEvent>,
+) -> OResult<KeycloakEventOutput> {
+    let expected = config::get()
+        .keycloak_webhook_secret
+        .as_deref()
+        .ok_or_else(|| Error::not_found("Keycloak webhook is not configured".to_string()))?;
+    if !secret
+        .0
+        .is_some_and(|given| secrets_match(expected, &given))
+    {
+        return Err(Error::forbidden("Invalid webhook secret".to_string()));
     }
 
-    tracing::info!("Railway redeployment triggered successfully");
+    let Some(user_id) = event.deleted_user_id() else {
+        return Ok(Json(KeycloakEventOutput {
+            deprovisioned: None,
+        }));
+    };
 
-    Ok(Json(ReturnItemOutput {
-        success: true,
-        message: "Railway redeployment triggered successfully".to_string(),
+    tracing::info!("Keycloak deleted user {}, deprovisioning", user_id);
+    let summary =
+        deprovision::deprovision_user(db.inner(), user_id, &Actor::System("keycloak_webhook"))
+            .await
+            .map_err(|e| Error::database_error(format!("Failed to deprovision user: {}", e)))?;
+
+    Ok(Json(KeycloakEventOutput {
+        deprovisioned: Some(DeprovisionUserOutput::new(user_id.to_string(), summary)),
     }))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keycloak_user_deletion() {
+        let event = KeycloakAdminEvent {
+            operation_type: "DELETE".to_string(),
+            resource_type: "USER".to_string(),
+            resource_path: "users/0b8c1c5e-1c8e-4f7a-9b0e-5d1f0c2a7e11".to_string(),
+        };
+        assert_eq!(
+            event.deleted_user_id(),
+            Some("0b8c1c5e-1c8e-4f7a-9b0e-5d1f0c2a7e11")
+        );
+
+        let update = KeycloakAdminEvent {
+            operation_type: "UPDATE".to_string(),
+            ..event.clone()
+        };
+        assert_eq!(update.deleted_user_id(), None);
+
+        let role_mapping = KeycloakAdminEvent {
+            resource_path: "users/abc/role-mappings/realm".to_string(),
+            ..event
+        };
+        assert_eq!(role_mapping.deleted_user_id(), None);
+    }
+
+    #[test]
+    fn test_secrets_match() {
+        assert!(secrets_match("s3cret", "s3cret"));
+        assert!(!secrets_match("s3cret", "s3cres"));
+        assert!(!secrets_match("s3cret", "s3cret2"));
+    }
+}
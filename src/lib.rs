@@ -4,9 +4,14 @@
 extern crate rocket;
 
 pub mod auth;
+pub mod backoff;
 pub mod bg_tasks;
+pub mod config;
 pub mod db;
 pub mod entities;
 pub mod error;
+#[cfg(feature = "grpc")]
+pub mod grpc;
 pub mod handlers;
+pub mod metrics;
 pub mod services;
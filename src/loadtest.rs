@@ -0,0 +1,571 @@
+//! `prompt-backend loadtest`: drive synthetic sessions through the prompt pipeline and report
+//! the numbers capacity planning needs.
+//!
+//! The real prompt poller, outbox publisher and IP return poller run in this process against
+//! the database. The sandbox, the IP allocator and the GitHub API are served by a mock on
+//! localhost, and `claude` is a shell script on `PATH` that streams `--messages` lines at
+//! `--message-interval-ms`. The report covers enqueue-to-first-message latency, run latency,
+//! message write throughput and memory per concurrent CLI.
+//!
+//! Sessions are owned by the `loadtest` user and deleted afterwards unless `--keep` is given.
+//! The run needs a database of its own: it refuses to start when other sessions are queued or
+//! when `APP_ENV` is production.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, FixedOffset};
+use clap::Args;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, PaginatorTrait, QueryFilter,
+    QuerySelect, Set,
+};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::auth::AuthenticatedUser;
+use crate::bg_tasks;
+use crate::config;
+use crate::entities::message::{self, Entity as Message};
+use crate::entities::prompt::{self, Entity as Prompt, PromptPriority};
+use crate::entities::session::{self, Entity as Session, UiStatus};
+use crate::handlers::sessions::{new_prompt, new_session};
+use crate::metrics;
+use crate::services::conversation::Conversation;
+use crate::services::process_supervisor;
+
+/// Owner of every session the load test creates
+const LOADTEST_USER: &str = "loadtest";
+
+/// How often progress is checked while the sessions run
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How often running CLIs are counted; runs can be shorter than `POLL_INTERVAL`
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Most CLIs seen running at once
+static PEAK_CLIS: AtomicI64 = AtomicI64::new(0);
+
+/// Statuses of a session whose run has not finished
+const UNFINISHED: [UiStatus; 3] = [
+    UiStatus::Pending,
+    UiStatus::WaitingForSandbox,
+    UiStatus::InProgress,
+];
+
+#[derive(Args, Debug, Clone, PartialEq)]
+pub struct LoadtestArgs {
+    /// Sessions created at once, each with one prompt
+    #[arg(long, default_value_t = 20)]
+    pub sessions: usize,
+    /// Messages the fake CLI streams per run
+    #[arg(long, default_value_t = 50)]
+    pub messages: usize,
+    /// Pause between the fake CLI's messages
+    #[arg(long, default_value_t = 50)]
+    pub message_interval_ms: u64,
+    /// CLI processes allowed at once, overriding `CLI_MAX_CONCURRENT`
+    #[arg(long)]
+    pub max_concurrent_clis: Option<usize>,
+    /// Give up on runs that have not finished after this long
+    #[arg(long, default_value_t = 300)]
+    pub timeout_secs: u64,
+    /// Port of the mock sandbox, allocator and GitHub API
+    #[arg(long, default_value_t = 18089)]
+    pub mock_port: u16,
+    /// Also write the report as JSON to this file
+    #[arg(long)]
+    pub report: Option<PathBuf>,
+    /// Leave the sessions in the database instead of deleting them
+    #[arg(long)]
+    pub keep: bool,
+}
+
+/// Distribution of a latency, in milliseconds
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LatencySummary {
+    pub samples: usize,
+    pub mean_ms: f64,
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+impl LatencySummary {
+    fn from_samples(mut samples: Vec<f64>) -> Option<LatencySummary> {
+        if samples.is_empty() {
+            return None;
+        }
+        samples.sort_by(f64::total_cmp);
+        Some(LatencySummary {
+            samples: samples.len(),
+            mean_ms: samples.iter().sum::<f64>() / samples.len() as f64,
+            p50_ms: percentile(&samples, 50.0),
+            p95_ms: percentile(&samples, 95.0),
+            p99_ms: percentile(&samples, 99.0),
+            max_ms: samples[samples.len() - 1],
+        })
+    }
+}
+
+/// Nearest-rank percentile of sorted, non-empty `samples`
+fn percentile(samples: &[f64], pct: f64) -> f64 {
+    let rank = (pct / 100.0 * samples.len() as f64).ceil() as usize;
+    samples[rank.clamp(1, samples.len()) - 1]
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LoadtestReport {
+    pub sessions: usize,
+    pub messages_per_run: usize,
+    pub message_interval_ms: u64,
+    /// Runs that finished with exit code 0
+    pub succeeded: usize,
+    /// Runs that finished otherwise
+    pub failed: usize,
+    /// Runs still unfinished at the timeout
+    pub timed_out: usize,
+    pub wall_clock_secs: f64,
+    /// From the prompt's creation to its first stored message
+    pub enqueue_to_first_message: Option<LatencySummary>,
+    /// From the prompt's creation to the end of its run
+    pub enqueue_to_completion: Option<LatencySummary>,
+    pub messages_written: u64,
+    /// Messages stored per second, between the first and the last one
+    pub message_writes_per_sec: Option<f64>,
+    /// Mean time the publisher spent storing one message
+    pub mean_message_write_ms: Option<f64>,
+    pub peak_concurrent_clis: i64,
+    /// Mean peak memory of a CLI process; the fake CLI's own footprint is negligible
+    pub mean_cli_peak_memory_bytes: Option<f64>,
+    /// Worker peak RSS growth over the run divided by the peak concurrent CLIs: what each
+    /// concurrent CLI costs the worker process
+    pub worker_rss_per_cli_bytes: Option<f64>,
+    pub worker_peak_rss_bytes: Option<u64>,
+}
+
+impl LoadtestReport {
+    fn print(&self) {
+        println!(
+            "Load test: {} sessions, {} messages each, {}ms apart",
+            self.sessions, self.messages_per_run, self.message_interval_ms
+        );
+        println!(
+            "  runs              {} succeeded, {} failed, {} timed out in {:.1}s",
+            self.succeeded, self.failed, self.timed_out, self.wall_clock_secs
+        );
+        for (label, summary) in [
+            ("first message", &self.enqueue_to_first_message),
+            ("completion", &self.enqueue_to_completion),
+        ] {
+            match summary {
+                Some(s) => println!(
+                    "  {:<17} p50 {:.0}ms  p95 {:.0}ms  p99 {:.0}ms  max {:.0}ms  mean {:.0}ms",
+                    label, s.p50_ms, s.p95_ms, s.p99_ms, s.max_ms, s.mean_ms
+                ),
+                None => println!("  {:<17} -", label),
+            }
+        }
+        println!(
+            "  messages          {} written, {} per second, {} per write",
+            self.messages_written,
+            format_opt(self.message_writes_per_sec, "", 1.0),
+            format_opt(self.mean_message_write_ms, "ms", 1.0),
+        );
+        println!("  concurrent CLIs   {} at peak", self.peak_concurrent_clis);
+        println!(
+            "  memory            {} per CLI process, {} worker RSS per concurrent CLI, {} worker peak",
+            format_opt(self.mean_cli_peak_memory_bytes, "MiB", MIB),
+            format_opt(self.worker_rss_per_cli_bytes, "MiB", MIB),
+            format_opt(self.worker_peak_rss_bytes.map(|b| b as f64), "MiB", MIB),
+        );
+    }
+}
+
+const MIB: f64 = 1024.0 * 1024.0;
+
+fn format_opt(value: Option<f64>, unit: &str, scale: f64) -> String {
+    match value {
+        Some(value) => format!("{:.1}{}", value / scale, unit),
+        None => "-".to_string(),
+    }
+}
+
+#[rocket::get("/allocator/borrow")]
+fn mock_borrow(port: &rocket::State<u16>) -> Json<serde_json::Value> {
+    Json(serde_json::json!({
+        "item": {
+            "api_url": format!("http://127.0.0.1:{}/sandbox", port.inner()),
+            "mcp_json_string": "{}",
+        },
+        "borrow_token": Uuid::new_v4().to_string(),
+    }))
+}
+
+#[rocket::post("/allocator/return")]
+fn mock_return() -> Json<serde_json::Value> {
+    Json(serde_json::json!({}))
+}
+
+/// Every sandbox call succeeds; the body satisfies each response type the pipeline reads, and
+/// the output reports a clone with nothing unpushed
+#[rocket::post("/sandbox/<_path..>")]
+fn mock_sandbox(_path: PathBuf) -> Json<serde_json::Value> {
+    Json(sandbox_ok_body())
+}
+
+/// A successful sandbox response that every response type the pipeline reads accepts
+pub(crate) fn sandbox_ok_body() -> serde_json::Value {
+    serde_json::json!({
+        "success": true,
+        "message": "ok",
+        "data": {
+            "session_id": "loadtest",
+            "command": "",
+            "status": "completed",
+            "output": "ahead 0",
+            "exit_code": 0,
+            "file": "",
+            "bytes_written": 0,
+            "path": "",
+            "files": [],
+            "total_count": 0,
+        },
+    })
+}
+
+/// Pull request lists are empty and everything else is missing, which the pipeline tolerates
+#[rocket::get("/github/<path..>")]
+pub(crate) fn mock_github(path: PathBuf) -> (Status, Json<serde_json::Value>) {
+    if path.ends_with("pulls") {
+        (Status::Ok, Json(serde_json::json!([])))
+    } else {
+        (
+            Status::NotFound,
+            Json(serde_json::json!({"message": "Not Found"})),
+        )
+    }
+}
+
+async fn start_mock(port: u16) -> anyhow::Result<()> {
+    let figment = rocket::Config::figment()
+        .merge(("address", "127.0.0.1"))
+        .merge(("port", port))
+        .merge(("log_level", rocket::config::LogLevel::Off));
+    let rocket = rocket::custom(figment).manage(port).mount(
+        "/",
+        rocket::routes![mock_borrow, mock_return, mock_sandbox, mock_github],
+    );
+    tokio::spawn(async move {
+        if let Err(e) = rocket.launch().await {
+            eprintln!("Mock server failed: {}", e);
+        }
+    });
+
+    let url = format!("http://127.0.0.1:{}/allocator/borrow", port);
+    for _ in 0..50 {
+        if reqwest::get(&url).await.is_ok() {
+            return Ok(());
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    anyhow::bail!("Mock server did not start on port {}", port)
+}
+
+/// A `claude` that prints `messages` stream-json lines, `interval_ms` apart
+fn fake_cli_script(messages: usize, interval_ms: u64) -> String {
+    format!(
+        r#"#!/bin/sh
+i=1
+while [ "$i" -lt {messages} ]; do
+  echo '{{"type":"assistant","message":{{"content":[{{"type":"text","text":"Working on step '"$i"'."}}]}}}}'
+  sleep {interval}
+  i=$((i + 1))
+done
+echo '{{"type":"result","subtype":"success","result":"Done."}}'
+"#,
+        messages = messages,
+        interval = interval_ms as f64 / 1000.0,
+    )
+}
+
+/// Put a `claude` running `script` first on `PATH`
+pub(crate) fn install_fake_cli(dir: &Path, script: &str) -> anyhow::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = dir.join("claude");
+    std::fs::write(&path, script)?;
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))?;
+    let search_path = std::env::var("PATH").unwrap_or_default();
+    std::env::set_var("PATH", format!("{}:{}", dir.display(), search_path));
+    Ok(())
+}
+
+async fn create_sessions(db: &DatabaseConnection, count: usize) -> anyhow::Result<Vec<Uuid>> {
+    let user = AuthenticatedUser {
+        user_id: LOADTEST_USER.to_string(),
+        email: None,
+        name: None,
+        roles: Vec::new(),
+        org_id: None,
+    };
+    let mut prompt_ids = Vec::with_capacity(count);
+    for i in 0..count {
+        let session_id = Uuid::new_v4();
+        let mut session = new_session(session_id, &user, None, "loadtest/repo", "main", None, None);
+        session.title = Set(Some(format!("Load test {}", i + 1)));
+        session.title_pending = Set(false);
+        session.insert(db).await?;
+
+        let prompt_id = Uuid::new_v4();
+        new_prompt(
+            prompt_id,
+            session_id,
+            serde_json::to_value(Conversation::from_text("Load test run"))?,
+            PromptPriority::Normal,
+        )
+        .insert(db)
+        .await?;
+        prompt_ids.push(prompt_id);
+    }
+    Ok(prompt_ids)
+}
+
+/// Prompts of the load test whose run has not finished
+async fn unfinished(db: &DatabaseConnection) -> anyhow::Result<u64> {
+    Ok(Session::find()
+        .filter(session::Column::UserId.eq(LOADTEST_USER))
+        .filter(session::Column::UiStatus.is_in(UNFINISHED))
+        .count(db)
+        .await?)
+}
+
+fn millis_between(from: DateTime<FixedOffset>, to: DateTime<FixedOffset>) -> f64 {
+    (to - from).num_microseconds().unwrap_or_default() as f64 / 1000.0
+}
+
+async fn build_report(
+    db: &DatabaseConnection,
+    args: &LoadtestArgs,
+    prompt_ids: &[Uuid],
+    elapsed: Duration,
+    peak_concurrent_clis: i64,
+    baseline_rss: Option<u64>,
+) -> anyhow::Result<LoadtestReport> {
+    let prompts = Prompt::find()
+        .filter(prompt::Column::Id.is_in(prompt_ids.to_vec()))
+        .find_also_related(Session)
+        .all(db)
+        .await?;
+
+    // First message and message count per prompt
+    let message_stats: Vec<(Uuid, DateTime<FixedOffset>, DateTime<FixedOffset>, i64)> =
+        Message::find()
+            .select_only()
+            .column(message::Column::PromptId)
+            .column_as(message::Column::CreatedAt.min(), "first_at")
+            .column_as(message::Column::CreatedAt.max(), "last_at")
+            .column_as(message::Column::Id.count(), "count")
+            .filter(message::Column::PromptId.is_in(prompt_ids.to_vec()))
+            .group_by(message::Column::PromptId)
+            .into_tuple()
+            .all(db)
+            .await?;
+    let message_stats: HashMap<Uuid, (DateTime<FixedOffset>, DateTime<FixedOffset>, i64)> =
+        message_stats
+            .into_iter()
+            .map(|(id, first, last, count)| (id, (first, last, count)))
+            .collect();
+
+    let (mut succeeded, mut failed, mut timed_out) = (0, 0, 0);
+    let mut first_message = Vec::new();
+    let mut completion = Vec::new();
+    let mut write_ms = 0.0;
+    for (prompt, session) in &prompts {
+        let finished = session
+            .as_ref()
+            .is_some_and(|s| !UNFINISHED.contains(&s.ui_status));
+        match (finished, prompt.exit_code) {
+            (false, _) => timed_out += 1,
+            (true, Some(0)) => succeeded += 1,
+            (true, _) => failed += 1,
+        }
+        if let Some((first_at, _, _)) = message_stats.get(&prompt.id) {
+            first_message.push(millis_between(prompt.created_at, *first_at));
+        }
+        if finished {
+            let completed_at = prompt
+                .completed_at
+                .or(session.as_ref().map(|s| s.updated_at))
+                .unwrap_or(prompt.updated_at);
+            completion.push(millis_between(prompt.created_at, completed_at));
+        }
+        write_ms += prompt
+            .timings
+            .as_ref()
+            .and_then(|t| t.get("message_db_write"))
+            .and_then(|v| v.as_f64())
+            .unwrap_or_default();
+    }
+
+    let messages_written: u64 = message_stats.values().map(|(_, _, n)| *n as u64).sum();
+    let first_write = message_stats.values().map(|(first, _, _)| *first).min();
+    let last_write = message_stats.values().map(|(_, last, _)| *last).max();
+    let message_writes_per_sec = match (first_write, last_write) {
+        (Some(first), Some(last)) if last > first => {
+            Some(messages_written as f64 / (millis_between(first, last) / 1000.0))
+        }
+        _ => None,
+    };
+
+    let memory = &metrics::get().cli_process_peak_memory_bytes;
+    let worker_peak_rss = process_supervisor::peak_rss_bytes(std::process::id());
+    let worker_rss_per_cli_bytes = match (baseline_rss, worker_peak_rss) {
+        (Some(baseline), Some(peak)) if peak_concurrent_clis > 0 => {
+            Some(peak.saturating_sub(baseline) as f64 / peak_concurrent_clis as f64)
+        }
+        _ => None,
+    };
+
+    Ok(LoadtestReport {
+        sessions: args.sessions,
+        messages_per_run: args.messages,
+        message_interval_ms: args.message_interval_ms,
+        succeeded,
+        failed,
+        timed_out,
+        wall_clock_secs: elapsed.as_secs_f64(),
+        enqueue_to_first_message: LatencySummary::from_samples(first_message),
+        enqueue_to_completion: LatencySummary::from_samples(completion),
+        messages_written,
+        message_writes_per_sec,
+        mean_message_write_ms: (messages_written > 0).then(|| write_ms / messages_written as f64),
+        peak_concurrent_clis,
+        mean_cli_peak_memory_bytes: (memory.get_sample_count() > 0)
+            .then(|| memory.get_sample_sum() / memory.get_sample_count() as f64),
+        worker_rss_per_cli_bytes,
+        worker_peak_rss_bytes: worker_peak_rss,
+    })
+}
+
+pub async fn run_loadtest(
+    db: &DatabaseConnection,
+    database_url: &str,
+    args: LoadtestArgs,
+) -> anyhow::Result<()> {
+    if config::is_production(std::env::var("APP_ENV").ok().as_deref()) {
+        anyhow::bail!("Refusing to run a load test because APP_ENV is production");
+    }
+    let queued = Session::find()
+        .filter(session::Column::UiStatus.is_in(UNFINISHED))
+        .count(db)
+        .await?;
+    if queued > 0 {
+        anyhow::bail!(
+            "{} sessions are already queued or running; the load test needs a database of its own",
+            queued
+        );
+    }
+
+    // Everything the pipeline talks to points at the mock, and `claude` at the fake CLI
+    let mock_url = format!("http://127.0.0.1:{}", args.mock_port);
+    std::env::set_var("IP_ALLOCATOR_URL", format!("{}/allocator", mock_url));
+    std::env::set_var("GITHUB_API_URL", format!("{}/github", mock_url));
+    std::env::set_var("GITHUB_TOKEN", "loadtest");
+    if let Some(max) = args.max_concurrent_clis {
+        std::env::set_var("CLI_MAX_CONCURRENT", max.to_string());
+    }
+    let cli_dir = tempfile::tempdir()?;
+    install_fake_cli(
+        cli_dir.path(),
+        &fake_cli_script(args.messages, args.message_interval_ms),
+    )?;
+    start_mock(args.mock_port).await?;
+
+    let baseline_rss = process_supervisor::peak_rss_bytes(std::process::id());
+    let pool = apalis_sql::postgres::PgPool::connect(database_url).await?;
+    let task_context = bg_tasks::TaskContext::new(Some(database_url.to_string())).await?;
+    let poller_db = db.clone();
+    let ip_return_db = db.clone();
+    let workers = [
+        tokio::spawn(
+            async move { bg_tasks::prompt_poller::run_prompt_poller(poller_db, pool).await },
+        ),
+        tokio::spawn(task_context.run_bg_tasks(vec![bg_tasks::OUTBOX_PUBLISHER.to_string()])),
+        tokio::spawn(async move {
+            bg_tasks::ip_return_poller::run_ip_return_poller(ip_return_db).await
+        }),
+    ];
+
+    let started = Instant::now();
+    let prompt_ids = create_sessions(db, args.sessions).await?;
+    eprintln!("Created {} sessions", prompt_ids.len());
+
+    let sampler = tokio::spawn(async {
+        let mut peak = 0;
+        loop {
+            peak = peak.max(metrics::get().cli_processes_running.get());
+            PEAK_CLIS.store(peak, Ordering::Relaxed);
+            tokio::time::sleep(SAMPLE_INTERVAL).await;
+        }
+    });
+
+    let deadline = started + Duration::from_secs(args.timeout_secs);
+    while unfinished(db).await? > 0 && Instant::now() < deadline {
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+    let elapsed = started.elapsed();
+    sampler.abort();
+    for worker in &workers {
+        worker.abort();
+    }
+    let peak_concurrent_clis = PEAK_CLIS.load(Ordering::Relaxed);
+
+    let report = build_report(
+        db,
+        &args,
+        &prompt_ids,
+        elapsed,
+        peak_concurrent_clis,
+        baseline_rss,
+    )
+    .await?;
+    report.print();
+    if let Some(path) = &args.report {
+        std::fs::write(path, serde_json::to_string_pretty(&report)?)?;
+        println!("Report written to {}", path.display());
+    }
+
+    if !args.keep {
+        // Prompts, messages and events go with their sessions
+        Session::delete_many()
+            .filter(session::Column::UserId.eq(LOADTEST_USER))
+            .exec(db)
+            .await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latency_summary() {
+        let samples: Vec<f64> = (1..=100).rev().map(f64::from).collect();
+        let summary = LatencySummary::from_samples(samples).unwrap();
+        assert_eq!(summary.p50_ms, 50.0);
+        assert_eq!(summary.p95_ms, 95.0);
+        assert_eq!(summary.p99_ms, 99.0);
+        assert_eq!(summary.max_ms, 100.0);
+        assert_eq!(summary.mean_ms, 50.5);
+
+        assert_eq!(percentile(&[7.0], 99.0), 7.0);
+        assert!(LatencySummary::from_samples(Vec::new()).is_none());
+    }
+}
@@ -6,21 +6,31 @@ use dotenv::dotenv;
 use tracing::info;
 
 use crate::auth::JwksCache;
-use crate::db::establish_connection;
+use crate::db::{establish_connection, ReadDb};
 
 use rocket_cors::{AllowedOrigins, CorsOptions};
+use rocket_okapi::okapi::openapi3::OpenApi;
 use rocket_okapi::settings::UrlObject;
 use rocket_okapi::swagger_ui::make_swagger_ui;
-use rocket_okapi::{openapi_get_routes, rapidoc::*, swagger_ui::*};
+use rocket_okapi::{rapidoc::*, swagger_ui::*};
 
 use sea_orm_migration::prelude::*;
 
 mod auth;
+mod backoff;
 mod bg_tasks;
+mod cli;
+mod config;
 mod db;
 mod entities;
 mod error;
+#[cfg(feature = "grpc")]
+mod grpc;
 mod handlers;
+mod loadtest;
+mod metrics;
+mod replay;
+mod seed;
 mod services;
 
 /// CLI application for the prompt backend server
@@ -34,58 +44,214 @@ struct Cli {
     /// Run the web server and background tasks
     #[arg(long)]
     server: bool,
+
+    /// Serve gRPC next to the web server, as with GRPC_ENABLED
+    #[arg(long)]
+    grpc: bool,
 }
 
 #[derive(Subcommand, PartialEq)]
 enum Commands {
     /// Print the OpenAPI specification in JSON format
     PrintOpenapi,
+    /// Inspect and act on dead letter queue entries
+    Dlq {
+        #[command(subcommand)]
+        command: cli::DlqCommand,
+        /// Print JSON instead of a table
+        #[arg(long, global = true)]
+        json: bool,
+    },
+    /// Fill a local database with fake users' sessions in every state
+    Seed(seed::SeedArgs),
+    /// Run synthetic sessions through the pipeline against mocks and report its capacity
+    Loadtest(loadtest::LoadtestArgs),
+    /// Record a prompt's run as a fixture, or replay the outbox job against one
+    Replay {
+        #[command(subcommand)]
+        command: replay::ReplayCommand,
+    },
+    /// Rebuild the conversation read model from the message table
+    RebuildConversations {
+        /// Only rebuild this session's conversation
+        #[arg(long)]
+        session: Option<uuid::Uuid>,
+    },
 }
 
-/// Generate OpenAPI specification
-fn generate_openapi_spec() -> String {
+/// API routes and their OpenAPI specification
+fn api_routes() -> (Vec<rocket::Route>, OpenApi) {
     let settings = rocket_okapi::settings::OpenApiSettings::new();
-    let spec = rocket_okapi::openapi_spec![
+    rocket_okapi::openapi_get_routes_spec![settings:
         handlers::health::health,
+        handlers::health::ready,
         handlers::sessions::create,
         handlers::sessions::create_with_prompt,
+        handlers::sessions::create_fan_out,
+        handlers::sessions::fan_out_progress,
+        handlers::sessions::preflight,
         handlers::sessions::read,
         handlers::sessions::list,
         handlers::sessions::update,
         handlers::sessions::delete,
         handlers::sessions::cancel,
+        handlers::sessions::push_unpushed_work,
+        handlers::sessions::release_unpushed_work,
+        handlers::sessions::queue,
+        handlers::sessions::estimate,
+        handlers::sessions::list_artifacts,
+        handlers::sessions::list_events,
+        handlers::sessions::read_conversation,
+        handlers::sessions::describe_pull_request,
+        handlers::uploads::create,
+        handlers::uploads::list,
+        handlers::attachments::create,
+        handlers::attachments::read,
+        handlers::sessions::add_tags,
+        handlers::sessions::remove_tag,
+        handlers::tags::list,
+        handlers::models::list,
+        handlers::meta::list_statuses,
+        handlers::settings::read,
+        handlers::settings::update,
+        handlers::users::erase_data,
+        handlers::users::read_erasure,
         handlers::prompts::create,
+        handlers::prompts::rerun,
         handlers::prompts::read,
+        handlers::prompts::read_run,
+        handlers::prompts::read_rendered,
+        handlers::prompts::list_artifacts,
+        handlers::prompts::list_changes,
+        handlers::prompts::find_artifacts,
         handlers::prompts::list,
         handlers::prompts::update,
+        handlers::prompts::requeue,
+        handlers::prompts::submit,
         handlers::prompts::delete,
         handlers::messages::create,
         handlers::messages::read,
         handlers::messages::list,
+        handlers::messages::stream_session_messages,
         handlers::messages::update,
         handlers::messages::delete,
+        handlers::annotations::create,
+        handlers::annotations::list,
+        handlers::annotations::update,
+        handlers::annotations::delete,
+        handlers::notifications::list,
+        handlers::notifications::mark_read,
+        handlers::notifications::mark_all_read,
+        handlers::notifications::watch,
+        handlers::notifications::unwatch,
+        handlers::github::search_repositories,
+        handlers::github::list_branches,
         handlers::webhooks::return_item,
+        handlers::webhooks::keycloak_event,
+        handlers::internal::update_session_status,
+        handlers::internal::create_session_messages,
+        handlers::internal::read_queue_stats,
         handlers::dead_letter_queue::list_dlq_entries,
+        handlers::dead_letter_queue::dlq_stats,
         handlers::dead_letter_queue::get_dlq_entry,
         handlers::dead_letter_queue::resolve_dlq,
+        handlers::dead_letter_queue::retry_dlq,
         handlers::dead_letter_queue::abandon_dlq,
-    ](&settings);
-    serde_json::to_string_pretty(&spec).unwrap()
+        handlers::admin::integrity_report,
+        handlers::admin::integrity_repair,
+        handlers::admin::list_operational_events,
+        handlers::admin::list_workers,
+        handlers::admin::pause_poller,
+        handlers::admin::resume_poller,
+        handlers::admin::overview,
+        handlers::admin::lookup_entity,
+        handlers::admin::offload_messages,
+        handlers::admin::recover_output,
+        handlers::admin::deprovision_user,
+        handlers::admin::list_organizations,
+        handlers::admin::update_organization,
+        handlers::admin::exec_in_sandbox,
+        handlers::admin::force_release_session,
+    ]
+}
+
+/// Generate OpenAPI specification
+fn generate_openapi_spec() -> String {
+    serde_json::to_string_pretty(&api_routes().1).unwrap()
 }
 
 #[rocket::main]
 async fn main() -> anyhow::Result<()> {
     dotenv().ok();
 
-    // Initialize tracing
-    tracing_subscriber::fmt::init();
-
     let cli = Cli::parse();
 
-    // Handle print-openapi command
-    if let Some(Commands::PrintOpenapi) = cli.command {
-        println!("{}", generate_openapi_spec());
-        return Ok(());
+    match cli.command {
+        // Handle print-openapi command
+        Some(Commands::PrintOpenapi) => {
+            tracing_subscriber::fmt::init();
+            println!("{}", generate_openapi_spec());
+            return Ok(());
+        }
+        // Only warnings, on stderr so they do not mix with the output
+        Some(Commands::Dlq { command, json }) => {
+            tracing_subscriber::fmt()
+                .with_max_level(tracing::Level::WARN)
+                .with_writer(std::io::stderr)
+                .init();
+            let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+            let db = establish_connection(&database_url, "cli").await?;
+            return cli::run_dlq(&db, command, json).await;
+        }
+        Some(Commands::Seed(args)) => {
+            tracing_subscriber::fmt()
+                .with_max_level(tracing::Level::WARN)
+                .with_writer(std::io::stderr)
+                .init();
+            let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+            let db = establish_connection(&database_url, "cli").await?;
+            return seed::run_seed(&db, args).await;
+        }
+        // The mock server is a Rocket instance too; its warnings are expected
+        Some(Commands::Loadtest(args)) => {
+            tracing_subscriber::fmt()
+                .with_env_filter("warn,rocket=error")
+                .with_writer(std::io::stderr)
+                .init();
+            let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+            let db = establish_connection(&database_url, "loadtest").await?;
+            return loadtest::run_loadtest(&db, &database_url, args).await;
+        }
+        // Like the load test, replays serve their mocks from a Rocket instance
+        Some(Commands::Replay { command }) => {
+            tracing_subscriber::fmt()
+                .with_env_filter("warn,rocket=error")
+                .with_writer(std::io::stderr)
+                .init();
+            let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+            let db = establish_connection(&database_url, "replay").await?;
+            return replay::run_replay(&db, &database_url, command).await;
+        }
+        Some(Commands::RebuildConversations { session }) => {
+            tracing_subscriber::fmt()
+                .with_max_level(tracing::Level::WARN)
+                .with_writer(std::io::stderr)
+                .init();
+            let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+            let db = establish_connection(&database_url, "cli").await?;
+            let counts = match session {
+                Some(session_id) => {
+                    services::conversation_view::rebuild_session(&db, session_id).await?
+                }
+                None => services::conversation_view::rebuild_all(&db).await?,
+            };
+            println!(
+                "Rebuilt {} conversation chunks for {} prompts",
+                counts.chunks, counts.prompts
+            );
+            return Ok(());
+        }
+        None => tracing_subscriber::fmt::init(),
     }
 
     let redis_url = std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1/".to_string());
@@ -98,9 +264,10 @@ async fn main() -> anyhow::Result<()> {
         let server_redis_url = redis_url.clone();
         let server_database_url = database_url.clone();
 
+        let grpc = cli.grpc || config::get().grpc.enabled;
         let server_handle = tokio::spawn(async move {
             info!("Starting web server");
-            run_server(server_redis_url, server_database_url).await
+            run_server(server_redis_url, server_database_url, grpc).await
         });
 
         handles.push(server_handle);
@@ -128,7 +295,7 @@ async fn main() -> anyhow::Result<()> {
             info!("Starting prompt poller");
 
             // Create SeaORM database connection for the poller
-            let db = establish_connection(&poller_database_url).await?;
+            let db = establish_connection(&poller_database_url, "prompt_poller").await?;
 
             // Create PostgreSQL pool for apalis storage
             let pool = apalis_sql::postgres::PgPool::connect(&poller_database_url).await?;
@@ -144,7 +311,7 @@ async fn main() -> anyhow::Result<()> {
             info!("Starting IP return poller");
 
             // Create SeaORM database connection for the poller
-            let db = establish_connection(&ip_return_database_url).await?;
+            let db = establish_connection(&ip_return_database_url, "ip_return_poller").await?;
 
             bg_tasks::ip_return_poller::run_ip_return_poller(db).await
         });
@@ -157,12 +324,106 @@ async fn main() -> anyhow::Result<()> {
             info!("Starting cancellation enforcer");
 
             // Create SeaORM database connection for the enforcer
-            let db = establish_connection(&cancellation_database_url).await?;
+            let db =
+                establish_connection(&cancellation_database_url, "cancellation_enforcer").await?;
 
             bg_tasks::cancellation_enforcer::run_cancellation_enforcer(db).await
         });
 
         handles.push(cancellation_handle);
+
+        // Spawn integrity checker
+        let integrity_database_url = database_url.clone();
+        let integrity_handle = tokio::spawn(async move {
+            info!("Starting integrity checker");
+
+            // Create SeaORM database connection for the checker
+            let db = establish_connection(&integrity_database_url, "integrity_checker").await?;
+
+            bg_tasks::integrity_checker::run_integrity_checker(db).await
+        });
+
+        handles.push(integrity_handle);
+
+        // Spawn data retention
+        let retention_database_url = database_url.clone();
+        let retention_handle = tokio::spawn(async move {
+            info!("Starting data retention");
+
+            let db = establish_connection(&retention_database_url, "data_retention").await?;
+
+            bg_tasks::data_retention::run_data_retention(db).await
+        });
+
+        handles.push(retention_handle);
+
+        // Spawn message compaction
+        let compaction_database_url = database_url.clone();
+        let compaction_handle = tokio::spawn(async move {
+            let db = establish_connection(&compaction_database_url, "message_compaction").await?;
+
+            bg_tasks::message_compaction::run_message_compaction(db).await
+        });
+
+        handles.push(compaction_handle);
+
+        // Spawn title regeneration
+        let titles_database_url = database_url.clone();
+        let titles_handle = tokio::spawn(async move {
+            let db = establish_connection(&titles_database_url, "title_regeneration").await?;
+
+            bg_tasks::title_regeneration::run_title_regeneration(db).await
+        });
+
+        handles.push(titles_handle);
+
+        // Spawn DLQ monitor
+        let dlq_database_url = database_url.clone();
+        let dlq_handle = tokio::spawn(async move {
+            info!("Starting DLQ monitor");
+
+            // Create SeaORM database connection for the monitor
+            let db = establish_connection(&dlq_database_url, "dlq_monitor").await?;
+
+            bg_tasks::dlq_monitor::run_dlq_monitor(db).await
+        });
+
+        handles.push(dlq_handle);
+
+        // Spawn queue monitor
+        let queue_database_url = database_url.clone();
+        let queue_handle = tokio::spawn(async move {
+            info!("Starting queue monitor");
+
+            let db = establish_connection(&queue_database_url, "queue_monitor").await?;
+
+            bg_tasks::queue_monitor::run_queue_monitor(db).await
+        });
+
+        handles.push(queue_handle);
+
+        // Spawn sandbox prewarmer
+        let prewarm_database_url = database_url.clone();
+        let prewarm_handle = tokio::spawn(async move {
+            let db = establish_connection(&prewarm_database_url, "sandbox_prewarm").await?;
+
+            bg_tasks::sandbox_prewarm::run_sandbox_prewarmer(db).await
+        });
+
+        handles.push(prewarm_handle);
+
+        // Spawn outbox event relay
+        let relay_database_url = database_url.clone();
+        let relay_handle = tokio::spawn(async move {
+            info!("Starting outbox event relay");
+
+            let db = establish_connection(&relay_database_url, "outbox_event_relay").await?;
+            let pool = apalis_sql::postgres::PgPool::connect(&relay_database_url).await?;
+
+            bg_tasks::outbox_events::run_outbox_event_relay(db, pool).await
+        });
+
+        handles.push(relay_handle);
     }
 
     // If no services specified, error out
@@ -180,8 +441,8 @@ async fn main() -> anyhow::Result<()> {
 }
 
 /// Run the Rocket web server
-async fn run_server(_redis_url: String, database_url: String) -> anyhow::Result<()> {
-    let db = establish_connection(&database_url)
+async fn run_server(_redis_url: String, database_url: String, grpc: bool) -> anyhow::Result<()> {
+    let db = establish_connection(&database_url, "server")
         .await
         .expect("Failed to connect to database");
 
@@ -192,17 +453,48 @@ async fn run_server(_redis_url: String, database_url: String) -> anyhow::Result<
         .expect("Failed to run migrations");
     println!("Migrations completed successfully");
 
-    // Initialize JWKS cache
-    let keycloak_issuer = std::env::var("KEYCLOAK_ISSUER").expect("KEYCLOAK_ISSUER must be set");
-    let keycloak_jwks_uri =
-        std::env::var("KEYCLOAK_JWKS_URI").expect("KEYCLOAK_JWKS_URI must be set");
+    // Read-only endpoints use the replica from DATABASE_READ_URL when one is configured
+    let read_db = ReadDb::connect(
+        db.clone(),
+        std::env::var("DATABASE_READ_URL").ok().as_deref(),
+    )
+    .await;
+    tokio::spawn(read_db.clone().run_health_check());
 
-    let jwks_cache = JwksCache::new(keycloak_jwks_uri, keycloak_issuer);
+    // Initialize JWKS cache
+    let jwks_cache = JwksCache::from_env();
+
+    if let Some(dev) = &config::get().dev_auth {
+        // Keycloak is optional in dev auth mode; its keys are fetched on first use
+        println!(
+            "DEV_AUTH_MODE enabled: DEV_AUTH_TOKEN authenticates as {}",
+            dev.user_id
+        );
+    } else {
+        // Pre-fetch JWKS on startup
+        println!("Fetching JWKS from Keycloak...");
+        jwks_cache
+            .fetch_jwks()
+            .await
+            .expect("Failed to fetch JWKS (KEYCLOAK_ISSUER and KEYCLOAK_JWKS_URI must be set)");
+        println!("JWKS fetched successfully");
+    }
 
-    // Pre-fetch JWKS on startup
-    println!("Fetching JWKS from Keycloak...");
-    jwks_cache.fetch_jwks().await.expect("Failed to fetch JWKS");
-    println!("JWKS fetched successfully");
+    if grpc {
+        #[cfg(feature = "grpc")]
+        {
+            let service =
+                grpc::GrpcService::new(db.clone(), read_db.clone(), jwks_cache.clone()).await;
+            let addr = std::net::SocketAddr::from(([0, 0, 0, 0], config::get().grpc.port));
+            tokio::spawn(async move {
+                if let Err(e) = grpc::serve(service, addr).await {
+                    tracing::error!("gRPC server stopped: {}", e);
+                }
+            });
+        }
+        #[cfg(not(feature = "grpc"))]
+        tracing::warn!("gRPC is enabled but this binary was built without the grpc feature");
+    }
 
     // Configure CORS to allow all origins, methods, and headers
     let cors = CorsOptions::default()
@@ -224,48 +516,51 @@ async fn run_server(_redis_url: String, database_url: String) -> anyhow::Result<
         .to_cors()
         .expect("Failed to create CORS fairing");
 
-    // Create Prometheus registry
-    let prometheus_registry = prometheus::Registry::new();
+    // Serve the application-wide Prometheus registry
+    let prometheus_registry = metrics::get().registry.clone();
 
-    let _ = rocket::build()
+    let (api_routes, spec) = api_routes();
+    let openapi_document = handlers::openapi::OpenApiDocument::new(&spec);
+
+    let rocket = rocket::build()
         .configure(rocket::Config {
             address: "0.0.0.0".parse().expect("valid IP address"),
             port: 8000,
+            limits: rocket::data::Limits::default()
+                .limit(
+                    "json",
+                    rocket::data::ByteUnit::from(config::get().request_limits.json_body_bytes),
+                )
+                .limit(
+                    "file",
+                    rocket::data::ByteUnit::from(config::get().uploads.max_bytes),
+                )
+                // Room for the other parts and boundaries of an upload's multipart body
+                .limit(
+                    "data-form",
+                    rocket::data::ByteUnit::from(config::get().uploads.max_bytes + 64 * 1024),
+                ),
             ..rocket::Config::default()
         })
         .attach(cors)
+        .attach(metrics::HttpMetrics)
+        .register(
+            "/",
+            catchers![
+                error::payload_too_large_catcher,
+                error::unprocessable_entity_catcher
+            ],
+        )
         .manage(db)
+        .manage(read_db)
         .manage(jwks_cache)
         .manage(prometheus_registry)
+        .manage(openapi_document)
+        .mount("/", metrics::instrument_routes(api_routes))
         .mount(
             "/",
-            openapi_get_routes![
-                handlers::health::health,
-                handlers::sessions::create,
-                handlers::sessions::create_with_prompt,
-                handlers::sessions::read,
-                handlers::sessions::list,
-                handlers::sessions::update,
-                handlers::sessions::delete,
-                handlers::sessions::cancel,
-                handlers::prompts::create,
-                handlers::prompts::read,
-                handlers::prompts::list,
-                handlers::prompts::update,
-                handlers::prompts::delete,
-                handlers::messages::create,
-                handlers::messages::read,
-                handlers::messages::list,
-                handlers::messages::update,
-                handlers::messages::delete,
-                handlers::webhooks::return_item,
-                handlers::dead_letter_queue::list_dlq_entries,
-                handlers::dead_letter_queue::get_dlq_entry,
-                handlers::dead_letter_queue::resolve_dlq,
-                handlers::dead_letter_queue::abandon_dlq,
-            ],
+            routes![handlers::metrics::metrics, handlers::openapi::openapi_json],
         )
-        .mount("/", routes![handlers::metrics::metrics])
         .mount(
             "/swagger-ui/",
             make_swagger_ui(&SwaggerUIConfig {
@@ -287,9 +582,13 @@ async fn run_server(_redis_url: String, database_url: String) -> anyhow::Result<
                 },
                 ..Default::default()
             }),
-        )
-        .launch()
-        .await?;
+        );
+
+    // Fault injection routes for exercising failure paths, never present in release builds
+    #[cfg(feature = "testing")]
+    let rocket = rocket.mount("/", handlers::chaos::routes());
+
+    let _ = rocket.launch().await?;
 
     Ok(())
 }
@@ -303,4 +602,14 @@ mod tests {
         let spec = generate_openapi_spec();
         insta::assert_snapshot!(spec);
     }
+
+    #[test]
+    fn test_client_openapi_spec_is_current() {
+        let checked_in = include_str!("../prompt-backend-client/openapi.json");
+        assert!(
+            checked_in.trim_end() == generate_openapi_spec(),
+            "prompt-backend-client/openapi.json is stale, regenerate it with \
+             `cargo run -- print-openapi > prompt-backend-client/openapi.json`"
+        );
+    }
 }
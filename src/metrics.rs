@@ -0,0 +1,607 @@
+use prometheus::{
+    Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry,
+};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::route::{self, Handler, Route};
+use rocket::{Data, Request, Response};
+use std::sync::LazyLock;
+use std::time::Instant;
+use tracing::Instrument;
+
+/// Application metrics, registered into the registry served at `/metrics`
+pub struct Metrics {
+    pub registry: Registry,
+    /// Duration of each phase of an outbox job (sandbox bootstrap steps, CLI run, DB writes)
+    pub prompt_phase_duration_seconds: HistogramVec,
+    /// Outbox job runs that failed, by the pipeline stage that failed
+    pub pipeline_failures_total: IntCounterVec,
+    /// Orphaned rows found by the last integrity check, by kind
+    pub integrity_orphans: IntGaugeVec,
+    /// Orphaned rows repaired by the integrity checker, by kind
+    pub integrity_orphans_repaired_total: IntCounterVec,
+    /// Session `ui_status` transitions accepted by the state machine
+    pub session_transitions_total: IntCounterVec,
+    /// Claude CLI runs waiting for a free process slot
+    pub cli_process_queue_depth: IntGauge,
+    /// Claude CLI processes currently holding a slot
+    pub cli_processes_running: IntGauge,
+    /// Peak memory of each finished Claude CLI process
+    pub cli_process_peak_memory_bytes: Histogram,
+    /// Disk used by a run's clone, at each check during the run
+    pub workspace_disk_bytes: Histogram,
+    /// HTTP requests served, by method, route template and status class
+    pub http_requests_total: IntCounterVec,
+    /// HTTP request latency, by method and route template
+    pub http_request_duration_seconds: HistogramVec,
+    /// Pending dead letter queue entries, by task type
+    pub dlq_pending_entries: IntGaugeVec,
+    /// Age of the oldest pending dead letter queue entry, by task type
+    pub dlq_oldest_pending_age_seconds: IntGaugeVec,
+    /// Time entries spent in the dead letter queue before being resolved, retried or abandoned
+    pub dlq_entry_age_seconds: HistogramVec,
+    /// Time from a prompt's creation to its first run claiming it, by priority
+    pub prompt_queue_latency_seconds: HistogramVec,
+    /// Open database connections, by pool and state (`in_use` or `idle`)
+    pub db_pool_connections: IntGaugeVec,
+    /// Configured size of each database pool
+    pub db_pool_max_connections: IntGaugeVec,
+    /// Queries slower than `DB_SLOW_QUERY_MS`, by pool
+    pub db_slow_queries_total: IntCounterVec,
+    /// Session histories sent as JSON because toon-format could not encode them
+    pub history_encoding_fallbacks_total: IntCounter,
+    /// Prompts waiting for a run, by priority
+    pub prompt_queue_pending: IntGaugeVec,
+    /// Age of the oldest prompt waiting for a run, by priority
+    pub prompt_queue_oldest_pending_age_seconds: IntGaugeVec,
+    /// Prompt runs currently claimed by a worker
+    pub prompt_runs_in_flight: IntGauge,
+    /// Background jobs in the worker queue, by job type and status
+    pub worker_queue_jobs: IntGaugeVec,
+    /// Background jobs run by this process's workers, by job type and outcome
+    pub worker_jobs_total: IntCounterVec,
+    /// Time each background job took, by job type and outcome
+    pub worker_job_duration_seconds: HistogramVec,
+    /// Pre-warmed sandboxes, by pool (`generic` or the repo) and status
+    pub warm_sandboxes: IntGaugeVec,
+    /// Sandbox dispatches, by whether a warm sandbox was claimed (`hit`) or one was borrowed
+    /// on demand (`miss`)
+    pub warm_sandbox_claims_total: IntCounterVec,
+    /// CLI messages read but not yet written, across runs
+    pub message_writer_buffer_depth: IntGauge,
+    /// Times a CLI reader waited because its message buffer was full
+    pub message_writer_overflows_total: IntCounter,
+    /// Time spent inserting each batch of CLI messages
+    pub message_insert_duration_seconds: Histogram,
+    /// CLI messages kept out of the message table by an ingestion filter, by event kind and
+    /// whether they were dropped or aggregated
+    pub messages_filtered_total: IntCounterVec,
+}
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+/// Get the process-wide metrics
+pub fn get() -> &'static Metrics {
+    &METRICS
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new();
+
+        let prompt_phase_duration_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "prompt_phase_duration_seconds",
+                "Time spent in each phase of processing a prompt",
+            )
+            .buckets(vec![
+                0.05, 0.1, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1800.0,
+            ]),
+            &["phase"],
+        )
+        .expect("valid prompt_phase_duration_seconds histogram");
+        registry
+            .register(Box::new(prompt_phase_duration_seconds.clone()))
+            .expect("register prompt_phase_duration_seconds");
+
+        let integrity_orphans = IntGaugeVec::new(
+            Opts::new(
+                "integrity_orphans",
+                "Orphaned rows found by the last integrity check",
+            ),
+            &["kind"],
+        )
+        .expect("valid integrity_orphans gauge");
+        registry
+            .register(Box::new(integrity_orphans.clone()))
+            .expect("register integrity_orphans");
+
+        let pipeline_failures_total = IntCounterVec::new(
+            Opts::new(
+                "pipeline_failures_total",
+                "Prompt runs that failed, by the pipeline stage that failed",
+            ),
+            &["kind"],
+        )
+        .expect("valid pipeline_failures_total counter");
+        registry
+            .register(Box::new(pipeline_failures_total.clone()))
+            .expect("register pipeline_failures_total");
+
+        let integrity_orphans_repaired_total = IntCounterVec::new(
+            Opts::new(
+                "integrity_orphans_repaired_total",
+                "Orphaned rows deleted or abandoned by the integrity checker",
+            ),
+            &["kind"],
+        )
+        .expect("valid integrity_orphans_repaired_total counter");
+        registry
+            .register(Box::new(integrity_orphans_repaired_total.clone()))
+            .expect("register integrity_orphans_repaired_total");
+
+        let session_transitions_total = IntCounterVec::new(
+            Opts::new(
+                "session_transitions_total",
+                "Session status transitions by previous status, new status and cause",
+            ),
+            &["from", "to", "cause"],
+        )
+        .expect("valid session_transitions_total counter");
+        registry
+            .register(Box::new(session_transitions_total.clone()))
+            .expect("register session_transitions_total");
+
+        let cli_process_queue_depth = IntGauge::new(
+            "cli_process_queue_depth",
+            "Claude CLI runs waiting for a free process slot",
+        )
+        .expect("valid cli_process_queue_depth gauge");
+        registry
+            .register(Box::new(cli_process_queue_depth.clone()))
+            .expect("register cli_process_queue_depth");
+
+        let cli_processes_running = IntGauge::new(
+            "cli_processes_running",
+            "Claude CLI processes currently running",
+        )
+        .expect("valid cli_processes_running gauge");
+        registry
+            .register(Box::new(cli_processes_running.clone()))
+            .expect("register cli_processes_running");
+
+        let cli_process_peak_memory_bytes = Histogram::with_opts(
+            HistogramOpts::new(
+                "cli_process_peak_memory_bytes",
+                "Peak memory used by each Claude CLI process",
+            )
+            .buckets(prometheus::exponential_buckets(64.0 * 1024.0 * 1024.0, 2.0, 8).unwrap()),
+        )
+        .expect("valid cli_process_peak_memory_bytes histogram");
+        registry
+            .register(Box::new(cli_process_peak_memory_bytes.clone()))
+            .expect("register cli_process_peak_memory_bytes");
+
+        let workspace_disk_bytes = Histogram::with_opts(
+            HistogramOpts::new(
+                "workspace_disk_bytes",
+                "Disk used by a run's clone in its sandbox, at each check",
+            )
+            .buckets(prometheus::exponential_buckets(64.0 * 1024.0 * 1024.0, 2.0, 10).unwrap()),
+        )
+        .expect("valid workspace_disk_bytes histogram");
+        registry
+            .register(Box::new(workspace_disk_bytes.clone()))
+            .expect("register workspace_disk_bytes");
+
+        let http_requests_total = IntCounterVec::new(
+            Opts::new(
+                "http_requests_total",
+                "HTTP requests by method, route template and status class",
+            ),
+            &["method", "route", "status_class"],
+        )
+        .expect("valid http_requests_total counter");
+        registry
+            .register(Box::new(http_requests_total.clone()))
+            .expect("register http_requests_total");
+
+        let http_request_duration_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time spent handling HTTP requests",
+            )
+            .buckets(vec![
+                0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+            ]),
+            &["method", "route"],
+        )
+        .expect("valid http_request_duration_seconds histogram");
+        registry
+            .register(Box::new(http_request_duration_seconds.clone()))
+            .expect("register http_request_duration_seconds");
+
+        let dlq_pending_entries = IntGaugeVec::new(
+            Opts::new(
+                "dlq_pending_entries",
+                "Pending dead letter queue entries by task type",
+            ),
+            &["task_type"],
+        )
+        .expect("valid dlq_pending_entries gauge");
+        registry
+            .register(Box::new(dlq_pending_entries.clone()))
+            .expect("register dlq_pending_entries");
+
+        let dlq_oldest_pending_age_seconds = IntGaugeVec::new(
+            Opts::new(
+                "dlq_oldest_pending_age_seconds",
+                "Age of the oldest pending dead letter queue entry by task type",
+            ),
+            &["task_type"],
+        )
+        .expect("valid dlq_oldest_pending_age_seconds gauge");
+        registry
+            .register(Box::new(dlq_oldest_pending_age_seconds.clone()))
+            .expect("register dlq_oldest_pending_age_seconds");
+
+        let dlq_entry_age_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "dlq_entry_age_seconds",
+                "Time dead letter queue entries waited before being resolved, retried or abandoned",
+            )
+            .buckets(vec![
+                60.0, 300.0, 900.0, 3600.0, 14400.0, 43200.0, 86400.0, 259200.0, 604800.0,
+            ]),
+            &["task_type", "outcome"],
+        )
+        .expect("valid dlq_entry_age_seconds histogram");
+        registry
+            .register(Box::new(dlq_entry_age_seconds.clone()))
+            .expect("register dlq_entry_age_seconds");
+
+        let prompt_queue_latency_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "prompt_queue_latency_seconds",
+                "Time prompts waited between creation and their first run by priority",
+            )
+            .buckets(vec![
+                1.0, 5.0, 15.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1800.0, 3600.0, 7200.0,
+            ]),
+            &["priority"],
+        )
+        .expect("valid prompt_queue_latency_seconds histogram");
+        registry
+            .register(Box::new(prompt_queue_latency_seconds.clone()))
+            .expect("register prompt_queue_latency_seconds");
+
+        let db_pool_connections = IntGaugeVec::new(
+            Opts::new(
+                "db_pool_connections",
+                "Open database connections by pool and state",
+            ),
+            &["pool", "state"],
+        )
+        .expect("valid db_pool_connections gauge");
+        registry
+            .register(Box::new(db_pool_connections.clone()))
+            .expect("register db_pool_connections");
+
+        let db_pool_max_connections = IntGaugeVec::new(
+            Opts::new(
+                "db_pool_max_connections",
+                "Maximum connections of each database pool",
+            ),
+            &["pool"],
+        )
+        .expect("valid db_pool_max_connections gauge");
+        registry
+            .register(Box::new(db_pool_max_connections.clone()))
+            .expect("register db_pool_max_connections");
+
+        let db_slow_queries_total = IntCounterVec::new(
+            Opts::new(
+                "db_slow_queries_total",
+                "Database queries slower than the slow query threshold by pool",
+            ),
+            &["pool"],
+        )
+        .expect("valid db_slow_queries_total counter");
+        registry
+            .register(Box::new(db_slow_queries_total.clone()))
+            .expect("register db_slow_queries_total");
+
+        let history_encoding_fallbacks_total = IntCounter::new(
+            "history_encoding_fallbacks_total",
+            "Session histories sent as JSON because toon-format could not encode them",
+        )
+        .expect("valid history_encoding_fallbacks_total counter");
+        registry
+            .register(Box::new(history_encoding_fallbacks_total.clone()))
+            .expect("register history_encoding_fallbacks_total");
+
+        let prompt_queue_pending = IntGaugeVec::new(
+            Opts::new(
+                "prompt_queue_pending",
+                "Prompts waiting for a run by priority",
+            ),
+            &["priority"],
+        )
+        .expect("valid prompt_queue_pending gauge");
+        registry
+            .register(Box::new(prompt_queue_pending.clone()))
+            .expect("register prompt_queue_pending");
+
+        let prompt_queue_oldest_pending_age_seconds = IntGaugeVec::new(
+            Opts::new(
+                "prompt_queue_oldest_pending_age_seconds",
+                "Age of the oldest prompt waiting for a run by priority",
+            ),
+            &["priority"],
+        )
+        .expect("valid prompt_queue_oldest_pending_age_seconds gauge");
+        registry
+            .register(Box::new(prompt_queue_oldest_pending_age_seconds.clone()))
+            .expect("register prompt_queue_oldest_pending_age_seconds");
+
+        let prompt_runs_in_flight = IntGauge::new(
+            "prompt_runs_in_flight",
+            "Prompt runs currently claimed by a worker",
+        )
+        .expect("valid prompt_runs_in_flight gauge");
+        registry
+            .register(Box::new(prompt_runs_in_flight.clone()))
+            .expect("register prompt_runs_in_flight");
+
+        let worker_queue_jobs = IntGaugeVec::new(
+            Opts::new(
+                "worker_queue_jobs",
+                "Background jobs in the worker queue by job type and status",
+            ),
+            &["job_type", "status"],
+        )
+        .expect("valid worker_queue_jobs gauge");
+        registry
+            .register(Box::new(worker_queue_jobs.clone()))
+            .expect("register worker_queue_jobs");
+
+        let worker_jobs_total = IntCounterVec::new(
+            Opts::new(
+                "worker_jobs_total",
+                "Background jobs run by this process by job type and outcome",
+            ),
+            &["job_type", "outcome"],
+        )
+        .expect("valid worker_jobs_total counter");
+        registry
+            .register(Box::new(worker_jobs_total.clone()))
+            .expect("register worker_jobs_total");
+
+        let worker_job_duration_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "worker_job_duration_seconds",
+                "Time each background job took by job type and outcome",
+            )
+            .buckets(vec![
+                0.05, 0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 300.0, 600.0, 1800.0, 3600.0,
+            ]),
+            &["job_type", "outcome"],
+        )
+        .expect("valid worker_job_duration_seconds histogram");
+        registry
+            .register(Box::new(worker_job_duration_seconds.clone()))
+            .expect("register worker_job_duration_seconds");
+
+        let warm_sandboxes = IntGaugeVec::new(
+            Opts::new("warm_sandboxes", "Pre-warmed sandboxes by pool and status"),
+            &["pool", "status"],
+        )
+        .expect("valid warm_sandboxes gauge");
+        registry
+            .register(Box::new(warm_sandboxes.clone()))
+            .expect("register warm_sandboxes");
+
+        let warm_sandbox_claims_total = IntCounterVec::new(
+            Opts::new(
+                "warm_sandbox_claims_total",
+                "Sandbox dispatches by whether a pre-warmed sandbox was claimed",
+            ),
+            &["result"],
+        )
+        .expect("valid warm_sandbox_claims_total counter");
+        registry
+            .register(Box::new(warm_sandbox_claims_total.clone()))
+            .expect("register warm_sandbox_claims_total");
+
+        let message_writer_buffer_depth = IntGauge::new(
+            "message_writer_buffer_depth",
+            "CLI messages read but not yet written to the database",
+        )
+        .expect("valid message_writer_buffer_depth gauge");
+        registry
+            .register(Box::new(message_writer_buffer_depth.clone()))
+            .expect("register message_writer_buffer_depth");
+
+        let message_writer_overflows_total = IntCounter::new(
+            "message_writer_overflows_total",
+            "Times a CLI reader waited for room in its full message buffer",
+        )
+        .expect("valid message_writer_overflows_total counter");
+        registry
+            .register(Box::new(message_writer_overflows_total.clone()))
+            .expect("register message_writer_overflows_total");
+
+        let message_insert_duration_seconds = Histogram::with_opts(
+            HistogramOpts::new(
+                "message_insert_duration_seconds",
+                "Time spent inserting each batch of CLI messages",
+            )
+            .buckets(vec![
+                0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+            ]),
+        )
+        .expect("valid message_insert_duration_seconds histogram");
+        registry
+            .register(Box::new(message_insert_duration_seconds.clone()))
+            .expect("register message_insert_duration_seconds");
+
+        let messages_filtered_total = IntCounterVec::new(
+            Opts::new(
+                "messages_filtered_total",
+                "CLI messages dropped or aggregated by an ingestion filter instead of stored",
+            ),
+            &["kind", "action"],
+        )
+        .expect("valid messages_filtered_total counter");
+        registry
+            .register(Box::new(messages_filtered_total.clone()))
+            .expect("register messages_filtered_total");
+
+        Self {
+            registry,
+            prompt_phase_duration_seconds,
+            integrity_orphans,
+            integrity_orphans_repaired_total,
+            pipeline_failures_total,
+            session_transitions_total,
+            cli_process_queue_depth,
+            cli_processes_running,
+            cli_process_peak_memory_bytes,
+            workspace_disk_bytes,
+            http_requests_total,
+            http_request_duration_seconds,
+            dlq_pending_entries,
+            dlq_oldest_pending_age_seconds,
+            dlq_entry_age_seconds,
+            prompt_queue_latency_seconds,
+            db_pool_connections,
+            db_pool_max_connections,
+            db_slow_queries_total,
+            history_encoding_fallbacks_total,
+            prompt_queue_pending,
+            prompt_queue_oldest_pending_age_seconds,
+            prompt_runs_in_flight,
+            worker_queue_jobs,
+            worker_jobs_total,
+            worker_job_duration_seconds,
+            warm_sandboxes,
+            warm_sandbox_claims_total,
+            message_writer_buffer_depth,
+            message_writer_overflows_total,
+            message_insert_duration_seconds,
+            messages_filtered_total,
+        }
+    }
+}
+
+/// Route label for requests that matched no route, so raw paths never become labels
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+/// Request start time, stored in the request-local cache
+struct RequestStart(Instant);
+
+/// Records `http_requests_total` and `http_request_duration_seconds` for every request.
+///
+/// Routes are labelled by their URI template (e.g. `/sessions/<id>`), not the requested path.
+pub struct HttpMetrics;
+
+#[rocket::async_trait]
+impl Fairing for HttpMetrics {
+    fn info(&self) -> Info {
+        Info {
+            name: "HTTP metrics",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, _: &mut Data<'_>) {
+        req.local_cache(|| RequestStart(Instant::now()));
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let elapsed = req.local_cache(|| RequestStart(Instant::now())).0.elapsed();
+        let method = req.method().as_str();
+        let route = req
+            .route()
+            .map(|r| r.uri.origin.path().as_str())
+            .unwrap_or(UNMATCHED_ROUTE);
+        let status_class = format!("{}xx", res.status().code / 100);
+
+        let metrics = get();
+        metrics
+            .http_requests_total
+            .with_label_values(&[method, route, &status_class])
+            .inc();
+        metrics
+            .http_request_duration_seconds
+            .with_label_values(&[method, route])
+            .observe(elapsed.as_secs_f64());
+    }
+}
+
+/// Runs a route's handler inside a `handler` span naming the route
+#[derive(Clone)]
+struct InstrumentedHandler {
+    inner: Box<dyn Handler>,
+    route: String,
+}
+
+#[rocket::async_trait]
+impl Handler for InstrumentedHandler {
+    async fn handle<'r>(&self, req: &'r Request<'_>, data: Data<'r>) -> route::Outcome<'r> {
+        let span = tracing::info_span!("handler", method = %req.method(), route = %self.route);
+        self.inner.handle(req, data).instrument(span).await
+    }
+}
+
+/// Wrap each route's handler so logs emitted while serving it, such as slow queries, carry the
+/// route they came from
+pub fn instrument_routes(routes: Vec<Route>) -> Vec<Route> {
+    routes
+        .into_iter()
+        .map(|mut route| {
+            let inner = route.handler.clone();
+            route.handler = Box::new(InstrumentedHandler {
+                inner,
+                route: route.uri.origin.path().to_string(),
+            });
+            route
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::local::blocking::Client;
+
+    #[get("/widgets/<id>")]
+    fn widget(id: u32) -> String {
+        id.to_string()
+    }
+
+    #[test]
+    fn test_http_metrics_label_route_templates() {
+        let rocket = rocket::build()
+            .attach(HttpMetrics)
+            .mount("/", routes![widget]);
+        let client = Client::tracked(rocket).expect("valid rocket instance");
+
+        let requests =
+            get()
+                .http_requests_total
+                .with_label_values(&["GET", "/widgets/<id>", "2xx"]);
+        let before = requests.get();
+        client.get("/widgets/1").dispatch();
+        client.get("/widgets/2").dispatch();
+        assert_eq!(requests.get(), before + 2);
+
+        let unmatched =
+            get()
+                .http_requests_total
+                .with_label_values(&["GET", UNMATCHED_ROUTE, "4xx"]);
+        let before = unmatched.get();
+        client.get("/nothing/here").dispatch();
+        assert_eq!(unmatched.get(), before + 1);
+    }
+}
@@ -0,0 +1,637 @@
+//! `prompt-backend replay`: run one prompt's outbox job against a recorded fixture.
+//!
+//! A fixture holds what a run saw from the outside: the repo and prompt, the sandbox's
+//! responses and the Claude CLI's output stream. `replay record` writes one from a prompt that
+//! ran, taking the stream from its stored messages or from the raw output log downloaded from
+//! its sandbox, and the capability probe's answer from the session. `replay run` creates a
+//! session and prompt for it, serves the sandbox and the GitHub API from a mock on localhost,
+//! puts a `claude` that prints the recorded stream on `PATH` and calls
+//! `outbox_publisher::process_outbox_job` once. The sandbox answers each request with the
+//! first unused recorded exchange of the same method and path, and every other request with a
+//! plain success, so fixtures only need the responses that matter for the case, e.g. a failing
+//! clone.
+//!
+//! The outcome is compared with the fixture's `expect`, and a mismatch fails the command, so
+//! fixtures double as regression tests of output parsing, status transitions and error paths.
+//! Sessions are owned by the `replay` user and deleted afterwards unless `--keep` is given.
+//! Refuses to run when `APP_ENV` is production.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use apalis::prelude::Data;
+use apalis_sql::postgres::PostgresStorage;
+use clap::Subcommand;
+use rocket::http::{ContentType, Status};
+use rocket::{Shutdown, State};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, PaginatorTrait, QueryFilter,
+    QueryOrder, Set,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::auth::AuthenticatedUser;
+use crate::bg_tasks::outbox_publisher::{self, OutboxContext, OutboxJob};
+use crate::config;
+use crate::entities::message::{self, Entity as Message};
+use crate::entities::prompt::{Entity as Prompt, PipelineErrorKind, PromptPriority};
+use crate::entities::session::{Entity as Session, UiStatus};
+use crate::handlers::sessions::{new_prompt, new_session};
+use crate::loadtest;
+use crate::services::message_blobs;
+use crate::services::sandbox_capabilities::{self, SandboxCapabilities};
+
+/// Owner of every session a replay creates
+const REPLAY_USER: &str = "replay";
+
+#[derive(Subcommand, Debug, Clone, PartialEq)]
+pub enum ReplayCommand {
+    /// Write a fixture from a prompt that ran
+    Record {
+        prompt: Uuid,
+        /// File to write the fixture to
+        #[arg(long)]
+        out: PathBuf,
+        /// Raw CLI output to replay instead of the stored messages, e.g. the run's
+        /// `claude_output_<prompt_id>.jsonl` downloaded from its sandbox
+        #[arg(long)]
+        output_log: Option<PathBuf>,
+    },
+    /// Run the outbox job against a fixture and check its outcome
+    Run {
+        fixture: PathBuf,
+        /// Port of the mock sandbox and GitHub API
+        #[arg(long, default_value_t = 18090)]
+        mock_port: u16,
+        /// Leave the session in the database instead of deleting it
+        #[arg(long)]
+        keep: bool,
+    },
+}
+
+/// A recorded run
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Fixture {
+    pub repo: String,
+    #[serde(default = "default_target_branch")]
+    pub target_branch: String,
+    /// The prompt's `data`
+    pub prompt: Value,
+    /// Sandbox responses, in the order the run asked for them
+    #[serde(default)]
+    pub sandbox: Vec<SandboxExchange>,
+    pub cli: CliOutput,
+    /// What the run should end with; fields left out are not checked
+    #[serde(default)]
+    pub expect: Outcome,
+}
+
+fn default_target_branch() -> String {
+    "main".to_string()
+}
+
+/// One sandbox request and the response it got
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SandboxExchange {
+    pub method: String,
+    /// Path under the sandbox API, e.g. `/v1/shell/exec`
+    pub path: String,
+    /// Only match requests whose body contains this, e.g. part of a command
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_contains: Option<String>,
+    #[serde(default = "default_status")]
+    pub status: u16,
+    /// Response body; a string is sent as plain text, as file downloads are
+    pub body: Value,
+}
+
+fn default_status() -> u16 {
+    200
+}
+
+/// What the Claude CLI printed and how it exited
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CliOutput {
+    /// Stdout lines, usually stream-json messages
+    pub stdout: Vec<String>,
+    #[serde(default)]
+    pub stderr: Vec<String>,
+    #[serde(default)]
+    pub exit_code: i32,
+}
+
+/// How a run ended
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Outcome {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ui_status: Option<UiStatus>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status_message: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_kind: Option<PipelineErrorKind>,
+    /// Messages stored for the prompt
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub messages: Option<u64>,
+}
+
+impl Outcome {
+    /// How `actual` differs from the fields set in this expectation
+    fn mismatches(&self, actual: &Outcome) -> Vec<String> {
+        fn check<T: PartialEq + std::fmt::Debug>(
+            field: &str,
+            expected: &Option<T>,
+            actual: &Option<T>,
+            out: &mut Vec<String>,
+        ) {
+            if expected.is_some() && expected != actual {
+                out.push(format!(
+                    "{}: expected {:?}, got {:?}",
+                    field, expected, actual
+                ));
+            }
+        }
+        let mut out = Vec::new();
+        check("ui_status", &self.ui_status, &actual.ui_status, &mut out);
+        check(
+            "status_message",
+            &self.status_message,
+            &actual.status_message,
+            &mut out,
+        );
+        check("exit_code", &self.exit_code, &actual.exit_code, &mut out);
+        check("error_kind", &self.error_kind, &actual.error_kind, &mut out);
+        check("messages", &self.messages, &actual.messages, &mut out);
+        out
+    }
+}
+
+/// The mock sandbox's state: recorded exchanges and the requests none of them matched
+#[derive(Debug)]
+struct ReplaySandbox {
+    exchanges: Vec<SandboxExchange>,
+    used: Mutex<Vec<bool>>,
+    unmatched: Mutex<Vec<String>>,
+}
+
+impl ReplaySandbox {
+    fn new(exchanges: Vec<SandboxExchange>) -> Self {
+        ReplaySandbox {
+            used: Mutex::new(vec![false; exchanges.len()]),
+            exchanges,
+            unmatched: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Status and body of the first unused exchange matching the request, or a success
+    fn respond(&self, method: &str, path: &str, body: &str) -> (u16, Value) {
+        let mut used = self.used.lock().unwrap_or_else(|e| e.into_inner());
+        let found = self.exchanges.iter().enumerate().position(|(i, e)| {
+            !used[i]
+                && e.method.eq_ignore_ascii_case(method)
+                && e.path == path
+                && e.request_contains
+                    .as_deref()
+                    .is_none_or(|needle| body.contains(needle))
+        });
+        match found {
+            Some(i) => {
+                used[i] = true;
+                (self.exchanges[i].status, self.exchanges[i].body.clone())
+            }
+            None => {
+                self.unmatched
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .push(format!("{} {}", method, path));
+                (200, loadtest::sandbox_ok_body())
+            }
+        }
+    }
+
+    /// Requests no exchange matched, answered with a success
+    fn unmatched(&self) -> Vec<String> {
+        self.unmatched
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Recorded exchanges the run never asked for
+    fn unused(&self) -> Vec<String> {
+        let used = self.used.lock().unwrap_or_else(|e| e.into_inner());
+        self.exchanges
+            .iter()
+            .zip(used.iter())
+            .filter(|(_, used)| !**used)
+            .map(|(e, _)| format!("{} {}", e.method, e.path))
+            .collect()
+    }
+}
+
+type MockResponse = (Status, (ContentType, String));
+
+fn mock_response(sandbox: &ReplaySandbox, method: &str, path: PathBuf, body: &str) -> MockResponse {
+    let path = format!("/{}", path.display());
+    let (status, body) = sandbox.respond(method, &path, body);
+    let status = Status::from_code(status).unwrap_or(Status::InternalServerError);
+    match body {
+        Value::String(text) => (status, (ContentType::Plain, text)),
+        body => (status, (ContentType::JSON, body.to_string())),
+    }
+}
+
+#[rocket::post("/sandbox/<path..>", data = "<body>")]
+fn replay_post(path: PathBuf, body: String, sandbox: &State<Arc<ReplaySandbox>>) -> MockResponse {
+    mock_response(sandbox, "POST", path, &body)
+}
+
+#[rocket::get("/sandbox/<path..>")]
+fn replay_get(path: PathBuf, sandbox: &State<Arc<ReplaySandbox>>) -> MockResponse {
+    mock_response(sandbox, "GET", path, "")
+}
+
+#[rocket::delete("/sandbox/<path..>")]
+fn replay_delete(path: PathBuf, sandbox: &State<Arc<ReplaySandbox>>) -> MockResponse {
+    mock_response(sandbox, "DELETE", path, "")
+}
+
+/// Serve the sandbox from `sandbox` and the GitHub API until the returned handle is notified
+async fn start_mock(port: u16, sandbox: Arc<ReplaySandbox>) -> anyhow::Result<Shutdown> {
+    let figment = rocket::Config::figment()
+        .merge(("address", "127.0.0.1"))
+        .merge(("port", port))
+        .merge(("log_level", rocket::config::LogLevel::Off))
+        // Prompt uploads carry the whole rendered history
+        .merge(("limits.string", "64MiB"));
+    let rocket = rocket::custom(figment)
+        .manage(sandbox)
+        .mount(
+            "/",
+            rocket::routes![
+                replay_post,
+                replay_get,
+                replay_delete,
+                loadtest::mock_github
+            ],
+        )
+        .ignite()
+        .await?;
+    let shutdown = rocket.shutdown();
+    tokio::spawn(async move {
+        if let Err(e) = rocket.launch().await {
+            eprintln!("Mock server failed: {}", e);
+        }
+    });
+
+    let url = format!("http://127.0.0.1:{}/github/ready", port);
+    for _ in 0..50 {
+        if reqwest::get(&url).await.is_ok() {
+            return Ok(shutdown);
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    anyhow::bail!("Mock server did not start on port {}", port)
+}
+
+/// A `claude` that prints the recorded streams from `dir` and exits with the recorded code
+fn fake_cli_script(dir: &Path, exit_code: i32) -> String {
+    format!(
+        "#!/bin/sh\ncat '{dir}/stdout.jsonl'\ncat '{dir}/stderr.txt' >&2\nexit {exit_code}\n",
+        dir = dir.display(),
+        exit_code = exit_code,
+    )
+}
+
+fn install_fake_cli(dir: &Path, cli: &CliOutput) -> anyhow::Result<()> {
+    let lines = |lines: &[String]| {
+        lines
+            .iter()
+            .map(|line| format!("{}\n", line))
+            .collect::<String>()
+    };
+    std::fs::write(dir.join("stdout.jsonl"), lines(&cli.stdout))?;
+    std::fs::write(dir.join("stderr.txt"), lines(&cli.stderr))?;
+    loadtest::install_fake_cli(dir, &fake_cli_script(dir, cli.exit_code))
+}
+
+/// The run's outcome as stored
+async fn outcome(
+    db: &DatabaseConnection,
+    session_id: Uuid,
+    prompt_id: Uuid,
+) -> anyhow::Result<Outcome> {
+    let session = Session::find_by_id(session_id).one(db).await?;
+    let prompt = Prompt::find_by_id(prompt_id).one(db).await?;
+    let messages = Message::find()
+        .filter(message::Column::PromptId.eq(prompt_id))
+        .count(db)
+        .await?;
+    Ok(Outcome {
+        ui_status: session.as_ref().map(|s| s.ui_status.clone()),
+        status_message: session.and_then(|s| s.status_message),
+        exit_code: prompt.as_ref().and_then(|p| p.exit_code),
+        error_kind: prompt.and_then(|p| p.error_kind),
+        messages: Some(messages),
+    })
+}
+
+async fn replay(
+    db: &DatabaseConnection,
+    database_url: &str,
+    fixture_path: &Path,
+    mock_port: u16,
+    keep: bool,
+) -> anyhow::Result<()> {
+    let fixture: Fixture = serde_json::from_str(&std::fs::read_to_string(fixture_path)?)?;
+
+    // GitHub calls go to the mock, and `claude` prints the recorded stream
+    let mock_url = format!("http://127.0.0.1:{}", mock_port);
+    std::env::set_var("GITHUB_API_URL", format!("{}/github", mock_url));
+    std::env::set_var("GITHUB_TOKEN", "replay");
+    let cli_dir = tempfile::tempdir()?;
+    install_fake_cli(cli_dir.path(), &fixture.cli)?;
+    let sandbox = Arc::new(ReplaySandbox::new(fixture.sandbox.clone()));
+    let shutdown = start_mock(mock_port, sandbox.clone()).await?;
+
+    // The session already holds the mock sandbox, as after the prompt poller borrowed one
+    let user = AuthenticatedUser {
+        user_id: REPLAY_USER.to_string(),
+        email: None,
+        name: None,
+        roles: Vec::new(),
+        org_id: None,
+    };
+    let session_id = Uuid::new_v4();
+    let mut new = new_session(
+        session_id,
+        &user,
+        None,
+        &fixture.repo,
+        &fixture.target_branch,
+        None,
+        None,
+    );
+    new.title = Set(Some(format!("Replay of {}", fixture_path.display())));
+    new.title_pending = Set(false);
+    new.ui_status = Set(UiStatus::InProgress);
+    new.sbx_config = Set(Some(serde_json::json!({
+        "item": {
+            "api_url": format!("{}/sandbox", mock_url),
+            "mcp_json_string": "{}",
+        },
+        "borrow_token": "replay",
+    })));
+    let session = new.insert(db).await?;
+    let prompt_id = Uuid::new_v4();
+    let prompt = new_prompt(
+        prompt_id,
+        session_id,
+        fixture.prompt.clone(),
+        PromptPriority::Normal,
+    )
+    .insert(db)
+    .await?;
+
+    let pool = apalis_sql::postgres::PgPool::connect(database_url).await?;
+    let ctx = OutboxContext {
+        db: db.clone(),
+        storage: PostgresStorage::new(pool),
+    };
+    let job = OutboxJob::new(&session, &prompt, "replay");
+    let job_result = outbox_publisher::process_outbox_job(job, Data::new(ctx)).await;
+    let actual = outcome(db, session_id, prompt_id).await?;
+    shutdown.notify();
+
+    println!("Replayed {}", fixture_path.display());
+    match &job_result {
+        Ok(()) => println!("  job               succeeded"),
+        Err(e) => println!("  job               failed: {}", e),
+    }
+    println!("  outcome           {}", serde_json::to_string(&actual)?);
+    let unmatched = sandbox.unmatched();
+    if !unmatched.is_empty() {
+        println!("  default answers   {}", unmatched.join(", "));
+    }
+    let unused = sandbox.unused();
+    if !unused.is_empty() {
+        println!("  unused exchanges  {}", unused.join(", "));
+    }
+
+    if keep {
+        println!("  session           {}", session_id);
+    } else {
+        // The prompt and its messages go with the session
+        Session::delete_by_id(session_id).exec(db).await?;
+    }
+
+    let mismatches = fixture.expect.mismatches(&actual);
+    if !mismatches.is_empty() {
+        anyhow::bail!(
+            "Replay of {} did not match its expectations:\n  {}",
+            fixture_path.display(),
+            mismatches.join("\n  ")
+        );
+    }
+    Ok(())
+}
+
+/// Stdout lines of a run: the raw output log when given, else the stored messages in order
+async fn recorded_stdout(
+    db: &DatabaseConnection,
+    prompt_id: Uuid,
+    output_log: Option<&Path>,
+) -> anyhow::Result<Vec<String>> {
+    if let Some(path) = output_log {
+        return Ok(std::fs::read_to_string(path)?
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(str::to_string)
+            .collect());
+    }
+    let messages = Message::find()
+        .filter(message::Column::PromptId.eq(prompt_id))
+        .order_by_asc(message::Column::CreatedAt)
+        .all(db)
+        .await?;
+    let mut lines = Vec::with_capacity(messages.len());
+    for model in &messages {
+        let data = message_blobs::resolve(model)
+            .await
+            .map_err(|e| anyhow::anyhow!(e))?;
+        lines.push(serde_json::to_string(&data)?);
+    }
+    Ok(lines)
+}
+
+/// The capability probe's answer in the session's sandbox, when it was probed
+fn probe_exchange(capabilities: &SandboxCapabilities) -> SandboxExchange {
+    let mut body = loadtest::sandbox_ok_body();
+    body["data"]["output"] = Value::String(capabilities.probe_output());
+    SandboxExchange {
+        method: "POST".to_string(),
+        path: "/v1/shell/exec".to_string(),
+        request_contains: Some(sandbox_capabilities::PROBE_MARKER.to_string()),
+        status: 200,
+        body,
+    }
+}
+
+async fn record(
+    db: &DatabaseConnection,
+    prompt_id: Uuid,
+    out: &Path,
+    output_log: Option<&Path>,
+) -> anyhow::Result<()> {
+    let (prompt, session) = Prompt::find_by_id(prompt_id)
+        .find_also_related(Session)
+        .one(db)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("Prompt {} not found", prompt_id))?;
+    let session = session.ok_or_else(|| anyhow::anyhow!("Session of {} not found", prompt_id))?;
+    if prompt.completed_at.is_none() && prompt.exit_code.is_none() {
+        anyhow::bail!("Prompt {} has not run yet", prompt_id);
+    }
+
+    let stdout = recorded_stdout(db, prompt_id, output_log).await?;
+    let messages = stdout
+        .iter()
+        .filter(|line| serde_json::from_str::<Value>(line).is_ok())
+        .count() as u64;
+    let fixture = Fixture {
+        repo: session.repo.unwrap_or_default(),
+        target_branch: session.target_branch.unwrap_or_else(default_target_branch),
+        prompt: prompt.data,
+        sandbox: SandboxCapabilities::from_json(session.sandbox_capabilities.as_ref())
+            .map(|c| vec![probe_exchange(&c)])
+            .unwrap_or_default(),
+        cli: CliOutput {
+            stdout,
+            stderr: prompt
+                .stderr
+                .as_deref()
+                .map(|s| s.lines().map(str::to_string).collect())
+                .unwrap_or_default(),
+            exit_code: prompt.exit_code.unwrap_or(0),
+        },
+        expect: Outcome {
+            exit_code: prompt.exit_code,
+            error_kind: prompt.error_kind,
+            messages: Some(messages),
+            ..Outcome::default()
+        },
+    };
+    std::fs::write(out, serde_json::to_string_pretty(&fixture)?)?;
+    println!(
+        "Recorded prompt {} ({} output lines) to {}",
+        prompt_id,
+        fixture.cli.stdout.len(),
+        out.display()
+    );
+    Ok(())
+}
+
+pub async fn run_replay(
+    db: &DatabaseConnection,
+    database_url: &str,
+    command: ReplayCommand,
+) -> anyhow::Result<()> {
+    match command {
+        ReplayCommand::Record {
+            prompt,
+            out,
+            output_log,
+        } => record(db, prompt, &out, output_log.as_deref()).await,
+        ReplayCommand::Run {
+            fixture,
+            mock_port,
+            keep,
+        } => {
+            if config::is_production(std::env::var("APP_ENV").ok().as_deref()) {
+                anyhow::bail!("Refusing to replay because APP_ENV is production");
+            }
+            replay(db, database_url, &fixture, mock_port, keep).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLONE_FAILS: &str = include_str!("../tests/fixtures/replay/clone_fails.json");
+    const CLI_EXITS_NONZERO: &str = include_str!("../tests/fixtures/replay/cli_exits_nonzero.json");
+
+    #[test]
+    fn test_fixtures_parse() {
+        let fixture: Fixture = serde_json::from_str(CLONE_FAILS).unwrap();
+        assert_eq!(fixture.target_branch, "main");
+        assert_eq!(fixture.sandbox[0].status, 200);
+        assert_eq!(
+            fixture.expect.error_kind,
+            Some(PipelineErrorKind::CloneFailed)
+        );
+
+        let fixture: Fixture = serde_json::from_str(CLI_EXITS_NONZERO).unwrap();
+        assert_eq!(fixture.cli.exit_code, 1);
+        assert!(fixture
+            .cli
+            .stdout
+            .iter()
+            .all(|line| serde_json::from_str::<Value>(line).is_ok()));
+        assert_eq!(
+            fixture.expect.messages,
+            Some(fixture.cli.stdout.len() as u64)
+        );
+    }
+
+    #[test]
+    fn test_sandbox_answers_exchanges_in_order_then_defaults() {
+        let exchange = |contains: Option<&str>, output: &str| SandboxExchange {
+            method: "POST".to_string(),
+            path: "/v1/shell/exec".to_string(),
+            request_contains: contains.map(str::to_string),
+            status: 200,
+            body: serde_json::json!({ "data": { "output": output } }),
+        };
+        let sandbox = ReplaySandbox::new(vec![
+            exchange(Some("git clone"), "cloned"),
+            exchange(None, "first"),
+            exchange(None, "second"),
+        ]);
+
+        let output = |method: &str, body: &str| {
+            sandbox.respond(method, "/v1/shell/exec", body).1["data"]["output"].clone()
+        };
+        assert_eq!(output("POST", "ls"), "first");
+        assert_eq!(output("post", "git clone x"), "cloned");
+        assert_eq!(output("POST", "git clone y"), "second");
+        assert_eq!(output("POST", "ls"), "ahead 0");
+        assert_eq!(sandbox.unmatched(), vec!["POST /v1/shell/exec"]);
+        assert!(sandbox.unused().is_empty());
+    }
+
+    #[test]
+    fn test_outcome_mismatches_only_check_expected_fields() {
+        let expected = Outcome {
+            ui_status: Some(UiStatus::NeedsReview),
+            messages: Some(3),
+            ..Outcome::default()
+        };
+        let actual = Outcome {
+            ui_status: Some(UiStatus::NeedsReview),
+            exit_code: Some(0),
+            messages: Some(2),
+            ..Outcome::default()
+        };
+        assert_eq!(
+            expected.mismatches(&actual),
+            vec!["messages: expected Some(3), got Some(2)"]
+        );
+        assert!(Outcome::default().mismatches(&actual).is_empty());
+    }
+}
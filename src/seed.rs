@@ -0,0 +1,254 @@
+//! `prompt-backend seed`: sessions of fake users in every state, for frontend development and
+//! load testing against a local database.
+//!
+//! Rows are written straight to the database with the same constructors the handlers use.
+//! Pending sessions are real work for the prompt poller, so a running server with a sandbox
+//! allocator will try to run them. Refuses to run when `APP_ENV` is production.
+
+use chrono::Utc;
+use clap::Args;
+use sea_orm::{ActiveModelTrait, DatabaseConnection, NotSet, Set};
+use uuid::Uuid;
+
+use crate::auth::AuthenticatedUser;
+use crate::config;
+use crate::entities::message;
+use crate::entities::prompt::PromptPriority;
+use crate::entities::session::UiStatus;
+use crate::handlers::sessions::{new_prompt, new_session};
+use crate::services::conversation::Conversation;
+use crate::services::dead_letter_queue::{self, IP_RETURN_TASK_TYPE};
+
+const REPOS: [&str; 4] = ["acme/api", "acme/web", "acme/mobile", "acme/infra"];
+
+const TASKS: [&str; 6] = [
+    "Add CSV export to the reports page",
+    "Fix flaky login test",
+    "Upgrade the HTTP client and fix deprecations",
+    "Document the deployment process",
+    "Add pagination to the orders endpoint",
+    "Speed up the search query",
+];
+
+#[derive(Args, Debug, Clone, PartialEq)]
+pub struct SeedArgs {
+    /// Fake users to create sessions for, `seed-user-1` and so on
+    #[arg(long, default_value_t = 3)]
+    pub users: usize,
+    /// Seed these user ids instead of fake ones, e.g. the `DEV_AUTH_USER_ID`; repeatable
+    #[arg(long = "user")]
+    pub user_ids: Vec<String>,
+    /// Sessions per user, spread over the states
+    #[arg(long, default_value_t = 8)]
+    pub sessions_per_user: usize,
+    /// Messages written for each prompt that ran
+    #[arg(long, default_value_t = 6)]
+    pub messages_per_prompt: usize,
+}
+
+/// The state a seeded session is left in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SeedState {
+    Pending,
+    InProgress,
+    NeedsReview,
+    /// In review with an IP return that failed into the dead letter queue
+    ReturnFailed,
+    Archived,
+}
+
+impl SeedState {
+    const ALL: [SeedState; 5] = [
+        SeedState::Pending,
+        SeedState::InProgress,
+        SeedState::NeedsReview,
+        SeedState::ReturnFailed,
+        SeedState::Archived,
+    ];
+
+    /// States cycle so every state appears once a user has as many sessions as there are states
+    fn for_index(index: usize) -> SeedState {
+        Self::ALL[index % Self::ALL.len()]
+    }
+
+    fn ui_status(self) -> UiStatus {
+        match self {
+            SeedState::Pending => UiStatus::Pending,
+            SeedState::InProgress => UiStatus::InProgress,
+            SeedState::NeedsReview | SeedState::ReturnFailed => UiStatus::NeedsReview,
+            SeedState::Archived => UiStatus::Archived,
+        }
+    }
+}
+
+fn fake_user(user_id: String) -> AuthenticatedUser {
+    AuthenticatedUser {
+        user_id,
+        email: None,
+        name: None,
+        roles: Vec::new(),
+        org_id: None,
+    }
+}
+
+/// A message in the shape the Claude CLI streams
+fn fake_message(index: usize, total: usize) -> serde_json::Value {
+    if index + 1 == total {
+        serde_json::json!({
+            "type": "result",
+            "subtype": "success",
+            "result": "Done. The changes are committed and pushed.",
+        })
+    } else if index.is_multiple_of(2) {
+        serde_json::json!({
+            "type": "assistant",
+            "message": {"content": [{"type": "text", "text": format!("Working on step {}.", index + 1)}]},
+        })
+    } else {
+        serde_json::json!({
+            "type": "assistant",
+            "message": {"content": [{
+                "type": "tool_use",
+                "name": "Bash",
+                "input": {"command": "cargo test"},
+            }]},
+        })
+    }
+}
+
+/// Create the session of `user` in `state`, with its prompt and the messages it produced
+async fn seed_session(
+    db: &DatabaseConnection,
+    user: &AuthenticatedUser,
+    index: usize,
+    state: SeedState,
+    messages_per_prompt: usize,
+) -> anyhow::Result<()> {
+    let session_id = Uuid::new_v4();
+    let task = TASKS[fastrand::usize(..TASKS.len())];
+    let repo = REPOS[fastrand::usize(..REPOS.len())];
+    let created_at = Utc::now() - chrono::Duration::minutes((index * 37) as i64);
+    let holds_sandbox = matches!(state, SeedState::InProgress | SeedState::ReturnFailed);
+
+    let mut session = new_session(session_id, user, None, repo, "main", None, None);
+    session.title = Set(Some(task.to_string()));
+    session.title_pending = Set(false);
+    session.branch = Set(Some(format!(
+        "claude/seed-{}",
+        &session_id.to_string()[..8]
+    )));
+    session.ui_status = Set(state.ui_status());
+    session.created_at = Set(created_at.into());
+    session.updated_at = Set(created_at.into());
+    if holds_sandbox {
+        session.sbx_config = Set(Some(serde_json::json!({
+            "item": {"api_url": "http://localhost:8080", "mcp_json_string": "{}"},
+            "borrow_token": format!("seed-{}", session_id),
+        })));
+    }
+    session.insert(db).await?;
+
+    let prompt_id = Uuid::new_v4();
+    let mut prompt = new_prompt(
+        prompt_id,
+        session_id,
+        serde_json::to_value(Conversation::from_text(task))?,
+        PromptPriority::Normal,
+    );
+    prompt.created_at = Set(created_at.into());
+    prompt.updated_at = Set(created_at.into());
+    if state != SeedState::Pending {
+        prompt.started_at = Set(Some(created_at.into()));
+    }
+    if !matches!(state, SeedState::Pending | SeedState::InProgress) {
+        prompt.completed_at = Set(Some((created_at + chrono::Duration::minutes(12)).into()));
+        prompt.progress = Set(100);
+        prompt.exit_code = Set(Some(0));
+    } else if state == SeedState::InProgress {
+        prompt.progress = Set(40);
+    }
+    prompt.insert(db).await?;
+
+    if state != SeedState::Pending {
+        // A run still in progress has only written part of its output
+        let count = match state {
+            SeedState::InProgress => messages_per_prompt / 2,
+            _ => messages_per_prompt,
+        };
+        for i in 0..count {
+            message::ActiveModel {
+                id: Set(Uuid::new_v4()),
+                prompt_id: Set(prompt_id),
+                data: Set(fake_message(i, messages_per_prompt)),
+                blob_key: Set(None),
+                created_at: NotSet,
+                updated_at: NotSet,
+                seq: NotSet,
+            }
+            .insert(db)
+            .await?;
+        }
+    }
+
+    if state == SeedState::ReturnFailed {
+        dead_letter_queue::insert_dlq_entry(
+            db,
+            IP_RETURN_TASK_TYPE,
+            session_id,
+            None,
+            dead_letter_queue::MAX_RETRY_COUNT,
+            "IP allocator returned 503 Service Unavailable",
+            created_at.into(),
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+pub async fn run_seed(db: &DatabaseConnection, args: SeedArgs) -> anyhow::Result<()> {
+    if config::is_production(std::env::var("APP_ENV").ok().as_deref()) {
+        anyhow::bail!("Refusing to seed fake data because APP_ENV is production");
+    }
+
+    let user_ids = if args.user_ids.is_empty() {
+        (1..=args.users)
+            .map(|i| format!("seed-user-{}", i))
+            .collect()
+    } else {
+        args.user_ids
+    };
+
+    let mut counts = [0usize; SeedState::ALL.len()];
+    for user_id in &user_ids {
+        let user = fake_user(user_id.clone());
+        for index in 0..args.sessions_per_user {
+            let state = SeedState::for_index(index);
+            seed_session(db, &user, index, state, args.messages_per_prompt).await?;
+            counts[index % SeedState::ALL.len()] += 1;
+        }
+    }
+
+    println!(
+        "Seeded {} sessions for {} users:",
+        user_ids.len() * args.sessions_per_user,
+        user_ids.len()
+    );
+    for (state, count) in SeedState::ALL.iter().zip(counts) {
+        println!("  {:<14} {}", format!("{:?}", state), count);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_states_cycle() {
+        let states: Vec<SeedState> = (0..6).map(SeedState::for_index).collect();
+        assert_eq!(&states[..5], &SeedState::ALL);
+        assert_eq!(states[5], SeedState::Pending);
+        assert_eq!(SeedState::ReturnFailed.ui_status(), UiStatus::NeedsReview);
+    }
+}
@@ -0,0 +1,187 @@
+//! One-call operational overview for the admin dashboard.
+//!
+//! Everything comes from two aggregate queries: one pass over `session` grouped by status, and
+//! one over the prompts started in the recent window with the warm pool and pending DLQ counts
+//! as scalar subqueries. Worker staleness is read from this process's worker registry.
+
+use chrono::{DateTime, Utc};
+use sea_orm::{
+    ActiveEnum, DatabaseConnection, DbBackend, DbErr, FromQueryResult, Iterable, Statement,
+};
+
+use crate::bg_tasks::worker_registry::{self, WorkerStatus};
+use crate::entities::dead_letter_queue::DlqStatus;
+use crate::entities::prompt::PipelineErrorKind;
+use crate::entities::session::UiStatus;
+use crate::services::sandbox_queue::queued_statuses;
+
+/// How far back runs count towards the recent failure rate
+pub const RECENT_WINDOW: chrono::Duration = chrono::Duration::hours(1);
+
+/// Sessions of one status
+#[derive(Debug, Clone, FromQueryResult)]
+pub struct StatusCount {
+    pub ui_status: UiStatus,
+    /// Sessions that are not deleted
+    pub sessions: i64,
+    /// Sessions, deleted or not, still holding a borrowed sandbox IP
+    pub holding_ip: i64,
+}
+
+#[derive(Debug, Clone, Default, FromQueryResult)]
+struct Totals {
+    warm_sandboxes: i64,
+    dlq_pending: i64,
+    runs_completed: i64,
+    runs_failed: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Overview {
+    /// Every status, including those without sessions
+    pub by_status: Vec<StatusCount>,
+    /// Sandboxes borrowed ahead of demand for the warm pool
+    pub warm_sandboxes: i64,
+    pub dlq_pending: i64,
+    /// Runs started in the recent window that completed
+    pub runs_completed: i64,
+    /// Runs started in the recent window that failed, not counting cancellations
+    pub runs_failed: i64,
+    pub workers: Vec<WorkerStatus>,
+    pub collected_at: DateTime<Utc>,
+}
+
+impl Overview {
+    /// Sessions queued for a sandbox or running
+    pub fn active_sessions(&self) -> i64 {
+        self.by_status
+            .iter()
+            .filter(|c| {
+                c.ui_status == UiStatus::InProgress || queued_statuses().contains(&c.ui_status)
+            })
+            .map(|c| c.sessions)
+            .sum()
+    }
+
+    /// Sandbox IPs borrowed from the allocator, by sessions and the warm pool
+    pub fn borrowed_ips(&self) -> i64 {
+        self.by_status.iter().map(|c| c.holding_ip).sum::<i64>() + self.warm_sandboxes
+    }
+
+    /// Share of finished recent runs that failed, None when none finished
+    pub fn failure_rate(&self) -> Option<f64> {
+        let finished = self.runs_completed + self.runs_failed;
+        (finished > 0).then(|| self.runs_failed as f64 / finished as f64)
+    }
+
+    pub fn stale_workers(&self) -> Vec<&WorkerStatus> {
+        self.workers
+            .iter()
+            .filter(|w| w.is_stale(self.collected_at))
+            .collect()
+    }
+}
+
+async fn sessions_by_status(db: &DatabaseConnection) -> Result<Vec<StatusCount>, DbErr> {
+    let counts = StatusCount::find_by_statement(Statement::from_string(
+        DbBackend::Postgres,
+        r#"SELECT ui_status,
+                  COUNT(*) FILTER (WHERE deleted_at IS NULL)::bigint AS sessions,
+                  COUNT(*) FILTER (WHERE sbx_config IS NOT NULL)::bigint AS holding_ip
+           FROM session
+           GROUP BY ui_status"#,
+    ))
+    .all(db)
+    .await?;
+
+    // Statuses without sessions report 0 so the dashboard's series do not disappear
+    Ok(UiStatus::iter()
+        .map(|status| {
+            counts
+                .iter()
+                .find(|c| c.ui_status == status)
+                .cloned()
+                .unwrap_or(StatusCount {
+                    ui_status: status,
+                    sessions: 0,
+                    holding_ip: 0,
+                })
+        })
+        .collect())
+}
+
+async fn totals(db: &DatabaseConnection, since: DateTime<Utc>) -> Result<Totals, DbErr> {
+    Ok(Totals::find_by_statement(Statement::from_sql_and_values(
+        DbBackend::Postgres,
+        r#"SELECT (SELECT COUNT(*) FROM warm_sandbox)::bigint AS warm_sandboxes,
+                  (SELECT COUNT(*) FROM dead_letter_queue WHERE status = $1)::bigint AS dlq_pending,
+                  COUNT(*) FILTER (WHERE completed_at IS NOT NULL)::bigint AS runs_completed,
+                  COUNT(*) FILTER (
+                      WHERE completed_at IS NULL AND error_kind IS NOT NULL AND error_kind <> $2
+                  )::bigint AS runs_failed
+           FROM prompt
+           WHERE started_at > $3"#,
+        [
+            DlqStatus::Pending.to_value().into(),
+            PipelineErrorKind::Cancelled.to_value().into(),
+            since.into(),
+        ],
+    ))
+    .one(db)
+    .await?
+    .unwrap_or_default())
+}
+
+pub async fn collect(db: &DatabaseConnection) -> Result<Overview, DbErr> {
+    let collected_at = Utc::now();
+    let by_status = sessions_by_status(db).await?;
+    let totals = totals(db, collected_at - RECENT_WINDOW).await?;
+    Ok(Overview {
+        by_status,
+        warm_sandboxes: totals.warm_sandboxes,
+        dlq_pending: totals.dlq_pending,
+        runs_completed: totals.runs_completed,
+        runs_failed: totals.runs_failed,
+        workers: worker_registry::snapshot(),
+        collected_at,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_derived_totals() {
+        let count = |ui_status, sessions, holding_ip| StatusCount {
+            ui_status,
+            sessions,
+            holding_ip,
+        };
+        let overview = Overview {
+            by_status: vec![
+                count(UiStatus::Pending, 2, 0),
+                count(UiStatus::WaitingForSandbox, 1, 0),
+                count(UiStatus::InProgress, 3, 3),
+                count(UiStatus::NeedsReview, 5, 4),
+                count(UiStatus::Archived, 9, 1),
+            ],
+            warm_sandboxes: 2,
+            dlq_pending: 0,
+            runs_completed: 3,
+            runs_failed: 1,
+            workers: Vec::new(),
+            collected_at: Utc::now(),
+        };
+        assert_eq!(overview.active_sessions(), 6);
+        assert_eq!(overview.borrowed_ips(), 10);
+        assert_eq!(overview.failure_rate(), Some(0.25));
+
+        let idle = Overview {
+            runs_completed: 0,
+            runs_failed: 0,
+            ..overview
+        };
+        assert_eq!(idle.failure_rate(), None);
+    }
+}
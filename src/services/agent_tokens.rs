@@ -0,0 +1,154 @@
+//! Per-run tokens letting the agent inside a sandbox, or the MCP servers it calls, write to its
+//! session without a user token.
+//!
+//! Each run that starts while `AGENT_CALLBACK_URL` is configured gets a fresh token, written to
+//! an env file in the sandbox alongside the callback URLs and session id. It is only accepted by
+//! the `/internal/sessions/<id>/...` endpoints of that session: status updates and message
+//! batches. Only a SHA-256 hash of the token is stored on the session. Issuing a new one
+//! invalidates the previous run's, the session leaving InProgress revokes it, and it expires
+//! `AGENT_TOKEN_TTL_SECS` after issue in any case.
+
+use chrono::{DateTime, Utc};
+use sea_orm::sea_query::Expr;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter, Set,
+};
+
+use crate::config;
+use crate::entities::session::{self, Entity as Session, Model as SessionModel};
+use crate::handlers::webhooks::secrets_match;
+use crate::services::hashing;
+
+/// Where the agent env file is written in the sandbox
+pub const ENV_FILE: &str = "/home/gem/.prompt-backend-agent.env";
+
+fn hash(token: &str) -> String {
+    hashing::sha256_hex(token)
+}
+
+/// A token handed to a run
+pub struct AgentToken {
+    pub token: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Issue a new token for `session_id`, replacing any earlier one
+pub async fn issue(db: &DatabaseConnection, session_id: uuid::Uuid) -> Result<AgentToken, DbErr> {
+    let token = format!(
+        "{}{}",
+        uuid::Uuid::new_v4().simple(),
+        uuid::Uuid::new_v4().simple()
+    );
+    let expires_at = Utc::now()
+        + chrono::Duration::from_std(config::get().agent_token_ttl)
+            .unwrap_or(chrono::Duration::MAX);
+    session::ActiveModel {
+        id: Set(session_id),
+        agent_token_hash: Set(Some(hash(&token))),
+        agent_token_expires_at: Set(Some(expires_at.into())),
+        ..Default::default()
+    }
+    .update(db)
+    .await?;
+    Ok(AgentToken { token, expires_at })
+}
+
+/// Stop accepting the session's agent token
+pub async fn revoke(db: &DatabaseConnection, session_id: uuid::Uuid) -> Result<(), DbErr> {
+    Session::update_many()
+        .col_expr(
+            session::Column::AgentTokenHash,
+            Expr::value(Option::<String>::None),
+        )
+        .col_expr(
+            session::Column::AgentTokenExpiresAt,
+            Expr::value(Option::<DateTime<Utc>>::None),
+        )
+        .filter(session::Column::Id.eq(session_id))
+        .filter(session::Column::AgentTokenHash.is_not_null())
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+/// Whether `token` is the session's current, unexpired agent token
+pub fn verify(session: &SessionModel, token: &str) -> bool {
+    token_valid(
+        session.agent_token_hash.as_deref(),
+        session
+            .agent_token_expires_at
+            .map(|at| at.with_timezone(&Utc)),
+        token,
+        Utc::now(),
+    )
+}
+
+/// Tokens issued before expiry was recorded have none and stay valid until revoked
+fn token_valid(
+    expected_hash: Option<&str>,
+    expires_at: Option<DateTime<Utc>>,
+    token: &str,
+    now: DateTime<Utc>,
+) -> bool {
+    expected_hash.is_some_and(|expected| secrets_match(expected, &hash(token)))
+        && expires_at.is_none_or(|at| now < at)
+}
+
+/// Contents of the sandbox env file for a run of `prompt_id`
+pub fn env_file(
+    callback_url: &str,
+    session_id: uuid::Uuid,
+    prompt_id: uuid::Uuid,
+    token: &AgentToken,
+) -> String {
+    let base = format!("{}/internal/sessions/{}", callback_url, session_id);
+    format!(
+        "PROMPT_BACKEND_STATUS_URL={base}/status\n\
+         PROMPT_BACKEND_MESSAGES_URL={base}/messages\n\
+         PROMPT_BACKEND_SESSION_ID={session_id}\n\
+         PROMPT_BACKEND_PROMPT_ID={prompt_id}\n\
+         PROMPT_BACKEND_AGENT_TOKEN={}\n\
+         PROMPT_BACKEND_AGENT_TOKEN_EXPIRES_AT={}\n",
+        token.token,
+        token.expires_at.to_rfc3339(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_valid() {
+        let stored = hash("t0ken");
+        let now = Utc::now();
+        let later = now + chrono::Duration::minutes(5);
+        assert_eq!(stored.len(), 64);
+        assert!(token_valid(Some(&stored), Some(later), "t0ken", now));
+        assert!(token_valid(Some(&stored), None, "t0ken", now));
+        assert!(!token_valid(Some(&stored), Some(later), "t0kem", now));
+        assert!(!token_valid(None, Some(later), "t0ken", now));
+        assert!(!token_valid(Some(&stored), Some(now), "t0ken", later));
+    }
+
+    #[test]
+    fn test_env_file() {
+        let session_id = uuid::Uuid::nil();
+        let prompt_id = uuid::Uuid::from_u128(7);
+        let token = AgentToken {
+            token: "abc".to_string(),
+            expires_at: Utc::now(),
+        };
+        let env = env_file("https://api.example.com", session_id, prompt_id, &token);
+        assert!(env.contains(&format!(
+            "PROMPT_BACKEND_STATUS_URL=https://api.example.com/internal/sessions/{}/status\n",
+            session_id
+        )));
+        assert!(env.contains(&format!(
+            "PROMPT_BACKEND_MESSAGES_URL=https://api.example.com/internal/sessions/{}/messages\n",
+            session_id
+        )));
+        assert!(env.contains(&format!("PROMPT_BACKEND_PROMPT_ID={}\n", prompt_id)));
+        assert!(env.contains("PROMPT_BACKEND_AGENT_TOKEN=abc\n"));
+    }
+}
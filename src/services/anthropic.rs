@@ -1,5 +1,15 @@
+use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
 use std::env;
+use std::time::Duration;
+use tracing::warn;
+
+use crate::backoff::jittered_backoff;
+use crate::services::http_client;
+
+/// Backoff between retries of a rate limited or overloaded call
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(1);
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(20);
 
 #[derive(Debug, Serialize)]
 struct AnthropicRequest {
@@ -24,95 +34,129 @@ struct ContentBlock {
     text: String,
 }
 
-pub async fn generate_session_title(
+/// Title and branch slug generated for a session
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct SessionNaming {
+    pub title: String,
+    /// Kebab-case description of the work, without the `claude/` prefix or session suffix
+    #[serde(rename = "branch")]
+    pub branch_slug: String,
+}
+
+impl SessionNaming {
+    /// Full branch name for `session_id`: `claude/<slug>-<first 24 chars of the id>`
+    pub fn branch_name(&self, session_id: &str) -> String {
+        format!("claude/{}-{}", self.branch_slug, &session_id[..24])
+    }
+}
+
+/// Generate a session's title and branch slug from its first prompt in a single API call,
+/// retried up to `max_retries` times when rate limited or overloaded
+pub async fn generate_session_naming(
     _git_repo: &str,
     _target_branch: &str,
     prompt: &str,
-) -> Result<String, String> {
-    let api_key = env::var("ANTHROPIC_API_KEY")
-        .map_err(|_| "ANTHROPIC_API_KEY not set in environment".to_string())?;
-
+    max_retries: u32,
+) -> Result<SessionNaming, String> {
     let user_message = format!(
-        "Generate a specific, descriptive title (max 60 characters) for a coding task.\n\nUser's request: {}\n\nIMPORTANT RULES:\n1. Extract the CORE TASK from the user's prompt - what specific thing are they asking for?\n2. Start with an action verb: Improve, Fix, Add, Implement, Refactor, Update, Remove, etc.\n3. Include the specific component/feature being modified\n4. NEVER use generic phrases like \"Code Session\", \"Update Master Branch\", \"Work on [repo name]\"\n5. If the request is vague, make your best guess about the specific work being done\n\nGOOD title examples:\n- User says \"the auto title generation could use some improvement\" → \"Improve Auto Title Generation Prompt\"\n- User says \"fix the memory leak\" → \"Fix Memory Leak in Session Handler\"\n- User says \"add authentication\" → \"Implement User Authentication\"\n- User says \"refactor the database code\" → \"Refactor Database Connection Layer\"\n\nBAD title examples (NEVER generate these):\n- \"Prompt-Backend Code Session: Update Master Branch\" ❌ Too generic\n- \"Update Code\" ❌ Not specific\n- \"Code Session\" ❌ Meaningless\n- \"Work on Repository\" ❌ Too vague\n\nRespond with ONLY the title, nothing else. Make it specific to the actual task!",
+        "Generate a title and a git branch name for a coding task.\n\nUser's request: {}\n\nTITLE RULES (max 60 characters):\n1. Extract the CORE TASK from the user's prompt - what specific thing are they asking for?\n2. Start with an action verb: Improve, Fix, Add, Implement, Refactor, Update, Remove, etc.\n3. Include the specific component/feature being modified\n4. NEVER use generic phrases like \"Code Session\", \"Update Master Branch\", \"Work on [repo name]\"\n5. If the request is vague, make your best guess about the specific work being done\n\nGOOD title examples:\n- User says \"the auto title generation could use some improvement\" → \"Improve Auto Title Generation Prompt\"\n- User says \"fix the memory leak\" → \"Fix Memory Leak in Session Handler\"\n- User says \"add authentication\" → \"Implement User Authentication\"\n- User says \"refactor the database code\" → \"Refactor Database Connection Layer\"\n\nBAD title examples (NEVER generate these):\n- \"Prompt-Backend Code Session: Update Master Branch\" ❌ Too generic\n- \"Update Code\" ❌ Not specific\n- \"Code Session\" ❌ Meaningless\n- \"Work on Repository\" ❌ Too vague\n\nBRANCH RULES (max 50 characters):\n- Descriptive of the task/feature\n- In kebab-case (lowercase with hyphens)\n- Git-safe (only alphanumeric characters and hyphens)\n- Do NOT include a 'claude/' prefix\n\nRespond with ONLY a JSON object, nothing else: {{\"title\": \"...\", \"branch\": \"...\"}}",
         prompt
     );
 
-    let request_body = AnthropicRequest {
-        model: "claude-haiku-4-5".to_string(),
-        max_tokens: 100,
-        messages: vec![Message {
-            role: "user".to_string(),
-            content: user_message,
-        }],
-    };
-
-    let client = reqwest::Client::new();
-    let response = client
-        .post("https://api.anthropic.com/v1/messages")
-        .header("x-api-key", api_key)
-        .header("anthropic-version", "2023-06-01")
-        .header("content-type", "application/json")
-        .json(&request_body)
-        .send()
-        .await
-        .map_err(|e| format!("Failed to send request to Anthropic API: {}", e))?;
-
-    if !response.status().is_success() {
-        let status = response.status();
-        let error_text = response
-            .text()
-            .await
-            .unwrap_or_else(|_| "Unknown error".to_string());
-        return Err(format!("Anthropic API error ({}): {}", status, error_text));
-    }
+    let text = send_message(user_message, 150, max_retries).await?;
+    parse_naming(&text)
+}
 
-    let anthropic_response: AnthropicResponse = response
-        .json()
-        .await
-        .map_err(|e| format!("Failed to parse Anthropic API response: {}", e))?;
+/// Summarize a session's pull request for its description: what was asked for and what
+/// changed. `prompts` are the session's prompts in order, `changed_files` one line per file.
+pub async fn generate_pull_request_summary(
+    prompts: &[String],
+    changed_files: &[String],
+) -> Result<String, String> {
+    let user_message = format!(
+        "Write the summary section of a pull request description for changes an AI coding \
+         agent made.\n\nThe user's requests, in order:\n{}\n\nFiles changed (additions/deletions):\n{}\n\n\
+         Write 2 to 4 sentences on the intent, then a markdown bullet list of the key changes. \
+         Describe only what the requests and files support; do not invent details. \
+         Respond with ONLY the markdown, no heading.",
+        prompts
+            .iter()
+            .enumerate()
+            .map(|(i, p)| format!("{}. {}", i + 1, p))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        if changed_files.is_empty() {
+            "(none)".to_string()
+        } else {
+            changed_files.join("\n")
+        }
+    );
 
-    let title = anthropic_response
-        .content
-        .first()
-        .map(|block| block.text.trim().to_string())
-        .unwrap_or_else(|| "Untitled Session".to_string());
+    send_message(user_message, 600, 0).await
+}
 
-    Ok(title)
+/// Send a single user message to Haiku and return the text of the first content block.
+/// Gives up after `ANTHROPIC_TIMEOUT_SECS`, retries included.
+async fn send_message(
+    user_message: String,
+    max_tokens: u32,
+    max_retries: u32,
+) -> Result<String, String> {
+    let timeout = crate::config::get().title_generation.api_timeout;
+    match tokio::time::timeout(
+        timeout,
+        try_send_message(user_message, max_tokens, max_retries),
+    )
+    .await
+    {
+        Ok(result) => result,
+        Err(_) => Err(format!("Anthropic API call timed out after {:?}", timeout)),
+    }
 }
 
-pub async fn generate_branch_name(
-    _git_repo: &str,
-    _target_branch: &str,
-    prompt: &str,
-    session_id: &str,
+async fn try_send_message(
+    user_message: String,
+    max_tokens: u32,
+    max_retries: u32,
 ) -> Result<String, String> {
     let api_key = env::var("ANTHROPIC_API_KEY")
         .map_err(|_| "ANTHROPIC_API_KEY not set in environment".to_string())?;
 
-    let user_message = format!(
-        "Generate a concise, descriptive git branch name (max 50 characters) for a coding session based on this context:\n\nPrompt: {}\n\nThe branch name should be:\n- Descriptive of the task/feature\n- In kebab-case (lowercase with hyphens)\n- Git-safe (only alphanumeric characters and hyphens)\n\nRespond with ONLY the branch name, nothing else. Do NOT include 'claude/' prefix.",
-        prompt
-    );
-
     let request_body = AnthropicRequest {
         model: "claude-haiku-4-5".to_string(),
-        max_tokens: 50,
+        max_tokens,
         messages: vec![Message {
             role: "user".to_string(),
             content: user_message,
         }],
     };
 
-    let client = reqwest::Client::new();
-    let response = client
-        .post("https://api.anthropic.com/v1/messages")
-        .header("x-api-key", api_key)
-        .header("anthropic-version", "2023-06-01")
-        .header("content-type", "application/json")
-        .json(&request_body)
-        .send()
-        .await
-        .map_err(|e| format!("Failed to send request to Anthropic API: {}", e))?;
+    let mut attempt = 0;
+    let response = loop {
+        let request = http_client::client()
+            .post("https://api.anthropic.com/v1/messages")
+            .header("x-api-key", &api_key)
+            .header("anthropic-version", "2023-06-01")
+            .header("content-type", "application/json")
+            .json(&request_body);
+        let response = http_client::send_with_retry(request)
+            .await
+            .map_err(|e| format!("Failed to send request to Anthropic API: {}", e))?;
+        if attempt >= max_retries || !is_transient(response.status()) {
+            break response;
+        }
+
+        let delay = retry_delay(attempt, retry_after(&response));
+        warn!(
+            "Anthropic API returned {}, retrying in {:?} (attempt {} of {})",
+            response.status(),
+            delay,
+            attempt + 1,
+            max_retries
+        );
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    };
 
     if !response.status().is_success() {
         let status = response.status();
@@ -128,14 +172,74 @@ pub async fn generate_branch_name(
         .await
         .map_err(|e| format!("Failed to parse Anthropic API response: {}", e))?;
 
-    let mut branch_name = anthropic_response
+    anthropic_response
         .content
         .first()
         .map(|block| block.text.trim().to_string())
-        .unwrap_or_else(|| "untitled-session".to_string());
+        .ok_or_else(|| "Anthropic API response has no content".to_string())
+}
+
+/// Rate limits, overload (529) and internal errors, which a later call may not hit. 502, 503
+/// and 504 are already retried by `http_client::send_with_retry`.
+fn is_transient(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::TOO_MANY_REQUESTS | StatusCode::INTERNAL_SERVER_ERROR
+    ) || status.as_u16() == 529
+}
+
+/// The `retry-after` the API asked for, in seconds
+fn retry_after(response: &reqwest::Response) -> Option<Duration> {
+    response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+/// Wait before retry `attempt`: what the API asked for, else jittered exponential backoff,
+/// never longer than `RETRY_MAX_DELAY`
+fn retry_delay(attempt: u32, retry_after: Option<Duration>) -> Duration {
+    retry_after
+        .map(|delay| delay.min(RETRY_MAX_DELAY))
+        .unwrap_or_else(|| jittered_backoff(attempt, RETRY_BASE_DELAY, RETRY_MAX_DELAY))
+}
+
+/// Parse the JSON object the model answered with, tolerating text around it
+fn parse_naming(text: &str) -> Result<SessionNaming, String> {
+    let start = text.find('{');
+    let end = text.rfind('}');
+    let json = match (start, end) {
+        (Some(start), Some(end)) if start < end => &text[start..=end],
+        _ => return Err(format!("Expected a JSON object, got: {}", text)),
+    };
+    let naming: SessionNaming = serde_json::from_str(json)
+        .map_err(|e| format!("Failed to parse generated naming: {}", e))?;
+
+    let title = naming.title.trim().to_string();
+    let branch_slug = slugify(&naming.branch_slug);
+    Ok(SessionNaming {
+        title: if title.is_empty() {
+            "Untitled Session".to_string()
+        } else {
+            title
+        },
+        branch_slug: if branch_slug.is_empty() {
+            "untitled-session".to_string()
+        } else {
+            branch_slug
+        },
+    })
+}
 
-    // Clean up the branch name to ensure it's git-safe
-    branch_name = branch_name
+/// Clean up a branch name to ensure it's git-safe
+fn slugify(name: &str) -> String {
+    name.trim()
+        .trim_start_matches("claude/")
         .to_lowercase()
         .chars()
         .map(|c| {
@@ -149,10 +253,45 @@ pub async fn generate_branch_name(
         .split('-')
         .filter(|s| !s.is_empty())
         .collect::<Vec<&str>>()
-        .join("-");
+        .join("-")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    // Add claude/ prefix and session ID suffix
-    let full_branch_name = format!("claude/{}-{}", branch_name, &session_id[..24]);
+    #[test]
+    fn test_parse_naming_tolerates_surrounding_text() {
+        let naming = parse_naming(
+            "Here you go:\n{\"title\": \"Fix Memory Leak\", \"branch\": \"claude/Fix Memory_Leak\"}",
+        )
+        .unwrap();
+        assert_eq!(naming.title, "Fix Memory Leak");
+        assert_eq!(naming.branch_slug, "fix-memory-leak");
+        assert_eq!(
+            naming.branch_name("0123456789abcdef0123456789abcdef"),
+            "claude/fix-memory-leak-0123456789abcdef01234567"
+        );
 
-    Ok(full_branch_name)
+        assert!(parse_naming("Fix Memory Leak").is_err());
+    }
+
+    #[test]
+    fn test_transient_statuses_and_retry_delay() {
+        assert!(is_transient(StatusCode::TOO_MANY_REQUESTS));
+        assert!(is_transient(StatusCode::from_u16(529).unwrap()));
+        assert!(is_transient(StatusCode::INTERNAL_SERVER_ERROR));
+        assert!(!is_transient(StatusCode::BAD_REQUEST));
+        assert!(!is_transient(StatusCode::UNAUTHORIZED));
+
+        assert_eq!(
+            retry_delay(0, Some(Duration::from_secs(7))),
+            Duration::from_secs(7)
+        );
+        assert_eq!(
+            retry_delay(0, Some(Duration::from_secs(600))),
+            RETRY_MAX_DELAY
+        );
+        assert!(retry_delay(2, None) <= RETRY_BASE_DELAY * 4);
+    }
 }
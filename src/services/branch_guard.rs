@@ -0,0 +1,160 @@
+//! Keeps Claude's pushes on the session branch.
+//!
+//! Before a run the session branch is checked against the target branch and GitHub's branch
+//! protection, and a `pre-push` hook is installed in the sandbox clone that refuses every ref
+//! other than the session branch, and by default commits `secret_scan` flags. After the run,
+//! CLI output is scanned for pushes the server rejected so the session can say why nothing
+//! landed.
+
+use tracing::warn;
+
+use crate::services::github::{GithubClient, GithubError};
+use crate::services::github_host::RepoRef;
+use crate::services::path_policy::PathPolicy;
+use crate::services::secret_scan;
+use crate::services::shell;
+
+/// Why a run must not start on this branch
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum BranchGuardError {
+    #[error("Session branch {0} is the target branch; changes must be pushed to a separate branch and merged through a pull request")]
+    IsTarget(String),
+    #[error("Session branch {0} is protected on GitHub and cannot be pushed to directly")]
+    Protected(String),
+    #[error("Branch {0} already exists on GitHub; refusing to reuse it for a new session")]
+    AlreadyExists(String),
+}
+
+/// Validate the session branch before the first push.
+///
+/// `generated` is true when the branch name was derived from the session rather than chosen by
+/// the user; only generated names are required not to exist yet, and only on the session's
+/// first run. GitHub lookups that fail for reasons other than a missing branch are logged and
+/// do not block the run; the pre-push hook still keeps the target branch safe.
+pub async fn check(
+    github: &GithubClient,
+    repo: &RepoRef,
+    branch: &str,
+    target_branch: &str,
+    generated: bool,
+    first_run: bool,
+) -> Result<(), BranchGuardError> {
+    if branch == target_branch {
+        return Err(BranchGuardError::IsTarget(branch.to_string()));
+    }
+
+    match github.get_branch(repo, branch).await {
+        Ok(existing) if existing.protected => Err(BranchGuardError::Protected(branch.to_string())),
+        Ok(_) if generated && first_run => Err(BranchGuardError::AlreadyExists(branch.to_string())),
+        Ok(_) | Err(GithubError::NotFound(_)) => Ok(()),
+        Err(e) => {
+            warn!(
+                "Could not check branch {} of {} on GitHub, continuing: {}",
+                branch, repo, e
+            );
+            Ok(())
+        }
+    }
+}
+
+/// A `pre-push` hook that only lets `branch` be pushed, with a path policy only commits that
+/// leave denied paths untouched, and with `scan_secrets` only commits without likely secrets
+pub fn pre_push_hook(
+    branch: &str,
+    target_branch: &str,
+    policy: Option<&PathPolicy>,
+    scan_secrets: bool,
+) -> String {
+    format!(
+        r#"#!/bin/sh
+# Installed by prompt-backend: this session may only push to its own branch.
+allowed={allowed}
+zero=0000000000000000000000000000000000000000
+while read local_ref local_sha remote_ref remote_sha; do
+  if [ "$remote_ref" != "$allowed" ]; then
+    echo "prompt-backend: refusing to push to $remote_ref; push to ${{allowed#refs/heads/}} and open a pull request instead" >&2
+    exit 1
+  fi
+  [ "$local_sha" = "$zero" ] && continue
+  if [ "$remote_sha" != "$zero" ] && git cat-file -e "$remote_sha" 2>/dev/null; then
+    base="$remote_sha"
+  else
+    base=$(git merge-base "$local_sha" {target} 2>/dev/null || git hash-object -t tree /dev/null)
+  fi
+{policy_check}{secret_check}done
+exit 0
+"#,
+        allowed = shell::quote(&format!("refs/heads/{}", branch)),
+        target = shell::quote(&format!("origin/{}", target_branch)),
+        policy_check = policy.map(PathPolicy::pre_push_check).unwrap_or_default(),
+        secret_check = if scan_secrets {
+            secret_scan::pre_push_check()
+        } else {
+            String::new()
+        },
+    )
+}
+
+/// Markers git and GitHub print when a push is refused by a hook or branch protection
+const REJECTION_MARKERS: &[&str] = &[
+    "[remote rejected]",
+    "pre-receive hook declined",
+    "protected branch hook declined",
+    "GH006",
+    "prompt-backend: refusing to push",
+];
+
+/// The CLI output line describing a rejected push, if `line` contains one
+pub fn push_rejection(line: &str) -> Option<String> {
+    let marker = REJECTION_MARKERS.iter().find(|m| line.contains(*m))?;
+    let start = line.find(marker).unwrap_or(0);
+    let detail: String = line[start..]
+        .split(['\n', '"'])
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .take(300)
+        .collect();
+    Some(detail.replace("\\n", " ").trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pre_push_hook_allows_only_session_branch() {
+        let hook = pre_push_hook("claude/abc", "main", None, false);
+        assert!(hook.starts_with("#!/bin/sh"));
+        assert!(hook.contains("allowed='refs/heads/claude/abc'"));
+        assert!(hook.contains("'origin/main'"));
+        assert!(!hook.contains("path policy"));
+        assert!(!hook.contains("secrets"));
+
+        let hook = pre_push_hook("claude/$(id)", "main'; reboot; '", None, false);
+        assert!(hook.contains("allowed='refs/heads/claude/$(id)'"));
+        assert!(hook.contains(r"'origin/main'\''; reboot; '\'''"));
+    }
+
+    #[test]
+    fn test_pre_push_hook_checks_path_policy() {
+        let policy = PathPolicy {
+            include: vec![],
+            exclude: vec!["deploy/**".to_string()],
+        };
+        let hook = pre_push_hook("claude/abc", "main", Some(&policy), true);
+        assert!(hook.contains("':(glob)deploy/**'"));
+        assert!(hook.contains("prompt-backend: refusing to push changes"));
+        assert!(hook.contains("look like they contain secrets"));
+    }
+
+    #[test]
+    fn test_push_rejection() {
+        let line = r#"{"type":"user","content":"To github.com:o/r.git\n ! [remote rejected] main -> main (protected branch hook declined)\nerror: failed to push"}"#;
+        assert_eq!(
+            push_rejection(line).as_deref(),
+            Some("[remote rejected] main -> main (protected branch hook declined) error: failed to push")
+        );
+        assert_eq!(push_rejection(r#"{"type":"assistant"}"#), None);
+    }
+}
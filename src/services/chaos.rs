@@ -0,0 +1,157 @@
+//! Fault injection for exercising the DLQ, retry and cancellation paths.
+//!
+//! Faults can only be armed in builds with the `testing` feature, through the admin routes in
+//! `handlers::chaos`. In normal builds every check is a no-op.
+
+#[cfg(any(test, feature = "testing"))]
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::time::Duration;
+
+/// A failure that can be injected into a background path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// The next IP returns to the allocator fail
+    IpReturn,
+    /// The next sandbox calls from the outbox job time out
+    SandboxTimeout,
+}
+
+/// Pending injected faults. Each armed fault fires once per check until its count runs out.
+#[cfg(any(test, feature = "testing"))]
+#[derive(Debug, Default)]
+pub struct FaultInjector {
+    ip_return: AtomicU32,
+    sandbox_timeout: AtomicU32,
+    /// Seconds the next CLI run should sleep instead of invoking Claude, 0 when unarmed
+    fake_cli_seconds: AtomicU64,
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl FaultInjector {
+    pub const fn new() -> Self {
+        Self {
+            ip_return: AtomicU32::new(0),
+            sandbox_timeout: AtomicU32::new(0),
+            fake_cli_seconds: AtomicU64::new(0),
+        }
+    }
+
+    fn counter(&self, fault: Fault) -> &AtomicU32 {
+        match fault {
+            Fault::IpReturn => &self.ip_return,
+            Fault::SandboxTimeout => &self.sandbox_timeout,
+        }
+    }
+
+    /// Make the next `times` checks for `fault` fail
+    pub fn arm(&self, fault: Fault, times: u32) {
+        self.counter(fault).store(times, Ordering::SeqCst);
+    }
+
+    /// Replace the next CLI run with a process that sleeps for `duration`
+    pub fn arm_fake_cli(&self, duration: Duration) {
+        self.fake_cli_seconds
+            .store(duration.as_secs().max(1), Ordering::SeqCst);
+    }
+
+    /// Consume one pending failure for `fault`, returning whether it should fire
+    pub fn take(&self, fault: Fault) -> bool {
+        self.counter(fault)
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            .is_ok()
+    }
+
+    pub fn take_fake_cli(&self) -> Option<Duration> {
+        match self.fake_cli_seconds.swap(0, Ordering::SeqCst) {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        }
+    }
+
+    /// Remaining failures for `fault`
+    pub fn pending(&self, fault: Fault) -> u32 {
+        self.counter(fault).load(Ordering::SeqCst)
+    }
+
+    pub fn pending_fake_cli(&self) -> Option<Duration> {
+        match self.fake_cli_seconds.load(Ordering::SeqCst) {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        }
+    }
+
+    pub fn reset(&self) {
+        self.ip_return.store(0, Ordering::SeqCst);
+        self.sandbox_timeout.store(0, Ordering::SeqCst);
+        self.fake_cli_seconds.store(0, Ordering::SeqCst);
+    }
+}
+
+#[cfg(feature = "testing")]
+static INJECTOR: FaultInjector = FaultInjector::new();
+
+/// The process-wide injector armed by the chaos admin routes
+#[cfg(feature = "testing")]
+pub fn injector() -> &'static FaultInjector {
+    &INJECTOR
+}
+
+/// Whether an injected `fault` should fire now. Always false without the `testing` feature.
+pub fn should_fail(fault: Fault) -> bool {
+    #[cfg(feature = "testing")]
+    {
+        let fire = INJECTOR.take(fault);
+        if fire {
+            tracing::warn!("Injecting {:?} fault", fault);
+        }
+        fire
+    }
+    #[cfg(not(feature = "testing"))]
+    {
+        let _ = fault;
+        false
+    }
+}
+
+/// How long a faked CLI run should last, if one is armed. Always None without the `testing`
+/// feature.
+pub fn fake_cli_run() -> Option<Duration> {
+    #[cfg(feature = "testing")]
+    {
+        INJECTOR.take_fake_cli()
+    }
+    #[cfg(not(feature = "testing"))]
+    {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_armed_fault_fires_exactly_n_times() {
+        let injector = FaultInjector::new();
+        assert!(!injector.take(Fault::IpReturn));
+
+        injector.arm(Fault::IpReturn, 2);
+        assert!(injector.take(Fault::IpReturn));
+        assert!(!injector.take(Fault::SandboxTimeout));
+        assert!(injector.take(Fault::IpReturn));
+        assert!(!injector.take(Fault::IpReturn));
+    }
+
+    #[test]
+    fn test_fake_cli_is_consumed_once() {
+        let injector = FaultInjector::new();
+        injector.arm_fake_cli(Duration::from_secs(30));
+        assert_eq!(injector.pending_fake_cli(), Some(Duration::from_secs(30)));
+        assert_eq!(injector.take_fake_cli(), Some(Duration::from_secs(30)));
+        assert_eq!(injector.take_fake_cli(), None);
+
+        injector.arm(Fault::SandboxTimeout, 1);
+        injector.reset();
+        assert_eq!(injector.pending(Fault::SandboxTimeout), 0);
+    }
+}
@@ -0,0 +1,360 @@
+//! The schema of prompt `data`: a conversation of user and assistant messages.
+//!
+//! ```json
+//! {"messages": [
+//!   {"role": "user", "content": [
+//!     {"type": "text", "text": "Add a CSV export"},
+//!     {"type": "attachment", "path": "/home/gem/uploads/<session>/sample.csv"}
+//!   ]}
+//! ]}
+//! ```
+//!
+//! Data is checked when a prompt is created or edited and stored as a conversation. Clients
+//! written before the schema send a plain string, an object with a `content`, `prompt`, `text`
+//! or `message` field, or role/content messages with string content; `PROMPT_LEGACY_DATA`
+//! decides whether those are converted or rejected. Prompts stored before the schema are
+//! always read through the same conversion.
+
+use rocket_okapi::okapi::schemars::{self, JsonSchema};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::config::{self, LegacyPromptData};
+use crate::entities::prompt_attachment::AttachmentKind;
+
+/// Fields a legacy object prompt keeps its text in, in order of preference
+const LEGACY_TEXT_FIELDS: [&str; 4] = ["content", "prompt", "text", "message"];
+
+/// Most messages accepted in one conversation
+const MAX_MESSAGES: usize = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Assistant,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    Text {
+        text: String,
+    },
+    /// A file in the sandbox, e.g. an upload's `path`
+    Attachment {
+        path: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+    },
+    /// A file uploaded with `POST /attachments`. Clients send its `attachment_id`; the other
+    /// fields are filled in from the attachment when the prompt is stored.
+    File {
+        attachment_id: String,
+        /// Where the run finds the file in the sandbox
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        path: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        kind: Option<AttachmentKind>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        content_type: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        size_bytes: Option<i64>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct ConversationMessage {
+    pub role: Role,
+    pub content: Vec<ContentPart>,
+}
+
+/// Prompt data: earlier turns for context, ending with the user message to act on
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Conversation {
+    pub messages: Vec<ConversationMessage>,
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum ConversationError {
+    #[error(
+        "expected a conversation such as \
+         {{\"messages\": [{{\"role\": \"user\", \"content\": [{{\"type\": \"text\", \"text\": \"...\"}}]}}]}}"
+    )]
+    Unrecognized,
+    #[error("legacy prompt data is not accepted; send a conversation with a messages array")]
+    LegacyRejected,
+    #[error("{0}")]
+    Invalid(String),
+}
+
+impl Conversation {
+    /// A conversation of one user message with `text`
+    pub fn from_text(text: impl Into<String>) -> Self {
+        Conversation {
+            messages: vec![ConversationMessage {
+                role: Role::User,
+                content: vec![ContentPart::Text { text: text.into() }],
+            }],
+        }
+    }
+
+    fn validate(&self) -> Result<(), ConversationError> {
+        let invalid = |msg: String| Err(ConversationError::Invalid(msg));
+        if self.messages.is_empty() {
+            return invalid("messages must not be empty".to_string());
+        }
+        if self.messages.len() > MAX_MESSAGES {
+            return invalid(format!("at most {} messages are accepted", MAX_MESSAGES));
+        }
+        if self.messages.last().map(|m| m.role) != Some(Role::User) {
+            return invalid("the last message must have role user".to_string());
+        }
+        for (i, message) in self.messages.iter().enumerate() {
+            if message.content.is_empty() {
+                return invalid(format!("messages[{}].content must not be empty", i));
+            }
+            for part in &message.content {
+                match part {
+                    ContentPart::Text { text } if text.trim().is_empty() => {
+                        return invalid(format!("messages[{}] has an empty text part", i))
+                    }
+                    ContentPart::Attachment { path, .. }
+                        if !path.starts_with('/') || path.contains(['\n', '`']) =>
+                    {
+                        return invalid(format!(
+                            "messages[{}] attachment path must be an absolute sandbox path",
+                            i
+                        ))
+                    }
+                    ContentPart::File { attachment_id, .. }
+                        if uuid::Uuid::parse_str(attachment_id).is_err() =>
+                    {
+                        return invalid(format!(
+                            "messages[{}] file attachment_id must be a UUID",
+                            i
+                        ))
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The text the CLI is given: the message as is when there is only one, otherwise every
+    /// turn under a heading naming its role
+    pub fn render(&self) -> String {
+        match self.messages.as_slice() {
+            [only] => render_content(&only.content),
+            messages => messages
+                .iter()
+                .map(|message| {
+                    let heading = match message.role {
+                        Role::User => "## User",
+                        Role::Assistant => "## Assistant",
+                    };
+                    format!("{}\n\n{}", heading, render_content(&message.content))
+                })
+                .collect::<Vec<_>>()
+                .join("\n\n"),
+        }
+    }
+}
+
+fn render_content(content: &[ContentPart]) -> String {
+    content
+        .iter()
+        .map(|part| match part {
+            ContentPart::Text { text } => text.clone(),
+            ContentPart::Attachment {
+                path,
+                name: Some(name),
+            } => format!("Attached file {}: `{}`", name, path),
+            ContentPart::Attachment { path, name: None } => format!("Attached file: `{}`", path),
+            ContentPart::File {
+                path: Some(path),
+                name,
+                kind,
+                content_type,
+                ..
+            } => {
+                let label = match kind {
+                    Some(AttachmentKind::Image) => "Attached image",
+                    Some(AttachmentKind::Log) => "Attached log",
+                    _ => "Attached file",
+                };
+                let name = name.as_deref().unwrap_or("");
+                match content_type {
+                    Some(ct) => format!("{} {} ({}): `{}`", label, name, ct, path),
+                    None => format!("{} {}: `{}`", label, name, path),
+                }
+            }
+            ContentPart::File { attachment_id, .. } => {
+                format!("Attached file {} (not available)", attachment_id)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+fn role(value: &Value) -> Option<Role> {
+    serde_json::from_value(value.get("role")?.clone()).ok()
+}
+
+/// A legacy message: a role with string content or content parts
+fn legacy_message(value: &Value) -> Option<ConversationMessage> {
+    let role = role(value)?;
+    let content = match value.get("content")? {
+        Value::String(text) => vec![ContentPart::Text { text: text.clone() }],
+        parts => serde_json::from_value(parts.clone()).ok()?,
+    };
+    Some(ConversationMessage { role, content })
+}
+
+fn legacy_messages(values: &[Value]) -> Option<Conversation> {
+    let messages = values
+        .iter()
+        .map(legacy_message)
+        .collect::<Option<Vec<_>>>()?;
+    Some(Conversation { messages })
+}
+
+/// Convert a shape clients sent before the schema, None when `data` is none of them
+fn from_legacy(data: &Value) -> Option<Conversation> {
+    match data {
+        Value::String(text) => Some(Conversation::from_text(text.clone())),
+        Value::Array(values) => legacy_messages(values),
+        Value::Object(obj) => {
+            if let Some(Value::Array(values)) = obj.get("messages") {
+                return legacy_messages(values);
+            }
+            LEGACY_TEXT_FIELDS
+                .iter()
+                .find_map(|field| obj.get(*field).and_then(Value::as_str))
+                .map(Conversation::from_text)
+        }
+        _ => None,
+    }
+}
+
+fn parse_with(data: &Value, legacy: LegacyPromptData) -> Result<Conversation, ConversationError> {
+    let conversation = match serde_json::from_value::<Conversation>(data.clone()) {
+        Ok(conversation) => conversation,
+        Err(_) => {
+            let converted = from_legacy(data).ok_or(ConversationError::Unrecognized)?;
+            if legacy == LegacyPromptData::Reject {
+                return Err(ConversationError::LegacyRejected);
+            }
+            converted
+        }
+    };
+    conversation.validate()?;
+    Ok(conversation)
+}
+
+/// Check prompt data sent by a client and return it as a conversation, for storage
+pub fn normalize(data: &Value) -> Result<Value, ConversationError> {
+    let conversation = parse_with(data, config::get().legacy_prompt_data)?;
+    serde_json::to_value(conversation).map_err(|e| ConversationError::Invalid(e.to_string()))
+}
+
+/// The conversation in stored prompt data, converting legacy shapes whatever the mode, since
+/// prompts stored before the schema still run
+pub fn from_stored(data: &Value) -> Option<Conversation> {
+    serde_json::from_value(data.clone())
+        .ok()
+        .or_else(|| from_legacy(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_legacy_shapes_convert() {
+        let expected = Conversation::from_text("fix it");
+        for data in [
+            json!("fix it"),
+            json!({"prompt": "fix it"}),
+            json!({"content": "fix it", "extra": 1}),
+            json!([{"role": "user", "content": "fix it"}]),
+            json!({"messages": [{"role": "user", "content": "fix it"}]}),
+        ] {
+            assert_eq!(
+                parse_with(&data, LegacyPromptData::Normalize),
+                Ok(expected.clone()),
+                "{}",
+                data
+            );
+        }
+
+        assert_eq!(
+            parse_with(&json!("fix it"), LegacyPromptData::Reject),
+            Err(ConversationError::LegacyRejected)
+        );
+        assert_eq!(
+            parse_with(&json!({"other": 1}), LegacyPromptData::Normalize),
+            Err(ConversationError::Unrecognized)
+        );
+    }
+
+    #[test]
+    fn test_validate_and_render() {
+        let data = json!({"messages": [
+            {"role": "user", "content": [{"type": "text", "text": "Add an export"}]},
+            {"role": "assistant", "content": [{"type": "text", "text": "Which format?"}]},
+            {"role": "user", "content": [
+                {"type": "text", "text": "CSV, like this"},
+                {"type": "attachment", "path": "/home/gem/uploads/s/sample.csv"}
+            ]}
+        ]});
+        let conversation = parse_with(&data, LegacyPromptData::Reject).unwrap();
+        assert_eq!(
+            conversation.render(),
+            "## User\n\nAdd an export\n\n## Assistant\n\nWhich format?\n\n## User\n\n\
+             CSV, like this\n\nAttached file: `/home/gem/uploads/s/sample.csv`"
+        );
+        assert_eq!(Conversation::from_text("fix it").render(), "fix it");
+
+        let ends_with_assistant = json!({"messages": [
+            {"role": "assistant", "content": [{"type": "text", "text": "Done"}]}
+        ]});
+        assert!(matches!(
+            parse_with(&ends_with_assistant, LegacyPromptData::Reject),
+            Err(ConversationError::Invalid(_))
+        ));
+        assert!(matches!(
+            parse_with(&json!({"messages": []}), LegacyPromptData::Reject),
+            Err(ConversationError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn test_file_parts() {
+        let id = "7d0c3f8e-2f4b-4c57-9a83-5c1e0d6f2a11";
+        let data = json!({"messages": [{"role": "user", "content": [
+            {"type": "text", "text": "The page looks broken"},
+            {"type": "file", "attachment_id": id, "path": "/home/gem/attachments/a/shot.png",
+             "name": "shot.png", "kind": "Image", "content_type": "image/png"}
+        ]}]});
+        let conversation = parse_with(&data, LegacyPromptData::Reject).unwrap();
+        assert_eq!(
+            conversation.render(),
+            "The page looks broken\n\n\
+             Attached image shot.png (image/png): `/home/gem/attachments/a/shot.png`"
+        );
+
+        let bad_id = json!({"messages": [{"role": "user", "content": [
+            {"type": "file", "attachment_id": "shot.png"}
+        ]}]});
+        assert!(matches!(
+            parse_with(&bad_id, LegacyPromptData::Reject),
+            Err(ConversationError::Invalid(_))
+        ));
+    }
+}
@@ -0,0 +1,322 @@
+//! Read model for rendering a session's conversation.
+//!
+//! Rendering a session used to join its prompts with every one of their messages, which runs
+//! into the thousands for long sessions. The `conversation_chunk` table keeps each prompt's
+//! messages denormalized in chunks of up to `CHUNK_SIZE`, so a whole conversation is one query
+//! over a few rows per prompt. The message writer and `POST /messages` append to it as
+//! messages come in; editing, deleting, discarding, offloading or purging messages rebuilds
+//! the chunks of the prompts involved from the message table, which stays the source of
+//! truth. `prompt-backend rebuild-conversations` rebuilds every session, or one.
+
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, DbBackend, DbErr, EntityTrait,
+    FromQueryResult, NotSet, QueryFilter, QueryOrder, QuerySelect, Set, Statement,
+    TransactionTrait,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::entities::conversation_chunk::{self, Entity as ConversationChunk};
+use crate::entities::message::{self, Entity as Message};
+use crate::entities::prompt::{self, Entity as Prompt};
+
+/// Most messages kept in one chunk
+pub const CHUNK_SIZE: usize = 200;
+
+/// Prompts rebuilt per page by `rebuild_all`
+const REBUILD_PAGE_SIZE: u64 = 500;
+
+/// A message as stored in a chunk: the message row without its prompt
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChunkMessage {
+    pub id: Uuid,
+    /// The row's `data`, a stub when the payload was offloaded
+    pub data: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blob_key: Option<String>,
+    pub created_at: String,
+}
+
+impl From<&message::Model> for ChunkMessage {
+    fn from(model: &message::Model) -> Self {
+        ChunkMessage {
+            id: model.id,
+            data: model.data.clone(),
+            blob_key: model.blob_key.clone(),
+            created_at: model.created_at.to_string(),
+        }
+    }
+}
+
+/// A prompt of a conversation with its messages in order
+#[derive(Debug, Clone)]
+pub struct ConversationPrompt {
+    pub prompt_id: Uuid,
+    pub data: Value,
+    pub created_at: String,
+    pub messages: Vec<ChunkMessage>,
+}
+
+/// Prompts and chunks written by a rebuild
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RebuildCounts {
+    pub prompts: u64,
+    pub chunks: u64,
+}
+
+/// Split `items` into what tops up a last chunk with `room` left and the new chunks after it
+fn split<T>(room: usize, mut items: Vec<T>, chunk_size: usize) -> (Vec<T>, Vec<Vec<T>>) {
+    let rest = items.split_off(room.min(items.len()));
+    let mut chunks = Vec::new();
+    let mut rest = rest.into_iter().peekable();
+    while rest.peek().is_some() {
+        chunks.push(rest.by_ref().take(chunk_size).collect());
+    }
+    (items, chunks)
+}
+
+fn new_chunk(
+    session_id: Uuid,
+    prompt_id: Uuid,
+    chunk_index: i32,
+    messages: Vec<ChunkMessage>,
+) -> conversation_chunk::ActiveModel {
+    conversation_chunk::ActiveModel {
+        id: Set(Uuid::new_v4()),
+        session_id: Set(session_id),
+        prompt_id: Set(prompt_id),
+        chunk_index: Set(chunk_index),
+        message_count: Set(messages.len() as i32),
+        messages: Set(serde_json::to_value(messages).unwrap_or_else(|_| Value::Array(vec![]))),
+        created_at: NotSet,
+        updated_at: NotSet,
+    }
+}
+
+/// Add newly written messages of `prompt_id`, in the order given, to the end of its
+/// conversation. Failures are logged; the next rebuild of the prompt catches up.
+pub async fn append(
+    db: &DatabaseConnection,
+    session_id: Uuid,
+    prompt_id: Uuid,
+    message_ids: &[Uuid],
+) {
+    if message_ids.is_empty() {
+        return;
+    }
+    if let Err(e) = try_append(db, session_id, prompt_id, message_ids).await {
+        warn!(
+            "Failed to add {} messages of prompt {} to the conversation view: {}",
+            message_ids.len(),
+            prompt_id,
+            e
+        );
+    }
+}
+
+async fn try_append(
+    db: &DatabaseConnection,
+    session_id: Uuid,
+    prompt_id: Uuid,
+    message_ids: &[Uuid],
+) -> Result<(), DbErr> {
+    let mut messages = Message::find()
+        .filter(message::Column::Id.is_in(message_ids.to_vec()))
+        .all(db)
+        .await?;
+    messages.sort_by_key(|m| message_ids.iter().position(|id| *id == m.id));
+    let entries: Vec<ChunkMessage> = messages.iter().map(ChunkMessage::from).collect();
+
+    let last = ConversationChunk::find()
+        .filter(conversation_chunk::Column::PromptId.eq(prompt_id))
+        .order_by_desc(conversation_chunk::Column::ChunkIndex)
+        .one(db)
+        .await?;
+    let room = last
+        .as_ref()
+        .map_or(0, |c| CHUNK_SIZE.saturating_sub(c.message_count as usize));
+    let (top_up, chunks) = split(room, entries, CHUNK_SIZE);
+
+    let next_index = last.as_ref().map_or(0, |c| c.chunk_index + 1);
+    if let Some(last) = last.filter(|_| !top_up.is_empty()) {
+        let mut stored: Vec<ChunkMessage> =
+            serde_json::from_value(last.messages.clone()).unwrap_or_default();
+        stored.extend(top_up);
+        let mut active: conversation_chunk::ActiveModel = last.into();
+        active.message_count = Set(stored.len() as i32);
+        active.messages =
+            Set(serde_json::to_value(stored).unwrap_or_else(|_| Value::Array(vec![])));
+        active.updated_at = Set(chrono::Utc::now().into());
+        active.update(db).await?;
+    }
+
+    if !chunks.is_empty() {
+        let models = chunks
+            .into_iter()
+            .enumerate()
+            .map(|(i, chunk)| new_chunk(session_id, prompt_id, next_index + i as i32, chunk));
+        ConversationChunk::insert_many(models).exec(db).await?;
+    }
+    Ok(())
+}
+
+/// Replace the chunks of `prompt_id` with its current messages. Returns how many chunks it
+/// has now; 0 for a prompt that no longer exists.
+pub async fn rebuild_prompt(db: &DatabaseConnection, prompt_id: Uuid) -> Result<u64, DbErr> {
+    let Some(prompt) = Prompt::find_by_id(prompt_id).one(db).await? else {
+        return Ok(0);
+    };
+    let messages = Message::find()
+        .filter(message::Column::PromptId.eq(prompt_id))
+        .order_by_asc(message::Column::CreatedAt)
+        .all(db)
+        .await?;
+    let entries: Vec<ChunkMessage> = messages.iter().map(ChunkMessage::from).collect();
+    let (_, chunks) = split(0, entries, CHUNK_SIZE);
+    let count = chunks.len() as u64;
+
+    let txn = db.begin().await?;
+    ConversationChunk::delete_many()
+        .filter(conversation_chunk::Column::PromptId.eq(prompt_id))
+        .exec(&txn)
+        .await?;
+    if !chunks.is_empty() {
+        let models = chunks
+            .into_iter()
+            .enumerate()
+            .map(|(i, chunk)| new_chunk(prompt.session_id, prompt_id, i as i32, chunk));
+        ConversationChunk::insert_many(models).exec(&txn).await?;
+    }
+    txn.commit().await?;
+    Ok(count)
+}
+
+/// Rebuild the chunks of each of `prompt_ids` after their messages changed, logging failures
+pub async fn refresh(db: &DatabaseConnection, prompt_ids: impl IntoIterator<Item = Uuid>) {
+    let mut seen = std::collections::HashSet::new();
+    for prompt_id in prompt_ids {
+        if !seen.insert(prompt_id) {
+            continue;
+        }
+        if let Err(e) = rebuild_prompt(db, prompt_id).await {
+            warn!(
+                "Failed to rebuild the conversation view of prompt {}: {}",
+                prompt_id, e
+            );
+        }
+    }
+}
+
+/// Rebuild the chunks of every prompt of `session_id`
+pub async fn rebuild_session(
+    db: &DatabaseConnection,
+    session_id: Uuid,
+) -> Result<RebuildCounts, DbErr> {
+    let prompt_ids: Vec<Uuid> = Prompt::find()
+        .select_only()
+        .column(prompt::Column::Id)
+        .filter(prompt::Column::SessionId.eq(session_id))
+        .into_tuple()
+        .all(db)
+        .await?;
+    let mut counts = RebuildCounts::default();
+    for prompt_id in prompt_ids {
+        counts.chunks += rebuild_prompt(db, prompt_id).await?;
+        counts.prompts += 1;
+    }
+    Ok(counts)
+}
+
+/// Rebuild the chunks of every prompt, e.g. after the table was added or got out of step
+pub async fn rebuild_all(db: &DatabaseConnection) -> Result<RebuildCounts, DbErr> {
+    let mut counts = RebuildCounts::default();
+    let mut after: Option<Uuid> = None;
+    loop {
+        let mut query = Prompt::find()
+            .select_only()
+            .column(prompt::Column::Id)
+            .order_by_asc(prompt::Column::Id)
+            .limit(REBUILD_PAGE_SIZE);
+        if let Some(after) = after {
+            query = query.filter(prompt::Column::Id.gt(after));
+        }
+        let prompt_ids: Vec<Uuid> = query.into_tuple().all(db).await?;
+        let Some(last) = prompt_ids.last().copied() else {
+            return Ok(counts);
+        };
+        for prompt_id in prompt_ids {
+            counts.chunks += rebuild_prompt(db, prompt_id).await?;
+            counts.prompts += 1;
+        }
+        after = Some(last);
+    }
+}
+
+#[derive(Debug, FromQueryResult)]
+struct ConversationRow {
+    prompt_id: Uuid,
+    prompt_data: Value,
+    prompt_created_at: chrono::DateTime<chrono::FixedOffset>,
+    messages: Option<Value>,
+}
+
+/// Every prompt of `session_id` with its messages, oldest first, in one query
+pub async fn load(
+    db: &DatabaseConnection,
+    session_id: Uuid,
+) -> Result<Vec<ConversationPrompt>, DbErr> {
+    let rows = ConversationRow::find_by_statement(Statement::from_sql_and_values(
+        DbBackend::Postgres,
+        r#"SELECT prompt.id AS prompt_id,
+                  prompt.data AS prompt_data,
+                  prompt.created_at AS prompt_created_at,
+                  conversation_chunk.messages
+           FROM prompt
+           LEFT JOIN conversation_chunk ON conversation_chunk.prompt_id = prompt.id
+           WHERE prompt.session_id = $1
+           ORDER BY prompt.created_at, prompt.id, conversation_chunk.chunk_index"#,
+        [session_id.into()],
+    ))
+    .all(db)
+    .await?;
+
+    let mut prompts: Vec<ConversationPrompt> = Vec::new();
+    for row in rows {
+        if prompts.last().map(|p| p.prompt_id) != Some(row.prompt_id) {
+            prompts.push(ConversationPrompt {
+                prompt_id: row.prompt_id,
+                data: row.prompt_data,
+                created_at: row.prompt_created_at.to_string(),
+                messages: Vec::new(),
+            });
+        }
+        if let Some(messages) = row.messages {
+            let chunk: Vec<ChunkMessage> = serde_json::from_value(messages).unwrap_or_default();
+            if let Some(prompt) = prompts.last_mut() {
+                prompt.messages.extend(chunk);
+            }
+        }
+    }
+    Ok(prompts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_tops_up_then_chunks() {
+        let (top_up, chunks) = split(2, (0..7).collect(), 3);
+        assert_eq!(top_up, vec![0, 1]);
+        assert_eq!(chunks, vec![vec![2, 3, 4], vec![5, 6]]);
+
+        let (top_up, chunks) = split(5, vec![0, 1], 3);
+        assert_eq!(top_up, vec![0, 1]);
+        assert!(chunks.is_empty());
+
+        let (top_up, chunks) = split::<i32>(0, vec![], 3);
+        assert!(top_up.is_empty() && chunks.is_empty());
+    }
+}
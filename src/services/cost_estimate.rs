@@ -0,0 +1,322 @@
+use sea_orm::{
+    ColumnTrait, DatabaseConnection, DbBackend, EntityTrait, FromQueryResult, QueryFilter,
+    Statement,
+};
+use serde_json::Value as JsonValue;
+
+use crate::bg_tasks::prompt_run::SYSTEM_PROMPT_TEMPLATE;
+use crate::config::ModelPrice;
+use crate::entities::prompt::{self, Entity as Prompt};
+use crate::entities::session::{ClaudeModel, Model as SessionModel};
+use crate::services::organizations;
+
+/// Rough characters per token for English text and code
+const CHARS_PER_TOKEN: u64 = 4;
+
+/// Number of recent runs on the same repo used to estimate a run's own token usage
+const RECENT_RUNS_SAMPLE: i64 = 20;
+
+/// Usage assumed for a run when the repo has no finished runs yet
+pub const DEFAULT_RUN_USAGE: TokenUsage = TokenUsage {
+    input_tokens: 200_000,
+    output_tokens: 10_000,
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TokenUsage {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+}
+
+impl TokenUsage {
+    pub fn total(&self) -> u64 {
+        self.input_tokens + self.output_tokens
+    }
+
+    pub fn cost_usd(&self, price: ModelPrice) -> f64 {
+        (self.input_tokens as f64 * price.input_per_mtok
+            + self.output_tokens as f64 * price.output_per_mtok)
+            / 1_000_000.0
+    }
+}
+
+/// Estimated usage and cost of running a session's pending prompts
+#[derive(Debug, Clone)]
+pub struct CostEstimate {
+    pub model: ClaudeModel,
+    pub pending_prompt_ids: Vec<uuid::Uuid>,
+    pub usage: TokenUsage,
+    pub cost_usd: f64,
+    /// Finished runs on the same repo the estimate is based on, 0 when defaults were used
+    pub sampled_runs: u64,
+}
+
+/// Token usage reported in a Claude CLI `result` message. Cache reads and writes are counted
+/// as input.
+pub fn usage_from_result(data: &JsonValue) -> Option<TokenUsage> {
+    if data.get("type")?.as_str()? != "result" {
+        return None;
+    }
+    Some(usage_from_object(data.get("usage")?))
+}
+
+/// Token usage of the API call behind a Claude CLI `assistant` message, with the call's
+/// message id. The CLI repeats the usage on every content block of a call, so callers adding
+/// it up should count each id once.
+pub fn usage_from_assistant(data: &JsonValue) -> Option<(&str, TokenUsage)> {
+    if data.get("type")?.as_str()? != "assistant" {
+        return None;
+    }
+    let message = data.get("message")?;
+    Some((
+        message.get("id")?.as_str()?,
+        usage_from_object(message.get("usage")?),
+    ))
+}
+
+fn usage_from_object(usage: &JsonValue) -> TokenUsage {
+    let field = |name: &str| usage.get(name).and_then(|v| v.as_u64()).unwrap_or(0);
+    TokenUsage {
+        input_tokens: field("input_tokens")
+            + field("cache_creation_input_tokens")
+            + field("cache_read_input_tokens"),
+        output_tokens: field("output_tokens"),
+    }
+}
+
+#[derive(Debug, FromQueryResult)]
+struct ResultMessage {
+    data: JsonValue,
+}
+
+/// Average usage of recent runs on `repo`, with the number of runs sampled
+async fn average_repo_usage(
+    db: &DatabaseConnection,
+    repo: &str,
+) -> Result<(TokenUsage, u64), sea_orm::DbErr> {
+    let results = ResultMessage::find_by_statement(Statement::from_sql_and_values(
+        DbBackend::Postgres,
+        r#"SELECT message.data
+           FROM message
+           JOIN prompt ON prompt.id = message.prompt_id
+           JOIN session ON session.id = prompt.session_id
+           WHERE session.repo = $1 AND message.data->>'type' = 'result'
+           ORDER BY message.created_at DESC
+           LIMIT $2"#,
+        [repo.into(), RECENT_RUNS_SAMPLE.into()],
+    ))
+    .all(db)
+    .await?;
+
+    let usages: Vec<TokenUsage> = results
+        .iter()
+        .filter_map(|r| usage_from_result(&r.data))
+        .collect();
+    if usages.is_empty() {
+        return Ok((DEFAULT_RUN_USAGE, 0));
+    }
+
+    let runs = usages.len() as u64;
+    Ok((
+        TokenUsage {
+            input_tokens: usages.iter().map(|u| u.input_tokens).sum::<u64>() / runs,
+            output_tokens: usages.iter().map(|u| u.output_tokens).sum::<u64>() / runs,
+        },
+        runs,
+    ))
+}
+
+#[derive(Debug, FromQueryResult)]
+struct ByteCount {
+    bytes: i64,
+}
+
+/// Size of everything the outbox sends as session history: every prompt's data and every
+/// message, using the recorded size for offloaded payloads
+async fn history_bytes(
+    db: &DatabaseConnection,
+    session_id: uuid::Uuid,
+) -> Result<u64, sea_orm::DbErr> {
+    let count = ByteCount::find_by_statement(Statement::from_sql_and_values(
+        DbBackend::Postgres,
+        r#"SELECT (
+             COALESCE((SELECT SUM(octet_length(prompt.data::text))
+                       FROM prompt WHERE prompt.session_id = $1 AND NOT prompt.draft), 0)
+             + COALESCE((SELECT SUM(CASE WHEN message.blob_key IS NULL
+                                         THEN octet_length(message.data::text)
+                                         ELSE COALESCE((message.data->>'size_bytes')::bigint, 0)
+                                    END)
+                         FROM message
+                         JOIN prompt ON prompt.id = message.prompt_id
+                         WHERE prompt.session_id = $1), 0)
+           )::bigint AS bytes"#,
+        [session_id.into()],
+    ))
+    .one(db)
+    .await?;
+
+    Ok(count.map(|c| c.bytes.max(0) as u64).unwrap_or(0))
+}
+
+/// Estimate the cost of running the session's pending prompts.
+///
+/// Each pending prompt is sent with the system prompt and the full session history, which is
+/// counted at roughly four characters per token. The tool calls and output of the run itself
+/// are taken from the average of recent runs on the same repo. Returns None when the session
+/// has no pending prompts.
+pub async fn estimate_session(
+    db: &DatabaseConnection,
+    session: &SessionModel,
+) -> Result<Option<CostEstimate>, sea_orm::DbErr> {
+    let pending: Vec<uuid::Uuid> = Prompt::find()
+        .filter(prompt::Column::SessionId.eq(session.id))
+        .filter(prompt::Column::CompletedAt.is_null())
+        .filter(prompt::Column::Draft.eq(false))
+        .all(db)
+        .await?
+        .into_iter()
+        .map(|p| p.id)
+        .collect();
+    if pending.is_empty() {
+        return Ok(None);
+    }
+
+    let context_tokens = (SYSTEM_PROMPT_TEMPLATE.len() as u64
+        + history_bytes(db, session.id).await?)
+        / CHARS_PER_TOKEN;
+    let (run_usage, sampled_runs) = match session.repo.as_deref() {
+        Some(repo) => average_repo_usage(db, repo).await?,
+        None => (DEFAULT_RUN_USAGE, 0),
+    };
+
+    let runs = pending.len() as u64;
+    let usage = TokenUsage {
+        input_tokens: (context_tokens + run_usage.input_tokens) * runs,
+        output_tokens: run_usage.output_tokens * runs,
+    };
+    let pricing = &crate::config::get().pricing;
+    let model = pricing.model_or_default(session.model);
+
+    Ok(Some(CostEstimate {
+        model,
+        pending_prompt_ids: pending,
+        usage,
+        cost_usd: usage.cost_usd(pricing.price_of(model)),
+        sampled_runs,
+    }))
+}
+
+#[derive(Debug, FromQueryResult)]
+struct Spend {
+    spent: f64,
+}
+
+/// USD the runs of sessions whose `column` equals `value` reported spending since the start of
+/// the calendar month
+async fn spent_this_month_by(
+    db: &DatabaseConnection,
+    column: &str,
+    value: &str,
+) -> Result<f64, sea_orm::DbErr> {
+    let spend = Spend::find_by_statement(Statement::from_sql_and_values(
+        DbBackend::Postgres,
+        format!(
+            r#"SELECT COALESCE(SUM((message.data->>'total_cost_usd')::float8), 0)::float8 AS spent
+               FROM message
+               JOIN prompt ON prompt.id = message.prompt_id
+               JOIN session ON session.id = prompt.session_id
+               WHERE session.{} = $1
+                 AND message.data->>'type' = 'result'
+                 AND message.created_at >= date_trunc('month', now())"#,
+            column
+        ),
+        [value.into()],
+    ))
+    .one(db)
+    .await?;
+
+    Ok(spend.map(|s| s.spent).unwrap_or(0.0))
+}
+
+/// USD the user's runs have reported spending since the start of the calendar month
+pub async fn spent_this_month(
+    db: &DatabaseConnection,
+    user_id: &str,
+) -> Result<f64, sea_orm::DbErr> {
+    spent_this_month_by(db, "user_id", user_id).await
+}
+
+/// What is left of the monthly budgets the session's runs count against: its user's and, when
+/// the organization has one, its organization's. The smaller remainder applies; None when
+/// neither budget is configured.
+pub async fn remaining_budget_usd(
+    db: &DatabaseConnection,
+    session: &SessionModel,
+) -> Result<Option<f64>, sea_orm::DbErr> {
+    let user_remaining = match crate::config::get().user_monthly_budget_usd {
+        Some(budget) => Some((budget - spent_this_month(db, &session.user_id).await?).max(0.0)),
+        None => None,
+    };
+
+    let org_budget = match &session.org_id {
+        Some(org_id) => organizations::find(db, org_id)
+            .await?
+            .and_then(|org| org.monthly_budget_usd)
+            .map(|budget| (org_id, budget)),
+        None => None,
+    };
+    let org_remaining = match org_budget {
+        Some((org_id, budget)) => {
+            Some((budget - spent_this_month_by(db, "org_id", org_id).await?).max(0.0))
+        }
+        None => None,
+    };
+
+    Ok(match (user_remaining, org_remaining) {
+        (Some(user), Some(org)) => Some(user.min(org)),
+        (user, org) => user.or(org),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usage_from_result_counts_cache_as_input() {
+        let data = serde_json::json!({
+            "type": "result",
+            "total_cost_usd": 0.42,
+            "usage": {
+                "input_tokens": 100,
+                "cache_creation_input_tokens": 1000,
+                "cache_read_input_tokens": 5000,
+                "output_tokens": 300
+            }
+        });
+        assert_eq!(
+            usage_from_result(&data),
+            Some(TokenUsage {
+                input_tokens: 6100,
+                output_tokens: 300
+            })
+        );
+        assert_eq!(
+            usage_from_result(&serde_json::json!({"type": "assistant"})),
+            None
+        );
+    }
+
+    #[test]
+    fn test_cost_usd() {
+        let usage = TokenUsage {
+            input_tokens: 2_000_000,
+            output_tokens: 100_000,
+        };
+        let price = ModelPrice {
+            input_per_mtok: 3.0,
+            output_per_mtok: 15.0,
+        };
+        assert!((usage.cost_usd(price) - 7.5).abs() < 1e-9);
+    }
+}
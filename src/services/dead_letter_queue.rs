@@ -1,10 +1,15 @@
 use crate::entities::dead_letter_queue::{
     self, ActiveModel, DlqStatus, Entity as DeadLetterQueue, Model,
 };
+use crate::entities::outbox_event::{self, Entity as OutboxEvent, OutboxEventStatus};
+use crate::entities::session::{self, Entity as Session};
+use crate::services::outbox_events;
 use sea_orm::entity::prelude::DateTimeWithTimeZone;
+use sea_orm::sea_query::Expr;
 use sea_orm::{
-    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, NotSet, PaginatorTrait,
-    QueryFilter, Set,
+    ActiveEnum, ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, DbBackend,
+    DbErr, EntityTrait, FromQueryResult, NotSet, PaginatorTrait, QueryFilter, QueryOrder, Set,
+    Statement,
 };
 use serde_json::Value as JsonValue;
 use uuid::Uuid;
@@ -12,6 +17,42 @@ use uuid::Uuid;
 /// Maximum number of retries before moving to DLQ
 pub const MAX_RETRY_COUNT: i32 = 5;
 
+/// Task type of DLQ entries for sandboxes the IP return poller could not hand back
+pub const IP_RETURN_TASK_TYPE: &str = "ip_return_poller";
+
+#[derive(Debug, thiserror::Error)]
+pub enum RetryError {
+    #[error("DLQ entry not found")]
+    NotFound,
+    #[error("DLQ entry is {0}, only pending entries can be retried")]
+    NotPending(String),
+    #[error("DLQ entries of task type {0} cannot be retried")]
+    UnsupportedTaskType(String),
+    #[error("{0} of DLQ entry no longer exists")]
+    EntityGone(&'static str),
+    #[error(transparent)]
+    Database(#[from] DbErr),
+}
+
+/// DLQ entries, newest first, optionally only those with `status`
+pub async fn list_entries(
+    db: &DatabaseConnection,
+    status: Option<DlqStatus>,
+) -> Result<Vec<Model>, DbErr> {
+    let mut query = DeadLetterQueue::find();
+    if let Some(status) = status {
+        query = query.filter(dead_letter_queue::Column::Status.eq(status));
+    }
+    query
+        .order_by_desc(dead_letter_queue::Column::CreatedAt)
+        .all(db)
+        .await
+}
+
+pub async fn find_entry(db: &DatabaseConnection, dlq_id: Uuid) -> Result<Option<Model>, DbErr> {
+    DeadLetterQueue::find_by_id(dlq_id).one(db).await
+}
+
 /// Insert a new entry into the dead letter queue
 pub async fn insert_dlq_entry(
     db: &DatabaseConnection,
@@ -66,6 +107,7 @@ pub async fn resolve_dlq_entry(
         sea_orm::DbErr::RecordNotFound("DLQ entry not found".to_string()),
     )?;
 
+    observe_age(&dlq_entry, "resolved");
     let mut active_entry: ActiveModel = dlq_entry.into();
     active_entry.status = Set(DlqStatus::Resolved);
     active_entry.resolution_notes = Set(resolution_notes);
@@ -84,6 +126,7 @@ pub async fn abandon_dlq_entry(
         sea_orm::DbErr::RecordNotFound("DLQ entry not found".to_string()),
     )?;
 
+    observe_age(&dlq_entry, "abandoned");
     let mut active_entry: ActiveModel = dlq_entry.into();
     active_entry.status = Set(DlqStatus::Abandoned);
     active_entry.resolution_notes = Set(resolution_notes);
@@ -91,3 +134,133 @@ pub async fn abandon_dlq_entry(
 
     active_entry.update(db).await
 }
+
+/// Mark the pending entries of `task_type` about `entity_id` resolved, returning their ids
+pub async fn resolve_pending_for<C: ConnectionTrait>(
+    db: &C,
+    task_type: &str,
+    entity_id: Uuid,
+    resolution_notes: &str,
+) -> Result<Vec<Uuid>, DbErr> {
+    let entries = DeadLetterQueue::find()
+        .filter(dead_letter_queue::Column::TaskType.eq(task_type))
+        .filter(dead_letter_queue::Column::EntityId.eq(entity_id))
+        .filter(dead_letter_queue::Column::Status.eq(DlqStatus::Pending))
+        .all(db)
+        .await?;
+    let mut resolved = Vec::with_capacity(entries.len());
+    for entry in entries {
+        observe_age(&entry, "resolved");
+        resolved.push(entry.id);
+        let mut active_entry: ActiveModel = entry.into();
+        active_entry.status = Set(DlqStatus::Resolved);
+        active_entry.resolution_notes = Set(Some(resolution_notes.to_string()));
+        active_entry.update(db).await?;
+    }
+    Ok(resolved)
+}
+
+/// Give the failed task of a pending DLQ entry a fresh set of attempts and mark the entry
+/// resolved. An outbox event is dispatched again right away; a session whose sandbox could not
+/// be returned is picked up by the IP return poller on its next pass.
+pub async fn retry_dlq_entry(
+    db: &DatabaseConnection,
+    dlq_id: Uuid,
+    resolution_notes: Option<String>,
+) -> Result<Model, RetryError> {
+    let dlq_entry = find_entry(db, dlq_id).await?.ok_or(RetryError::NotFound)?;
+    if dlq_entry.status != DlqStatus::Pending {
+        return Err(RetryError::NotPending(dlq_entry.status.to_value()));
+    }
+
+    let now = chrono::Utc::now();
+    let retried = match dlq_entry.task_type.as_str() {
+        outbox_events::DLQ_TASK_TYPE => {
+            OutboxEvent::update_many()
+                .col_expr(
+                    outbox_event::Column::Status,
+                    Expr::value(OutboxEventStatus::Pending),
+                )
+                .col_expr(outbox_event::Column::Attempts, Expr::value(0))
+                .col_expr(outbox_event::Column::NextAttemptAt, Expr::value(now))
+                .col_expr(outbox_event::Column::UpdatedAt, Expr::value(now))
+                .filter(outbox_event::Column::Id.eq(dlq_entry.entity_id))
+                .exec(db)
+                .await?
+        }
+        IP_RETURN_TASK_TYPE => {
+            Session::update_many()
+                .col_expr(session::Column::IpReturnRetryCount, Expr::value(0))
+                .filter(session::Column::Id.eq(dlq_entry.entity_id))
+                .exec(db)
+                .await?
+        }
+        other => return Err(RetryError::UnsupportedTaskType(other.to_string())),
+    };
+    if retried.rows_affected == 0 {
+        return Err(RetryError::EntityGone(
+            if dlq_entry.task_type == IP_RETURN_TASK_TYPE {
+                "Session"
+            } else {
+                "Outbox event"
+            },
+        ));
+    }
+
+    observe_age(&dlq_entry, "retried");
+    let mut active_entry: ActiveModel = dlq_entry.into();
+    active_entry.status = Set(DlqStatus::Resolved);
+    active_entry.resolution_notes = Set(Some(
+        resolution_notes.unwrap_or_else(|| "Retried".to_string()),
+    ));
+    active_entry.updated_at = NotSet;
+
+    Ok(active_entry.update(db).await?)
+}
+
+/// Record in `dlq_entry_age_seconds` how long a pending entry sat in the DLQ before `outcome`
+fn observe_age(entry: &Model, outcome: &str) {
+    if entry.status != DlqStatus::Pending {
+        return;
+    }
+    let age = chrono::Utc::now().signed_duration_since(entry.created_at);
+    crate::metrics::get()
+        .dlq_entry_age_seconds
+        .with_label_values(&[&entry.task_type, outcome])
+        .observe(age.num_milliseconds().max(0) as f64 / 1000.0);
+}
+
+/// Pending entries of one task type
+#[derive(Debug, Clone, FromQueryResult)]
+pub struct PendingDlqStats {
+    pub task_type: String,
+    pub pending: i64,
+    /// When the oldest pending entry entered the DLQ
+    pub oldest_created_at: DateTimeWithTimeZone,
+}
+
+impl PendingDlqStats {
+    /// Seconds the oldest pending entry has been waiting
+    pub fn oldest_age_secs(&self, now: chrono::DateTime<chrono::Utc>) -> u64 {
+        now.signed_duration_since(self.oldest_created_at)
+            .num_seconds()
+            .max(0) as u64
+    }
+}
+
+/// Pending entry counts and oldest entry by task type
+pub async fn pending_stats(
+    db: &DatabaseConnection,
+) -> Result<Vec<PendingDlqStats>, sea_orm::DbErr> {
+    PendingDlqStats::find_by_statement(Statement::from_sql_and_values(
+        DbBackend::Postgres,
+        r#"SELECT task_type, COUNT(*)::bigint AS pending, MIN(created_at) AS oldest_created_at
+           FROM dead_letter_queue
+           WHERE status = $1
+           GROUP BY task_type
+           ORDER BY task_type"#,
+        [DlqStatus::Pending.to_value().into()],
+    ))
+    .all(db)
+    .await
+}
@@ -0,0 +1,168 @@
+//! Cleanup of everything a removed user owns.
+//!
+//! Running and queued sessions get a cancellation request, reviewed sessions are archived so
+//! the IP return poller hands their sandboxes back, sandboxes held for unpushed work are
+//! released, and every session is soft-deleted. The
+//! actual kill and IP return happen in the cancellation enforcer and IP return poller; this
+//! only puts the sessions in the states those loops act on.
+
+use chrono::Utc;
+use sea_orm::{ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, Set};
+use tracing::info;
+
+use crate::entities::session::{self, CancellationStatus, Entity as Session, UiStatus};
+use crate::entities::session_event::SessionEventType;
+use crate::entities::user_deprovision;
+use crate::services::session_events;
+use crate::services::session_state_machine::{Actor, SessionStateMachine, TransitionCause};
+
+/// What deprovisioning did to a user's sessions
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DeprovisionSummary {
+    /// Running or queued sessions whose cancellation was requested
+    pub sessions_cancelled: i32,
+    /// Reviewed sessions archived so their IPs are returned
+    pub sessions_archived: i32,
+    /// Sessions marked deleted
+    pub sessions_deleted: i32,
+}
+
+/// What happens to a session in `ui_status` when its owner is deprovisioned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Cleanup {
+    Cancel,
+    Archive,
+    /// Archive a session held for unpushed work, discarding the work
+    Release,
+    Nothing,
+}
+
+fn cleanup_for(ui_status: &UiStatus) -> Cleanup {
+    match ui_status {
+        UiStatus::Pending | UiStatus::WaitingForSandbox | UiStatus::InProgress => Cleanup::Cancel,
+        UiStatus::NeedsReview | UiStatus::NeedsReviewIpReturned => Cleanup::Archive,
+        UiStatus::NeedsAttention => Cleanup::Release,
+        UiStatus::Archived => Cleanup::Nothing,
+    }
+}
+
+/// Cancel, archive and soft-delete every session of `user_id` and record an audit row.
+///
+/// Sessions deleted by an earlier run are skipped, so calling this again is harmless.
+pub async fn deprovision_user(
+    db: &DatabaseConnection,
+    user_id: &str,
+    actor: &Actor,
+) -> Result<DeprovisionSummary, sea_orm::DbErr> {
+    let sessions = Session::find()
+        .filter(session::Column::UserId.eq(user_id))
+        .filter(session::Column::DeletedAt.is_null())
+        .all(db)
+        .await?;
+
+    let now = Utc::now();
+    let mut summary = DeprovisionSummary::default();
+
+    for session in sessions {
+        let cleanup = cleanup_for(&session.ui_status);
+        let already_cancelled = session.cancellation_status.is_some();
+
+        let from = session.ui_status.clone();
+        let mut archived = None;
+        let mut active_session = match cleanup {
+            Cleanup::Archive | Cleanup::Release => {
+                let cause = if cleanup == Cleanup::Release {
+                    TransitionCause::ForceReleased
+                } else {
+                    TransitionCause::Archived
+                };
+                match SessionStateMachine::transition(
+                    session.clone(),
+                    UiStatus::Archived,
+                    cause,
+                    actor,
+                ) {
+                    Ok(active_session) => {
+                        summary.sessions_archived += 1;
+                        archived = Some(cause);
+                        active_session
+                    }
+                    Err(_) => session.into(),
+                }
+            }
+            Cleanup::Cancel | Cleanup::Nothing => session.into(),
+        };
+
+        let cancelled = cleanup == Cleanup::Cancel && !already_cancelled;
+        if cancelled {
+            active_session.cancellation_status = Set(Some(CancellationStatus::Requested));
+            active_session.cancelled_at = Set(Some(now.into()));
+            active_session.cancelled_by = Set(Some(actor.to_string()));
+            summary.sessions_cancelled += 1;
+        }
+
+        active_session.deleted_at = Set(Some(now.into()));
+        active_session.updated_at = Set(now.into());
+        let updated = active_session.update(db).await?;
+        summary.sessions_deleted += 1;
+
+        if let Some(cause) = archived {
+            SessionStateMachine::after_save(db, &from, &updated, cause, actor).await;
+        }
+        if cancelled {
+            session_events::record(
+                db,
+                updated.id,
+                SessionEventType::CancellationRequested,
+                actor,
+                None,
+            )
+            .await;
+        }
+        session_events::record(
+            db,
+            updated.id,
+            SessionEventType::Deleted,
+            actor,
+            Some(serde_json::json!({ "reason": "deprovisioned" })),
+        )
+        .await;
+    }
+
+    let audit = user_deprovision::ActiveModel {
+        id: Set(uuid::Uuid::new_v4()),
+        user_id: Set(user_id.to_string()),
+        requested_by: Set(actor.to_string()),
+        sessions_cancelled: Set(summary.sessions_cancelled),
+        sessions_archived: Set(summary.sessions_archived),
+        sessions_deleted: Set(summary.sessions_deleted),
+        created_at: Set(now.into()),
+    };
+    audit.insert(db).await?;
+
+    info!(
+        target: "session_audit",
+        user_id = %user_id,
+        actor = %actor,
+        "Deprovisioned user: {} sessions cancelled, {} archived, {} deleted",
+        summary.sessions_cancelled,
+        summary.sessions_archived,
+        summary.sessions_deleted
+    );
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cleanup_for_status() {
+        assert_eq!(cleanup_for(&UiStatus::InProgress), Cleanup::Cancel);
+        assert_eq!(cleanup_for(&UiStatus::WaitingForSandbox), Cleanup::Cancel);
+        assert_eq!(cleanup_for(&UiStatus::NeedsReview), Cleanup::Archive);
+        assert_eq!(cleanup_for(&UiStatus::NeedsAttention), Cleanup::Release);
+        assert_eq!(cleanup_for(&UiStatus::Archived), Cleanup::Nothing);
+    }
+}
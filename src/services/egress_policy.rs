@@ -0,0 +1,295 @@
+//! Per-session limits on where the sandbox may connect to during runs.
+//!
+//! A policy either allows a list of domains or denies external network altogether. Before the
+//! Claude CLI starts, the outbox publisher installs it as an iptables chain in the sandbox
+//! through its shell API. The domains are resolved to addresses in the sandbox at that point.
+//! The repo's GitHub host, its API and the agent callback host stay reachable so the run can
+//! push and report, as do the domains in `SANDBOX_EGRESS_ALWAYS_ALLOW`. Loopback, DNS and
+//! replies on established connections (including the worker's requests to the sandbox API)
+//! are always let through.
+//!
+//! Sandboxes are reused across sessions, so runs without a policy remove any chain an earlier
+//! session left behind. The rules that were installed are stored on the session as its
+//! effective policy.
+
+use chrono::Utc;
+use rocket_okapi::okapi::schemars::{self, JsonSchema};
+use sandbox_client::types::ShellExecRequest;
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+use crate::services::github_host::GithubHost;
+
+/// Most domains accepted in an allowlist
+const MAX_DOMAINS: usize = 100;
+
+/// iptables chain holding the rules, jumped to from `OUTPUT`
+const CHAIN: &str = "PROMPT_EGRESS";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum EgressMode {
+    /// Only the listed domains may be reached
+    Allowlist,
+    /// No external network; only what the run itself needs stays reachable
+    DenyExternal,
+}
+
+/// Where a session's sandbox may connect to during runs
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct EgressPolicy {
+    pub mode: EgressMode,
+    /// Domains reachable in `Allowlist` mode, e.g. `registry.npmjs.org`; subdomains have to be
+    /// listed separately
+    #[serde(default)]
+    pub allowed_domains: Vec<String>,
+}
+
+/// The rules a run installed for its session's policy
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct EffectiveEgressPolicy {
+    pub mode: EgressMode,
+    /// Every domain that was allowed: the policy's own plus those the run needs
+    pub allowed_domains: Vec<String>,
+    /// When the rules were installed, RFC 3339
+    pub applied_at: String,
+}
+
+impl EgressPolicy {
+    /// The policy stored on a session, None when it has none
+    pub fn from_json(value: Option<&serde_json::Value>) -> Option<EgressPolicy> {
+        serde_json::from_value(value?.clone()).ok()
+    }
+
+    /// Check the domains before the policy is stored. They end up quoted in a shell script,
+    /// so only plain host names are accepted.
+    pub fn validate(&self) -> Result<(), String> {
+        match self.mode {
+            EgressMode::DenyExternal if !self.allowed_domains.is_empty() => {
+                return Err(
+                    "egress_policy.allowed_domains must be empty in DenyExternal mode".to_string(),
+                )
+            }
+            EgressMode::Allowlist if self.allowed_domains.len() > MAX_DOMAINS => {
+                return Err(format!(
+                    "egress_policy.allowed_domains may have at most {} domains",
+                    MAX_DOMAINS
+                ))
+            }
+            _ => {}
+        }
+        for domain in &self.allowed_domains {
+            if !is_valid_domain(domain) {
+                return Err(format!(
+                    "Invalid egress_policy.allowed_domains entry {:?}: expected a host name \
+                     such as pypi.org",
+                    domain
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// The policy with the domains a run on `host` needs added
+    pub fn effective(&self, host: &GithubHost) -> EffectiveEgressPolicy {
+        let mut domains: Vec<String> = self
+            .allowed_domains
+            .iter()
+            .map(|d| d.to_ascii_lowercase())
+            .chain(required_domains(host))
+            .collect();
+        domains.sort();
+        domains.dedup();
+        EffectiveEgressPolicy {
+            mode: self.mode,
+            allowed_domains: domains,
+            applied_at: Utc::now().to_rfc3339(),
+        }
+    }
+
+    /// Section appended to the system prompt so Claude does not burn time on blocked requests
+    pub fn prompt_section(&self, effective: &EffectiveEgressPolicy) -> String {
+        let mut section = String::from(
+            "\n\n## Network Policy\n\nNetwork access from this sandbox is restricted. \
+             Connections to other hosts are rejected; do not try to work around this.\n",
+        );
+        match self.mode {
+            EgressMode::DenyExternal => section.push_str(
+                "\nExternal network is disabled apart from the git host needed to push your work.\n",
+            ),
+            EgressMode::Allowlist => {
+                section.push_str("\nOnly these hosts can be reached:\n");
+                for domain in &effective.allowed_domains {
+                    section.push_str(&format!("- `{}`\n", domain));
+                }
+            }
+        }
+        section
+    }
+}
+
+fn is_valid_domain(domain: &str) -> bool {
+    domain.len() <= 253
+        && domain.contains('.')
+        && domain.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+/// Domains every run on `host` needs: the git host, its API, the agent callback host and the
+/// configured extras
+fn required_domains(host: &GithubHost) -> Vec<String> {
+    let mut domains = vec![host.hostname.clone()];
+    let urls = std::iter::once(host.api_base_url()).chain(config::get().agent_callback_url.clone());
+    for url in urls {
+        if let Some(domain) = reqwest::Url::parse(&url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string))
+        {
+            domains.push(domain);
+        }
+    }
+    domains.extend(config::get().egress_always_allowed.iter().cloned());
+    domains
+        .into_iter()
+        .map(|d| d.to_ascii_lowercase())
+        .filter(|d| is_valid_domain(d))
+        .collect()
+}
+
+/// Shell that replaces the sandbox's egress chain with rules for `effective`
+pub fn firewall_script(effective: &EffectiveEgressPolicy) -> String {
+    let domains: Vec<String> = effective
+        .allowed_domains
+        .iter()
+        .map(|d| format!("'{}'", d))
+        .collect();
+    format!(
+        r#"set -e
+ipt="sudo -n iptables"
+$ipt -N {chain} 2>/dev/null || $ipt -F {chain}
+$ipt -C OUTPUT -j {chain} 2>/dev/null || $ipt -I OUTPUT 1 -j {chain}
+$ipt -A {chain} -o lo -j RETURN
+$ipt -A {chain} -m conntrack --ctstate ESTABLISHED,RELATED -j RETURN
+$ipt -A {chain} -p udp --dport 53 -j RETURN
+$ipt -A {chain} -p tcp --dport 53 -j RETURN
+for domain in {domains}; do
+  for ip in $(getent ahostsv4 "$domain" | awk '{{print $1}}' | sort -u); do
+    $ipt -A {chain} -d "$ip" -j RETURN
+  done
+done
+$ipt -A {chain} -j REJECT
+"#,
+        chain = CHAIN,
+        domains = domains.join(" "),
+    )
+}
+
+/// Shell that removes the egress chain, if there is one
+pub fn reset_script() -> String {
+    format!(
+        "sudo -n iptables -D OUTPUT -j {chain} 2>/dev/null; \
+         sudo -n iptables -F {chain} 2>/dev/null; \
+         sudo -n iptables -X {chain} 2>/dev/null; true",
+        chain = CHAIN
+    )
+}
+
+/// Install `policy` in the sandbox for a run on `host`, or remove the rules of an earlier
+/// session when there is none. Returns the effective policy.
+pub async fn apply(
+    sbx: &sandbox_client::Client,
+    policy: Option<&EgressPolicy>,
+    host: &GithubHost,
+) -> Result<Option<EffectiveEgressPolicy>, String> {
+    let effective = policy.map(|policy| policy.effective(host));
+    let command = match &effective {
+        Some(effective) => firewall_script(effective),
+        None => reset_script(),
+    };
+
+    let result = sbx
+        .exec_command_v1_shell_exec_post(&ShellExecRequest {
+            command,
+            async_mode: false,
+            id: None,
+            timeout: Some(60.0_f64),
+            exec_dir: Some(String::from("/home/gem")),
+        })
+        .await
+        .map_err(|e| e.to_string())?
+        .into_inner()
+        .data;
+
+    match result {
+        Some(result) if effective.is_some() && result.exit_code != Some(0) => Err(format!(
+            "Failed to install egress rules (exit code {:?}): {}",
+            result.exit_code,
+            result.output.unwrap_or_default().trim()
+        )),
+        _ => Ok(effective),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let policy = |mode, domains: &[&str]| EgressPolicy {
+            mode,
+            allowed_domains: domains.iter().map(|d| d.to_string()).collect(),
+        };
+
+        assert!(policy(
+            EgressMode::Allowlist,
+            &["pypi.org", "files.pythonhosted.org"]
+        )
+        .validate()
+        .is_ok());
+        assert!(policy(EgressMode::DenyExternal, &[]).validate().is_ok());
+        assert!(policy(EgressMode::DenyExternal, &["pypi.org"])
+            .validate()
+            .is_err());
+        assert!(policy(EgressMode::Allowlist, &["*.example.com"])
+            .validate()
+            .is_err());
+        assert!(policy(EgressMode::Allowlist, &["x.org'; rm -rf /"])
+            .validate()
+            .is_err());
+        assert!(policy(EgressMode::Allowlist, &["localhost"])
+            .validate()
+            .is_err());
+    }
+
+    #[test]
+    fn test_effective_adds_git_host() {
+        let policy = EgressPolicy {
+            mode: EgressMode::Allowlist,
+            allowed_domains: vec!["PyPI.org".to_string(), "github.com".to_string()],
+        };
+        let host = GithubHost {
+            hostname: "github.com".to_string(),
+            idp_alias: None,
+        };
+        let effective = policy.effective(&host);
+
+        assert!(effective.allowed_domains.contains(&"pypi.org".to_string()));
+        assert!(effective
+            .allowed_domains
+            .contains(&"api.github.com".to_string()));
+        assert_eq!(
+            effective
+                .allowed_domains
+                .iter()
+                .filter(|d| *d == "github.com")
+                .count(),
+            1
+        );
+        assert!(firewall_script(&effective).contains("'pypi.org'"));
+    }
+}
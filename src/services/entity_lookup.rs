@@ -0,0 +1,195 @@
+//! Find what a bare UUID from a log line refers to.
+//!
+//! Sessions, prompts, messages and DLQ entries all use UUID primary keys, so an id alone does
+//! not say which table to look in. `lookup` checks each of them, deleted sessions included, and
+//! also returns DLQ entries whose `entity_id` is the id, since a failed job is usually logged
+//! under the entity it was working on. Every match carries its owning user, the session it
+//! belongs to, the state fields triage starts from, and API paths to read more.
+
+use rocket_okapi::okapi::schemars::{self, JsonSchema};
+use sea_orm::{ColumnTrait, Condition, DatabaseConnection, DbErr, EntityTrait, QueryFilter};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+use crate::entities::dead_letter_queue::{self, Entity as DeadLetterQueue};
+use crate::entities::message::Entity as Message;
+use crate::entities::prompt::{Entity as Prompt, Model as PromptModel};
+use crate::entities::session::{Entity as Session, Model as SessionModel};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum EntityKind {
+    Session,
+    Prompt,
+    Message,
+    DeadLetterQueueEntry,
+}
+
+/// A row the id was found in
+#[derive(Debug, Clone)]
+pub struct EntityMatch {
+    pub kind: EntityKind,
+    /// Primary key of the row; differs from the looked-up id for DLQ entries about it
+    pub id: Uuid,
+    /// User owning the session the row belongs to
+    pub owner: Option<String>,
+    pub session_id: Option<Uuid>,
+    /// Key state fields of the row
+    pub state: Value,
+    /// API paths by name, e.g. `self` and `events`
+    pub links: BTreeMap<String, String>,
+}
+
+fn links(pairs: &[(&str, String)]) -> BTreeMap<String, String> {
+    pairs
+        .iter()
+        .map(|(name, path)| (name.to_string(), path.clone()))
+        .collect()
+}
+
+fn session_match(session: SessionModel) -> EntityMatch {
+    EntityMatch {
+        kind: EntityKind::Session,
+        id: session.id,
+        owner: Some(session.user_id),
+        session_id: Some(session.id),
+        state: json!({
+            "ui_status": session.ui_status,
+            "status_message": session.status_message,
+            "repo": session.repo,
+            "branch": session.branch,
+            "cancellation_status": session.cancellation_status,
+            "worker_instance_id": session.worker_instance_id,
+            "process_pid": session.process_pid,
+            "created_at": session.created_at.to_rfc3339(),
+            "deleted_at": session.deleted_at.map(|at| at.to_rfc3339()),
+        }),
+        links: links(&[
+            ("self", format!("/sessions/{}", session.id)),
+            ("events", format!("/sessions/{}/events", session.id)),
+            (
+                "conversation",
+                format!("/sessions/{}/conversation", session.id),
+            ),
+        ]),
+    }
+}
+
+fn prompt_match(prompt: PromptModel, owner: Option<String>) -> EntityMatch {
+    EntityMatch {
+        kind: EntityKind::Prompt,
+        id: prompt.id,
+        owner,
+        session_id: Some(prompt.session_id),
+        state: json!({
+            "draft": prompt.draft,
+            "priority": prompt.priority,
+            "run_id": prompt.run_id,
+            "started_at": prompt.started_at.map(|at| at.to_rfc3339()),
+            "completed_at": prompt.completed_at.map(|at| at.to_rfc3339()),
+            "exit_code": prompt.exit_code,
+            "error_kind": prompt.error_kind,
+            "created_at": prompt.created_at.to_rfc3339(),
+        }),
+        links: links(&[
+            ("self", format!("/prompts/{}", prompt.id)),
+            ("run", format!("/prompts/{}/run", prompt.id)),
+            ("session", format!("/sessions/{}", prompt.session_id)),
+        ]),
+    }
+}
+
+/// Owner of the session `session_id`, deleted or not
+async fn session_owner(db: &DatabaseConnection, session_id: Uuid) -> Result<Option<String>, DbErr> {
+    Ok(Session::find_by_id(session_id)
+        .one(db)
+        .await?
+        .map(|s| s.user_id))
+}
+
+/// Session and owner of a DLQ entry's entity, when it is a session or prompt that still exists
+async fn dlq_entity_owner(
+    db: &DatabaseConnection,
+    entity_id: Uuid,
+) -> Result<(Option<Uuid>, Option<String>), DbErr> {
+    if let Some(session) = Session::find_by_id(entity_id).one(db).await? {
+        return Ok((Some(session.id), Some(session.user_id)));
+    }
+    if let Some(prompt) = Prompt::find_by_id(entity_id).one(db).await? {
+        let owner = session_owner(db, prompt.session_id).await?;
+        return Ok((Some(prompt.session_id), owner));
+    }
+    Ok((None, None))
+}
+
+/// Every row `id` names, and every DLQ entry about it
+pub async fn lookup(db: &DatabaseConnection, id: Uuid) -> Result<Vec<EntityMatch>, DbErr> {
+    let mut matches = Vec::new();
+
+    if let Some(session) = Session::find_by_id(id).one(db).await? {
+        matches.push(session_match(session));
+    }
+
+    if let Some(prompt) = Prompt::find_by_id(id).one(db).await? {
+        let owner = session_owner(db, prompt.session_id).await?;
+        matches.push(prompt_match(prompt, owner));
+    }
+
+    if let Some(message) = Message::find_by_id(id).one(db).await? {
+        let prompt = Prompt::find_by_id(message.prompt_id).one(db).await?;
+        let session_id = prompt.as_ref().map(|p| p.session_id);
+        let owner = match session_id {
+            Some(session_id) => session_owner(db, session_id).await?,
+            None => None,
+        };
+        let mut message_links = vec![
+            ("self", format!("/messages/{}", message.id)),
+            ("prompt", format!("/prompts/{}", message.prompt_id)),
+        ];
+        if let Some(session_id) = session_id {
+            message_links.push(("session", format!("/sessions/{}", session_id)));
+        }
+        matches.push(EntityMatch {
+            kind: EntityKind::Message,
+            id: message.id,
+            owner,
+            session_id,
+            state: json!({
+                "prompt_id": message.prompt_id,
+                "offloaded": message.blob_key.is_some(),
+                "created_at": message.created_at.to_rfc3339(),
+            }),
+            links: links(&message_links),
+        });
+    }
+
+    let entries = DeadLetterQueue::find()
+        .filter(
+            Condition::any()
+                .add(dead_letter_queue::Column::Id.eq(id))
+                .add(dead_letter_queue::Column::EntityId.eq(id)),
+        )
+        .all(db)
+        .await?;
+    for entry in entries {
+        let (session_id, owner) = dlq_entity_owner(db, entry.entity_id).await?;
+        matches.push(EntityMatch {
+            kind: EntityKind::DeadLetterQueueEntry,
+            id: entry.id,
+            owner,
+            session_id,
+            state: json!({
+                "task_type": entry.task_type,
+                "entity_id": entry.entity_id,
+                "status": entry.status,
+                "retry_count": entry.retry_count,
+                "last_error": entry.last_error,
+                "last_error_at": entry.last_error_at.to_rfc3339(),
+            }),
+            links: links(&[("self", format!("/dead-letter-queue/{}", entry.id))]),
+        });
+    }
+
+    Ok(matches)
+}
@@ -0,0 +1,223 @@
+//! Fan-out: the same prompt run against many repos.
+//!
+//! `POST /sessions/fan-out` creates a tracking session, marked by `fan_out_limit`, and one child
+//! session per repo with the tracking session as their parent. The tracking session never runs
+//! itself: it stays in progress while any child still has to run and the prompt poller moves it
+//! to review once they are all done. The poller starts at most `fan_out_limit` children at a
+//! time, so a large fan-out queues behind itself instead of taking every sandbox at once.
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+
+use sea_orm::{
+    ColumnTrait, DatabaseConnection, DbErr, EntityTrait, Iterable, PaginatorTrait, QueryFilter,
+    QueryOrder,
+};
+use uuid::Uuid;
+
+use crate::entities::session::{
+    self, CancellationStatus, Entity as Session, Model as SessionModel, UiStatus,
+};
+use crate::services::sandbox_queue::queued_statuses;
+
+/// Whether `child` is running or will still run. Queued children with a cancellation requested
+/// are never started, so they count as done.
+pub fn is_unfinished(child: &SessionModel) -> bool {
+    match child.ui_status {
+        UiStatus::InProgress => true,
+        ref status if queued_statuses().contains(status) => {
+            child.cancellation_status != Some(CancellationStatus::Requested)
+        }
+        _ => false,
+    }
+}
+
+/// Child sessions of the fan-out tracked by `parent_id`, in repo order
+pub async fn children(
+    db: &DatabaseConnection,
+    parent_id: Uuid,
+) -> Result<Vec<SessionModel>, DbErr> {
+    Session::find()
+        .filter(session::Column::Parent.eq(parent_id))
+        .order_by_asc(session::Column::CreatedAt)
+        .order_by_asc(session::Column::Repo)
+        .all(db)
+        .await
+}
+
+/// Child counts of a fan-out by status
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Summary {
+    pub total: u64,
+    /// Children per `ui_status`, in the order of `UiStatus`
+    pub by_status: Vec<(UiStatus, u64)>,
+    /// Children whose cancellation was requested or carried out
+    pub cancelled: u64,
+    /// Children running or still to run, see `is_unfinished`
+    pub unfinished: u64,
+}
+
+impl Summary {
+    /// One line description, used as the tracking session's status message
+    pub fn describe(&self) -> String {
+        let mut message = format!(
+            "{} of {} sessions finished",
+            self.total - self.unfinished,
+            self.total
+        );
+        if self.cancelled > 0 {
+            message.push_str(&format!(", {} cancelled", self.cancelled));
+        }
+        message
+    }
+}
+
+pub fn summarize(children: &[SessionModel]) -> Summary {
+    let count = |f: fn(&SessionModel) -> bool| children.iter().filter(|c| f(c)).count() as u64;
+    Summary {
+        total: children.len() as u64,
+        by_status: UiStatus::iter()
+            .map(|status| {
+                let n = children.iter().filter(|c| c.ui_status == status).count() as u64;
+                (status, n)
+            })
+            .collect(),
+        cancelled: count(|c| c.cancellation_status.is_some()),
+        unfinished: count(is_unfinished),
+    }
+}
+
+/// Per poll view of how many children of each fan-out hold a sandbox, so children started
+/// earlier in the same poll count against the limit
+#[derive(Debug, Default)]
+pub struct Slots {
+    /// Limit and running children by tracking session, None when the parent is not a fan-out
+    parents: HashMap<Uuid, Option<(u64, u64)>>,
+}
+
+impl Slots {
+    /// Why `session` has to wait for a sibling to finish, None when it may start now
+    pub async fn hold_reason(
+        &mut self,
+        db: &DatabaseConnection,
+        session: &SessionModel,
+    ) -> Result<Option<String>, DbErr> {
+        let Some(parent_id) = session.parent else {
+            return Ok(None);
+        };
+        if let Entry::Vacant(entry) = self.parents.entry(parent_id) {
+            let slots = match Session::find_by_id(parent_id)
+                .one(db)
+                .await?
+                .and_then(|parent| parent.fan_out_limit)
+            {
+                Some(limit) => {
+                    let running = Session::find()
+                        .filter(session::Column::Parent.eq(parent_id))
+                        .filter(session::Column::UiStatus.eq(UiStatus::InProgress))
+                        .count(db)
+                        .await?;
+                    Some((limit.max(1) as u64, running))
+                }
+                None => None,
+            };
+            entry.insert(slots);
+        }
+
+        Ok(match self.parents.get(&parent_id) {
+            Some(Some((limit, running))) if running >= limit => Some(format!(
+                "Waiting for a fan-out slot ({} of {} sessions running)",
+                running, limit
+            )),
+            _ => None,
+        })
+    }
+
+    /// Count `session` as holding a sandbox from now on
+    pub fn claim(&mut self, session: &SessionModel) {
+        if let Some(Some((_, running))) = session.parent.and_then(|p| self.parents.get_mut(&p)) {
+            *running += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn child(ui_status: UiStatus, cancellation_status: Option<CancellationStatus>) -> SessionModel {
+        let now = chrono::Utc::now().into();
+        SessionModel {
+            id: Uuid::new_v4(),
+            sbx_config: None,
+            parent: None,
+            branch: None,
+            repo: Some("owner/repo".to_string()),
+            target_branch: Some("main".to_string()),
+            title: None,
+            ui_status,
+            created_at: now,
+            updated_at: now,
+            deleted_at: None,
+            user_id: "user".to_string(),
+            ip_return_retry_count: 0,
+            cancellation_status,
+            cancelled_at: None,
+            cancelled_by: None,
+            process_pid: None,
+            status_message: None,
+            sandbox_borrow_attempts: 0,
+            next_borrow_attempt_at: None,
+            tags: serde_json::json!([]),
+            path_policy: None,
+            title_pending: false,
+            agent_token_hash: None,
+            agent_token_expires_at: None,
+            org_id: None,
+            ip_return_key: None,
+            model: None,
+            cancellation_mode: None,
+            cancel_signalled_at: None,
+            fan_out_limit: None,
+            egress_policy: None,
+            effective_egress_policy: None,
+            pull_request_description: None,
+            keep_sandbox_until_archive: false,
+            unpushed_work: None,
+            region: None,
+            sandbox_region: None,
+            worker_id: None,
+            worker_claimed_at: None,
+            last_activity_at: None,
+            budget: None,
+            estimated_start_at: None,
+            worker_instance_id: None,
+            sandbox_capabilities: None,
+            message_compaction: None,
+            title_attempts: 0,
+            title_retry_at: None,
+            workspace_disk_bytes: None,
+            workspace_disk_checked_at: None,
+            ingestion_filter: None,
+            messages_filtered: 0,
+        }
+    }
+
+    #[test]
+    fn test_summarize_counts_cancelled_queued_children_as_finished() {
+        let children = [
+            child(UiStatus::Pending, None),
+            child(UiStatus::Pending, Some(CancellationStatus::Requested)),
+            child(UiStatus::InProgress, Some(CancellationStatus::Requested)),
+            child(UiStatus::NeedsReview, None),
+        ];
+        let summary = summarize(&children);
+
+        assert_eq!(summary.total, 4);
+        assert_eq!(summary.unfinished, 2);
+        assert_eq!(summary.cancelled, 2);
+        assert!(summary.by_status.contains(&(UiStatus::Pending, 2)));
+        assert!(summary.by_status.contains(&(UiStatus::Archived, 0)));
+        assert_eq!(summary.describe(), "2 of 4 sessions finished, 2 cancelled");
+    }
+}
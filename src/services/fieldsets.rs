@@ -0,0 +1,180 @@
+//! Sparse fieldsets for heavy response items.
+//!
+//! Read and list endpoints of sessions and messages take `?fields=id,title,uiStatus` and return
+//! only the named fields of each item, so list views can leave out blobs such as `sbxConfig` or
+//! message `data`. Names are the fields as serialized and `id` is always included. Without
+//! `fields` items are returned in full.
+
+use rocket_okapi::okapi::schemars::gen::SchemaGenerator;
+use rocket_okapi::okapi::schemars::schema::Schema;
+use rocket_okapi::okapi::schemars::JsonSchema;
+use serde::ser::{Error as _, SerializeMap};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+/// Field every item keeps
+const ALWAYS_INCLUDED: &str = "id";
+
+/// Fields requested with `?fields=`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fieldset(BTreeSet<String>);
+
+impl Fieldset {
+    /// Parse a comma-separated `?fields=` against the fields of `T`. None when it was not
+    /// given or is empty, so the full item is returned.
+    pub fn parse<T: JsonSchema>(fields: Option<&str>) -> Result<Option<Arc<Fieldset>>, String> {
+        let requested: BTreeSet<String> = fields
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|f| !f.is_empty())
+            .map(str::to_string)
+            .collect();
+        if requested.is_empty() {
+            return Ok(None);
+        }
+
+        let known = field_names::<T>();
+        let unknown: Vec<&str> = requested
+            .iter()
+            .filter(|f| !known.contains(*f))
+            .map(String::as_str)
+            .collect();
+        if !unknown.is_empty() {
+            return Err(format!(
+                "Unknown fields: {}; available fields: {}",
+                unknown.join(", "),
+                known.into_iter().collect::<Vec<_>>().join(", ")
+            ));
+        }
+
+        let mut fields = requested;
+        fields.insert(ALWAYS_INCLUDED.to_string());
+        Ok(Some(Arc::new(Fieldset(fields))))
+    }
+
+    pub fn contains(&self, field: &str) -> bool {
+        self.0.contains(field)
+    }
+}
+
+/// Serialized field names of `T`, from its JSON schema
+fn field_names<T: JsonSchema>() -> BTreeSet<String> {
+    let root = SchemaGenerator::default().into_root_schema_for::<T>();
+    root.schema
+        .object
+        .map(|object| object.properties.into_keys().collect())
+        .unwrap_or_default()
+}
+
+/// An item serialized with only the fields of a `Fieldset`, or in full without one.
+///
+/// Its schema is the item's, so the API description still lists every field.
+#[derive(Debug, Clone)]
+pub struct Sparse<T> {
+    item: T,
+    fields: Option<Arc<Fieldset>>,
+}
+
+impl<T> Sparse<T> {
+    pub fn new(item: T, fields: Option<&Arc<Fieldset>>) -> Self {
+        Sparse {
+            item,
+            fields: fields.cloned(),
+        }
+    }
+
+    /// The whole item, whatever fields were selected
+    #[cfg_attr(not(feature = "grpc"), allow(dead_code))]
+    pub fn into_inner(self) -> T {
+        self.item
+    }
+}
+
+impl<T: Serialize> Serialize for Sparse<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let Some(fields) = &self.fields else {
+            return self.item.serialize(serializer);
+        };
+        match serde_json::to_value(&self.item).map_err(S::Error::custom)? {
+            Value::Object(object) => {
+                let mut map = serializer.serialize_map(None)?;
+                for (key, value) in object.iter().filter(|(key, _)| fields.contains(key)) {
+                    map.serialize_entry(key, value)?;
+                }
+                map.end()
+            }
+            other => other.serialize(serializer),
+        }
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Sparse<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize(deserializer).map(|item| Sparse { item, fields: None })
+    }
+}
+
+impl<T: JsonSchema> JsonSchema for Sparse<T> {
+    fn is_referenceable() -> bool {
+        T::is_referenceable()
+    }
+
+    fn schema_name() -> String {
+        T::schema_name()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        T::json_schema(gen)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket_okapi::okapi::schemars;
+
+    #[derive(Serialize, JsonSchema)]
+    #[serde(rename_all = "camelCase")]
+    struct Item {
+        id: u32,
+        ui_status: String,
+        sbx_config: Value,
+    }
+
+    fn item() -> Item {
+        Item {
+            id: 7,
+            ui_status: "pending".to_string(),
+            sbx_config: serde_json::json!({"large": true}),
+        }
+    }
+
+    #[test]
+    fn test_only_requested_fields_and_id_are_serialized() {
+        let fields = Fieldset::parse::<Item>(Some("uiStatus, ")).unwrap();
+        assert_eq!(
+            serde_json::to_value(Sparse::new(item(), fields.as_ref())).unwrap(),
+            serde_json::json!({"id": 7, "uiStatus": "pending"})
+        );
+
+        let full = Fieldset::parse::<Item>(None).unwrap();
+        assert!(full.is_none());
+        assert_eq!(
+            serde_json::to_value(Sparse::new(item(), full.as_ref())).unwrap(),
+            serde_json::to_value(item()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_unknown_fields_are_rejected() {
+        let error = Fieldset::parse::<Item>(Some("uiStatus,sbx_config")).unwrap_err();
+        assert!(
+            error.starts_with("Unknown fields: sbx_config;"),
+            "{}",
+            error
+        );
+    }
+}
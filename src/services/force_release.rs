@@ -0,0 +1,169 @@
+//! Admin release of a session whose sandbox IP return is stuck.
+//!
+//! When the IP return poller gives up on a session, or a DLQ entry about it was handled out of
+//! band, the session keeps its `sbx_config` and is retried or skipped forever. A force release
+//! returns the item to the allocator unless told not to, then archives the session, clears its
+//! sandbox, resolves its pending IP return DLQ entries and appends an `ip_force_released`
+//! event in one transaction, so none of these is left half done.
+
+use chrono::Utc;
+use sea_orm::{
+    ActiveEnum, ActiveModelTrait, DatabaseConnection, DbErr, EntityTrait, Set, TransactionTrait,
+};
+use tracing::info;
+
+use crate::entities::session::{self, Entity as Session, Model as SessionModel, UiStatus};
+use crate::entities::session_event::{self, SessionEventType};
+use crate::services::dead_letter_queue::{self, IP_RETURN_TASK_TYPE};
+use crate::services::ip_allocator::{self, ReturnOutcome};
+use crate::services::session_state_machine::{Actor, SessionStateMachine, TransitionCause};
+
+#[derive(Debug, thiserror::Error)]
+pub enum ForceReleaseError {
+    #[error("Session not found")]
+    NotFound,
+    #[error("Session is {}; cancel it before releasing its sandbox", .0.to_value())]
+    Active(UiStatus),
+    #[error("Failed to return the IP: {0}; retry with skip_allocator to release it anyway")]
+    Allocator(String),
+    #[error(transparent)]
+    Database(#[from] DbErr),
+}
+
+/// What a force release did besides archiving the session
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ForceReleaseSummary {
+    /// Whether the item was handed back to the allocator
+    pub allocator_called: bool,
+    /// Pending IP return DLQ entries marked resolved
+    pub dlq_entries_resolved: Vec<uuid::Uuid>,
+}
+
+/// Whether a session in `ui_status` may still be using its sandbox
+fn is_active(ui_status: &UiStatus) -> bool {
+    matches!(
+        ui_status,
+        UiStatus::Pending | UiStatus::WaitingForSandbox | UiStatus::InProgress
+    )
+}
+
+/// Return the session's IP unless `skip_allocator`, archive it and clear its sandbox
+pub async fn force_release(
+    db: &DatabaseConnection,
+    session_id: uuid::Uuid,
+    actor: &Actor,
+    skip_allocator: bool,
+    reason: Option<String>,
+) -> Result<(SessionModel, ForceReleaseSummary), ForceReleaseError> {
+    let mut session = Session::find_by_id(session_id)
+        .one(db)
+        .await?
+        .ok_or(ForceReleaseError::NotFound)?;
+    if is_active(&session.ui_status) {
+        return Err(ForceReleaseError::Active(session.ui_status));
+    }
+
+    let mut summary = ForceReleaseSummary::default();
+    if let (Some(config), false) = (session.sbx_config.clone(), skip_allocator) {
+        let (key, with_key) = ip_allocator::return_key(db, session).await?;
+        session = with_key;
+        let (item, borrow_token) = ip_allocator::borrowed_item(&config);
+        match ip_allocator::return_item(item, borrow_token, key)
+            .await
+            .map_err(ForceReleaseError::Allocator)?
+        {
+            ReturnOutcome::Returned => {}
+            ReturnOutcome::AlreadyReturned(status) => info!(
+                "Allocator answered {} for the IP of session {}, treating it as returned",
+                status, session_id
+            ),
+        }
+        summary.allocator_called = true;
+    }
+
+    let from = session.ui_status.clone();
+    let archived = from != UiStatus::Archived;
+    let mut active_session = if archived {
+        SessionStateMachine::transition(
+            session,
+            UiStatus::Archived,
+            TransitionCause::ForceReleased,
+            actor,
+        )
+        .map_err(|e| DbErr::Custom(e.to_string()))?
+    } else {
+        session::ActiveModel::from(session)
+    };
+    let now = Utc::now();
+    active_session.sbx_config = Set(None);
+    active_session.ip_return_key = Set(None);
+    active_session.ip_return_retry_count = Set(0);
+    active_session.status_message = Set(Some("Sandbox released by an admin".to_string()));
+    active_session.updated_at = Set(now.into());
+
+    let notes = format!(
+        "Force released by {}{}",
+        actor,
+        reason
+            .as_deref()
+            .map(|reason| format!(": {}", reason))
+            .unwrap_or_default()
+    );
+    let txn = db.begin().await?;
+    let updated = active_session.update(&txn).await?;
+    summary.dlq_entries_resolved =
+        dead_letter_queue::resolve_pending_for(&txn, IP_RETURN_TASK_TYPE, session_id, &notes)
+            .await?;
+    session_event::ActiveModel {
+        id: Set(uuid::Uuid::new_v4()),
+        session_id: Set(session_id),
+        event_type: Set(SessionEventType::IpForceReleased),
+        actor: Set(actor.to_string()),
+        from_status: Set(None),
+        to_status: Set(None),
+        metadata: Set(Some(serde_json::json!({
+            "reason": reason,
+            "allocator_skipped": skip_allocator,
+            "allocator_called": summary.allocator_called,
+            "dlq_entries_resolved": summary.dlq_entries_resolved,
+        }))),
+        created_at: Set(now.into()),
+    }
+    .insert(&txn)
+    .await?;
+    txn.commit().await?;
+
+    if archived {
+        SessionStateMachine::after_save(db, &from, &updated, TransitionCause::ForceReleased, actor)
+            .await;
+    }
+    info!(
+        target: "session_audit",
+        session_id = %session_id,
+        actor = %actor,
+        allocator_called = summary.allocator_called,
+        dlq_entries_resolved = summary.dlq_entries_resolved.len(),
+        "Force released session sandbox"
+    );
+    Ok((updated, summary))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_finished_sessions_are_released() {
+        assert!(is_active(&UiStatus::Pending));
+        assert!(is_active(&UiStatus::WaitingForSandbox));
+        assert!(is_active(&UiStatus::InProgress));
+        assert!(!is_active(&UiStatus::NeedsReview));
+        assert!(!is_active(&UiStatus::NeedsAttention));
+        assert!(!is_active(&UiStatus::Archived));
+        assert!(SessionStateMachine::is_allowed(
+            &UiStatus::NeedsReviewIpReturned,
+            &UiStatus::Archived,
+            TransitionCause::ForceReleased
+        ));
+    }
+}
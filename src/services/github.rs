@@ -0,0 +1,331 @@
+use reqwest::header::HeaderMap;
+use reqwest::{Method, RequestBuilder, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::sync::Mutex;
+use tracing::warn;
+
+use crate::services::github_host::{self, GithubHost, RepoRef};
+use crate::services::http_client;
+
+/// Page size requested from list endpoints (GitHub's maximum)
+const PER_PAGE: u32 = 100;
+
+/// Upper bound on pages fetched by a single list call
+const MAX_PAGES: usize = 50;
+
+#[derive(Debug, thiserror::Error)]
+pub enum GithubError {
+    #[error("GitHub token unavailable: {0}")]
+    Token(String),
+    #[error("GitHub request failed: {0}")]
+    Request(#[from] reqwest::Error),
+    #[error("GitHub resource not found: {0}")]
+    NotFound(String),
+    #[error("GitHub rate limit exhausted until {reset_at} (unix time)")]
+    RateLimited { reset_at: u64 },
+    #[error("GitHub returned {status}: {body}")]
+    Status { status: StatusCode, body: String },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Repository {
+    pub full_name: String,
+    pub private: bool,
+    pub default_branch: String,
+    pub html_url: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BranchCommit {
+    pub sha: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Branch {
+    pub name: String,
+    pub commit: BranchCommit,
+    #[serde(default)]
+    pub protected: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PullRequest {
+    pub number: u64,
+    pub html_url: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ComparedFile {
+    pub filename: String,
+    pub status: String,
+    pub additions: u64,
+    pub deletions: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Comparison {
+    #[serde(default)]
+    pub files: Vec<ComparedFile>,
+}
+
+/// Rate limit state from the last response's `X-RateLimit-*` headers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct RateLimit {
+    remaining: u64,
+    reset_at: u64,
+}
+
+fn parse_rate_limit(headers: &HeaderMap) -> Option<RateLimit> {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok())
+    };
+    Some(RateLimit {
+        remaining: header("x-ratelimit-remaining")?,
+        reset_at: header("x-ratelimit-reset")?,
+    })
+}
+
+/// URL of the `rel="next"` page from a `Link` header
+fn next_page_url(link: &str) -> Option<String> {
+    link.split(',').find_map(|part| {
+        let (url, rel) = part.split_once(';')?;
+        if rel.trim() != "rel=\"next\"" {
+            return None;
+        }
+        Some(
+            url.trim()
+                .trim_start_matches('<')
+                .trim_end_matches('>')
+                .to_string(),
+        )
+    })
+}
+
+/// Typed client for the GitHub REST API of one host, authenticated as one user
+pub struct GithubClient {
+    http: reqwest::Client,
+    api_base: String,
+    token: String,
+    rate_limit: Mutex<Option<RateLimit>>,
+}
+
+impl GithubClient {
+    pub fn new(host: &GithubHost, token: String) -> Self {
+        Self {
+            http: http_client::client(),
+            api_base: host.api_base_url(),
+            token,
+            rate_limit: Mutex::new(None),
+        }
+    }
+
+    /// Client for `user_id` on `host`, using the same token resolution as cloning
+    pub async fn for_user(host: &GithubHost, user_id: &str) -> Result<Self, GithubError> {
+        let token = github_host::resolve_token(host, user_id)
+            .await
+            .map_err(GithubError::Token)?;
+        Ok(Self::new(host, token))
+    }
+
+    fn request(&self, method: Method, url: &str) -> RequestBuilder {
+        self.http
+            .request(method, url)
+            .bearer_auth(&self.token)
+            .header("Accept", "application/vnd.github+json")
+            .header("X-GitHub-Api-Version", "2022-11-28")
+            .header("User-Agent", "prompt-backend")
+    }
+
+    /// Fail fast while the last response said the rate limit is exhausted
+    fn check_rate_limit(&self) -> Result<(), GithubError> {
+        let now = chrono::Utc::now().timestamp() as u64;
+        match *self.rate_limit.lock().unwrap() {
+            Some(limit) if limit.remaining == 0 && limit.reset_at > now => {
+                Err(GithubError::RateLimited {
+                    reset_at: limit.reset_at,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Send a request and return the successful response, recording rate limit headers
+    async fn send(&self, builder: RequestBuilder) -> Result<reqwest::Response, GithubError> {
+        self.check_rate_limit()?;
+
+        let response = http_client::send_with_retry(builder).await?;
+        let rate_limit = parse_rate_limit(response.headers());
+        if let Some(limit) = rate_limit {
+            if limit.remaining == 0 {
+                warn!(
+                    "GitHub rate limit exhausted for {} until {}",
+                    self.api_base, limit.reset_at
+                );
+            }
+            *self.rate_limit.lock().unwrap() = Some(limit);
+        }
+
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+
+        let url = response.url().to_string();
+        match (status, rate_limit) {
+            (StatusCode::FORBIDDEN | StatusCode::TOO_MANY_REQUESTS, Some(limit))
+                if limit.remaining == 0 =>
+            {
+                Err(GithubError::RateLimited {
+                    reset_at: limit.reset_at,
+                })
+            }
+            (StatusCode::NOT_FOUND, _) => Err(GithubError::NotFound(url)),
+            _ => Err(GithubError::Status {
+                status,
+                body: response.text().await.unwrap_or_default(),
+            }),
+        }
+    }
+
+    async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T, GithubError> {
+        let url = format!("{}{}", self.api_base, path);
+        Ok(self
+            .send(self.request(Method::GET, &url))
+            .await?
+            .json()
+            .await?)
+    }
+
+    /// GET every page of a list endpoint by following `Link: rel="next"`
+    async fn get_all<T: DeserializeOwned>(&self, path: &str) -> Result<Vec<T>, GithubError> {
+        let separator = if path.contains('?') { '&' } else { '?' };
+        let mut next = Some(format!(
+            "{}{}{}per_page={}",
+            self.api_base, path, separator, PER_PAGE
+        ));
+        let mut items = Vec::new();
+
+        for _ in 0..MAX_PAGES {
+            let Some(url) = next.take() else {
+                break;
+            };
+            let response = self.send(self.request(Method::GET, &url)).await?;
+            next = response
+                .headers()
+                .get("link")
+                .and_then(|v| v.to_str().ok())
+                .and_then(next_page_url);
+            items.extend(response.json::<Vec<T>>().await?);
+        }
+
+        Ok(items)
+    }
+
+    /// Repositories the user can access
+    pub async fn list_repos(&self) -> Result<Vec<Repository>, GithubError> {
+        self.get_all("/user/repos?sort=pushed").await
+    }
+
+    pub async fn get_repo(&self, repo: &RepoRef) -> Result<Repository, GithubError> {
+        self.get(&format!("/repos/{}", repo.path())).await
+    }
+
+    pub async fn list_branches(&self, repo: &RepoRef) -> Result<Vec<Branch>, GithubError> {
+        self.get_all(&format!("/repos/{}/branches", repo.path()))
+            .await
+    }
+
+    pub async fn get_branch(&self, repo: &RepoRef, branch: &str) -> Result<Branch, GithubError> {
+        self.get(&format!("/repos/{}/branches/{}", repo.path(), branch))
+            .await
+    }
+
+    /// Pull requests in any state opened from `branch` of `repo` itself
+    pub async fn list_pull_requests_for_branch(
+        &self,
+        repo: &RepoRef,
+        branch: &str,
+    ) -> Result<Vec<PullRequest>, GithubError> {
+        self.get_all(&format!(
+            "/repos/{}/pulls?state=all&head={}:{}",
+            repo.path(),
+            repo.owner,
+            branch
+        ))
+        .await
+    }
+
+    /// Replace the body of pull request `number`
+    pub async fn update_pull_request_body(
+        &self,
+        repo: &RepoRef,
+        number: u64,
+        body: &str,
+    ) -> Result<PullRequest, GithubError> {
+        let url = format!("{}/repos/{}/pulls/{}", self.api_base, repo.path(), number);
+        Ok(self
+            .send(
+                self.request(Method::PATCH, &url)
+                    .json(&serde_json::json!({ "body": body })),
+            )
+            .await?
+            .json()
+            .await?)
+    }
+
+    /// Compare `head` against `base` (branches, tags or SHAs)
+    pub async fn compare(
+        &self,
+        repo: &RepoRef,
+        base: &str,
+        head: &str,
+    ) -> Result<Comparison, GithubError> {
+        self.get(&format!(
+            "/repos/{}/compare/{}...{}",
+            repo.path(),
+            base,
+            head
+        ))
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn test_next_page_url() {
+        let link = "<https://api.github.com/user/repos?page=2>; rel=\"next\", \
+                    <https://api.github.com/user/repos?page=5>; rel=\"last\"";
+        assert_eq!(
+            next_page_url(link).as_deref(),
+            Some("https://api.github.com/user/repos?page=2")
+        );
+        assert_eq!(
+            next_page_url("<https://api.github.com/user/repos?page=1>; rel=\"prev\""),
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse_rate_limit() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(parse_rate_limit(&headers), None);
+
+        headers.insert("x-ratelimit-remaining", HeaderValue::from_static("0"));
+        headers.insert("x-ratelimit-reset", HeaderValue::from_static("1700000000"));
+        assert_eq!(
+            parse_rate_limit(&headers),
+            Some(RateLimit {
+                remaining: 0,
+                reset_at: 1700000000
+            })
+        );
+    }
+}
@@ -0,0 +1,30 @@
+//! SHA-256 digests as lowercase hex, for content hashes, cache keys and stored token hashes.
+
+use sha2::{Digest, Sha256};
+
+/// Lowercase hex SHA-256 of `data`
+pub fn sha256_hex(data: impl AsRef<[u8]>) -> String {
+    hex(&Sha256::digest(data))
+}
+
+/// Lowercase hex of `bytes`, e.g. a digest finished by a caller hashing in chunks
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sha256_hex() {
+        assert_eq!(
+            sha256_hex("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        let mut hasher = Sha256::new();
+        hasher.update(b"a");
+        hasher.update(b"bc");
+        assert_eq!(hex(&hasher.finalize()), sha256_hex(b"abc"));
+    }
+}
@@ -0,0 +1,133 @@
+//! The one place outbound HTTP clients are built.
+//!
+//! Anthropic, GitHub, Keycloak, the IP allocator and the sandboxes are all called through
+//! clients from here, so proxy, CA and timeout settings apply everywhere. Proxies come from the
+//! standard `HTTP_PROXY`, `HTTPS_PROXY`, `ALL_PROXY` and `NO_PROXY` variables, which reqwest
+//! reads itself; sandbox hosts usually belong in `NO_PROXY`.
+
+use reqwest::{Certificate, ClientBuilder, RequestBuilder, Response, StatusCode};
+use std::sync::LazyLock;
+use std::time::Duration;
+use tracing::{error, warn};
+
+use crate::backoff::jittered_backoff;
+use crate::config::HttpClientConfig;
+
+/// Backoff ceiling before the first retry, doubled for each one after it
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(250);
+
+/// Longest wait between two attempts
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(5);
+
+static CLIENT: LazyLock<reqwest::Client> = LazyLock::new(|| {
+    builder(&crate::config::get().http)
+        .build()
+        .unwrap_or_else(|e| {
+            error!(
+                "Failed to build HTTP client from configuration, using defaults: {}",
+                e
+            );
+            reqwest::Client::new()
+        })
+});
+
+/// The shared client; cloning is cheap and reuses its connection pool
+pub fn client() -> reqwest::Client {
+    CLIENT.clone()
+}
+
+/// A client builder with the configured CA bundle and timeouts applied
+pub fn builder(config: &HttpClientConfig) -> ClientBuilder {
+    let mut builder = reqwest::Client::builder()
+        .connect_timeout(config.connect_timeout)
+        .timeout(config.timeout)
+        .user_agent("prompt-backend");
+
+    if let Some(path) = &config.ca_bundle {
+        match load_certificates(path) {
+            Ok(certificates) => {
+                for certificate in certificates {
+                    builder = builder.add_root_certificate(certificate);
+                }
+            }
+            Err(e) => error!("Failed to load HTTP_CA_BUNDLE {}: {}", path, e),
+        }
+    }
+    builder
+}
+
+/// Every certificate in a PEM bundle
+fn load_certificates(path: &str) -> Result<Vec<Certificate>, String> {
+    let pem = std::fs::read(path).map_err(|e| e.to_string())?;
+    let certificates = Certificate::from_pem_bundle(&pem).map_err(|e| e.to_string())?;
+    if certificates.is_empty() {
+        return Err("no certificates found".to_string());
+    }
+    Ok(certificates)
+}
+
+/// Whether a response status is worth retrying; rate limits are left to the caller
+fn is_retryable_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+/// Send `request`, retrying connection failures, timeouts and 502/503/504 responses up to
+/// `HTTP_MAX_RETRIES` times with exponential backoff. Requests with a streaming body cannot be
+/// replayed and are sent once. The last response or error is returned.
+pub async fn send_with_retry(request: RequestBuilder) -> reqwest::Result<Response> {
+    let max_retries = crate::config::get().http.max_retries;
+    let mut attempt = 0;
+
+    loop {
+        let retry = if attempt < max_retries {
+            request.try_clone()
+        } else {
+            None
+        };
+        let Some(next) = retry else {
+            return request.send().await;
+        };
+
+        match next.send().await {
+            Ok(response) if !is_retryable_status(response.status()) => return Ok(response),
+            Ok(response) => warn!(
+                "{} returned {}, retrying (attempt {} of {})",
+                response.url(),
+                response.status(),
+                attempt + 1,
+                max_retries
+            ),
+            Err(e) if e.is_connect() || e.is_timeout() => warn!(
+                "Request failed, retrying (attempt {} of {}): {}",
+                attempt + 1,
+                max_retries,
+                e
+            ),
+            Err(e) => return Err(e),
+        }
+
+        tokio::time::sleep(jittered_backoff(attempt, RETRY_BASE_DELAY, RETRY_MAX_DELAY)).await;
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retryable_statuses() {
+        assert!(is_retryable_status(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(is_retryable_status(StatusCode::BAD_GATEWAY));
+        assert!(!is_retryable_status(StatusCode::TOO_MANY_REQUESTS));
+        assert!(!is_retryable_status(StatusCode::NOT_FOUND));
+    }
+
+    #[test]
+    fn test_missing_ca_bundle_is_an_error() {
+        assert!(load_certificates("/nonexistent/ca.pem").is_err());
+    }
+}
@@ -0,0 +1,242 @@
+//! `Idempotency-Key` handling for requests that create prompts.
+//!
+//! A client retrying a request, or a user double-clicking, sends the same key again. The first
+//! request reserves the key before it does anything; when it succeeds its response is stored and
+//! later requests with the key get that response back instead of creating a second prompt. A
+//! request that fails releases the key so it can be retried. Keys are per user, bound to the
+//! endpoint and body they were first sent with, and forgotten after `IDEMPOTENCY_KEY_TTL_SECS`.
+
+use chrono::Utc;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket_okapi::gen::OpenApiGenerator;
+use rocket_okapi::okapi::openapi3::{Object, Parameter, ParameterValue};
+use rocket_okapi::request::{OpenApiFromRequest, RequestHeaderInput};
+use sea_orm::sea_query::OnConflict;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter, Set,
+};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::config;
+use crate::entities::idempotency_key::{self, Entity as IdempotencyKeyEntity};
+use crate::services::hashing;
+
+pub const HEADER: &str = "Idempotency-Key";
+
+/// Longest key accepted
+const MAX_KEY_LEN: usize = 255;
+
+/// Value of the `Idempotency-Key` header, if sent
+pub struct IdempotencyKey(Option<String>);
+
+impl IdempotencyKey {
+    /// A key sent other than in the header, e.g. in gRPC metadata
+    #[cfg_attr(not(feature = "grpc"), allow(dead_code))]
+    pub fn new(key: Option<String>) -> Self {
+        IdempotencyKey(key)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IdempotencyKey {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(IdempotencyKey(
+            request.headers().get_one(HEADER).map(str::to_string),
+        ))
+    }
+}
+
+impl<'a> OpenApiFromRequest<'a> for IdempotencyKey {
+    fn from_request_input(
+        gen: &mut OpenApiGenerator,
+        _name: String,
+        _required: bool,
+    ) -> rocket_okapi::Result<RequestHeaderInput> {
+        Ok(RequestHeaderInput::Parameter(Parameter {
+            name: HEADER.to_string(),
+            location: "header".to_string(),
+            description: Some(
+                "Client-chosen key; repeating a request with the same key returns the first \
+                 response instead of creating another prompt"
+                    .to_string(),
+            ),
+            required: false,
+            deprecated: false,
+            allow_empty_value: false,
+            value: ParameterValue::Schema {
+                style: None,
+                explode: None,
+                allow_reserved: false,
+                schema: gen.json_schema::<String>(),
+                example: None,
+                examples: None,
+            },
+            extensions: Object::default(),
+        }))
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum IdempotencyError {
+    #[error("Idempotency-Key must be 1 to {MAX_KEY_LEN} visible ASCII characters")]
+    InvalidKey,
+    #[error("A request with this Idempotency-Key is still being processed")]
+    InProgress,
+    #[error("Idempotency-Key was already used for a different request")]
+    Mismatch,
+    #[error(transparent)]
+    Database(#[from] DbErr),
+}
+
+/// How a request should proceed given its key
+pub enum Begin<T> {
+    /// No key was sent
+    Unkeyed,
+    /// The key is now held by this request, which must `finish` or `release` it
+    Reserved(Reservation),
+    /// The key was used for this request before; reply with the stored response
+    Replay(T),
+}
+
+/// A key held by the request being handled
+pub struct Reservation {
+    id: Uuid,
+}
+
+fn is_valid_key(key: &str) -> bool {
+    !key.is_empty() && key.len() <= MAX_KEY_LEN && key.bytes().all(|b| b.is_ascii_graphic())
+}
+
+fn request_hash(endpoint: &str, request: &impl Serialize) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(endpoint.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(serde_json::to_vec(request).unwrap_or_default());
+    hashing::hex(&hasher.finalize())
+}
+
+/// Reserve `key` for a request to `endpoint`, or find the response of the request that used it
+/// first
+pub async fn begin<T: DeserializeOwned>(
+    db: &DatabaseConnection,
+    key: &IdempotencyKey,
+    user_id: &str,
+    endpoint: &str,
+    request: &impl Serialize,
+) -> Result<Begin<T>, IdempotencyError> {
+    let Some(key) = key.0.as_deref() else {
+        return Ok(Begin::Unkeyed);
+    };
+    if !is_valid_key(key) {
+        return Err(IdempotencyError::InvalidKey);
+    }
+    let hash = request_hash(endpoint, request);
+    let now = Utc::now();
+
+    IdempotencyKeyEntity::delete_many()
+        .filter(idempotency_key::Column::ExpiresAt.lt(now))
+        .exec(db)
+        .await?;
+
+    let id = Uuid::new_v4();
+    let reserved = IdempotencyKeyEntity::insert(idempotency_key::ActiveModel {
+        id: Set(id),
+        user_id: Set(user_id.to_string()),
+        key: Set(key.to_string()),
+        endpoint: Set(endpoint.to_string()),
+        request_hash: Set(hash.clone()),
+        response: Set(None),
+        created_at: Set(now.into()),
+        expires_at: Set((now + config::get().idempotency_key_ttl).into()),
+    })
+    .on_conflict(
+        OnConflict::columns([
+            idempotency_key::Column::UserId,
+            idempotency_key::Column::Key,
+        ])
+        .do_nothing()
+        .to_owned(),
+    )
+    .exec(db)
+    .await;
+    match reserved {
+        Ok(_) => return Ok(Begin::Reserved(Reservation { id })),
+        Err(DbErr::RecordNotInserted) => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    let existing = IdempotencyKeyEntity::find()
+        .filter(idempotency_key::Column::UserId.eq(user_id))
+        .filter(idempotency_key::Column::Key.eq(key))
+        .one(db)
+        .await?
+        .ok_or(IdempotencyError::InProgress)?;
+    if existing.endpoint != endpoint || existing.request_hash != hash {
+        return Err(IdempotencyError::Mismatch);
+    }
+    let response = existing.response.ok_or(IdempotencyError::InProgress)?;
+    info!(
+        "Replaying {} response for Idempotency-Key {} of user {}",
+        endpoint, key, user_id
+    );
+    serde_json::from_value(response)
+        .map(Begin::Replay)
+        .map_err(|e| DbErr::Json(e.to_string()).into())
+}
+
+impl Reservation {
+    /// Store the response to replay for the key
+    pub async fn finish(self, db: &DatabaseConnection, response: &impl Serialize) {
+        let stored = idempotency_key::ActiveModel {
+            id: Set(self.id),
+            response: Set(serde_json::to_value(response).ok()),
+            ..Default::default()
+        }
+        .update(db)
+        .await;
+        if let Err(e) = stored {
+            warn!(
+                "Failed to store response for idempotency key {}: {}",
+                self.id, e
+            );
+        }
+    }
+
+    /// Free the key after the request failed, so it can be retried
+    pub async fn release(self, db: &DatabaseConnection) {
+        if let Err(e) = IdempotencyKeyEntity::delete_by_id(self.id).exec(db).await {
+            warn!("Failed to release idempotency key {}: {}", self.id, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_validation() {
+        assert!(is_valid_key("3f1c2a9e-5b7d-4e8f-9a0b-1c2d3e4f5a6b"));
+        assert!(!is_valid_key(""));
+        assert!(!is_valid_key("has space"));
+        assert!(!is_valid_key(&"k".repeat(MAX_KEY_LEN + 1)));
+    }
+
+    #[test]
+    fn test_request_hash_covers_endpoint_and_body() {
+        let body = serde_json::json!({ "session_id": "s", "data": { "text": "hi" } });
+        let hash = request_hash("POST /prompts", &body);
+        assert_eq!(hash, request_hash("POST /prompts", &body.clone()));
+        assert_ne!(hash, request_hash("POST /sessions/with-prompt", &body));
+        assert_ne!(
+            hash,
+            request_hash("POST /prompts", &serde_json::json!({ "session_id": "s" }))
+        );
+    }
+}
@@ -0,0 +1,312 @@
+//! Filtering of a run's CLI output before it is stored, so verbose event kinds such as
+//! progress pings do not fill the message table.
+//!
+//! Rules map an event kind to an action. The kind of a message is its `type`, or
+//! `type:subtype` when it has a subtype; a rule for `type:subtype` wins over one for `type`.
+//! `drop` discards the message, `aggregate` collapses consecutive messages of the kind into
+//! one `{"type": "aggregated", "event_kind", "count", "last"}` message written once another
+//! kind comes in or the output ends, and `keep` stores it as is. The deployment's rules come
+//! from `MESSAGE_INGESTION_FILTER`; a session's own rules, set at creation, override them for
+//! the same kinds, so `keep` turns a deployment rule off. Filtered messages are counted in
+//! `messages_filtered_total` and on the session, and never reach the output log copy, so a
+//! backfill does not store them after all.
+
+use chrono::Utc;
+use rocket_okapi::okapi::schemars::{self, JsonSchema};
+use sea_orm::sea_query::Expr;
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use tracing::warn;
+
+use crate::entities::session::{self, Entity as Session};
+
+/// Most rules a session may set
+const MAX_RULES: usize = 50;
+
+/// Longest event kind a rule may name
+const MAX_KIND_LENGTH: usize = 100;
+
+/// What happens to messages of a kind
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum IngestionAction {
+    /// Store every message
+    Keep,
+    /// Store none
+    Drop,
+    /// Store one message per run of consecutive messages, with their count and the last one
+    Aggregate,
+}
+
+impl IngestionAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IngestionAction::Keep => "keep",
+            IngestionAction::Drop => "drop",
+            IngestionAction::Aggregate => "aggregate",
+        }
+    }
+}
+
+impl FromStr for IngestionAction {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "keep" => Ok(IngestionAction::Keep),
+            "drop" => Ok(IngestionAction::Drop),
+            "aggregate" => Ok(IngestionAction::Aggregate),
+            other => Err(format!("Unknown ingestion action {:?}", other)),
+        }
+    }
+}
+
+/// Actions by event kind
+pub type IngestionRules = BTreeMap<String, IngestionAction>;
+
+/// The rules stored on a session, None when it has none
+pub fn from_json(value: Option<&Value>) -> Option<IngestionRules> {
+    let rules: IngestionRules = serde_json::from_value(value?.clone()).ok()?;
+    (!rules.is_empty()).then_some(rules)
+}
+
+pub fn validate(rules: &IngestionRules) -> Result<(), String> {
+    if rules.len() > MAX_RULES {
+        return Err(format!(
+            "ingestion_filter may have at most {} rules",
+            MAX_RULES
+        ));
+    }
+    for kind in rules.keys() {
+        if kind.trim().is_empty() || kind.chars().count() > MAX_KIND_LENGTH {
+            return Err(format!(
+                "ingestion_filter event kinds must be between 1 and {} characters",
+                MAX_KIND_LENGTH
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// The rules a session's runs are filtered by: the deployment's, overridden by the session's
+/// own, without the kinds that are kept anyway
+pub fn effective(stored: Option<&Value>, defaults: &IngestionRules) -> IngestionRules {
+    let mut rules = defaults.clone();
+    rules.extend(from_json(stored).unwrap_or_default());
+    rules.retain(|_, action| *action != IngestionAction::Keep);
+    rules
+}
+
+/// `type:subtype` of a message, or its `type` when it has no subtype
+pub fn event_kind(message: &Value) -> Option<String> {
+    let kind = message.get("type")?.as_str()?;
+    Some(match message.get("subtype").and_then(Value::as_str) {
+        Some(subtype) => format!("{}:{}", kind, subtype),
+        None => kind.to_string(),
+    })
+}
+
+/// Consecutive aggregated messages of one kind
+#[derive(Debug)]
+struct Aggregate {
+    kind: String,
+    count: u64,
+    last: Value,
+}
+
+impl Aggregate {
+    fn into_message(self) -> Value {
+        serde_json::json!({
+            "type": "aggregated",
+            "event_kind": self.kind,
+            "count": self.count,
+            "last": self.last,
+        })
+    }
+}
+
+/// Applies the rules to one run's messages in order
+#[derive(Debug, Default)]
+pub struct IngestionFilter {
+    rules: IngestionRules,
+    pending: Option<Aggregate>,
+    filtered: u64,
+}
+
+impl IngestionFilter {
+    pub fn new(rules: IngestionRules) -> Self {
+        IngestionFilter {
+            rules,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        !self.rules.is_empty()
+    }
+
+    /// Messages that were dropped or folded into an aggregated message so far
+    pub fn filtered(&self) -> u64 {
+        self.filtered
+    }
+
+    fn action(&self, kind: Option<&str>) -> (IngestionAction, Option<String>) {
+        let Some(kind) = kind else {
+            return (IngestionAction::Keep, None);
+        };
+        let base = kind.split_once(':').map(|(base, _)| base);
+        let action = self
+            .rules
+            .get(kind)
+            .or_else(|| base.and_then(|base| self.rules.get(base)))
+            .copied()
+            .unwrap_or(IngestionAction::Keep);
+        (action, Some(kind.to_string()))
+    }
+
+    /// The messages to store for `message`, in order: none, an aggregated message that ended,
+    /// `message` itself, or both
+    pub fn apply(&mut self, message: Value) -> Vec<Value> {
+        if !self.is_active() {
+            return vec![message];
+        }
+        let kind = event_kind(&message);
+        let (action, kind) = self.action(kind.as_deref());
+        if action != IngestionAction::Keep {
+            self.filtered += 1;
+            crate::metrics::get()
+                .messages_filtered_total
+                .with_label_values(&[kind.as_deref().unwrap_or_default(), action.as_str()])
+                .inc();
+        }
+
+        match (action, kind) {
+            (IngestionAction::Drop, _) => Vec::new(),
+            (IngestionAction::Aggregate, Some(kind)) => {
+                if let Some(pending) = self.pending.as_mut().filter(|p| p.kind == kind) {
+                    pending.count += 1;
+                    pending.last = message;
+                    return Vec::new();
+                }
+                let ended = self.pending.replace(Aggregate {
+                    kind,
+                    count: 1,
+                    last: message,
+                });
+                ended.map(Aggregate::into_message).into_iter().collect()
+            }
+            _ => {
+                let mut messages: Vec<Value> = self
+                    .pending
+                    .take()
+                    .map(Aggregate::into_message)
+                    .into_iter()
+                    .collect();
+                messages.push(message);
+                messages
+            }
+        }
+    }
+
+    /// The aggregated message still open when the output ended
+    pub fn finish(&mut self) -> Option<Value> {
+        self.pending.take().map(Aggregate::into_message)
+    }
+}
+
+/// Add `filtered` to the session's count of filtered messages
+pub async fn record(db: &DatabaseConnection, session_id: uuid::Uuid, filtered: u64) {
+    if filtered == 0 {
+        return;
+    }
+    let stored = Session::update_many()
+        .col_expr(
+            session::Column::MessagesFiltered,
+            Expr::col(session::Column::MessagesFiltered).add(filtered as i64),
+        )
+        .col_expr(session::Column::UpdatedAt, Expr::value(Utc::now()))
+        .filter(session::Column::Id.eq(session_id))
+        .exec(db)
+        .await;
+    if let Err(e) = stored {
+        warn!(
+            "Failed to count filtered messages of session {}: {}",
+            session_id, e
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_drops_and_aggregates_by_kind() {
+        let defaults = IngestionRules::from([
+            ("stream_event".to_string(), IngestionAction::Drop),
+            ("system".to_string(), IngestionAction::Drop),
+            ("system:status".to_string(), IngestionAction::Aggregate),
+        ]);
+        let own = json!({"system": "keep"});
+        let rules = effective(Some(&own), &defaults);
+        assert_eq!(
+            rules.keys().collect::<Vec<_>>(),
+            ["stream_event", "system:status"]
+        );
+
+        let mut filter = IngestionFilter::new(rules);
+        assert!(filter.is_active());
+        assert!(filter.apply(json!({"type": "stream_event"})).is_empty());
+        assert!(filter
+            .apply(json!({"type": "system", "subtype": "status", "n": 1}))
+            .is_empty());
+        assert!(filter
+            .apply(json!({"type": "system", "subtype": "status", "n": 2}))
+            .is_empty());
+        let stored = filter.apply(json!({"type": "system", "subtype": "init"}));
+        assert_eq!(
+            stored,
+            vec![
+                json!({
+                    "type": "aggregated",
+                    "event_kind": "system:status",
+                    "count": 2,
+                    "last": {"type": "system", "subtype": "status", "n": 2},
+                }),
+                json!({"type": "system", "subtype": "init"}),
+            ]
+        );
+        assert!(filter
+            .apply(json!({"type": "system", "subtype": "status", "n": 3}))
+            .is_empty());
+        assert_eq!(filter.finish().unwrap()["count"], 1);
+        assert_eq!(filter.finish(), None);
+        assert_eq!(filter.filtered(), 4);
+
+        let mut inactive = IngestionFilter::new(IngestionRules::new());
+        assert_eq!(
+            inactive.apply(json!({"type": "stream_event"})),
+            vec![json!({"type": "stream_event"})]
+        );
+    }
+
+    #[test]
+    fn test_validate_rules() {
+        assert!(validate(&IngestionRules::from([(
+            "stream_event".to_string(),
+            IngestionAction::Drop
+        )]))
+        .is_ok());
+        assert!(validate(&IngestionRules::from([(
+            " ".to_string(),
+            IngestionAction::Drop
+        )]))
+        .is_err());
+        assert_eq!("Aggregate".parse(), Ok(IngestionAction::Aggregate));
+        assert!("squash".parse::<IngestionAction>().is_err());
+    }
+}
@@ -0,0 +1,123 @@
+use sea_orm::sea_query::{Query, SelectStatement};
+use sea_orm::{
+    ColumnTrait, Condition, DatabaseConnection, EntityTrait, PaginatorTrait, QueryFilter, Set,
+};
+
+use crate::entities::dead_letter_queue::{self, DlqStatus, Entity as DeadLetterQueue};
+use crate::entities::message::{self, Entity as Message};
+use crate::entities::outbox_event::{self, Entity as OutboxEvent};
+use crate::entities::prompt::{self, Entity as Prompt};
+use crate::entities::session::{self, Entity as Session};
+
+/// Counts of rows that reference entities which no longer exist
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OrphanCounts {
+    /// Prompts whose session is gone
+    pub prompts: u64,
+    /// Messages whose prompt is gone
+    pub messages: u64,
+    /// Pending DLQ entries whose session or prompt is gone
+    pub dlq_entries: u64,
+}
+
+impl OrphanCounts {
+    pub fn total(&self) -> u64 {
+        self.prompts + self.messages + self.dlq_entries
+    }
+}
+
+/// Note written on DLQ entries abandoned because their entity was deleted
+pub const ORPHANED_DLQ_NOTE: &str =
+    "Abandoned by integrity check: referenced entity no longer exists";
+
+fn session_ids() -> SelectStatement {
+    Query::select()
+        .column(session::Column::Id)
+        .from(Session)
+        .to_owned()
+}
+
+fn prompt_ids() -> SelectStatement {
+    Query::select()
+        .column(prompt::Column::Id)
+        .from(Prompt)
+        .to_owned()
+}
+
+fn outbox_event_ids() -> SelectStatement {
+    Query::select()
+        .column(outbox_event::Column::Id)
+        .from(OutboxEvent)
+        .to_owned()
+}
+
+/// Prompts whose session no longer exists
+pub fn orphaned_prompts() -> Condition {
+    Condition::all().add(prompt::Column::SessionId.not_in_subquery(session_ids()))
+}
+
+/// Messages whose prompt no longer exists
+pub fn orphaned_messages() -> Condition {
+    Condition::all().add(message::Column::PromptId.not_in_subquery(prompt_ids()))
+}
+
+/// Pending DLQ entries whose entity no longer exists. Entries reference a session, a prompt
+/// or an outbox event depending on the task type.
+pub fn orphaned_dlq_entries() -> Condition {
+    Condition::all()
+        .add(dead_letter_queue::Column::Status.eq(DlqStatus::Pending))
+        .add(dead_letter_queue::Column::EntityId.not_in_subquery(session_ids()))
+        .add(dead_letter_queue::Column::EntityId.not_in_subquery(prompt_ids()))
+        .add(dead_letter_queue::Column::EntityId.not_in_subquery(outbox_event_ids()))
+}
+
+/// Count orphaned rows without changing anything
+pub async fn detect_orphans(db: &DatabaseConnection) -> Result<OrphanCounts, sea_orm::DbErr> {
+    Ok(OrphanCounts {
+        prompts: Prompt::find().filter(orphaned_prompts()).count(db).await?,
+        messages: Message::find()
+            .filter(orphaned_messages())
+            .count(db)
+            .await?,
+        dlq_entries: DeadLetterQueue::find()
+            .filter(orphaned_dlq_entries())
+            .count(db)
+            .await?,
+    })
+}
+
+/// Delete orphaned prompts and messages and abandon orphaned DLQ entries.
+///
+/// Prompts are removed first so their messages are picked up by the message pass.
+/// Returns the number of rows repaired per kind.
+pub async fn repair_orphans(db: &DatabaseConnection) -> Result<OrphanCounts, sea_orm::DbErr> {
+    let prompts = Prompt::delete_many()
+        .filter(orphaned_prompts())
+        .exec(db)
+        .await?
+        .rows_affected;
+
+    let messages = Message::delete_many()
+        .filter(orphaned_messages())
+        .exec(db)
+        .await?
+        .rows_affected;
+
+    let dlq_entries = DeadLetterQueue::update_many()
+        .set(dead_letter_queue::ActiveModel {
+            status: Set(DlqStatus::Abandoned),
+            resolution_notes: Set(Some(ORPHANED_DLQ_NOTE.to_string())),
+            updated_at: Set(chrono::Utc::now().into()),
+            ..Default::default()
+        })
+        .filter(orphaned_dlq_entries())
+        .exec(db)
+        .await?
+        .rows_affected;
+
+    Ok(OrphanCounts {
+        prompts,
+        messages,
+        dlq_entries,
+    })
+}
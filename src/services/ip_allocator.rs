@@ -0,0 +1,169 @@
+//! Borrowing sandbox IPs, optionally from a preferred region, and returning them to the
+//! allocator, safe to retry.
+//!
+//! A return can reach the allocator and succeed while the session update recording it fails,
+//! so the next poll returns the same IP again. Every attempt for a session therefore carries
+//! the same `Idempotency-Key`, persisted on the session before the first attempt, and a 403 or
+//! 404 (the borrow token is no longer known to the allocator) counts as already returned.
+
+use reqwest::StatusCode;
+use sea_orm::{ActiveModelTrait, DatabaseConnection, DbErr, Set};
+use tracing::warn;
+
+use crate::entities::session::{self, Model as SessionModel};
+use crate::services::http_client;
+
+/// Base URL of the IP allocator, from `IP_ALLOCATOR_URL`
+pub fn allocator_url() -> String {
+    std::env::var("IP_ALLOCATOR_URL").unwrap_or_else(|_| "http://localhost:8000".to_string())
+}
+
+/// How a return attempt ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReturnOutcome {
+    /// The allocator took the IP back
+    Returned,
+    /// The allocator no longer knows the borrow, e.g. an earlier attempt returned it
+    AlreadyReturned(StatusCode),
+}
+
+/// An IP borrowed for a session
+#[derive(Debug, Clone)]
+pub struct Borrowed {
+    pub output: ip_allocator_client::types::BorrowOutput,
+    /// Region the sandbox is in: as reported on the item, else the region it was borrowed from
+    pub region: Option<String>,
+    /// The preferred region had no capacity and the IP came from any region
+    pub fell_back: bool,
+}
+
+/// Region the allocator reports on a borrowed item
+pub fn item_region(item: &serde_json::Value) -> Option<String> {
+    item.get("region")?.as_str().map(str::to_string)
+}
+
+async fn borrow_from(
+    region: Option<&str>,
+) -> Result<ip_allocator_client::types::BorrowOutput, String> {
+    let mut request =
+        http_client::client().get(format!("{}/borrow", allocator_url().trim_end_matches('/')));
+    if let Some(region) = region {
+        request = request.query(&[("region", region)]);
+    }
+    let response = request.send().await.map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("IP allocator returned {}", response.status()));
+    }
+    response.json().await.map_err(|e| e.to_string())
+}
+
+/// Borrow an IP, from `region` when given. The region is sent as `?region=`; when the
+/// allocator cannot serve it the IP is borrowed from any region instead.
+pub async fn borrow(region: Option<&str>) -> Result<Borrowed, String> {
+    if let Some(preferred) = region {
+        match borrow_from(Some(preferred)).await {
+            Ok(output) => {
+                let region = item_region(&output.item).or_else(|| Some(preferred.to_string()));
+                return Ok(Borrowed {
+                    fell_back: region.as_deref() != Some(preferred),
+                    output,
+                    region,
+                });
+            }
+            Err(e) => warn!(
+                "No sandbox available in region {}, borrowing from any region: {}",
+                preferred, e
+            ),
+        }
+    }
+    let output = borrow_from(None).await?;
+    Ok(Borrowed {
+        region: item_region(&output.item),
+        fell_back: region.is_some(),
+        output,
+    })
+}
+
+/// Status message of a session whose preferred region had no capacity
+pub fn fallback_message(preferred: &str, actual: Option<&str>) -> String {
+    format!(
+        "No sandbox capacity in region {}; running in {} instead",
+        preferred,
+        actual.unwrap_or("another region")
+    )
+}
+
+/// Outcome of a return from the allocator's response status, Err for failures worth retrying
+fn classify(status: StatusCode) -> Result<ReturnOutcome, String> {
+    match status {
+        status if status.is_success() => Ok(ReturnOutcome::Returned),
+        StatusCode::FORBIDDEN | StatusCode::NOT_FOUND => Ok(ReturnOutcome::AlreadyReturned(status)),
+        status => Err(format!("IP allocator returned {}", status)),
+    }
+}
+
+/// The session's return key, generated and stored before its first return attempt
+pub async fn return_key(
+    db: &DatabaseConnection,
+    session: SessionModel,
+) -> Result<(uuid::Uuid, SessionModel), DbErr> {
+    if let Some(key) = session.ip_return_key {
+        return Ok((key, session));
+    }
+    let key = uuid::Uuid::new_v4();
+    let mut active_session: session::ActiveModel = session.into();
+    active_session.ip_return_key = Set(Some(key));
+    Ok((key, active_session.update(db).await?))
+}
+
+/// The borrowed item and its borrow token in a session's `sbx_config`
+pub fn borrowed_item(sbx_config: &serde_json::Value) -> (serde_json::Value, String) {
+    let item = sbx_config
+        .get("item")
+        .cloned()
+        .unwrap_or_else(|| sbx_config.clone());
+    let borrow_token = sbx_config
+        .get("borrow_token")
+        .and_then(|v| v.as_str())
+        .unwrap_or("")
+        .to_string();
+    (item, borrow_token)
+}
+
+/// Return `item` borrowed with `borrow_token`, sending `key` as the idempotency key
+pub async fn return_item(
+    item: serde_json::Value,
+    borrow_token: String,
+    key: uuid::Uuid,
+) -> Result<ReturnOutcome, String> {
+    let body = ip_allocator_client::types::ReturnInput { item, borrow_token };
+    let request = http_client::client()
+        .post(format!("{}/return", allocator_url().trim_end_matches('/')))
+        .header("Idempotency-Key", key.to_string())
+        .json(&body);
+
+    let response = http_client::send_with_retry(request)
+        .await
+        .map_err(|e| e.to_string())?;
+    classify(response.status())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify_treats_unknown_borrow_as_returned() {
+        assert_eq!(classify(StatusCode::OK), Ok(ReturnOutcome::Returned));
+        assert_eq!(
+            classify(StatusCode::NOT_FOUND),
+            Ok(ReturnOutcome::AlreadyReturned(StatusCode::NOT_FOUND))
+        );
+        assert_eq!(
+            classify(StatusCode::FORBIDDEN),
+            Ok(ReturnOutcome::AlreadyReturned(StatusCode::FORBIDDEN))
+        );
+        assert!(classify(StatusCode::BAD_REQUEST).is_err());
+        assert!(classify(StatusCode::SERVICE_UNAVAILABLE).is_err());
+    }
+}
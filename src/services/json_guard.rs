@@ -0,0 +1,111 @@
+use serde_json::Value;
+use std::fmt;
+
+/// Why a JSON document was rejected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JsonLimitError {
+    TooLarge {
+        field: &'static str,
+        size: usize,
+        limit: usize,
+    },
+    TooDeep {
+        field: &'static str,
+        limit: usize,
+    },
+}
+
+impl fmt::Display for JsonLimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JsonLimitError::TooLarge { field, size, limit } => write!(
+                f,
+                "`{}` is {} bytes, larger than the {} byte limit",
+                field, size, limit
+            ),
+            JsonLimitError::TooDeep { field, limit } => write!(
+                f,
+                "`{}` is nested deeper than the limit of {} levels",
+                field, limit
+            ),
+        }
+    }
+}
+
+impl std::error::Error for JsonLimitError {}
+
+/// Nesting depth of a JSON value, stopping early once `limit` is exceeded.
+///
+/// Scalars have depth 0; each enclosing array or object adds one.
+fn exceeds_depth(value: &Value, limit: usize) -> bool {
+    let mut stack = vec![(value, 0usize)];
+    while let Some((value, depth)) = stack.pop() {
+        let children: Box<dyn Iterator<Item = &Value>> = match value {
+            Value::Array(items) => Box::new(items.iter()),
+            Value::Object(map) => Box::new(map.values()),
+            _ => continue,
+        };
+        if depth + 1 > limit {
+            return true;
+        }
+        stack.extend(children.map(|child| (child, depth + 1)));
+    }
+    false
+}
+
+/// Check a `data` document against size and depth limits before it is stored
+pub fn validate(
+    field: &'static str,
+    value: &Value,
+    max_bytes: usize,
+    max_depth: usize,
+) -> Result<(), JsonLimitError> {
+    if exceeds_depth(value, max_depth) {
+        return Err(JsonLimitError::TooDeep {
+            field,
+            limit: max_depth,
+        });
+    }
+
+    let size = value.to_string().len();
+    if size > max_bytes {
+        return Err(JsonLimitError::TooLarge {
+            field,
+            size,
+            limit: max_bytes,
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_validate_depth() {
+        assert!(validate("data", &json!("text"), 100, 0).is_ok());
+        assert!(validate("data", &json!({"a": [1, 2]}), 100, 2).is_ok());
+        assert_eq!(
+            validate("data", &json!({"a": {"b": [1]}}), 100, 2),
+            Err(JsonLimitError::TooDeep {
+                field: "data",
+                limit: 2
+            })
+        );
+    }
+
+    #[test]
+    fn test_validate_size() {
+        assert_eq!(
+            validate("data", &json!({"content": "x".repeat(20)}), 16, 8),
+            Err(JsonLimitError::TooLarge {
+                field: "data",
+                size: 34,
+                limit: 16
+            })
+        );
+    }
+}
@@ -0,0 +1,83 @@
+use serde::Deserialize;
+use std::env;
+
+use crate::services::http_client;
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+}
+
+/// Client for the Keycloak token endpoint using this backend's service account
+pub struct KeycloakClient {
+    token_endpoint: String,
+    client_id: String,
+    client_secret: String,
+}
+
+impl KeycloakClient {
+    /// Build a client from `KEYCLOAK_ISSUER`, `KEYCLOAK_CLIENT_ID` and `KEYCLOAK_CLIENT_SECRET`.
+    /// Returns None when the service account is not configured.
+    pub fn from_env() -> Option<Self> {
+        let issuer = env::var("KEYCLOAK_ISSUER").ok()?;
+        let client_id = env::var("KEYCLOAK_CLIENT_ID").ok()?;
+        let client_secret = env::var("KEYCLOAK_CLIENT_SECRET").ok()?;
+
+        Some(Self {
+            token_endpoint: format!(
+                "{}/protocol/openid-connect/token",
+                issuer.trim_end_matches('/')
+            ),
+            client_id,
+            client_secret,
+        })
+    }
+
+    async fn request_token(&self, form: &[(&str, &str)]) -> Result<String, String> {
+        let request = http_client::client().post(&self.token_endpoint).form(form);
+        let response = http_client::send_with_retry(request)
+            .await
+            .map_err(|e| format!("Failed to send request to Keycloak: {}", e))?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let error_text = response
+                .text()
+                .await
+                .unwrap_or_else(|_| "Unknown error".to_string());
+            return Err(format!("Keycloak token error ({}): {}", status, error_text));
+        }
+
+        let token: TokenResponse = response
+            .json()
+            .await
+            .map_err(|e| format!("Failed to parse Keycloak token response: {}", e))?;
+
+        Ok(token.access_token)
+    }
+
+    /// Fetch the token a user obtained from an external identity provider (e.g. a GitHub
+    /// Enterprise instance brokered under `idp_alias`) via Keycloak token exchange
+    pub async fn fetch_idp_token(&self, user_id: &str, idp_alias: &str) -> Result<String, String> {
+        let service_token = self
+            .request_token(&[
+                ("grant_type", "client_credentials"),
+                ("client_id", &self.client_id),
+                ("client_secret", &self.client_secret),
+            ])
+            .await?;
+
+        self.request_token(&[
+            (
+                "grant_type",
+                "urn:ietf:params:oauth:grant-type:token-exchange",
+            ),
+            ("client_id", &self.client_id),
+            ("client_secret", &self.client_secret),
+            ("subject_token", &service_token),
+            ("requested_subject", user_id),
+            ("requested_issuer", idp_alias),
+        ])
+        .await
+    }
+}
@@ -0,0 +1,287 @@
+use chrono::{DateTime, Utc};
+use object_store::aws::AmazonS3Builder;
+use object_store::path::Path;
+use object_store::{ObjectStore, PutPayload};
+use sea_orm::sea_query::Expr;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter,
+    QuerySelect, Set,
+};
+use serde_json::{json, Value};
+use std::sync::{Arc, LazyLock};
+use tracing::{error, warn};
+
+use crate::entities::message::{self, Entity as Message, Model as MessageModel};
+use crate::services::conversation_view;
+
+/// Payloads larger than this many bytes are offloaded when `MESSAGE_BLOB_THRESHOLD_BYTES` is unset
+const DEFAULT_THRESHOLD_BYTES: usize = 64 * 1024;
+
+/// Object storage for large message payloads
+pub struct MessageBlobStore {
+    store: Arc<dyn ObjectStore>,
+    threshold_bytes: usize,
+}
+
+static STORE: LazyLock<Option<MessageBlobStore>> = LazyLock::new(MessageBlobStore::from_env);
+
+/// Get the configured blob store, None when offloading is disabled
+pub fn get() -> Option<&'static MessageBlobStore> {
+    STORE.as_ref()
+}
+
+impl MessageBlobStore {
+    pub fn new(store: Arc<dyn ObjectStore>, threshold_bytes: usize) -> Self {
+        Self {
+            store,
+            threshold_bytes,
+        }
+    }
+
+    /// Build an S3-compatible store from `MESSAGE_BLOB_BUCKET` and the standard `AWS_*`
+    /// variables (`AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY`, `AWS_REGION`, `AWS_ENDPOINT`).
+    /// Returns None when no bucket is configured.
+    fn from_env() -> Option<Self> {
+        let bucket = std::env::var("MESSAGE_BLOB_BUCKET").ok()?;
+        let threshold_bytes = std::env::var("MESSAGE_BLOB_THRESHOLD_BYTES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_THRESHOLD_BYTES);
+
+        match AmazonS3Builder::from_env().with_bucket_name(bucket).build() {
+            Ok(store) => Some(Self::new(Arc::new(store), threshold_bytes)),
+            Err(e) => {
+                error!(
+                    "Failed to configure message blob store, keeping payloads inline: {}",
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    fn key_for(message_id: uuid::Uuid) -> String {
+        format!("messages/{}.json", message_id)
+    }
+
+    /// Upload `data` if it is above the threshold, returning its key
+    pub async fn offload(
+        &self,
+        message_id: uuid::Uuid,
+        data: &Value,
+    ) -> Result<Option<String>, String> {
+        let bytes = serde_json::to_vec(data).map_err(|e| e.to_string())?;
+        if bytes.len() <= self.threshold_bytes {
+            return Ok(None);
+        }
+        self.upload(message_id, bytes).await.map(Some)
+    }
+
+    /// Upload the payload of message `message_id` whatever its size, returning its key
+    pub async fn upload(&self, message_id: uuid::Uuid, bytes: Vec<u8>) -> Result<String, String> {
+        let key = Self::key_for(message_id);
+        self.store
+            .put(&Path::from(key.as_str()), PutPayload::from(bytes))
+            .await
+            .map_err(|e| format!("Failed to upload message payload {}: {}", key, e))?;
+        Ok(key)
+    }
+
+    /// Download an offloaded payload
+    pub async fn load(&self, key: &str) -> Result<Value, String> {
+        let bytes = self
+            .store
+            .get(&Path::from(key))
+            .await
+            .map_err(|e| format!("Failed to fetch message payload {}: {}", key, e))?
+            .bytes()
+            .await
+            .map_err(|e| format!("Failed to read message payload {}: {}", key, e))?;
+        serde_json::from_slice(&bytes)
+            .map_err(|e| format!("Failed to parse message payload {}: {}", key, e))
+    }
+
+    /// Download the raw bytes under `key`
+    pub async fn get_bytes(&self, key: &str) -> Result<Vec<u8>, String> {
+        let bytes = self
+            .store
+            .get(&Path::from(key))
+            .await
+            .map_err(|e| format!("Failed to fetch object {}: {}", key, e))?
+            .bytes()
+            .await
+            .map_err(|e| format!("Failed to read object {}: {}", key, e))?;
+        Ok(bytes.to_vec())
+    }
+
+    /// Upload raw bytes under `key`
+    pub async fn put(&self, key: &str, bytes: Vec<u8>) -> Result<(), String> {
+        self.store
+            .put(&Path::from(key), PutPayload::from(bytes))
+            .await
+            .map(|_| ())
+            .map_err(|e| format!("Failed to upload object {}: {}", key, e))
+    }
+
+    pub async fn delete(&self, key: &str) -> Result<(), String> {
+        self.store
+            .delete(&Path::from(key))
+            .await
+            .map_err(|e| format!("Failed to delete message payload {}: {}", key, e))
+    }
+}
+
+/// Stub kept in `message.data` in place of an offloaded payload
+fn stub(size_bytes: usize) -> Value {
+    json!({ "offloaded": true, "size_bytes": size_bytes })
+}
+
+/// Stub kept in `message.data` once data retention purged the payload
+pub fn purged_stub() -> Value {
+    json!({ "purged": true })
+}
+
+/// Decide what to store for a new or replaced message payload.
+///
+/// Returns the value for `data` and the blob key. Payloads stay inline when offloading is
+/// disabled, below the threshold, or the upload fails.
+pub async fn prepare(message_id: uuid::Uuid, data: Value) -> (Value, Option<String>) {
+    let Some(blobs) = get() else {
+        return (data, None);
+    };
+
+    match blobs.offload(message_id, &data).await {
+        Ok(Some(key)) => (stub(data.to_string().len()), Some(key)),
+        Ok(None) => (data, None),
+        Err(e) => {
+            warn!("{}, storing inline", e);
+            (data, None)
+        }
+    }
+}
+
+/// The full payload of a message, fetched from object storage when it was offloaded
+pub async fn resolve(model: &MessageModel) -> Result<Value, String> {
+    resolve_stored(model.id, &model.data, model.blob_key.as_deref()).await
+}
+
+/// The full payload of message `message_id` stored as `data` and `blob_key`, e.g. in the
+/// conversation read model
+pub async fn resolve_stored(
+    message_id: uuid::Uuid,
+    data: &Value,
+    blob_key: Option<&str>,
+) -> Result<Value, String> {
+    match (blob_key, get()) {
+        (None, _) => Ok(data.clone()),
+        (Some(key), Some(blobs)) => blobs.load(key).await,
+        (Some(key), None) => Err(format!(
+            "Message {} is stored at {} but no blob store is configured",
+            message_id, key
+        )),
+    }
+}
+
+/// Best-effort removal of a message's offloaded payload
+pub async fn remove(model: &MessageModel) {
+    if let (Some(key), Some(blobs)) = (&model.blob_key, get()) {
+        if let Err(e) = blobs.delete(key).await {
+            warn!("{}", e);
+        }
+    }
+}
+
+/// Offload up to `limit` existing inline messages above the threshold.
+///
+/// Used to migrate rows written before offloading was enabled. Returns how many were moved.
+pub async fn offload_existing(db: &DatabaseConnection, limit: u64) -> Result<u64, String> {
+    let Some(blobs) = get() else {
+        return Err("Message blob store is not configured".to_string());
+    };
+
+    let candidates = Message::find()
+        .filter(message::Column::BlobKey.is_null())
+        .filter(Expr::cust_with_values(
+            "octet_length(data::text) > $1",
+            [blobs.threshold_bytes as i64],
+        ))
+        .limit(limit)
+        .all(db)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let mut moved = 0;
+    let mut changed_prompts = Vec::new();
+    for model in candidates {
+        let Some(key) = blobs.offload(model.id, &model.data).await? else {
+            continue;
+        };
+
+        let active_message = message::ActiveModel {
+            id: Set(model.id),
+            data: Set(stub(model.data.to_string().len())),
+            blob_key: Set(Some(key)),
+            ..Default::default()
+        };
+        active_message.update(db).await.map_err(|e| e.to_string())?;
+        moved += 1;
+        changed_prompts.push(model.prompt_id);
+    }
+    conversation_view::refresh(db, changed_prompts).await;
+
+    Ok(moved)
+}
+
+/// Replace the payload of up to `limit` messages created before `cutoff` with
+/// `purged_stub`, removing offloaded copies. Returns how many were purged.
+pub async fn purge_older_than(
+    db: &DatabaseConnection,
+    cutoff: DateTime<Utc>,
+    limit: u64,
+) -> Result<u64, DbErr> {
+    let expired = Message::find()
+        .filter(message::Column::CreatedAt.lt(cutoff))
+        .filter(message::Column::Data.ne(purged_stub()))
+        .limit(limit)
+        .all(db)
+        .await?;
+
+    for model in &expired {
+        remove(model).await;
+        let active_message = message::ActiveModel {
+            id: Set(model.id),
+            data: Set(purged_stub()),
+            blob_key: Set(None),
+            ..Default::default()
+        };
+        active_message.update(db).await?;
+    }
+    // The read model must not keep purged content either
+    let prompt_ids: Vec<uuid::Uuid> = expired.iter().map(|m| m.prompt_id).collect();
+    conversation_view::refresh(db, prompt_ids).await;
+
+    Ok(expired.len() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::memory::InMemory;
+
+    #[tokio::test]
+    async fn test_offload_only_above_threshold() {
+        let blobs = MessageBlobStore::new(Arc::new(InMemory::new()), 32);
+        let id = uuid::Uuid::new_v4();
+
+        let small = json!({ "type": "text" });
+        assert_eq!(blobs.offload(id, &small).await.unwrap(), None);
+
+        let large = json!({ "type": "tool_result", "content": "x".repeat(100) });
+        let key = blobs.offload(id, &large).await.unwrap().unwrap();
+        assert_eq!(key, format!("messages/{}.json", id));
+        assert_eq!(blobs.load(&key).await.unwrap(), large);
+
+        blobs.delete(&key).await.unwrap();
+        assert!(blobs.load(&key).await.is_err());
+    }
+}
@@ -0,0 +1,409 @@
+//! Soft quotas on a session's stored messages.
+//!
+//! Sessions with hundreds of thousands of messages slow down every query on the message table.
+//! Once a session stores more than `SESSION_MESSAGE_SOFT_LIMIT` messages or
+//! `SESSION_MESSAGE_SOFT_LIMIT_BYTES` bytes of payloads in the database, the message
+//! compaction task cuts the content of its older tool results, which make up most of a long
+//! session, to their first `MESSAGE_COMPACTION_PREVIEW_CHARS` characters. Its most recent
+//! `MESSAGE_COMPACTION_KEEP_RECENT` messages are left alone, and so are payloads already in
+//! object storage.
+//!
+//! When the message blob store is configured the original payload is uploaded first and the
+//! compacted one is kept in `message.data` in place of the offload stub, so reads still return
+//! the full payload while queries on the table only see the preview. Without a store the cut
+//! content is gone. Compacted payloads are marked with `compacted: true` and their original
+//! `size_bytes`, and the outcome is recorded in `session.message_compaction`.
+
+use chrono::{DateTime, Utc};
+use rocket_okapi::okapi::schemars::{self, JsonSchema};
+use sea_orm::prelude::DateTimeWithTimeZone;
+use sea_orm::sea_query::Expr;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, DbBackend, DbErr, EntityTrait,
+    FromQueryResult, QueryFilter, Set, Statement,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::config::MessageQuotaConfig;
+use crate::entities::message::{self, Entity as Message};
+use crate::entities::session::{self, Entity as Session};
+use crate::services::{conversation_view, message_blobs};
+
+/// Messages compacted per query, so one pass never holds many payloads in memory
+const BATCH: u64 = 200;
+
+/// Outcome of the latest compaction of a session's messages
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct MessageCompaction {
+    /// Messages the session stores
+    pub message_count: u64,
+    /// Bytes of message payloads kept in the database
+    pub size_bytes: u64,
+    /// Messages compacted so far
+    pub compacted_messages: u64,
+    /// Whether the session is still over a soft limit, e.g. because the limit is on the
+    /// number of messages, which compaction does not change
+    pub over_quota: bool,
+    pub compacted_at: String,
+}
+
+impl MessageCompaction {
+    pub fn from_json(value: Option<&Value>) -> Option<MessageCompaction> {
+        value.and_then(|v| serde_json::from_value(v.clone()).ok())
+    }
+}
+
+/// How much a session stores
+#[derive(Debug, Clone, Copy, PartialEq, FromQueryResult)]
+pub struct MessageUsage {
+    pub session_id: Uuid,
+    pub message_count: i64,
+    pub size_bytes: i64,
+}
+
+impl MessageUsage {
+    fn over(&self, quota: &MessageQuotaConfig) -> bool {
+        quota
+            .max_messages
+            .is_some_and(|max| self.message_count.max(0) as u64 > max)
+            || quota
+                .max_bytes
+                .is_some_and(|max| self.size_bytes.max(0) as u64 > max)
+    }
+}
+
+const USAGE_SQL: &str = r#"SELECT prompt.session_id AS session_id,
+       COUNT(*)::bigint AS message_count,
+       COALESCE(SUM(octet_length(message.data::text)), 0)::bigint AS size_bytes
+FROM message
+JOIN prompt ON prompt.id = message.prompt_id"#;
+
+/// Up to `limit` sessions over a soft limit, largest first
+pub async fn over_quota(
+    db: &DatabaseConnection,
+    quota: &MessageQuotaConfig,
+    limit: u64,
+) -> Result<Vec<MessageUsage>, DbErr> {
+    let max = |limit: Option<u64>| limit.map(|l| l as i64).unwrap_or(i64::MAX);
+    MessageUsage::find_by_statement(Statement::from_sql_and_values(
+        DbBackend::Postgres,
+        format!(
+            "{}
+             GROUP BY prompt.session_id
+             HAVING COUNT(*) > $1 OR SUM(octet_length(message.data::text)) > $2
+             ORDER BY size_bytes DESC
+             LIMIT $3",
+            USAGE_SQL
+        ),
+        [
+            max(quota.max_messages).into(),
+            max(quota.max_bytes).into(),
+            (limit as i64).into(),
+        ],
+    ))
+    .all(db)
+    .await
+}
+
+async fn usage(db: &DatabaseConnection, session_id: Uuid) -> Result<MessageUsage, DbErr> {
+    let usage = MessageUsage::find_by_statement(Statement::from_sql_and_values(
+        DbBackend::Postgres,
+        format!(
+            "{} WHERE prompt.session_id = $1 GROUP BY prompt.session_id",
+            USAGE_SQL
+        ),
+        [session_id.into()],
+    ))
+    .one(db)
+    .await?;
+    Ok(usage.unwrap_or(MessageUsage {
+        session_id,
+        message_count: 0,
+        size_bytes: 0,
+    }))
+}
+
+/// Older messages of the session with a tool result worth compacting, after `after` in
+/// `(created_at, id)` order
+async fn candidates(
+    db: &DatabaseConnection,
+    session_id: Uuid,
+    quota: &MessageQuotaConfig,
+    after: (DateTimeWithTimeZone, Uuid),
+) -> Result<Vec<message::Model>, DbErr> {
+    Message::find()
+        .from_raw_sql(Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            r#"SELECT message.* FROM message
+               JOIN prompt ON prompt.id = message.prompt_id
+               WHERE prompt.session_id = $1
+                 AND message.blob_key IS NULL
+                 AND message.data->'compacted' IS NULL
+                 AND octet_length(message.data::text) > $3
+                 AND jsonb_path_exists(message.data, '$.** ? (@.type == "tool_result")')
+                 AND message.created_at <= (
+                     SELECT recent.created_at FROM message recent
+                     JOIN prompt recent_prompt ON recent_prompt.id = recent.prompt_id
+                     WHERE recent_prompt.session_id = $1
+                     ORDER BY recent.created_at DESC
+                     OFFSET $2 LIMIT 1)
+                 AND (message.created_at, message.id) > ($5, $6)
+               ORDER BY message.created_at, message.id
+               LIMIT $4"#,
+            [
+                session_id.into(),
+                (quota.keep_recent as i64).into(),
+                (quota.preview_chars as i64).into(),
+                (BATCH as i64).into(),
+                after.0.into(),
+                after.1.into(),
+            ],
+        ))
+        .all(db)
+        .await
+}
+
+/// Compact the older tool results of a session over a soft limit and record the outcome.
+/// Returns how many messages were compacted.
+pub async fn compact_session(
+    db: &DatabaseConnection,
+    session: &session::Model,
+    quota: &MessageQuotaConfig,
+) -> Result<u64, String> {
+    let mut compacted = 0;
+    // Messages left as they are, e.g. after a failed upload, are skipped rather than fetched
+    // again
+    let mut after = (DateTime::UNIX_EPOCH.fixed_offset(), Uuid::nil());
+    loop {
+        let batch = candidates(db, session.id, quota, after)
+            .await
+            .map_err(|e| e.to_string())?;
+        if let Some(last) = batch.last() {
+            after = (last.created_at, last.id);
+        }
+        let mut changed_prompts = Vec::new();
+        for model in &batch {
+            if compact_message(db, model, quota.preview_chars).await? {
+                compacted += 1;
+                changed_prompts.push(model.prompt_id);
+            }
+        }
+        conversation_view::refresh(db, changed_prompts).await;
+        if (batch.len() as u64) < BATCH {
+            break;
+        }
+    }
+
+    let usage = usage(db, session.id).await.map_err(|e| e.to_string())?;
+    let previous = MessageCompaction::from_json(session.message_compaction.as_ref());
+    let outcome = MessageCompaction {
+        message_count: usage.message_count.max(0) as u64,
+        size_bytes: usage.size_bytes.max(0) as u64,
+        compacted_messages: previous.map_or(0, |p| p.compacted_messages) + compacted,
+        over_quota: usage.over(quota),
+        compacted_at: Utc::now().to_rfc3339(),
+    };
+    Session::update_many()
+        .col_expr(
+            session::Column::MessageCompaction,
+            Expr::value(serde_json::to_value(&outcome).ok()),
+        )
+        .filter(session::Column::Id.eq(session.id))
+        .exec(db)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if compacted > 0 {
+        info!(
+            "Compacted {} messages of session {}, which now stores {} messages in {} bytes",
+            compacted, session.id, outcome.message_count, outcome.size_bytes
+        );
+    }
+    Ok(compacted)
+}
+
+/// Replace one message's payload with its compacted form, keeping the original in object
+/// storage when there is one. Returns false when there was nothing to compact.
+async fn compact_message(
+    db: &DatabaseConnection,
+    model: &message::Model,
+    preview_chars: usize,
+) -> Result<bool, String> {
+    let Some(compacted) = compact_payload(&model.data, preview_chars) else {
+        return Ok(false);
+    };
+
+    let blob_key = match message_blobs::get() {
+        Some(blobs) => {
+            let bytes = serde_json::to_vec(&model.data).map_err(|e| e.to_string())?;
+            match blobs.upload(model.id, bytes).await {
+                Ok(key) => Some(key),
+                Err(e) => {
+                    // Keep the whole payload rather than lose what a later pass can offload
+                    warn!("{}, leaving message {} as it is", e, model.id);
+                    return Ok(false);
+                }
+            }
+        }
+        None => None,
+    };
+
+    let active_message = message::ActiveModel {
+        id: Set(model.id),
+        data: Set(compacted),
+        blob_key: Set(blob_key),
+        ..Default::default()
+    };
+    active_message.update(db).await.map_err(|e| e.to_string())?;
+    Ok(true)
+}
+
+/// `data` with each tool result longer than `preview_chars` cut to its start, marked as
+/// compacted, or None when no tool result is that long
+pub fn compact_payload(data: &Value, preview_chars: usize) -> Option<Value> {
+    let mut compacted = data.clone();
+    if !compact_tool_results(&mut compacted, preview_chars) {
+        return None;
+    }
+    if let Value::Object(fields) = &mut compacted {
+        fields.insert("compacted".to_string(), Value::Bool(true));
+        fields.insert(
+            "size_bytes".to_string(),
+            Value::from(data.to_string().len()),
+        );
+    }
+    Some(compacted)
+}
+
+/// Cut the content of every tool result under `value`, returning whether any was cut
+fn compact_tool_results(value: &mut Value, preview_chars: usize) -> bool {
+    match value {
+        Value::Object(fields)
+            if fields.get("type").and_then(Value::as_str) == Some("tool_result") =>
+        {
+            fields
+                .get_mut("content")
+                .is_some_and(|content| cut_content(content, preview_chars))
+        }
+        Value::Object(fields) => compact_all(fields.values_mut(), preview_chars),
+        Value::Array(items) => compact_all(items.iter_mut(), preview_chars),
+        _ => false,
+    }
+}
+
+/// Like `compact_tool_results` for each value, visiting all of them
+fn compact_all<'a>(values: impl Iterator<Item = &'a mut Value>, preview_chars: usize) -> bool {
+    let mut cut = false;
+    for value in values {
+        cut |= compact_tool_results(value, preview_chars);
+    }
+    cut
+}
+
+/// Cut a tool result's content, a string or a list of blocks, to `preview_chars` of text.
+/// Blocks other than text, e.g. images, are replaced with a note.
+fn cut_content(content: &mut Value, preview_chars: usize) -> bool {
+    match content {
+        Value::String(text) => cut_text(text, preview_chars),
+        Value::Array(blocks) => {
+            let mut cut = false;
+            for block in blocks.iter_mut() {
+                match block.get_mut("text") {
+                    Some(Value::String(text)) => cut |= cut_text(text, preview_chars),
+                    Some(_) => {}
+                    None => {
+                        let kind = block
+                            .get("type")
+                            .and_then(Value::as_str)
+                            .unwrap_or("content")
+                            .to_string();
+                        *block = serde_json::json!({
+                            "type": "text",
+                            "text": format!("[{} compacted]", kind),
+                        });
+                        cut = true;
+                    }
+                }
+            }
+            cut
+        }
+        _ => false,
+    }
+}
+
+fn cut_text(text: &mut String, preview_chars: usize) -> bool {
+    let total = text.chars().count();
+    if total <= preview_chars {
+        return false;
+    }
+    let mut preview: String = text.chars().take(preview_chars).collect();
+    preview.push_str(&format!(
+        "\n[... {} more characters compacted]",
+        total - preview_chars
+    ));
+    *text = preview;
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_compact_payload_cuts_long_tool_results() {
+        let data = json!({
+            "type": "user",
+            "message": {
+                "role": "user",
+                "content": [
+                    { "type": "tool_result", "tool_use_id": "t1", "content": "a".repeat(30) },
+                    { "type": "tool_result", "tool_use_id": "t2", "content": [
+                        { "type": "text", "text": "short" },
+                        { "type": "image", "source": { "data": "iVBOR" } },
+                    ] },
+                    { "type": "text", "text": "b".repeat(30) },
+                ],
+            },
+        });
+
+        let compacted = compact_payload(&data, 10).unwrap();
+        let content = &compacted["message"]["content"];
+        assert_eq!(
+            content[0]["content"],
+            format!("{}\n[... 20 more characters compacted]", "a".repeat(10))
+        );
+        assert_eq!(content[1]["content"][0]["text"], "short");
+        assert_eq!(content[1]["content"][1]["text"], "[image compacted]");
+        // Only tool results are cut
+        assert_eq!(content[2]["text"], "b".repeat(30));
+        assert_eq!(compacted["compacted"], true);
+        assert_eq!(compacted["size_bytes"], data.to_string().len());
+
+        // Images are replaced whatever the preview length
+        assert!(compact_payload(&data, 100).is_some());
+        let text_only = json!({ "type": "assistant", "message": { "content": "c".repeat(30) } });
+        assert_eq!(compact_payload(&text_only, 10), None);
+    }
+
+    #[test]
+    fn test_usage_over_quota() {
+        let quota = MessageQuotaConfig {
+            max_messages: None,
+            max_bytes: Some(1000),
+            keep_recent: 10,
+            preview_chars: 100,
+            interval: std::time::Duration::from_secs(60),
+        };
+        let usage = |message_count, size_bytes| MessageUsage {
+            session_id: Uuid::new_v4(),
+            message_count,
+            size_bytes,
+        };
+        assert!(usage(1_000_000, 1001).over(&quota));
+        assert!(!usage(1_000_000, 1000).over(&quota));
+    }
+}
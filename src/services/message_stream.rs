@@ -0,0 +1,130 @@
+//! Server-sent event streams of a session's messages that clients can resume.
+//!
+//! Every message gets a `seq` from a database sequence when it is stored, and its event carries
+//! it as the event id. A client that lost its connection reconnects with the last id it saw in
+//! `Last-Event-ID`, which browsers send on their own, and the stream starts right after that
+//! message, so nothing is sent twice or skipped.
+//!
+//! A session's messages can be written by more than one writer at once, the run's message writer
+//! and the sandbox agent among them, and a `seq` drawn by one transaction may commit after a
+//! higher one drawn by another. A stream that had already passed the higher one would then never
+//! send it. Messages are therefore stored through `insert`, which holds a per-session advisory
+//! lock from drawing the `seq` until the commit, so a session's messages become visible in
+//! `seq` order.
+
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket_okapi::gen::OpenApiGenerator;
+use rocket_okapi::okapi::openapi3::{Object, Parameter, ParameterValue};
+use rocket_okapi::request::{OpenApiFromRequest, RequestHeaderInput};
+use sea_orm::sea_query::Query;
+use sea_orm::{
+    ColumnTrait, ConnectionTrait, DatabaseBackend, DatabaseConnection, DbErr, EntityTrait,
+    QueryFilter, QueryOrder, QuerySelect, Statement, TransactionTrait,
+};
+use uuid::Uuid;
+
+use crate::entities::message::{self, Entity as Message};
+use crate::entities::prompt;
+
+pub const HEADER: &str = "Last-Event-ID";
+
+/// Messages sent from one query; a full batch is followed by the next one at once
+pub const BATCH_SIZE: u64 = 100;
+
+/// `seq` of the last message the client got, from the `Last-Event-ID` header
+pub struct LastEventId(pub Option<i64>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for LastEventId {
+    type Error = String;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match request.headers().get_one(HEADER).map(str::trim) {
+            None | Some("") => Outcome::Success(LastEventId(None)),
+            Some(value) => match value.parse() {
+                Ok(seq) => Outcome::Success(LastEventId(Some(seq))),
+                Err(_) => Outcome::Error((
+                    Status::BadRequest,
+                    format!("{} must be the id of an event", HEADER),
+                )),
+            },
+        }
+    }
+}
+
+impl<'a> OpenApiFromRequest<'a> for LastEventId {
+    fn from_request_input(
+        gen: &mut OpenApiGenerator,
+        _name: String,
+        _required: bool,
+    ) -> rocket_okapi::Result<RequestHeaderInput> {
+        Ok(RequestHeaderInput::Parameter(Parameter {
+            name: HEADER.to_string(),
+            location: "header".to_string(),
+            description: Some(
+                "Id of the last event received; the stream resumes with the message after it"
+                    .to_string(),
+            ),
+            required: false,
+            deprecated: false,
+            allow_empty_value: false,
+            value: ParameterValue::Schema {
+                style: None,
+                explode: None,
+                allow_reserved: false,
+                schema: gen.json_schema::<String>(),
+                example: None,
+                examples: None,
+            },
+            extensions: Object::default(),
+        }))
+    }
+}
+
+/// Take `session_id`'s message insert lock until the end of the transaction `conn` is in
+pub async fn lock(conn: &impl ConnectionTrait, session_id: Uuid) -> Result<(), DbErr> {
+    conn.execute(Statement::from_sql_and_values(
+        DatabaseBackend::Postgres,
+        "SELECT pg_advisory_xact_lock(hashtextextended($1, 0))",
+        [session_id.to_string().into()],
+    ))
+    .await?;
+    Ok(())
+}
+
+/// Store `models`, messages of `session_id`'s prompts, in one statement under the session's
+/// insert lock
+pub async fn insert(
+    db: &DatabaseConnection,
+    session_id: Uuid,
+    models: Vec<message::ActiveModel>,
+) -> Result<(), DbErr> {
+    let txn = db.begin().await?;
+    lock(&txn, session_id).await?;
+    Message::insert_many(models).exec(&txn).await?;
+    txn.commit().await
+}
+
+/// Up to `BATCH_SIZE` of the session's messages stored after `after_seq`, in order
+pub async fn after(
+    db: &DatabaseConnection,
+    session_id: Uuid,
+    after_seq: i64,
+) -> Result<Vec<message::Model>, DbErr> {
+    Message::find()
+        .filter(message::Column::Seq.gt(after_seq))
+        .filter(
+            message::Column::PromptId.in_subquery(
+                Query::select()
+                    .column(prompt::Column::Id)
+                    .from(prompt::Entity)
+                    .and_where(prompt::Column::SessionId.eq(session_id))
+                    .to_owned(),
+            ),
+        )
+        .order_by_asc(message::Column::Seq)
+        .limit(BATCH_SIZE)
+        .all(db)
+        .await
+}
@@ -1,2 +1,64 @@
+pub mod admin_overview;
+pub mod agent_tokens;
 pub mod anthropic;
+pub mod branch_guard;
+pub mod chaos;
+pub mod conversation;
+pub mod conversation_view;
+pub mod cost_estimate;
 pub mod dead_letter_queue;
+pub mod deprovision;
+pub mod egress_policy;
+pub mod entity_lookup;
+pub mod fan_out;
+pub mod fieldsets;
+pub mod force_release;
+pub mod github;
+pub mod github_host;
+pub mod hashing;
+pub mod http_client;
+pub mod idempotency;
+pub mod ingestion_filter;
+pub mod integrity;
+pub mod ip_allocator;
+pub mod json_guard;
+pub mod keycloak;
+pub mod message_blobs;
+pub mod message_compaction;
+pub mod message_stream;
+pub mod notifications;
+pub mod operational_events;
+pub mod organizations;
+pub mod outbox_events;
+pub mod output_log;
+pub mod path_policy;
+pub mod poller_control;
+pub mod pr_description;
+pub mod process_supervisor;
+pub mod prompt_artifacts;
+pub mod prompt_attachments;
+pub mod prompt_changes;
+pub mod queue_stats;
+pub mod railway;
+pub mod repo_lock;
+pub mod sandbox_capabilities;
+pub mod sandbox_exec;
+pub mod sandbox_gh_auth;
+pub mod sandbox_queue;
+pub mod secret_scan;
+pub mod session_activity;
+pub mod session_artifacts;
+pub mod session_budget;
+pub mod session_events;
+pub mod session_preflight;
+pub mod session_state_machine;
+pub mod session_tags;
+pub mod session_titles;
+pub mod session_uploads;
+pub mod shell;
+pub mod soft_cancel;
+pub mod status_meta;
+pub mod unpushed_work;
+pub mod user_erasure;
+pub mod user_settings;
+pub mod workspace_disk;
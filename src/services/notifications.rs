@@ -0,0 +1,191 @@
+//! In-app notifications for session owners and watchers.
+//!
+//! Notifications are created by the state machine's `after_save` hook when a run ends or a
+//! sandbox is held for unpushed work, and by the outbox when a prompt's run fails. Failing to notify never fails the caller.
+
+use sea_orm::{ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, Set};
+use tracing::warn;
+
+use crate::entities::notification::{self, NotificationKind};
+use crate::entities::prompt::Entity as Prompt;
+use crate::entities::session::{Entity as Session, Model as SessionModel, UiStatus};
+use crate::entities::session_watcher::{self, Entity as SessionWatcher};
+use crate::services::session_state_machine::TransitionCause;
+
+/// The notification a transition to `to` produces, if any
+pub fn kind_for(to: &UiStatus, cause: TransitionCause) -> Option<NotificationKind> {
+    match (to, cause) {
+        (
+            UiStatus::NeedsReview,
+            TransitionCause::RunCompleted
+            | TransitionCause::AgentReported
+            | TransitionCause::FanOutFinished,
+        ) => Some(NotificationKind::NeedsReview),
+        (UiStatus::NeedsReview, TransitionCause::Cancelled) => Some(NotificationKind::Cancelled),
+        (UiStatus::NeedsAttention, TransitionCause::UnpushedWorkFound) => {
+            Some(NotificationKind::NeedsAttention)
+        }
+        _ => None,
+    }
+}
+
+fn session_label(session: &SessionModel) -> String {
+    match session.title.as_deref() {
+        Some(title) if !title.trim().is_empty() => format!("\"{}\"", title.trim()),
+        _ => session.id.to_string(),
+    }
+}
+
+fn message_for(kind: &NotificationKind, session: &SessionModel) -> String {
+    let label = session_label(session);
+    match kind {
+        NotificationKind::NeedsReview => match session.status_message.as_deref() {
+            Some(status) => format!("Session {} needs review: {}", label, status),
+            None => format!("Session {} needs review", label),
+        },
+        NotificationKind::Cancelled => format!("Session {} was cancelled", label),
+        NotificationKind::PromptFailed => format!("A prompt in session {} failed", label),
+        NotificationKind::NeedsAttention => match session.status_message.as_deref() {
+            Some(status) => format!("Session {} needs attention: {}", label, status),
+            None => format!("Session {} needs attention", label),
+        },
+    }
+}
+
+/// Users notified about `session`: the owner unless they muted it, plus everyone watching it
+pub async fn recipients(
+    db: &DatabaseConnection,
+    session: &SessionModel,
+) -> Result<Vec<String>, sea_orm::DbErr> {
+    let watchers = SessionWatcher::find()
+        .filter(session_watcher::Column::SessionId.eq(session.id))
+        .all(db)
+        .await?;
+
+    let owner_muted = watchers
+        .iter()
+        .any(|w| w.user_id == session.user_id && !w.watching);
+    let mut users: Vec<String> = watchers
+        .into_iter()
+        .filter(|w| w.watching && w.user_id != session.user_id)
+        .map(|w| w.user_id)
+        .collect();
+    if !owner_muted {
+        users.insert(0, session.user_id.clone());
+    }
+    Ok(users)
+}
+
+/// Notify every recipient of `session`, returning how many notifications were created
+pub async fn notify(
+    db: &DatabaseConnection,
+    session: &SessionModel,
+    prompt_id: Option<uuid::Uuid>,
+    kind: NotificationKind,
+    message: String,
+) -> Result<usize, sea_orm::DbErr> {
+    let users = recipients(db, session).await?;
+    for user_id in &users {
+        notification::ActiveModel {
+            id: Set(uuid::Uuid::new_v4()),
+            user_id: Set(user_id.clone()),
+            session_id: Set(session.id),
+            prompt_id: Set(prompt_id),
+            kind: Set(kind.clone()),
+            message: Set(message.clone()),
+            read_at: Set(None),
+            created_at: Set(chrono::Utc::now().into()),
+        }
+        .insert(db)
+        .await?;
+    }
+    Ok(users.len())
+}
+
+/// Notify about a saved transition of `session` from `from`, if it is one users care about
+pub async fn on_transition(
+    db: &DatabaseConnection,
+    from: &UiStatus,
+    session: &SessionModel,
+    cause: TransitionCause,
+) {
+    if *from == session.ui_status {
+        return;
+    }
+    let Some(kind) = kind_for(&session.ui_status, cause) else {
+        return;
+    };
+
+    let message = message_for(&kind, session);
+    if let Err(e) = notify(db, session, None, kind, message).await {
+        warn!(
+            "Failed to create notifications for session {}: {}",
+            session.id, e
+        );
+    }
+}
+
+/// Notify that the run of `prompt_id` failed with `error`
+pub async fn prompt_failed(db: &DatabaseConnection, prompt_id: uuid::Uuid, error: &str) {
+    let session = match Prompt::find_by_id(prompt_id).one(db).await {
+        Ok(Some(prompt)) => Session::find_by_id(prompt.session_id).one(db).await,
+        Ok(None) => Ok(None),
+        Err(e) => Err(e),
+    };
+
+    let result = match session {
+        Ok(Some(session)) => {
+            let message = format!(
+                "{}: {}",
+                message_for(&NotificationKind::PromptFailed, &session),
+                error
+            );
+            notify(
+                db,
+                &session,
+                Some(prompt_id),
+                NotificationKind::PromptFailed,
+                message,
+            )
+            .await
+            .map(|_| ())
+        }
+        Ok(None) => Ok(()),
+        Err(e) => Err(e),
+    };
+
+    if let Err(e) = result {
+        warn!(
+            "Failed to create failure notifications for prompt {}: {}",
+            prompt_id, e
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kind_for_transition() {
+        assert_eq!(
+            kind_for(&UiStatus::NeedsReview, TransitionCause::RunCompleted),
+            Some(NotificationKind::NeedsReview)
+        );
+        assert_eq!(
+            kind_for(&UiStatus::NeedsReview, TransitionCause::Cancelled),
+            Some(NotificationKind::Cancelled)
+        );
+        assert_eq!(
+            kind_for(
+                &UiStatus::NeedsAttention,
+                TransitionCause::UnpushedWorkFound
+            ),
+            Some(NotificationKind::NeedsAttention)
+        );
+        assert_eq!(
+            kind_for(&UiStatus::InProgress, TransitionCause::SandboxBorrowed),
+            None
+        );
+    }
+}
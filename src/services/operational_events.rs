@@ -0,0 +1,167 @@
+//! Non-fatal errors of background loops and workers, kept in the `operational_event` table so
+//! operators can read what actually failed after the stdout logs rotated away.
+//!
+//! A loop that keeps failing the same way would add a row per iteration, so an error with the
+//! same source, kind and message as a row seen within `OPERATIONAL_EVENT_MERGE_WINDOW_SECS`
+//! bumps that row's count and replaces its context instead. Rows not seen for
+//! `OPERATIONAL_EVENT_RETENTION_DAYS` are deleted by the data retention loop. Like session
+//! events, recording never fails the caller.
+
+use chrono::{DateTime, Utc};
+use sea_orm::sea_query::Expr;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter, QueryOrder,
+    QuerySelect, Set,
+};
+use tracing::warn;
+
+use crate::config;
+use crate::entities::operational_event::{self, Entity as OperationalEvent, OperationalEventKind};
+
+/// Longer messages are cut, e.g. errors quoting a whole response body
+const MAX_MESSAGE_CHARS: usize = 2000;
+
+/// Which events to list
+#[derive(Debug, Clone, Default)]
+pub struct EventFilter {
+    pub source: Option<String>,
+    pub kind: Option<OperationalEventKind>,
+    /// Only events seen at or after this time
+    pub since: Option<DateTime<Utc>>,
+}
+
+/// Kind of an error a loop iteration returned: `database` when a query failed anywhere in its
+/// chain, `other` otherwise
+pub fn kind_of(error: &anyhow::Error) -> OperationalEventKind {
+    if error.chain().any(|cause| cause.is::<DbErr>()) {
+        OperationalEventKind::Database
+    } else {
+        OperationalEventKind::Other
+    }
+}
+
+fn truncate(message: &str) -> String {
+    if message.chars().count() <= MAX_MESSAGE_CHARS {
+        return message.to_string();
+    }
+    format!(
+        "{}…",
+        message.chars().take(MAX_MESSAGE_CHARS).collect::<String>()
+    )
+}
+
+/// Record an error of `source`, merging it into a recent row with the same kind and message
+pub async fn record(
+    db: &DatabaseConnection,
+    source: &str,
+    kind: OperationalEventKind,
+    message: &str,
+    context: Option<serde_json::Value>,
+) {
+    if let Err(e) = upsert(db, source, kind, &truncate(message), context).await {
+        warn!("Failed to record operational event of {}: {}", source, e);
+    }
+}
+
+/// Record the error a loop iteration of `source` returned
+pub async fn record_iteration_error(db: &DatabaseConnection, source: &str, error: &anyhow::Error) {
+    record(db, source, kind_of(error), &format!("{:#}", error), None).await;
+}
+
+async fn upsert(
+    db: &DatabaseConnection,
+    source: &str,
+    kind: OperationalEventKind,
+    message: &str,
+    context: Option<serde_json::Value>,
+) -> Result<(), DbErr> {
+    let now = Utc::now();
+    let merge_after = now
+        - chrono::Duration::from_std(config::get().operational_events.merge_window)
+            .unwrap_or_default();
+    let merged = OperationalEvent::update_many()
+        .col_expr(
+            operational_event::Column::Count,
+            Expr::col(operational_event::Column::Count).add(1),
+        )
+        .col_expr(operational_event::Column::LastSeenAt, Expr::value(now))
+        .col_expr(
+            operational_event::Column::Context,
+            Expr::value(context.clone()),
+        )
+        .filter(operational_event::Column::Source.eq(source))
+        .filter(operational_event::Column::Kind.eq(kind))
+        .filter(operational_event::Column::Message.eq(message))
+        .filter(operational_event::Column::LastSeenAt.gte(merge_after))
+        .exec(db)
+        .await?;
+    if merged.rows_affected > 0 {
+        return Ok(());
+    }
+
+    operational_event::ActiveModel {
+        id: Set(uuid::Uuid::new_v4()),
+        source: Set(source.to_string()),
+        kind: Set(kind),
+        message: Set(message.to_string()),
+        context: Set(context),
+        count: Set(1),
+        first_seen_at: Set(now.into()),
+        last_seen_at: Set(now.into()),
+    }
+    .insert(db)
+    .await?;
+    Ok(())
+}
+
+/// Events matching `filter`, most recently seen first
+pub async fn list(
+    db: &DatabaseConnection,
+    filter: &EventFilter,
+    limit: u64,
+) -> Result<Vec<operational_event::Model>, DbErr> {
+    let mut query = OperationalEvent::find();
+    if let Some(source) = &filter.source {
+        query = query.filter(operational_event::Column::Source.eq(source.as_str()));
+    }
+    if let Some(kind) = filter.kind {
+        query = query.filter(operational_event::Column::Kind.eq(kind));
+    }
+    if let Some(since) = filter.since {
+        query = query.filter(operational_event::Column::LastSeenAt.gte(since));
+    }
+    query
+        .order_by_desc(operational_event::Column::LastSeenAt)
+        .limit(limit)
+        .all(db)
+        .await
+}
+
+/// Delete events last seen before `cutoff`, returning how many were deleted
+pub async fn prune(db: &DatabaseConnection, cutoff: DateTime<Utc>) -> Result<u64, DbErr> {
+    let deleted = OperationalEvent::delete_many()
+        .filter(operational_event::Column::LastSeenAt.lt(cutoff))
+        .exec(db)
+        .await?;
+    Ok(deleted.rows_affected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kind_of_finds_database_errors_in_the_chain() {
+        let db_error = anyhow::Error::new(DbErr::Custom("connection reset".to_string()))
+            .context("Failed to load sessions");
+        assert_eq!(kind_of(&db_error), OperationalEventKind::Database);
+        assert_eq!(
+            kind_of(&anyhow::anyhow!("allocator unavailable")),
+            OperationalEventKind::Other
+        );
+
+        let long = "x".repeat(MAX_MESSAGE_CHARS + 10);
+        assert_eq!(truncate(&long).chars().count(), MAX_MESSAGE_CHARS + 1);
+        assert_eq!(truncate("short"), "short");
+    }
+}
@@ -0,0 +1,68 @@
+//! Organization-scoped access to sessions.
+//!
+//! A user's organization comes from the JWT claim named by `ORG_CLAIM`. Sessions record the
+//! organization of the user who created them, and members of that organization can read them
+//! (the session, its prompts, messages, uploads and artifacts); changing a session stays with
+//! its owner. Users without an organization only see their own sessions, as before.
+//! Organization-wide quotas are kept in the `organization` table, managed by admins.
+
+use sea_orm::{ColumnTrait, Condition, DatabaseConnection, DbErr, EntityTrait};
+use serde_json::Value;
+
+use crate::auth::AuthenticatedUser;
+use crate::entities::organization::{self, Entity as Organization};
+use crate::entities::session;
+
+/// Organization id from the value of the organization claim.
+///
+/// Accepts a string, an array of strings or Keycloak's organization mapper object keyed by
+/// organization; with several organizations the first one is used.
+pub fn org_id_from_claim(value: &Value) -> Option<String> {
+    let org = match value {
+        Value::String(org) => Some(org.as_str()),
+        Value::Array(orgs) => orgs.iter().find_map(Value::as_str),
+        Value::Object(orgs) => orgs.keys().next().map(String::as_str),
+        _ => None,
+    }?;
+    let org = org.trim();
+    (!org.is_empty()).then(|| org.to_string())
+}
+
+/// Filter matching the sessions `user` may read: their own and their organization's
+pub fn visible_to(user: &AuthenticatedUser) -> Condition {
+    let condition = Condition::any().add(session::Column::UserId.eq(&user.user_id));
+    match &user.org_id {
+        Some(org_id) => condition.add(session::Column::OrgId.eq(org_id)),
+        None => condition,
+    }
+}
+
+/// Quotas and settings of the organization, None when it has no row
+pub async fn find(
+    db: &DatabaseConnection,
+    org_id: &str,
+) -> Result<Option<organization::Model>, DbErr> {
+    Organization::find_by_id(org_id.to_string()).one(db).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_org_id_from_claim() {
+        assert_eq!(org_id_from_claim(&json!("acme")), Some("acme".to_string()));
+        assert_eq!(
+            org_id_from_claim(&json!(["acme", "other"])),
+            Some("acme".to_string())
+        );
+        assert_eq!(
+            org_id_from_claim(&json!({"acme": {"id": "42"}})),
+            Some("acme".to_string())
+        );
+        assert_eq!(org_id_from_claim(&json!(" ")), None);
+        assert_eq!(org_id_from_claim(&json!([])), None);
+        assert_eq!(org_id_from_claim(&json!(42)), None);
+    }
+}
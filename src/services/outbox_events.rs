@@ -0,0 +1,170 @@
+//! Transactional outbox for external side effects.
+//!
+//! A side effect is recorded with [`record`] on the same connection or transaction as the
+//! state change that causes it, so either both are committed or neither is. The outbox event
+//! relay claims due events and hands them to the apalis worker, which dispatches them and
+//! records the outcome; failed events are retried with backoff and moved to the DLQ after
+//! `MAX_RETRY_COUNT` attempts.
+
+use sea_orm::{
+    ActiveEnum, ActiveModelTrait, ConnectionTrait, DatabaseConnection, DbBackend, FromQueryResult,
+    Set, Statement,
+};
+use serde_json::Value as JsonValue;
+use std::time::Duration;
+use tracing::{error, warn};
+
+use crate::backoff::jittered_backoff;
+use crate::entities::outbox_event::{
+    self, Model as OutboxEventModel, OutboxEventKind, OutboxEventStatus,
+};
+use crate::services::dead_letter_queue::{insert_dlq_entry, MAX_RETRY_COUNT};
+use crate::services::railway;
+
+/// DLQ task type of events that ran out of attempts
+pub const DLQ_TASK_TYPE: &str = "outbox_event";
+
+/// Events left dispatching this long are assumed lost with their worker and claimed again
+pub const DISPATCH_LEASE: Duration = Duration::from_secs(300);
+
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(30);
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(1800);
+
+/// Record a side effect to dispatch once `conn`'s transaction commits
+pub async fn record<C: ConnectionTrait>(
+    conn: &C,
+    kind: OutboxEventKind,
+    payload: JsonValue,
+) -> Result<OutboxEventModel, sea_orm::DbErr> {
+    let now = chrono::Utc::now();
+    outbox_event::ActiveModel {
+        id: Set(uuid::Uuid::new_v4()),
+        kind: Set(kind),
+        payload: Set(payload),
+        status: Set(OutboxEventStatus::Pending),
+        attempts: Set(0),
+        last_error: Set(None),
+        next_attempt_at: Set(now.into()),
+        created_at: Set(now.into()),
+        updated_at: Set(now.into()),
+        dispatched_at: Set(None),
+    }
+    .insert(conn)
+    .await
+}
+
+#[derive(Debug, FromQueryResult)]
+struct ClaimedEvent {
+    id: uuid::Uuid,
+}
+
+/// Move up to `limit` due events to dispatching and return their ids. Events whose dispatch
+/// lease expired are claimed again.
+pub async fn claim_due(
+    db: &DatabaseConnection,
+    limit: u64,
+) -> Result<Vec<uuid::Uuid>, sea_orm::DbErr> {
+    let claimed = ClaimedEvent::find_by_statement(Statement::from_sql_and_values(
+        DbBackend::Postgres,
+        r#"UPDATE outbox_event
+           SET status = $1, updated_at = now()
+           WHERE id IN (
+             SELECT id FROM outbox_event
+             WHERE (status = $2 AND next_attempt_at <= now())
+                OR (status = $1 AND updated_at < now() - make_interval(secs => $3))
+             ORDER BY created_at
+             LIMIT $4
+             FOR UPDATE SKIP LOCKED
+           )
+           RETURNING id"#,
+        [
+            OutboxEventStatus::Dispatching.to_value().into(),
+            OutboxEventStatus::Pending.to_value().into(),
+            (DISPATCH_LEASE.as_secs() as f64).into(),
+            (limit as i64).into(),
+        ],
+    ))
+    .all(db)
+    .await?;
+
+    Ok(claimed.into_iter().map(|c| c.id).collect())
+}
+
+/// Carry out the side effect of `event`
+pub async fn dispatch(event: &OutboxEventModel) -> Result<(), String> {
+    match event.kind {
+        OutboxEventKind::RailwayRedeploy => railway::redeploy().await,
+    }
+}
+
+/// Record a successful dispatch
+pub async fn mark_dispatched(
+    db: &DatabaseConnection,
+    event: OutboxEventModel,
+) -> Result<(), sea_orm::DbErr> {
+    let now = chrono::Utc::now();
+    let mut active_event: outbox_event::ActiveModel = event.into();
+    active_event.status = Set(OutboxEventStatus::Dispatched);
+    active_event.last_error = Set(None);
+    active_event.dispatched_at = Set(Some(now.into()));
+    active_event.updated_at = Set(now.into());
+    active_event.update(db).await.map(|_| ())
+}
+
+/// Record a failed dispatch: schedule a retry with backoff, or move the event to the DLQ
+/// once it has used up its attempts
+pub async fn mark_failed(
+    db: &DatabaseConnection,
+    event: OutboxEventModel,
+    error: &str,
+) -> Result<(), sea_orm::DbErr> {
+    let attempts = event.attempts + 1;
+    let now = chrono::Utc::now();
+    let dead = attempts >= MAX_RETRY_COUNT;
+
+    if dead {
+        error!(
+            "Outbox event {} ({}) failed {} times, moving to DLQ: {}",
+            event.id,
+            event.kind.to_value(),
+            attempts,
+            error
+        );
+        insert_dlq_entry(
+            db,
+            DLQ_TASK_TYPE,
+            event.id,
+            Some(serde_json::json!({
+                "kind": event.kind.to_value(),
+                "payload": event.payload,
+            })),
+            attempts,
+            error,
+            event.created_at,
+        )
+        .await?;
+    } else {
+        warn!(
+            "Outbox event {} ({}) failed (attempt {} of {}): {}",
+            event.id,
+            event.kind.to_value(),
+            attempts,
+            MAX_RETRY_COUNT,
+            error
+        );
+    }
+
+    let delay = jittered_backoff(attempts as u32, RETRY_BASE_DELAY, RETRY_MAX_DELAY);
+    let mut active_event: outbox_event::ActiveModel = event.into();
+    active_event.attempts = Set(attempts);
+    active_event.last_error = Set(Some(error.to_string()));
+    active_event.updated_at = Set(now.into());
+    if dead {
+        active_event.status = Set(OutboxEventStatus::Failed);
+    } else {
+        active_event.status = Set(OutboxEventStatus::Pending);
+        active_event.next_attempt_at =
+            Set((now + chrono::Duration::from_std(delay).unwrap_or_default()).into());
+    }
+    active_event.update(db).await.map(|_| ())
+}
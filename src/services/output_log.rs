@@ -0,0 +1,423 @@
+//! Copy of each run's CLI output kept in its sandbox, to recover messages the database missed.
+//!
+//! The message writer is the only way from the CLI's stdout into the database, so output
+//! printed while the database was unavailable used to be lost. The CLI runs on the worker,
+//! not in the sandbox, so every message handed to the writer, after the ingestion filter, is
+//! also appended, in batches, to
+//! `/home/gem/claude_output_<prompt_id>.jsonl` in the sandbox through its file API, and the
+//! path is recorded on the prompt. The copy is optimistic: a failed append is logged and the
+//! run goes on, and lines are left out of the copy rather than slowing the reader when its
+//! buffer is full.
+//!
+//! A run that stored fewer messages than it read backfills the missing ones from the copy
+//! before its sandbox is returned; `POST /admin/prompts/<id>/recover-output` does the same on
+//! demand while the session still holds its sandbox. Messages cut down by
+//! `services::message_compaction` since they were stored still count as stored.
+
+use chrono::{DateTime, FixedOffset};
+use rocket::futures::StreamExt;
+use sandbox_client::types::{FileContentEncoding, FileWriteRequest};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, NotSet, QueryFilter,
+    QueryOrder, Set,
+};
+use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::bg_tasks::message_writer;
+use crate::entities::message::{self, Entity as Message};
+use crate::entities::prompt::{self, Model as PromptModel};
+use crate::entities::session::Entity as Session;
+use crate::services::{
+    conversation_view, http_client, message_blobs, message_stream, sandbox_exec,
+};
+
+/// Lines the reader may queue before new ones are left out of the copy
+const BUFFER_CAPACITY: usize = 10_000;
+
+/// Most lines appended per write
+const BATCH_SIZE: usize = 200;
+
+/// How long a line waits for its batch to fill
+const BATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Sandbox file a prompt's CLI output is copied to
+pub fn log_path(prompt_id: Uuid) -> String {
+    format!("/home/gem/claude_output_{}.jsonl", prompt_id)
+}
+
+/// What a copy wrote over a run
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CopyStats {
+    /// Lines appended to the file
+    pub lines: u64,
+    /// Lines left out because the buffer was full or an append failed
+    pub missed: u64,
+}
+
+/// A run's copy in progress; feed it every line of output, then `finish` it
+pub struct OutputCopy {
+    tx: mpsc::Sender<String>,
+    dropped: u64,
+    task: JoinHandle<CopyStats>,
+}
+
+impl OutputCopy {
+    /// Start copying to `path` in the sandbox at `api_url`, replacing what an earlier
+    /// attempt left there
+    pub fn spawn(api_url: &str, path: String) -> Self {
+        let sbx = sandbox_client::Client::new_with_client(api_url, http_client::client());
+        let (tx, rx) = mpsc::channel(BUFFER_CAPACITY);
+        OutputCopy {
+            tx,
+            dropped: 0,
+            task: tokio::spawn(copy(sbx, path, rx)),
+        }
+    }
+
+    /// Queue a line, leaving it out when the buffer is full
+    pub fn send(&mut self, line: String) {
+        match self.tx.try_send(line) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) | Err(TrySendError::Closed(_)) => self.dropped += 1,
+        }
+    }
+
+    /// Append what is still queued and return the totals
+    pub async fn finish(self) -> CopyStats {
+        drop(self.tx);
+        let mut stats = self.task.await.unwrap_or_default();
+        stats.missed += self.dropped;
+        stats
+    }
+}
+
+async fn copy(
+    sbx: sandbox_client::Client,
+    path: String,
+    mut rx: mpsc::Receiver<String>,
+) -> CopyStats {
+    let mut stats = CopyStats::default();
+    if let Err(e) = write(&sbx, &path, String::new(), false).await {
+        warn!("Failed to create output log {} in sandbox: {}", path, e);
+    }
+    while let Some(batch) = message_writer::next_batch(&mut rx, BATCH_SIZE, BATCH_INTERVAL).await {
+        let count = batch.len() as u64;
+        match write(&sbx, &path, batch.join("\n"), true).await {
+            Ok(()) => stats.lines += count,
+            Err(e) => {
+                stats.missed += count;
+                warn!(
+                    "Failed to append {} lines to output log {} in sandbox: {}",
+                    count, path, e
+                );
+            }
+        }
+    }
+    stats
+}
+
+async fn write(
+    sbx: &sandbox_client::Client,
+    path: &str,
+    content: String,
+    append: bool,
+) -> Result<(), String> {
+    let trailing_newline = !content.is_empty();
+    sbx.write_file(&FileWriteRequest {
+        content,
+        file: path.to_string(),
+        append,
+        sudo: false,
+        encoding: FileContentEncoding::Utf8,
+        leading_newline: false,
+        trailing_newline,
+    })
+    .await
+    .map(|_| ())
+    .map_err(|e| e.to_string())
+}
+
+/// Record where the prompt's current run copies its output
+pub async fn record_path(db: &DatabaseConnection, prompt_id: Uuid, path: &str) {
+    let active_prompt = prompt::ActiveModel {
+        id: Set(prompt_id),
+        output_log_path: Set(Some(path.to_string())),
+        ..Default::default()
+    };
+    if let Err(e) = active_prompt.update(db).await {
+        warn!(
+            "Failed to record output log path of prompt {}: {}",
+            prompt_id, e
+        );
+    }
+}
+
+/// Lines of a copy checked and messages written by a recovery
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RecoveryCounts {
+    /// Messages in the copy
+    pub logged: u64,
+    /// Messages the prompt already had
+    pub stored: u64,
+    /// Messages of the copy that were missing and are now stored
+    pub backfilled: u64,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RecoveryError {
+    #[error("Prompt {0} has no output log")]
+    NoLog(Uuid),
+    #[error("Session {0} no longer holds a sandbox")]
+    NoSandbox(Uuid),
+    #[error("Failed to read output log {0}: {1}")]
+    Read(String, String),
+    #[error("Failed to load a stored message: {0}")]
+    Storage(String),
+    #[error(transparent)]
+    Database(#[from] DbErr),
+}
+
+/// Backfill the messages of `prompt`'s last run from its copy in the session's sandbox
+pub async fn recover(
+    db: &DatabaseConnection,
+    prompt: &PromptModel,
+) -> Result<RecoveryCounts, RecoveryError> {
+    let path = prompt
+        .output_log_path
+        .as_deref()
+        .ok_or(RecoveryError::NoLog(prompt.id))?;
+    let api_url = Session::find_by_id(prompt.session_id)
+        .one(db)
+        .await?
+        .as_ref()
+        .and_then(sandbox_exec::sandbox_api_url)
+        .ok_or(RecoveryError::NoSandbox(prompt.session_id))?;
+    let sbx = sandbox_client::Client::new_with_client(&api_url, http_client::client());
+    backfill(db, &sbx, prompt.session_id, prompt.id, path).await
+}
+
+/// Store the messages of the copy at `path` that `prompt_id` of `session_id` is missing, each
+/// placed right after the stored message it followed in the output
+pub async fn backfill(
+    db: &DatabaseConnection,
+    sbx: &sandbox_client::Client,
+    session_id: Uuid,
+    prompt_id: Uuid,
+    path: &str,
+) -> Result<RecoveryCounts, RecoveryError> {
+    let logged = parse(&read(sbx, path).await?);
+
+    let models = Message::find()
+        .filter(message::Column::PromptId.eq(prompt_id))
+        .order_by_asc(message::Column::CreatedAt)
+        .all(db)
+        .await?;
+    let mut stored = Vec::with_capacity(models.len());
+    for model in &models {
+        let data = message_blobs::resolve(model)
+            .await
+            .map_err(RecoveryError::Storage)?;
+        stored.push((data, model.created_at));
+    }
+
+    let mut counts = RecoveryCounts {
+        logged: logged.len() as u64,
+        stored: stored.len() as u64,
+        backfilled: 0,
+    };
+    let missing = missing(logged, stored);
+    if missing.is_empty() {
+        return Ok(counts);
+    }
+
+    let mut offsets: HashMap<Option<DateTime<FixedOffset>>, i64> = HashMap::new();
+    for (data, after) in missing {
+        let message_id = Uuid::new_v4();
+        let (data, blob_key) = message_blobs::prepare(message_id, data).await;
+        let offset = offsets.entry(after).or_default();
+        *offset += 1;
+        let created_at = match after {
+            Some(after) => Set(after + chrono::Duration::microseconds(*offset)),
+            None => NotSet,
+        };
+        let model = message::ActiveModel {
+            id: Set(message_id),
+            prompt_id: Set(prompt_id),
+            data: Set(data),
+            blob_key: Set(blob_key),
+            created_at,
+            updated_at: NotSet,
+            seq: NotSet,
+        };
+        message_stream::insert(db, session_id, vec![model]).await?;
+        counts.backfilled += 1;
+    }
+
+    conversation_view::refresh(db, [prompt_id]).await;
+    info!(
+        "Backfilled {} of {} logged messages of prompt {} from {}",
+        counts.backfilled, counts.logged, prompt_id, path
+    );
+    Ok(counts)
+}
+
+async fn read(sbx: &sandbox_client::Client, path: &str) -> Result<String, RecoveryError> {
+    let failed = |e: String| RecoveryError::Read(path.to_string(), e);
+    let mut stream = sbx
+        .download_file(path)
+        .await
+        .map_err(|e| failed(e.to_string()))?
+        .into_inner()
+        .into_inner();
+    let mut bytes = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        bytes.extend_from_slice(&chunk.map_err(|e| failed(e.to_string()))?);
+    }
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// The JSON messages of a copy; other lines were not stored either
+fn parse(text: &str) -> Vec<Value> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+/// `value` with object keys in order, so equal messages compare equal whatever their key
+/// order
+fn canonical(value: &Value) -> String {
+    match value {
+        Value::Object(map) => {
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            let entries: Vec<String> = entries
+                .into_iter()
+                .map(|(key, value)| format!("{}:{}", Value::from(key.as_str()), canonical(value)))
+                .collect();
+            format!("{{{}}}", entries.join(","))
+        }
+        Value::Array(items) => {
+            let items: Vec<String> = items.iter().map(canonical).collect();
+            format!("[{}]", items.join(","))
+        }
+        other => other.to_string(),
+    }
+}
+
+/// `value` without its tool result contents or compaction marks, which is all a message
+/// compacted without a blob store still shares with the line it was logged as
+fn without_tool_results(value: &Value) -> Value {
+    match value {
+        Value::Object(fields) => {
+            let is_tool_result = fields.get("type").and_then(Value::as_str) == Some("tool_result");
+            let fields = fields
+                .iter()
+                .filter(|(key, _)| !matches!(key.as_str(), "compacted" | "size_bytes"))
+                .map(|(key, value)| match key.as_str() {
+                    "content" if is_tool_result => (key.clone(), Value::Null),
+                    _ => (key.clone(), without_tool_results(value)),
+                })
+                .collect();
+            Value::Object(fields)
+        }
+        Value::Array(items) => Value::Array(items.iter().map(without_tool_results).collect()),
+        other => other.clone(),
+    }
+}
+
+/// The logged messages with no stored counterpart, each with the time of the stored message
+/// logged before it, None when there is none. A stored message marked `compacted` no longer
+/// equals its line and is matched to one line that is equal apart from tool result contents.
+fn missing<T: Copy>(logged: Vec<Value>, stored: Vec<(Value, T)>) -> Vec<(Value, Option<T>)> {
+    let mut unmatched: HashMap<String, VecDeque<T>> = HashMap::new();
+    let mut compacted: HashMap<String, VecDeque<T>> = HashMap::new();
+    for (data, at) in stored {
+        if data.get("compacted") == Some(&Value::Bool(true)) {
+            let key = canonical(&without_tool_results(&data));
+            compacted.entry(key).or_default().push_back(at);
+        } else {
+            unmatched.entry(canonical(&data)).or_default().push_back(at);
+        }
+    }
+
+    let mut after = None;
+    let mut missing = Vec::new();
+    for data in logged {
+        let matched = unmatched
+            .get_mut(&canonical(&data))
+            .and_then(VecDeque::pop_front)
+            .or_else(|| {
+                compacted
+                    .get_mut(&canonical(&without_tool_results(&data)))
+                    .and_then(VecDeque::pop_front)
+            });
+        match matched {
+            Some(at) => after = Some(at),
+            None => missing.push((data, after)),
+        }
+    }
+    missing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_missing_messages_follow_their_predecessor() {
+        let logged = parse(
+            "{\"type\":\"system\",\"n\":1}\nnot json\n\n{\"n\":2,\"type\":\"assistant\"}\n\
+             {\"type\":\"assistant\",\"n\":3}\n{\"type\":\"result\",\"n\":4}\n",
+        );
+        assert_eq!(logged.len(), 4);
+        let stored = vec![
+            (json!({"type": "system", "n": 1}), 10),
+            // Key order differs from the log, as after a round trip through jsonb
+            (json!({"type": "assistant", "n": 2}), 20),
+        ];
+        assert_eq!(
+            missing(logged, stored),
+            vec![
+                (json!({"type": "assistant", "n": 3}), Some(20)),
+                (json!({"type": "result", "n": 4}), Some(20)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_compacted_messages_match_their_line() {
+        let result = |content: &str| {
+            json!({
+                "type": "user",
+                "message": {"content": [
+                    {"type": "tool_result", "tool_use_id": "t1", "content": content}
+                ]}
+            })
+        };
+        let full = result(&"x".repeat(5000));
+        let compacted = crate::services::message_compaction::compact_payload(&full, 10).unwrap();
+        let next = json!({"type": "assistant", "n": 2});
+        let logged = vec![full.clone(), next.clone(), full.clone()];
+
+        // The compacted copy stands for one logged line; the repeat is still missing
+        let stored = vec![(compacted, 1), (next, 2)];
+        assert_eq!(missing(logged, stored), vec![(full, Some(2))]);
+    }
+
+    #[test]
+    fn test_repeated_messages_are_matched_once_each() {
+        let line = json!({"type": "user"});
+        let logged = vec![line.clone(), line.clone(), line.clone()];
+        let missing = missing(logged, vec![(line.clone(), 1)]);
+        assert_eq!(missing, vec![(line.clone(), Some(1)), (line, Some(1))]);
+    }
+}
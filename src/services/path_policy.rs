@@ -0,0 +1,336 @@
+//! Per-session rules on which repo paths Claude may change.
+//!
+//! A policy is a pair of include and exclude glob lists over repo-relative paths. It is added
+//! to the system prompt, enforced by the sandbox's `pre-push` hook, and checked again against
+//! the sandbox clone once the run is over, so a run that touched a denied path fails even when
+//! nothing was pushed.
+
+use glob::{MatchOptions, Pattern};
+use rocket_okapi::okapi::schemars::{self, JsonSchema};
+use sandbox_client::types::ShellExecRequest;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::services::shell;
+
+/// Most globs accepted in each list
+const MAX_PATTERNS: usize = 50;
+
+/// Longest glob accepted, in characters
+const MAX_PATTERN_LENGTH: usize = 200;
+
+/// Most denied paths named in a violation message
+const MAX_REPORTED_VIOLATIONS: usize = 10;
+
+/// Same semantics as git's `:(glob)` pathspecs, which the pre-push hook uses
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// Which paths a session may change.
+///
+/// Globs are matched against the whole repo-relative path: `*` stays within a directory and
+/// `**` spans directories, e.g. `deploy/**` or `**/*.pem`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct PathPolicy {
+    /// Paths that may be changed; empty allows every path that is not excluded
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// Paths that must not be changed, even when they are also included
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+impl PathPolicy {
+    /// The policy stored on a session, None when the session has none or it is empty
+    pub fn from_json(value: Option<&serde_json::Value>) -> Option<PathPolicy> {
+        let policy: PathPolicy = serde_json::from_value(value?.clone()).ok()?;
+        (!policy.is_empty()).then_some(policy)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    /// Check every glob before the policy is stored.
+    ///
+    /// Globs end up quoted in a shell script, so quotes and line breaks are refused along with
+    /// absolute paths, `..` and git pathspec magic.
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, patterns) in [("include", &self.include), ("exclude", &self.exclude)] {
+            if patterns.len() > MAX_PATTERNS {
+                return Err(format!(
+                    "path_policy.{} may have at most {} globs",
+                    name, MAX_PATTERNS
+                ));
+            }
+            for pattern in patterns {
+                validate_pattern(pattern).map_err(|e| {
+                    format!("Invalid path_policy.{} glob {:?}: {}", name, pattern, e)
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the policy lets `path` be changed
+    pub fn allows(&self, path: &str) -> bool {
+        let matches = |p: &String| {
+            Pattern::new(p)
+                .map(|p| p.matches_with(path, MATCH_OPTIONS))
+                .unwrap_or(false)
+        };
+        (self.include.is_empty() || self.include.iter().any(matches))
+            && !self.exclude.iter().any(matches)
+    }
+
+    /// Changed paths the policy denies, sorted and without duplicates
+    pub fn violations<'a>(&self, changed: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+        let mut denied: Vec<String> = changed
+            .into_iter()
+            .map(str::trim)
+            .filter(|p| !p.is_empty() && !self.allows(p))
+            .map(str::to_string)
+            .collect();
+        denied.sort();
+        denied.dedup();
+        denied
+    }
+
+    /// Section appended to the system prompt so Claude knows the rules up front
+    pub fn prompt_section(&self) -> String {
+        let mut section = String::from(
+            "\n\n## Path Policy\n\nThis session is restricted in which files it may change. \
+             Pushes that change other files are rejected and the task fails.\n",
+        );
+        if !self.include.is_empty() {
+            section.push_str("\nOnly change files matching:\n");
+            for pattern in &self.include {
+                section.push_str(&format!("- `{}`\n", pattern));
+            }
+        }
+        if !self.exclude.is_empty() {
+            section.push_str("\nNever change files matching:\n");
+            for pattern in &self.exclude {
+                section.push_str(&format!("- `{}`\n", pattern));
+            }
+        }
+        section
+    }
+
+    /// Shell run by the `pre-push` hook for each pushed ref: refuses the push when the commits
+    /// being pushed change a denied path. Expects `base` and `local_sha` to bound the commits.
+    pub fn pre_push_check(&self) -> String {
+        let range = r#""$base" "$local_sha""#;
+        let mut listings = Vec::new();
+        if !self.exclude.is_empty() {
+            listings.push(format!(
+                "git diff --name-only {} -- {}",
+                range,
+                pathspecs(&self.exclude, "glob")
+            ));
+        }
+        if !self.include.is_empty() {
+            listings.push(format!(
+                "git diff --name-only {} -- . {}",
+                range,
+                pathspecs(&self.include, "exclude,glob")
+            ));
+        }
+
+        format!(
+            r#"  denied=$( {{ {listings}; }} | sort -u | tr '\n' ' ')
+  if [ -n "$denied" ]; then
+    echo "prompt-backend: refusing to push changes to paths denied by the session's path policy: $denied" >&2
+    exit 1
+  fi
+"#,
+            listings = listings.join("; "),
+        )
+    }
+}
+
+fn validate_pattern(pattern: &str) -> Result<(), String> {
+    if pattern.trim().is_empty() {
+        return Err("glob must not be empty".to_string());
+    }
+    if pattern.chars().count() > MAX_PATTERN_LENGTH {
+        return Err(format!(
+            "glob must be at most {} characters",
+            MAX_PATTERN_LENGTH
+        ));
+    }
+    if pattern.contains(['\'', '"', '\n', '\r', '\\']) {
+        return Err("glob must not contain quotes, backslashes or line breaks".to_string());
+    }
+    if pattern.starts_with('/') || pattern.starts_with(':') {
+        return Err("glob must be relative to the repo root".to_string());
+    }
+    if pattern.split('/').any(|part| part == "..") {
+        return Err("glob must not contain '..'".to_string());
+    }
+    Pattern::new(pattern)
+        .map(|_| ())
+        .map_err(|e| e.msg.to_string())
+}
+
+/// Single-quoted git pathspecs with `magic` for each glob
+fn pathspecs(patterns: &[String], magic: &str) -> String {
+    patterns
+        .iter()
+        .map(|p| shell::quote(&format!(":({}){}", magic, p)))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Status message of a run that changed denied paths
+pub fn violation_message(violations: &[String]) -> String {
+    let mut listed = violations
+        .iter()
+        .take(MAX_REPORTED_VIOLATIONS)
+        .cloned()
+        .collect::<Vec<_>>()
+        .join(", ");
+    if violations.len() > MAX_REPORTED_VIOLATIONS {
+        listed.push_str(&format!(
+            " and {} more",
+            violations.len() - MAX_REPORTED_VIOLATIONS
+        ));
+    }
+    format!("Path policy violation: changed denied paths {}", listed)
+}
+
+/// Paths changed in the sandbox clone since it branched off the target branch, whether
+/// committed, staged, modified or untracked.
+///
+/// Returns a violation message when any of them is denied. A failed listing is logged and
+/// treated as no violation; the pre-push hook still kept denied changes from being pushed.
+pub async fn check(
+    sbx: &sandbox_client::Client,
+    policy: &PathPolicy,
+    repo_path: &str,
+    target_branch: &str,
+) -> Option<String> {
+    let command = format!(
+        "base=$(git merge-base HEAD {} 2>/dev/null || git hash-object -t tree /dev/null); \
+         {{ git diff --name-only \"$base\"; git ls-files --others --exclude-standard; }} | sort -u",
+        shell::quote(&format!("origin/{}", target_branch))
+    );
+    let response = sbx
+        .exec_command_v1_shell_exec_post(&ShellExecRequest {
+            command,
+            async_mode: false,
+            id: None,
+            timeout: Some(30.0_f64),
+            exec_dir: Some(repo_path.to_string()),
+        })
+        .await;
+
+    let output = match response {
+        Ok(response) => match response.into_inner().data {
+            Some(result) if result.exit_code == Some(0) => result.output.unwrap_or_default(),
+            Some(result) => {
+                warn!(
+                    "Listing changed paths in {} exited with {:?}, skipping path policy check",
+                    repo_path, result.exit_code
+                );
+                return None;
+            }
+            None => return None,
+        },
+        Err(e) => {
+            warn!(
+                "Failed to list changed paths in {}, skipping path policy check: {}",
+                repo_path, e
+            );
+            return None;
+        }
+    };
+
+    let violations = policy.violations(output.lines());
+    (!violations.is_empty()).then(|| violation_message(&violations))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(include: &[&str], exclude: &[&str]) -> PathPolicy {
+        PathPolicy {
+            include: include.iter().map(|s| s.to_string()).collect(),
+            exclude: exclude.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_allows_exclude_wins_over_include() {
+        let policy = policy(&["src/**", "Cargo.toml"], &["src/secrets/**", "**/*.pem"]);
+        assert!(policy.allows("src/main.rs"));
+        assert!(policy.allows("Cargo.toml"));
+        assert!(!policy.allows("src/secrets/token.txt"));
+        assert!(!policy.allows("src/certs/server.pem"));
+        assert!(!policy.allows("deploy/prod.yaml"));
+    }
+
+    #[test]
+    fn test_star_stays_within_directory() {
+        let policy = policy(&[], &["*.env"]);
+        assert!(!policy.allows("prod.env"));
+        assert!(policy.allows("config/prod.env"));
+    }
+
+    #[test]
+    fn test_violations_are_sorted_and_deduplicated() {
+        let policy = policy(&[], &["deploy/**"]);
+        assert_eq!(
+            policy.violations([
+                "deploy/b.yaml",
+                "src/lib.rs",
+                "deploy/a.yaml",
+                "deploy/b.yaml",
+                ""
+            ]),
+            vec!["deploy/a.yaml".to_string(), "deploy/b.yaml".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_validate_rejects_unsafe_globs() {
+        assert!(policy(&["src/**"], &["deploy/**", "**/*.pem"])
+            .validate()
+            .is_ok());
+        assert!(policy(&[""], &[]).validate().is_err());
+        assert!(policy(&[], &["/etc/**"]).validate().is_err());
+        assert!(policy(&[], &["../other/**"]).validate().is_err());
+        assert!(policy(&[], &["a'; rm -rf /; '"]).validate().is_err());
+        assert!(policy(&[], &[":(exclude)src"]).validate().is_err());
+        assert!(policy(&[], &["src/[a"]).validate().is_err());
+    }
+
+    #[test]
+    fn test_pre_push_check_uses_git_glob_pathspecs() {
+        let check = policy(&["src/**"], &["deploy/**"]).pre_push_check();
+        assert!(
+            check.contains(r#"git diff --name-only "$base" "$local_sha" -- ':(glob)deploy/**'"#)
+        );
+        assert!(check.contains(r#"-- . ':(exclude,glob)src/**'"#));
+
+        let check = policy(&[], &["deploy/**"]).pre_push_check();
+        assert!(!check.contains("exclude,glob"));
+    }
+
+    #[test]
+    fn test_from_json_ignores_empty_policy() {
+        assert_eq!(PathPolicy::from_json(None), None);
+        assert_eq!(
+            PathPolicy::from_json(Some(&serde_json::json!({"include": [], "exclude": []}))),
+            None
+        );
+        assert_eq!(
+            PathPolicy::from_json(Some(&serde_json::json!({"exclude": ["deploy/**"]}))),
+            Some(policy(&[], &["deploy/**"]))
+        );
+    }
+}
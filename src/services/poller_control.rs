@@ -0,0 +1,82 @@
+//! Pausing background pollers without stopping the process, for incident response.
+//!
+//! An admin pauses a poller with `POST /admin/pollers/<name>/pause`; the flag lives in the
+//! `poller_control` table so it reaches every instance. Each poller checks it at the start of
+//! every iteration and skips the iteration while paused, so a pause takes effect within one
+//! poll interval and nothing already running is interrupted.
+
+use chrono::Utc;
+use sea_orm::sea_query::OnConflict;
+use sea_orm::{DatabaseConnection, DbErr, EntityTrait, Set};
+use tracing::{info, warn};
+
+use crate::bg_tasks::worker_registry;
+use crate::entities::poller_control::{self, Entity as PollerControl, Model as PollerControlModel};
+
+/// Pollers that can be paused
+pub const PAUSABLE: &[&str] = &["prompt_poller", "ip_return_poller", "cancellation_enforcer"];
+
+pub fn is_pausable(name: &str) -> bool {
+    PAUSABLE.contains(&name)
+}
+
+/// Pause or resume `name` on every instance
+pub async fn set_paused(
+    db: &DatabaseConnection,
+    name: &str,
+    paused: bool,
+    updated_by: &str,
+) -> Result<PollerControlModel, DbErr> {
+    let model = poller_control::ActiveModel {
+        name: Set(name.to_string()),
+        paused: Set(paused),
+        updated_by: Set(Some(updated_by.to_string())),
+        updated_at: Set(Utc::now().into()),
+    };
+    PollerControl::insert(model)
+        .on_conflict(
+            OnConflict::column(poller_control::Column::Name)
+                .update_columns([
+                    poller_control::Column::Paused,
+                    poller_control::Column::UpdatedBy,
+                    poller_control::Column::UpdatedAt,
+                ])
+                .to_owned(),
+        )
+        .exec(db)
+        .await?;
+
+    info!(
+        "Poller {} {} by {}",
+        name,
+        if paused { "paused" } else { "resumed" },
+        updated_by
+    );
+    PollerControl::find_by_id(name.to_string())
+        .one(db)
+        .await?
+        .ok_or_else(|| DbErr::RecordNotFound(format!("Poller control {} not found", name)))
+}
+
+/// Control rows of every poller that has been paused or resumed
+pub async fn all(db: &DatabaseConnection) -> Result<Vec<PollerControlModel>, DbErr> {
+    PollerControl::find().all(db).await
+}
+
+/// Whether the poller `name` should skip this iteration, noting the answer in the worker
+/// registry. A failed lookup is logged and treated as running, so a database hiccup cannot
+/// silently stop a poller.
+pub async fn paused(db: &DatabaseConnection, name: &'static str) -> bool {
+    let paused = match PollerControl::find_by_id(name.to_string()).one(db).await {
+        Ok(control) => control.is_some_and(|c| c.paused),
+        Err(e) => {
+            warn!(
+                "Failed to check whether {} is paused, running it: {}",
+                name, e
+            );
+            false
+        }
+    };
+    worker_registry::record_paused(name, paused);
+    paused
+}
@@ -0,0 +1,315 @@
+//! Pull request descriptions generated from what happened in a session.
+//!
+//! Claude opens a session's pull request with `gh pr create` and a body of its own. Once the
+//! pull request has been recorded after a run, its body is replaced with one filled in from a
+//! template: a summary of the session's prompts and diff written by the Anthropic API, the
+//! changed files, the tool calls the runs made and a link back to the session. The template is
+//! `prompts/pull_request_description.md` unless `PR_DESCRIPTION_TEMPLATE_FILE` names another,
+//! with `{TITLE}`, `{SUMMARY}`, `{CHANGES}`, `{TOOL_USAGE}`, `{SESSION_URL}` and
+//! `{SESSION_LINK}` placeholders. The description is stored on the session, which also marks
+//! its pull request as described.
+
+use sea_orm::sea_query::Expr;
+use sea_orm::{
+    ColumnTrait, DatabaseConnection, DbErr, EntityTrait, PaginatorTrait, QueryFilter, QueryOrder,
+};
+use std::collections::BTreeMap;
+use tracing::{info, warn};
+
+use crate::bg_tasks::prompt_history;
+use crate::bg_tasks::prompt_tools::ToolSummary;
+use crate::config;
+use crate::entities::prompt::{self, Entity as Prompt};
+use crate::entities::prompt_artifact::{self, PromptArtifactKind};
+use crate::entities::session::{self, Entity as Session, Model as SessionModel};
+use crate::services::anthropic;
+use crate::services::github::{ComparedFile, GithubClient, GithubError};
+use crate::services::github_host;
+
+pub const DEFAULT_TEMPLATE: &str = include_str!("../../prompts/pull_request_description.md");
+
+/// Characters of each prompt given to the summary
+const MAX_PROMPT_CHARS: usize = 2000;
+
+/// Changed files listed in the description
+const MAX_LISTED_FILES: usize = 50;
+
+#[derive(Debug, thiserror::Error)]
+pub enum PrDescriptionError {
+    #[error("Session has no repo: {0}")]
+    NoRepo(String),
+    #[error("No pull request is open from branch {0}")]
+    NoPullRequest(String),
+    #[error(transparent)]
+    Github(#[from] GithubError),
+    #[error(transparent)]
+    Database(#[from] DbErr),
+}
+
+/// A description written to a pull request
+#[derive(Debug, Clone)]
+pub struct Described {
+    pub pull_request_url: String,
+    pub description: String,
+}
+
+/// Values of the template's placeholders
+#[derive(Debug, Clone, Default)]
+struct Fields {
+    title: String,
+    summary: String,
+    changes: String,
+    tool_usage: String,
+    session_url: Option<String>,
+}
+
+fn render(template: &str, fields: &Fields) -> String {
+    let session_url = fields.session_url.clone().unwrap_or_default();
+    let session_link = fields
+        .session_url
+        .as_ref()
+        .map(|url| format!("[View the session]({})", url))
+        .unwrap_or_default();
+    let rendered = template
+        .replace("{TITLE}", &fields.title)
+        .replace("{SUMMARY}", &fields.summary)
+        .replace("{CHANGES}", &fields.changes)
+        .replace("{TOOL_USAGE}", &fields.tool_usage)
+        .replace("{SESSION_URL}", &session_url)
+        .replace("{SESSION_LINK}", &session_link);
+    format!("{}\n", rendered.trim_end())
+}
+
+fn template() -> String {
+    let Some(path) = &config::get().pull_requests.template_file else {
+        return DEFAULT_TEMPLATE.to_string();
+    };
+    std::fs::read_to_string(path).unwrap_or_else(|e| {
+        warn!(
+            "Failed to read pull request template {}, using the default: {}",
+            path, e
+        );
+        DEFAULT_TEMPLATE.to_string()
+    })
+}
+
+fn file_line(file: &ComparedFile) -> String {
+    format!(
+        "`{}` ({}, +{}/-{})",
+        file.filename, file.status, file.additions, file.deletions
+    )
+}
+
+fn changes_list(files: &[ComparedFile]) -> String {
+    if files.is_empty() {
+        return "No changed files found.".to_string();
+    }
+    let mut lines: Vec<String> = files
+        .iter()
+        .take(MAX_LISTED_FILES)
+        .map(|file| format!("- {}", file_line(file)))
+        .collect();
+    if files.len() > MAX_LISTED_FILES {
+        lines.push(format!("- …and {} more", files.len() - MAX_LISTED_FILES));
+    }
+    lines.join("\n")
+}
+
+fn tool_usage(summaries: &[ToolSummary], runs: usize) -> String {
+    let total: u64 = summaries.iter().map(|s| s.total).sum();
+    if total == 0 {
+        return format!("{} runs, no tool calls recorded.", runs);
+    }
+    let mut by_category = BTreeMap::new();
+    for summary in summaries {
+        for (category, count) in &summary.by_category {
+            *by_category.entry(category.as_str()).or_insert(0) += count;
+        }
+    }
+    let categories = by_category
+        .iter()
+        .map(|(category, count)| format!("{} {}", category, count))
+        .collect::<Vec<_>>()
+        .join(", ");
+    format!("{} runs, {} tool calls: {}", runs, total, categories)
+}
+
+fn truncate(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+    format!("{}…", text.chars().take(max_chars).collect::<String>())
+}
+
+/// Generate a description for the pull request from `session`'s branch, write it to the pull
+/// request and store it on the session
+pub async fn describe(
+    db: &DatabaseConnection,
+    session: &SessionModel,
+) -> Result<Described, PrDescriptionError> {
+    let repo = session.repo.as_deref().unwrap_or_default();
+    let location =
+        github_host::resolve_repo(repo).map_err(|e| PrDescriptionError::NoRepo(e.to_string()))?;
+    let branch = session.branch.clone().unwrap_or_default();
+    let target_branch = session
+        .target_branch
+        .clone()
+        .unwrap_or_else(|| "main".to_string());
+    let github = GithubClient::for_user(&location.host, &session.user_id).await?;
+
+    let pull_request = github
+        .list_pull_requests_for_branch(&location.repo, &branch)
+        .await?
+        .into_iter()
+        .next()
+        .ok_or_else(|| PrDescriptionError::NoPullRequest(branch.clone()))?;
+
+    let prompts = Prompt::find()
+        .filter(prompt::Column::SessionId.eq(session.id))
+        .filter(prompt::Column::Draft.eq(false))
+        .order_by_asc(prompt::Column::CreatedAt)
+        .all(db)
+        .await?;
+    let prompt_texts: Vec<String> = prompts
+        .iter()
+        .map(|p| truncate(&prompt_history::prompt_text(&p.data), MAX_PROMPT_CHARS))
+        .collect();
+    let summaries: Vec<ToolSummary> = prompts
+        .iter()
+        .filter_map(|p| serde_json::from_value(p.tool_summary.clone()?).ok())
+        .collect();
+    let runs = prompts.iter().filter(|p| p.completed_at.is_some()).count();
+
+    let files = match github
+        .compare(&location.repo, &target_branch, &branch)
+        .await
+    {
+        Ok(comparison) => comparison.files,
+        Err(e) => {
+            warn!(
+                "Failed to compare {} with {} of {}: {}",
+                branch, target_branch, location.repo, e
+            );
+            Vec::new()
+        }
+    };
+    let file_lines: Vec<String> = files.iter().map(file_line).collect();
+
+    let summary = match anthropic::generate_pull_request_summary(&prompt_texts, &file_lines).await {
+        Ok(summary) => summary,
+        Err(e) => {
+            warn!(
+                "Failed to generate pull request summary of session {}, using its first prompt: {}",
+                session.id, e
+            );
+            prompt_texts.first().cloned().unwrap_or_default()
+        }
+    };
+
+    let fields = Fields {
+        title: session.title.clone().unwrap_or_default(),
+        summary,
+        changes: changes_list(&files),
+        tool_usage: tool_usage(&summaries, runs),
+        session_url: config::get()
+            .pull_requests
+            .session_url_template
+            .as_ref()
+            .map(|template| template.replace("{session_id}", &session.id.to_string())),
+    };
+    let description = render(&template(), &fields);
+
+    github
+        .update_pull_request_body(&location.repo, pull_request.number, &description)
+        .await?;
+    Session::update_many()
+        .col_expr(
+            session::Column::PullRequestDescription,
+            Expr::value(description.clone()),
+        )
+        .filter(session::Column::Id.eq(session.id))
+        .exec(db)
+        .await?;
+
+    info!(
+        "Wrote description of pull request {} for session {}",
+        pull_request.html_url, session.id
+    );
+    Ok(Described {
+        pull_request_url: pull_request.html_url,
+        description,
+    })
+}
+
+/// Describe the session's pull request if one has been recorded and not described yet.
+/// Best-effort: failures are logged.
+pub async fn describe_if_new(db: &DatabaseConnection, session_id: uuid::Uuid) {
+    if !config::get().pull_requests.describe {
+        return;
+    }
+    let session = match Session::find_by_id(session_id).one(db).await {
+        Ok(Some(session)) if session.pull_request_description.is_none() => session,
+        Ok(_) => return,
+        Err(e) => {
+            warn!("Failed to load session {}: {}", session_id, e);
+            return;
+        }
+    };
+    let recorded = prompt_artifact::Entity::find()
+        .filter(prompt_artifact::Column::SessionId.eq(session_id))
+        .filter(prompt_artifact::Column::Kind.eq(PromptArtifactKind::PullRequest))
+        .count(db)
+        .await;
+    match recorded {
+        Ok(0) => {}
+        Ok(_) => {
+            if let Err(e) = describe(db, &session).await {
+                warn!(
+                    "Failed to describe pull request of session {}: {}",
+                    session_id, e
+                );
+            }
+        }
+        Err(e) => warn!(
+            "Failed to look up pull requests of session {}: {}",
+            session_id, e
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_default_template() {
+        let fields = Fields {
+            title: "Add CSV export".to_string(),
+            summary: "Adds a CSV export.".to_string(),
+            changes: changes_list(&[ComparedFile {
+                filename: "src/export.rs".to_string(),
+                status: "added".to_string(),
+                additions: 40,
+                deletions: 0,
+            }]),
+            tool_usage: tool_usage(
+                &[ToolSummary {
+                    total: 3,
+                    by_category: [("shell".to_string(), 2), ("file_write".to_string(), 1)].into(),
+                    by_tool: BTreeMap::new(),
+                }],
+                1,
+            ),
+            session_url: Some("https://app.example.com/sessions/s1".to_string()),
+        };
+        let description = render(DEFAULT_TEMPLATE, &fields);
+
+        assert!(description.contains("## Summary\n\nAdds a CSV export."));
+        assert!(description.contains("- `src/export.rs` (added, +40/-0)"));
+        assert!(description.contains("1 runs, 3 tool calls: file_write 1, shell 2"));
+        assert!(description.ends_with("[View the session](https://app.example.com/sessions/s1)\n"));
+
+        let without_link = render(DEFAULT_TEMPLATE, &Fields::default());
+        assert!(without_link.ends_with("## Agent activity\n"));
+    }
+}
@@ -0,0 +1,265 @@
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::LazyLock;
+use tokio::sync::{Semaphore, SemaphorePermit};
+use tracing::{info, warn};
+
+use crate::config::KillSignal;
+
+/// Resource limits applied to Claude CLI processes
+#[derive(Debug, Clone)]
+pub struct ProcessLimits {
+    /// CLI processes allowed to run at once; excess runs wait for a slot
+    pub max_concurrent: usize,
+    /// Niceness the CLI runs at, None to inherit ours
+    pub nice: Option<i32>,
+    /// cgroup v2 `memory.max` for each CLI process, None for no ceiling
+    pub memory_max_bytes: Option<u64>,
+    /// Parent cgroup under which a child cgroup is created per process
+    pub cgroup_root: PathBuf,
+}
+
+impl ProcessLimits {
+    /// Read limits from `CLI_MAX_CONCURRENT` (default: available CPUs), `CLI_NICE`,
+    /// `CLI_MEMORY_MAX_MB` and `CLI_CGROUP_ROOT` (default: /sys/fs/cgroup/prompt-backend)
+    fn from_env() -> Self {
+        let max_concurrent = std::env::var("CLI_MAX_CONCURRENT")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|n| *n > 0)
+            .unwrap_or_else(|| {
+                std::thread::available_parallelism()
+                    .map(|n| n.get())
+                    .unwrap_or(4)
+            });
+
+        Self {
+            max_concurrent,
+            nice: std::env::var("CLI_NICE").ok().and_then(|v| v.parse().ok()),
+            memory_max_bytes: std::env::var("CLI_MEMORY_MAX_MB")
+                .ok()
+                .and_then(|v| v.parse::<u64>().ok())
+                .map(|mb| mb * 1024 * 1024),
+            cgroup_root: std::env::var("CLI_CGROUP_ROOT")
+                .unwrap_or_else(|_| "/sys/fs/cgroup/prompt-backend".to_string())
+                .into(),
+        }
+    }
+}
+
+/// Runs Claude CLI invocations under `ProcessLimits`, queueing runs beyond the concurrency cap
+pub struct ProcessSupervisor {
+    limits: ProcessLimits,
+    slots: Semaphore,
+}
+
+static SUPERVISOR: LazyLock<ProcessSupervisor> = LazyLock::new(|| {
+    let limits = ProcessLimits::from_env();
+    info!("CLI process limits: {:?}", limits);
+    ProcessSupervisor::new(limits)
+});
+
+/// Get the process-wide supervisor
+pub fn get() -> &'static ProcessSupervisor {
+    &SUPERVISOR
+}
+
+/// A reserved CLI slot, released when dropped
+pub struct CliSlot {
+    _permit: SemaphorePermit<'static>,
+}
+
+impl Drop for CliSlot {
+    fn drop(&mut self) {
+        crate::metrics::get().cli_processes_running.dec();
+    }
+}
+
+/// A per-process cgroup enforcing the memory ceiling, removed when dropped
+pub struct CliCgroup {
+    path: PathBuf,
+}
+
+impl CliCgroup {
+    /// Peak memory of everything in the cgroup (`memory.peak`, Linux 5.19+)
+    pub fn peak_memory_bytes(&self) -> Option<u64> {
+        std::fs::read_to_string(self.path.join("memory.peak"))
+            .ok()
+            .and_then(|v| v.trim().parse().ok())
+    }
+}
+
+impl Drop for CliCgroup {
+    fn drop(&mut self) {
+        // Only succeeds once the process has exited and the cgroup is empty
+        if let Err(e) = std::fs::remove_dir(&self.path) {
+            warn!("Failed to remove cgroup {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+impl ProcessSupervisor {
+    pub fn new(limits: ProcessLimits) -> Self {
+        Self {
+            slots: Semaphore::new(limits.max_concurrent),
+            limits,
+        }
+    }
+
+    /// Wait for a free CLI slot. Waiting runs are counted in `cli_process_queue_depth`.
+    pub async fn acquire(&'static self) -> CliSlot {
+        let metrics = crate::metrics::get();
+        metrics.cli_process_queue_depth.inc();
+        let permit = self
+            .slots
+            .acquire()
+            .await
+            .expect("CLI slot semaphore is never closed");
+        metrics.cli_process_queue_depth.dec();
+        metrics.cli_processes_running.inc();
+        CliSlot { _permit: permit }
+    }
+
+    /// A command for `program`, run through `nice` when a niceness is configured
+    pub fn command(&self, program: &str) -> Command {
+        match self.limits.nice {
+            Some(nice) => {
+                let mut command = Command::new("nice");
+                command.args(["-n", &nice.to_string(), program]);
+                command
+            }
+            None => Command::new(program),
+        }
+    }
+
+    /// Move a spawned process into its own cgroup with the configured memory ceiling.
+    ///
+    /// Returns None when no ceiling is configured or cgroups are unavailable; the process
+    /// then runs without a memory limit.
+    pub fn confine(&self, pid: u32) -> Option<CliCgroup> {
+        let memory_max = self.limits.memory_max_bytes?;
+        let cgroup = CliCgroup {
+            path: self.limits.cgroup_root.join(format!("cli-{}", pid)),
+        };
+
+        let result = std::fs::create_dir_all(&cgroup.path)
+            .and_then(|_| std::fs::write(cgroup.path.join("memory.max"), memory_max.to_string()))
+            .and_then(|_| std::fs::write(cgroup.path.join("cgroup.procs"), pid.to_string()));
+
+        match result {
+            Ok(()) => Some(cgroup),
+            Err(e) => {
+                warn!(
+                    "Failed to apply memory limit to CLI process {} via {}: {}",
+                    pid,
+                    cgroup.path.display(),
+                    e
+                );
+                None
+            }
+        }
+    }
+}
+
+/// Peak resident set size of a running process (`VmHWM` from /proc/<pid>/status)
+pub fn peak_rss_bytes(pid: u32) -> Option<u64> {
+    let status = std::fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+    parse_vm_hwm(&status)
+}
+
+fn parse_vm_hwm(status: &str) -> Option<u64> {
+    let line = status.lines().find(|l| l.starts_with("VmHWM:"))?;
+    let kb: u64 = line
+        .trim_start_matches("VmHWM:")
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kb * 1024)
+}
+
+/// Send `signal` (a name such as `TERM`, or `0` to check the process exists) to `pid`,
+/// returning whether it was delivered
+pub fn signal(pid: u32, signal: &str) -> bool {
+    Command::new("kill")
+        .arg(format!("-{}", signal))
+        .arg(pid.to_string())
+        .output()
+        .map(|output| output.status.success())
+        .unwrap_or(false)
+}
+
+/// SIGTERM `pid`, then SIGKILL it if it is still running `grace` later
+pub async fn terminate(pid: u32, grace: std::time::Duration) {
+    signal(pid, "TERM");
+    tokio::time::sleep(grace).await;
+    if signal(pid, "0") {
+        info!(
+            "CLI process {} still running {:?} after SIGTERM, killing it",
+            pid, grace
+        );
+        signal(pid, "KILL");
+    }
+}
+
+/// Send the rest of `signals` to `pid` after the first was delivered, each after its delay,
+/// until the process is gone
+pub async fn escalate(pid: u32, signals: &[KillSignal]) {
+    for next in signals.iter().skip(1) {
+        tokio::time::sleep(next.after).await;
+        if !signal(pid, "0") {
+            return;
+        }
+        info!(
+            "Process {} still running {:?} later, sending SIG{}",
+            pid, next.after, next.name
+        );
+        if !signal(pid, &next.name) {
+            warn!("Failed to send SIG{} to process {}", next.name, pid);
+        }
+    }
+}
+
+/// Record a finished CLI process's peak memory
+pub fn observe_peak_memory(bytes: u64) {
+    crate::metrics::get()
+        .cli_process_peak_memory_bytes
+        .observe(bytes as f64);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(nice: Option<i32>) -> ProcessLimits {
+        ProcessLimits {
+            max_concurrent: 1,
+            nice,
+            memory_max_bytes: None,
+            cgroup_root: PathBuf::from("/nonexistent"),
+        }
+    }
+
+    #[test]
+    fn test_command_applies_nice() {
+        let command = ProcessSupervisor::new(limits(Some(10))).command("claude");
+        assert_eq!(command.get_program(), "nice");
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            vec!["-n", "10", "claude"]
+        );
+
+        let command = ProcessSupervisor::new(limits(None)).command("claude");
+        assert_eq!(command.get_program(), "claude");
+        assert!(ProcessSupervisor::new(limits(None)).confine(1).is_none());
+    }
+
+    #[test]
+    fn test_parse_vm_hwm() {
+        let status =
+            "Name:\tclaude\nVmPeak:\t  900000 kB\nVmHWM:\t  204800 kB\nVmRSS:\t  100000 kB\n";
+        assert_eq!(parse_vm_hwm(status), Some(204800 * 1024));
+        assert_eq!(parse_vm_hwm("Name:\tclaude\n"), None);
+    }
+}
@@ -0,0 +1,251 @@
+//! Traceability from prompts to the commits and pull requests their runs produced.
+//!
+//! The sandbox clone's `HEAD` is noted before the CLI runs. Afterwards every commit between it
+//! and the session branch's remote-tracking ref, which `git push` updates, is recorded against
+//! the prompt. Pull requests are looked up on GitHub by head branch: one first seen after a run
+//! is attributed to that run's prompt, and its URL is filled in on earlier commits of the
+//! session that had none.
+
+use chrono::Utc;
+use sandbox_client::types::ShellExecRequest;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter, Set,
+};
+use tracing::{info, warn};
+
+use crate::entities::prompt_artifact::{self, PromptArtifactKind};
+use crate::services::github::GithubClient;
+use crate::services::github_host::RepoRef;
+use crate::services::shell;
+
+/// Output of `command` run in `repo_path`, None when it could not run or exited nonzero
+pub async fn git_output(
+    sbx: &sandbox_client::Client,
+    repo_path: &str,
+    command: String,
+) -> Option<String> {
+    let response = sbx
+        .exec_command_v1_shell_exec_post(&ShellExecRequest {
+            command: command.clone(),
+            async_mode: false,
+            id: None,
+            timeout: Some(30.0_f64),
+            exec_dir: Some(repo_path.to_string()),
+        })
+        .await;
+    match response {
+        Ok(response) => match response.into_inner().data {
+            Some(result) if result.exit_code == Some(0) => result.output,
+            Some(result) => {
+                warn!(
+                    "`{}` in {} exited with {:?}",
+                    command, repo_path, result.exit_code
+                );
+                None
+            }
+            None => None,
+        },
+        Err(e) => {
+            warn!("Failed to run `{}` in {}: {}", command, repo_path, e);
+            None
+        }
+    }
+}
+
+/// Full commit SHAs in `output`, one per line, ignoring anything else
+fn parse_shas(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .map(str::trim)
+        .filter(|line| line.len() == 40 && line.chars().all(|c| c.is_ascii_hexdigit()))
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Commit the sandbox clone is at, noted before the run so its pushes can be told apart
+pub async fn head_sha(sbx: &sandbox_client::Client, repo_path: &str) -> Option<String> {
+    let output = git_output(sbx, repo_path, "git rev-parse HEAD".to_string()).await?;
+    parse_shas(&output).into_iter().next()
+}
+
+/// Record the commits pushed to `branch` since `base_sha` and the branch's pull requests.
+///
+/// Best-effort: failures are logged and whatever could be determined is kept. A retried run
+/// replaces the commits recorded by the earlier attempt. Returns the number of rows recorded.
+#[allow(clippy::too_many_arguments)]
+pub async fn record(
+    db: &DatabaseConnection,
+    sbx: &sandbox_client::Client,
+    github: &GithubClient,
+    repo: &RepoRef,
+    repo_path: &str,
+    branch: &str,
+    session_id: uuid::Uuid,
+    prompt_id: uuid::Uuid,
+    base_sha: Option<&str>,
+) -> usize {
+    match record_inner(
+        db, sbx, github, repo, repo_path, branch, session_id, prompt_id, base_sha,
+    )
+    .await
+    {
+        Ok(recorded) => recorded,
+        Err(e) => {
+            warn!(
+                "Failed to record commits and pull requests of prompt {}: {}",
+                prompt_id, e
+            );
+            0
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn record_inner(
+    db: &DatabaseConnection,
+    sbx: &sandbox_client::Client,
+    github: &GithubClient,
+    repo: &RepoRef,
+    repo_path: &str,
+    branch: &str,
+    session_id: uuid::Uuid,
+    prompt_id: uuid::Uuid,
+    base_sha: Option<&str>,
+) -> Result<usize, DbErr> {
+    let pushed = match base_sha {
+        Some(base) => git_output(
+            sbx,
+            repo_path,
+            format!(
+                "git rev-list --reverse {}",
+                shell::quote(&format!("{}..refs/remotes/origin/{}", base, branch))
+            ),
+        )
+        .await
+        .map(|output| parse_shas(&output))
+        .unwrap_or_default(),
+        None => Vec::new(),
+    };
+
+    let pull_request_urls = match github.list_pull_requests_for_branch(repo, branch).await {
+        Ok(pull_requests) => pull_requests
+            .into_iter()
+            .map(|pr| pr.html_url)
+            .collect::<Vec<_>>(),
+        Err(e) => {
+            warn!(
+                "Failed to look up pull requests for {} of {}: {}",
+                branch, repo, e
+            );
+            Vec::new()
+        }
+    };
+    // The most recently opened pull request of the branch
+    let pull_request_url = pull_request_urls.first().cloned();
+
+    prompt_artifact::Entity::delete_many()
+        .filter(prompt_artifact::Column::PromptId.eq(prompt_id))
+        .filter(prompt_artifact::Column::Kind.eq(PromptArtifactKind::Commit))
+        .exec(db)
+        .await?;
+
+    let mut recorded = 0;
+    for sha in &pushed {
+        new_artifact(
+            prompt_id,
+            session_id,
+            repo,
+            PromptArtifactKind::Commit,
+            Some(sha.clone()),
+            pull_request_url.clone(),
+        )
+        .insert(db)
+        .await?;
+        recorded += 1;
+    }
+
+    for url in &pull_request_urls {
+        let known = prompt_artifact::Entity::find()
+            .filter(prompt_artifact::Column::SessionId.eq(session_id))
+            .filter(prompt_artifact::Column::Kind.eq(PromptArtifactKind::PullRequest))
+            .filter(prompt_artifact::Column::PullRequestUrl.eq(url))
+            .one(db)
+            .await?
+            .is_some();
+        if known {
+            continue;
+        }
+        new_artifact(
+            prompt_id,
+            session_id,
+            repo,
+            PromptArtifactKind::PullRequest,
+            None,
+            Some(url.clone()),
+        )
+        .insert(db)
+        .await?;
+        recorded += 1;
+    }
+
+    if let Some(url) = &pull_request_url {
+        prompt_artifact::Entity::update_many()
+            .col_expr(
+                prompt_artifact::Column::PullRequestUrl,
+                sea_orm::sea_query::Expr::value(url.clone()),
+            )
+            .filter(prompt_artifact::Column::SessionId.eq(session_id))
+            .filter(prompt_artifact::Column::Kind.eq(PromptArtifactKind::Commit))
+            .filter(prompt_artifact::Column::PullRequestUrl.is_null())
+            .exec(db)
+            .await?;
+    }
+
+    info!(
+        "Recorded {} commits and {} pull requests for prompt {}",
+        pushed.len(),
+        pull_request_urls.len(),
+        prompt_id
+    );
+    Ok(recorded)
+}
+
+fn new_artifact(
+    prompt_id: uuid::Uuid,
+    session_id: uuid::Uuid,
+    repo: &RepoRef,
+    kind: PromptArtifactKind,
+    commit_sha: Option<String>,
+    pull_request_url: Option<String>,
+) -> prompt_artifact::ActiveModel {
+    prompt_artifact::ActiveModel {
+        id: Set(uuid::Uuid::new_v4()),
+        prompt_id: Set(prompt_id),
+        session_id: Set(session_id),
+        kind: Set(kind),
+        repo: Set(repo.path()),
+        commit_sha: Set(commit_sha),
+        pull_request_url: Set(pull_request_url),
+        created_at: Set(Utc::now().into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_shas_skips_noise() {
+        let output = "warning: refname is ambiguous\n\
+                      3F786850E387550FDAB836ED7E6DC881DE23001B\n\
+                      abc123\n\
+                      89e6c98d92887913cadf06b2adb97f26cde4849b\n";
+        assert_eq!(
+            parse_shas(output),
+            vec![
+                "3f786850e387550fdab836ed7e6dc881de23001b",
+                "89e6c98d92887913cadf06b2adb97f26cde4849b"
+            ]
+        );
+    }
+}
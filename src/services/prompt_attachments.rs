@@ -0,0 +1,268 @@
+//! Files attached to prompts, e.g. a screenshot of a broken page or the log of a failed build.
+//!
+//! `POST /attachments` stores the contents in the message blob bucket and records them in
+//! `prompt_attachment`. Prompt data references an attachment with a `file` content part
+//! naming its id; when the prompt is stored the part is filled in from the record with the
+//! file's name, kind, content type, size and the path it will have in the sandbox. Before the
+//! CLI starts, the outbox publisher copies every attachment the prompt references to that
+//! path, and the rendered prompt points the agent at it.
+
+use base64::Engine;
+use chrono::Utc;
+use sandbox_client::types::{FileContentEncoding, FileWriteRequest};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter, Set,
+};
+use serde_json::Value;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tracing::info;
+use uuid::Uuid;
+
+use crate::entities::prompt_attachment::{self, AttachmentKind, Entity as PromptAttachment};
+use crate::services::conversation::{self, ContentPart};
+use crate::services::hashing;
+use crate::services::message_blobs;
+use crate::services::session_uploads::{sanitize_file_name, UploadError, CHUNK_BYTES};
+
+/// Extensions of plain text output treated as logs
+const LOG_EXTENSIONS: [&str; 3] = ["log", "out", "txt"];
+
+#[derive(Debug, thiserror::Error)]
+pub enum AttachmentError {
+    #[error("Attachments need object storage, and MESSAGE_BLOB_BUCKET is not set")]
+    NotConfigured,
+    #[error("Invalid file name: {0}")]
+    InvalidFileName(String),
+    #[error("Attachment {0} not found")]
+    NotFound(String),
+    #[error("{0}")]
+    Storage(String),
+    #[error("Failed to read the attachment: {0}")]
+    Read(#[from] std::io::Error),
+    #[error(transparent)]
+    Database(#[from] DbErr),
+}
+
+/// The kind of a file from its content type, falling back to its extension
+pub fn kind_for(file_name: &str, content_type: Option<&str>) -> AttachmentKind {
+    let extension = file_name
+        .rsplit_once('.')
+        .map(|(_, ext)| ext.to_ascii_lowercase())
+        .unwrap_or_default();
+    match content_type.map(|ct| ct.to_ascii_lowercase()) {
+        Some(ct) if ct.starts_with("image/") => AttachmentKind::Image,
+        _ if matches!(extension.as_str(), "png" | "jpg" | "jpeg" | "gif" | "webp") => {
+            AttachmentKind::Image
+        }
+        _ if LOG_EXTENSIONS.contains(&extension.as_str()) => AttachmentKind::Log,
+        _ => AttachmentKind::File,
+    }
+}
+
+/// Where a run finds the attachment in the sandbox
+pub fn sandbox_path(attachment: &prompt_attachment::Model) -> String {
+    format!(
+        "{}/{}/{}",
+        crate::config::get().uploads.attachment_dir,
+        attachment.id,
+        attachment.file_name
+    )
+}
+
+/// Store the contents of `reader` as an attachment of `user_id`
+pub async fn store<R: AsyncRead + Unpin>(
+    db: &DatabaseConnection,
+    user_id: &str,
+    file_name: &str,
+    content_type: Option<String>,
+    mut reader: R,
+) -> Result<prompt_attachment::Model, AttachmentError> {
+    let blobs = message_blobs::get().ok_or(AttachmentError::NotConfigured)?;
+    let file_name = sanitize_file_name(file_name).map_err(|e| match e {
+        UploadError::InvalidFileName(name) => AttachmentError::InvalidFileName(name),
+        e => AttachmentError::Storage(e.to_string()),
+    })?;
+
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes).await?;
+    let sha256 = hashing::sha256_hex(&bytes);
+
+    let id = Uuid::new_v4();
+    let blob_key = format!("attachments/{}/{}", id, file_name);
+    let size_bytes = bytes.len() as i64;
+    blobs
+        .put(&blob_key, bytes)
+        .await
+        .map_err(AttachmentError::Storage)?;
+
+    let record = prompt_attachment::ActiveModel {
+        id: Set(id),
+        user_id: Set(user_id.to_string()),
+        kind: Set(kind_for(&file_name, content_type.as_deref())),
+        file_name: Set(file_name),
+        content_type: Set(content_type),
+        size_bytes: Set(size_bytes),
+        sha256: Set(sha256),
+        blob_key: Set(blob_key),
+        created_at: Set(Utc::now().into()),
+    }
+    .insert(db)
+    .await?;
+
+    info!(
+        "Stored attachment {} ({}, {} bytes) for user {}",
+        record.id, record.file_name, record.size_bytes, user_id
+    );
+    Ok(record)
+}
+
+/// Fill in the `file` parts of normalized prompt `data` from the attachments they name, which
+/// have to belong to `user_id`
+pub async fn resolve(
+    db: &DatabaseConnection,
+    user_id: &str,
+    data: Value,
+) -> Result<Value, AttachmentError> {
+    let Some(mut conversation) = conversation::from_stored(&data) else {
+        return Ok(data);
+    };
+    let mut resolved = false;
+    for part in conversation
+        .messages
+        .iter_mut()
+        .flat_map(|message| message.content.iter_mut())
+    {
+        let ContentPart::File { attachment_id, .. } = part else {
+            continue;
+        };
+        let attachment = match Uuid::parse_str(attachment_id) {
+            Ok(id) => {
+                PromptAttachment::find_by_id(id)
+                    .filter(prompt_attachment::Column::UserId.eq(user_id))
+                    .one(db)
+                    .await?
+            }
+            Err(_) => None,
+        }
+        .ok_or_else(|| AttachmentError::NotFound(attachment_id.clone()))?;
+
+        *part = ContentPart::File {
+            attachment_id: attachment.id.to_string(),
+            path: Some(sandbox_path(&attachment)),
+            name: Some(attachment.file_name),
+            kind: Some(attachment.kind),
+            content_type: attachment.content_type,
+            size_bytes: Some(attachment.size_bytes),
+        };
+        resolved = true;
+    }
+    if !resolved {
+        return Ok(data);
+    }
+    serde_json::to_value(conversation).map_err(|e| AttachmentError::Storage(e.to_string()))
+}
+
+/// Ids of the attachments stored prompt `data` references
+pub fn referenced(data: &Value) -> Vec<Uuid> {
+    let Some(conversation) = conversation::from_stored(data) else {
+        return Vec::new();
+    };
+    let mut ids: Vec<Uuid> = conversation
+        .messages
+        .iter()
+        .flat_map(|message| &message.content)
+        .filter_map(|part| match part {
+            ContentPart::File { attachment_id, .. } => Uuid::parse_str(attachment_id).ok(),
+            _ => None,
+        })
+        .collect();
+    ids.sort();
+    ids.dedup();
+    ids
+}
+
+/// Copy the attachments prompt `data` references into the sandbox. Returns how many were
+/// copied; a missing attachment or store fails the run, since the prompt points at its path.
+pub async fn ship(
+    db: &DatabaseConnection,
+    sbx: &sandbox_client::Client,
+    data: &Value,
+) -> Result<usize, AttachmentError> {
+    let ids = referenced(data);
+    if ids.is_empty() {
+        return Ok(0);
+    }
+    let blobs = message_blobs::get().ok_or(AttachmentError::NotConfigured)?;
+    let attachments = PromptAttachment::find()
+        .filter(prompt_attachment::Column::Id.is_in(ids.clone()))
+        .all(db)
+        .await?;
+    if let Some(missing) = ids
+        .iter()
+        .find(|id| !attachments.iter().any(|a| a.id == **id))
+    {
+        return Err(AttachmentError::NotFound(missing.to_string()));
+    }
+
+    for attachment in &attachments {
+        let bytes = blobs
+            .get_bytes(&attachment.blob_key)
+            .await
+            .map_err(AttachmentError::Storage)?;
+        let path = sandbox_path(attachment);
+        // An empty file still needs the one write that creates it
+        let chunks: Vec<&[u8]> = if bytes.is_empty() {
+            vec![&[]]
+        } else {
+            bytes.chunks(CHUNK_BYTES).collect()
+        };
+        for (i, chunk) in chunks.into_iter().enumerate() {
+            sbx.write_file(&FileWriteRequest {
+                content: base64::engine::general_purpose::STANDARD.encode(chunk),
+                file: path.clone(),
+                append: i > 0,
+                sudo: false,
+                encoding: FileContentEncoding::Base64,
+                leading_newline: false,
+                trailing_newline: false,
+            })
+            .await
+            .map_err(|e| {
+                AttachmentError::Storage(format!("Failed to copy {} to the sandbox: {}", path, e))
+            })?;
+        }
+    }
+    Ok(attachments.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kind_for() {
+        assert_eq!(
+            kind_for("screenshot.bin", Some("image/png")),
+            AttachmentKind::Image
+        );
+        assert_eq!(kind_for("Screen Shot.JPG", None), AttachmentKind::Image);
+        assert_eq!(
+            kind_for("build.log", Some("application/octet-stream")),
+            AttachmentKind::Log
+        );
+        assert_eq!(kind_for("data.csv", Some("text/csv")), AttachmentKind::File);
+        assert_eq!(kind_for("README", None), AttachmentKind::File);
+    }
+
+    #[test]
+    fn test_referenced_ids() {
+        let id = Uuid::new_v4();
+        let data = serde_json::json!({"messages": [{"role": "user", "content": [
+            {"type": "text", "text": "Why does this fail?"},
+            {"type": "file", "attachment_id": id.to_string()},
+            {"type": "file", "attachment_id": id.to_string()}
+        ]}]});
+        assert_eq!(referenced(&data), vec![id]);
+        assert!(referenced(&serde_json::json!("plain text")).is_empty());
+    }
+}
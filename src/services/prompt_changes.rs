@@ -0,0 +1,209 @@
+//! What each prompt's run changed in the repository, for a compact "changed 4 files (+120/-35)"
+//! in the review UI.
+//!
+//! After the CLI exits, `git diff --numstat` between the commit noted before the run and the
+//! clone's `HEAD` is stored per file in `prompt_file_change`, totalled in the prompt's
+//! `change_summary`, and appended to the session's event history.
+
+use chrono::Utc;
+use rocket_okapi::okapi::schemars::{self, JsonSchema};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter, Set,
+};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::entities::prompt;
+use crate::entities::prompt_file_change;
+use crate::entities::session_event::SessionEventType;
+use crate::services::prompt_artifacts::git_output;
+use crate::services::session_events;
+use crate::services::session_state_machine::Actor;
+
+/// Files and lines a run changed, as stored in the prompt's `change_summary` column
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct ChangeSummary {
+    pub files_changed: u64,
+    /// Lines added across text files
+    pub insertions: u64,
+    /// Lines removed across text files
+    pub deletions: u64,
+}
+
+impl ChangeSummary {
+    pub fn from_json(value: Option<&serde_json::Value>) -> Option<Self> {
+        serde_json::from_value(value?.clone()).ok()
+    }
+}
+
+/// One line of `git diff --numstat`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileChange {
+    pub path: String,
+    /// None for binary files, which numstat reports as `-`
+    pub insertions: Option<i32>,
+    pub deletions: Option<i32>,
+}
+
+/// The files in `git diff --numstat` output, ignoring lines of any other shape
+fn parse_numstat(output: &str) -> Vec<FileChange> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, '\t');
+            let insertions = fields.next()?;
+            let deletions = fields.next()?;
+            let path = fields.next()?.trim();
+            let count = |field: &str| match field {
+                "-" => Some(None),
+                n => n.parse().ok().map(Some),
+            };
+            if path.is_empty() {
+                return None;
+            }
+            Some(FileChange {
+                path: path.to_string(),
+                insertions: count(insertions)?,
+                deletions: count(deletions)?,
+            })
+        })
+        .collect()
+}
+
+pub fn summarize(changes: &[FileChange]) -> ChangeSummary {
+    ChangeSummary {
+        files_changed: changes.len() as u64,
+        insertions: changes
+            .iter()
+            .filter_map(|c| c.insertions)
+            .map(|n| n as u64)
+            .sum(),
+        deletions: changes
+            .iter()
+            .filter_map(|c| c.deletions)
+            .map(|n| n as u64)
+            .sum(),
+    }
+}
+
+/// Record what the run of `prompt_id` changed since `base_sha`.
+///
+/// Best-effort: without a base commit or when the diff cannot be taken nothing is recorded.
+/// A retried run replaces the files recorded by the earlier attempt.
+pub async fn record(
+    db: &DatabaseConnection,
+    sbx: &sandbox_client::Client,
+    repo_path: &str,
+    session_id: uuid::Uuid,
+    prompt_id: uuid::Uuid,
+    base_sha: Option<&str>,
+) -> Option<ChangeSummary> {
+    let base_sha = base_sha?;
+    let output = git_output(
+        sbx,
+        repo_path,
+        format!("git diff --numstat -M {} HEAD", base_sha),
+    )
+    .await?;
+    let changes = parse_numstat(&output);
+    let summary = summarize(&changes);
+
+    match store(db, session_id, prompt_id, &changes, &summary).await {
+        Ok(()) => {
+            info!(
+                "Prompt {} changed {} files (+{}/-{})",
+                prompt_id, summary.files_changed, summary.insertions, summary.deletions
+            );
+            Some(summary)
+        }
+        Err(e) => {
+            warn!("Failed to record changes of prompt {}: {}", prompt_id, e);
+            None
+        }
+    }
+}
+
+async fn store(
+    db: &DatabaseConnection,
+    session_id: uuid::Uuid,
+    prompt_id: uuid::Uuid,
+    changes: &[FileChange],
+    summary: &ChangeSummary,
+) -> Result<(), DbErr> {
+    prompt_file_change::Entity::delete_many()
+        .filter(prompt_file_change::Column::PromptId.eq(prompt_id))
+        .exec(db)
+        .await?;
+    let now = Utc::now();
+    for change in changes {
+        prompt_file_change::ActiveModel {
+            id: Set(uuid::Uuid::new_v4()),
+            prompt_id: Set(prompt_id),
+            session_id: Set(session_id),
+            path: Set(change.path.clone()),
+            insertions: Set(change.insertions),
+            deletions: Set(change.deletions),
+            created_at: Set(now.into()),
+        }
+        .insert(db)
+        .await?;
+    }
+
+    prompt::ActiveModel {
+        id: Set(prompt_id),
+        change_summary: Set(serde_json::to_value(summary).ok()),
+        ..Default::default()
+    }
+    .update(db)
+    .await?;
+
+    if summary.files_changed > 0 {
+        session_events::record(
+            db,
+            session_id,
+            SessionEventType::FilesChanged,
+            &Actor::System("outbox_publisher"),
+            Some(serde_json::json!({
+                "prompt_id": prompt_id,
+                "files_changed": summary.files_changed,
+                "insertions": summary.insertions,
+                "deletions": summary.deletions,
+            })),
+        )
+        .await;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_numstat() {
+        let output = "120\t30\tsrc/main.rs\n\
+                      0\t5\tREADME.md\n\
+                      -\t-\tassets/logo.png\n\
+                      3\t2\tsrc/{old.rs => new.rs}\n\
+                      warning: inexact rename detection was skipped\n";
+        let changes = parse_numstat(output);
+        assert_eq!(changes.len(), 4);
+        assert_eq!(
+            changes[2],
+            FileChange {
+                path: "assets/logo.png".to_string(),
+                insertions: None,
+                deletions: None,
+            }
+        );
+        assert_eq!(changes[3].path, "src/{old.rs => new.rs}");
+        assert_eq!(
+            summarize(&changes),
+            ChangeSummary {
+                files_changed: 4,
+                insertions: 123,
+                deletions: 37,
+            }
+        );
+    }
+}
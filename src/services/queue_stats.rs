@@ -0,0 +1,203 @@
+//! Depth of the prompt queue, for autoscaling worker deployments.
+//!
+//! A prompt is pending from its creation until an outbox job claims it for a run; a run is in
+//! flight until it completes, is released or goes stale. Alongside those, the apalis job table
+//! shows how many jobs wait for a worker, which is what a worker deployment scales on.
+
+use apalis::prelude::Job;
+use chrono::{DateTime, Utc};
+use sea_orm::entity::prelude::DateTimeWithTimeZone;
+use sea_orm::{DatabaseConnection, DbBackend, DbErr, FromQueryResult, Statement};
+use tracing::warn;
+
+use crate::bg_tasks::outbox_events::OutboxEventJob;
+use crate::bg_tasks::outbox_publisher::OutboxJob;
+use crate::bg_tasks::prompt_run;
+use crate::entities::prompt::PromptPriority;
+
+/// Pending prompts of one priority
+#[derive(Debug, Clone, FromQueryResult)]
+pub struct PriorityDepth {
+    pub priority: PromptPriority,
+    pub pending: i64,
+    pub oldest_created_at: DateTimeWithTimeZone,
+}
+
+impl PriorityDepth {
+    /// Seconds the oldest pending prompt has been waiting
+    pub fn oldest_age_secs(&self, now: DateTime<Utc>) -> u64 {
+        now.signed_duration_since(self.oldest_created_at)
+            .num_seconds()
+            .max(0) as u64
+    }
+}
+
+/// Jobs of one type and status in the apalis queue
+#[derive(Debug, Clone, FromQueryResult)]
+pub struct JobCount {
+    pub job_type: String,
+    pub status: String,
+    pub count: i64,
+}
+
+#[derive(Debug, FromQueryResult)]
+struct InFlight {
+    in_flight: i64,
+}
+
+/// Snapshot of the queue
+#[derive(Debug, Clone)]
+pub struct QueueStats {
+    /// Pending prompts by priority, highest priority first; priorities without any are left out
+    pub by_priority: Vec<PriorityDepth>,
+    pub in_flight_runs: i64,
+    /// Jobs waiting for or held by a worker
+    pub jobs: Vec<JobCount>,
+    pub collected_at: DateTime<Utc>,
+}
+
+impl QueueStats {
+    pub fn pending_prompts(&self) -> i64 {
+        self.by_priority.iter().map(|d| d.pending).sum()
+    }
+
+    /// Seconds the oldest pending prompt of any priority has been waiting, 0 when none is
+    pub fn oldest_pending_age_secs(&self) -> u64 {
+        self.by_priority
+            .iter()
+            .map(|d| d.oldest_age_secs(self.collected_at))
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+async fn pending_by_priority(db: &DatabaseConnection) -> Result<Vec<PriorityDepth>, DbErr> {
+    let mut depths = PriorityDepth::find_by_statement(Statement::from_string(
+        DbBackend::Postgres,
+        r#"SELECT p.priority, COUNT(*)::bigint AS pending, MIN(p.created_at) AS oldest_created_at
+           FROM prompt p
+           JOIN session s ON s.id = p.session_id
+           WHERE p.run_id IS NULL
+             AND p.completed_at IS NULL
+             AND NOT p.draft
+             AND s.deleted_at IS NULL
+             AND s.cancellation_status IS NULL
+           GROUP BY p.priority"#,
+    ))
+    .all(db)
+    .await?;
+    depths.sort_by_key(|d| d.priority.rank());
+    Ok(depths)
+}
+
+async fn in_flight_runs(db: &DatabaseConnection) -> Result<i64, DbErr> {
+    let stale_before = Utc::now()
+        - chrono::Duration::from_std(prompt_run::run_stale_after())
+            .unwrap_or(chrono::Duration::zero());
+    Ok(InFlight::find_by_statement(Statement::from_sql_and_values(
+        DbBackend::Postgres,
+        r#"SELECT COUNT(*)::bigint AS in_flight
+           FROM prompt
+           WHERE run_id IS NOT NULL AND completed_at IS NULL AND started_at > $1"#,
+        [stale_before.into()],
+    ))
+    .one(db)
+    .await?
+    .map_or(0, |row| row.in_flight))
+}
+
+/// Pending and running apalis jobs. The table only exists once a worker has set up its
+/// storage, so a missing table counts as an empty queue.
+async fn worker_jobs(db: &DatabaseConnection) -> Vec<JobCount> {
+    let jobs = JobCount::find_by_statement(Statement::from_string(
+        DbBackend::Postgres,
+        r#"SELECT job_type, status, COUNT(*)::bigint AS count
+           FROM apalis.jobs
+           WHERE status IN ('Pending', 'Running')
+           GROUP BY job_type, status
+           ORDER BY job_type, status"#,
+    ))
+    .all(db)
+    .await;
+    jobs.unwrap_or_else(|e| {
+        warn!("Failed to count worker queue jobs: {}", e);
+        Vec::new()
+    })
+}
+
+pub async fn collect(db: &DatabaseConnection) -> Result<QueueStats, DbErr> {
+    Ok(QueueStats {
+        by_priority: pending_by_priority(db).await?,
+        in_flight_runs: in_flight_runs(db).await?,
+        jobs: worker_jobs(db).await,
+        collected_at: Utc::now(),
+    })
+}
+
+/// Publish `stats` as the queue gauges
+pub fn observe(stats: &QueueStats) {
+    let metrics = crate::metrics::get();
+
+    // Priorities and job states that emptied report 0 rather than disappearing, so
+    // autoscalers see the queue drain
+    for priority in PromptPriority::ALL {
+        let depth = stats.by_priority.iter().find(|d| d.priority == priority);
+        metrics
+            .prompt_queue_pending
+            .with_label_values(&[priority.as_str()])
+            .set(depth.map_or(0, |d| d.pending));
+        metrics
+            .prompt_queue_oldest_pending_age_seconds
+            .with_label_values(&[priority.as_str()])
+            .set(depth.map_or(0, |d| d.oldest_age_secs(stats.collected_at) as i64));
+    }
+    metrics.prompt_runs_in_flight.set(stats.in_flight_runs);
+
+    metrics.worker_queue_jobs.reset();
+    for job_type in [<OutboxJob as Job>::NAME, <OutboxEventJob as Job>::NAME] {
+        for status in ["Pending", "Running"] {
+            metrics
+                .worker_queue_jobs
+                .with_label_values(&[job_type, status])
+                .set(0);
+        }
+    }
+    for job in &stats.jobs {
+        metrics
+            .worker_queue_jobs
+            .with_label_values(&[&job.job_type, &job.status])
+            .set(job.count);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_totals_across_priorities() {
+        let now = Utc::now();
+        let depth = |priority, pending, waited_secs| PriorityDepth {
+            priority,
+            pending,
+            oldest_created_at: (now - chrono::Duration::seconds(waited_secs)).into(),
+        };
+        let stats = QueueStats {
+            by_priority: vec![
+                depth(PromptPriority::High, 2, 30),
+                depth(PromptPriority::Low, 5, 600),
+            ],
+            in_flight_runs: 1,
+            jobs: Vec::new(),
+            collected_at: now,
+        };
+        assert_eq!(stats.pending_prompts(), 7);
+        assert_eq!(stats.oldest_pending_age_secs(), 600);
+
+        let empty = QueueStats {
+            by_priority: Vec::new(),
+            ..stats
+        };
+        assert_eq!(empty.oldest_pending_age_secs(), 0);
+    }
+}
@@ -0,0 +1,56 @@
+use sea_orm::{ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter, QueryOrder};
+use uuid::Uuid;
+
+use crate::config::RepoLockMode;
+use crate::entities::session::{self, Entity as Session, Model as SessionModel, UiStatus};
+use crate::services::sandbox_queue::queued_statuses;
+
+/// Statuses in which a session may still push to its branch or open a PR
+fn active_statuses() -> Vec<UiStatus> {
+    let mut statuses = queued_statuses().to_vec();
+    statuses.push(UiStatus::InProgress);
+    statuses
+}
+
+/// The oldest active session other than `except` already targeting `repo` / `target_branch`,
+/// if any
+pub async fn find_conflict(
+    db: &DatabaseConnection,
+    repo: &str,
+    target_branch: &str,
+    except: Option<Uuid>,
+) -> Result<Option<SessionModel>, DbErr> {
+    let mut query = Session::find()
+        .filter(session::Column::Repo.eq(repo))
+        .filter(session::Column::TargetBranch.eq(target_branch))
+        .filter(session::Column::UiStatus.is_in(active_statuses()));
+    if let Some(except) = except {
+        query = query.filter(session::Column::Id.ne(except));
+    }
+    query.order_by_asc(session::Column::CreatedAt).one(db).await
+}
+
+/// Check a new session, or `except` moving to `repo` / `target_branch`, against the repo lock.
+///
+/// Returns the conflicting session when one exists and the mode is not `Off`; whether that
+/// blocks creation is up to the caller, based on `mode`.
+pub async fn check(
+    db: &DatabaseConnection,
+    mode: RepoLockMode,
+    repo: &str,
+    target_branch: &str,
+    except: Option<Uuid>,
+) -> Result<Option<SessionModel>, DbErr> {
+    if mode == RepoLockMode::Off {
+        return Ok(None);
+    }
+    find_conflict(db, repo, target_branch, except).await
+}
+
+/// Human readable description of a conflict with `other`
+pub fn conflict_message(other: &SessionModel, repo: &str, target_branch: &str) -> String {
+    format!(
+        "Session {} is already working on {} targeting {}",
+        other.id, repo, target_branch
+    )
+}
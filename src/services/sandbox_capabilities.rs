@@ -0,0 +1,258 @@
+//! What a session's sandbox image supports, discovered when a run bootstraps.
+//!
+//! Sandbox images differ: some have no `iptables` or cannot use it without a password, some
+//! lack tools or leave out the file API. Before each run the outbox publisher asks the
+//! sandbox for its version and probes for the capabilities optional pipeline features need,
+//! and caches the result on the session for as long as it holds the same sandbox. Features
+//! whose capability is missing are skipped, or fail the run when skipping would be unsafe:
+//!
+//! - egress policies need `firewall`; a session with a policy fails instead of running
+//!   unrestricted
+//! - linking commits and summarizing changes of a prompt need `git`
+//! - collecting session artifacts, soft cancellation and the CLI output copy need `file_api`;
+//!   soft cancels become hard ones
+//!
+//! When the probe itself fails nothing is cached and every feature runs as before.
+
+use chrono::Utc;
+use rocket_okapi::okapi::schemars::{self, JsonSchema};
+use sandbox_client::types::{FileListRequest, ShellExecRequest};
+use sea_orm::sea_query::Expr;
+use sea_orm::{ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use tracing::{info, warn};
+
+use crate::entities::session::{self, Entity as Session};
+
+/// Directory the file API probe lists; run files such as the soft-cancel sentinel live here
+const HOME_DIR: &str = "/home/gem";
+
+/// Prints the name of each tool capability the sandbox has, one per line
+const PROBE_SCRIPT: &str = "command -v git >/dev/null 2>&1 && echo git; \
+     sudo -n iptables -S OUTPUT >/dev/null 2>&1 && echo firewall; \
+     command -v inotifywait >/dev/null 2>&1 && echo file_watch; true";
+
+/// Part of the probe command, for recognizing it among recorded sandbox requests
+pub const PROBE_MARKER: &str = "echo firewall";
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Capability {
+    /// The file read, write and list endpoints answer
+    FileApi,
+    /// `iptables` can be run through `sudo -n`
+    Firewall,
+    /// `git` is installed
+    Git,
+    /// `inotifywait` is installed; reported for tooling in the sandbox
+    FileWatch,
+}
+
+impl Capability {
+    /// What the probe prints for the capability; the file API is probed separately
+    fn probe_name(self) -> Option<&'static str> {
+        match self {
+            Capability::Git => Some("git"),
+            Capability::Firewall => Some("firewall"),
+            Capability::FileWatch => Some("file_watch"),
+            Capability::FileApi => None,
+        }
+    }
+
+    fn from_probe(line: &str) -> Option<Capability> {
+        [Capability::Git, Capability::Firewall, Capability::FileWatch]
+            .into_iter()
+            .find(|c| c.probe_name() == Some(line.trim()))
+    }
+}
+
+/// Capabilities discovered in the sandbox at `api_url`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct SandboxCapabilities {
+    pub api_url: String,
+    /// Version reported by the sandbox, when it reports one
+    pub version: Option<String>,
+    pub capabilities: BTreeSet<Capability>,
+    pub discovered_at: String,
+}
+
+impl SandboxCapabilities {
+    pub fn from_json(value: Option<&serde_json::Value>) -> Option<SandboxCapabilities> {
+        value.and_then(|v| serde_json::from_value(v.clone()).ok())
+    }
+
+    pub fn has(&self, capability: Capability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+
+    /// What the probe command prints in a sandbox with these capabilities
+    pub fn probe_output(&self) -> String {
+        self.capabilities
+            .iter()
+            .filter_map(|c| c.probe_name())
+            .map(|name| format!("{}\n", name))
+            .collect()
+    }
+}
+
+/// Whether a feature needing `capability` runs: always when nothing was discovered
+pub fn supports(capabilities: Option<&SandboxCapabilities>, capability: Capability) -> bool {
+    capabilities.is_none_or(|c| c.has(capability))
+}
+
+/// Why a feature is left out, for the session's status message
+pub fn unsupported_message(feature: &str, capability: Capability) -> String {
+    let capability = serde_json::to_value(capability)
+        .ok()
+        .and_then(|v| v.as_str().map(str::to_string))
+        .unwrap_or_default();
+    format!(
+        "The sandbox image does not support {} (missing capability `{}`)",
+        feature, capability
+    )
+}
+
+/// Capabilities of the sandbox at `api_url` for `session`: the cached ones while it holds the
+/// same sandbox, else discovered and cached. None when the sandbox could not be probed.
+pub async fn for_run(
+    db: &DatabaseConnection,
+    sbx: &sandbox_client::Client,
+    session: &session::Model,
+    api_url: &str,
+) -> Option<SandboxCapabilities> {
+    if let Some(cached) = SandboxCapabilities::from_json(session.sandbox_capabilities.as_ref())
+        .filter(|c| c.api_url == api_url)
+    {
+        return Some(cached);
+    }
+
+    let discovered = discover(sbx, api_url).await?;
+    info!(
+        "Sandbox of session {} (version {}) supports {:?}",
+        session.id,
+        discovered.version.as_deref().unwrap_or("unknown"),
+        discovered.capabilities
+    );
+    if let Err(e) = store(db, session.id, &discovered).await {
+        warn!(
+            "Failed to cache sandbox capabilities of session {}: {}",
+            session.id, e
+        );
+    }
+    Some(discovered)
+}
+
+/// Ask the sandbox at `api_url` what it supports
+pub async fn discover(sbx: &sandbox_client::Client, api_url: &str) -> Option<SandboxCapabilities> {
+    let probe = sbx
+        .exec_command_v1_shell_exec_post(&ShellExecRequest {
+            command: PROBE_SCRIPT.to_string(),
+            async_mode: false,
+            id: None,
+            timeout: Some(30.0_f64),
+            exec_dir: None,
+        })
+        .await;
+    let output = match probe {
+        Ok(response) => response
+            .into_inner()
+            .data
+            .and_then(|result| result.output)
+            .unwrap_or_default(),
+        Err(e) => {
+            warn!("Failed to probe sandbox {} capabilities: {}", api_url, e);
+            return None;
+        }
+    };
+    let mut capabilities: BTreeSet<Capability> =
+        output.lines().filter_map(Capability::from_probe).collect();
+
+    let file_api = sbx
+        .list_path_v1_file_list_post(&FileListRequest {
+            file_types: vec![],
+            include_permissions: false,
+            include_size: false,
+            max_depth: Some(1),
+            path: HOME_DIR.to_string(),
+            recursive: false,
+            show_hidden: false,
+            sort_by: "name".to_string(),
+            sort_desc: false,
+        })
+        .await;
+    if file_api.is_ok() {
+        capabilities.insert(Capability::FileApi);
+    }
+
+    let version = sbx
+        .get_sandbox_context_v1_sandbox_get()
+        .await
+        .ok()
+        .map(|response| response.into_inner().version)
+        .filter(|version| !version.is_empty());
+
+    Some(SandboxCapabilities {
+        api_url: api_url.to_string(),
+        version,
+        capabilities,
+        discovered_at: Utc::now().to_rfc3339(),
+    })
+}
+
+async fn store(
+    db: &DatabaseConnection,
+    session_id: uuid::Uuid,
+    capabilities: &SandboxCapabilities,
+) -> Result<(), DbErr> {
+    Session::update_many()
+        .col_expr(
+            session::Column::SandboxCapabilities,
+            Expr::value(serde_json::to_value(capabilities).ok()),
+        )
+        .filter(session::Column::Id.eq(session_id))
+        .exec(db)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_probe_output_and_gating() {
+        let capabilities: BTreeSet<Capability> = "git\nfile_watch\nsudo: a password is required\n"
+            .lines()
+            .filter_map(Capability::from_probe)
+            .collect();
+        let discovered = SandboxCapabilities {
+            api_url: "http://10.0.0.1:8080".to_string(),
+            version: Some("1.0.0".to_string()),
+            capabilities,
+            discovered_at: Utc::now().to_rfc3339(),
+        };
+
+        assert!(supports(Some(&discovered), Capability::Git));
+        assert!(!supports(Some(&discovered), Capability::Firewall));
+        assert!(supports(None, Capability::Firewall));
+        assert_eq!(discovered.probe_output(), "git\nfile_watch\n");
+        assert!(PROBE_SCRIPT.contains(PROBE_MARKER));
+
+        let json = serde_json::to_value(&discovered).unwrap();
+        assert_eq!(
+            json["capabilities"],
+            serde_json::json!(["git", "file_watch"])
+        );
+        assert_eq!(
+            SandboxCapabilities::from_json(Some(&json)),
+            Some(discovered)
+        );
+        assert_eq!(
+            unsupported_message("egress policies", Capability::Firewall),
+            "The sandbox image does not support egress policies (missing capability `firewall`)"
+        );
+    }
+}
@@ -0,0 +1,156 @@
+//! Admin shell access to a session's sandbox, for debugging a run that behaves strangely.
+//!
+//! A single command is proxied through `sandbox_client` under a hard timeout, and every
+//! attempt is recorded in `sandbox_exec` whether or not the command completed.
+
+use chrono::Utc;
+use sandbox_client::types::ShellExecRequest;
+use sea_orm::{ActiveModelTrait, DatabaseConnection, DbErr, Set};
+use std::time::{Duration, Instant};
+use tracing::info;
+
+use crate::entities::sandbox_exec;
+use crate::entities::session::{Model as SessionModel, UiStatus};
+use crate::services::http_client;
+
+/// Timeout when the request does not give one
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Longest timeout accepted; stays below the shared HTTP client's default request timeout
+pub const MAX_TIMEOUT: Duration = Duration::from_secs(45);
+
+/// Extra time given to the sandbox API to report a command it timed out itself
+const TIMEOUT_GRACE: Duration = Duration::from_secs(5);
+
+/// Output kept in the response and the audit record
+const MAX_OUTPUT_BYTES: usize = 64 * 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub enum SandboxExecError {
+    #[error("Session {0} no longer holds a sandbox")]
+    NoSandbox(uuid::Uuid),
+    #[error(transparent)]
+    Database(#[from] DbErr),
+}
+
+/// API URL of the sandbox the session holds, None once its IP was returned
+pub fn sandbox_api_url(session: &SessionModel) -> Option<String> {
+    if matches!(
+        session.ui_status,
+        UiStatus::NeedsReviewIpReturned | UiStatus::Archived
+    ) {
+        return None;
+    }
+    session
+        .sbx_config
+        .as_ref()?
+        .get("item")?
+        .get("api_url")?
+        .as_str()
+        .map(str::to_string)
+}
+
+/// Where the outbox clones the session's repo
+pub fn default_exec_dir(session_id: uuid::Uuid) -> String {
+    format!("/home/gem/repo_{}", session_id)
+}
+
+fn truncate_output(mut output: String) -> String {
+    if output.len() <= MAX_OUTPUT_BYTES {
+        return output;
+    }
+    let mut end = MAX_OUTPUT_BYTES;
+    while !output.is_char_boundary(end) {
+        end -= 1;
+    }
+    let dropped = output.len() - end;
+    output.truncate(end);
+    output.push_str(&format!("\n[truncated {} bytes]", dropped));
+    output
+}
+
+/// Run `command` in the session's sandbox and record the attempt.
+///
+/// Fails only when the session holds no sandbox or the audit record cannot be written; a
+/// command that could not be sent, failed or timed out is returned with `error` set.
+pub async fn run(
+    db: &DatabaseConnection,
+    session: &SessionModel,
+    requested_by: &str,
+    command: &str,
+    exec_dir: Option<String>,
+    timeout: Duration,
+) -> Result<sandbox_exec::Model, SandboxExecError> {
+    let api_url = sandbox_api_url(session).ok_or(SandboxExecError::NoSandbox(session.id))?;
+    let exec_dir = exec_dir.unwrap_or_else(|| default_exec_dir(session.id));
+    let sbx = sandbox_client::Client::new_with_client(&api_url, http_client::client());
+
+    info!(
+        "Admin {} running command in sandbox of session {}: {}",
+        requested_by, session.id, command
+    );
+    let started = Instant::now();
+    let response = tokio::time::timeout(
+        timeout + TIMEOUT_GRACE,
+        sbx.exec_command_v1_shell_exec_post(&ShellExecRequest {
+            command: command.to_string(),
+            async_mode: false,
+            id: None,
+            timeout: Some(timeout.as_secs_f64()),
+            exec_dir: Some(exec_dir.clone()),
+        }),
+    )
+    .await;
+    let duration = started.elapsed();
+
+    let (exit_code, output, error) = match response {
+        Err(_) => (
+            None,
+            None,
+            Some(format!("Command did not finish within {:?}", timeout)),
+        ),
+        Ok(Err(e)) => (None, None, Some(format!("Sandbox request failed: {}", e))),
+        Ok(Ok(response)) => {
+            let response = response.into_inner();
+            match response.data {
+                Some(result) => {
+                    let error = result
+                        .exit_code
+                        .is_none()
+                        .then(|| format!("Command did not complete: {}", result.status));
+                    (result.exit_code, result.output.map(truncate_output), error)
+                }
+                None => (None, None, Some(response.message)),
+            }
+        }
+    };
+
+    let record = sandbox_exec::ActiveModel {
+        id: Set(uuid::Uuid::new_v4()),
+        session_id: Set(session.id),
+        requested_by: Set(requested_by.to_string()),
+        command: Set(command.to_string()),
+        exec_dir: Set(exec_dir),
+        exit_code: Set(exit_code),
+        output: Set(output),
+        error: Set(error),
+        duration_ms: Set(duration.as_millis() as i64),
+        created_at: Set(Utc::now().into()),
+    };
+    Ok(record.insert(db).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truncate_output_keeps_char_boundary() {
+        assert_eq!(truncate_output("ok".to_string()), "ok");
+
+        let output = "é".repeat(MAX_OUTPUT_BYTES);
+        let truncated = truncate_output(output);
+        assert!(truncated.ends_with(&format!("[truncated {} bytes]", MAX_OUTPUT_BYTES)));
+        assert!(truncated.len() < MAX_OUTPUT_BYTES + 32);
+    }
+}
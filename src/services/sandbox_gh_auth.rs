@@ -0,0 +1,116 @@
+//! Logging `gh` in to a GitHub host inside a sandbox without putting the token in a command.
+//!
+//! Commands sent to the sandbox shell API show up in its logs, in shell history and in process
+//! listings. Instead an empty file only the sandbox user can read is created, the token is
+//! written to it through the file API, and `gh auth login --with-token` reads it on stdin
+//! before the file is shredded.
+
+use sandbox_client::types::{FileContentEncoding, FileWriteRequest, ShellExecRequest};
+
+/// Directory the token file is created in; removed again by the login command
+const TOKEN_DIR: &str = "/home/gem";
+
+/// The requests that log `gh` in: `prepare` and `login` are shell commands, and only `write`
+/// carries the token
+#[derive(Debug)]
+pub struct LoginPlan {
+    pub token_file: String,
+    pub prepare: String,
+    pub write: FileWriteRequest,
+    pub login: String,
+}
+
+/// Requests logging `gh` in to `hostname` with `token` and making it git's credential helper
+pub fn plan(hostname: &str, token: &str) -> LoginPlan {
+    let token_file = format!("{}/.gh-token-{}", TOKEN_DIR, uuid::Uuid::new_v4().simple());
+    LoginPlan {
+        prepare: format!("install -m 600 /dev/null {}", token_file),
+        write: FileWriteRequest {
+            content: token.to_string(),
+            file: token_file.clone(),
+            append: false,
+            sudo: false,
+            encoding: FileContentEncoding::Utf8,
+            leading_newline: false,
+            trailing_newline: false,
+        },
+        login: format!(
+            "gh auth login --hostname {host} --with-token < {file}; status=$?; \
+             shred -u {file} 2>/dev/null || rm -f {file}; \
+             [ $status -eq 0 ] && gh auth setup-git --hostname {host}",
+            host = hostname,
+            file = token_file
+        ),
+        token_file,
+    }
+}
+
+/// Log `gh` in to `hostname` in the sandbox, see `plan`. The token file is removed whether or
+/// not the login succeeds.
+pub async fn login(
+    sbx: &sandbox_client::Client,
+    hostname: &str,
+    token: &str,
+) -> Result<(), String> {
+    let plan = plan(hostname, token);
+    exec(sbx, plan.prepare.clone()).await?;
+    if let Err(e) = sbx.write_file(&plan.write).await {
+        let _ = exec(sbx, format!("rm -f {}", plan.token_file)).await;
+        return Err(format!("Failed to write the GitHub token file: {}", e));
+    }
+    exec(sbx, plan.login).await
+}
+
+async fn exec(sbx: &sandbox_client::Client, command: String) -> Result<(), String> {
+    let result = sbx
+        .exec_command_v1_shell_exec_post(&ShellExecRequest {
+            command,
+            async_mode: false,
+            id: None,
+            timeout: Some(30.0_f64),
+            exec_dir: Some(String::from(TOKEN_DIR)),
+        })
+        .await
+        .map_err(|e| e.to_string())?
+        .into_inner()
+        .data;
+
+    match result {
+        Some(result) if result.exit_code != Some(0) => Err(format!(
+            "exit code {:?}: {}",
+            result.exit_code,
+            result.output.unwrap_or_default().trim()
+        )),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_only_in_file_write() {
+        let token = "ghp_s3cr3t'; echo leaked";
+        let plan = plan("github.example.com", token);
+
+        for command in [&plan.prepare, &plan.login] {
+            assert!(!command.contains(token));
+            assert!(!command.contains("ghp_"));
+            assert!(command.contains(&plan.token_file));
+        }
+        assert_eq!(plan.write.content, token);
+        assert_eq!(plan.write.file, plan.token_file);
+        assert!(plan.prepare.starts_with("install -m 600 /dev/null "));
+        assert!(plan.login.contains("--with-token < "));
+        assert!(plan.login.contains("shred -u"));
+    }
+
+    #[test]
+    fn test_token_files_are_unique() {
+        assert_ne!(
+            plan("github.com", "t").token_file,
+            plan("github.com", "t").token_file
+        );
+    }
+}
@@ -0,0 +1,377 @@
+//! Where a session stands in the sandbox queue and when it is expected to start.
+//!
+//! Sessions are handed sandboxes in the prompt poller's dispatch order: the most urgent
+//! priority among their submitted prompts first, then the oldest of those prompts. While nobody is waiting for a sandbox the
+//! allocator has room and a queued session only waits for its bootstrap (rendering the
+//! prompt, logging in and cloning); otherwise every in-flight session is a slot that frees up
+//! after an average run. The queue monitor stores the resulting `estimated_start_at` on each
+//! queued session.
+
+use chrono::Utc;
+use sea_orm::prelude::DateTimeWithTimeZone;
+use sea_orm::sea_query::Expr;
+use sea_orm::{
+    ColumnTrait, Condition, DatabaseConnection, EntityTrait, PaginatorTrait, QueryFilter,
+    QueryOrder, QuerySelect,
+};
+use serde_json::Value as JsonValue;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use crate::entities::prompt::{self, Entity as Prompt, PromptPriority};
+use crate::entities::session::{self, Entity as Session, Model as SessionModel, UiStatus};
+
+/// Run duration assumed when no completed prompts have recorded timings yet
+pub const DEFAULT_RUN_SECONDS: u64 = 600;
+
+/// Bootstrap duration assumed when no prompt run has recorded timings yet
+pub const DEFAULT_BOOTSTRAP_SECONDS: u64 = 60;
+
+/// Number of recent prompt runs used to estimate the average run duration
+const RECENT_RUNS_SAMPLE: u64 = 50;
+
+/// Timing phases of a run before the CLI starts, see `PromptTimings`
+const BOOTSTRAP_PHASES: [&str; 5] = ["history", "upload_prompt", "gh_auth", "clone", "checkout"];
+
+/// Statuses of sessions still waiting to be handed a sandbox
+pub fn queued_statuses() -> [UiStatus; 2] {
+    [UiStatus::Pending, UiStatus::WaitingForSandbox]
+}
+
+/// Snapshot of the sandbox queue as seen by one session
+#[derive(Debug, Clone)]
+pub struct QueueEstimate {
+    /// 1-based position among queued sessions, None when the session is not queued
+    pub position: Option<u64>,
+    /// Sessions currently holding a sandbox and running
+    pub in_flight: u64,
+    /// Average duration of recent prompt runs in seconds
+    pub average_run_seconds: u64,
+    /// Estimated seconds until a sandbox frees up for this session
+    pub estimated_wait_seconds: Option<u64>,
+    /// Estimated seconds until the session's run starts, bootstrap included
+    pub estimated_start_seconds: Option<u64>,
+}
+
+/// Average durations of recent prompt runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunAverages {
+    /// Whole run, in seconds
+    pub run_seconds: u64,
+    /// Phases before the CLI starts, in seconds
+    pub bootstrap_seconds: u64,
+}
+
+/// What the queue looks like to every queued session
+#[derive(Debug, Clone, Copy)]
+struct QueueCapacity {
+    in_flight: u64,
+    /// Whether the allocator has room, i.e. no session is waiting for a sandbox
+    allocator_free: bool,
+    averages: RunAverages,
+}
+
+impl QueueCapacity {
+    async fn load(db: &DatabaseConnection) -> Result<Self, sea_orm::DbErr> {
+        let in_flight = Session::find()
+            .filter(session::Column::UiStatus.eq(UiStatus::InProgress))
+            .filter(session::Column::FanOutLimit.is_null())
+            .filter(session::Column::DeletedAt.is_null())
+            .count(db)
+            .await?;
+        let waiting = Session::find()
+            .filter(session::Column::UiStatus.eq(UiStatus::WaitingForSandbox))
+            .filter(session::Column::DeletedAt.is_null())
+            .count(db)
+            .await?;
+        Ok(QueueCapacity {
+            in_flight,
+            allocator_free: waiting == 0,
+            averages: run_averages(db).await?,
+        })
+    }
+
+    fn start_seconds(&self, position: u64) -> u64 {
+        estimate_start_seconds(position, self.in_flight, self.allocator_free, self.averages)
+    }
+}
+
+/// Sort key of a session's prompts: the most urgent priority among those not yet completed,
+/// then the oldest of them
+pub fn dispatch_order(prompts: &[prompt::Model]) -> (u8, Option<DateTimeWithTimeZone>) {
+    prompts
+        .iter()
+        .filter(|p| p.completed_at.is_none())
+        .map(|p| (p.priority.rank(), Some(p.created_at)))
+        .min()
+        .unwrap_or((PromptPriority::Low.rank() + 1, None))
+}
+
+/// Ids of the queued sessions in dispatch order, oldest session first among equal keys.
+/// Drafts are not dispatched, so sessions with nothing else come last.
+async fn queue_order(db: &DatabaseConnection) -> Result<Vec<Uuid>, sea_orm::DbErr> {
+    let queued: Vec<Uuid> = Session::find()
+        .select_only()
+        .column(session::Column::Id)
+        .filter(session::Column::UiStatus.is_in(queued_statuses()))
+        .filter(session::Column::DeletedAt.is_null())
+        .order_by_asc(session::Column::CreatedAt)
+        .into_tuple()
+        .all(db)
+        .await?;
+    if queued.is_empty() {
+        return Ok(queued);
+    }
+
+    let mut prompts_by_session: HashMap<Uuid, Vec<prompt::Model>> = HashMap::new();
+    for prompt in Prompt::find()
+        .filter(prompt::Column::SessionId.is_in(queued.clone()))
+        .filter(prompt::Column::Draft.eq(false))
+        .all(db)
+        .await?
+    {
+        prompts_by_session
+            .entry(prompt.session_id)
+            .or_default()
+            .push(prompt);
+    }
+    let mut queue: Vec<_> = queued
+        .into_iter()
+        .map(|id| {
+            let order = dispatch_order(prompts_by_session.get(&id).map_or(&[], Vec::as_slice));
+            (id, order)
+        })
+        .collect();
+    queue.sort_by_key(|(_, order)| *order);
+    Ok(queue.into_iter().map(|(id, _)| id).collect())
+}
+
+/// Estimate the wait for `session_model` based on queued sessions ahead of it and how many
+/// sessions are currently in flight
+pub async fn estimate_for_session(
+    db: &DatabaseConnection,
+    session_model: &SessionModel,
+) -> Result<QueueEstimate, sea_orm::DbErr> {
+    let capacity = QueueCapacity::load(db).await?;
+    let in_flight = capacity.in_flight;
+    let average_run_seconds = capacity.averages.run_seconds;
+
+    let position = if queued_statuses().contains(&session_model.ui_status)
+        && session_model.deleted_at.is_none()
+    {
+        queue_order(db)
+            .await?
+            .iter()
+            .position(|id| *id == session_model.id)
+            .map(|index| index as u64 + 1)
+    } else {
+        None
+    };
+
+    Ok(QueueEstimate {
+        position,
+        in_flight,
+        average_run_seconds,
+        estimated_wait_seconds: position
+            .map(|p| estimate_wait_seconds(p, in_flight, average_run_seconds)),
+        estimated_start_seconds: position.map(|p| capacity.start_seconds(p)),
+    })
+}
+
+/// Store `estimated_start_at` on every queued session and clear it on sessions that left
+/// the queue. Returns the number of queued sessions.
+pub async fn refresh_estimates(db: &DatabaseConnection) -> Result<u64, sea_orm::DbErr> {
+    Session::update_many()
+        .col_expr(
+            session::Column::EstimatedStartAt,
+            Expr::value(Option::<chrono::DateTime<chrono::FixedOffset>>::None),
+        )
+        .filter(session::Column::EstimatedStartAt.is_not_null())
+        .filter(
+            Condition::any()
+                .add(session::Column::UiStatus.is_not_in(queued_statuses()))
+                .add(session::Column::DeletedAt.is_not_null()),
+        )
+        .exec(db)
+        .await?;
+
+    let queued = queue_order(db).await?;
+    if queued.is_empty() {
+        return Ok(0);
+    }
+
+    let capacity = QueueCapacity::load(db).await?;
+    let now = Utc::now();
+    for (index, session_id) in queued.iter().enumerate() {
+        let start_at = start_at(now, capacity.start_seconds(index as u64 + 1));
+        Session::update_many()
+            .col_expr(session::Column::EstimatedStartAt, Expr::value(start_at))
+            .filter(session::Column::Id.eq(*session_id))
+            .exec(db)
+            .await?;
+    }
+    Ok(queued.len() as u64)
+}
+
+/// The time `seconds` after `now`
+pub fn start_at(now: chrono::DateTime<Utc>, seconds: u64) -> chrono::DateTime<chrono::FixedOffset> {
+    (now + chrono::Duration::seconds(seconds as i64)).fixed_offset()
+}
+
+/// Each in-flight session is treated as a sandbox slot that frees up after an average run,
+/// so the session at `position` waits for `ceil(position / slots)` rounds of runs
+pub fn estimate_wait_seconds(position: u64, in_flight: u64, average_run_seconds: u64) -> u64 {
+    if position == 0 {
+        return 0;
+    }
+    let slots = in_flight.max(1);
+    position.div_ceil(slots) * average_run_seconds
+}
+
+/// Seconds until the session at `position` starts running: just its bootstrap while the
+/// allocator has room, else also the wait for a sandbox to free up
+pub fn estimate_start_seconds(
+    position: u64,
+    in_flight: u64,
+    allocator_free: bool,
+    averages: RunAverages,
+) -> u64 {
+    let wait = if allocator_free {
+        0
+    } else {
+        estimate_wait_seconds(position, in_flight, averages.run_seconds)
+    };
+    wait + averages.bootstrap_seconds
+}
+
+/// Average `total` timing across recently completed prompt runs
+pub async fn average_run_seconds(db: &DatabaseConnection) -> Result<u64, sea_orm::DbErr> {
+    Ok(run_averages(db).await?.run_seconds)
+}
+
+/// Average run and bootstrap durations across recently completed prompt runs
+pub async fn run_averages(db: &DatabaseConnection) -> Result<RunAverages, sea_orm::DbErr> {
+    let recent = Prompt::find()
+        .filter(prompt::Column::Timings.is_not_null())
+        .order_by_desc(prompt::Column::UpdatedAt)
+        .limit(RECENT_RUNS_SAMPLE)
+        .all(db)
+        .await?;
+    let timings: Vec<&JsonValue> = recent.iter().filter_map(|p| p.timings.as_ref()).collect();
+
+    Ok(RunAverages {
+        run_seconds: average_seconds(&timings, &["total"]).unwrap_or(DEFAULT_RUN_SECONDS),
+        bootstrap_seconds: average_seconds(&timings, &BOOTSTRAP_PHASES)
+            .unwrap_or(DEFAULT_BOOTSTRAP_SECONDS),
+    })
+}
+
+/// Average over runs of the sum of `phases`, counting only runs that recorded any of them
+fn average_seconds(timings: &[&JsonValue], phases: &[&str]) -> Option<u64> {
+    let totals_ms: Vec<u64> = timings
+        .iter()
+        .filter_map(|t| {
+            let recorded: Vec<u64> = phases
+                .iter()
+                .filter_map(|phase| t.get(*phase).and_then(JsonValue::as_u64))
+                .collect();
+            (!recorded.is_empty()).then(|| recorded.iter().sum())
+        })
+        .collect();
+    if totals_ms.is_empty() {
+        return None;
+    }
+    Some(totals_ms.iter().sum::<u64>() / totals_ms.len() as u64 / 1000)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prompt(priority: PromptPriority, age_secs: i64, completed: bool) -> prompt::Model {
+        let created_at: DateTimeWithTimeZone =
+            (Utc::now() - chrono::Duration::seconds(age_secs)).into();
+        prompt::Model {
+            id: uuid::Uuid::new_v4(),
+            session_id: uuid::Uuid::new_v4(),
+            data: serde_json::json!({}),
+            created_at,
+            updated_at: created_at,
+            timings: None,
+            progress: 0,
+            rerun_of: None,
+            system_prompt: None,
+            cli_args: None,
+            template_version: None,
+            run_id: None,
+            started_at: None,
+            completed_at: completed.then_some(created_at),
+            tool_summary: None,
+            priority,
+            include_history: true,
+            history_depth: None,
+            rendered_prompt: None,
+            model: None,
+            exit_code: None,
+            stderr: None,
+            error_kind: None,
+            change_summary: None,
+            last_activity_at: None,
+            secret_findings: None,
+            output_log_path: None,
+            draft: false,
+        }
+    }
+
+    #[test]
+    fn test_dispatch_order_prefers_priority_then_age() {
+        let old_low = [prompt(PromptPriority::Low, 600, false)];
+        let new_high = [
+            prompt(PromptPriority::Normal, 300, false),
+            prompt(PromptPriority::High, 10, false),
+        ];
+        let old_normal = [prompt(PromptPriority::Normal, 900, false)];
+        let finished_high = [
+            prompt(PromptPriority::High, 1200, true),
+            prompt(PromptPriority::Low, 30, false),
+        ];
+
+        let mut queue = [&old_low[..], &new_high, &old_normal, &finished_high];
+        queue.sort_by_key(|prompts| dispatch_order(prompts));
+        assert_eq!(queue[0][1].priority, PromptPriority::High);
+        assert_eq!(queue[1][0].created_at, old_normal[0].created_at);
+        assert_eq!(queue[2][0].created_at, old_low[0].created_at);
+        assert_eq!(queue[3][0].created_at, finished_high[0].created_at);
+    }
+
+    #[test]
+    fn test_estimate_wait_seconds() {
+        assert_eq!(estimate_wait_seconds(1, 0, 600), 600);
+        assert_eq!(estimate_wait_seconds(3, 3, 600), 600);
+        assert_eq!(estimate_wait_seconds(4, 3, 600), 1200);
+        assert_eq!(estimate_wait_seconds(0, 3, 600), 0);
+    }
+
+    #[test]
+    fn test_estimate_start_seconds_adds_bootstrap() {
+        let averages = RunAverages {
+            run_seconds: 600,
+            bootstrap_seconds: 45,
+        };
+        assert_eq!(estimate_start_seconds(4, 3, true, averages), 45);
+        assert_eq!(estimate_start_seconds(4, 3, false, averages), 1245);
+    }
+
+    #[test]
+    fn test_average_seconds_sums_phases_per_run() {
+        let runs = [
+            serde_json::json!({"clone": 20_000, "checkout": 4_000, "total": 300_000}),
+            serde_json::json!({"history": 1_000, "clone": 9_000, "total": 500_000}),
+            serde_json::json!({"cli": 1_000}),
+        ];
+        let timings: Vec<&JsonValue> = runs.iter().collect();
+        assert_eq!(average_seconds(&timings, &BOOTSTRAP_PHASES), Some(17));
+        assert_eq!(average_seconds(&timings, &["total"]), Some(400));
+        assert_eq!(average_seconds(&[], &["total"]), None);
+    }
+}
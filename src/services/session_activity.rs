@@ -0,0 +1,58 @@
+//! Liveness timestamps telling users whether a long run is still producing output.
+//!
+//! `last_activity_at` on the prompt and its session moves forward when a run starts and
+//! whenever messages are stored for it, so clients can warn about runs that have gone quiet.
+
+use chrono::{DateTime, Utc};
+use sea_orm::sea_query::Expr;
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+use tracing::warn;
+
+use crate::entities::prompt::{self, Entity as Prompt};
+use crate::entities::session::{self, Entity as Session};
+
+/// Mark `prompt_id` and its session active now. Failures are logged but never fail the caller.
+pub async fn record(db: &DatabaseConnection, session_id: uuid::Uuid, prompt_id: uuid::Uuid) {
+    let now = Utc::now();
+    let prompt_result = Prompt::update_many()
+        .col_expr(prompt::Column::LastActivityAt, Expr::value(now))
+        .filter(prompt::Column::Id.eq(prompt_id))
+        .exec(db)
+        .await;
+    if let Err(e) = prompt_result {
+        warn!("Failed to record activity of prompt {}: {}", prompt_id, e);
+    }
+
+    let session_result = Session::update_many()
+        .col_expr(session::Column::LastActivityAt, Expr::value(now))
+        .filter(session::Column::Id.eq(session_id))
+        .exec(db)
+        .await;
+    if let Err(e) = session_result {
+        warn!("Failed to record activity of session {}: {}", session_id, e);
+    }
+}
+
+/// Whole seconds from `last_activity_at` to `now`, None before any activity
+pub fn seconds_since<Tz: chrono::TimeZone>(
+    last_activity_at: Option<&DateTime<Tz>>,
+    now: DateTime<Utc>,
+) -> Option<i64> {
+    last_activity_at.map(|at| (now - at.with_timezone(&Utc)).num_seconds().max(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seconds_since_activity() {
+        let now = Utc::now();
+        assert_eq!(seconds_since::<Utc>(None, now), None);
+        let earlier = now - chrono::Duration::minutes(8);
+        assert_eq!(seconds_since(Some(&earlier), now), Some(480));
+        // Clock skew between hosts never reports negative ages
+        let later = now + chrono::Duration::seconds(3);
+        assert_eq!(seconds_since(Some(&later), now), Some(0));
+    }
+}
@@ -0,0 +1,169 @@
+//! Collection of files Claude left in the sandbox outside the repo.
+//!
+//! The sandbox is wiped when its IP is returned, so once a prompt's CLI run finishes the
+//! configured artifact directories are listed and every file is copied into the message blob
+//! bucket and recorded in `session_artifact`.
+
+use rocket::futures::StreamExt;
+use sandbox_client::types::FileListRequest;
+use sea_orm::{ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, Set};
+use tracing::{info, warn};
+
+use crate::entities::session_artifact;
+use crate::services::message_blobs::{self, MessageBlobStore};
+
+fn key_for(session_id: uuid::Uuid, prompt_id: uuid::Uuid, path: &str) -> String {
+    format!(
+        "artifacts/{}/{}/{}",
+        session_id,
+        prompt_id,
+        path.trim_start_matches('/')
+    )
+}
+
+/// Copy every file under the configured artifact paths into object storage.
+///
+/// Best-effort: a missing directory, an oversized file or a failed upload is logged and
+/// skipped, and nothing is collected when no blob store is configured. Returns the number of
+/// files recorded.
+pub async fn collect(
+    db: &DatabaseConnection,
+    sbx: &sandbox_client::Client,
+    session_id: uuid::Uuid,
+    prompt_id: uuid::Uuid,
+) -> usize {
+    let config = crate::config::get();
+    if config.artifact_paths.is_empty() {
+        return 0;
+    }
+    let Some(blobs) = message_blobs::get() else {
+        info!(
+            "No blob store configured, skipping artifact collection for session {}",
+            session_id
+        );
+        return 0;
+    };
+
+    // A retried run replaces the artifacts of the earlier attempt
+    if let Err(e) = session_artifact::Entity::delete_many()
+        .filter(session_artifact::Column::PromptId.eq(prompt_id))
+        .exec(db)
+        .await
+    {
+        warn!(
+            "Failed to clear earlier artifacts of prompt {}: {}",
+            prompt_id, e
+        );
+    }
+
+    let mut collected = 0;
+    for dir in &config.artifact_paths {
+        let listing = sbx
+            .list_path_v1_file_list_post(&FileListRequest {
+                file_types: vec![],
+                include_permissions: false,
+                include_size: true,
+                max_depth: None,
+                path: dir.clone(),
+                recursive: true,
+                show_hidden: true,
+                sort_by: "name".to_string(),
+                sort_desc: false,
+            })
+            .await;
+
+        let files = match listing {
+            Ok(response) => match response.into_inner().data {
+                Some(result) => result.files,
+                None => continue,
+            },
+            Err(e) => {
+                info!("No artifacts listed at {} in sandbox: {}", dir, e);
+                continue;
+            }
+        };
+
+        for file in files.into_iter().filter(|f| !f.is_directory) {
+            let size = file.size.unwrap_or(0);
+            if size > config.artifact_max_bytes {
+                warn!(
+                    "Skipping artifact {} ({} bytes), above the {} byte limit",
+                    file.path, size, config.artifact_max_bytes
+                );
+                continue;
+            }
+
+            match upload(db, sbx, blobs, session_id, prompt_id, &file.path).await {
+                Ok(()) => collected += 1,
+                Err(e) => warn!("Failed to collect artifact {}: {}", file.path, e),
+            }
+        }
+    }
+
+    if collected > 0 {
+        info!(
+            "Collected {} artifacts for session {} prompt {}",
+            collected, session_id, prompt_id
+        );
+    }
+    collected
+}
+
+async fn upload(
+    db: &DatabaseConnection,
+    sbx: &sandbox_client::Client,
+    blobs: &MessageBlobStore,
+    session_id: uuid::Uuid,
+    prompt_id: uuid::Uuid,
+    path: &str,
+) -> Result<(), String> {
+    let mut stream = sbx
+        .download_file(path)
+        .await
+        .map_err(|e| format!("download failed: {}", e))?
+        .into_inner()
+        .into_inner();
+
+    let mut bytes = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| format!("download failed: {}", e))?;
+        bytes.extend_from_slice(&chunk);
+    }
+
+    let key = key_for(session_id, prompt_id, path);
+    let size_bytes = bytes.len() as i64;
+    blobs.put(&key, bytes).await?;
+
+    let artifact = session_artifact::ActiveModel {
+        id: Set(uuid::Uuid::new_v4()),
+        session_id: Set(session_id),
+        prompt_id: Set(Some(prompt_id)),
+        path: Set(path.to_string()),
+        blob_key: Set(key),
+        size_bytes: Set(size_bytes),
+        created_at: Set(chrono::Utc::now().into()),
+    };
+    artifact
+        .insert(db)
+        .await
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_for_nests_sandbox_path() {
+        let session_id = uuid::Uuid::nil();
+        let prompt_id = uuid::Uuid::max();
+        assert_eq!(
+            key_for(session_id, prompt_id, "/home/gem/artifacts/report.md"),
+            format!(
+                "artifacts/{}/{}/home/gem/artifacts/report.md",
+                session_id, prompt_id
+            )
+        );
+    }
+}
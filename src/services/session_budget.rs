@@ -0,0 +1,325 @@
+//! Limits on how much a session's runs may use, so a runaway agent loop cannot burn tokens
+//! for hours.
+//!
+//! A budget caps the run time, the number of messages and the tokens used across all of a
+//! session's prompts. Sessions may set their own limits at creation; the platform defaults
+//! from `SESSION_BUDGET_*` apply to the ones they leave out. A run is refused when the session
+//! has already used up its budget, and the running job checks the budget every
+//! `SESSION_BUDGET_CHECK_INTERVAL_SECS`, terminating the CLI once a limit is reached. Either
+//! way the prompt ends with the `budget_exceeded` error kind.
+
+use rocket_okapi::okapi::schemars::{self, JsonSchema};
+use sea_orm::{DatabaseConnection, DbBackend, DbErr, FromQueryResult, Statement};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+use crate::config::SessionBudgetConfig;
+use crate::services::cost_estimate;
+use crate::services::process_supervisor;
+
+/// Limits on a session's runs; a limit left out is not enforced
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct SessionBudget {
+    /// Seconds the session's runs may take in total
+    #[serde(default)]
+    pub max_wall_clock_secs: Option<u64>,
+    /// Messages the session's runs may produce in total
+    #[serde(default)]
+    pub max_messages: Option<u64>,
+    /// Tokens the session's runs may use in total, input (including cache reads and writes)
+    /// plus output
+    #[serde(default)]
+    pub max_tokens: Option<u64>,
+}
+
+/// A limit a session reached, with its value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BudgetLimit {
+    WallClockSecs(u64),
+    Messages(u64),
+    Tokens(u64),
+}
+
+impl std::fmt::Display for BudgetLimit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BudgetLimit::WallClockSecs(limit) => {
+                write!(f, "Session budget of {} seconds of run time used up", limit)
+            }
+            BudgetLimit::Messages(limit) => {
+                write!(f, "Session budget of {} messages used up", limit)
+            }
+            BudgetLimit::Tokens(limit) => write!(f, "Session budget of {} tokens used up", limit),
+        }
+    }
+}
+
+/// What a session's runs have used
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Spent {
+    pub wall_clock_secs: u64,
+    pub messages: u64,
+    pub tokens: u64,
+}
+
+impl Spent {
+    fn plus(self, other: Spent) -> Spent {
+        Spent {
+            wall_clock_secs: self.wall_clock_secs + other.wall_clock_secs,
+            messages: self.messages + other.messages,
+            tokens: self.tokens + other.tokens,
+        }
+    }
+}
+
+impl SessionBudget {
+    /// The budget stored on a session, None when it has none or it is empty
+    pub fn from_json(value: Option<&Value>) -> Option<SessionBudget> {
+        let budget: SessionBudget = serde_json::from_value(value?.clone()).ok()?;
+        (!budget.is_empty()).then_some(budget)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.max_wall_clock_secs.is_none()
+            && self.max_messages.is_none()
+            && self.max_tokens.is_none()
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, limit) in [
+            ("max_wall_clock_secs", self.max_wall_clock_secs),
+            ("max_messages", self.max_messages),
+            ("max_tokens", self.max_tokens),
+        ] {
+            if limit == Some(0) {
+                return Err(format!("budget.{} must be at least 1", name));
+            }
+        }
+        Ok(())
+    }
+
+    /// The budget a session runs under: its own limits, and the platform defaults for the
+    /// ones it leaves out
+    pub fn effective(stored: Option<&Value>, defaults: &SessionBudgetConfig) -> SessionBudget {
+        let own = SessionBudget::from_json(stored).unwrap_or_default();
+        SessionBudget {
+            max_wall_clock_secs: own.max_wall_clock_secs.or(defaults.max_wall_clock_secs),
+            max_messages: own.max_messages.or(defaults.max_messages),
+            max_tokens: own.max_tokens.or(defaults.max_tokens),
+        }
+    }
+
+    /// The first limit `spent` has reached
+    pub fn exceeded(&self, spent: &Spent) -> Option<BudgetLimit> {
+        let reached = |limit: Option<u64>, used: u64| limit.filter(|limit| used >= *limit);
+        reached(self.max_wall_clock_secs, spent.wall_clock_secs)
+            .map(BudgetLimit::WallClockSecs)
+            .or_else(|| reached(self.max_messages, spent.messages).map(BudgetLimit::Messages))
+            .or_else(|| reached(self.max_tokens, spent.tokens).map(BudgetLimit::Tokens))
+    }
+}
+
+#[derive(Debug, FromQueryResult)]
+struct Totals {
+    wall_clock_secs: i64,
+    messages: i64,
+}
+
+#[derive(Debug, FromQueryResult)]
+struct ResultMessage {
+    data: Value,
+}
+
+/// What the runs of the session's other prompts used. Run time only counts runs that
+/// completed; tokens come from the usage in their `result` messages.
+pub async fn spent(
+    db: &DatabaseConnection,
+    session_id: uuid::Uuid,
+    excluding_prompt_id: uuid::Uuid,
+) -> Result<Spent, DbErr> {
+    let totals = Totals::find_by_statement(Statement::from_sql_and_values(
+        DbBackend::Postgres,
+        r#"SELECT
+             COALESCE((SELECT SUM(EXTRACT(EPOCH FROM (completed_at - started_at)))
+                       FROM prompt
+                       WHERE session_id = $1 AND id <> $2
+                         AND started_at IS NOT NULL AND completed_at IS NOT NULL), 0)::bigint
+               AS wall_clock_secs,
+             (SELECT COUNT(*)
+              FROM message
+              JOIN prompt ON prompt.id = message.prompt_id
+              WHERE prompt.session_id = $1 AND prompt.id <> $2) AS messages"#,
+        [session_id.into(), excluding_prompt_id.into()],
+    ))
+    .one(db)
+    .await?;
+
+    let results = ResultMessage::find_by_statement(Statement::from_sql_and_values(
+        DbBackend::Postgres,
+        r#"SELECT message.data
+           FROM message
+           JOIN prompt ON prompt.id = message.prompt_id
+           WHERE prompt.session_id = $1 AND prompt.id <> $2
+             AND message.data->>'type' = 'result'"#,
+        [session_id.into(), excluding_prompt_id.into()],
+    ))
+    .all(db)
+    .await?;
+
+    Ok(Spent {
+        wall_clock_secs: totals
+            .as_ref()
+            .map_or(0, |t| t.wall_clock_secs.max(0) as u64),
+        messages: totals.as_ref().map_or(0, |t| t.messages.max(0) as u64),
+        tokens: results
+            .iter()
+            .filter_map(|r| cost_estimate::usage_from_result(&r.data))
+            .map(|usage| usage.total())
+            .sum(),
+    })
+}
+
+/// What the running CLI has used so far, fed by the stdout reader
+#[derive(Debug, Default)]
+pub struct RunMeter {
+    messages: AtomicU64,
+    tokens: AtomicU64,
+    /// API calls whose usage is already counted
+    counted_calls: Mutex<HashSet<String>>,
+}
+
+impl RunMeter {
+    /// Count a message of the CLI's output
+    pub fn record(&self, message: &Value) {
+        self.messages.fetch_add(1, Ordering::Relaxed);
+        if let Some((call_id, usage)) = cost_estimate::usage_from_assistant(message) {
+            let mut counted = self.counted_calls.lock().unwrap_or_else(|e| e.into_inner());
+            if counted.insert(call_id.to_string()) {
+                self.tokens.fetch_add(usage.total(), Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn spent(&self, elapsed: Duration) -> Spent {
+        Spent {
+            wall_clock_secs: elapsed.as_secs(),
+            messages: self.messages.load(Ordering::Relaxed),
+            tokens: self.tokens.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Check `budget` every `check_interval` against what the session used before this run
+/// plus what the run has used since `started`, and stop the CLI process `pid` once a limit is
+/// reached: SIGTERM first so it can exit cleanly, then SIGKILL if it is still running after
+/// `kill_grace`. Returns the limit reached; it never returns while the budget holds, so abort
+/// it once the run is over.
+pub async fn enforce(
+    budget: SessionBudget,
+    before: Spent,
+    meter: std::sync::Arc<RunMeter>,
+    started: Instant,
+    pid: u32,
+    settings: &SessionBudgetConfig,
+) -> BudgetLimit {
+    let limit = loop {
+        tokio::time::sleep(settings.check_interval).await;
+        let spent = before.plus(meter.spent(started.elapsed()));
+        if let Some(limit) = budget.exceeded(&spent) {
+            break limit;
+        }
+    };
+
+    warn!("{}, terminating CLI process {}", limit, pid);
+    process_supervisor::terminate(pid, settings.kill_grace).await;
+    limit
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn defaults() -> SessionBudgetConfig {
+        SessionBudgetConfig {
+            max_wall_clock_secs: Some(3600),
+            max_messages: None,
+            max_tokens: Some(1_000_000),
+            check_interval: Duration::from_secs(10),
+            kill_grace: Duration::from_secs(30),
+        }
+    }
+
+    #[test]
+    fn test_session_limits_override_defaults() {
+        let stored = serde_json::json!({"max_tokens": 5000, "max_messages": 100});
+        assert_eq!(
+            SessionBudget::effective(Some(&stored), &defaults()),
+            SessionBudget {
+                max_wall_clock_secs: Some(3600),
+                max_messages: Some(100),
+                max_tokens: Some(5000),
+            }
+        );
+        assert_eq!(
+            SessionBudget::effective(None, &defaults()).max_tokens,
+            Some(1_000_000)
+        );
+        assert!(SessionBudget {
+            max_messages: Some(0),
+            ..Default::default()
+        }
+        .validate()
+        .is_err());
+    }
+
+    #[test]
+    fn test_exceeded_reports_first_limit_reached() {
+        let budget = SessionBudget {
+            max_wall_clock_secs: Some(600),
+            max_messages: Some(100),
+            max_tokens: None,
+        };
+        let mut spent = Spent {
+            wall_clock_secs: 599,
+            messages: 99,
+            tokens: u64::MAX,
+        };
+        assert_eq!(budget.exceeded(&spent), None);
+        spent.messages = 100;
+        assert_eq!(budget.exceeded(&spent), Some(BudgetLimit::Messages(100)));
+        spent.wall_clock_secs = 600;
+        assert_eq!(
+            budget.exceeded(&spent),
+            Some(BudgetLimit::WallClockSecs(600))
+        );
+    }
+
+    #[test]
+    fn test_meter_counts_each_call_once() {
+        let meter = RunMeter::default();
+        let block = serde_json::json!({
+            "type": "assistant",
+            "message": {
+                "id": "msg_1",
+                "usage": {"input_tokens": 10, "cache_read_input_tokens": 90, "output_tokens": 5}
+            }
+        });
+        meter.record(&block);
+        meter.record(&block);
+        meter.record(&serde_json::json!({"type": "user"}));
+        let spent = meter.spent(Duration::from_secs(7));
+        assert_eq!(
+            spent,
+            Spent {
+                wall_clock_secs: 7,
+                messages: 3,
+                tokens: 105,
+            }
+        );
+    }
+}
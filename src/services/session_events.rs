@@ -0,0 +1,74 @@
+//! Lifecycle history of sessions.
+//!
+//! Status changes are appended by the state machine's `after_save` hook; handlers and jobs
+//! append the events that do not change the status, such as a prompt being added or an IP
+//! return failing. Like notifications, recording an event never fails the caller.
+
+use chrono::Utc;
+use sea_orm::{ActiveModelTrait, DatabaseConnection, Set};
+use tracing::warn;
+
+use crate::entities::session::UiStatus;
+use crate::entities::session_event::{self, SessionEventType};
+use crate::services::session_state_machine::{Actor, TransitionCause};
+
+/// Append an event without a status change
+pub async fn record(
+    db: &DatabaseConnection,
+    session_id: uuid::Uuid,
+    event_type: SessionEventType,
+    actor: &Actor,
+    metadata: Option<serde_json::Value>,
+) {
+    insert(db, session_id, event_type, actor, None, None, metadata).await;
+}
+
+/// Append the change of the session's status from `from` to `to`
+pub async fn status_changed(
+    db: &DatabaseConnection,
+    session_id: uuid::Uuid,
+    from: &UiStatus,
+    to: &UiStatus,
+    cause: TransitionCause,
+    actor: &Actor,
+) {
+    insert(
+        db,
+        session_id,
+        SessionEventType::StatusChanged,
+        actor,
+        Some(from.clone()),
+        Some(to.clone()),
+        Some(serde_json::json!({ "cause": cause.as_str() })),
+    )
+    .await;
+}
+
+async fn insert(
+    db: &DatabaseConnection,
+    session_id: uuid::Uuid,
+    event_type: SessionEventType,
+    actor: &Actor,
+    from_status: Option<UiStatus>,
+    to_status: Option<UiStatus>,
+    metadata: Option<serde_json::Value>,
+) {
+    let inserted = session_event::ActiveModel {
+        id: Set(uuid::Uuid::new_v4()),
+        session_id: Set(session_id),
+        event_type: Set(event_type.clone()),
+        actor: Set(actor.to_string()),
+        from_status: Set(from_status),
+        to_status: Set(to_status),
+        metadata: Set(metadata),
+        created_at: Set(Utc::now().into()),
+    }
+    .insert(db)
+    .await;
+    if let Err(e) = inserted {
+        warn!(
+            "Failed to record {:?} event of session {}: {}",
+            event_type, session_id, e
+        );
+    }
+}
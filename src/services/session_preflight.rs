@@ -0,0 +1,399 @@
+use sea_orm::sea_query::SimpleExpr;
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, PaginatorTrait, QueryFilter};
+
+use crate::config::{self, RepoLockMode};
+use crate::entities::session::{self, Entity as Session, UiStatus};
+use crate::services::github::{GithubClient, GithubError};
+use crate::services::github_host::{self, RepoLocation};
+use crate::services::organizations;
+use crate::services::repo_lock;
+use crate::services::sandbox_queue::{self, queued_statuses};
+
+/// Outcome of a single preflight validation
+#[derive(Debug, Clone)]
+pub struct PreflightCheck {
+    pub name: &'static str,
+    pub passed: bool,
+    pub message: String,
+}
+
+impl PreflightCheck {
+    fn from_result(name: &'static str, result: Result<String, String>) -> Self {
+        match result {
+            Ok(message) => Self {
+                name,
+                passed: true,
+                message,
+            },
+            Err(message) => Self {
+                name,
+                passed: false,
+                message,
+            },
+        }
+    }
+}
+
+/// Everything a session creation would be checked against, without creating it
+#[derive(Debug, Clone)]
+pub struct PreflightReport {
+    pub checks: Vec<PreflightCheck>,
+    /// Sessions that would be queued ahead of the new session
+    pub queued_ahead: u64,
+    /// Estimated seconds until a new session would get a sandbox
+    pub estimated_wait_seconds: u64,
+}
+
+impl PreflightReport {
+    /// Whether creating the session would be accepted and able to run.
+    ///
+    /// Sandbox capacity is informational only; a new session simply queues.
+    pub fn ready(&self) -> bool {
+        self.checks
+            .iter()
+            .filter(|c| c.name != SANDBOX_CAPACITY)
+            .all(|c| c.passed)
+    }
+}
+
+const SANDBOX_CAPACITY: &str = "sandbox_capacity";
+
+/// Resolve and validate the repo a session would clone. Shared with session creation.
+pub fn validate_repo(repo: &str) -> Result<RepoLocation, String> {
+    github_host::resolve_repo(repo).map_err(|e| e.to_string())
+}
+
+/// Longest branch name accepted
+const MAX_BRANCH_LEN: usize = 255;
+
+/// Check `branch` is a name `git check-ref-format --branch` accepts. Branch names end up in
+/// the git commands run in the sandbox, so this is also the first guard against them being
+/// read as options or shell syntax. Shared with session creation and updates.
+pub fn validate_branch(branch: &str) -> Result<(), String> {
+    let invalid = |reason: &str| Err(format!("Invalid branch name {:?}: {}", branch, reason));
+    if branch.is_empty() {
+        return invalid("it is empty");
+    }
+    if branch.len() > MAX_BRANCH_LEN {
+        return invalid(&format!("it is longer than {} bytes", MAX_BRANCH_LEN));
+    }
+    if branch.starts_with('-') {
+        return invalid("it starts with '-'");
+    }
+    if branch == "@" {
+        return invalid("it is '@'");
+    }
+    if let Some(c) = branch.chars().find(|c| {
+        c.is_ascii_control() || matches!(c, ' ' | '~' | '^' | ':' | '?' | '*' | '[' | '\\')
+    }) {
+        return invalid(&format!("it contains {:?}", c));
+    }
+    if branch.contains("..") || branch.contains("@{") {
+        return invalid("it contains '..' or '@{'");
+    }
+    if branch.ends_with('.') {
+        return invalid("it ends with '.'");
+    }
+    for component in branch.split('/') {
+        if component.is_empty() {
+            return invalid("it has an empty path component");
+        }
+        if component.starts_with('.') || component.ends_with(".lock") {
+            return invalid("a path component starts with '.' or ends with '.lock'");
+        }
+    }
+    Ok(())
+}
+
+/// Per-user limit on sessions that are queued or running, from `MAX_ACTIVE_SESSIONS_PER_USER`.
+/// Unlimited when unset.
+fn max_active_sessions_per_user() -> Option<u64> {
+    std::env::var("MAX_ACTIVE_SESSIONS_PER_USER")
+        .ok()
+        .and_then(|v| v.parse().ok())
+}
+
+/// Check the user, and their organization when they have one, are below their active session
+/// limits. Shared with session creation.
+pub async fn check_quota(
+    db: &DatabaseConnection,
+    user_id: &str,
+    org_id: Option<&str>,
+) -> Result<String, String> {
+    check_quota_for(db, user_id, org_id, 1).await
+}
+
+/// Check the user and their organization have room for `new_sessions` more active sessions,
+/// e.g. all the child sessions of a fan-out
+pub async fn check_quota_for(
+    db: &DatabaseConnection,
+    user_id: &str,
+    org_id: Option<&str>,
+    new_sessions: u64,
+) -> Result<String, String> {
+    let mut messages = Vec::new();
+
+    if let Some(limit) = max_active_sessions_per_user() {
+        let active = count_active(db, session::Column::UserId.eq(user_id)).await?;
+        if active + new_sessions > limit {
+            return Err(over_limit_message(
+                "Active session limit reached",
+                active,
+                limit,
+                new_sessions,
+            ));
+        }
+        messages.push(format!("{} of {} active sessions in use", active, limit));
+    }
+
+    let org_limit = match org_id {
+        Some(org_id) => organizations::find(db, org_id)
+            .await
+            .map_err(|e| format!("Failed to load organization {}: {}", org_id, e))?
+            .and_then(|org| org.max_active_sessions)
+            .map(|limit| (org_id, limit.max(0) as u64)),
+        None => None,
+    };
+    if let Some((org_id, limit)) = org_limit {
+        let active = count_active(db, session::Column::OrgId.eq(org_id)).await?;
+        if active + new_sessions > limit {
+            return Err(over_limit_message(
+                &format!("Active session limit of organization {} reached", org_id),
+                active,
+                limit,
+                new_sessions,
+            ));
+        }
+        messages.push(format!(
+            "{} of {} active sessions of organization {} in use",
+            active, limit, org_id
+        ));
+    }
+
+    if messages.is_empty() {
+        return Ok("No active session limit configured".to_string());
+    }
+    Ok(messages.join("; "))
+}
+
+fn over_limit_message(reason: &str, active: u64, limit: u64, new_sessions: u64) -> String {
+    if new_sessions == 1 {
+        format!("{} ({} of {})", reason, active, limit)
+    } else {
+        format!(
+            "{} ({} of {}, {} more requested)",
+            reason, active, limit, new_sessions
+        )
+    }
+}
+
+/// Sessions matching `filter` that are queued or running, not counting fan-out tracking
+/// sessions
+async fn count_active(db: &DatabaseConnection, filter: SimpleExpr) -> Result<u64, String> {
+    let mut active_statuses = queued_statuses().to_vec();
+    active_statuses.push(UiStatus::InProgress);
+
+    Session::find()
+        .filter(filter)
+        .filter(session::Column::UiStatus.is_in(active_statuses))
+        .filter(session::Column::FanOutLimit.is_null())
+        .count(db)
+        .await
+        .map_err(|e| format!("Failed to count active sessions: {}", e))
+}
+
+async fn check_repo_access(
+    client: &GithubClient,
+    location: &RepoLocation,
+) -> Result<String, String> {
+    match client.get_repo(&location.repo).await {
+        Ok(repo) => Ok(format!("Repository {} is accessible", repo.full_name)),
+        Err(GithubError::NotFound(_)) => Err(format!(
+            "Repository {} was not found or is not accessible",
+            location.repo
+        )),
+        Err(e) => Err(format!(
+            "Failed to check repository {}: {}",
+            location.repo, e
+        )),
+    }
+}
+
+async fn check_branch(
+    client: &GithubClient,
+    location: &RepoLocation,
+    target_branch: &str,
+) -> Result<String, String> {
+    validate_branch(target_branch)?;
+    match client.get_branch(&location.repo, target_branch).await {
+        Ok(branch) => Ok(format!("Branch {} exists", branch.name)),
+        Err(GithubError::NotFound(_)) => Err(format!(
+            "Branch {} does not exist in {}",
+            target_branch, location.repo
+        )),
+        Err(e) => Err(format!("Failed to check branch {}: {}", target_branch, e)),
+    }
+}
+
+/// Run every validation a session creation for `repo` / `target_branch` depends on,
+/// without inserting rows or borrowing a sandbox
+pub async fn run_preflight(
+    db: &DatabaseConnection,
+    user_id: &str,
+    org_id: Option<&str>,
+    repo: &str,
+    target_branch: &str,
+) -> Result<PreflightReport, sea_orm::DbErr> {
+    let mut checks = Vec::new();
+
+    let location = validate_repo(repo);
+    checks.push(PreflightCheck::from_result(
+        "repo",
+        location
+            .as_ref()
+            .map(|l| format!("{} on {}", l.repo, l.host.hostname))
+            .map_err(Clone::clone),
+    ));
+
+    if let Ok(location) = &location {
+        match GithubClient::for_user(&location.host, user_id).await {
+            Ok(client) => {
+                let access = check_repo_access(&client, location).await;
+                let accessible = access.is_ok();
+                checks.push(PreflightCheck::from_result("repo_access", access));
+                if accessible {
+                    checks.push(PreflightCheck::from_result(
+                        "target_branch",
+                        check_branch(&client, location, target_branch).await,
+                    ));
+                }
+            }
+            Err(e) => checks.push(PreflightCheck::from_result(
+                "repo_access",
+                Err(e.to_string()),
+            )),
+        }
+    }
+
+    checks.push(PreflightCheck::from_result(
+        "quota",
+        check_quota(db, user_id, org_id).await,
+    ));
+
+    // Sessions store the canonical form, which the lock compares against
+    let repo = location
+        .as_ref()
+        .map_or_else(|_| repo.trim().to_string(), RepoLocation::canonical);
+    let lock_mode = config::get().repo_lock;
+    if let Some(other) = repo_lock::check(db, lock_mode, &repo, target_branch, None).await? {
+        let message = repo_lock::conflict_message(&other, &repo, target_branch);
+        checks.push(PreflightCheck::from_result(
+            "repo_lock",
+            if lock_mode == RepoLockMode::Block {
+                Err(message)
+            } else {
+                Ok(message)
+            },
+        ));
+    }
+
+    let waiting = Session::find()
+        .filter(session::Column::UiStatus.eq(UiStatus::WaitingForSandbox))
+        .count(db)
+        .await?;
+    checks.push(PreflightCheck::from_result(
+        SANDBOX_CAPACITY,
+        if waiting == 0 {
+            Ok("Sandboxes are available".to_string())
+        } else {
+            Err(format!(
+                "Allocator is at capacity, {} sessions are waiting for a sandbox",
+                waiting
+            ))
+        },
+    ));
+
+    let queued_ahead = Session::find()
+        .filter(session::Column::UiStatus.is_in(queued_statuses()))
+        .count(db)
+        .await?;
+    let in_flight = Session::find()
+        .filter(session::Column::UiStatus.eq(UiStatus::InProgress))
+        .filter(session::Column::FanOutLimit.is_null())
+        .count(db)
+        .await?;
+    let average_run_seconds = sandbox_queue::average_run_seconds(db).await?;
+
+    Ok(PreflightReport {
+        checks,
+        queued_ahead,
+        estimated_wait_seconds: if queued_ahead == 0 {
+            0
+        } else {
+            sandbox_queue::estimate_wait_seconds(queued_ahead + 1, in_flight, average_run_seconds)
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(name: &'static str, passed: bool) -> PreflightCheck {
+        PreflightCheck {
+            name,
+            passed,
+            message: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_validate_branch() {
+        // git accepts shell syntax in branch names, so scripts still have to quote them
+        for branch in [
+            "main",
+            "claude/fix-login-1234",
+            "release/v1.2",
+            "feature/a.b",
+            "$(id)",
+        ] {
+            assert!(validate_branch(branch).is_ok(), "{}", branch);
+        }
+        for branch in [
+            "",
+            "-f",
+            "@",
+            "main; rm -rf ~",
+            "a..b",
+            "a@{1}",
+            "a/",
+            "/a",
+            "a//b",
+            "a/.hidden",
+            "a.lock",
+            "a.",
+            "a~1",
+            "a:b",
+            "a\nb",
+        ] {
+            assert!(validate_branch(branch).is_err(), "{:?}", branch);
+        }
+    }
+
+    #[test]
+    fn test_ready_ignores_sandbox_capacity() {
+        let report = PreflightReport {
+            checks: vec![check("repo", true), check(SANDBOX_CAPACITY, false)],
+            queued_ahead: 3,
+            estimated_wait_seconds: 600,
+        };
+        assert!(report.ready());
+
+        let report = PreflightReport {
+            checks: vec![check("repo", true), check("quota", false)],
+            queued_ahead: 0,
+            estimated_wait_seconds: 0,
+        };
+        assert!(!report.ready());
+    }
+}
@@ -0,0 +1,376 @@
+use sea_orm::{ActiveEnum, DatabaseConnection, Set};
+use std::fmt;
+use tracing::{info, warn};
+
+use crate::entities::session::{self, Model as SessionModel, UiStatus};
+use crate::services::{agent_tokens, notifications, session_events};
+
+/// Who caused a session status transition
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Actor {
+    /// An authenticated user, by user id
+    User(String),
+    /// A background task or job, by name
+    System(&'static str),
+}
+
+impl fmt::Display for Actor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Actor::User(user_id) => write!(f, "user:{}", user_id),
+            Actor::System(name) => write!(f, "system:{}", name),
+        }
+    }
+}
+
+/// Why a session changed status
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransitionCause {
+    /// A sandbox IP was borrowed and the session's prompts were enqueued
+    SandboxBorrowed,
+    /// The IP allocator had no capacity or could not be reached
+    SandboxUnavailable,
+    /// The Claude CLI run finished
+    RunCompleted,
+    /// A cancellation request was carried out
+    Cancelled,
+    /// The user added a prompt to a reviewed session
+    PromptAdded,
+    /// The user put a prompt of a reviewed session back in the queue
+    PromptRequeued,
+    /// The sandbox IP was returned to the allocator
+    IpReturned,
+    /// The user archived the session
+    Archived,
+    /// The user moved an archived session back to review
+    Unarchived,
+    /// The agent in the sandbox reported the work finished before the run ended
+    AgentReported,
+    /// Every child session of a fan-out finished
+    FanOutFinished,
+    /// The check before returning the IP found commits or changes that were never pushed
+    UnpushedWorkFound,
+    /// The user pushed the work the check found
+    WorkPushed,
+    /// The sandbox was released without the usual checks: by the user discarding the work the
+    /// check found, or by an admin unsticking its IP return
+    ForceReleased,
+}
+
+impl TransitionCause {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransitionCause::SandboxBorrowed => "sandbox_borrowed",
+            TransitionCause::SandboxUnavailable => "sandbox_unavailable",
+            TransitionCause::RunCompleted => "run_completed",
+            TransitionCause::Cancelled => "cancelled",
+            TransitionCause::PromptAdded => "prompt_added",
+            TransitionCause::PromptRequeued => "prompt_requeued",
+            TransitionCause::IpReturned => "ip_returned",
+            TransitionCause::Archived => "archived",
+            TransitionCause::Unarchived => "unarchived",
+            TransitionCause::AgentReported => "agent_reported",
+            TransitionCause::FanOutFinished => "fan_out_finished",
+            TransitionCause::UnpushedWorkFound => "unpushed_work_found",
+            TransitionCause::WorkPushed => "work_pushed",
+            TransitionCause::ForceReleased => "force_released",
+        }
+    }
+}
+
+impl fmt::Display for TransitionCause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Every allowed `(from, to, cause)` transition of `ui_status`.
+///
+/// See docs/UI_STATUS_STATE_MACHINE.md for the side effects of each one.
+const TRANSITIONS: &[(UiStatus, UiStatus, TransitionCause)] = &[
+    (
+        UiStatus::Pending,
+        UiStatus::InProgress,
+        TransitionCause::SandboxBorrowed,
+    ),
+    (
+        UiStatus::WaitingForSandbox,
+        UiStatus::InProgress,
+        TransitionCause::SandboxBorrowed,
+    ),
+    (
+        UiStatus::Pending,
+        UiStatus::WaitingForSandbox,
+        TransitionCause::SandboxUnavailable,
+    ),
+    (
+        UiStatus::WaitingForSandbox,
+        UiStatus::WaitingForSandbox,
+        TransitionCause::SandboxUnavailable,
+    ),
+    (
+        UiStatus::InProgress,
+        UiStatus::NeedsReview,
+        TransitionCause::RunCompleted,
+    ),
+    (
+        UiStatus::InProgress,
+        UiStatus::NeedsReview,
+        TransitionCause::Cancelled,
+    ),
+    (
+        UiStatus::InProgress,
+        UiStatus::NeedsReview,
+        TransitionCause::AgentReported,
+    ),
+    (
+        UiStatus::InProgress,
+        UiStatus::NeedsReview,
+        TransitionCause::FanOutFinished,
+    ),
+    (
+        UiStatus::NeedsReview,
+        UiStatus::Pending,
+        TransitionCause::PromptAdded,
+    ),
+    (
+        UiStatus::NeedsReviewIpReturned,
+        UiStatus::Pending,
+        TransitionCause::PromptAdded,
+    ),
+    (
+        UiStatus::NeedsReview,
+        UiStatus::Pending,
+        TransitionCause::PromptRequeued,
+    ),
+    (
+        UiStatus::NeedsReviewIpReturned,
+        UiStatus::Pending,
+        TransitionCause::PromptRequeued,
+    ),
+    (
+        UiStatus::NeedsReview,
+        UiStatus::NeedsReviewIpReturned,
+        TransitionCause::IpReturned,
+    ),
+    (
+        UiStatus::NeedsReview,
+        UiStatus::Archived,
+        TransitionCause::Archived,
+    ),
+    (
+        UiStatus::NeedsReviewIpReturned,
+        UiStatus::Archived,
+        TransitionCause::Archived,
+    ),
+    (
+        UiStatus::Archived,
+        UiStatus::NeedsReview,
+        TransitionCause::Unarchived,
+    ),
+    (
+        UiStatus::Archived,
+        UiStatus::NeedsReviewIpReturned,
+        TransitionCause::Unarchived,
+    ),
+    (
+        UiStatus::NeedsReview,
+        UiStatus::NeedsAttention,
+        TransitionCause::UnpushedWorkFound,
+    ),
+    (
+        UiStatus::Archived,
+        UiStatus::NeedsAttention,
+        TransitionCause::UnpushedWorkFound,
+    ),
+    (
+        UiStatus::NeedsAttention,
+        UiStatus::NeedsReview,
+        TransitionCause::WorkPushed,
+    ),
+    (
+        UiStatus::NeedsAttention,
+        UiStatus::Archived,
+        TransitionCause::WorkPushed,
+    ),
+    (
+        UiStatus::NeedsAttention,
+        UiStatus::NeedsReview,
+        TransitionCause::ForceReleased,
+    ),
+    (
+        UiStatus::NeedsAttention,
+        UiStatus::Archived,
+        TransitionCause::ForceReleased,
+    ),
+    (
+        UiStatus::NeedsReview,
+        UiStatus::Archived,
+        TransitionCause::ForceReleased,
+    ),
+    (
+        UiStatus::NeedsReviewIpReturned,
+        UiStatus::Archived,
+        TransitionCause::ForceReleased,
+    ),
+    (
+        UiStatus::NeedsAttention,
+        UiStatus::Pending,
+        TransitionCause::PromptAdded,
+    ),
+];
+
+/// A transition that is not in the allowed set
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidTransition {
+    pub session_id: uuid::Uuid,
+    pub from: UiStatus,
+    pub to: UiStatus,
+    pub cause: TransitionCause,
+}
+
+impl fmt::Display for InvalidTransition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Session {} cannot move from {} to {} ({})",
+            self.session_id,
+            self.from.to_value(),
+            self.to.to_value(),
+            self.cause
+        )
+    }
+}
+
+impl std::error::Error for InvalidTransition {}
+
+/// The single place `ui_status` is changed for an existing session
+pub struct SessionStateMachine;
+
+impl SessionStateMachine {
+    /// Whether `from -> to` is allowed for `cause`
+    pub fn is_allowed(from: &UiStatus, to: &UiStatus, cause: TransitionCause) -> bool {
+        TRANSITIONS
+            .iter()
+            .any(|(f, t, c)| f == from && t == to && *c == cause)
+    }
+
+    /// Validate a transition and return an active model with the new `ui_status` set.
+    ///
+    /// Callers set any other fields that change alongside the status and save the model.
+    /// Accepted transitions are logged under the `session_audit` target with actor and cause
+    /// and counted in `session_transitions_total`; rejected ones are logged and returned.
+    pub fn transition(
+        session: SessionModel,
+        to: UiStatus,
+        cause: TransitionCause,
+        actor: &Actor,
+    ) -> Result<session::ActiveModel, InvalidTransition> {
+        let from = session.ui_status.clone();
+
+        if !Self::is_allowed(&from, &to, cause) {
+            let err = InvalidTransition {
+                session_id: session.id,
+                from,
+                to,
+                cause,
+            };
+            warn!(target: "session_audit", actor = %actor, "Rejected transition: {}", err);
+            return Err(err);
+        }
+
+        info!(
+            target: "session_audit",
+            session_id = %session.id,
+            from = %from.to_value(),
+            to = %to.to_value(),
+            cause = %cause,
+            actor = %actor,
+            "Session status transition"
+        );
+        crate::metrics::get()
+            .session_transitions_total
+            .with_label_values(&[&from.to_value(), &to.to_value(), cause.as_str()])
+            .inc();
+
+        let mut active_session: session::ActiveModel = session.into();
+        active_session.ui_status = Set(to);
+        Ok(active_session)
+    }
+
+    /// Hook for side effects of a transition once it is saved, given the status the session
+    /// had before and the updated row. Appends the change to the session's event history,
+    /// unless the status stayed the same, and creates notifications for its watchers.
+    pub async fn after_save(
+        db: &DatabaseConnection,
+        from: &UiStatus,
+        session: &SessionModel,
+        cause: TransitionCause,
+        actor: &Actor,
+    ) {
+        if *from != session.ui_status {
+            session_events::status_changed(db, session.id, from, &session.ui_status, cause, actor)
+                .await;
+        }
+        notifications::on_transition(db, from, session, cause).await;
+        if *from == UiStatus::InProgress && session.ui_status != UiStatus::InProgress {
+            if let Err(e) = agent_tokens::revoke(db, session.id).await {
+                warn!(
+                    "Failed to revoke agent token of session {}: {}",
+                    session.id, e
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allowed_transitions() {
+        assert!(SessionStateMachine::is_allowed(
+            &UiStatus::Pending,
+            &UiStatus::InProgress,
+            TransitionCause::SandboxBorrowed
+        ));
+        assert!(SessionStateMachine::is_allowed(
+            &UiStatus::NeedsReviewIpReturned,
+            &UiStatus::Pending,
+            TransitionCause::PromptAdded
+        ));
+        assert!(SessionStateMachine::is_allowed(
+            &UiStatus::Archived,
+            &UiStatus::NeedsAttention,
+            TransitionCause::UnpushedWorkFound
+        ));
+    }
+
+    #[test]
+    fn test_rejected_transitions() {
+        // Archived sessions never go straight back to work
+        assert!(!SessionStateMachine::is_allowed(
+            &UiStatus::Archived,
+            &UiStatus::InProgress,
+            TransitionCause::SandboxBorrowed
+        ));
+        assert!(!SessionStateMachine::is_allowed(
+            &UiStatus::Archived,
+            &UiStatus::NeedsReviewIpReturned,
+            TransitionCause::IpReturned
+        ));
+        // Held work is pushed or released, never archived over
+        assert!(!SessionStateMachine::is_allowed(
+            &UiStatus::NeedsAttention,
+            &UiStatus::Archived,
+            TransitionCause::Archived
+        ));
+        // The cause has to match, not just the endpoints
+        assert!(!SessionStateMachine::is_allowed(
+            &UiStatus::InProgress,
+            &UiStatus::NeedsReview,
+            TransitionCause::PromptAdded
+        ));
+    }
+}
@@ -0,0 +1,129 @@
+use sea_orm::sea_query::{Expr, SimpleExpr};
+use sea_orm::{DatabaseConnection, DbBackend, FromQueryResult, Statement};
+use serde_json::Value;
+
+/// Longest tag accepted, in characters
+pub const MAX_TAG_LENGTH: usize = 50;
+
+/// Most tags a single session may carry
+pub const MAX_TAGS_PER_SESSION: usize = 20;
+
+/// Trim and validate tags supplied by a user, dropping duplicates
+pub fn normalize(tags: &[String]) -> Result<Vec<String>, String> {
+    let mut normalized: Vec<String> = Vec::new();
+    for tag in tags {
+        let tag = tag.trim();
+        if tag.is_empty() {
+            return Err("Tags must not be empty".to_string());
+        }
+        if tag.chars().count() > MAX_TAG_LENGTH {
+            return Err(format!(
+                "Tag exceeds {} characters: {}",
+                MAX_TAG_LENGTH, tag
+            ));
+        }
+        if !normalized.iter().any(|t| t == tag) {
+            normalized.push(tag.to_string());
+        }
+    }
+    Ok(normalized)
+}
+
+/// Tags stored on a session
+pub fn from_json(tags: &Value) -> Vec<String> {
+    tags.as_array()
+        .map(|items| {
+            items
+                .iter()
+                .filter_map(|t| t.as_str().map(str::to_string))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Add `new_tags` to `current`, keeping existing order
+pub fn add(current: Vec<String>, new_tags: Vec<String>) -> Result<Vec<String>, String> {
+    let mut tags = current;
+    for tag in new_tags {
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+    if tags.len() > MAX_TAGS_PER_SESSION {
+        return Err(format!(
+            "Sessions can have at most {} tags",
+            MAX_TAGS_PER_SESSION
+        ));
+    }
+    Ok(tags)
+}
+
+/// Filter matching sessions tagged with `tag` (served by the GIN index on `tags`)
+pub fn has_tag(tag: &str) -> SimpleExpr {
+    Expr::cust_with_values("tags @> $1", [serde_json::json!([tag])])
+}
+
+#[derive(Debug, FromQueryResult)]
+pub struct TagCount {
+    pub tag: String,
+    pub count: i64,
+}
+
+/// Every tag on the user's sessions with how many sessions use it, most used first
+pub async fn counts_for_user(
+    db: &DatabaseConnection,
+    user_id: &str,
+) -> Result<Vec<TagCount>, sea_orm::DbErr> {
+    TagCount::find_by_statement(Statement::from_sql_and_values(
+        DbBackend::Postgres,
+        r#"SELECT tag, COUNT(*) AS count
+           FROM session, jsonb_array_elements_text(session.tags) AS tag
+           WHERE session.user_id = $1
+           GROUP BY tag
+           ORDER BY count DESC, tag ASC"#,
+        [user_id.into()],
+    ))
+    .all(db)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_trims_and_dedupes() {
+        let tags = normalize(&[
+            " backend ".to_string(),
+            "backend".to_string(),
+            "infra".to_string(),
+        ])
+        .unwrap();
+        assert_eq!(tags, vec!["backend", "infra"]);
+
+        assert!(normalize(&["  ".to_string()]).is_err());
+        assert!(normalize(&["x".repeat(MAX_TAG_LENGTH + 1)]).is_err());
+    }
+
+    #[test]
+    fn test_add_keeps_order_and_limit() {
+        let tags = add(
+            vec!["a".to_string()],
+            vec!["b".to_string(), "a".to_string()],
+        )
+        .unwrap();
+        assert_eq!(tags, vec!["a", "b"]);
+
+        let many = (0..=MAX_TAGS_PER_SESSION).map(|i| i.to_string()).collect();
+        assert!(add(Vec::new(), many).is_err());
+    }
+
+    #[test]
+    fn test_from_json() {
+        assert_eq!(
+            from_json(&serde_json::json!(["a", "b"])),
+            vec!["a".to_string(), "b".to_string()]
+        );
+        assert!(from_json(&Value::Null).is_empty());
+    }
+}
@@ -0,0 +1,302 @@
+//! Title and branch generation for new sessions.
+//!
+//! Session creation does not wait on the Anthropic API: sessions are stored with a placeholder
+//! title and branch and flagged `title_pending`. The prompt poller does not wait on the API
+//! either: it starts generating both from the session's first prompt in a single call in the
+//! background, moves on to other sessions and dispatches this one on a later poll, once the
+//! flag is cleared, so its first run pushes to the generated branch. Results are cached by repo
+//! and prompt so retries and re-created sessions do not pay for them again.
+//!
+//! That first try makes a single API call. When it fails the session runs with the placeholders
+//! and `title_retry_at` schedules another try for `bg_tasks::title_regeneration`, with growing
+//! delays, up to `TITLE_REGENERATION_MAX_ATTEMPTS` times. Only those later tries retry rate
+//! limited or overloaded calls with backoff, so nothing waits on them but that task. A later success replaces the title unless the user
+//! set one, and the branch only while no run has pushed to it.
+
+use chrono::Utc;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter, QueryOrder,
+    QuerySelect, Set,
+};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::backoff::jittered_backoff;
+use crate::config;
+use crate::entities::prompt::{self, Entity as Prompt, Model as PromptModel};
+use crate::entities::session::{self, Entity as Session, Model as SessionModel};
+use crate::services::anthropic::{self, SessionNaming};
+use crate::services::hashing;
+
+/// Title of a session until its real one is generated
+pub const PLACEHOLDER_TITLE: &str = "Untitled Session";
+
+/// Generated namings kept in memory
+const CACHE_CAPACITY: usize = 256;
+
+/// Backoff between later tries of a failed generation
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(60);
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(3600);
+
+/// Branch of a session until its real one is generated
+pub fn placeholder_branch(session_id: uuid::Uuid) -> String {
+    format!("claude/session-{}", &session_id.to_string()[..24])
+}
+
+/// Least recently used namings by repo and prompt hash
+#[derive(Default)]
+struct NamingCache {
+    entries: HashMap<(String, String), SessionNaming>,
+    order: VecDeque<(String, String)>,
+}
+
+impl NamingCache {
+    fn get(&mut self, key: &(String, String)) -> Option<SessionNaming> {
+        let naming = self.entries.get(key)?.clone();
+        self.order.retain(|k| k != key);
+        self.order.push_back(key.clone());
+        Some(naming)
+    }
+
+    fn insert(&mut self, key: (String, String), naming: SessionNaming) {
+        if self.entries.insert(key.clone(), naming).is_none() {
+            self.order.push_back(key);
+        }
+        while self.order.len() > CACHE_CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+    }
+}
+
+static CACHE: LazyLock<Mutex<NamingCache>> = LazyLock::new(Default::default);
+
+/// Sessions whose naming was started in the background and has not finished
+static IN_FLIGHT: LazyLock<Mutex<HashSet<uuid::Uuid>>> = LazyLock::new(Default::default);
+
+fn cache_key(repo: &str, prompt_content: &str) -> (String, String) {
+    (repo.to_string(), hashing::sha256_hex(prompt_content))
+}
+
+/// Title and branch slug for a session on `repo` starting with `prompt_content`, from the
+/// cache when the same prompt was named before
+pub async fn generate(
+    repo: &str,
+    target_branch: &str,
+    prompt_content: &str,
+    max_retries: u32,
+) -> Result<SessionNaming, String> {
+    let key = cache_key(repo, prompt_content);
+    if let Some(naming) = CACHE.lock().unwrap_or_else(|e| e.into_inner()).get(&key) {
+        return Ok(naming);
+    }
+
+    let naming =
+        anthropic::generate_session_naming(repo, target_branch, prompt_content, max_retries)
+            .await?;
+    CACHE
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(key, naming.clone());
+    Ok(naming)
+}
+
+/// When to try again after `attempts` failed generations, None once they are used up
+fn next_retry_at(attempts: u32, max_attempts: u32) -> Option<chrono::DateTime<Utc>> {
+    (attempts <= max_attempts).then(|| {
+        let delay =
+            RETRY_BASE_DELAY + jittered_backoff(attempts, RETRY_BASE_DELAY, RETRY_MAX_DELAY);
+        Utc::now() + chrono::Duration::from_std(delay).unwrap_or(chrono::Duration::zero())
+    })
+}
+
+/// Generate the title and branch of a `title_pending` session from `first_prompt`.
+///
+/// The flag is cleared either way so a failing API is not retried on every poll; a failure
+/// keeps the placeholders and schedules a later try. Returns the updated session.
+pub async fn refresh(
+    db: &DatabaseConnection,
+    session_model: SessionModel,
+    first_prompt: &PromptModel,
+) -> Result<SessionModel, DbErr> {
+    let updated = name_session(db, session_model, first_prompt, 0).await?;
+    info!(
+        "Named session {} from its first prompt: title {:?}, branch {:?}",
+        updated.id, updated.title, updated.branch
+    );
+    Ok(updated)
+}
+
+/// Start `refresh` of a `title_pending` session in the background, unless it is already
+/// running for the session, and return at once
+pub fn spawn_refresh(
+    db: &DatabaseConnection,
+    session_model: SessionModel,
+    first_prompt: &PromptModel,
+) {
+    let session_id = session_model.id;
+    if !IN_FLIGHT
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(session_id)
+    {
+        return;
+    }
+    let db = db.clone();
+    let first_prompt = first_prompt.clone();
+    tokio::spawn(async move {
+        if let Err(e) = refresh(&db, session_model, &first_prompt).await {
+            warn!(
+                "Failed to store the naming of session {}: {}",
+                session_id, e
+            );
+        }
+        IN_FLIGHT
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&session_id);
+    });
+}
+
+/// Generate and store the naming of `session_model`. The title is only replaced while it is
+/// the placeholder, and the branch while no run of the session has started, since a run pushes
+/// to it.
+async fn name_session(
+    db: &DatabaseConnection,
+    session_model: SessionModel,
+    first_prompt: &PromptModel,
+    max_retries: u32,
+) -> Result<SessionModel, DbErr> {
+    let session_id = session_model.id;
+    let repo = session_model.repo.clone().unwrap_or_default();
+    let target_branch = session_model.target_branch.clone().unwrap_or_default();
+    let prompt_content = first_prompt.data.to_string();
+
+    let naming = generate(&repo, &target_branch, &prompt_content, max_retries).await;
+
+    let run_started = Prompt::find()
+        .filter(prompt::Column::SessionId.eq(session_id))
+        .filter(prompt::Column::StartedAt.is_not_null())
+        .one(db)
+        .await?
+        .is_some();
+    let has_placeholder_title =
+        session_model.title_pending || session_model.title.as_deref() == Some(PLACEHOLDER_TITLE);
+    let attempts = session_model.title_attempts.max(0) as u32;
+
+    let mut active_session: session::ActiveModel = session_model.into();
+    active_session.title_pending = Set(false);
+    match naming {
+        Ok(naming) => {
+            if !run_started {
+                active_session.branch = Set(Some(naming.branch_name(&session_id.to_string())));
+            }
+            if has_placeholder_title {
+                active_session.title = Set(Some(naming.title));
+            }
+            active_session.title_retry_at = Set(None);
+        }
+        Err(e) => {
+            let attempts = attempts + 1;
+            let retry_at = next_retry_at(attempts, config::get().title_generation.max_attempts);
+            warn!(
+                "Failed to generate title and branch of session {} (attempt {}), {}: {}",
+                session_id,
+                attempts,
+                match retry_at {
+                    Some(at) => format!("trying again at {}", at.to_rfc3339()),
+                    None => "keeping the placeholders".to_string(),
+                },
+                e
+            );
+            active_session.title_attempts = Set(attempts as i32);
+            active_session.title_retry_at = Set(retry_at.map(Into::into));
+        }
+    }
+    active_session.update(db).await
+}
+
+/// Try again to name up to `limit` sessions whose generation failed and whose retry is due.
+/// Returns how many were named.
+pub async fn regenerate_due(db: &DatabaseConnection, limit: u64) -> Result<u64, DbErr> {
+    let due = Session::find()
+        .filter(session::Column::TitleRetryAt.lte(Utc::now()))
+        .filter(session::Column::DeletedAt.is_null())
+        .order_by_asc(session::Column::TitleRetryAt)
+        .limit(limit)
+        .all(db)
+        .await?;
+
+    let max_retries = config::get().title_generation.api_max_retries;
+    let mut named = 0;
+    for session_model in due {
+        let first_prompt = Prompt::find()
+            .filter(prompt::Column::SessionId.eq(session_model.id))
+            .order_by_asc(prompt::Column::CreatedAt)
+            .one(db)
+            .await?;
+        let Some(first_prompt) = first_prompt else {
+            // Nothing to name it from
+            session::ActiveModel {
+                id: Set(session_model.id),
+                title_retry_at: Set(None),
+                ..Default::default()
+            }
+            .update(db)
+            .await?;
+            continue;
+        };
+
+        let updated = name_session(db, session_model, &first_prompt, max_retries).await?;
+        if updated.title_retry_at.is_none() && updated.title.as_deref() != Some(PLACEHOLDER_TITLE) {
+            info!(
+                "Named session {} on a later try: title {:?}, branch {:?}",
+                updated.id, updated.title, updated.branch
+            );
+            named += 1;
+        }
+    }
+    Ok(named)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn naming(title: &str) -> SessionNaming {
+        SessionNaming {
+            title: title.to_string(),
+            branch_slug: title.to_lowercase(),
+        }
+    }
+
+    #[test]
+    fn test_naming_cache_evicts_least_recently_used() {
+        let mut cache = NamingCache::default();
+        for i in 0..CACHE_CAPACITY {
+            cache.insert(cache_key("o/r", &i.to_string()), naming(&i.to_string()));
+        }
+        // Touch the oldest entry so the second oldest is evicted instead
+        assert_eq!(cache.get(&cache_key("o/r", "0")), Some(naming("0")));
+        cache.insert(cache_key("o/r", "new"), naming("new"));
+
+        assert!(cache.get(&cache_key("o/r", "0")).is_some());
+        assert!(cache.get(&cache_key("o/r", "1")).is_none());
+        assert!(cache.get(&cache_key("other/repo", "2")).is_none());
+        assert_eq!(cache.entries.len(), CACHE_CAPACITY);
+    }
+
+    #[test]
+    fn test_next_retry_at_backs_off_and_gives_up() {
+        let now = Utc::now();
+        let first = next_retry_at(1, 3).unwrap();
+        assert!(first >= now + chrono::Duration::seconds(60));
+        assert!(first <= now + chrono::Duration::seconds(181));
+        assert!(next_retry_at(3, 3).is_some());
+        assert!(next_retry_at(4, 3).is_none());
+        assert!(next_retry_at(1, 0).is_none());
+    }
+}
@@ -0,0 +1,243 @@
+//! Files users upload into a session's sandbox, e.g. fixtures or datasets for Claude to use.
+//!
+//! Uploads are forwarded to the sandbox through `sandbox_client`'s file write API in
+//! base64-encoded chunks, so no request to the sandbox carries more than `CHUNK_BYTES` of
+//! the file. Each file lands under a per-session directory outside the repo and is recorded
+//! in `session_upload`. When `UPLOAD_SCAN_COMMAND` is set, it is run on the written file
+//! before the upload is accepted.
+
+use base64::Engine;
+use chrono::Utc;
+use sandbox_client::types::{FileContentEncoding, FileWriteRequest, ShellExecRequest};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter, Set,
+};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tracing::{info, warn};
+
+use crate::entities::session::Model as SessionModel;
+use crate::entities::session_upload;
+use crate::services::hashing;
+use crate::services::http_client;
+use crate::services::sandbox_exec::sandbox_api_url;
+
+/// File bytes sent per write request, before base64 encoding
+pub const CHUNK_BYTES: usize = 1024 * 1024;
+
+/// Longest file name kept, in characters
+const MAX_FILE_NAME_CHARS: usize = 255;
+
+/// How long the scan command may run, in seconds
+const SCAN_TIMEOUT_SECS: f64 = 120.0;
+
+#[derive(Debug, thiserror::Error)]
+pub enum UploadError {
+    #[error("Session {0} holds no sandbox; uploads are accepted while a sandbox is assigned")]
+    NoSandbox(uuid::Uuid),
+    #[error("Invalid file name: {0}")]
+    InvalidFileName(String),
+    #[error("Upload rejected by the scan: {0}")]
+    Rejected(String),
+    #[error("Failed to write the upload to the sandbox: {0}")]
+    Sandbox(String),
+    #[error("Failed to read the upload: {0}")]
+    Read(#[from] std::io::Error),
+    #[error(transparent)]
+    Database(#[from] DbErr),
+}
+
+/// Reduce a client-supplied name to a single path component of `[A-Za-z0-9._-]`
+pub fn sanitize_file_name(name: &str) -> Result<String, UploadError> {
+    let base = name.rsplit(['/', '\\']).next().unwrap_or_default().trim();
+    let sanitized: String = base
+        .chars()
+        .take(MAX_FILE_NAME_CHARS)
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if sanitized.is_empty() || sanitized.chars().all(|c| c == '.') {
+        return Err(UploadError::InvalidFileName(name.to_string()));
+    }
+    Ok(sanitized)
+}
+
+/// Sandbox directory holding the session's uploads
+pub fn upload_dir(session_id: uuid::Uuid) -> String {
+    format!("{}/{}", crate::config::get().uploads.dir, session_id)
+}
+
+/// Stream `reader` into the session's sandbox as `file_name` and record the upload.
+///
+/// A file of the same name is overwritten and its record replaced. If the write fails midway
+/// or the scan rejects the file, the partial file is removed on a best-effort basis.
+pub async fn upload<R: AsyncRead + Unpin>(
+    db: &DatabaseConnection,
+    session: &SessionModel,
+    user_id: &str,
+    file_name: &str,
+    content_type: Option<String>,
+    mut reader: R,
+) -> Result<session_upload::Model, UploadError> {
+    let api_url = sandbox_api_url(session).ok_or(UploadError::NoSandbox(session.id))?;
+    let file_name = sanitize_file_name(file_name)?;
+    let path = format!("{}/{}", upload_dir(session.id), file_name);
+    let sbx = sandbox_client::Client::new_with_client(&api_url, http_client::client());
+
+    let mut hasher = Sha256::new();
+    let mut size_bytes = 0usize;
+    let mut buf = vec![0u8; CHUNK_BYTES];
+    loop {
+        let filled = read_chunk(&mut reader, &mut buf).await?;
+        // The first write creates or truncates the file, so an empty upload still writes once
+        if filled == 0 && size_bytes > 0 {
+            break;
+        }
+        hasher.update(&buf[..filled]);
+        let written = sbx
+            .write_file(&FileWriteRequest {
+                content: base64::engine::general_purpose::STANDARD.encode(&buf[..filled]),
+                file: path.clone(),
+                append: size_bytes > 0,
+                sudo: false,
+                encoding: FileContentEncoding::Base64,
+                leading_newline: false,
+                trailing_newline: false,
+            })
+            .await;
+        if let Err(e) = written {
+            remove(&sbx, &path).await;
+            return Err(UploadError::Sandbox(e.to_string()));
+        }
+        size_bytes += filled;
+        if filled < CHUNK_BYTES {
+            break;
+        }
+    }
+
+    if let Some(command) = &crate::config::get().uploads.scan_command {
+        if let Err(reason) = scan(&sbx, command, &path).await {
+            warn!(
+                "Scan rejected upload {} to session {}: {}",
+                path, session.id, reason
+            );
+            remove(&sbx, &path).await;
+            return Err(UploadError::Rejected(reason));
+        }
+    }
+
+    session_upload::Entity::delete_many()
+        .filter(session_upload::Column::SessionId.eq(session.id))
+        .filter(session_upload::Column::Path.eq(&path))
+        .exec(db)
+        .await?;
+    let record = session_upload::ActiveModel {
+        id: Set(uuid::Uuid::new_v4()),
+        session_id: Set(session.id),
+        user_id: Set(user_id.to_string()),
+        file_name: Set(file_name),
+        path: Set(path),
+        content_type: Set(content_type),
+        size_bytes: Set(size_bytes as i64),
+        sha256: Set(hashing::hex(&hasher.finalize())),
+        created_at: Set(Utc::now().into()),
+    }
+    .insert(db)
+    .await?;
+
+    info!(
+        "Uploaded {} ({} bytes) to session {}",
+        record.path, record.size_bytes, session.id
+    );
+    Ok(record)
+}
+
+/// Fill `buf` from `reader`, returning fewer bytes only at the end of the input
+async fn read_chunk<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    buf: &mut [u8],
+) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let read = reader.read(&mut buf[filled..]).await?;
+        if read == 0 {
+            break;
+        }
+        filled += read;
+    }
+    Ok(filled)
+}
+
+/// Run the scan command on `path`; Err with its output when it exits nonzero or cannot run
+async fn scan(sbx: &sandbox_client::Client, command: &str, path: &str) -> Result<(), String> {
+    let response = sbx
+        .exec_command_v1_shell_exec_post(&ShellExecRequest {
+            command: format!("{} '{}'", command, path),
+            async_mode: false,
+            id: None,
+            timeout: Some(SCAN_TIMEOUT_SECS),
+            exec_dir: Some("/home/gem".to_string()),
+        })
+        .await
+        .map_err(|e| format!("Scan could not be run: {}", e))?
+        .into_inner();
+    let result = response.data.ok_or(response.message)?;
+    match result.exit_code {
+        Some(0) => Ok(()),
+        _ => Err(result
+            .output
+            .filter(|output| !output.trim().is_empty())
+            .unwrap_or_else(|| format!("Scan exited with {:?}", result.exit_code))),
+    }
+}
+
+async fn remove(sbx: &sandbox_client::Client, path: &str) {
+    let removed = sbx
+        .exec_command_v1_shell_exec_post(&ShellExecRequest {
+            command: format!("rm -f '{}'", path),
+            async_mode: false,
+            id: None,
+            timeout: Some(30.0),
+            exec_dir: Some("/home/gem".to_string()),
+        })
+        .await;
+    if let Err(e) = removed {
+        warn!("Failed to remove upload {} from the sandbox: {}", path, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize_file_name() {
+        assert_eq!(sanitize_file_name("data.csv").unwrap(), "data.csv");
+        assert_eq!(sanitize_file_name("../../etc/pass wd").unwrap(), "pass_wd");
+        assert_eq!(
+            sanitize_file_name("C:\\fixtures\\it's.json").unwrap(),
+            "it_s.json"
+        );
+        assert!(sanitize_file_name("..").is_err());
+        assert!(sanitize_file_name("dir/").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_read_chunk_fills_across_short_reads() {
+        let (mut writer, mut reader) = tokio::io::duplex(4);
+        tokio::spawn(async move {
+            use tokio::io::AsyncWriteExt;
+            writer.write_all(b"0123456789").await.unwrap();
+        });
+        let mut buf = [0u8; 8];
+        assert_eq!(read_chunk(&mut reader, &mut buf).await.unwrap(), 8);
+        assert_eq!(&buf, b"01234567");
+        assert_eq!(read_chunk(&mut reader, &mut buf).await.unwrap(), 2);
+        assert_eq!(read_chunk(&mut reader, &mut buf).await.unwrap(), 0);
+    }
+}
@@ -0,0 +1,20 @@
+//! Building shell commands for the sandbox from values users control, such as branch names.
+
+/// `value` as a single shell word: single-quoted, with embedded single quotes closed, escaped
+/// and reopened, so nothing in it is expanded or interpreted
+pub fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("claude/fix-login"), "'claude/fix-login'");
+        assert_eq!(quote("a'b"), r"'a'\''b'");
+        assert_eq!(quote("main; rm -rf ~"), "'main; rm -rf ~'");
+        assert_eq!(quote("$(id)"), "'$(id)'");
+    }
+}
@@ -0,0 +1,52 @@
+//! Soft cancellation: asking the agent to wrap up instead of killing it mid-flight.
+//!
+//! Every run's system prompt names a sentinel file in the sandbox. When a soft cancellation is
+//! requested the enforcer writes the wrap-up prompt into that file; the agent is told to check
+//! for it between steps and, once it appears, commit and push its work in progress and finish
+//! with a summary. The process is only terminated if it is still running after the grace
+//! period.
+
+use sandbox_client::types::{FileContentEncoding, FileWriteRequest};
+
+use crate::entities::session::Model as SessionModel;
+use crate::services::{http_client, sandbox_exec};
+
+/// Instructions written into the sentinel file
+const WRAP_UP_PROMPT: &str = "The user cancelled this task. Do not start any new work. Commit \
+     what you have so far as work in progress, push it, and finish with a short summary of \
+     what was done and what remains.";
+
+/// Path of the sentinel file in the session's sandbox, outside the repo so it is never committed
+pub fn sentinel_path(session_id: uuid::Uuid) -> String {
+    format!("/home/gem/cancel_{}", session_id)
+}
+
+/// Section appended to the system prompt so the agent knows to watch for the sentinel
+pub fn prompt_section(session_id: uuid::Uuid) -> String {
+    format!(
+        "\n\n## Cancellation\n\nThe user may cancel this task while you work. Before each step, \
+         check whether the file `{}` exists. If it does, stop and follow the instructions it \
+         contains.\n",
+        sentinel_path(session_id)
+    )
+}
+
+/// Write the sentinel into the session's sandbox
+pub async fn signal(session: &SessionModel) -> Result<(), String> {
+    let api_url = sandbox_exec::sandbox_api_url(session)
+        .ok_or_else(|| format!("Session {} no longer holds a sandbox", session.id))?;
+    let sbx = sandbox_client::Client::new_with_client(&api_url, http_client::client());
+
+    sbx.write_file(&FileWriteRequest {
+        content: WRAP_UP_PROMPT.to_string(),
+        file: sentinel_path(session.id),
+        append: false,
+        sudo: false,
+        encoding: FileContentEncoding::Utf8,
+        leading_newline: false,
+        trailing_newline: true,
+    })
+    .await
+    .map(|_| ())
+    .map_err(|e| e.to_string())
+}
@@ -0,0 +1,193 @@
+//! Display metadata of the status enums, served to frontends by `GET /meta/statuses`.
+//!
+//! Each enum lists its values from its own variants and describes them with exhaustive
+//! matches, so a new variant does not compile until it has a label and color here. Labels and
+//! colors can be overridden per value with `STATUS_LABELS` and `STATUS_COLORS`, keyed
+//! `<Enum>.<Value>` with the names the API uses, e.g. `UiStatus.NeedsReview=#f59e0b`.
+
+use sea_orm::Iterable;
+use serde::Serialize;
+use std::collections::HashMap;
+
+use crate::entities::dead_letter_queue::DlqStatus;
+use crate::entities::session::{CancellationStatus, UiStatus};
+
+/// A status enum as frontends see it
+pub trait StatusMeta: Iterable + Serialize {
+    /// Name of the enum in the API schema
+    const NAME: &'static str;
+
+    fn label(&self) -> &'static str;
+
+    /// CSS color, e.g. `#22c55e`
+    fn color(&self) -> &'static str;
+
+    /// Whether the status ends the entity's lifecycle; only a user can move it on from there
+    fn terminal(&self) -> bool;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatusValue {
+    /// The value as the API sends it
+    pub name: String,
+    pub label: String,
+    pub color: String,
+    pub terminal: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatusEnum {
+    pub name: &'static str,
+    pub values: Vec<StatusValue>,
+}
+
+/// Every value of `T` in declaration order, with `labels` and `colors` overrides applied
+pub fn describe<T: StatusMeta>(
+    labels: &HashMap<String, String>,
+    colors: &HashMap<String, String>,
+) -> StatusEnum {
+    let values = T::iter()
+        .map(|value| {
+            let name = serde_json::to_value(&value)
+                .ok()
+                .and_then(|v| v.as_str().map(str::to_string))
+                .unwrap_or_default();
+            let key = format!("{}.{}", T::NAME, name);
+            StatusValue {
+                label: labels
+                    .get(&key)
+                    .cloned()
+                    .unwrap_or_else(|| value.label().to_string()),
+                color: colors
+                    .get(&key)
+                    .cloned()
+                    .unwrap_or_else(|| value.color().to_string()),
+                terminal: value.terminal(),
+                name,
+            }
+        })
+        .collect();
+    StatusEnum {
+        name: T::NAME,
+        values,
+    }
+}
+
+impl StatusMeta for UiStatus {
+    const NAME: &'static str = "UiStatus";
+
+    fn label(&self) -> &'static str {
+        match self {
+            UiStatus::Pending => "Pending",
+            UiStatus::WaitingForSandbox => "Waiting for sandbox",
+            UiStatus::InProgress => "In progress",
+            UiStatus::NeedsReview => "Needs review",
+            UiStatus::NeedsReviewIpReturned => "Needs review",
+            UiStatus::Archived => "Archived",
+            UiStatus::NeedsAttention => "Needs attention",
+        }
+    }
+
+    fn color(&self) -> &'static str {
+        match self {
+            UiStatus::Pending => "#94a3b8",
+            UiStatus::WaitingForSandbox => "#a78bfa",
+            UiStatus::InProgress => "#3b82f6",
+            UiStatus::NeedsReview => "#f59e0b",
+            UiStatus::NeedsReviewIpReturned => "#f59e0b",
+            UiStatus::Archived => "#64748b",
+            UiStatus::NeedsAttention => "#ef4444",
+        }
+    }
+
+    fn terminal(&self) -> bool {
+        match self {
+            UiStatus::Archived => true,
+            UiStatus::Pending
+            | UiStatus::WaitingForSandbox
+            | UiStatus::InProgress
+            | UiStatus::NeedsReview
+            | UiStatus::NeedsReviewIpReturned
+            | UiStatus::NeedsAttention => false,
+        }
+    }
+}
+
+impl StatusMeta for CancellationStatus {
+    const NAME: &'static str = "CancellationStatus";
+
+    fn label(&self) -> &'static str {
+        match self {
+            CancellationStatus::Requested => "Cancelling",
+            CancellationStatus::Cancelled => "Cancelled",
+        }
+    }
+
+    fn color(&self) -> &'static str {
+        match self {
+            CancellationStatus::Requested => "#f97316",
+            CancellationStatus::Cancelled => "#64748b",
+        }
+    }
+
+    fn terminal(&self) -> bool {
+        match self {
+            CancellationStatus::Requested => false,
+            CancellationStatus::Cancelled => true,
+        }
+    }
+}
+
+impl StatusMeta for DlqStatus {
+    const NAME: &'static str = "DlqStatus";
+
+    fn label(&self) -> &'static str {
+        match self {
+            DlqStatus::Pending => "Pending",
+            DlqStatus::Resolved => "Resolved",
+            DlqStatus::Abandoned => "Abandoned",
+        }
+    }
+
+    fn color(&self) -> &'static str {
+        match self {
+            DlqStatus::Pending => "#ef4444",
+            DlqStatus::Resolved => "#22c55e",
+            DlqStatus::Abandoned => "#64748b",
+        }
+    }
+
+    fn terminal(&self) -> bool {
+        match self {
+            DlqStatus::Pending => false,
+            DlqStatus::Resolved | DlqStatus::Abandoned => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_describe_applies_overrides() {
+        let labels = HashMap::from([(
+            "UiStatus.NeedsReview".to_string(),
+            "Ready for review".to_string(),
+        )]);
+        let colors = HashMap::from([("DlqStatus.Pending".to_string(), "red".to_string())]);
+
+        let ui = describe::<UiStatus>(&labels, &colors);
+        assert_eq!(ui.name, "UiStatus");
+        assert_eq!(ui.values.len(), UiStatus::iter().count());
+        let review = ui.values.iter().find(|v| v.name == "NeedsReview").unwrap();
+        assert_eq!(review.label, "Ready for review");
+        assert_eq!(review.color, "#f59e0b");
+        assert!(ui.values.iter().any(|v| v.name == "Archived" && v.terminal));
+
+        let dlq = describe::<DlqStatus>(&labels, &colors);
+        assert_eq!(dlq.values[0].name, "Pending");
+        assert_eq!(dlq.values[0].color, "red");
+        assert!(!dlq.values[0].terminal);
+    }
+}
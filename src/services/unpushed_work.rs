@@ -0,0 +1,251 @@
+//! Guards against losing work when a session's sandbox IP is returned.
+//!
+//! Returning the IP destroys the sandbox, including commits the agent made but never pushed
+//! and changes it never committed. Before the IP return poller returns an IP it runs a check
+//! in the sandbox clone; when something would be lost the session moves to NeedsAttention
+//! with the findings in `unpushed_work` and keeps its sandbox. The user then either pushes the
+//! work to the session branch or releases the sandbox, discarding it.
+
+use chrono::Utc;
+use rocket_okapi::okapi::schemars::{self, JsonSchema};
+use sandbox_client::types::ShellExecRequest;
+use serde::{Deserialize, Serialize};
+
+use crate::entities::session::{Model as SessionModel, UiStatus};
+use crate::services::http_client;
+use crate::services::sandbox_exec::default_exec_dir;
+use crate::services::shell;
+
+/// Timeout of the check and push commands; `git fetch` and `git push` talk to the remote
+const COMMAND_TIMEOUT_SECS: f64 = 30.0;
+
+/// Uncommitted paths kept in the findings
+const MAX_DIRTY_FILES: usize = 50;
+
+/// Message of the commit `push` makes of uncommitted changes
+const WIP_COMMIT_MESSAGE: &str = "Work in progress left uncommitted in the session sandbox";
+
+#[derive(Debug, thiserror::Error)]
+pub enum UnpushedWorkError {
+    #[error("Session {0} no longer holds a sandbox")]
+    NoSandbox(uuid::Uuid),
+    #[error("Sandbox request failed: {0}")]
+    Sandbox(String),
+    #[error("Command exited with {exit_code:?}: {output}")]
+    Command {
+        exit_code: Option<i64>,
+        output: String,
+    },
+}
+
+/// Work found in the sandbox clone that returning the IP would destroy, stored as the
+/// session's `unpushed_work`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct UnpushedWork {
+    /// Branch the work belongs on
+    pub branch: String,
+    /// Commits on HEAD that the remote does not have
+    pub commits_ahead: i64,
+    /// `git status --porcelain` lines of uncommitted changes, at most 50
+    pub dirty_files: Vec<String>,
+    pub checked_at: String,
+    /// Status the session had when the check held it, restored once the work is dealt with
+    pub held_from: UiStatus,
+    /// Set when the user released the sandbox without pushing
+    pub released_at: Option<String>,
+    pub released_by: Option<String>,
+}
+
+impl UnpushedWork {
+    pub fn from_json(value: Option<&serde_json::Value>) -> Option<Self> {
+        serde_json::from_value(value?.clone()).ok()
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::to_value(self).unwrap_or_default()
+    }
+
+    /// Short description for the session's status message
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if self.commits_ahead > 0 {
+            parts.push(format!(
+                "{} unpushed commit{}",
+                self.commits_ahead,
+                if self.commits_ahead == 1 { "" } else { "s" }
+            ));
+        }
+        if !self.dirty_files.is_empty() {
+            parts.push(format!(
+                "{} uncommitted change{}",
+                self.dirty_files.len(),
+                if self.dirty_files.len() == 1 { "" } else { "s" }
+            ));
+        }
+        format!(
+            "Sandbox kept: {} on {}; push the work or release the sandbox",
+            parts.join(" and "),
+            self.branch
+        )
+    }
+}
+
+/// Whether the user released the session's sandbox, so the IP is returned without a check
+pub fn is_released(session: &SessionModel) -> bool {
+    UnpushedWork::from_json(session.unpushed_work.as_ref())
+        .is_some_and(|work| work.released_at.is_some())
+}
+
+fn branch_of(session: &SessionModel) -> String {
+    session
+        .branch
+        .clone()
+        .unwrap_or_else(|| format!("claude/{}", session.id))
+}
+
+/// Shell script printing `ahead <n>` and then the porcelain status of the clone. The remote
+/// session branch is the base, falling back to the target branch before the first push.
+fn check_script(branch: &str, target_branch: &str) -> String {
+    format!(
+        r#"[ -d .git ] || {{ echo "ahead 0"; exit 0; }}
+git fetch -q origin 2>/dev/null
+base=$(git rev-parse -q --verify {branch} || git rev-parse -q --verify {target} || git rev-parse -q --verify origin/HEAD)
+if [ -n "$base" ]; then echo "ahead $(git rev-list --count "$base"..HEAD)"; else echo "ahead $(git rev-list --count HEAD)"; fi
+git status --porcelain"#,
+        branch = shell::quote(&format!("origin/{}", branch)),
+        target = shell::quote(&format!("origin/{}", target_branch))
+    )
+}
+
+/// Commits ahead and uncommitted paths in the output of `check_script`, None when the output
+/// does not have the expected shape
+fn parse_check(output: &str) -> Option<(i64, Vec<String>)> {
+    let mut lines = output
+        .lines()
+        .skip_while(|line| !line.starts_with("ahead "));
+    let commits_ahead = lines.next()?.strip_prefix("ahead ")?.trim().parse().ok()?;
+    let dirty_files = lines
+        .filter(|line| !line.trim().is_empty())
+        .take(MAX_DIRTY_FILES)
+        .map(|line| line.trim_end().to_string())
+        .collect();
+    Some((commits_ahead, dirty_files))
+}
+
+fn sandbox_client_for(session: &SessionModel) -> Result<sandbox_client::Client, UnpushedWorkError> {
+    // Archived sessions still hold their sandbox until the IP is returned
+    let api_url = session
+        .sbx_config
+        .as_ref()
+        .and_then(|config| config.get("item")?.get("api_url")?.as_str())
+        .ok_or(UnpushedWorkError::NoSandbox(session.id))?;
+    Ok(sandbox_client::Client::new_with_client(
+        api_url,
+        http_client::client(),
+    ))
+}
+
+/// Output of `command` in the session's clone, failing unless it exits 0
+async fn exec(session: &SessionModel, command: String) -> Result<String, UnpushedWorkError> {
+    let sbx = sandbox_client_for(session)?;
+    let response = sbx
+        .exec_command_v1_shell_exec_post(&ShellExecRequest {
+            command,
+            async_mode: false,
+            id: None,
+            timeout: Some(COMMAND_TIMEOUT_SECS),
+            exec_dir: Some(default_exec_dir(session.id)),
+        })
+        .await
+        .map_err(|e| UnpushedWorkError::Sandbox(e.to_string()))?
+        .into_inner();
+    match response.data {
+        Some(result) if result.exit_code == Some(0) => Ok(result.output.unwrap_or_default()),
+        Some(result) => Err(UnpushedWorkError::Command {
+            exit_code: result.exit_code,
+            output: result.output.unwrap_or_else(|| result.status.to_string()),
+        }),
+        None => Err(UnpushedWorkError::Sandbox(response.message)),
+    }
+}
+
+/// Check the session's clone for work that has not reached the remote. Returns None when
+/// everything is pushed or there is no clone.
+pub async fn check(session: &SessionModel) -> Result<Option<UnpushedWork>, UnpushedWorkError> {
+    let branch = branch_of(session);
+    let target_branch = session.target_branch.clone().unwrap_or_default();
+    let output = exec(session, check_script(&branch, &target_branch)).await?;
+    let (commits_ahead, dirty_files) =
+        parse_check(&output).ok_or_else(|| UnpushedWorkError::Command {
+            exit_code: Some(0),
+            output: output.clone(),
+        })?;
+    if commits_ahead == 0 && dirty_files.is_empty() {
+        return Ok(None);
+    }
+
+    // A session held before keeps the status it was held from
+    let held_from = UnpushedWork::from_json(session.unpushed_work.as_ref())
+        .filter(|_| session.ui_status == UiStatus::NeedsAttention)
+        .map(|work| work.held_from)
+        .unwrap_or_else(|| session.ui_status.clone());
+    Ok(Some(UnpushedWork {
+        branch,
+        commits_ahead,
+        dirty_files,
+        checked_at: Utc::now().to_rfc3339(),
+        held_from,
+        released_at: None,
+        released_by: None,
+    }))
+}
+
+/// Commit any uncommitted changes and push HEAD to the session branch. Returns git's output.
+pub async fn push(session: &SessionModel) -> Result<String, UnpushedWorkError> {
+    let branch = branch_of(session);
+    exec(
+        session,
+        format!(
+            "git add -A && {{ git diff --cached --quiet || git commit -q -m \"{}\"; }} && git push -u origin {} 2>&1",
+            WIP_COMMIT_MESSAGE,
+            shell::quote(&format!("HEAD:refs/heads/{}", branch))
+        ),
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_check() {
+        assert_eq!(parse_check("ahead 0\n"), Some((0, vec![])));
+        assert_eq!(
+            parse_check("warning: noise\nahead 2\n M src/main.rs\n?? notes.txt\n\n"),
+            Some((
+                2,
+                vec![" M src/main.rs".to_string(), "?? notes.txt".to_string()]
+            ))
+        );
+        assert_eq!(parse_check("fatal: not a git repository"), None);
+    }
+
+    #[test]
+    fn test_describe() {
+        let work = UnpushedWork {
+            branch: "claude/fix".to_string(),
+            commits_ahead: 1,
+            dirty_files: vec![" M a.rs".to_string(), "?? b.rs".to_string()],
+            checked_at: Utc::now().to_rfc3339(),
+            held_from: UiStatus::NeedsReview,
+            released_at: None,
+            released_by: None,
+        };
+        assert_eq!(
+            work.describe(),
+            "Sandbox kept: 1 unpushed commit and 2 uncommitted changes on claude/fix; push the work or release the sandbox"
+        );
+        assert_eq!(UnpushedWork::from_json(Some(&work.to_json())), Some(work));
+    }
+}
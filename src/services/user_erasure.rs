@@ -0,0 +1,357 @@
+//! Erasure of everything stored about a user, on their own request.
+//!
+//! Erasure starts like deprovisioning: running and queued sessions get a cancellation
+//! request and reviewed sessions are archived, so the cancellation enforcer and IP return
+//! poller stop their processes and hand their sandboxes back. Sessions that hold neither are
+//! deleted for good straight away, with their prompts, messages, artifacts and events; the rest
+//! stay pending until the data retention task finds them released. Rows the user created
+//! outside their sessions (settings, notifications, watches, annotations, uploads, prompt
+//! attachments, idempotency keys) are deleted, and DLQ entries about their sessions lose their
+//! stored payload. Agent tokens belong to sessions and go with them.
+//!
+//! A `user_erasure` row records the request and a running report, and outlives the data.
+
+use chrono::Utc;
+use rocket_okapi::okapi::schemars::{self, JsonSchema};
+use sea_orm::sea_query::Expr;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter,
+    QuerySelect, Set,
+};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::bg_tasks::prompt_run;
+use crate::entities::annotation::{self, Entity as Annotation};
+use crate::entities::dead_letter_queue::{self, Entity as DeadLetterQueue};
+use crate::entities::idempotency_key::{self, Entity as IdempotencyKey};
+use crate::entities::message::{self, Entity as Message};
+use crate::entities::notification::{self, Entity as Notification};
+use crate::entities::prompt::{self, Entity as Prompt};
+use crate::entities::prompt_attachment::{self, Entity as PromptAttachment};
+use crate::entities::sandbox_exec::{self, Entity as SandboxExec};
+use crate::entities::session::{self, Entity as Session, Model as SessionModel, UiStatus};
+use crate::entities::session_artifact::{self, Entity as SessionArtifact};
+use crate::entities::session_upload::{self, Entity as SessionUpload};
+use crate::entities::session_watcher::{self, Entity as SessionWatcher};
+use crate::entities::user_erasure::{self, Entity as UserErasure, ErasureStatus};
+use crate::entities::user_settings::{self, Entity as UserSettings};
+use crate::services::deprovision;
+use crate::services::message_blobs;
+use crate::services::session_state_machine::Actor;
+
+/// What an erasure has deleted so far
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct ErasureReport {
+    /// Running or queued sessions whose cancellation was requested
+    pub sessions_cancelled: u64,
+    /// Sessions deleted with everything they contained
+    pub sessions_deleted: u64,
+    /// Sessions still holding a sandbox or running process, deleted once released
+    pub sessions_pending: u64,
+    pub prompts_deleted: u64,
+    pub messages_deleted: u64,
+    /// Offloaded message payloads, collected artifacts and attachments removed from object
+    /// storage
+    pub stored_objects_deleted: u64,
+    /// Annotations the user wrote on any session
+    pub annotations_deleted: u64,
+    pub notifications_deleted: u64,
+    /// Settings, session watches, uploads, attachments and idempotency keys
+    pub other_records_deleted: u64,
+    /// DLQ entries about the user's sessions whose stored payload was removed
+    pub dlq_entries_scrubbed: u64,
+}
+
+/// Payload left on DLQ entries scrubbed by an erasure
+fn scrubbed_entity_data() -> serde_json::Value {
+    serde_json::json!({ "erased": true })
+}
+
+/// Whether `session` still holds resources the cancellation enforcer or IP return poller has
+/// to release before its row can go
+async fn is_releasing(db: &DatabaseConnection, session: &SessionModel) -> Result<bool, DbErr> {
+    if session.sbx_config.is_some()
+        || session.process_pid.is_some()
+        || session.ui_status == UiStatus::InProgress
+    {
+        return Ok(true);
+    }
+    prompt_run::has_active_run(db, session.id).await
+}
+
+/// Delete one session and everything stored for it
+async fn delete_session(
+    db: &DatabaseConnection,
+    session_id: Uuid,
+    report: &mut ErasureReport,
+) -> Result<(), DbErr> {
+    let prompt_ids: Vec<Uuid> = Prompt::find()
+        .select_only()
+        .column(prompt::Column::Id)
+        .filter(prompt::Column::SessionId.eq(session_id))
+        .into_tuple()
+        .all(db)
+        .await?;
+
+    let messages = Message::find()
+        .filter(message::Column::PromptId.is_in(prompt_ids.clone()))
+        .all(db)
+        .await?;
+    for model in messages.iter().filter(|m| m.blob_key.is_some()) {
+        message_blobs::remove(model).await;
+        report.stored_objects_deleted += 1;
+    }
+
+    let artifacts = SessionArtifact::find()
+        .filter(session_artifact::Column::SessionId.eq(session_id))
+        .all(db)
+        .await?;
+    if let Some(blobs) = message_blobs::get() {
+        for artifact in &artifacts {
+            match blobs.delete(&artifact.blob_key).await {
+                Ok(()) => report.stored_objects_deleted += 1,
+                Err(e) => warn!("{}", e),
+            }
+        }
+    }
+
+    SandboxExec::delete_many()
+        .filter(sandbox_exec::Column::SessionId.eq(session_id))
+        .exec(db)
+        .await?;
+    // Prompts, messages, annotations, artifacts, uploads, events, notifications and watches
+    // cascade with the session
+    Session::delete_by_id(session_id).exec(db).await?;
+
+    report.sessions_deleted += 1;
+    report.prompts_deleted += prompt_ids.len() as u64;
+    report.messages_deleted += messages.len() as u64;
+    Ok(())
+}
+
+/// Delete every session of `user_id` that has been released, counting the rest as pending
+async fn delete_sessions(
+    db: &DatabaseConnection,
+    user_id: &str,
+    report: &mut ErasureReport,
+) -> Result<(), DbErr> {
+    let sessions = Session::find()
+        .filter(session::Column::UserId.eq(user_id))
+        .all(db)
+        .await?;
+
+    report.sessions_pending = 0;
+    for session in sessions {
+        if is_releasing(db, &session).await? {
+            report.sessions_pending += 1;
+        } else {
+            delete_session(db, session.id, report).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Remove the stored payload of DLQ entries about the user's sessions or their prompts
+async fn scrub_dlq(
+    db: &DatabaseConnection,
+    user_id: &str,
+    report: &mut ErasureReport,
+) -> Result<(), DbErr> {
+    let session_ids: Vec<Uuid> = Session::find()
+        .select_only()
+        .column(session::Column::Id)
+        .filter(session::Column::UserId.eq(user_id))
+        .into_tuple()
+        .all(db)
+        .await?;
+    let prompt_ids: Vec<Uuid> = Prompt::find()
+        .select_only()
+        .column(prompt::Column::Id)
+        .filter(prompt::Column::SessionId.is_in(session_ids.clone()))
+        .into_tuple()
+        .all(db)
+        .await?;
+
+    let scrubbed = DeadLetterQueue::update_many()
+        .col_expr(
+            dead_letter_queue::Column::EntityData,
+            Expr::value(scrubbed_entity_data()),
+        )
+        .filter(
+            dead_letter_queue::Column::EntityId.is_in(session_ids.into_iter().chain(prompt_ids)),
+        )
+        .filter(dead_letter_queue::Column::EntityData.ne(scrubbed_entity_data()))
+        .exec(db)
+        .await?;
+    report.dlq_entries_scrubbed += scrubbed.rows_affected;
+    Ok(())
+}
+
+/// Delete what the user created outside their own sessions
+async fn delete_user_records(
+    db: &DatabaseConnection,
+    user_id: &str,
+    report: &mut ErasureReport,
+) -> Result<(), DbErr> {
+    report.annotations_deleted += Annotation::delete_many()
+        .filter(annotation::Column::AuthorId.eq(user_id))
+        .exec(db)
+        .await?
+        .rows_affected;
+    report.notifications_deleted += Notification::delete_many()
+        .filter(notification::Column::UserId.eq(user_id))
+        .exec(db)
+        .await?
+        .rows_affected;
+
+    let attachments = PromptAttachment::find()
+        .filter(prompt_attachment::Column::UserId.eq(user_id))
+        .all(db)
+        .await?;
+    if let Some(blobs) = message_blobs::get() {
+        for attachment in &attachments {
+            match blobs.delete(&attachment.blob_key).await {
+                Ok(()) => report.stored_objects_deleted += 1,
+                Err(e) => warn!("{}", e),
+            }
+        }
+    }
+
+    let other = [
+        PromptAttachment::delete_many()
+            .filter(prompt_attachment::Column::UserId.eq(user_id))
+            .exec(db)
+            .await?,
+        UserSettings::delete_many()
+            .filter(user_settings::Column::UserId.eq(user_id))
+            .exec(db)
+            .await?,
+        SessionWatcher::delete_many()
+            .filter(session_watcher::Column::UserId.eq(user_id))
+            .exec(db)
+            .await?,
+        SessionUpload::delete_many()
+            .filter(session_upload::Column::UserId.eq(user_id))
+            .exec(db)
+            .await?,
+        IdempotencyKey::delete_many()
+            .filter(idempotency_key::Column::UserId.eq(user_id))
+            .exec(db)
+            .await?,
+    ];
+    report.other_records_deleted += other.iter().map(|r| r.rows_affected).sum::<u64>();
+    Ok(())
+}
+
+fn status_for(report: &ErasureReport) -> ErasureStatus {
+    if report.sessions_pending == 0 {
+        ErasureStatus::Completed
+    } else {
+        ErasureStatus::Pending
+    }
+}
+
+/// Erase everything stored about `user_id` and record the request.
+///
+/// Returns the erasure record; it stays `Pending` while sessions are still being released.
+pub async fn erase_user(
+    db: &DatabaseConnection,
+    user_id: &str,
+    actor: &Actor,
+) -> Result<user_erasure::Model, DbErr> {
+    let deprovisioned = deprovision::deprovision_user(db, user_id, actor).await?;
+    let mut report = ErasureReport {
+        sessions_cancelled: deprovisioned.sessions_cancelled as u64,
+        ..Default::default()
+    };
+
+    scrub_dlq(db, user_id, &mut report).await?;
+    delete_user_records(db, user_id, &mut report).await?;
+    delete_sessions(db, user_id, &mut report).await?;
+
+    let now = Utc::now();
+    let status = status_for(&report);
+    let erasure = user_erasure::ActiveModel {
+        id: Set(Uuid::new_v4()),
+        user_id: Set(user_id.to_string()),
+        requested_by: Set(actor.to_string()),
+        completed_at: Set((status == ErasureStatus::Completed).then(|| now.into())),
+        status: Set(status),
+        report: Set(serde_json::json!(report)),
+        created_at: Set(now.into()),
+    }
+    .insert(db)
+    .await?;
+
+    info!(
+        target: "session_audit",
+        user_id = %user_id,
+        actor = %actor,
+        "Erased user data: {} sessions deleted, {} pending, {} messages deleted",
+        report.sessions_deleted,
+        report.sessions_pending,
+        report.messages_deleted
+    );
+
+    Ok(erasure)
+}
+
+/// Delete the sessions pending erasures were waiting on, completing erasures with nothing
+/// left. Returns how many sessions were deleted.
+pub async fn complete_pending(db: &DatabaseConnection) -> Result<u64, DbErr> {
+    let pending = UserErasure::find()
+        .filter(user_erasure::Column::Status.eq(ErasureStatus::Pending))
+        .all(db)
+        .await?;
+
+    let mut deleted = 0;
+    for erasure in pending {
+        let mut report: ErasureReport =
+            serde_json::from_value(erasure.report.clone()).unwrap_or_default();
+        let before = report.sessions_deleted;
+        // Data written since the request, e.g. DLQ entries of a failed IP return
+        scrub_dlq(db, &erasure.user_id, &mut report).await?;
+        delete_sessions(db, &erasure.user_id, &mut report).await?;
+        deleted += report.sessions_deleted - before;
+
+        let status = status_for(&report);
+        let completed = status == ErasureStatus::Completed;
+        let user_id = erasure.user_id.clone();
+        let mut active_erasure: user_erasure::ActiveModel = erasure.into();
+        if completed {
+            active_erasure.completed_at = Set(Some(Utc::now().into()));
+        }
+        active_erasure.status = Set(status);
+        active_erasure.report = Set(serde_json::json!(report));
+        active_erasure.update(db).await?;
+
+        if completed {
+            info!(
+                target: "session_audit",
+                user_id = %user_id,
+                "Completed erasure of user data"
+            );
+        }
+    }
+    Ok(deleted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_waits_for_pending_sessions() {
+        let mut report = ErasureReport {
+            sessions_deleted: 3,
+            sessions_pending: 1,
+            ..Default::default()
+        };
+        assert_eq!(status_for(&report), ErasureStatus::Pending);
+
+        report.sessions_pending = 0;
+        assert_eq!(status_for(&report), ErasureStatus::Completed);
+    }
+}
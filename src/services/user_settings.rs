@@ -0,0 +1,113 @@
+//! Per-user defaults: the model new sessions run on and the optional tools runs may use.
+//!
+//! The agent works through the sandbox's MCP tools, which are always allowed, while the CLI's
+//! local file and shell tools are always denied. The tools in between are the user's choice;
+//! with `--dangerously-skip-permissions` anything not denied is usable, so tools the user
+//! leaves out are passed as disallowed.
+
+use rocket_okapi::okapi::schemars::{self, JsonSchema};
+use sea_orm::{DatabaseConnection, DbErr, EntityTrait};
+use serde::{Deserialize, Serialize};
+
+use crate::entities::user_settings::{self, Entity as UserSettings};
+
+/// Tools the agent always uses: everything the sandbox exposes over MCP
+const ALWAYS_ALLOWED: &[&str] = &["mcp__*"];
+
+/// Local tools that would act on the worker instead of the sandbox
+const ALWAYS_DISALLOWED: &[&str] = &[
+    "Bash",
+    "Edit",
+    "Write",
+    "NotebookEdit",
+    "Read",
+    "Glob",
+    "Grep",
+    "KillShell",
+    "BashOutput",
+    "TodoWrite",
+];
+
+/// Optional CLI tools a user can allow for their runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum AgentTool {
+    WebSearch,
+    WebFetch,
+    ListMcpResourcesTool,
+    ReadMcpResourceTool,
+}
+
+impl AgentTool {
+    pub const ALL: [AgentTool; 4] = [
+        AgentTool::WebSearch,
+        AgentTool::WebFetch,
+        AgentTool::ListMcpResourcesTool,
+        AgentTool::ReadMcpResourceTool,
+    ];
+
+    /// Tool name as the CLI knows it
+    pub fn cli_name(&self) -> &'static str {
+        match self {
+            AgentTool::WebSearch => "WebSearch",
+            AgentTool::WebFetch => "WebFetch",
+            AgentTool::ListMcpResourcesTool => "ListMcpResourcesTool",
+            AgentTool::ReadMcpResourceTool => "ReadMcpResourceTool",
+        }
+    }
+}
+
+/// The user's settings, None when they never saved any
+pub async fn find(
+    db: &DatabaseConnection,
+    user_id: &str,
+) -> Result<Option<user_settings::Model>, DbErr> {
+    UserSettings::find_by_id(user_id.to_string()).one(db).await
+}
+
+/// Tools the user allows, every optional tool when they have not chosen
+pub fn allowed_tools(settings: Option<&user_settings::Model>) -> Vec<AgentTool> {
+    settings
+        .and_then(|s| s.allowed_tools.clone())
+        .and_then(|tools| serde_json::from_value(tools).ok())
+        .unwrap_or_else(|| AgentTool::ALL.to_vec())
+}
+
+/// `--allowedTools` and `--disallowedTools` values for a run allowing `allowed`
+pub fn tool_names(allowed: &[AgentTool]) -> (Vec<String>, Vec<String>) {
+    let allowed_names = ALWAYS_ALLOWED
+        .iter()
+        .copied()
+        .chain(allowed.iter().map(AgentTool::cli_name))
+        .map(str::to_string)
+        .collect();
+    let disallowed_names = ALWAYS_DISALLOWED
+        .iter()
+        .copied()
+        .chain(
+            AgentTool::ALL
+                .iter()
+                .filter(|tool| !allowed.contains(tool))
+                .map(AgentTool::cli_name),
+        )
+        .map(str::to_string)
+        .collect();
+    (allowed_names, disallowed_names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tool_names_disallow_unselected_tools() {
+        let (allowed, disallowed) = tool_names(&[AgentTool::WebSearch]);
+        assert_eq!(allowed, vec!["mcp__*", "WebSearch"]);
+        assert!(disallowed.contains(&"Bash".to_string()));
+        assert!(disallowed.contains(&"WebFetch".to_string()));
+        assert!(!disallowed.contains(&"WebSearch".to_string()));
+
+        let (allowed, disallowed) = tool_names(&allowed_tools(None));
+        assert_eq!(allowed.len(), 1 + AgentTool::ALL.len());
+        assert_eq!(disallowed.len(), ALWAYS_DISALLOWED.len());
+    }
+}
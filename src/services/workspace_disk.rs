@@ -0,0 +1,175 @@
+//! Disk usage of the clone a run works in, so a huge repository or its build output fails the
+//! run with a clear message instead of filling the sandbox until commands break.
+//!
+//! The clone is measured with `du` once it is checked out and every
+//! `WORKSPACE_DISK_CHECK_INTERVAL_SECS` while the CLI runs. Each measurement is stored on the
+//! session and observed in `workspace_disk_bytes`. With `WORKSPACE_DISK_LIMIT_GB` set, a clone
+//! over the limit stops the run before the CLI starts or terminates the CLI, and the prompt
+//! ends with the `disk_limit_exceeded` error kind.
+
+use chrono::Utc;
+use sandbox_client::types::ShellExecRequest;
+use sea_orm::sea_query::Expr;
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+use std::time::Duration;
+use tracing::warn;
+
+use crate::config::WorkspaceDiskConfig;
+use crate::entities::session::{self, Entity as Session};
+use crate::services::http_client;
+use crate::services::process_supervisor;
+
+const BYTES_PER_GB: u64 = 1024 * 1024 * 1024;
+
+/// `du` can take a while on clones with many files
+const MEASURE_TIMEOUT_SECS: f64 = 120.0;
+
+/// A clone over the limit, with what it used
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiskLimit {
+    pub used_bytes: u64,
+    pub limit_gb: u64,
+}
+
+impl std::fmt::Display for DiskLimit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Workspace exceeds {} GB ({:.1} GB used); keep dependencies and build output out of \
+             the repository, or ask for them to be cleaned up as part of the task",
+            self.limit_gb,
+            self.used_bytes as f64 / BYTES_PER_GB as f64
+        )
+    }
+}
+
+/// The limit `used_bytes` goes over, if any
+pub fn exceeded(used_bytes: u64, max_gb: Option<u64>) -> Option<DiskLimit> {
+    max_gb
+        .filter(|limit_gb| used_bytes > limit_gb.saturating_mul(BYTES_PER_GB))
+        .map(|limit_gb| DiskLimit {
+            used_bytes,
+            limit_gb,
+        })
+}
+
+/// Bytes in the total `du -sk` prints first
+fn parse_du(output: &str) -> Option<u64> {
+    let kib: u64 = output.split_whitespace().next()?.parse().ok()?;
+    Some(kib * 1024)
+}
+
+/// Disk used by `repo_path`, None when it could not be measured
+pub async fn measure(sbx: &sandbox_client::Client, repo_path: &str) -> Option<u64> {
+    // du exits nonzero over files it cannot read but still prints the total
+    let response = sbx
+        .exec_command_v1_shell_exec_post(&ShellExecRequest {
+            command: format!("du -sk {} 2>/dev/null", repo_path),
+            async_mode: false,
+            id: None,
+            timeout: Some(MEASURE_TIMEOUT_SECS),
+            exec_dir: Some(String::from("/home/gem")),
+        })
+        .await;
+    match response {
+        Ok(response) => {
+            let output = response.into_inner().data.and_then(|result| result.output);
+            let used = output.as_deref().and_then(parse_du);
+            if used.is_none() {
+                warn!(
+                    "Could not read disk usage of {} from {:?}",
+                    repo_path, output
+                );
+            }
+            used
+        }
+        Err(e) => {
+            warn!("Failed to measure disk usage of {}: {}", repo_path, e);
+            None
+        }
+    }
+}
+
+/// Store `used_bytes` on the session and observe it
+async fn record(db: &DatabaseConnection, session_id: uuid::Uuid, used_bytes: u64) {
+    crate::metrics::get()
+        .workspace_disk_bytes
+        .observe(used_bytes as f64);
+    let stored = Session::update_many()
+        .col_expr(
+            session::Column::WorkspaceDiskBytes,
+            Expr::value(used_bytes as i64),
+        )
+        .col_expr(
+            session::Column::WorkspaceDiskCheckedAt,
+            Expr::value(Utc::now()),
+        )
+        .filter(session::Column::Id.eq(session_id))
+        .exec(db)
+        .await;
+    if let Err(e) = stored {
+        warn!(
+            "Failed to store disk usage of session {}: {}",
+            session_id, e
+        );
+    }
+}
+
+/// Measure the session's clone and record it, returning the limit it goes over. A clone that
+/// could not be measured is let through.
+pub async fn check(
+    db: &DatabaseConnection,
+    sbx: &sandbox_client::Client,
+    session_id: uuid::Uuid,
+    repo_path: &str,
+    settings: &WorkspaceDiskConfig,
+) -> Option<DiskLimit> {
+    let used_bytes = measure(sbx, repo_path).await?;
+    record(db, session_id, used_bytes).await;
+    exceeded(used_bytes, settings.max_gb)
+}
+
+/// Check the clone every `check_interval` while the CLI `pid` runs, terminating it once the
+/// clone goes over the limit. Runs until aborted when there is no limit.
+pub async fn enforce(
+    db: DatabaseConnection,
+    api_url: String,
+    session_id: uuid::Uuid,
+    repo_path: String,
+    pid: u32,
+    settings: &WorkspaceDiskConfig,
+    kill_grace: Duration,
+) -> DiskLimit {
+    let sbx = sandbox_client::Client::new_with_client(&api_url, http_client::client());
+    let limit = loop {
+        tokio::time::sleep(settings.check_interval).await;
+        if let Some(limit) = check(&db, &sbx, session_id, &repo_path, settings).await {
+            break limit;
+        }
+    };
+
+    warn!("{}, terminating CLI process {}", limit, pid);
+    process_supervisor::terminate(pid, kill_grace).await;
+    limit
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_du_and_limit() {
+        let used = parse_du("11534336\t/home/gem/repo_x\n").unwrap();
+        assert_eq!(used, 11 * BYTES_PER_GB);
+        assert_eq!(parse_du(""), None);
+        assert_eq!(parse_du("du: cannot access"), None);
+
+        assert_eq!(exceeded(used, None), None);
+        assert_eq!(exceeded(used, Some(11)), None);
+        let limit = exceeded(used, Some(10)).unwrap();
+        assert_eq!(limit.limit_gb, 10);
+        assert!(limit
+            .to_string()
+            .starts_with("Workspace exceeds 10 GB (11.0 GB used)"));
+    }
+}
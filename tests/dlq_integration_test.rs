@@ -1,7 +1,10 @@
-use rust_redis_webserver::entities::dead_letter_queue::{DlqStatus, Entity as DeadLetterQueue};
+use rust_redis_webserver::entities::dead_letter_queue::{
+    self, DlqStatus, Entity as DeadLetterQueue,
+};
 use rust_redis_webserver::services::dead_letter_queue::{
-    exists_in_dlq, insert_dlq_entry, MAX_RETRY_COUNT,
+    exists_in_dlq, insert_dlq_entry, pending_stats, resolve_dlq_entry, MAX_RETRY_COUNT,
 };
+use rust_redis_webserver::services::integrity::{detect_orphans, orphaned_dlq_entries};
 use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
 use uuid::Uuid;
 
@@ -200,6 +203,80 @@ async fn test_dlq_filters_by_status() {
     let _ = DeadLetterQueue::delete_by_id(entry.id).exec(&db).await;
 }
 
+#[tokio::test]
+async fn test_dlq_pending_stats_by_task_type() {
+    let db = skip_if_no_db!(try_create_test_db().await);
+    // Unique task type so entries from other tests are not counted
+    let task_type = format!("stats_test_{}", Uuid::new_v4());
+
+    let mut entries = vec![];
+    for _ in 0..2 {
+        entries.push(
+            insert_dlq_entry(
+                &db,
+                &task_type,
+                Uuid::new_v4(),
+                None,
+                MAX_RETRY_COUNT,
+                "Test error",
+                chrono::Utc::now().into(),
+            )
+            .await
+            .expect("Failed to insert DLQ entry"),
+        );
+    }
+    resolve_dlq_entry(&db, entries[0].id, None)
+        .await
+        .expect("Failed to resolve DLQ entry");
+
+    let stats = pending_stats(&db).await.expect("Failed to get DLQ stats");
+    let ours = stats
+        .iter()
+        .find(|s| s.task_type == task_type)
+        .expect("Task type should have pending entries");
+    assert_eq!(ours.pending, 1, "Resolved entries are not pending");
+
+    // Clean up
+    for entry in entries {
+        let _ = DeadLetterQueue::delete_by_id(entry.id).exec(&db).await;
+    }
+}
+
+#[tokio::test]
+async fn test_integrity_check_detects_dlq_entry_for_missing_entity() {
+    let db = skip_if_no_db!(try_create_test_db().await);
+
+    // Entity id that matches no session or prompt
+    let entry = insert_dlq_entry(
+        &db,
+        "test_task",
+        Uuid::new_v4(),
+        None,
+        MAX_RETRY_COUNT,
+        "Test error",
+        chrono::Utc::now().into(),
+    )
+    .await
+    .expect("Failed to insert DLQ entry");
+
+    let orphaned = DeadLetterQueue::find()
+        .filter(orphaned_dlq_entries())
+        .filter(dead_letter_queue::Column::Id.eq(entry.id))
+        .one(&db)
+        .await
+        .expect("Failed to query DLQ");
+    assert!(
+        orphaned.is_some(),
+        "DLQ entry for a missing entity should be reported as an orphan"
+    );
+
+    let counts = detect_orphans(&db).await.expect("Failed to detect orphans");
+    assert!(counts.dlq_entries >= 1);
+
+    // Clean up
+    let _ = DeadLetterQueue::delete_by_id(entry.id).exec(&db).await;
+}
+
 #[test]
 fn test_dlq_status_enum_values() {
     // Unit test to verify DLQ status enum values